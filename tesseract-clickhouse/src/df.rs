@@ -1,9 +1,10 @@
 //! Convert clickhouse Block to tesseract_core::DataFrame
 
 use failure::{Error, bail};
+use std::str;
 
 use clickhouse_rs::types::{Block, ColumnType, Complex, Simple, SqlType};
-use tesseract_core::{DataFrame, Column, ColumnData};
+use tesseract_core::{DataFrame, Column, ColumnData, TextDictBuilder};
 
 // from source code of clickhouse_rs
 //             SqlType::UInt8 => "UInt8".into(),
@@ -67,16 +68,16 @@ macro_rules! def_column_builder {
                             ColumnData::Int64(src_column.iter::<i64>()?.copied().collect()),
                         )),
                         SqlType::String => {
-                            let mut column_data = Vec::with_capacity(block.row_count());
+                            // dimension labels repeat, so they're dictionary encoded
+                            let mut column_data = TextDictBuilder::with_capacity(block.row_count());
 
                             for source in src_column.iter::<&[u8]>()? {
-                                let text = String::from_utf8(source.into())?;
-                                column_data.push(text);
+                                column_data.push(str::from_utf8(source)?);
                             }
 
                             Ok(Column::new(
                                 src_column.name().to_owned(),
-                                ColumnData::Text(column_data),
+                                column_data.finish_column(),
                             ))
                         }
                         SqlType::Float32 => Ok(Column::new(
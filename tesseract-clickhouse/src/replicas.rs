@@ -0,0 +1,114 @@
+//! Load balancing across read replicas.
+//!
+//! Queries are spread round-robin over the replicas. A replica that fails to give a
+//! connection is skipped for `UNHEALTHY_COOLDOWN`, after which it gets tried again. If all
+//! replicas are unhealthy, they're still tried in turn, so that the error surfaces.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+
+pub struct Replica<T> {
+    pub name: String,
+    pub inner: T,
+    unhealthy_since: Mutex<Option<Instant>>,
+}
+
+impl<T> Replica<T> {
+    pub fn new(name: String, inner: T) -> Self {
+        Replica {
+            name,
+            inner,
+            unhealthy_since: Mutex::new(None),
+        }
+    }
+
+    pub fn is_healthy(&self, now: Instant) -> bool {
+        match *self.unhealthy_since.lock().unwrap() {
+            Some(since) => now.duration_since(since) >= UNHEALTHY_COOLDOWN,
+            None => true,
+        }
+    }
+
+    pub fn mark_unhealthy(&self, now: Instant) {
+        *self.unhealthy_since.lock().unwrap() = Some(now);
+    }
+
+    pub fn mark_healthy(&self) {
+        *self.unhealthy_since.lock().unwrap() = None;
+    }
+}
+
+
+pub struct Replicas<T> {
+    replicas: Vec<Arc<Replica<T>>>,
+    next: AtomicUsize,
+}
+
+impl<T> Replicas<T> {
+    /// `replicas` must not be empty.
+    pub fn new(replicas: Vec<Replica<T>>) -> Self {
+        assert!(!replicas.is_empty(), "at least one replica is needed");
+
+        Replicas {
+            replicas: replicas.into_iter().map(Arc::new).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Next healthy replica in round-robin order, or the next one if none are healthy.
+    pub fn next(&self, now: Instant) -> Arc<Replica<T>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.replicas.len();
+
+        let replica = (0..len)
+            .map(|offset| &self.replicas[(start + offset) % len])
+            .find(|replica| replica.is_healthy(now))
+            .unwrap_or(&self.replicas[start % len]);
+
+        replica.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replicas_round_robin() {
+        let replicas = Replicas::new(vec![
+            Replica::new("a".to_owned(), ()),
+            Replica::new("b".to_owned(), ()),
+            Replica::new("c".to_owned(), ()),
+        ]);
+        let now = Instant::now();
+
+        let picked: Vec<_> = (0..4).map(|_| replicas.next(now).name.clone()).collect();
+        assert_eq!(picked, vec!["a", "b", "c", "a"]);
+
+        // unhealthy replicas are skipped (their turn goes to the next one) until the
+        // cooldown is over
+        replicas.replicas[1].mark_unhealthy(now);
+        let picked: Vec<_> = (0..3).map(|_| replicas.next(now).name.clone()).collect();
+        assert_eq!(picked, vec!["c", "c", "a"]);
+
+        let later = now + UNHEALTHY_COOLDOWN;
+        let picked: Vec<_> = (0..3).map(|_| replicas.next(later).name.clone()).collect();
+        assert_eq!(picked, vec!["b", "c", "a"]);
+
+        // with all unhealthy, keep rotating
+        for replica in &replicas.replicas {
+            replica.mark_unhealthy(now);
+        }
+        let picked: Vec<_> = (0..2).map(|_| replicas.next(now).name.clone()).collect();
+        assert_eq!(picked, vec!["b", "c"]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Session settings applied to every generated query, e.g. `max_memory_usage` or
+//! `join_use_nulls`, for deployments where the server-wide defaults can't be changed.
+//!
+//! Settings are added to the `settings` clause of the sql generated for a query (see
+//! `clickhouse_sql`), rather than to sql strings, so that queries the server writes itself,
+//! like table inspection, are left as is. Settings the query already sets (e.g. `priority`
+//! or `max_threads` from the query priority) take precedence.
+
+use failure::{Error, format_err};
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionSettings(Vec<(String, String)>);
+
+impl SessionSettings {
+    /// Parses a comma-separated list of `name=value`, e.g.
+    /// `max_memory_usage=10000000000,max_execution_time=60`. Values are written as in sql, so
+    /// strings must be quoted.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let settings = s.split(',')
+            .map(|setting| setting.trim())
+            .filter(|setting| !setting.is_empty())
+            .map(|setting| {
+                let mut name_value = setting.splitn(2, '=');
+                let name = name_value.next().unwrap_or("").trim();
+                let value = name_value.next()
+                    .map(|value| value.trim())
+                    .filter(|value| !value.is_empty())
+                    .ok_or_else(|| format_err!("clickhouse setting {} has no value", setting))?;
+
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format_err!("clickhouse setting {} has an invalid name", setting));
+                }
+
+                Ok((name.to_owned(), value.to_owned()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(SessionSettings(settings))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Adds the settings which aren't in a query's own `settings` to them. The query's
+    /// settings are comma separated `name = value`, as generated.
+    pub fn merge(&self, settings: &mut Vec<String>) {
+        let query_names: Vec<String> = settings.iter()
+            .flat_map(|setting| setting.split(','))
+            .filter_map(|setting| setting.split('=').next())
+            .map(|name| name.trim().to_owned())
+            .collect();
+
+        settings.extend(
+            self.0.iter()
+                .filter(|(name, _)| !query_names.contains(name))
+                .map(|(name, value)| format!("{} = {}", name, value))
+        );
+    }
+}
+
+
+/// Splits the `settings` clause off the end of generated sql, e.g. to move it to a query
+/// wrapping it. Only a `settings` outside of string literals and quoted identifiers counts.
+pub fn split_settings(sql: &str) -> (&str, &str) {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut settings_idx = None;
+
+    for (idx, c) in sql.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '`' || c == '"' => quote = Some(c),
+            None if sql[idx..].starts_with(" settings ") => settings_idx = Some(idx),
+            None => (),
+        }
+    }
+
+    match settings_idx {
+        Some(idx) => (&sql[..idx], &sql[idx..]),
+        None => (sql, ""),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_settings() {
+        let settings = SessionSettings::parse("max_memory_usage=1000, join_use_nulls = 1,").unwrap();
+
+        let mut query_settings = vec![];
+        settings.merge(&mut query_settings);
+        assert_eq!(query_settings.join(", "), "max_memory_usage = 1000, join_use_nulls = 1");
+
+        let mut query_settings = vec!["priority = 3, max_memory_usage = 5".to_owned()];
+        settings.merge(&mut query_settings);
+        assert_eq!(query_settings.join(", "), "priority = 3, max_memory_usage = 5, join_use_nulls = 1");
+
+        let mut query_settings = vec!["priority = 1".to_owned()];
+        SessionSettings::default().merge(&mut query_settings);
+        assert_eq!(query_settings, vec!["priority = 1".to_owned()]);
+
+        assert!(SessionSettings::parse("max_memory_usage").is_err());
+        assert!(SessionSettings::parse("max memory=1").is_err());
+    }
+
+    #[test]
+    fn test_split_settings() {
+        assert_eq!(
+            split_settings("select 1 settings priority = 1"),
+            ("select 1", " settings priority = 1"),
+        );
+        assert_eq!(
+            split_settings("select 1 where name = 'a settings b' settings priority = 1"),
+            ("select 1 where name = 'a settings b'", " settings priority = 1"),
+        );
+        assert_eq!(
+            split_settings("select 1 where name in ('it\\'s settings ', 'b')"),
+            ("select 1 where name in ('it\\'s settings ', 'b')", ""),
+        );
+        assert_eq!(split_settings("select `x settings y` from t"), ("select `x settings y` from t", ""));
+    }
+}
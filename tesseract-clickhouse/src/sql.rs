@@ -15,19 +15,22 @@ use tesseract_core::query_ir::{
     RcaSql,
     GrowthSql,
     RateSql,
+    RowFilterSql,
     dim_subquery,
 };
 use tesseract_core::{QueryIr};
 
-use self::options::wrap_options;
+use crate::session_settings::SessionSettings;
+use self::options::{distributed_settings, priority_settings, wrap_options};
 use self::primary_agg::primary_agg;
 use self::rate::rate_calculation;
 
 
 /// Error checking is done before this point. This string formatter
-/// accepts any input
+/// accepts any input. `session_settings` are added to the query's own settings.
 pub fn clickhouse_sql(
-    query_ir: &QueryIr
+    query_ir: &QueryIr,
+    session_settings: &SessionSettings,
     ) -> String
 {
     let meas = &query_ir.meas;
@@ -38,16 +41,17 @@ pub fn clickhouse_sql(
         // here that it's simply not passed to calculations.
         let table = &query_ir.table;
         let cuts = &query_ir.cuts;
+        let row_filters = &query_ir.row_filters;
         let drills = &query_ir.drills;
 
         let rca = &query_ir.rca;
         let rate = &query_ir.rate;
         if let Some(rca) = rca {
-            rca::calculate(table, cuts, drills, meas, rca)
+            rca::calculate(table, cuts, row_filters, drills, meas, rca)
         } else if let Some(rate) = rate {
-            rate_calculation(table, cuts, drills, meas, rate)
+            rate_calculation(table, cuts, row_filters, drills, meas, rate)
         } else {
-            primary_agg(table, cuts, drills, meas, Some(&query_ir.hidden_drills))
+            primary_agg(table, cuts, row_filters, drills, meas, Some(&query_ir.hidden_drills))
         }
     };
     if let Some(growth) = &query_ir.growth {
@@ -56,19 +60,67 @@ pub fn clickhouse_sql(
         final_drill_cols = drill_cols;
     }
 
-    final_sql = wrap_options(final_sql, &final_drill_cols, &query_ir, meas.len());
+    final_sql = wrap_options(final_sql, &final_drill_cols, &query_ir, &final_mea_cols(query_ir));
+
+    let mut settings = vec![];
+    if let Some(priority) = &query_ir.priority {
+        settings.push(priority_settings(priority));
+    }
+    if query_ir.distributed.is_some() {
+        settings.push(distributed_settings());
+    }
+    session_settings.merge(&mut settings);
+    if !settings.is_empty() {
+        final_sql = format!("{} settings {}", final_sql, settings.join(", "));
+    }
 
     final_sql
 }
 
 
-// TODO test having not cuts or drilldowns
+/// Names of the measure columns in the final result, which depend on the
+/// calculation that wraps the primary aggregation.
+fn final_mea_cols(query_ir: &QueryIr) -> Vec<String> {
+    let num_measures = query_ir.meas.len();
+
+    let mea_cols: Vec<String> = if query_ir.rca.is_some() {
+        // rca replaces the first measure, others are carried over
+        let mut cols = vec!["rca".to_owned()];
+        cols.extend((1..num_measures+1).map(|i| format!("final_m{}", i)));
+        cols
+    } else if query_ir.rate.is_some() {
+        vec!["final_m0".to_owned()]
+    } else {
+        (0..num_measures).map(|i| format!("final_m{}", i)).collect()
+    };
+
+    if let Some(growth) = &query_ir.growth {
+        // growth renames the measure it's calculated on, and the other measures
+        return mea_cols.into_iter()
+            .map(|col| {
+                if col == growth.mea {
+                    "final_m".to_owned()
+                } else {
+                    col.replace("final_m", "final_other_m")
+                }
+            })
+            .collect();
+    }
+
+    mea_cols
+}
+
+
 #[cfg(test)]
 mod test {
     use super::*;
     use tesseract_core::Table;
     use tesseract_core::names::Mask;
-    use tesseract_core::query_ir::{LevelColumn, MemberType};
+    use tesseract_core::query_ir::{LevelColumn, LimitSql, MemberType};
+    use tesseract_core::Aggregator;
+    use tesseract_core::QueryPriority;
+    use tesseract_core::schema::DistributedTable;
+    use tesseract_core::query::{Comparison, Constraint};
 
     // TODO move this to better place?
     // Should all of these internal checks be moved to one place? Is this an ok place?
@@ -79,6 +131,7 @@ mod test {
                 foreign_key: "".into(),
                 primary_key: "".into(),
                 inline_table: None,
+                joins: vec![],
                 table: Table { name: "".into(), schema: None, primary_key: None },
                 column: "geo".into(),
                 members: vec!["1".into(), "2".into()],
@@ -90,6 +143,7 @@ mod test {
                 foreign_key: "".into(),
                 primary_key: "".into(),
                 inline_table: None,
+                joins: vec![],
                 table: Table { name: "".into(), schema: None, primary_key: None },
                 column: "age".into(),
                 members: vec!["3".into()],
@@ -118,6 +172,7 @@ mod test {
             foreign_key: "product_id".into(),
             primary_key: "product_id".into(),
             inline_table: None,
+            joins: vec![],
             table: Table { name: "dim_products".into(), schema: None, primary_key: None },
             level_columns: vec![
                 LevelColumn {
@@ -138,4 +193,235 @@ mod test {
         );
     }
 
+    #[test]
+    /// Tests:
+    /// - rate on a level that's not drilled down on
+    /// - rate with text members
+    /// - cuts and limit together with rate
+    fn rate_with_cut_and_limit() {
+        let year_drill = DrilldownSql {
+            alias_postfix: "".into(),
+            foreign_key: "year".into(),
+            primary_key: "year".into(),
+            inline_table: None,
+            joins: vec![],
+            table: Table { name: "sales".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "year".into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        };
+        let category_drill = DrilldownSql {
+            alias_postfix: "".into(),
+            foreign_key: "category_id".into(),
+            primary_key: "category_id".into(),
+            inline_table: None,
+            joins: vec![],
+            table: Table { name: "dim_category".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "category_code".into(),
+                    name_column: None,
+                },
+            ],
+            property_columns: vec![],
+        };
+
+        let query_ir = QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None, sample: None, sql_where: None },
+            cuts: vec![
+                CutSql {
+                    foreign_key: "year".into(),
+                    primary_key: "year".into(),
+                    inline_table: None,
+                    joins: vec![],
+                    table: Table { name: "sales".into(), schema: None, primary_key: None },
+                    column: "year".into(),
+                    members: vec!["2019".into()],
+                    member_type: MemberType::NonText,
+                    mask: Mask::Include,
+                    for_match: false,
+                },
+            ],
+            drills: vec![year_drill],
+            meas: vec![MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), null_zero: false }],
+            hidden_drills: vec![],
+            filters: vec![],
+            row_filters: vec![],
+            top: None,
+            top_where: None,
+            sort: vec![],
+            limit: Some(LimitSql { offset: None, n: 5 }),
+            rca: None,
+            growth: None,
+            share: None,
+            previous_year: None,
+            rate: Some(RateSql {
+                drilldown_sql: category_drill,
+                members: vec!["a".into(), "b".into()],
+                member_type: MemberType::Text,
+            }),
+            sparse: false,
+            priority: Some(QueryPriority::Low),
+            distributed: None,
+            null_zero_idxs: vec![],
+            key_widths: vec![],
+            all_members: vec![],
+        };
+
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (select year_, final_m0, sum(final_m0_rate) / avg(final_m0) from (select year_, category_code__group, final_m0_agg as final_m0, final_m0_rate from (select year_, sum(final_m0) as final_m0_agg, groupArray(final_m0) as final_m0_rate, groupArray(category_code_) as category_code__group from (SELECT year_, category_code_, sum(m0) as final_m0 FROM (SELECT year_, category_id, category_code_, m0 FROM (select category_code as category_code_, category_id as category_id from dim_category) ALIAS0 ALL INNER JOIN (SELECT year as year_, category_id, sum(quantity) as m0 FROM sales WHERE year in (2019) GROUP BY year_, category_id) ALIAS1 USING category_id) GROUP BY year_, category_code_) group by year_) array join category_code__group as category_code__group, final_m0_rate as final_m0_rate) where category_code__group in ('a', 'b') group by year_, final_m0)  order by year_ limit 5 settings priority = 3, max_threads = 2".to_owned()
+        );
+    }
+
+    #[test]
+    fn distributed_table_settings() {
+        let query_ir = QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None, sample: None, sql_where: None },
+            cuts: vec![],
+            drills: vec![],
+            meas: vec![MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), null_zero: false }],
+            hidden_drills: vec![],
+            filters: vec![],
+            row_filters: vec![],
+            top: None,
+            top_where: None,
+            sort: vec![],
+            limit: None,
+            rca: None,
+            growth: None,
+            share: None,
+            previous_year: None,
+            rate: None,
+            sparse: false,
+            priority: Some(QueryPriority::High),
+            distributed: Some(DistributedTable { local_name: "sales_local".into(), cluster: None }),
+            null_zero_idxs: vec![],
+            key_widths: vec![],
+            all_members: vec![],
+        };
+
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (SELECT sum(m0) as final_m0 FROM (SELECT sum(quantity) as m0 FROM sales))    settings priority = 1, distributed_aggregation_memory_efficient = 1, optimize_distributed_group_by_sharding_key = 1".to_owned()
+        );
+    }
+
+    #[test]
+    fn measures_only() {
+        let mut query_ir = QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None, sample: None, sql_where: None },
+            cuts: vec![],
+            drills: vec![],
+            meas: vec![MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), null_zero: false }],
+            hidden_drills: vec![],
+            filters: vec![],
+            row_filters: vec![],
+            top: None,
+            top_where: None,
+            sort: vec![],
+            limit: None,
+            rca: None,
+            growth: None,
+            share: None,
+            previous_year: None,
+            rate: None,
+            sparse: false,
+            priority: None,
+            distributed: None,
+            null_zero_idxs: vec![],
+            key_widths: vec![],
+            all_members: vec![],
+        };
+
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (SELECT sum(m0) as final_m0 FROM (SELECT sum(quantity) as m0 FROM sales))   ".to_owned()
+        );
+
+        query_ir.table.sample = Some(0.01);
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (SELECT sum(m0) as final_m0 FROM (SELECT sum(quantity) as m0 FROM sales SAMPLE 0.01))   ".to_owned()
+        );
+
+        query_ir.table.sample = None;
+        query_ir.table.sql_where = Some("status = 'final'".into());
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (SELECT sum(m0) as final_m0 FROM (SELECT sum(quantity) as m0 FROM sales WHERE (status = 'final')))   ".to_owned()
+        );
+
+        query_ir.row_filters = vec![RowFilterSql {
+            column: "quantity".into(),
+            constraint: Constraint { comparison: Comparison::GreaterThan, n: 1000.0 },
+            operator: None,
+            constraint2: None,
+        }];
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (SELECT sum(m0) as final_m0 FROM (SELECT sum(quantity) as m0 FROM sales WHERE (status = 'final') AND quantity > 1000))   ".to_owned()
+        );
+    }
+
+    #[test]
+    fn sparse_final_mea_cols() {
+        let mut query_ir = QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None, sample: None, sql_where: None },
+            cuts: vec![],
+            drills: vec![],
+            meas: vec![
+                MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), null_zero: false },
+                MeasureSql { aggregator: Aggregator::Sum, column: "price".into(), null_zero: false },
+            ],
+            hidden_drills: vec![],
+            filters: vec![],
+            row_filters: vec![],
+            top: None,
+            top_where: None,
+            sort: vec![],
+            limit: None,
+            rca: None,
+            growth: None,
+            share: None,
+            previous_year: None,
+            rate: None,
+            sparse: true,
+            priority: None,
+            distributed: None,
+            null_zero_idxs: vec![],
+            key_widths: vec![],
+            all_members: vec![],
+        };
+
+        assert_eq!(
+            clickhouse_sql(&query_ir, &SessionSettings::default()),
+            "select * from (SELECT sum(m0) as final_m0, sum(m1) as final_m1 FROM (SELECT sum(quantity) as m0, sum(price) as m1 FROM sales)) where (ifNull(final_m0, 0) != 0 or ifNull(final_m1, 0) != 0)  ".to_owned()
+        );
+
+        // growth renames its measure columns
+        query_ir.growth = Some(GrowthSql {
+            time_drill: DrilldownSql {
+                alias_postfix: "".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                joins: vec![],
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            mea: "final_m1".into(),
+        });
+        assert_eq!(final_mea_cols(&query_ir), vec!["final_other_m0".to_owned(), "final_m".to_owned()]);
+    }
 }
@@ -34,6 +34,10 @@ pub fn agg_sql_string_pass_1(col: &str, aggregator: &Aggregator, mea_idx: usize)
         Aggregator::Average => format!("avg({}) as m{}", col, mea_idx),
         Aggregator::Max => format!("max({}) as m{}", col, mea_idx),
         Aggregator::Min => format!("min({}) as m{}", col, mea_idx),
+        // Distinct counts can't be rolled up from counts, so the first pass keeps
+        // the aggregate state, which is merged in the second pass.
+        Aggregator::DistinctCount => format!("uniqExactState({}) as m{}", col, mea_idx),
+        Aggregator::ApproxDistinctCount => format!("uniqState({}) as m{}", col, mea_idx),
         Aggregator::BasicGroupedMedian { group_aggregator, .. } => format!("{}({}) as m{}", group_aggregator, col, mea_idx),
         Aggregator::WeightedAverage { weight_column } => {
             format!("sum({0} * {1}) as m{2}_weighted_avg_num, sum({1}) as m{2}_weighted_avg_denom",
@@ -107,6 +111,8 @@ pub fn agg_sql_string_select_mea(aggregator: &Aggregator, mea_idx: usize) -> Str
         Aggregator::Average => format!("m{0}", mea_idx),
         Aggregator::Max => format!("m{0}", mea_idx),
         Aggregator::Min => format!("m{0}", mea_idx),
+        Aggregator::DistinctCount => format!("m{0}", mea_idx),
+        Aggregator::ApproxDistinctCount => format!("m{0}", mea_idx),
         Aggregator::BasicGroupedMedian { .. } => format!("m{0}", mea_idx),
         Aggregator::WeightedAverage { .. } => {
             format!("m{0}_weighted_avg_num, m{0}_weighted_avg_denom",
@@ -163,6 +169,8 @@ pub fn agg_sql_string_pass_2(aggregator: &Aggregator, mea_idx: usize) -> String
         Aggregator::Average => format!("avg(m{0}) as final_m{0}", mea_idx),
         Aggregator::Max => format!("max(m{0}) as final_m{0}", mea_idx),
         Aggregator::Min => format!("min(m{0}) as final_m{0}", mea_idx),
+        Aggregator::DistinctCount => format!("uniqExactMerge(m{0}) as final_m{0}", mea_idx),
+        Aggregator::ApproxDistinctCount => format!("uniqMerge(m{0}) as final_m{0}", mea_idx),
         Aggregator::BasicGroupedMedian { .. } => format!("median(m{0}) as final_m{0}", mea_idx),
         Aggregator::WeightedAverage { .. } => {
             format!("(sum(m{0}_weighted_avg_num) / sum(m{0}_weighted_avg_denom)) as final_m{0}",
@@ -276,6 +284,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn distinct_count_agg() {
+        assert_eq!(
+            agg_sql_string_pass_1("col_1".into(), &Aggregator::DistinctCount, 0),
+            "uniqExactState(col_1) as m0".to_owned(),
+        );
+        assert_eq!(
+            agg_sql_string_pass_2(&Aggregator::DistinctCount, 0),
+            "uniqExactMerge(m0) as final_m0".to_owned(),
+        );
+        assert_eq!(
+            agg_sql_string_pass_1("col_1".into(), &Aggregator::ApproxDistinctCount, 0),
+            "uniqState(col_1) as m0".to_owned(),
+        );
+        assert_eq!(
+            agg_sql_string_pass_2(&Aggregator::ApproxDistinctCount, 0),
+            "uniqMerge(m0) as final_m0".to_owned(),
+        );
+        assert_eq!(
+            agg_sql_string_select_mea(&Aggregator::ApproxDistinctCount, 0),
+            "m0".to_owned(),
+        );
+    }
+
     #[test]
     fn weighted_avg() {
         let agg = Aggregator::WeightedAverage {
@@ -1,12 +1,12 @@
 use itertools::join;
-use tesseract_core::{QueryIr};
+use tesseract_core::{QueryIr, QueryPriority};
 
 
 pub fn wrap_options(
     final_sql: String,
     final_drill_cols: &str,
     query_ir: &QueryIr,
-    num_measures: usize
+    final_mea_cols: &[String],
     ) -> String
 {
     let mut final_sql = final_sql;
@@ -32,6 +32,7 @@ pub fn wrap_options(
     // - it sorts by final_drill_cols
     // - unless there's a specific sort, which just goes to head of cols
     // - or if there's a top, sort by the by_dim col.
+    // - final drill cols always break ties, so that pages from limits are stable.
     // - limits
     let limit_sql = {
         if let Some(limit) = limit {
@@ -46,16 +47,19 @@ pub fn wrap_options(
     };
 
     let sort_sql = {
-        if let Some(sort) = sort {
-            format!("order by {} {}, {}",
-                sort.column,
-                sort.direction.sql_string(),
+        if final_drill_cols.is_empty() {
+            // measure-only queries return a single row
+            "".to_string()
+        } else if !sort.is_empty() {
+            format!("order by {}, {}",
+                join(sort.iter().map(|s| format!("{} {}", s.column, s.direction.sql_string())), ", "),
                 final_drill_cols,
             )
         } else if let Some(top) = top {
-            format!("order by {} asc, {}",
+            format!("order by {} asc, {}, {}",
                 top.by_column,
                 join(top.sort_columns.iter().map(|c| format!("{} desc", c)), ", "),
+                final_drill_cols,
             )
         } else {
             // default uses just final drill cols
@@ -82,13 +86,14 @@ pub fn wrap_options(
         "".into()
     };
 
-    // Determine if sparse filter is needed, and construct appropriate filters_sql
-    {
-        let sparse_clauses = (0..num_measures).into_iter().map(|i| format!("isNotNull(final_m{})", i));
-        let sparse_filter_sql = join(sparse_clauses, " and ");
-        if filters.is_empty() && query_ir.sparse {
+    // Determine if sparse filter is needed, and construct appropriate filters_sql.
+    // Sparse omits rows where all the measures are null or zero.
+    if query_ir.sparse && !final_mea_cols.is_empty() {
+        let sparse_clauses = final_mea_cols.iter().map(|col| format!("ifNull({}, 0) != 0", col));
+        let sparse_filter_sql = format!("({})", join(sparse_clauses, " or "));
+        if filters.is_empty() {
             filters_sql = format!("where {}", sparse_filter_sql);
-        } else if !filters.is_empty() && query_ir.sparse {
+        } else {
             filters_sql = format!("{} and {}", filters_sql, sparse_filter_sql);
         }
     }
@@ -103,3 +108,23 @@ pub fn wrap_options(
 
     final_sql
 }
+
+
+/// ClickHouse settings for queries on distributed fact tables: shards stream partial
+/// aggregates, and skip the final merge when grouping by the sharding key (whose groups
+/// can't span shards).
+pub fn distributed_settings() -> String {
+    "distributed_aggregation_memory_efficient = 1, optimize_distributed_group_by_sharding_key = 1".to_owned()
+}
+
+
+/// ClickHouse settings for a query priority. For `priority`, a lower value is
+/// a higher priority; low priority queries are also limited in threads so that
+/// long exports don't starve interactive queries.
+pub fn priority_settings(priority: &QueryPriority) -> String {
+    match priority {
+        QueryPriority::High => "priority = 1".to_owned(),
+        QueryPriority::Normal => "priority = 2".to_owned(),
+        QueryPriority::Low => "priority = 3, max_threads = 2".to_owned(),
+    }
+}
@@ -12,6 +12,7 @@ use super::{
     DrilldownSql,
     MeasureSql,
     HiddenDrilldownSql,
+    RowFilterSql,
     dim_subquery,
 };
 
@@ -21,6 +22,7 @@ use super::{
 pub fn primary_agg(
     table: &TableSql,
     cuts: &[CutSql],
+    row_filters: &[RowFilterSql],
     drills: &[DrilldownSql],
     meas: &[MeasureSql],
     hidden_drills: Option<&[HiddenDrilldownSql]>,
@@ -102,7 +104,15 @@ pub fn primary_agg(
     let inline_dim_cols = inline_drills.iter().map(|d| d.col_alias_string());
     let inline_dim_aliass = inline_drills.iter().map(|d| d.col_alias_only_string());
 
-    let dim_idx_cols = dim_subqueries.iter().map(|d| d.foreign_key.clone());
+    // drilldowns on several hierarchies of one dimension share a foreign key, which is
+    // only selected once
+    let mut dim_idx_cols: Vec<String> = vec![];
+    for dim_subquery in &dim_subqueries {
+        if !dim_idx_cols.contains(&dim_subquery.foreign_key) {
+            dim_idx_cols.push(dim_subquery.foreign_key.clone());
+        }
+    }
+    let dim_idx_cols = dim_idx_cols.into_iter();
 
     let all_fact_dim_cols = join(inline_dim_cols.chain(dim_idx_cols.clone()), ", ");
     let all_fact_dim_aliass = join(inline_dim_aliass.chain(dim_idx_cols), ", ");
@@ -111,14 +121,30 @@ pub fn primary_agg(
     let hidden_drills = hidden_drills.map(|ds| ds.to_vec()).unwrap_or(vec![]);
     let hidden_dim_cols = join(hidden_drills.iter().map(|d| d.drilldown_sql.col_alias_string()), ", ");
 
-    let mut fact_sql = format!("SELECT {}", all_fact_dim_cols);
+    // A measure-only query has no dim cols at all; it aggregates over the
+    // whole (cut) fact table into a single row.
+    let mut fact_dim_cols = vec![];
+    if !all_fact_dim_cols.is_empty() {
+        fact_dim_cols.push(all_fact_dim_cols);
+    }
 
     // done separately so that it isn't projected up the subqueries
     if !hidden_drills.is_empty() {
-        fact_sql.push_str(&format!(", {}", hidden_dim_cols));
+        fact_dim_cols.push(hidden_dim_cols.clone());
     }
 
-    fact_sql.push_str(&format!(", {} FROM {}", mea_cols, table.name));
+    // distinct queries have no measures
+    let mut fact_sql = format!("SELECT {} FROM {}",
+        join(fact_dim_cols.iter().chain(Some(&mea_cols).filter(|cols| !cols.is_empty())), ", "),
+        table.name,
+    );
+
+    // requires a sampling key on the table
+    if let Some(sample) = table.sample {
+        fact_sql = format!("{} SAMPLE {}", fact_sql, sample);
+    }
+
+    let mut where_clauses: Vec<String> = vec![];
 
     if (inline_cuts.len() > 0) || (ext_cuts_for_inline.len() > 0) {
         let inline_cut_clause = inline_cuts
@@ -133,7 +159,7 @@ pub fn primary_agg(
                         let inline_table_sql = it.sql_string();
                         format!("({}) as {}", inline_table_sql, c.table.full_name())
                     },
-                    None => c.table.full_name()
+                    None => c.table.join_chain_sql(&c.joins)
                 };
 
                 if c.members.is_empty() {
@@ -154,16 +180,31 @@ pub fn primary_agg(
                 }
             });
 
-        let cut_clause = join(inline_cut_clause.chain(ext_cut_clause), "AND ");
+        where_clauses.push(join(inline_cut_clause.chain(ext_cut_clause), "AND "));
+    }
+
+    if let Some(ref sql_where) = table.sql_where {
+        where_clauses.push(format!("({})", sql_where));
+    }
+
+    where_clauses.extend(row_filters.iter().map(|f| f.sql_string()));
 
-        fact_sql.push_str(&format!(" WHERE {}", cut_clause));
+    if !where_clauses.is_empty() {
+        fact_sql.push_str(&format!(" WHERE {}", join(where_clauses, " AND ")));
     }
 
-    fact_sql.push_str(&format!(" GROUP BY {}", all_fact_dim_aliass));
+    let mut fact_group_cols = vec![];
+    if !all_fact_dim_aliass.is_empty() {
+        fact_group_cols.push(all_fact_dim_aliass.clone());
+    }
 
     // done separately so that it isn't projected up the subqueries
     if !hidden_drills.is_empty() {
-        fact_sql.push_str(&format!(", {}", hidden_dim_cols));
+        fact_group_cols.push(hidden_dim_cols);
+    }
+
+    if !fact_group_cols.is_empty() {
+        fact_sql.push_str(&format!(" GROUP BY {}", join(fact_group_cols, ", ")));
     }
 
     // Now second half, feed DimSubquery into the multiple joins with fact table
@@ -194,15 +235,12 @@ pub fn primary_agg(
             current_dim_cols.push(cols);
         }
 
-        let sub_queries_dim_cols = if !current_dim_cols.is_empty() {
-            format!("{}, ", join(current_dim_cols.iter(), ", "))
-        } else {
-            "".to_owned()
-        };
+        let sub_queries_cols = current_dim_cols.iter()
+            .chain(Some(&select_mea_cols))
+            .filter(|cols| !cols.is_empty());
         // Now construct subquery
-        sub_queries = format!("SELECT {}{} FROM ({}) ALIAS{} ALL INNER JOIN ({}) ALIAS{} USING {}",
-            sub_queries_dim_cols,
-            select_mea_cols,
+        sub_queries = format!("SELECT {} FROM ({}) ALIAS{} ALL INNER JOIN ({}) ALIAS{} USING {}",
+            join(sub_queries_cols, ", "),
             dim_subquery.sql,
             alias_counter,
             sub_queries,
@@ -223,12 +261,25 @@ pub fn primary_agg(
     let final_mea_cols = join(final_mea_cols, ", ");
 
     // This is the final result of the groupings.
-    let final_sql = format!("SELECT {}, {} FROM ({}) GROUP BY {}",
-        final_drill_cols,
-        final_mea_cols,
-        sub_queries,
-        final_drill_cols,
-    );
+    let final_sql = if final_drill_cols.is_empty() {
+        format!("SELECT {} FROM ({})",
+            final_mea_cols,
+            sub_queries,
+        )
+    } else if final_mea_cols.is_empty() {
+        // distinct queries have no measures to aggregate
+        format!("SELECT DISTINCT {} FROM ({})",
+            final_drill_cols,
+            sub_queries,
+        )
+    } else {
+        format!("SELECT {}, {} FROM ({}) GROUP BY {}",
+            final_drill_cols,
+            final_mea_cols,
+            sub_queries,
+            final_drill_cols,
+        )
+    };
 
     (final_sql, final_drill_cols)
 }
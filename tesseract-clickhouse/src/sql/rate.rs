@@ -6,6 +6,7 @@ use super::{
     DrilldownSql,
     MeasureSql,
     RateSql,
+    RowFilterSql,
 };
 
 use crate::sql::primary_agg::primary_agg;
@@ -16,6 +17,7 @@ use tesseract_core::{Aggregator};
 pub fn rate_calculation(
     table: &TableSql,
     cuts: &[CutSql],
+    row_filters: &[RowFilterSql],
     drills: &[DrilldownSql],
     meas: &[MeasureSql],
     rate: &RateSql
@@ -38,7 +40,7 @@ pub fn rate_calculation(
 
     // Call primary agg
     let (final_sql, _final_drill_cols) = {
-        primary_agg(table, cuts, &new_drills, meas, None)
+        primary_agg(table, cuts, row_filters, &new_drills, meas, None)
     };
 
     let mut rate_sql = "".to_string();
@@ -86,7 +88,7 @@ pub fn rate_calculation(
         rate_aggregator,
         rate_sql,
         rate_drill_cols[0],
-        join(rate.members.clone(), ", "),
+        rate.members_string(),
         original_drill_cols
     );
 
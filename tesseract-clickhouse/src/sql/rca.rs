@@ -45,11 +45,13 @@ use super::{
     DrilldownSql,
     MeasureSql,
     RcaSql,
+    RowFilterSql,
 };
 
 pub fn calculate(
     table: &TableSql,
     cuts: &[CutSql],
+    row_filters: &[RowFilterSql],
     drills: &[DrilldownSql],
     meas: &[MeasureSql],
     rca: &RcaSql,
@@ -132,8 +134,8 @@ pub fn calculate(
     // If there's no internal cuts, then b, c, d are calculated from a.
 
     // First do aggregation for part a, b
-    let (a, a_final_drills) = primary_agg(table, &ac_cuts, &a_drills, &all_meas, None);
-    let (b, b_final_drills) = primary_agg(table, &bd_cuts, &b_drills, &all_meas, None);
+    let (a, a_final_drills) = primary_agg(table, &ac_cuts, row_filters, &a_drills, &all_meas, None);
+    let (b, b_final_drills) = primary_agg(table, &bd_cuts, row_filters, &b_drills, &all_meas, None);
 
     // replace final_m0 with letter name.
     // I put the rca measure at the beginning of the drills, so it should
@@ -149,10 +151,11 @@ pub fn calculate(
         .flat_map(|d| {
             let alias_postfix = &d.alias_postfix;
             d.level_columns.iter().map(move |l| {
-                if let Some(ref name_col) = l.name_column {
-                    format!("groupArray({key_col}_{alias_postfix}) as {key_col}_{alias_postfix}_s, groupArray({name_col}_{alias_postfix}) as {name_col}_{alias_postfix}_s", key_col=l.key_column, name_col=name_col, alias_postfix=alias_postfix)
+                let key_alias = l.key_alias(alias_postfix);
+                if let Some(name_alias) = l.name_alias(alias_postfix) {
+                    format!("groupArray({key_alias}) as {key_alias}_s, groupArray({name_alias}) as {name_alias}_s", key_alias=key_alias, name_alias=name_alias)
                 } else {
-                    format!("groupArray({col}_{alias_postfix}) as {col}_{alias_postfix}_s", col=l.key_column, alias_postfix=alias_postfix)
+                    format!("groupArray({alias}) as {alias}_s", alias=key_alias)
                 }
             })
         });
@@ -162,10 +165,11 @@ pub fn calculate(
         .flat_map(|d| {
             let alias_postfix = &d.alias_postfix;
             d.level_columns.iter().map(move |l| {
-                if let Some(ref name_col) = l.name_column {
-                    format!("{key_col}_{alias_postfix}_s as {key_col}_{alias_postfix}, {name_col}_{alias_postfix}_s as {name_col}_{alias_postfix}", key_col=l.key_column, name_col=name_col, alias_postfix=alias_postfix)
+                let key_alias = l.key_alias(alias_postfix);
+                if let Some(name_alias) = l.name_alias(alias_postfix) {
+                    format!("{key_alias}_s as {key_alias}, {name_alias}_s as {name_alias}", key_alias=key_alias, name_alias=name_alias)
                 } else {
-                    format!("{col}_{alias_postfix}_s as {col}_{alias_postfix}", col=l.key_column, alias_postfix=alias_postfix)
+                    format!("{alias}_s as {alias}", alias=key_alias)
                 }
             })
         });
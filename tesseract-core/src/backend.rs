@@ -1,9 +1,80 @@
-use failure::Error;
-use futures::{Future, Stream};
+use failure::{Error, bail};
+use futures::{Async, Future, Poll, Stream};
+use serde_derive::Serialize;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::dataframe::DataFrame;
+use crate::dialect::{registered_or, SqlDialect, StandardSql};
 use crate::query_ir::QueryIr;
-use crate::sql;
+
+
+/// A table found in the database, as listed by `Backend::inspect_tables`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableInfo {
+    pub schema: Option<String>,
+    pub name: String,
+}
+
+/// A column of a table, with its type as named by the database.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub column_type: String,
+}
+
+
+/// Features a backend supports beyond the standard aggregation. A query needing one it
+/// lacks is an error, instead of sql which is invalid or which silently leaves it out.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// Window functions, for `preview`.
+    pub window_functions: bool,
+    /// `limit n by`, for `top` (and its `top_where`).
+    pub limit_by: bool,
+    /// Table sampling, for `sample`.
+    pub sampling: bool,
+    /// Streamed results, with `Backend::exec_sql_stream`.
+    pub streaming: bool,
+    /// `rca` and `filters`, which are calculated in the generated sql.
+    pub sql_calculations: bool,
+}
+
+/// The capabilities of standard sql, except sampling, which each dialect opts into since its
+/// syntax varies (see `SqlDialect::sample_table_sql`).
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            window_functions: true,
+            limit_by: false,
+            sampling: false,
+            streaming: false,
+            sql_calculations: false,
+        }
+    }
+}
+
+impl Capabilities {
+    /// Checks that the features used by a query are supported.
+    pub fn check(&self, query_ir: &QueryIr) -> Result<(), Error> {
+        if query_ir.top.is_some() && !self.limit_by {
+            bail!("Top is not supported on this backend");
+        }
+        if query_ir.rca.is_some() && !self.sql_calculations {
+            bail!("Rca is not supported on this backend");
+        }
+        if !query_ir.filters.is_empty() && !self.sql_calculations {
+            bail!("Filters are not supported on this backend");
+        }
+        if query_ir.table.sample.is_some() && !self.sampling {
+            bail!("Sample is not supported on this backend");
+        }
+
+        Ok(())
+    }
+}
 
 
 pub trait Backend {
@@ -14,34 +85,246 @@ pub trait Backend {
     /// Takes in a SQL string, outputs a stream of
     /// DataFrames, which will go on to be formatted into the
     /// desired query output format.
-    fn exec_sql_stream(&self, sql: String) -> Box<dyn Stream<Item=Result<DataFrame, Error>, Error=Error>> {
+    fn exec_sql_stream(&self, _sql: String) -> Box<dyn Stream<Item=Result<DataFrame, Error>, Error=Error>> {
         unimplemented!()
     }
 
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync>;
 
+    /// Sql dialect of the backend. Backends with their own dialect should return it through
+    /// `registered_or`, so that it can be replaced by a registered dialect of the same name.
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        registered_or(StandardSql)
+    }
+
+    /// Features of the backend, checked before generating sql. By default, those of its
+    /// dialect, without streaming.
+    fn capabilities(&self) -> Capabilities {
+        self.dialect().capabilities()
+    }
+
     /// Receives an intermediate representation of the Query
     /// (the table, col, and relationship info needed for each drill,
-    /// mea, cut, etc.) and generates a `String` of sql. Cannot error,
-    /// and all checks should be done before calling this.
+    /// mea, cut, etc.) and generates a `String` of sql with the backend's
+    /// dialect. Cannot error, and all checks should be done before calling this.
     fn generate_sql(&self, query_ir: QueryIr) -> String {
-        // standard sql implementation
-        sql::standard_sql(
-            &query_ir.table,
-            &query_ir.cuts,
-            &query_ir.drills,
-            &query_ir.meas,
-            &query_ir.top,
-            &query_ir.sort,
-            &query_ir.limit,
-            &query_ir.rca,
-            &query_ir.growth,
-        )
+        self.dialect().generate_sql(query_ir)
+    }
+
+    /// Lists the tables in the database, excluding system schemas. The default
+    /// implementation queries `information_schema.tables`.
+    fn inspect_tables(&self) -> Box<dyn Future<Item=Vec<TableInfo>, Error=Error>> {
+        let sql = format!(
+            "select table_schema, table_name from information_schema.tables \
+            where table_schema not in ({}) order by table_schema, table_name",
+            SYSTEM_SCHEMAS.iter().map(|s| format!("'{}'", s)).collect::<Vec<_>>().join(", "),
+        );
+
+        Box::new(self.exec_sql(sql).map(|df| {
+            let (schemas, names) = two_string_columns(&df);
+
+            schemas.into_iter()
+                .zip(names)
+                .map(|(schema, name)| TableInfo { schema: Some(schema), name })
+                .collect()
+        }))
+    }
+
+    /// Lists the columns of `table` (optionally qualified by its schema, as `schema.table`)
+    /// in order, with their types. The default implementation queries
+    /// `information_schema.columns`.
+    fn inspect_columns(&self, table: &str) -> Box<dyn Future<Item=Vec<ColumnInfo>, Error=Error>> {
+        let sql = format!(
+            "select column_name, data_type from information_schema.columns \
+            where {} order by ordinal_position",
+            table_filter(&*self.dialect(), table, "table_schema", "table_name"),
+        );
+
+        Box::new(self.exec_sql(sql).map(|df| {
+            let (names, types) = two_string_columns(&df);
+
+            names.into_iter()
+                .zip(types)
+                .map(|(name, column_type)| ColumnInfo { name, column_type })
+                .collect()
+        }))
     }
 }
 
+const SYSTEM_SCHEMAS: &[&str] = &[
+    "information_schema",
+    "pg_catalog",
+    "mysql",
+    "performance_schema",
+    "sys",
+];
+
+/// Sql condition matching `table`, which may be qualified by its schema. The names are
+/// escaped as literals of the dialect, as the table name may come from user input.
+pub fn table_filter(dialect: &dyn SqlDialect, table: &str, schema_col: &str, table_col: &str) -> String {
+    let escape = |s: &str| dialect.escape_literal(s);
+
+    let mut parts = table.rsplitn(2, '.');
+    let name = parts.next().unwrap_or(table);
+
+    match parts.next() {
+        Some(schema) => format!(
+            "{} = '{}' and {} = '{}'",
+            schema_col, escape(schema), table_col, escape(name),
+        ),
+        None => format!("{} = '{}'", table_col, escape(name)),
+    }
+}
+
+static QUERY_TAGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Prefixes sql with a comment unique to the query, e.g. `/* tesseract 1f2e-123-4 */`, by
+/// which it can be found among the database's running queries to cancel it. Returns the
+/// tag and the tagged sql.
+pub fn tag_sql(sql: &str) -> (String, String) {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let tag = format!(
+        "/* tesseract {:x}-{}-{} */",
+        nanos,
+        process::id(),
+        QUERY_TAGS.fetch_add(1, Ordering::Relaxed),
+    );
+    let sql = format!("{} {}", tag, sql);
+
+    (tag, sql)
+}
+
+/// Wraps the future (or stream) of a query, and calls `cancel` if it's dropped before it
+/// finishes. Actix drops the response future of a request when the client disconnects, so
+/// backends use it to cancel the query in the database too.
+pub struct CancelOnDrop<F> {
+    inner: F,
+    cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<F> CancelOnDrop<F> {
+    pub fn new<C: FnOnce() + Send + 'static>(inner: F, cancel: C) -> Self {
+        CancelOnDrop {
+            inner,
+            cancel: Some(Box::new(cancel)),
+        }
+    }
+}
+
+impl<F: Future> Future for CancelOnDrop<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let res = self.inner.poll();
+        match res {
+            Ok(Async::NotReady) => (),
+            _ => self.cancel = None,
+        }
+        res
+    }
+}
+
+impl<S: Stream> Stream for CancelOnDrop<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        let res = self.inner.poll();
+        match res {
+            Ok(Async::Ready(Some(_))) | Ok(Async::NotReady) => (),
+            _ => self.cancel = None,
+        }
+        res
+    }
+}
+
+impl<F> Drop for CancelOnDrop<F> {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+/// The first two columns of an introspection query, as strings.
+pub fn two_string_columns(df: &DataFrame) -> (Vec<String>, Vec<String>) {
+    let mut cols = df.columns.iter().map(|col| col.stringify_column_data());
+
+    (
+        cols.next().unwrap_or_default(),
+        cols.next().unwrap_or_default(),
+    )
+}
+
 impl Clone for Box<dyn Backend + Send + Sync> {
     fn clone(&self) -> Box<dyn Backend + Send + Sync> {
         self.box_clone()
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table_filter() {
+        assert_eq!(
+            table_filter(&StandardSql, "sales", "table_schema", "table_name"),
+            "table_name = 'sales'"
+        );
+        assert_eq!(
+            table_filter(&StandardSql, "public.sales", "table_schema", "table_name"),
+            "table_schema = 'public' and table_name = 'sales'"
+        );
+        assert_eq!(
+            table_filter(&StandardSql, "o'brien", "database", "table"),
+            "table = 'o''brien'"
+        );
+
+        // dialects reading backslash escapes escape those too
+        struct BackslashSql;
+        impl SqlDialect for BackslashSql {
+            fn name(&self) -> &str {
+                "backslash"
+            }
+
+            fn generate_sql(&self, query_ir: QueryIr) -> String {
+                StandardSql.generate_sql(query_ir)
+            }
+
+            fn escape_literal(&self, s: &str) -> String {
+                s.replace('\\', "\\\\").replace('\'', "\\'")
+            }
+        }
+        assert_eq!(
+            table_filter(&BackslashSql, "o\\'brien", "database", "table"),
+            "table = 'o\\\\\\'brien'"
+        );
+    }
+
+    #[test]
+    fn test_cancel_on_drop() {
+        use futures::future;
+        use std::sync::atomic::AtomicBool;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled_clone = cancelled.clone();
+        let fut = CancelOnDrop::new(future::ok::<_, ()>(1), move || cancelled_clone.store(true, Ordering::SeqCst));
+        assert_eq!(fut.wait(), Ok(1));
+        assert!(!cancelled.load(Ordering::SeqCst));
+
+        let cancelled_clone = cancelled.clone();
+        let fut = CancelOnDrop::new(future::empty::<(), ()>(), move || cancelled_clone.store(true, Ordering::SeqCst));
+        drop(fut);
+        assert!(cancelled.load(Ordering::SeqCst));
+
+        let (tag, sql) = tag_sql("select 1");
+        assert!(tag.starts_with("/* tesseract ") && tag.ends_with(" */"));
+        assert_eq!(sql, format!("{} select 1", tag));
+        assert_ne!(tag_sql("select 1").0, tag);
+    }
+}
@@ -0,0 +1,113 @@
+//! Chunked assembly of a `DataFrame` from rows.
+//!
+//! Drivers which read rows one at a time, without a row count, would otherwise push into
+//! columns which reallocate and copy all of their rows each time they grow. The
+//! `DataFrameBuilder` pushes into chunks of a fixed number of rows instead, and copies each
+//! chunk once into the finished `DataFrame`, which is allocated with its exact size.
+
+use failure::Error;
+use std::mem;
+
+use crate::dataframe::{Column, DataFrame};
+
+
+pub const DEFAULT_CHUNK_ROWS: usize = 65_536;
+
+#[derive(Debug)]
+pub struct DataFrameBuilder {
+    chunk_rows: usize,
+    chunks: Vec<DataFrame>,
+    current: DataFrame,
+    current_rows: usize,
+}
+
+impl DataFrameBuilder {
+    /// `columns` are empty columns of the types of the results.
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self::with_chunk_rows(columns, DEFAULT_CHUNK_ROWS)
+    }
+
+    pub fn with_chunk_rows(columns: Vec<Column>, chunk_rows: usize) -> Self {
+        // the first chunk grows as usual, so that small results don't allocate a whole chunk
+        DataFrameBuilder {
+            chunk_rows: chunk_rows.max(1),
+            chunks: vec![],
+            current: DataFrame::from_vec(columns),
+            current_rows: 0,
+        }
+    }
+
+    /// The columns of the current chunk, to push the values of a row into. Each row
+    /// must be followed by `end_row`.
+    pub fn columns_mut(&mut self) -> &mut [Column] {
+        &mut self.current.columns
+    }
+
+    /// Ends a row, starting a new chunk when the current one is full.
+    pub fn end_row(&mut self) {
+        self.current_rows += 1;
+
+        if self.current_rows >= self.chunk_rows {
+            let next = self.current.columns.iter()
+                .map(|col| Column::new(col.name.clone(), col.column_data.empty_with_capacity(self.chunk_rows)))
+                .collect();
+
+            self.chunks.push(mem::replace(&mut self.current, DataFrame::from_vec(next)));
+            self.current_rows = 0;
+        }
+    }
+
+    /// Concatenates the chunks. A chunk whose number columns widened while being read
+    /// widens the whole column.
+    pub fn finish(self) -> Result<DataFrame, Error> {
+        if self.chunks.is_empty() {
+            return Ok(self.current);
+        }
+
+        let rows: usize = self.chunks.iter().map(|chunk| chunk.len()).sum::<usize>() + self.current.len();
+
+        let mut chunks = self.chunks.into_iter().chain(Some(self.current));
+        let mut df = chunks.next().expect("checked not empty");
+        let first_rows = df.len();
+        df.reserve(rows - first_rows);
+
+        for chunk in chunks {
+            for (col, chunk_col) in df.columns.iter_mut().zip(chunk.columns) {
+                col.column_data.append(chunk_col.column_data)?;
+            }
+        }
+
+        Ok(df)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dataframe::ColumnData;
+
+    #[test]
+    fn test_chunked_builder() {
+        let mut builder = DataFrameBuilder::with_chunk_rows(vec![
+            Column::new("id".into(), ColumnData::Int32(vec![])),
+            Column::new("name".into(), ColumnData::Text(vec![])),
+        ], 2);
+
+        for (id, name) in &[(1, "a"), (2, "b"), (5_000_000_000, "c"), (4, "d"), (5, "e")] {
+            for col in builder.columns_mut() {
+                match col.column_data {
+                    ColumnData::Text(ref mut v) => v.push(name.to_string()),
+                    ref mut column_data => column_data.push_i64(*id).unwrap(),
+                }
+            }
+            builder.end_row();
+        }
+
+        let df = builder.finish().unwrap();
+        assert_eq!(df.columns[0].column_data, ColumnData::Int64(vec![1, 2, 5_000_000_000, 4, 5]));
+        assert_eq!(df.columns[1].column_data, ColumnData::Text(
+            vec!["a".into(), "b".into(), "c".into(), "d".into(), "e".into()]
+        ));
+    }
+}
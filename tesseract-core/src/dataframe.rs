@@ -1,4 +1,11 @@
-use failure::{Error, format_err};
+use failure::{Error, bail, format_err};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::query::SortDirection;
+use crate::query_ir::{AllMemberColumn, LimitSql};
+use crate::text_dict::TextDict;
 
 
 #[derive(Debug)]
@@ -33,6 +40,7 @@ impl DataFrame {
                 ColumnData::Float32(ref ns) => ns.len(),
                 ColumnData::Float64(ref ns) => ns.len(),
                 ColumnData::Text(ref ss) => ss.len(),
+                ColumnData::DictText(ref d) => d.len(),
                 ColumnData::NullableInt8(ref ns) => ns.len(),
                 ColumnData::NullableInt16(ref ns) => ns.len(),
                 ColumnData::NullableInt32(ref ns) => ns.len(),
@@ -49,6 +57,1080 @@ impl DataFrame {
             0
         }
     }
+
+    /// Keeps only the first `len` rows.
+    pub fn truncate(&mut self, len: usize) {
+        for col in &mut self.columns {
+            col.column_data.truncate(len);
+        }
+    }
+
+    /// Reserves capacity for at least `additional` more rows in each column, e.g. from the
+    /// row count of a driver's results.
+    pub fn reserve(&mut self, additional: usize) {
+        for col in &mut self.columns {
+            col.column_data.reserve(additional);
+        }
+    }
+
+    /// Takes off the row number and total columns added by `SqlDialect::preview_sql`, and
+    /// returns the total number of rows of the query. No rows means a total of 0.
+    pub fn take_preview_total(&mut self) -> Result<u64, Error> {
+        if self.columns.len() < 2 {
+            bail!("Preview results are missing the row number and total columns");
+        }
+
+        let total_col = self.columns.pop().expect("checked length");
+        self.columns.pop();
+
+        match total_col.stringify_column_data().first() {
+            Some(total) => total.parse::<u64>()
+                .map_err(|_| format_err!("Preview total is not a number: {}", total)),
+            None => Ok(0),
+        }
+    }
+
+    /// Inserts the columns of drilldowns on All levels, which repeat the All member on each
+    /// row, at their indexes in the results.
+    pub fn insert_all_members(&mut self, all_members: &[AllMemberColumn]) -> Result<(), Error> {
+        let len = self.len();
+
+        for all in all_members {
+            if all.idx > self.columns.len() {
+                bail!("All member columns do not match the results");
+            }
+
+            let dict = TextDict {
+                values: vec![all.member.clone()],
+                codes: vec![0; len],
+            };
+            self.columns.insert(all.idx, Column::new(all.member.clone(), ColumnData::DictText(dict)));
+        }
+
+        Ok(())
+    }
+
+    /// Reshapes the DataFrame into wide format: each member of the `pivot_idx` column
+    /// becomes a set of columns, one per measure in `mea_idxs`, named `<member> <measure>`.
+    /// The remaining columns identify the rows, except for `drop_idxs` (e.g. the ID column
+    /// of the pivot level), which are left out.
+    ///
+    /// Rows and members keep the order in which they first appear. Combinations which
+    /// don't have a row are null.
+    pub fn pivot(
+        &self,
+        headers: &[String],
+        pivot_idx: usize,
+        drop_idxs: &[usize],
+        mea_idxs: &[usize],
+    ) -> Result<(Vec<String>, DataFrame), Error>
+    {
+        if headers.len() != self.columns.len() {
+            return Err(format_err!("Headers don't match the number of columns"));
+        }
+        if mea_idxs.is_empty() {
+            return Err(format_err!("Pivot requires at least one measure"));
+        }
+
+        let key_idxs: Vec<usize> = (0..self.columns.len())
+            .filter(|i| *i != pivot_idx && !drop_idxs.contains(i) && !mea_idxs.contains(i))
+            .collect();
+
+        let key_data: Vec<Vec<String>> = key_idxs.iter()
+            .map(|i| self.columns[*i].stringify_column_data())
+            .collect();
+        let pivot_data = self.columns[pivot_idx].stringify_column_data();
+
+        // first row index of each row key, and the row index of each (row key, member)
+        let mut row_keys: Vec<Vec<&str>> = vec![];
+        let mut row_key_idxs: HashMap<Vec<&str>, usize> = HashMap::new();
+        let mut first_rows: Vec<usize> = vec![];
+        let mut members: Vec<&str> = vec![];
+        let mut cells: HashMap<(usize, &str), usize> = HashMap::new();
+
+        for row in 0..self.len() {
+            let row_key: Vec<&str> = key_data.iter().map(|col| col[row].as_str()).collect();
+
+            let row_key_idx = match row_key_idxs.get(&row_key) {
+                Some(idx) => *idx,
+                None => {
+                    let idx = row_keys.len();
+                    row_key_idxs.insert(row_key.clone(), idx);
+                    row_keys.push(row_key);
+                    first_rows.push(row);
+                    idx
+                }
+            };
+
+            let member = pivot_data[row].as_str();
+            if !members.contains(&member) {
+                members.push(member);
+            }
+
+            cells.insert((row_key_idx, member), row);
+        }
+
+        let mut pivot_headers = vec![];
+        let mut columns = vec![];
+
+        let first_rows: Vec<Option<usize>> = first_rows.into_iter().map(Some).collect();
+        for i in &key_idxs {
+            pivot_headers.push(headers[*i].clone());
+            columns.push(Column::new(
+                self.columns[*i].name.clone(),
+                self.columns[*i].column_data.take_nullable(&first_rows),
+            ));
+        }
+
+        for member in &members {
+            let member_rows: Vec<Option<usize>> = (0..row_keys.len())
+                .map(|row_key_idx| cells.get(&(row_key_idx, *member)).cloned())
+                .collect();
+
+            for i in mea_idxs {
+                let name = format!("{} {}", member, headers[*i]);
+                pivot_headers.push(name.clone());
+                columns.push(Column::new(
+                    name,
+                    self.columns[*i].column_data.take_nullable(&member_rows),
+                ));
+            }
+        }
+
+        Ok((pivot_headers, DataFrame::from_vec(columns)))
+    }
+
+    /// Appends subtotal rows and a grand total row, summing the measures in `mea_idxs`.
+    ///
+    /// `drill_idxs` holds the columns of each drilldown, in order. Like a SQL rollup, there
+    /// are subtotals for every prefix of the drilldowns (all but the last, then all but the
+    /// last two...), ending with the grand total. Columns not grouped on are null in those rows.
+    ///
+    /// Only correct for additive measures. Integer measures are widened to 64 bits, so that
+    /// their totals fit; a total which doesn't fit in 64 bits is an error.
+    pub fn with_totals(&self, drill_idxs: &[Vec<usize>], mea_idxs: &[usize]) -> Result<DataFrame, Error> {
+        let all_rows: Vec<Option<usize>> = (0..self.len()).map(Some).collect();
+        let row_groups: Vec<Vec<usize>> = (0..self.len()).map(|row| vec![row]).collect();
+
+        let mut columns: Vec<Column> = vec![];
+        for (i, col) in self.columns.iter().enumerate() {
+            // summing each row on its own widens the measure like its totals
+            let column_data = if mea_idxs.contains(&i) {
+                col.column_data.sum_nullable(&row_groups)?
+            } else {
+                col.column_data.take_nullable(&all_rows)
+            };
+            columns.push(Column::new(col.name.clone(), column_data));
+        }
+
+        if self.len() == 0 {
+            return Ok(DataFrame::from_vec(columns));
+        }
+
+        let str_columns: Vec<Vec<String>> = self.columns.iter()
+            .map(|col| col.stringify_column_data())
+            .collect();
+
+        for k in (0..drill_idxs.len()).rev() {
+            let group_idxs = drill_idxs[..k].concat();
+
+            // rows of each group, in the order in which the groups first appear
+            let mut group_keys: HashMap<Vec<&str>, usize> = HashMap::new();
+            let mut groups: Vec<Vec<usize>> = vec![];
+
+            for row in 0..self.len() {
+                let key: Vec<&str> = group_idxs.iter().map(|i| str_columns[*i][row].as_str()).collect();
+
+                match group_keys.get(&key) {
+                    Some(group_idx) => groups[*group_idx].push(row),
+                    None => {
+                        group_keys.insert(key, groups.len());
+                        groups.push(vec![row]);
+                    },
+                }
+            }
+
+            let first_rows: Vec<Option<usize>> = groups.iter().map(|group| Some(group[0])).collect();
+            let nulls: Vec<Option<usize>> = vec![None; groups.len()];
+
+            for (i, col) in self.columns.iter().enumerate() {
+                let total_data = if group_idxs.contains(&i) {
+                    col.column_data.take_nullable(&first_rows)
+                } else if mea_idxs.contains(&i) {
+                    col.column_data.sum_nullable(&groups)?
+                } else {
+                    col.column_data.take_nullable(&nulls)
+                };
+
+                columns[i].column_data.append_nullable(total_data)?;
+            }
+        }
+
+        Ok(DataFrame::from_vec(columns))
+    }
+
+    /// Adds a row for each of `periods` missing from each group of rows, so that time series
+    /// have no gaps. `time_idxs` are the columns of the time level: its ID, which holds the
+    /// period, and optionally its label. Rows are grouped by the keys of the other drilldowns
+    /// (`key_idxs`); their other columns, like names, parents and properties, don't split
+    /// the groups.
+    ///
+    /// The measures of added rows are zero or null, depending on `fill`, and their other
+    /// columns are copied from the first row of their group. The label of an added period is
+    /// copied from a row with the same period, or else is the period itself.
+    /// Groups keep the order in which they first appear, and are sorted in the order of
+    /// `periods`; rows of other periods come last in their group.
+    pub fn fill_periods(
+        &self,
+        time_idxs: &[usize],
+        key_idxs: &[usize],
+        mea_idxs: &[usize],
+        periods: &[String],
+        fill: TimeFill,
+    ) -> Result<DataFrame, Error>
+    {
+        let period_idx = *time_idxs.first()
+            .ok_or_else(|| format_err!("Filling periods requires a time column"))?;
+
+        if key_idxs.iter().any(|i| *i >= self.columns.len()) {
+            bail!("Drilldown columns do not match the results");
+        }
+
+        let str_columns: Vec<Vec<String>> = self.columns.iter()
+            .map(|col| col.stringify_column_data())
+            .collect();
+        let period_data = &str_columns[period_idx];
+
+        // rows of each group, in the order in which the groups first appear
+        let mut group_keys: HashMap<Vec<&str>, usize> = HashMap::new();
+        let mut groups: Vec<Vec<usize>> = vec![];
+        // first row of each period, for the labels of added rows
+        let mut period_rows: HashMap<&str, usize> = HashMap::new();
+
+        for row in 0..self.len() {
+            let key: Vec<&str> = key_idxs.iter().map(|i| str_columns[*i][row].as_str()).collect();
+
+            match group_keys.get(&key) {
+                Some(group_idx) => groups[*group_idx].push(row),
+                None => {
+                    group_keys.insert(key, groups.len());
+                    groups.push(vec![row]);
+                },
+            }
+
+            period_rows.entry(period_data[row].as_str()).or_insert(row);
+        }
+
+        let period_set: HashSet<&str> = periods.iter().map(|p| p.as_str()).collect();
+
+        let mut fill_rows = vec![];
+        for group in &groups {
+            let mut group_periods: HashMap<&str, Vec<usize>> = HashMap::new();
+            for row in group {
+                group_periods.entry(period_data[*row].as_str()).or_insert_with(Vec::new).push(*row);
+            }
+
+            for period in periods {
+                match group_periods.get(period.as_str()) {
+                    Some(rows) => fill_rows.extend(rows.iter().map(|row| FillRow::Existing(*row))),
+                    None => fill_rows.push(FillRow::Added { group_row: group[0], period: period.as_str() }),
+                }
+            }
+
+            fill_rows.extend(
+                group.iter()
+                    .filter(|row| !period_set.contains(period_data[**row].as_str()))
+                    .map(|row| FillRow::Existing(*row))
+            );
+        }
+
+        let mut columns = vec![];
+
+        for (i, col) in self.columns.iter().enumerate() {
+            let is_time = time_idxs.contains(&i);
+            let is_mea = mea_idxs.contains(&i);
+
+            let idxs: Vec<Option<usize>> = fill_rows.iter()
+                .map(|fill_row| match fill_row {
+                    FillRow::Existing(row) => Some(*row),
+                    FillRow::Added { period, .. } if is_time => period_rows.get(period).cloned(),
+                    FillRow::Added { .. } if is_mea => None,
+                    FillRow::Added { group_row, .. } => Some(*group_row),
+                })
+                .collect();
+
+            let mut column_data = col.column_data.take_nullable(&idxs);
+
+            for (row, fill_row) in fill_rows.iter().enumerate() {
+                if let FillRow::Added { period, .. } = fill_row {
+                    if is_time && !period_rows.contains_key(period) {
+                        column_data.set_nullable_str(row, period)?;
+                    } else if is_mea && fill == TimeFill::Zero {
+                        column_data.set_nullable_str(row, "0")?;
+                    }
+                }
+            }
+
+            columns.push(Column::new(col.name.clone(), column_data));
+        }
+
+        Ok(DataFrame::from_vec(columns))
+    }
+
+    /// Calculates growth on results queried without it, for dialects which can't calculate
+    /// it in sql. Rows are grouped by the drilldown columns other than the time columns, and
+    /// sorted by time within each group. Growth is the change of the measure from the
+    /// previous period in the group, divided by the previous value; it's null for the first
+    /// period of a group, and when the previous value is null or zero.
+    ///
+    /// Columns are moved into the same order as the sql calculation: time columns after the
+    /// other drilldown columns, and the growth measure after the other measures, followed by
+    /// the growth and the change in value. Grouping loses the query's sort, so rows are
+    /// sorted again on the `sort` columns.
+    pub fn with_growth(&self, growth: &GrowthColumns) -> Result<DataFrame, Error> {
+        let mea_end = growth.drill_count + growth.mea_count;
+
+        if growth.time_idxs.is_empty() || mea_end > self.columns.len() {
+            return Err(format_err!("Growth columns do not match the results"));
+        }
+
+        let str_columns: Vec<Vec<String>> = self.columns.iter()
+            .map(|col| col.stringify_column_data())
+            .collect();
+
+        let key_idxs: Vec<usize> = (0..growth.drill_count)
+            .filter(|i| !growth.time_idxs.contains(i))
+            .collect();
+
+        let groups = period_groups(&str_columns, growth.drill_count, &growth.time_idxs, self.len());
+        let (growths, diffs) = group_growths(&self.float_values(growth.mea_idx)?, &groups);
+
+        // rows are returned by group, in order of period
+        let rows: Vec<Option<usize>> = groups.iter().flatten().map(|row| Some(*row)).collect();
+        let growths = rows.iter().map(|row| row.and_then(|row| growths[row])).collect();
+        let diffs = rows.iter().map(|row| row.and_then(|row| diffs[row])).collect();
+
+        let col_order = key_idxs.iter().cloned()
+            .chain(growth.time_idxs.iter().cloned())
+            .chain((growth.drill_count..mea_end).filter(|i| *i != growth.mea_idx))
+            .chain(Some(growth.mea_idx));
+
+        let mut columns: Vec<Column> = col_order
+            .map(|i| {
+                let col = &self.columns[i];
+                Column::new(col.name.clone(), col.column_data.take_nullable(&rows))
+            })
+            .collect();
+
+        columns.push(Column::new("growth".to_owned(), ColumnData::NullableFloat64(growths)));
+        columns.push(Column::new("growth_value".to_owned(), ColumnData::NullableFloat64(diffs)));
+
+        // calculations after the measures, like rate, stay last
+        columns.extend(self.columns[mea_end..].iter()
+            .map(|col| Column::new(col.name.clone(), col.column_data.take_nullable(&rows)))
+        );
+
+        let mut order: Vec<usize> = (0..rows.len()).collect();
+
+        if !growth.sort.is_empty() {
+            let sort_values = growth.sort.iter()
+                .map(|(idx, direction)| columns.get(*idx).map(|col| (col.stringify_column_data(), direction)))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format_err!("Growth sort columns do not match the results"))?;
+
+            order.sort_by(|a, b| {
+                sort_values.iter()
+                    .map(|(values, direction)| {
+                        // nulls last, as with the default of most databases
+                        match (values[*a].is_empty(), values[*b].is_empty()) {
+                            (true, false) => Ordering::Greater,
+                            (false, true) => Ordering::Less,
+                            _ if **direction == SortDirection::Desc => cmp_values(&values[*b], &values[*a]),
+                            _ => cmp_values(&values[*a], &values[*b]),
+                        }
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+
+        // the limit is only applied once growth is calculated on all the rows
+        if let Some(ref limit) = growth.limit {
+            order = order.into_iter()
+                .skip(limit.offset.unwrap_or(0) as usize)
+                .take(limit.n as usize)
+                .collect();
+        }
+
+        if !growth.sort.is_empty() || growth.limit.is_some() {
+            let order: Vec<_> = order.into_iter().map(Some).collect();
+            columns = columns.into_iter()
+                .map(|col| Column::new(col.name, col.column_data.take_nullable(&order)))
+                .collect();
+        }
+
+        Ok(DataFrame::from_vec(columns))
+    }
+
+    /// Appends the `calculations` of each measure in `columns` as new columns, after the
+    /// existing ones, and their names to `headers`; rows keep their order.
+    ///
+    /// - Growth is the change from the previous period of the same drilldown members,
+    ///   relative to it. It needs a time drilldown.
+    /// - Share is the part of the total of the measure in the same period, or in all of the
+    ///   results without a time drilldown.
+    pub fn add_calculations(
+        &mut self,
+        headers: &mut Vec<String>,
+        columns: &CalculationColumns,
+        calculations: &[ResultCalculation],
+        ) -> Result<(), Error>
+    {
+        if columns.drill_count > self.columns.len() || columns.mea_idxs.iter().any(|i| *i >= self.columns.len()) {
+            return Err(format_err!("Calculation columns do not match the results"));
+        }
+
+        let str_columns: Vec<Vec<String>> = self.columns.iter()
+            .map(|col| col.stringify_column_data())
+            .collect();
+
+        let mut new_columns = vec![];
+
+        for calculation in calculations {
+            if *calculation == ResultCalculation::Growth && columns.time_idxs.is_empty() {
+                return Err(format_err!("Growth calculation needs a time drilldown"));
+            }
+
+            let groups = match calculation {
+                ResultCalculation::Growth => period_groups(&str_columns, columns.drill_count, &columns.time_idxs, self.len()),
+                ResultCalculation::Share => group_rows(&str_columns, &columns.time_idxs, self.len()),
+            };
+
+            for mea_idx in &columns.mea_idxs {
+                let values = self.float_values(*mea_idx)?;
+                let results = match calculation {
+                    ResultCalculation::Growth => group_growths(&values, &groups).0,
+                    ResultCalculation::Share => group_shares(&values, &groups),
+                };
+
+                let header = headers.get(*mea_idx)
+                    .ok_or_else(|| format_err!("Results have no header for column {}", mea_idx))?;
+                new_columns.push(Column::new(
+                    format!("{} {}", header, calculation),
+                    ColumnData::NullableFloat64(results),
+                ));
+            }
+        }
+
+        for column in new_columns {
+            headers.push(column.name.clone());
+            self.columns.push(column);
+        }
+
+        Ok(())
+    }
+
+    /// Appends the share of each row in the total of the measure in `share`, within its
+    /// group, as a `share` column.
+    pub fn add_share(&mut self, share: &ShareColumns) -> Result<(), Error> {
+        if share.mea_idx >= self.columns.len() || share.group_idxs.iter().any(|i| *i >= self.columns.len()) {
+            return Err(format_err!("Share columns do not match the results"));
+        }
+
+        let str_columns: Vec<Vec<String>> = share.group_idxs.iter()
+            .map(|i| self.columns[*i].stringify_column_data())
+            .collect();
+        let key_idxs: Vec<usize> = (0..str_columns.len()).collect();
+
+        let groups = group_rows(&str_columns, &key_idxs, self.len());
+        let shares = group_shares(&self.float_values(share.mea_idx)?, &groups);
+
+        self.columns.push(Column::new("share".to_owned(), ColumnData::NullableFloat64(shares)));
+
+        Ok(())
+    }
+
+    /// Appends the value of the measure in `columns` in the same period of the previous year,
+    /// as a `previous_year` column, followed by the difference from it and the growth over
+    /// it, which is null when the previous value is null or zero. Rows are compared with the
+    /// rows of the same members of the other drilldowns.
+    ///
+    /// Rows outside of the `period_cuts`, which were only queried to be compared to, are
+    /// dropped afterwards.
+    pub fn add_previous_year(&mut self, columns: &PreviousYearColumns) -> Result<(), Error> {
+        let len = self.columns.len();
+        if columns.time_idx >= len || columns.mea_idx >= len || columns.group_idxs.iter().any(|i| *i >= len) {
+            return Err(format_err!("Previous year columns do not match the results"));
+        }
+
+        let str_columns: Vec<Vec<String>> = columns.group_idxs.iter()
+            .map(|i| self.columns[*i].stringify_column_data())
+            .collect();
+        let keys: Vec<Vec<&str>> = (0..self.len())
+            .map(|row| str_columns.iter().map(|col| col[row].as_str()).collect())
+            .collect();
+
+        let periods = self.columns[columns.time_idx].stringify_column_data().iter()
+            .map(|period| {
+                period.parse::<i64>()
+                    .ok()
+                    .filter(|key| columns.period_level.is_key(*key))
+                    .ok_or_else(|| format_err!("Time key {} is not a {} period ({})", period, columns.period_level, columns.period_level.key_format()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let rows: HashMap<(&[&str], i64), usize> = keys.iter()
+            .zip(&periods)
+            .enumerate()
+            .map(|(row, (key, period))| ((key.as_slice(), *period), row))
+            .collect();
+
+        let values = self.float_values(columns.mea_idx)?;
+        let offset = columns.period_level.year_offset();
+
+        let mut prevs = Vec::with_capacity(self.len());
+        let mut diffs = Vec::with_capacity(self.len());
+        let mut growths = Vec::with_capacity(self.len());
+
+        for (row, (key, period)) in keys.iter().zip(&periods).enumerate() {
+            let prev = rows.get(&(key.as_slice(), period - offset))
+                .and_then(|prev_row| values[*prev_row]);
+            let diff = match (values[row], prev) {
+                (Some(current), Some(prev)) => Some(current - prev),
+                _ => None,
+            };
+            let growth = match (diff, prev) {
+                (Some(diff), Some(prev)) if prev != 0.0 => Some(diff / prev),
+                _ => None,
+            };
+
+            prevs.push(prev);
+            diffs.push(diff);
+            growths.push(growth);
+        }
+
+        self.columns.push(Column::new("previous_year".to_owned(), ColumnData::NullableFloat64(prevs)));
+        self.columns.push(Column::new("previous_year_difference".to_owned(), ColumnData::NullableFloat64(diffs)));
+        self.columns.push(Column::new("previous_year_growth".to_owned(), ColumnData::NullableFloat64(growths)));
+
+        let kept: Vec<Option<usize>> = periods.iter()
+            .enumerate()
+            .filter(|(_, period)| {
+                columns.period_cuts.iter().all(|cut| {
+                    columns.period_level.containing_period(**period, cut.level)
+                        .map(|cut_period| cut.periods.contains(&cut_period))
+                        .unwrap_or(true)
+                })
+            })
+            .map(|(row, _)| Some(row))
+            .collect();
+
+        if kept.len() < periods.len() {
+            for column in &mut self.columns {
+                column.column_data = column.column_data.take_nullable(&kept);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keeps only the columns named in `columns`, in that order. Columns are named by their
+    /// header in `headers`, which is projected the same way.
+    pub fn project(&mut self, headers: &mut Vec<String>, columns: &[String]) -> Result<(), Error> {
+        if headers.len() != self.columns.len() {
+            return Err(format_err!("Headers do not match the results"));
+        }
+
+        let idxs = columns.iter()
+            .map(|column| {
+                headers.iter()
+                    .position(|header| header == column)
+                    .ok_or_else(|| format_err!("Column {} not found in results; columns are: {}", column, headers.join(", ")))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut old_columns: Vec<Option<Column>> = self.columns.drain(..).map(Some).collect();
+        let old_headers = std::mem::replace(headers, vec![]);
+
+        for idx in idxs {
+            let column = match old_columns[idx].take() {
+                Some(column) => column,
+                None => return Err(format_err!("Column {} is repeated", old_headers[idx])),
+            };
+            self.columns.push(column);
+            headers.push(old_headers[idx].clone());
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the nulls of the measure columns at `idxs` with zeros.
+    pub fn fill_null_zeros(&mut self, idxs: &[usize]) -> Result<(), Error> {
+        for &idx in idxs {
+            let column = self.columns.get_mut(idx)
+                .ok_or_else(|| format_err!("Measure columns do not match the results"))?;
+            column.column_data.fill_nulls("0")?;
+        }
+
+        Ok(())
+    }
+
+    /// Converts the key columns at `widths`, `(index, width)`, to text keys zero-padded to
+    /// the width, for levels with a `key_width`.
+    pub fn pad_keys(&mut self, widths: &[(usize, usize)]) -> Result<(), Error> {
+        for &(idx, width) in widths {
+            let column = self.columns.get_mut(idx)
+                .ok_or_else(|| format_err!("Key columns do not match the results"))?;
+            let keys = column.stringify_column_data().into_iter()
+                .map(|key| pad_key(key, width))
+                .collect();
+            column.column_data = ColumnData::Text(keys);
+        }
+
+        Ok(())
+    }
+
+    /// Drops the key columns of levels which also have a name column, found by their
+    /// header, `<Level> ID`, right before the `<Level>` header.
+    pub fn drop_id_columns(&mut self, headers: &mut Vec<String>) -> Result<(), Error> {
+        let columns: Vec<String> = headers.iter()
+            .enumerate()
+            .filter(|(i, header)| {
+                headers.get(i + 1)
+                    .map(|next| **header != format!("{} ID", next))
+                    .unwrap_or(true)
+            })
+            .map(|(_, header)| header.clone())
+            .collect();
+
+        self.project(headers, &columns)
+    }
+
+    /// Values of a numeric column as floats, for calculations.
+    fn float_values(&self, idx: usize) -> Result<Vec<Option<f64>>, Error> {
+        match self.columns[idx].column_data.scale(1.0)? {
+            ColumnData::Float64(v) => Ok(v.into_iter().map(Some).collect()),
+            ColumnData::NullableFloat64(v) => Ok(v),
+            _ => Err(format_err!("Calculations need numeric measures")),
+        }
+    }
+
+    /// Scales the measure columns of `conversions` to their units. Measures are found by their
+    /// header, from the end since they come after the drilldown columns.
+    pub fn convert_units(&mut self, headers: &[String], conversions: &[UnitConversion]) -> Result<(), Error> {
+        for conversion in conversions {
+            let idx = headers.iter()
+                .rposition(|h| *h == conversion.measure)
+                .ok_or_else(|| format_err!("Measure {} to convert is not in the results", conversion.measure))?;
+            let column = self.columns.get_mut(idx)
+                .ok_or_else(|| format_err!("Results have no column for measure {}", conversion.measure))?;
+
+            column.column_data = column.column_data.scale(conversion.unit.factor())?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Columns of the results of a query used by `DataFrame::with_growth`, by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrowthColumns {
+    /// Number of drilldown columns, which come first.
+    pub drill_count: usize,
+    /// Columns of the time drilldown.
+    pub time_idxs: Vec<usize>,
+    /// Column of the growth measure.
+    pub mea_idx: usize,
+    /// Number of measure columns, after the drilldown columns.
+    pub mea_count: usize,
+    /// The query's sort, on the columns of the results with growth.
+    pub sort: Vec<(usize, SortDirection)>,
+    /// The query's limit, applied after the sort.
+    pub limit: Option<LimitSql>,
+}
+
+impl GrowthColumns {
+    /// Index in the results with growth of the column at `idx` in the results without it.
+    pub fn result_idx(&self, idx: usize) -> usize {
+        let mea_end = self.drill_count + self.mea_count;
+
+        if idx < self.drill_count {
+            match self.time_idxs.iter().position(|i| *i == idx) {
+                Some(pos) => self.drill_count - self.time_idxs.len() + pos,
+                None => idx - self.time_idxs.iter().filter(|i| **i < idx).count(),
+            }
+        } else if idx == self.mea_idx {
+            mea_end - 1
+        } else if idx < mea_end {
+            if idx > self.mea_idx { idx - 1 } else { idx }
+        } else {
+            // after the growth and the change in value
+            idx + 2
+        }
+    }
+}
+
+
+/// Rows grouped by their values in the `key_idxs` columns, in the order in which the groups
+/// first appear.
+fn group_rows(str_columns: &[Vec<String>], key_idxs: &[usize], len: usize) -> Vec<Vec<usize>> {
+    let mut group_keys: HashMap<Vec<&str>, usize> = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = vec![];
+
+    for row in 0..len {
+        let key: Vec<&str> = key_idxs.iter().map(|i| str_columns[*i][row].as_str()).collect();
+
+        match group_keys.get(&key) {
+            Some(group_idx) => groups[*group_idx].push(row),
+            None => {
+                group_keys.insert(key, groups.len());
+                groups.push(vec![row]);
+            },
+        }
+    }
+
+    groups
+}
+
+/// Rows grouped by their values in the drilldown columns other than the time columns, each
+/// group sorted by period.
+fn period_groups(str_columns: &[Vec<String>], drill_count: usize, time_idxs: &[usize], len: usize) -> Vec<Vec<usize>> {
+    let key_idxs: Vec<usize> = (0..drill_count)
+        .filter(|i| !time_idxs.contains(i))
+        .collect();
+
+    let mut groups = group_rows(str_columns, &key_idxs, len);
+    for group in &mut groups {
+        group.sort_by(|a, b| cmp_periods(str_columns, time_idxs, *a, *b));
+    }
+    groups
+}
+
+/// The growth of each row's value over the previous row of its group, and the change in value
+/// from it, by row. Both are null for the first row of a group and when either value is
+/// null, and growth also when the previous value is zero.
+fn group_growths(values: &[Option<f64>], groups: &[Vec<usize>]) -> (Vec<Option<f64>>, Vec<Option<f64>>) {
+    let mut growths = vec![None; values.len()];
+    let mut diffs = vec![None; values.len()];
+
+    for group in groups {
+        for pair in group.windows(2) {
+            if let (Some(prev), Some(current)) = (values[pair[0]], values[pair[1]]) {
+                diffs[pair[1]] = Some(current - prev);
+                if prev != 0.0 {
+                    growths[pair[1]] = Some((current - prev) / prev);
+                }
+            }
+        }
+    }
+
+    (growths, diffs)
+}
+
+/// The share of each row's value in the total of its group, by row. Null when the value is
+/// null or the total is zero.
+fn group_shares(values: &[Option<f64>], groups: &[Vec<usize>]) -> Vec<Option<f64>> {
+    let mut shares = vec![None; values.len()];
+
+    for group in groups {
+        let total: f64 = group.iter().filter_map(|row| values[*row]).sum();
+        for row in group {
+            shares[*row] = match values[*row] {
+                Some(value) if total != 0.0 => Some(value / total),
+                _ => None,
+            };
+        }
+    }
+
+    shares
+}
+
+/// Compares the periods of two rows. Time ids are usually numbers, which don't sort
+/// correctly as strings.
+fn cmp_periods(str_columns: &[Vec<String>], time_idxs: &[usize], a: usize, b: usize) -> Ordering {
+    for idx in time_idxs {
+        let ordering = cmp_values(&str_columns[*idx][a], &str_columns[*idx][b]);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Compares two values of a column, as numbers if they both are.
+fn cmp_values(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+
+/// Columns of the results of a query used by `DataFrame::add_calculations`, by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalculationColumns {
+    /// Number of drilldown columns, which come first.
+    pub drill_count: usize,
+    /// Columns of the time drilldown, if there is one.
+    pub time_idxs: Vec<usize>,
+    /// Columns of the measures to calculate on.
+    pub mea_idxs: Vec<usize>,
+}
+
+
+/// Columns of the results of a query used by `DataFrame::add_share`, by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareColumns {
+    /// Columns of the drilldowns other than the share level; rows with the same members
+    /// in them are a group.
+    pub group_idxs: Vec<usize>,
+    /// Column of the share measure.
+    pub mea_idx: usize,
+}
+
+
+/// Columns of the results of a query used by `DataFrame::add_previous_year`, by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviousYearColumns {
+    /// Columns of the drilldowns other than the time level; rows with the same members
+    /// in them are compared.
+    pub group_idxs: Vec<usize>,
+    /// Key column of the time level.
+    pub time_idx: usize,
+    pub period_level: PeriodLevel,
+    /// Column of the compared measure.
+    pub mea_idx: usize,
+    /// Cuts on the time dimension, which were widened to the previous year.
+    pub period_cuts: Vec<PeriodCut>,
+}
+
+/// Periods of a cut on a time level, before the previous year was added to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodCut {
+    pub level: PeriodLevel,
+    pub periods: Vec<i64>,
+}
+
+/// Time levels which can be compared with the previous year, by the format of their keys:
+/// `YYYY` for years, `YYYYQ` for quarters and `YYYYMM` for months. Ordered from the
+/// coarsest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PeriodLevel {
+    Year,
+    Quarter,
+    Month,
+}
+
+impl PeriodLevel {
+    /// The period of a level named `Year`, `Quarter` or `Month`, as for logic layer
+    /// time cuts.
+    pub fn from_level_name(level_name: &str) -> Option<Self> {
+        match level_name {
+            "Year" => Some(PeriodLevel::Year),
+            "Quarter" => Some(PeriodLevel::Quarter),
+            "Month" => Some(PeriodLevel::Month),
+            _ => None,
+        }
+    }
+
+    /// Whether `key` has the format of this level's keys, e.g. `201907` for a month. Keys
+    /// of a month within its year (`1` to `12`) don't, since they can't tell years apart.
+    pub fn is_key(&self, key: i64) -> bool {
+        match self {
+            PeriodLevel::Year => (1000..=9999).contains(&key),
+            PeriodLevel::Quarter => (10000..=99999).contains(&key) && (1..=4).contains(&(key % 10)),
+            PeriodLevel::Month => (100000..=999999).contains(&key) && (1..=12).contains(&(key % 100)),
+        }
+    }
+
+    /// Format of this level's keys.
+    pub fn key_format(&self) -> &str {
+        match self {
+            PeriodLevel::Year => "YYYY",
+            PeriodLevel::Quarter => "YYYYQ",
+            PeriodLevel::Month => "YYYYMM",
+        }
+    }
+
+    /// Difference between the key of a period and the key of the same period of the
+    /// previous year.
+    pub fn year_offset(&self) -> i64 {
+        match self {
+            PeriodLevel::Year => 1,
+            PeriodLevel::Quarter => 10,
+            PeriodLevel::Month => 100,
+        }
+    }
+
+    /// Key of the period of `level` which contains `period`, a key of this level. `None` if
+    /// `level` is finer.
+    pub fn containing_period(&self, period: i64, level: PeriodLevel) -> Option<i64> {
+        match (self, level) {
+            (PeriodLevel::Month, PeriodLevel::Quarter) => Some(period / 100 * 10 + (period % 100 - 1) / 3 + 1),
+            (PeriodLevel::Month, PeriodLevel::Year) => Some(period / 100),
+            (PeriodLevel::Quarter, PeriodLevel::Year) => Some(period / 10),
+            _ if *self == level => Some(period),
+            _ => None,
+        }
+    }
+
+    /// First and last keys of this level's periods within `period`, a key of `level`: the
+    /// inverse of `containing_period`. `None` if `level` is finer.
+    pub fn contained_periods(&self, period: i64, level: PeriodLevel) -> Option<(i64, i64)> {
+        match (self, level) {
+            (PeriodLevel::Month, PeriodLevel::Quarter) => {
+                let first = period / 10 * 100 + (period % 10 - 1) * 3 + 1;
+                Some((first, first + 2))
+            },
+            (PeriodLevel::Month, PeriodLevel::Year) => Some((period * 100 + 1, period * 100 + 12)),
+            (PeriodLevel::Quarter, PeriodLevel::Year) => Some((period * 10 + 1, period * 10 + 4)),
+            _ if *self == level => Some((period, period)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for PeriodLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let level = match self {
+            PeriodLevel::Year => "year",
+            PeriodLevel::Quarter => "quarter",
+            PeriodLevel::Month => "month",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+
+/// Calculation which `DataFrame::add_calculations` appends for each measure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultCalculation {
+    Growth,
+    Share,
+}
+
+impl FromStr for ResultCalculation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "growth" => Ok(ResultCalculation::Growth),
+            "share" => Ok(ResultCalculation::Share),
+            _ => Err(format_err!("calculation {} not supported, use growth or share", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ResultCalculation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let calculation = match self {
+            ResultCalculation::Growth => "Growth",
+            ResultCalculation::Share => "Share",
+        };
+        write!(f, "{}", calculation)
+    }
+}
+
+
+/// Value of the measures in the rows `DataFrame::fill_periods` adds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeFill {
+    Zero,
+    Null,
+}
+
+impl FromStr for TimeFill {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(TimeFill::Zero),
+            "null" => Ok(TimeFill::Null),
+            _ => Err(format_err!("time_fill {} not supported, use zero or null", s)),
+        }
+    }
+}
+
+/// Unit which `DataFrame::convert_units` converts a measure to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Thousands,
+    Millions,
+    Billions,
+    /// For ratios, e.g. `0.25` becomes `25`.
+    Percent,
+}
+
+impl Unit {
+    pub fn factor(&self) -> f64 {
+        match self {
+            Unit::Thousands => 1e-3,
+            Unit::Millions => 1e-6,
+            Unit::Billions => 1e-9,
+            Unit::Percent => 100.0,
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "thousands" => Ok(Unit::Thousands),
+            "millions" => Ok(Unit::Millions),
+            "billions" => Ok(Unit::Billions),
+            "percent" => Ok(Unit::Percent),
+            _ => Err(format_err!("unit {} not supported, use thousands, millions, billions or percent", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let unit = match self {
+            Unit::Thousands => "thousands",
+            Unit::Millions => "millions",
+            Unit::Billions => "billions",
+            Unit::Percent => "percent",
+        };
+        write!(f, "{}", unit)
+    }
+}
+
+/// Conversion of a measure to a unit, in the format `Measure:unit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitConversion {
+    pub measure: String,
+    pub unit: Unit,
+}
+
+impl FromStr for UnitConversion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // measure names may have colons, units don't
+        let idx = s.rfind(':')
+            .ok_or_else(|| format_err!("conversion {} should be Measure:unit", s))?;
+
+        Ok(UnitConversion {
+            measure: s[..idx].to_owned(),
+            unit: s[idx + 1..].parse()?,
+        })
+    }
+}
+
+impl std::fmt::Display for UnitConversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.measure, self.unit)
+    }
+}
+
+// A row of `fill_periods` output: an existing row, or a period added to the group of a row.
+enum FillRow<'a> {
+    Existing(usize),
+    Added { group_row: usize, period: &'a str },
 }
 
 #[derive(Debug)]
@@ -87,6 +1169,7 @@ impl Column {
                 return Err(format_err!("Cannot sort Float64 column"));
             },
             ColumnData::Text(ref mut v) => v.sort(),
+            ColumnData::DictText(ref mut d) => d.sort(),
             ColumnData::NullableInt8(ref mut v) => v.sort(),
             ColumnData::NullableInt16(ref mut v) => v.sort(),
             ColumnData::NullableInt32(ref mut v) => v.sort(),
@@ -122,6 +1205,7 @@ impl Column {
             ColumnData::Float32(v) => v.iter().map(|&e| e.to_string()).collect(),
             ColumnData::Float64(v) => v.iter().map(|&e| e.to_string()).collect(),
             ColumnData::Text(v) => v.to_vec(),
+            ColumnData::DictText(d) => d.to_text(),
             ColumnData::NullableInt8(v) => {
                 v.iter().map(|&e| {
                     match e {
@@ -227,6 +1311,8 @@ pub enum ColumnData {
     Float32(Vec<f32>),
     Float64(Vec<f64>),
     Text(Vec<String>),
+    /// Text of repeated values, e.g. dimension labels, stored once each.
+    DictText(TextDict),
     NullableInt8(Vec<Option<i8>>),
     NullableInt16(Vec<Option<i16>>),
     NullableInt32(Vec<Option<i32>>),
@@ -240,6 +1326,377 @@ pub enum ColumnData {
     NullableText(Vec<Option<String>>),
 }
 
+impl ColumnData {
+    /// Builds a nullable column out of the rows at `idxs`; a `None` index is a null.
+    pub fn take_nullable(&self, idxs: &[Option<usize>]) -> ColumnData {
+        macro_rules! take {
+            ($v:expr, $variant:ident) => {
+                ColumnData::$variant(idxs.iter().map(|idx| idx.map(|i| $v[i].clone())).collect())
+            };
+        }
+        macro_rules! take_nullable {
+            ($v:expr, $variant:ident) => {
+                ColumnData::$variant(idxs.iter().map(|idx| idx.and_then(|i| $v[i].clone())).collect())
+            };
+        }
+
+        match self {
+            ColumnData::Int8(v) => take!(v, NullableInt8),
+            ColumnData::Int16(v) => take!(v, NullableInt16),
+            ColumnData::Int32(v) => take!(v, NullableInt32),
+            ColumnData::Int64(v) => take!(v, NullableInt64),
+            ColumnData::UInt8(v) => take!(v, NullableUInt8),
+            ColumnData::UInt16(v) => take!(v, NullableUInt16),
+            ColumnData::UInt32(v) => take!(v, NullableUInt32),
+            ColumnData::UInt64(v) => take!(v, NullableUInt64),
+            ColumnData::Float32(v) => take!(v, NullableFloat32),
+            ColumnData::Float64(v) => take!(v, NullableFloat64),
+            ColumnData::Text(v) => take!(v, NullableText),
+            ColumnData::DictText(d) => {
+                ColumnData::NullableText(idxs.iter().map(|idx| idx.map(|i| d.get(i).to_owned())).collect())
+            },
+            ColumnData::NullableInt8(v) => take_nullable!(v, NullableInt8),
+            ColumnData::NullableInt16(v) => take_nullable!(v, NullableInt16),
+            ColumnData::NullableInt32(v) => take_nullable!(v, NullableInt32),
+            ColumnData::NullableInt64(v) => take_nullable!(v, NullableInt64),
+            ColumnData::NullableUInt8(v) => take_nullable!(v, NullableUInt8),
+            ColumnData::NullableUInt16(v) => take_nullable!(v, NullableUInt16),
+            ColumnData::NullableUInt32(v) => take_nullable!(v, NullableUInt32),
+            ColumnData::NullableUInt64(v) => take_nullable!(v, NullableUInt64),
+            ColumnData::NullableFloat32(v) => take_nullable!(v, NullableFloat32),
+            ColumnData::NullableFloat64(v) => take_nullable!(v, NullableFloat64),
+            ColumnData::NullableText(v) => take_nullable!(v, NullableText),
+        }
+    }
+
+    /// Sums the rows of each group into a nullable column, with one row per group.
+    /// Nulls are skipped; a group of only nulls sums to null.
+    ///
+    /// Integers are summed as 64-bit integers, signed or not like the column, so the result
+    /// is an `Int64` or `UInt64` column; a sum which doesn't fit is an error.
+    pub fn sum_nullable(&self, groups: &[Vec<usize>]) -> Result<ColumnData, Error> {
+        macro_rules! sum {
+            ($variant:ident, $get:expr) => {
+                ColumnData::$variant(groups.iter()
+                    .map(|group| {
+                        group.iter()
+                            .filter_map(|i| $get(*i))
+                            .fold(None, |acc, x| Some(acc.map_or(x, |acc| acc + x)))
+                    })
+                    .collect()
+                )
+            };
+        }
+        macro_rules! sum_int {
+            ($variant:ident, $wide:ty, $get:expr) => {
+                ColumnData::$variant(groups.iter()
+                    .map(|group| {
+                        group.iter()
+                            .filter_map(|i| $get(*i))
+                            .try_fold(None, |acc: Option<$wide>, x| {
+                                let x = x as $wide;
+                                match acc {
+                                    Some(acc) => acc.checked_add(x).map(Some),
+                                    None => Some(Some(x)),
+                                }
+                            })
+                            .ok_or_else(|| format_err!("Sum is too large for a 64-bit integer"))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?
+                )
+            };
+        }
+
+        let res = match self {
+            ColumnData::Int8(v) => sum_int!(NullableInt64, i64, |i: usize| Some(v[i])),
+            ColumnData::Int16(v) => sum_int!(NullableInt64, i64, |i: usize| Some(v[i])),
+            ColumnData::Int32(v) => sum_int!(NullableInt64, i64, |i: usize| Some(v[i])),
+            ColumnData::Int64(v) => sum_int!(NullableInt64, i64, |i: usize| Some(v[i])),
+            ColumnData::UInt8(v) => sum_int!(NullableUInt64, u64, |i: usize| Some(v[i])),
+            ColumnData::UInt16(v) => sum_int!(NullableUInt64, u64, |i: usize| Some(v[i])),
+            ColumnData::UInt32(v) => sum_int!(NullableUInt64, u64, |i: usize| Some(v[i])),
+            ColumnData::UInt64(v) => sum_int!(NullableUInt64, u64, |i: usize| Some(v[i])),
+            ColumnData::Float32(v) => sum!(NullableFloat32, |i: usize| Some(v[i])),
+            ColumnData::Float64(v) => sum!(NullableFloat64, |i: usize| Some(v[i])),
+            ColumnData::NullableInt8(v) => sum_int!(NullableInt64, i64, |i: usize| v[i]),
+            ColumnData::NullableInt16(v) => sum_int!(NullableInt64, i64, |i: usize| v[i]),
+            ColumnData::NullableInt32(v) => sum_int!(NullableInt64, i64, |i: usize| v[i]),
+            ColumnData::NullableInt64(v) => sum_int!(NullableInt64, i64, |i: usize| v[i]),
+            ColumnData::NullableUInt8(v) => sum_int!(NullableUInt64, u64, |i: usize| v[i]),
+            ColumnData::NullableUInt16(v) => sum_int!(NullableUInt64, u64, |i: usize| v[i]),
+            ColumnData::NullableUInt32(v) => sum_int!(NullableUInt64, u64, |i: usize| v[i]),
+            ColumnData::NullableUInt64(v) => sum_int!(NullableUInt64, u64, |i: usize| v[i]),
+            ColumnData::NullableFloat32(v) => sum!(NullableFloat32, |i: usize| v[i]),
+            ColumnData::NullableFloat64(v) => sum!(NullableFloat64, |i: usize| v[i]),
+            ColumnData::Text(_) | ColumnData::DictText(_) | ColumnData::NullableText(_) => {
+                return Err(format_err!("Cannot sum a text column"));
+            },
+        };
+
+        Ok(res)
+    }
+
+    /// Multiplies each value by `factor`, e.g. to estimate totals from a sample. The result is
+    /// a float column, nullable if this column is.
+    pub fn scale(&self, factor: f64) -> Result<ColumnData, Error> {
+        macro_rules! scale {
+            ($v:expr) => {
+                ColumnData::Float64($v.iter().map(|x| *x as f64 * factor).collect())
+            };
+        }
+        macro_rules! scale_nullable {
+            ($v:expr) => {
+                ColumnData::NullableFloat64($v.iter().map(|x| x.map(|x| x as f64 * factor)).collect())
+            };
+        }
+
+        let res = match self {
+            ColumnData::Int8(v) => scale!(v),
+            ColumnData::Int16(v) => scale!(v),
+            ColumnData::Int32(v) => scale!(v),
+            ColumnData::Int64(v) => scale!(v),
+            ColumnData::UInt8(v) => scale!(v),
+            ColumnData::UInt16(v) => scale!(v),
+            ColumnData::UInt32(v) => scale!(v),
+            ColumnData::UInt64(v) => scale!(v),
+            ColumnData::Float32(v) => scale!(v),
+            ColumnData::Float64(v) => scale!(v),
+            ColumnData::NullableInt8(v) => scale_nullable!(v),
+            ColumnData::NullableInt16(v) => scale_nullable!(v),
+            ColumnData::NullableInt32(v) => scale_nullable!(v),
+            ColumnData::NullableInt64(v) => scale_nullable!(v),
+            ColumnData::NullableUInt8(v) => scale_nullable!(v),
+            ColumnData::NullableUInt16(v) => scale_nullable!(v),
+            ColumnData::NullableUInt32(v) => scale_nullable!(v),
+            ColumnData::NullableUInt64(v) => scale_nullable!(v),
+            ColumnData::NullableFloat32(v) => scale_nullable!(v),
+            ColumnData::NullableFloat64(v) => scale_nullable!(v),
+            ColumnData::Text(_) | ColumnData::DictText(_) | ColumnData::NullableText(_) => {
+                return Err(format_err!("Cannot scale a text column"));
+            },
+        };
+
+        Ok(res)
+    }
+
+    /// Appends the rows of `other`; both must be the same nullable type.
+    pub fn append_nullable(&mut self, other: ColumnData) -> Result<(), Error> {
+        match (self, other) {
+            (ColumnData::NullableInt8(v), ColumnData::NullableInt8(other)) => v.extend(other),
+            (ColumnData::NullableInt16(v), ColumnData::NullableInt16(other)) => v.extend(other),
+            (ColumnData::NullableInt32(v), ColumnData::NullableInt32(other)) => v.extend(other),
+            (ColumnData::NullableInt64(v), ColumnData::NullableInt64(other)) => v.extend(other),
+            (ColumnData::NullableUInt8(v), ColumnData::NullableUInt8(other)) => v.extend(other),
+            (ColumnData::NullableUInt16(v), ColumnData::NullableUInt16(other)) => v.extend(other),
+            (ColumnData::NullableUInt32(v), ColumnData::NullableUInt32(other)) => v.extend(other),
+            (ColumnData::NullableUInt64(v), ColumnData::NullableUInt64(other)) => v.extend(other),
+            (ColumnData::NullableFloat32(v), ColumnData::NullableFloat32(other)) => v.extend(other),
+            (ColumnData::NullableFloat64(v), ColumnData::NullableFloat64(other)) => v.extend(other),
+            (ColumnData::NullableText(v), ColumnData::NullableText(other)) => v.extend(other),
+            _ => return Err(format_err!("Cannot append columns of different types")),
+        }
+
+        Ok(())
+    }
+
+    /// Appends the rows of `other`. Number columns of different types, e.g. a chunk which
+    /// widened while being read, are combined with the checked pushes, which widen this
+    /// column if needed.
+    pub fn append(&mut self, other: ColumnData) -> Result<(), Error> {
+        if !is_same_columndata_type(self, &other) {
+            return match other {
+                ColumnData::Int8(v) => v.into_iter().try_for_each(|x| self.push_i64(x as i64)),
+                ColumnData::Int16(v) => v.into_iter().try_for_each(|x| self.push_i64(x as i64)),
+                ColumnData::Int32(v) => v.into_iter().try_for_each(|x| self.push_i64(x as i64)),
+                ColumnData::Int64(v) => v.into_iter().try_for_each(|x| self.push_i64(x)),
+                ColumnData::UInt8(v) => v.into_iter().try_for_each(|x| self.push_u64(x as u64)),
+                ColumnData::UInt16(v) => v.into_iter().try_for_each(|x| self.push_u64(x as u64)),
+                ColumnData::UInt32(v) => v.into_iter().try_for_each(|x| self.push_u64(x as u64)),
+                ColumnData::UInt64(v) => v.into_iter().try_for_each(|x| self.push_u64(x)),
+                ColumnData::Float32(v) => v.into_iter().try_for_each(|x| self.push_f64(x as f64)),
+                ColumnData::Float64(v) => v.into_iter().try_for_each(|x| self.push_f64(x)),
+                _ => Err(format_err!("Cannot append columns of different types")),
+            };
+        }
+
+        match (self, other) {
+            (ColumnData::Int8(v), ColumnData::Int8(other)) => v.extend(other),
+            (ColumnData::Int16(v), ColumnData::Int16(other)) => v.extend(other),
+            (ColumnData::Int32(v), ColumnData::Int32(other)) => v.extend(other),
+            (ColumnData::Int64(v), ColumnData::Int64(other)) => v.extend(other),
+            (ColumnData::UInt8(v), ColumnData::UInt8(other)) => v.extend(other),
+            (ColumnData::UInt16(v), ColumnData::UInt16(other)) => v.extend(other),
+            (ColumnData::UInt32(v), ColumnData::UInt32(other)) => v.extend(other),
+            (ColumnData::UInt64(v), ColumnData::UInt64(other)) => v.extend(other),
+            (ColumnData::Float32(v), ColumnData::Float32(other)) => v.extend(other),
+            (ColumnData::Float64(v), ColumnData::Float64(other)) => v.extend(other),
+            (ColumnData::Text(v), ColumnData::Text(other)) => v.extend(other),
+            (ColumnData::DictText(d), ColumnData::DictText(other)) => d.append(other),
+            (v, other) => v.append_nullable(other)?,
+        }
+
+        Ok(())
+    }
+
+    /// An empty column of the same type, with capacity for `capacity` rows.
+    pub fn empty_with_capacity(&self, capacity: usize) -> ColumnData {
+        match self {
+            ColumnData::Int8(_) => ColumnData::Int8(Vec::with_capacity(capacity)),
+            ColumnData::Int16(_) => ColumnData::Int16(Vec::with_capacity(capacity)),
+            ColumnData::Int32(_) => ColumnData::Int32(Vec::with_capacity(capacity)),
+            ColumnData::Int64(_) => ColumnData::Int64(Vec::with_capacity(capacity)),
+            ColumnData::UInt8(_) => ColumnData::UInt8(Vec::with_capacity(capacity)),
+            ColumnData::UInt16(_) => ColumnData::UInt16(Vec::with_capacity(capacity)),
+            ColumnData::UInt32(_) => ColumnData::UInt32(Vec::with_capacity(capacity)),
+            ColumnData::UInt64(_) => ColumnData::UInt64(Vec::with_capacity(capacity)),
+            ColumnData::Float32(_) => ColumnData::Float32(Vec::with_capacity(capacity)),
+            ColumnData::Float64(_) => ColumnData::Float64(Vec::with_capacity(capacity)),
+            ColumnData::Text(_) => ColumnData::Text(Vec::with_capacity(capacity)),
+            ColumnData::DictText(_) => ColumnData::DictText(TextDict {
+                values: vec![],
+                codes: Vec::with_capacity(capacity),
+            }),
+            ColumnData::NullableInt8(_) => ColumnData::NullableInt8(Vec::with_capacity(capacity)),
+            ColumnData::NullableInt16(_) => ColumnData::NullableInt16(Vec::with_capacity(capacity)),
+            ColumnData::NullableInt32(_) => ColumnData::NullableInt32(Vec::with_capacity(capacity)),
+            ColumnData::NullableInt64(_) => ColumnData::NullableInt64(Vec::with_capacity(capacity)),
+            ColumnData::NullableUInt8(_) => ColumnData::NullableUInt8(Vec::with_capacity(capacity)),
+            ColumnData::NullableUInt16(_) => ColumnData::NullableUInt16(Vec::with_capacity(capacity)),
+            ColumnData::NullableUInt32(_) => ColumnData::NullableUInt32(Vec::with_capacity(capacity)),
+            ColumnData::NullableUInt64(_) => ColumnData::NullableUInt64(Vec::with_capacity(capacity)),
+            ColumnData::NullableFloat32(_) => ColumnData::NullableFloat32(Vec::with_capacity(capacity)),
+            ColumnData::NullableFloat64(_) => ColumnData::NullableFloat64(Vec::with_capacity(capacity)),
+            ColumnData::NullableText(_) => ColumnData::NullableText(Vec::with_capacity(capacity)),
+        }
+    }
+
+    /// Reserves capacity for at least `additional` more rows.
+    pub fn reserve(&mut self, additional: usize) {
+        match self {
+            ColumnData::Int8(v) => v.reserve(additional),
+            ColumnData::Int16(v) => v.reserve(additional),
+            ColumnData::Int32(v) => v.reserve(additional),
+            ColumnData::Int64(v) => v.reserve(additional),
+            ColumnData::UInt8(v) => v.reserve(additional),
+            ColumnData::UInt16(v) => v.reserve(additional),
+            ColumnData::UInt32(v) => v.reserve(additional),
+            ColumnData::UInt64(v) => v.reserve(additional),
+            ColumnData::Float32(v) => v.reserve(additional),
+            ColumnData::Float64(v) => v.reserve(additional),
+            ColumnData::Text(v) => v.reserve(additional),
+            ColumnData::DictText(d) => d.codes.reserve(additional),
+            ColumnData::NullableInt8(v) => v.reserve(additional),
+            ColumnData::NullableInt16(v) => v.reserve(additional),
+            ColumnData::NullableInt32(v) => v.reserve(additional),
+            ColumnData::NullableInt64(v) => v.reserve(additional),
+            ColumnData::NullableUInt8(v) => v.reserve(additional),
+            ColumnData::NullableUInt16(v) => v.reserve(additional),
+            ColumnData::NullableUInt32(v) => v.reserve(additional),
+            ColumnData::NullableUInt64(v) => v.reserve(additional),
+            ColumnData::NullableFloat32(v) => v.reserve(additional),
+            ColumnData::NullableFloat64(v) => v.reserve(additional),
+            ColumnData::NullableText(v) => v.reserve(additional),
+        }
+    }
+
+    /// Sets the value at `idx` of a nullable column, parsing it from a string.
+    pub fn set_nullable_str(&mut self, idx: usize, value: &str) -> Result<(), Error> {
+        macro_rules! set {
+            ($v:expr) => {
+                $v[idx] = Some(value.parse()
+                    .map_err(|_| format_err!("Could not parse {} for column type", value))?)
+            };
+        }
+
+        match self {
+            ColumnData::NullableInt8(v) => set!(v),
+            ColumnData::NullableInt16(v) => set!(v),
+            ColumnData::NullableInt32(v) => set!(v),
+            ColumnData::NullableInt64(v) => set!(v),
+            ColumnData::NullableUInt8(v) => set!(v),
+            ColumnData::NullableUInt16(v) => set!(v),
+            ColumnData::NullableUInt32(v) => set!(v),
+            ColumnData::NullableUInt64(v) => set!(v),
+            ColumnData::NullableFloat32(v) => set!(v),
+            ColumnData::NullableFloat64(v) => set!(v),
+            ColumnData::NullableText(v) => v[idx] = Some(value.to_owned()),
+            _ => return Err(format_err!("Cannot set a value in a non-nullable column")),
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the nulls of a nullable column with `value`. Columns which aren't nullable
+    /// have no nulls, and are left as is.
+    pub fn fill_nulls(&mut self, value: &str) -> Result<(), Error> {
+        macro_rules! fill {
+            ($v:expr) => {{
+                let value = value.parse()
+                    .map_err(|_| format_err!("Could not parse {} for column type", value))?;
+                $v.iter_mut().filter(|x| x.is_none()).for_each(|x| *x = Some(value));
+            }};
+        }
+
+        match self {
+            ColumnData::NullableInt8(v) => fill!(v),
+            ColumnData::NullableInt16(v) => fill!(v),
+            ColumnData::NullableInt32(v) => fill!(v),
+            ColumnData::NullableInt64(v) => fill!(v),
+            ColumnData::NullableUInt8(v) => fill!(v),
+            ColumnData::NullableUInt16(v) => fill!(v),
+            ColumnData::NullableUInt32(v) => fill!(v),
+            ColumnData::NullableUInt64(v) => fill!(v),
+            ColumnData::NullableFloat32(v) => fill!(v),
+            ColumnData::NullableFloat64(v) => fill!(v),
+            ColumnData::NullableText(v) => {
+                v.iter_mut().filter(|x| x.is_none()).for_each(|x| *x = Some(value.to_owned()));
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Keeps only the first `len` rows.
+    pub fn truncate(&mut self, len: usize) {
+        match self {
+            ColumnData::Int8(v) => v.truncate(len),
+            ColumnData::Int16(v) => v.truncate(len),
+            ColumnData::Int32(v) => v.truncate(len),
+            ColumnData::Int64(v) => v.truncate(len),
+            ColumnData::UInt8(v) => v.truncate(len),
+            ColumnData::UInt16(v) => v.truncate(len),
+            ColumnData::UInt32(v) => v.truncate(len),
+            ColumnData::UInt64(v) => v.truncate(len),
+            ColumnData::Float32(v) => v.truncate(len),
+            ColumnData::Float64(v) => v.truncate(len),
+            ColumnData::Text(v) => v.truncate(len),
+            ColumnData::DictText(d) => d.truncate(len),
+            ColumnData::NullableInt8(v) => v.truncate(len),
+            ColumnData::NullableInt16(v) => v.truncate(len),
+            ColumnData::NullableInt32(v) => v.truncate(len),
+            ColumnData::NullableInt64(v) => v.truncate(len),
+            ColumnData::NullableUInt8(v) => v.truncate(len),
+            ColumnData::NullableUInt16(v) => v.truncate(len),
+            ColumnData::NullableUInt32(v) => v.truncate(len),
+            ColumnData::NullableUInt64(v) => v.truncate(len),
+            ColumnData::NullableFloat32(v) => v.truncate(len),
+            ColumnData::NullableFloat64(v) => v.truncate(len),
+            ColumnData::NullableText(v) => v.truncate(len),
+        }
+    }
+}
+
+
+/// A numeric key zero-padded to `width`; other values, e.g. empty null keys, are kept as is.
+pub fn pad_key(key: String, width: usize) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_digit()) {
+        format!("{:0>width$}", key, width = width)
+    } else {
+        key
+    }
+}
+
 
 pub fn is_same_columndata_type(col_1: &ColumnData, col_2: &ColumnData) -> bool {
     match col_1 {
@@ -309,6 +1766,12 @@ pub fn is_same_columndata_type(col_1: &ColumnData, col_2: &ColumnData) -> bool {
                 _ => false
             }
         },
+        ColumnData::DictText(_) => {
+            match col_2 {
+                ColumnData::DictText(_) => true,
+                _ => false
+            }
+        },
         ColumnData::NullableInt8(_) => {
             match col_2 {
                 ColumnData::NullableInt8(_) => true,
@@ -377,3 +1840,385 @@ pub fn is_same_columndata_type(col_1: &ColumnData, col_2: &ColumnData) -> bool {
         },
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pivot() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2018, 2019])),
+            Column::new("state_id".into(), ColumnData::Text(vec!["01".into(), "02".into(), "01".into()])),
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AK".into(), "AL".into()])),
+            Column::new("m0".into(), ColumnData::Int64(vec![1, 2, 3])),
+        ]);
+        let headers: Vec<String> = vec!["Year".into(), "State ID".into(), "State".into(), "Quantity".into()];
+
+        let (headers, pivoted) = df.pivot(&headers, 2, &[1], &[3]).unwrap();
+
+        assert_eq!(headers, vec!["Year".to_owned(), "AL Quantity".to_owned(), "AK Quantity".to_owned()]);
+        assert_eq!(pivoted.columns[0].column_data, ColumnData::NullableInt32(vec![Some(2018), Some(2019)]));
+        assert_eq!(pivoted.columns[1].column_data, ColumnData::NullableInt64(vec![Some(1), Some(3)]));
+        assert_eq!(pivoted.columns[2].column_data, ColumnData::NullableInt64(vec![Some(2), None]));
+    }
+
+    #[test]
+    fn test_with_totals() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2018, 2019])),
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AK".into(), "AL".into()])),
+            Column::new("m0".into(), ColumnData::NullableInt64(vec![Some(1), Some(2), None])),
+        ]);
+
+        let totals = df.with_totals(&[vec![0], vec![1]], &[2]).unwrap();
+
+        assert_eq!(totals.columns[0].column_data, ColumnData::NullableInt32(
+            vec![Some(2018), Some(2018), Some(2019), Some(2018), Some(2019), None]
+        ));
+        assert_eq!(totals.columns[1].column_data, ColumnData::NullableText(
+            vec![Some("AL".into()), Some("AK".into()), Some("AL".into()), None, None, None]
+        ));
+        assert_eq!(totals.columns[2].column_data, ColumnData::NullableInt64(
+            vec![Some(1), Some(2), None, Some(3), None, Some(3)]
+        ));
+
+        // integer measures are widened, so their totals don't overflow
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("m0".into(), ColumnData::Int8(vec![100, 100])),
+            Column::new("m1".into(), ColumnData::UInt32(vec![u32::MAX, 1])),
+        ]);
+
+        let totals = df.with_totals(&[vec![0]], &[1, 2]).unwrap();
+        assert_eq!(totals.columns[1].column_data, ColumnData::NullableInt64(vec![Some(100), Some(100), Some(200)]));
+        assert_eq!(totals.columns[2].column_data, ColumnData::NullableUInt64(
+            vec![Some(u32::MAX as u64), Some(1), Some(u32::MAX as u64 + 1)]
+        ));
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("m0".into(), ColumnData::Int64(vec![i64::MAX, 1])),
+        ]);
+        assert!(df.with_totals(&[vec![0]], &[1]).is_err());
+    }
+
+    #[test]
+    fn test_fill_periods() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AL".into(), "AK".into()])),
+            Column::new("year".into(), ColumnData::Int32(vec![2016, 2019, 2017])),
+            Column::new("m0".into(), ColumnData::Float64(vec![1.0, 2.0, 3.0])),
+        ]);
+        let periods: Vec<String> = vec!["2016".into(), "2017".into(), "2018".into(), "2019".into()];
+
+        let filled = df.fill_periods(&[1], &[0], &[2], &periods, TimeFill::Zero).unwrap();
+
+        assert_eq!(filled.len(), 8);
+        assert_eq!(filled.columns[0].column_data, ColumnData::NullableText(
+            vec!["AL", "AL", "AL", "AL", "AK", "AK", "AK", "AK"].into_iter().map(|s| Some(s.to_owned())).collect()
+        ));
+        assert_eq!(filled.columns[1].column_data, ColumnData::NullableInt32(
+            vec![Some(2016), Some(2017), Some(2018), Some(2019), Some(2016), Some(2017), Some(2018), Some(2019)]
+        ));
+        assert_eq!(filled.columns[2].column_data, ColumnData::NullableFloat64(
+            vec![Some(1.0), Some(0.0), Some(0.0), Some(2.0), Some(0.0), Some(3.0), Some(0.0), Some(0.0)]
+        ));
+
+        let filled = df.fill_periods(&[1], &[0], &[2], &periods[1..3], TimeFill::Null).unwrap();
+
+        // periods outside of the fill range are kept, at the end of their group
+        assert_eq!(filled.columns[1].column_data, ColumnData::NullableInt32(
+            vec![Some(2017), Some(2018), Some(2016), Some(2019), Some(2017), Some(2018)]
+        ));
+        assert_eq!(filled.columns[2].column_data, ColumnData::NullableFloat64(
+            vec![None, None, Some(1.0), Some(2.0), Some(3.0), None]
+        ));
+
+        // a property which changes over time doesn't split the group
+        let mut df = df;
+        df.columns.push(Column::new("region".into(), ColumnData::Text(vec!["South".into(), "Southeast".into(), "West".into()])));
+        let filled = df.fill_periods(&[1], &[0], &[2], &periods, TimeFill::Zero).unwrap();
+        assert_eq!(filled.len(), 8);
+        assert_eq!(filled.columns[3].column_data, ColumnData::NullableText(
+            vec!["South", "South", "South", "Southeast", "West", "West", "West", "West"].into_iter().map(|s| Some(s.to_owned())).collect()
+        ));
+    }
+
+    #[test]
+    fn test_with_growth() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2019, 2018, 2018, 2020, 2019])),
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AL".into(), "AK".into(), "AL".into(), "AK".into()])),
+            Column::new("m0".into(), ColumnData::Float64(vec![15.0, 10.0, 4.0, 0.0, 0.0])),
+            Column::new("m1".into(), ColumnData::Int64(vec![1, 2, 3, 4, 5])),
+        ]);
+        let mut growth = GrowthColumns { drill_count: 2, time_idxs: vec![0], mea_idx: 2, mea_count: 2, sort: vec![], limit: None };
+
+        let sorted_df = df.with_growth(&GrowthColumns { sort: vec![(4, SortDirection::Desc)], ..growth.clone() }).unwrap();
+        assert_eq!(sorted_df.columns[4].column_data, ColumnData::NullableFloat64(
+            vec![Some(0.5), Some(-1.0), Some(-1.0), None, None]
+        ));
+        assert_eq!(sorted_df.columns[0].column_data, ColumnData::NullableText(
+            vec![Some("AL".into()), Some("AL".into()), Some("AK".into()), Some("AL".into()), Some("AK".into())]
+        ));
+
+        // limited after the growth is calculated
+        let limit = Some(LimitSql { offset: Some(1), n: 2 });
+        let limited_df = df.with_growth(&GrowthColumns { sort: vec![(4, SortDirection::Desc)], limit, ..growth.clone() }).unwrap();
+        assert_eq!(limited_df.columns[4].column_data, ColumnData::NullableFloat64(
+            vec![Some(-1.0), Some(-1.0)]
+        ));
+
+        // sorted on the measure, which moves after the other measures
+        growth.sort = vec![(growth.result_idx(2), SortDirection::Asc)];
+        assert_eq!(growth.sort, vec![(3, SortDirection::Asc)]);
+        assert_eq!(growth.result_idx(0), 1);
+        assert_eq!(growth.result_idx(1), 0);
+        assert_eq!(growth.result_idx(3), 2);
+        let sorted_df = df.with_growth(&growth).unwrap();
+        assert_eq!(sorted_df.columns[3].column_data, ColumnData::NullableFloat64(
+            vec![Some(0.0), Some(0.0), Some(4.0), Some(10.0), Some(15.0)]
+        ));
+
+        growth.sort = vec![];
+        let df = df.with_growth(&growth).unwrap();
+
+        let names: Vec<_> = df.columns.iter().map(|col| col.name.as_str()).collect();
+        assert_eq!(names, vec!["state", "year", "m1", "m0", "growth", "growth_value"]);
+        assert_eq!(df.columns[1].column_data, ColumnData::NullableInt32(
+            vec![Some(2018), Some(2019), Some(2020), Some(2018), Some(2019)]
+        ));
+        assert_eq!(df.columns[4].column_data, ColumnData::NullableFloat64(
+            vec![None, Some(0.5), Some(-1.0), None, Some(-1.0)]
+        ));
+        assert_eq!(df.columns[5].column_data, ColumnData::NullableFloat64(
+            vec![None, Some(5.0), Some(-15.0), None, Some(-4.0)]
+        ));
+    }
+
+    #[test]
+    fn test_add_calculations() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2019, 2018, 2018, 2019])),
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AL".into(), "AK".into(), "AK".into()])),
+            Column::new("m0".into(), ColumnData::Float64(vec![15.0, 10.0, 30.0, 0.0])),
+        ]);
+        let mut headers = vec!["Year".to_owned(), "State".to_owned(), "Population".to_owned()];
+        let columns = CalculationColumns { drill_count: 2, time_idxs: vec![0], mea_idxs: vec![2] };
+
+        df.add_calculations(&mut headers, &columns, &[ResultCalculation::Growth, ResultCalculation::Share]).unwrap();
+
+        assert_eq!(headers, vec!["Year", "State", "Population", "Population Growth", "Population Share"]);
+        assert_eq!(df.columns[3].column_data, ColumnData::NullableFloat64(
+            vec![Some(0.5), None, None, Some(-1.0)]
+        ));
+        assert_eq!(df.columns[4].column_data, ColumnData::NullableFloat64(
+            vec![Some(1.0), Some(0.25), Some(0.75), Some(0.0)]
+        ));
+
+        let columns = CalculationColumns { drill_count: 2, time_idxs: vec![], mea_idxs: vec![2] };
+        assert!(df.add_calculations(&mut headers, &columns, &[ResultCalculation::Growth]).is_err());
+    }
+
+    #[test]
+    fn test_add_share() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2018, 2019, 2019])),
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AK".into(), "AL".into(), "AK".into()])),
+            Column::new("m0".into(), ColumnData::NullableInt64(vec![Some(30), Some(10), None, Some(0)])),
+        ]);
+        let share = ShareColumns { group_idxs: vec![0], mea_idx: 2 };
+
+        df.add_share(&share).unwrap();
+
+        assert_eq!(df.columns[3].name, "share");
+        assert_eq!(df.columns[3].column_data, ColumnData::NullableFloat64(
+            vec![Some(0.75), Some(0.25), None, None]
+        ));
+    }
+
+    #[test]
+    fn test_add_previous_year() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("month".into(), ColumnData::Int32(vec![201901, 201902, 202001, 202002, 202001])),
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AL".into(), "AL".into(), "AL".into(), "AK".into()])),
+            Column::new("m0".into(), ColumnData::Float64(vec![10.0, 0.0, 15.0, 5.0, 3.0])),
+        ]);
+        let columns = PreviousYearColumns {
+            group_idxs: vec![1],
+            time_idx: 0,
+            period_level: PeriodLevel::Month,
+            mea_idx: 2,
+            period_cuts: vec![PeriodCut { level: PeriodLevel::Year, periods: vec![2020] }],
+        };
+
+        df.add_previous_year(&columns).unwrap();
+
+        let names: Vec<_> = df.columns.iter().map(|col| col.name.as_str()).collect();
+        assert_eq!(names, vec!["month", "state", "m0", "previous_year", "previous_year_difference", "previous_year_growth"]);
+        assert_eq!(df.columns[0].column_data, ColumnData::NullableInt32(vec![Some(202001), Some(202002), Some(202001)]));
+        assert_eq!(df.columns[3].column_data, ColumnData::NullableFloat64(vec![Some(10.0), Some(0.0), None]));
+        assert_eq!(df.columns[4].column_data, ColumnData::NullableFloat64(vec![Some(5.0), Some(5.0), None]));
+        assert_eq!(df.columns[5].column_data, ColumnData::NullableFloat64(vec![Some(0.5), None, None]));
+    }
+
+    #[test]
+    fn test_containing_period() {
+        assert_eq!(PeriodLevel::Month.containing_period(202011, PeriodLevel::Quarter), Some(20204));
+        assert_eq!(PeriodLevel::Month.containing_period(202001, PeriodLevel::Year), Some(2020));
+        assert_eq!(PeriodLevel::Quarter.containing_period(20203, PeriodLevel::Year), Some(2020));
+        assert_eq!(PeriodLevel::Quarter.containing_period(20203, PeriodLevel::Quarter), Some(20203));
+        assert_eq!(PeriodLevel::Quarter.containing_period(20203, PeriodLevel::Month), None);
+
+        assert!(PeriodLevel::Month.is_key(201912));
+        assert!(!PeriodLevel::Month.is_key(12));
+        assert!(!PeriodLevel::Month.is_key(201913));
+        assert!(PeriodLevel::Quarter.is_key(20194));
+        assert!(!PeriodLevel::Quarter.is_key(201904));
+        assert!(!PeriodLevel::Quarter.is_key(4));
+    }
+
+    #[test]
+    fn test_contained_periods() {
+        assert_eq!(PeriodLevel::Month.contained_periods(20204, PeriodLevel::Quarter), Some((202010, 202012)));
+        assert_eq!(PeriodLevel::Month.contained_periods(2020, PeriodLevel::Year), Some((202001, 202012)));
+        assert_eq!(PeriodLevel::Quarter.contained_periods(2020, PeriodLevel::Year), Some((20201, 20204)));
+        assert_eq!(PeriodLevel::Quarter.contained_periods(20203, PeriodLevel::Quarter), Some((20203, 20203)));
+        assert_eq!(PeriodLevel::Quarter.contained_periods(202003, PeriodLevel::Month), None);
+    }
+
+    #[test]
+    fn test_project() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year_id".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("year".into(), ColumnData::Text(vec!["2018".into(), "2019".into()])),
+            Column::new("m0".into(), ColumnData::Int64(vec![1, 2])),
+        ]);
+        let mut headers = vec!["Year ID".to_owned(), "Year".to_owned(), "Quantity".to_owned()];
+
+        df.project(&mut headers, &["Quantity".to_owned(), "Year".to_owned()]).unwrap();
+
+        assert_eq!(headers, vec!["Quantity", "Year"]);
+        assert_eq!(df.columns[0].column_data, ColumnData::Int64(vec![1, 2]));
+        assert_eq!(df.columns[1].name, "year");
+
+        assert!(df.project(&mut headers, &["Year ID".to_owned()]).is_err());
+
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year_id".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("year".into(), ColumnData::Text(vec!["2018".into(), "2019".into()])),
+            Column::new("state_id".into(), ColumnData::Int32(vec![1, 2])),
+            Column::new("m0".into(), ColumnData::Int64(vec![1, 2])),
+        ]);
+        let mut headers = vec!["Year ID".to_owned(), "Year".to_owned(), "State ID".to_owned(), "Quantity".to_owned()];
+
+        df.drop_id_columns(&mut headers).unwrap();
+
+        assert_eq!(headers, vec!["Year", "State ID", "Quantity"]);
+        assert_eq!(df.columns.len(), 3);
+    }
+
+    #[test]
+    fn test_fill_null_zeros() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("m0".into(), ColumnData::NullableInt64(vec![Some(3), None])),
+            Column::new("m1".into(), ColumnData::NullableFloat64(vec![None, Some(1.5)])),
+        ]);
+
+        df.fill_null_zeros(&[1]).unwrap();
+
+        assert_eq!(df.columns[1].column_data, ColumnData::NullableInt64(vec![Some(3), Some(0)]));
+        assert_eq!(df.columns[2].column_data, ColumnData::NullableFloat64(vec![None, Some(1.5)]));
+        assert!(df.fill_null_zeros(&[3]).is_err());
+    }
+
+    #[test]
+    fn test_pad_keys() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("County ID".into(), ColumnData::NullableUInt32(vec![Some(1001), None, Some(6037)])),
+            Column::new("m0".into(), ColumnData::Int64(vec![1, 2, 3])),
+        ]);
+
+        df.pad_keys(&[(0, 5)]).unwrap();
+
+        assert_eq!(
+            df.columns[0].column_data,
+            ColumnData::Text(vec!["01001".into(), "".into(), "06037".into()]),
+        );
+        assert!(df.pad_keys(&[(2, 5)]).is_err());
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(
+            ColumnData::Int32(vec![1, 2]).scale(100.0).unwrap(),
+            ColumnData::Float64(vec![100.0, 200.0])
+        );
+        assert_eq!(
+            ColumnData::NullableInt64(vec![Some(3), None]).scale(10.0).unwrap(),
+            ColumnData::NullableFloat64(vec![Some(30.0), None])
+        );
+        assert!(ColumnData::Text(vec![]).scale(10.0).is_err());
+    }
+
+    #[test]
+    fn test_convert_units() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2019])),
+            Column::new("m0".into(), ColumnData::Int64(vec![1500, 2000])),
+            Column::new("m1".into(), ColumnData::NullableFloat64(vec![Some(0.25), None])),
+        ]);
+        let headers: Vec<String> = vec!["Year".into(), "Exports".into(), "Share".into()];
+        let conversions: Vec<UnitConversion> = vec![
+            "Exports:thousands".parse().unwrap(),
+            "Share:percent".parse().unwrap(),
+        ];
+
+        df.convert_units(&headers, &conversions).unwrap();
+        assert_eq!(df.columns[0].column_data, ColumnData::Int32(vec![2018, 2019]));
+        assert_eq!(df.columns[1].column_data, ColumnData::Float64(vec![1.5, 2.0]));
+        assert_eq!(df.columns[2].column_data, ColumnData::NullableFloat64(vec![Some(25.0), None]));
+
+        assert_eq!(conversions[0].to_string(), "Exports:thousands");
+        assert!("Exports:dozens".parse::<UnitConversion>().is_err());
+        assert!("Exports".parse::<UnitConversion>().is_err());
+        assert!(df.convert_units(&headers, &["Imports:millions".parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018, 2018, 2019])),
+            Column::new("m0".into(), ColumnData::NullableInt64(vec![Some(1), Some(2), None])),
+        ]);
+
+        df.truncate(2);
+        assert_eq!(df.len(), 2);
+        assert_eq!(df.columns[1].column_data, ColumnData::NullableInt64(vec![Some(1), Some(2)]));
+
+        df.truncate(5);
+        assert_eq!(df.len(), 2);
+    }
+
+    #[test]
+    fn test_take_preview_total() {
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2010, 2019])),
+            Column::new("preview_row".into(), ColumnData::Int64(vec![1, 10])),
+            Column::new("preview_total".into(), ColumnData::Int64(vec![10, 10])),
+        ]);
+
+        assert_eq!(df.take_preview_total().unwrap(), 10);
+        assert_eq!(df.columns.len(), 1);
+
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![])),
+            Column::new("preview_row".into(), ColumnData::Int64(vec![])),
+            Column::new("preview_total".into(), ColumnData::Int64(vec![])),
+        ]);
+        assert_eq!(df.take_preview_total().unwrap(), 0);
+    }
+}
@@ -0,0 +1,541 @@
+//! Sql dialects.
+//!
+//! A dialect generates the sql for a query from its `QueryIr`. Each backend has a built in
+//! dialect (see `Backend::dialect`), but dialects are looked up by name in a registry first,
+//! so crates outside of tesseract-core can add dialects, or replace the built in ones, with
+//! `register_dialect`. A backend can also be given another registered dialect with
+//! `WithDialect`, e.g. for databases which speak the postgres protocol but not its sql.
+
+use failure::{Error, format_err};
+use futures::{Future, Stream};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::backend::{Backend, Capabilities, ColumnInfo, TableInfo};
+use crate::dataframe::{DataFrame, PreviousYearColumns};
+use crate::query::SortDirection;
+use crate::query_ir::QueryIr;
+use crate::sql;
+
+
+pub const STANDARD_DIALECT: &str = "standard";
+
+pub trait SqlDialect: Send + Sync {
+    /// Name the dialect is registered under, e.g. `clickhouse`.
+    fn name(&self) -> &str;
+
+    /// Generates sql from an intermediate representation of the Query. Cannot error, and all
+    /// checks should be done before calling this.
+    fn generate_sql(&self, query_ir: QueryIr) -> String;
+
+    /// Whether the generated sql calculates growth. If not, growth is taken out of the
+    /// `QueryIr` with `QueryIr::take_growth` and calculated on the results.
+    fn supports_growth(&self) -> bool {
+        false
+    }
+
+    /// Features of the generated sql. Backends add the ones of their own, like streaming,
+    /// in `Backend::capabilities`.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Escapes a member for a single quoted string literal. Standard sql only doubles the
+    /// quotes; dialects which read backslash escapes in literals must escape those too.
+    fn escape_literal(&self, s: &str) -> String {
+        s.replace('\'', "''")
+    }
+
+    /// Quotes a table name which isn't a plain identifier, or is a reserved word.
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_identifier(name, '"', '"')
+    }
+
+    /// Wraps the sql of a query so that it only returns the first and last `rows` rows of its
+    /// results, for previews. Two columns are added after the query's own: the row number
+    /// and the total number of rows, which `DataFrame::take_preview_total` takes off. Rows
+    /// are numbered in the query's `order` (see `QueryIr::result_order`).
+    fn preview_sql(&self, sql: &str, rows: u64, order: &ResultOrder) -> String {
+        preview_sql(sql, rows, &order.row_number_sql("()"), Some(order.column_count))
+    }
+
+    /// Whether `previous_year_sql` can compare a measure with the previous year in sql. If not,
+    /// the comparison is calculated on the results, with `DataFrame::add_previous_year`.
+    fn supports_previous_year(&self) -> bool {
+        self.capabilities().window_functions
+    }
+
+    /// Wraps the sql of a query comparing a measure with the same period of the previous
+    /// year (see `QueryIr::previous_year`), so that the database adds the comparison columns
+    /// and drops the previous year's rows, keeping the first `limit` rows of the rest.
+    /// `order` is the query's, as for `preview_sql`.
+    fn previous_year_sql(&self, sql: &str, columns: &PreviousYearColumns, order: &ResultOrder, limit: Option<u64>) -> String {
+        let sql = previous_year_sql(sql, columns, order);
+        match limit {
+            Some(n) => format!("{} limit {};", sql.trim_end_matches(';'), n),
+            None => sql,
+        }
+    }
+
+    /// A table read on a random `sample` of its rows, between 0 and 1, for the diagnosis
+    /// checks. Aggregate queries sample the fact table in `generate_sql`. Only used when the
+    /// dialect's `capabilities` have `sampling`.
+    fn sample_table_sql(&self, table: &str, sample: f64) -> String {
+        format!("{} tablesample system ({})", table, sample_percent(sample))
+    }
+
+    /// An expression as text, e.g. for reading a date column the same way on every backend.
+    fn cast_to_text_sql(&self, expr: &str) -> String {
+        format!("cast({} as varchar)", expr)
+    }
+
+    /// Statement creating the table `table` from the results of the query `sql`, for
+    /// suggested pre-aggregations.
+    fn create_table_as_sql(&self, table: &str, sql: &str) -> String {
+        format!("create table {} as {};", self.quote_identifier(table), sql.trim_end_matches(';'))
+    }
+}
+
+
+/// Standard sql, used by backends without a dialect of their own.
+pub struct StandardSql;
+
+impl SqlDialect for StandardSql {
+    fn name(&self) -> &str {
+        STANDARD_DIALECT
+    }
+
+    fn generate_sql(&self, mut query_ir: QueryIr) -> String {
+        query_ir.escape(self);
+        escaped_standard_sql(query_ir)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sampling: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+/// Standard sql for a `QueryIr` which was already escaped, for dialects with their own
+/// escaping (see `QueryIr::escape`) but standard sql otherwise.
+pub fn escaped_standard_sql(query_ir: QueryIr) -> String {
+    sql::standard_sql(&query_ir)
+}
+
+/// Words which can't be table names without quotes.
+const RESERVED_WORDS: &[&str] = &[
+    "all", "and", "as", "between", "by", "case", "check", "column", "constraint", "create",
+    "default", "distinct", "drop", "else", "end", "fetch", "foreign", "from", "grant", "group",
+    "having", "in", "is", "join", "key", "like", "limit", "not", "null", "offset", "on", "or", "order",
+    "over", "partition", "primary", "references", "select", "table", "then", "to", "union",
+    "user", "when", "where", "window", "with",
+];
+
+/// Quotes `name` with `open` and `close`, doubling any `close` in it, unless it's a plain
+/// identifier (letters, digits and underscores, not starting with a digit) which isn't a
+/// reserved word. Names which are already quoted, or are subqueries, are left as is.
+pub fn quote_identifier(name: &str, open: char, close: char) -> String {
+    let is_plain = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && !RESERVED_WORDS.contains(&name.to_lowercase().as_str());
+    let is_quoted = name.starts_with(|c| c == '"' || c == '`' || c == '[' || c == '(');
+
+    if is_plain || is_quoted {
+        name.to_owned()
+    } else {
+        let escaped_close: String = [close, close].iter().collect();
+        format!("{}{}{}", open, name.replace(close, &escaped_close), close)
+    }
+}
+
+/// A sample fraction as a percentage, rounded to 4 decimals so it formats cleanly.
+pub fn sample_percent(sample: f64) -> f64 {
+    (sample * 1_000_000.0).round() / 10_000.0
+}
+
+/// Quotes each part of a name which may be qualified, e.g. by its schema. Subqueries are
+/// left as is, and dots inside quotes don't split the name.
+pub fn quote_qualified(dialect: &dyn SqlDialect, name: &str) -> String {
+    if name.trim_start().starts_with('(') {
+        return name.to_owned();
+    }
+
+    split_qualified(name).into_iter()
+        .map(|part| dialect.quote_identifier(part))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Quotes a column name like `quote_qualified`, including names with spaces or accents.
+/// Columns with parentheses, operators or string literals, or `case` expressions, are
+/// expressions, and are left as is.
+pub fn quote_column(dialect: &dyn SqlDialect, name: &str) -> String {
+    let is_expression = name.contains(|c| "()+-*/%<>=!|&,';".contains(c))
+        || name.trim_start().to_lowercase().starts_with("case ");
+
+    if is_expression {
+        name.to_owned()
+    } else {
+        quote_qualified(dialect, name)
+    }
+}
+
+/// Splits a name on the dots which aren't inside `"`, backtick or bracket quotes.
+fn split_qualified(name: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut close = None;
+    let mut start = 0;
+
+    for (i, c) in name.char_indices() {
+        match close {
+            Some(close_c) if c == close_c => close = None,
+            Some(_) => (),
+            None => match c {
+                '"' | '`' => close = Some(c),
+                '[' => close = Some(']'),
+                '.' => {
+                    parts.push(&name[start..i]);
+                    start = i + 1;
+                },
+                _ => (),
+            },
+        }
+    }
+    parts.push(&name[start..]);
+
+    parts
+}
+
+/// A column name as a plain identifier, to build aliases from: without its quotes, and with
+/// anything but letters, digits and underscores replaced by underscores. Plain identifiers
+/// are unchanged, and a column gives the same alias before and after it's quoted.
+pub fn plain_identifier(name: &str) -> String {
+    let unquoted = match (name.chars().next(), name.chars().last()) {
+        (Some(open), Some(close)) if name.len() > 1 && ((open == '"' && close == '"') || (open == '`' && close == '`') || (open == '[' && close == ']')) => {
+            let escaped_close: String = [close, close].iter().collect();
+            name[1..name.len() - 1].replace(&escaped_close, &close.to_string())
+        },
+        _ => name.to_owned(),
+    };
+
+    unquoted.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+
+lazy_static! {
+    static ref DIALECTS: RwLock<HashMap<String, Arc<dyn SqlDialect>>> = RwLock::new(HashMap::new());
+}
+
+/// Registers a dialect under its name, replacing any dialect with the same name.
+pub fn register_dialect(dialect: Arc<dyn SqlDialect>) {
+    DIALECTS.write().unwrap().insert(dialect.name().to_owned(), dialect);
+}
+
+/// Registered dialect with this name, if any.
+pub fn get_dialect(name: &str) -> Option<Arc<dyn SqlDialect>> {
+    DIALECTS.read().unwrap().get(name).cloned()
+}
+
+/// Names of the registered dialects, sorted.
+pub fn dialect_names() -> Vec<String> {
+    let mut names: Vec<String> = DIALECTS.read().unwrap().keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// How the results of a query are sorted, for numbering their rows in previews.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultOrder {
+    /// Number of columns in the results.
+    pub column_count: usize,
+    /// Columns the results are sorted on, by their index, with the direction.
+    pub sort: Vec<(usize, SortDirection)>,
+}
+
+impl ResultOrder {
+    /// `row_number()` over the sorted columns, as named by `preview_sql`, or over the
+    /// `unsorted` window for results without a sort.
+    pub fn row_number_sql(&self, unsorted: &str) -> String {
+        if self.sort.is_empty() {
+            return format!("row_number() over {}", unsorted);
+        }
+
+        let sort_cols = self.sort.iter()
+            .map(|(idx, direction)| format!("preview_c{} {}", idx, direction.sql_string()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("row_number() over (order by {})", sort_cols)
+    }
+}
+
+/// Preview sql for dialects with window functions. `row_number` is the dialect's expression
+/// for the number of a row (from 1), in the order of the query's results. With a
+/// `column_count`, the query's columns are named `preview_c0`, `preview_c1`, ..., so that
+/// `row_number` can sort on them.
+pub fn preview_sql(sql: &str, rows: u64, row_number: &str, column_count: Option<usize>) -> String {
+    let column_names = match column_count {
+        Some(count) => {
+            let names = (0..count).map(|i| format!("preview_c{}", i)).collect::<Vec<_>>();
+            format!(" ({})", names.join(", "))
+        },
+        None => "".to_owned(),
+    };
+
+    format!(
+        "select * from (select preview_q.*, {row_number} as preview_row, count(*) over () as preview_total \
+        from ({sql}) as preview_q{column_names}) as preview_p \
+        where preview_row <= {rows} or preview_row > preview_total - {rows} order by preview_row;",
+        row_number = row_number,
+        sql = sql.trim_end_matches(';'),
+        column_names = column_names,
+        rows = rows,
+    )
+}
+
+/// Previous year sql for dialects with window functions. The query's columns are named
+/// `py_c0`, `py_c1`, ..., and each row is compared with the previous row of the same members
+/// of the other drilldowns and the same month or quarter of the year, when it's of the
+/// previous year. Like `DataFrame::add_previous_year`, the previous year's value, the
+/// difference and the growth are appended, and rows outside of the `period_cuts` dropped.
+/// The time level's keys must be numbers in the database.
+pub fn previous_year_sql(sql: &str, columns: &PreviousYearColumns, order: &ResultOrder) -> String {
+    let col = |idx: usize| format!("py_c{}", idx);
+    let time_col = col(columns.time_idx);
+    let mea_col = col(columns.mea_idx);
+    let offset = columns.period_level.year_offset();
+
+    let column_names = (0..order.column_count).map(col).collect::<Vec<_>>();
+
+    let mut partition_cols: Vec<_> = columns.group_idxs.iter().map(|idx| col(*idx)).collect();
+    partition_cols.push(format!("{} % {}", time_col, offset));
+    let window = format!("over (partition by {} order by {})", partition_cols.join(", "), time_col);
+
+    let period_clauses: Vec<_> = columns.period_cuts.iter()
+        .map(|cut| {
+            let ranges: Vec<_> = cut.periods.iter()
+                .filter_map(|period| columns.period_level.contained_periods(*period, cut.level))
+                .map(|(first, last)| format!("py_r.{} between {} and {}", time_col, first, last))
+                .collect();
+            format!("({})", ranges.join(" or "))
+        })
+        .collect();
+    let where_clause = if period_clauses.is_empty() {
+        "".to_owned()
+    } else {
+        format!(" where {}", period_clauses.join(" and "))
+    };
+
+    let order_clause = if order.sort.is_empty() {
+        "".to_owned()
+    } else {
+        let sort_cols = order.sort.iter()
+            .map(|(idx, direction)| format!("py_r.{} {}", col(*idx), direction.sql_string()))
+            .collect::<Vec<_>>();
+        format!(" order by {}", sort_cols.join(", "))
+    };
+
+    format!(
+        "select py_r.*, py_r.{mea} - py_r.previous_year, 1.0 * (py_r.{mea} - py_r.previous_year) / nullif(py_r.previous_year, 0) \
+        from (select py_q.*, case when lag({time}) {window} = {time} - {offset} then lag({mea}) {window} end as previous_year \
+        from ({sql}) as py_q ({column_names})) as py_r{where_clause}{order_clause};",
+        mea = mea_col,
+        time = time_col,
+        window = window,
+        offset = offset,
+        sql = sql.trim_end_matches(';'),
+        column_names = column_names.join(", "),
+        where_clause = where_clause,
+        order_clause = order_clause,
+    )
+}
+
+/// The registered dialect with the same name as `dialect`, or else `dialect` itself.
+/// Used by backends to look up their built in dialect.
+pub fn registered_or<D: SqlDialect + 'static>(dialect: D) -> Arc<dyn SqlDialect> {
+    get_dialect(dialect.name()).unwrap_or_else(|| Arc::new(dialect))
+}
+
+
+/// Wraps a backend, generating its sql with a registered dialect instead of its own.
+#[derive(Clone)]
+pub struct WithDialect {
+    inner: Arc<dyn Backend + Send + Sync>,
+    dialect: Arc<dyn SqlDialect>,
+}
+
+impl WithDialect {
+    pub fn new(inner: Box<dyn Backend + Send + Sync>, dialect_name: &str) -> Result<Self, Error> {
+        let dialect = get_dialect(dialect_name)
+            .ok_or_else(|| format_err!(
+                "sql dialect {} is not registered, registered dialects: {}",
+                dialect_name, dialect_names().join(", "),
+            ))?;
+
+        Ok(WithDialect {
+            inner: inner.into(),
+            dialect,
+        })
+    }
+}
+
+impl Backend for WithDialect {
+    fn exec_sql(&self, sql: String) -> Box<dyn Future<Item=DataFrame, Error=Error>> {
+        self.inner.exec_sql(sql)
+    }
+
+    fn exec_sql_stream(&self, sql: String) -> Box<dyn Stream<Item=Result<DataFrame, Error>, Error=Error>> {
+        self.inner.exec_sql_stream(sql)
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        self.dialect.clone()
+    }
+
+    /// The sql features of the dialect, with the streaming of the backend.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            streaming: self.inner.capabilities().streaming,
+            ..self.dialect.capabilities()
+        }
+    }
+
+    fn inspect_tables(&self) -> Box<dyn Future<Item=Vec<TableInfo>, Error=Error>> {
+        self.inner.inspect_tables()
+    }
+
+    fn inspect_columns(&self, table: &str) -> Box<dyn Future<Item=Vec<ColumnInfo>, Error=Error>> {
+        self.inner.inspect_columns(table)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dataframe::{PeriodCut, PeriodLevel};
+
+    struct UpperSql;
+
+    impl SqlDialect for UpperSql {
+        fn name(&self) -> &str {
+            "upper"
+        }
+
+        fn generate_sql(&self, _query_ir: QueryIr) -> String {
+            "SELECT 1".to_owned()
+        }
+    }
+
+    #[test]
+    fn test_register_dialect() {
+        assert!(get_dialect("upper").is_none());
+        assert_eq!(registered_or(StandardSql).name(), STANDARD_DIALECT);
+
+        register_dialect(Arc::new(UpperSql));
+
+        assert_eq!(get_dialect("upper").unwrap().name(), "upper");
+        assert!(dialect_names().contains(&"upper".to_owned()));
+    }
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(StandardSql.quote_identifier("sales_2019"), "sales_2019");
+        assert_eq!(StandardSql.quote_identifier("Sales Data"), "\"Sales Data\"");
+        assert_eq!(StandardSql.quote_identifier("user"), "\"user\"");
+        assert_eq!(quote_identifier("key", '`', '`'), "`key`");
+        assert_eq!(StandardSql.quote_identifier("2019"), "\"2019\"");
+        assert_eq!(StandardSql.quote_identifier("odd\"name"), "\"odd\"\"name\"");
+        assert_eq!(StandardSql.quote_identifier("[sales]"), "[sales]");
+        assert_eq!(quote_identifier("Sales Data", '`', '`'), "`Sales Data`");
+    }
+
+    #[test]
+    fn test_quote_qualified() {
+        assert_eq!(quote_qualified(&StandardSql, "public.sales"), "public.sales");
+        assert_eq!(quote_qualified(&StandardSql, "public.order"), "public.\"order\"");
+        assert_eq!(quote_qualified(&StandardSql, "\"my.schema\".sales data"), "\"my.schema\".\"sales data\"");
+        assert_eq!(quote_qualified(&StandardSql, "(select a.b from c.d)"), "(select a.b from c.d)");
+
+        assert_eq!(quote_column(&StandardSql, "quantity"), "quantity");
+        assert_eq!(quote_column(&StandardSql, "Order ID"), "\"Order ID\"");
+        assert_eq!(quote_column(&StandardSql, "sales.año"), "sales.\"año\"");
+        assert_eq!(quote_column(&StandardSql, "\"Order ID\""), "\"Order ID\"");
+        assert_eq!(quote_column(&StandardSql, "sales.\"Order ID\""), "sales.\"Order ID\"");
+        assert_eq!(quote_column(&StandardSql, "price-discount"), "price-discount");
+        assert_eq!(quote_column(&StandardSql, "price - discount"), "price - discount");
+        assert_eq!(quote_column(&StandardSql, "case when a then b end"), "case when a then b end");
+        assert_eq!(quote_column(&StandardSql, "sales.order"), "sales.\"order\"");
+        assert_eq!(quote_column(&StandardSql, "toYear(date)"), "toYear(date)");
+        assert_eq!(quote_column(&StandardSql, "price*quantity"), "price*quantity");
+
+        assert_eq!(plain_identifier("year"), "year");
+        assert_eq!(plain_identifier("Order ID"), "Order_ID");
+        assert_eq!(plain_identifier("\"Order ID\""), "Order_ID");
+        assert_eq!(plain_identifier("`Order ID`"), "Order_ID");
+        assert_eq!(plain_identifier("[Order ID]"), "Order_ID");
+        assert_eq!(StandardSql.cast_to_text_sql("max(updated)"), "cast(max(updated) as varchar)");
+    }
+
+    #[test]
+    fn test_create_table_as_sql() {
+        assert_eq!(
+            StandardSql.create_table_as_sql("sales_agg_year", "select year, sum(quantity) from sales group by year;"),
+            "create table sales_agg_year as select year, sum(quantity) from sales group by year;"
+        );
+    }
+
+    #[test]
+    fn test_sample_table_sql() {
+        assert_eq!(StandardSql.sample_table_sql("sales", 0.07), "sales tablesample system (7)");
+    }
+
+    #[test]
+    fn test_preview_sql() {
+        assert_eq!(
+            StandardSql.preview_sql("select year, sum(quantity) from sales group by year;", 50, &ResultOrder { column_count: 2, sort: vec![] }),
+            "select * from (select preview_q.*, row_number() over () as preview_row, count(*) over () as preview_total \
+            from (select year, sum(quantity) from sales group by year) as preview_q (preview_c0, preview_c1)) as preview_p \
+            where preview_row <= 50 or preview_row > preview_total - 50 order by preview_row;"
+        );
+
+        let order = ResultOrder { column_count: 2, sort: vec![(1, SortDirection::Desc), (0, SortDirection::Asc)] };
+        assert_eq!(
+            StandardSql.preview_sql("select year, sum(quantity) from sales group by year order by 2 desc, 1 asc;", 50, &order),
+            "select * from (select preview_q.*, row_number() over (order by preview_c1 desc, preview_c0 asc) as preview_row, count(*) over () as preview_total \
+            from (select year, sum(quantity) from sales group by year order by 2 desc, 1 asc) as preview_q (preview_c0, preview_c1)) as preview_p \
+            where preview_row <= 50 or preview_row > preview_total - 50 order by preview_row;"
+        );
+    }
+
+    #[test]
+    fn test_previous_year_sql() {
+        let columns = PreviousYearColumns {
+            group_idxs: vec![1],
+            time_idx: 0,
+            period_level: PeriodLevel::Month,
+            mea_idx: 2,
+            period_cuts: vec![PeriodCut { level: PeriodLevel::Year, periods: vec![2020] }],
+        };
+        let order = ResultOrder { column_count: 3, sort: vec![(2, SortDirection::Desc)] };
+
+        assert_eq!(
+            StandardSql.previous_year_sql("select month, state, sum(quantity) from sales group by month, state order by 3 desc;", &columns, &order, Some(11)),
+            "select py_r.*, py_r.py_c2 - py_r.previous_year, 1.0 * (py_r.py_c2 - py_r.previous_year) / nullif(py_r.previous_year, 0) \
+            from (select py_q.*, case when lag(py_c0) over (partition by py_c1, py_c0 % 100 order by py_c0) = py_c0 - 100 \
+            then lag(py_c2) over (partition by py_c1, py_c0 % 100 order by py_c0) end as previous_year \
+            from (select month, state, sum(quantity) from sales group by month, state order by 3 desc) as py_q (py_c0, py_c1, py_c2)) as py_r \
+            where (py_r.py_c0 between 202001 and 202012) order by py_r.py_c2 desc limit 11;"
+        );
+    }
+}
@@ -3,7 +3,7 @@ use failure::{Error, format_err};
 use indexmap::IndexMap;
 use serde::Serializer;
 use serde::ser::{SerializeSeq};
-use serde_json::{Value};
+use serde_json::{json, Value};
 
 use crate::schema::metadata::SourceMetadata;
 use crate::dataframe::{DataFrame, ColumnData};
@@ -13,6 +13,7 @@ pub enum FormatType{
     Csv,
     JsonRecords,
     JsonArrays,
+    GeoJson,
 }
 
 impl std::str::FromStr for FormatType {
@@ -23,28 +24,244 @@ impl std::str::FromStr for FormatType {
             "csv" => Ok(FormatType::Csv),
             "jsonrecords" => Ok(FormatType::JsonRecords),
             "jsonarrays" => Ok(FormatType::JsonArrays),
+            "geojson" => Ok(FormatType::GeoJson),
             _ => Err(format_err!("{} is not a supported format", s)),
         }
     }
 }
 
+/// Header of the column holding the GeoJSON geometry of each row, for the `geojson` format.
+pub const GEOMETRY_HEADER: &str = "geometry";
+
+/// Number format for rendering measures in CSV, parsed from a spreadsheet style
+/// template like `#,##0.00`, `$#,##0` or `0.0%`.
+///
+/// A `,` in the number groups thousands; the `0`s after the `.` are the fixed decimals,
+/// and the `#`s are optional ones. Text around the number is kept as is, and a `%`
+/// multiplies the value by 100.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    prefix: String,
+    suffix: String,
+    grouping: bool,
+    min_decimals: usize,
+    max_decimals: usize,
+    percent: bool,
+}
+
+impl NumberFormat {
+    pub fn format(&self, n: f64, locale: &FormatLocale) -> String {
+        let n = if self.percent { n * 100.0 } else { n };
+
+        if !n.is_finite() {
+            return n.to_string();
+        }
+
+        let mut number = format!("{:.*}", self.max_decimals, n.abs());
+
+        // trim optional decimals
+        if self.max_decimals > self.min_decimals {
+            let point = number.find('.').expect("max_decimals > 0 always has a decimal point");
+            let min_len = point + 1 + self.min_decimals;
+            while number.len() > min_len && number.ends_with('0') {
+                number.pop();
+            }
+            if number.ends_with('.') {
+                number.pop();
+            }
+        }
+
+        // don't show a sign for values which round to zero
+        let sign = if n < 0.0 && number.chars().any(|c| c != '0' && c != '.') { "-" } else { "" };
+
+        format!("{}{}{}{}", sign, self.prefix, locale.localize(&number, self.grouping), self.suffix)
+    }
+}
+
+impl std::str::FromStr for NumberFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let start = s.find(|c| c == '#' || c == '0')
+            .ok_or_else(|| format_err!("Number format {} has no digits", s))?;
+        let end = s[start..].find(|c| c != '#' && c != '0' && c != ',' && c != '.')
+            .map(|i| start + i)
+            .unwrap_or_else(|| s.len());
+
+        let prefix = &s[..start];
+        let pattern = &s[start..end];
+        let suffix = &s[end..];
+
+        let (int_pattern, dec_pattern) = match pattern.find('.') {
+            Some(point) => (&pattern[..point], &pattern[point+1..]),
+            None => (pattern, ""),
+        };
+
+        if dec_pattern.contains(|c| c == '.' || c == ',') {
+            return Err(format_err!("Number format {} has an invalid decimal part", s));
+        }
+
+        let min_decimals = dec_pattern.chars().take_while(|c| *c == '0').count();
+        if dec_pattern[min_decimals..].contains('0') {
+            return Err(format_err!("Number format {} has fixed decimals after optional ones", s));
+        }
+
+        Ok(NumberFormat {
+            prefix: prefix.to_owned(),
+            suffix: suffix.to_owned(),
+            grouping: int_pattern.contains(','),
+            min_decimals,
+            max_decimals: dec_pattern.len(),
+            percent: prefix.contains('%') || suffix.contains('%'),
+        })
+    }
+}
+
+/// Decimal and thousands separators of a locale, e.g. `1.234,56` for `de-DE`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatLocale {
+    decimal_separator: char,
+    grouping_separator: char,
+}
+
+impl FormatLocale {
+    /// Localizes an unsigned number formatted by rust, like `1234.56`, optionally
+    /// grouping thousands.
+    pub fn localize(&self, number: &str, grouping: bool) -> String {
+        let (int_part, dec_part) = match number.find('.') {
+            Some(point) => (&number[..point], Some(&number[point+1..])),
+            None => (number, None),
+        };
+
+        let mut res = String::new();
+        for (i, c) in int_part.chars().enumerate() {
+            if grouping && i > 0 && (int_part.len() - i) % 3 == 0 {
+                res.push(self.grouping_separator);
+            }
+            res.push(c);
+        }
+
+        if let Some(dec_part) = dec_part {
+            res.push(self.decimal_separator);
+            res.push_str(dec_part);
+        }
+
+        res
+    }
+
+    /// Formats a float with the locale's separators, grouping thousands.
+    pub fn format_float(&self, n: f64) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+
+        let sign = if n < 0.0 { "-" } else { "" };
+
+        format!("{}{}", sign, self.localize(&n.abs().to_string(), true))
+    }
+}
+
+impl Default for FormatLocale {
+    fn default() -> Self {
+        FormatLocale {
+            decimal_separator: '.',
+            grouping_separator: ',',
+        }
+    }
+}
+
+impl std::str::FromStr for FormatLocale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // match on the language, unless the region changes the separators
+        let lang = s.split(|c| c == '-' || c == '_').next().unwrap_or("");
+
+        let (decimal_separator, grouping_separator) = match (s, lang) {
+            ("de-CH", _) | ("de_CH", _) => ('.', '\''),
+            (_, "en") | (_, "ja") | (_, "ko") | (_, "zh") => ('.', ','),
+            (_, "de") | (_, "es") | (_, "it") | (_, "nl") | (_, "pt") | (_, "id") | (_, "tr") => (',', '.'),
+            (_, "fr") | (_, "ru") | (_, "pl") | (_, "cs") | (_, "sv") | (_, "fi") | (_, "nb") => (',', '\u{a0}'),
+            _ => return Err(format_err!("{} is not a supported format locale", s)),
+        };
+
+        Ok(FormatLocale {
+            decimal_separator,
+            grouping_separator,
+        })
+    }
+}
+
+/// Renders the columns which have a `NumberFormat` as text, for output formats meant for
+/// people (i.e. CSV). `formats` has one entry per column.
+pub fn apply_number_formats(
+    df: &mut DataFrame,
+    formats: &[Option<NumberFormat>],
+    locale: &FormatLocale,
+) -> Result<(), Error>
+{
+    for (col, format) in df.columns.iter_mut().zip(formats) {
+        let format = match format {
+            Some(format) => format,
+            None => continue,
+        };
+
+        let values: Vec<Option<f64>> = match col.column_data {
+            ColumnData::Int8(ref ns) =>    ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::Int16(ref ns) =>   ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::Int32(ref ns) =>   ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::Int64(ref ns) =>   ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::UInt8(ref ns) =>   ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::UInt16(ref ns) =>  ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::UInt32(ref ns) =>  ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::UInt64(ref ns) =>  ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::Float32(ref ns) => ns.iter().map(|n| Some(*n as f64)).collect(),
+            ColumnData::Float64(ref ns) => ns.iter().map(|n| Some(*n)).collect(),
+            ColumnData::NullableInt8(ref ns) =>    ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableInt16(ref ns) =>   ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableInt32(ref ns) =>   ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableInt64(ref ns) =>   ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableUInt8(ref ns) =>   ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableUInt16(ref ns) =>  ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableUInt32(ref ns) =>  ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableUInt64(ref ns) =>  ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableFloat32(ref ns) => ns.iter().map(|n| n.map(|n| n as f64)).collect(),
+            ColumnData::NullableFloat64(ref ns) => ns.clone(),
+            ColumnData::Text(_) | ColumnData::DictText(_) | ColumnData::NullableText(_) => {
+                return Err(format_err!("Cannot apply a number format to text column {}", col.name));
+            },
+        };
+
+        col.column_data = ColumnData::NullableText(
+            values.into_iter().map(|n| n.map(|n| format.format(n, locale))).collect()
+        );
+    }
+
+    Ok(())
+}
+
 /// Wrapper to format `DataFrame` to the desired output format.
+///
+/// `locale` sets the decimal and thousands separators of floats in CSV; JSON formats
+/// always use plain numbers.
 pub fn format_records(
     headers: &[String],
     df: DataFrame,
     format_type: FormatType,
+    locale: Option<&FormatLocale>,
     source_data: Option<SourceMetadata>,
     error: bool
 ) -> Result<String, Error> {
     match format_type {
-        FormatType::Csv => Ok(format_csv(headers, df)?),
+        FormatType::Csv => Ok(format_csv(headers, df, locale)?),
         FormatType::JsonRecords => Ok(format_jsonrecords(headers, df, source_data, error)?),
         FormatType::JsonArrays => Ok(format_jsonarrays(headers, df, error)?),
+        FormatType::GeoJson => Ok(format_geojson(headers, df)?),
     }
 }
 
 /// Formats response `DataFrame` to CSV.
-fn format_csv(headers: &[String], df: DataFrame) -> Result<String, Error> {
+fn format_csv(headers: &[String], df: DataFrame, locale: Option<&FormatLocale>) -> Result<String, Error> {
     let mut wtr = csv::WriterBuilder::new()
         .from_writer(vec![]);
 
@@ -68,6 +285,7 @@ fn format_csv(headers: &[String], df: DataFrame) -> Result<String, Error> {
                 ColumnData::Float32(ref ns) => ns[row_idx].to_string(),
                 ColumnData::Float64(ref ns) => ns[row_idx].to_string(),
                 ColumnData::Text(ref ss) =>    ss[row_idx].to_string(),
+                ColumnData::DictText(ref d) =>  d.get(row_idx).to_string(),
                 ColumnData::NullableInt8(ref ns) =>    ns[row_idx].map(|n| n.to_string()).unwrap_or("".into()),
                 ColumnData::NullableInt16(ref ns) =>   ns[row_idx].map(|n| n.to_string()).unwrap_or("".into()),
                 ColumnData::NullableInt32(ref ns) =>   ns[row_idx].map(|n| n.to_string()).unwrap_or("".into()),
@@ -81,6 +299,15 @@ fn format_csv(headers: &[String], df: DataFrame) -> Result<String, Error> {
                 ColumnData::NullableText(ref ss) =>    ss[row_idx].clone().unwrap_or("".into()),
             };
 
+            // floats are the only numbers which are localized; integers are often ids or years
+            let val = match (locale, &df.columns[col_idx].column_data) {
+                (Some(locale), ColumnData::Float32(ns)) => locale.format_float(ns[row_idx] as f64),
+                (Some(locale), ColumnData::Float64(ns)) => locale.format_float(ns[row_idx]),
+                (Some(locale), ColumnData::NullableFloat32(ns)) => ns[row_idx].map(|n| locale.format_float(n as f64)).unwrap_or_default(),
+                (Some(locale), ColumnData::NullableFloat64(ns)) => ns[row_idx].map(|n| locale.format_float(n)).unwrap_or_default(),
+                _ => val,
+            };
+
             row_buf.push(val);
         }
         wtr.write_record(&row_buf)?;
@@ -135,6 +362,7 @@ fn format_jsonrecords(headers: &[String], df: DataFrame, source_data: Option<Sou
                 ColumnData::Float32(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Float64(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Text(ref ss) =>    ss[row_idx].clone().into(),
+                ColumnData::DictText(ref d) =>  d.get(row_idx).into(),
                 ColumnData::NullableInt8(ref ns) =>    ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt16(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt32(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
@@ -171,6 +399,117 @@ fn format_jsonrecords(headers: &[String], df: DataFrame, source_data: Option<Sou
 //    Ok(res.to_string())
 }
 
+/// Formats response `DataFrame` to JSON records, followed by a `schema` block with the name,
+/// type (`integer`, `float` or `string`) and nullability of each column, for clients which
+/// need the types up front instead of guessing them from the values.
+pub fn format_jsonrecords_with_schema(
+    headers: &[String],
+    df: DataFrame,
+    source_data: Option<SourceMetadata>,
+) -> Result<String, Error>
+{
+    let schema: Vec<Value> = headers.iter()
+        .zip(&df.columns)
+        .map(|(header, column)| {
+            let (column_type, nullable) = column_type(&column.column_data);
+            json!({ "name": header, "type": column_type, "nullable": nullable })
+        })
+        .collect();
+
+    let mut res = format_jsonrecords(headers, df, source_data, false)?;
+    // reopens the object to add the schema after the data
+    res.pop();
+    res.push_str(",\n\"schema\": ");
+    res.push_str(&serde_json::to_string(&schema)?);
+    res.push('}');
+    Ok(res)
+}
+
+fn column_type(column_data: &ColumnData) -> (&'static str, bool) {
+    match column_data {
+        ColumnData::Int8(_) | ColumnData::Int16(_) | ColumnData::Int32(_) | ColumnData::Int64(_) |
+        ColumnData::UInt8(_) | ColumnData::UInt16(_) | ColumnData::UInt32(_) | ColumnData::UInt64(_) => ("integer", false),
+        ColumnData::Float32(_) | ColumnData::Float64(_) => ("float", false),
+        ColumnData::Text(_) | ColumnData::DictText(_) => ("string", false),
+        ColumnData::NullableInt8(_) | ColumnData::NullableInt16(_) | ColumnData::NullableInt32(_) | ColumnData::NullableInt64(_) |
+        ColumnData::NullableUInt8(_) | ColumnData::NullableUInt16(_) | ColumnData::NullableUInt32(_) | ColumnData::NullableUInt64(_) => ("integer", true),
+        ColumnData::NullableFloat32(_) | ColumnData::NullableFloat64(_) => ("float", true),
+        ColumnData::NullableText(_) => ("string", true),
+    }
+}
+
+/// Formats response `DataFrame` to a GeoJSON FeatureCollection.
+///
+/// The column with the `GEOMETRY_HEADER` header holds each feature's geometry as a GeoJSON
+/// string; all the other columns become the feature's properties.
+fn format_geojson(headers: &[String], df: DataFrame) -> Result<String, Error> {
+    let geometry_idx = headers.iter()
+        .position(|h| h == GEOMETRY_HEADER)
+        .ok_or_else(|| format_err!("GeoJSON format requires a geometry column"))?;
+
+    let mut features = Vec::with_capacity(df.len());
+
+    for row_idx in 0..df.len() {
+        let mut properties = serde_json::Map::new();
+        let mut geometry = Value::Null;
+
+        for col_idx in 0..df.columns.len() {
+            let val = column_value(&df.columns[col_idx].column_data, row_idx);
+
+            if col_idx == geometry_idx {
+                geometry = match val {
+                    Value::String(ref s) => serde_json::from_str(s)
+                        .map_err(|err| format_err!("Invalid geometry in row {}: {}", row_idx, err))?,
+                    _ => Value::Null,
+                };
+            } else {
+                properties.insert(headers[col_idx].clone(), val);
+            }
+        }
+
+        features.push(json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": properties,
+        }));
+    }
+
+    let res = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+
+    Ok(res.to_string())
+}
+
+fn column_value(column_data: &ColumnData, row_idx: usize) -> Value {
+    match column_data {
+        ColumnData::Int8(ns) =>    ns[row_idx].into(),
+        ColumnData::Int16(ns) =>   ns[row_idx].into(),
+        ColumnData::Int32(ns) =>   ns[row_idx].into(),
+        ColumnData::Int64(ns) =>   ns[row_idx].into(),
+        ColumnData::UInt8(ns) =>   ns[row_idx].into(),
+        ColumnData::UInt16(ns) =>  ns[row_idx].into(),
+        ColumnData::UInt32(ns) =>  ns[row_idx].into(),
+        ColumnData::UInt64(ns) =>  ns[row_idx].into(),
+        ColumnData::Float32(ns) => ns[row_idx].into(),
+        ColumnData::Float64(ns) => ns[row_idx].into(),
+        ColumnData::Text(ss) =>    ss[row_idx].clone().into(),
+        ColumnData::DictText(d) =>  d.get(row_idx).into(),
+        ColumnData::NullableInt8(ns) =>    ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableInt16(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableInt32(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableInt64(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt8(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt16(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt32(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt64(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableFloat32(ns) => ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableFloat64(ns) => ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableText(ss) =>    ss[row_idx].clone().map(|s| s.into()).unwrap_or(Value::Null),
+    }
+}
+
 /// Formats response `DataFrame` to JSON arrays.
 fn format_jsonarrays(headers: &[String], df: DataFrame, error: bool) -> Result<String, Error> {
     // use streaming serializer
@@ -224,6 +563,7 @@ fn format_jsonarrays(headers: &[String], df: DataFrame, error: bool) -> Result<S
                 ColumnData::Float32(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Float64(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Text(ref ss) =>    ss[row_idx].clone().into(),
+                ColumnData::DictText(ref d) =>  d.get(row_idx).into(),
                 ColumnData::NullableInt8(ref ns) =>    ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt16(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt32(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
@@ -255,3 +595,104 @@ fn format_jsonarrays(headers: &[String], df: DataFrame, error: bool) -> Result<S
 //        "data": rows,
 //    });
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dataframe::Column;
+
+    #[test]
+    fn test_number_format() {
+        let en = FormatLocale::default();
+
+        let fmt: NumberFormat = "#,##0.00".parse().unwrap();
+        assert_eq!(fmt.format(1234567.891, &en), "1,234,567.89");
+        assert_eq!(fmt.format(-0.5, &en), "-0.50");
+        assert_eq!(fmt.format(-0.001, &en), "0.00");
+
+        let fmt: NumberFormat = "$#,##0".parse().unwrap();
+        assert_eq!(fmt.format(999.6, &en), "$1,000");
+
+        let fmt: NumberFormat = "0.0#%".parse().unwrap();
+        assert_eq!(fmt.format(0.1234, &en), "12.34%");
+        assert_eq!(fmt.format(0.5, &en), "50.0%");
+        assert_eq!(fmt.format(std::f64::NAN, &en), "NaN");
+        assert_eq!(fmt.format(std::f64::INFINITY, &en), "inf");
+        assert_eq!(fmt.format(std::f64::NEG_INFINITY, &en), "-inf");
+        assert_eq!(fmt.format(std::f64::MAX, &en), "inf");
+
+        assert!("abc".parse::<NumberFormat>().is_err());
+        assert!("0.#0".parse::<NumberFormat>().is_err());
+    }
+
+    #[test]
+    fn test_format_locale() {
+        let de: FormatLocale = "de-DE".parse().unwrap();
+        assert_eq!(de.format_float(1234.56), "1.234,56");
+        assert_eq!(de.format_float(-12.5), "-12,5");
+
+        let fmt: NumberFormat = "#,##0.00".parse().unwrap();
+        assert_eq!(fmt.format(1234567.891, &de), "1.234.567,89");
+
+        assert!("xx-XX".parse::<FormatLocale>().is_err());
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018])),
+            Column::new("m0".into(), ColumnData::Float64(vec![1234.5])),
+        ]);
+        let headers: Vec<String> = vec!["Year".into(), "Price".into()];
+
+        let res = format_records(&headers, df, FormatType::Csv, Some(&de), None, false).unwrap();
+        assert_eq!(res, "Year,Price\n2018,\"1.234,5\"\n");
+    }
+
+    #[test]
+    fn test_format_jsonrecords_with_schema() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2018])),
+            Column::new("m0".into(), ColumnData::NullableFloat64(vec![Some(1.5)])),
+        ]);
+        let headers: Vec<String> = vec!["Year".into(), "Price".into()];
+
+        let res = format_jsonrecords_with_schema(&headers, df, None).unwrap();
+        let res: Value = serde_json::from_str(&res).unwrap();
+
+        assert_eq!(res, json!({
+            "data": [{"Year": 2018, "Price": 1.5}],
+            "schema": [
+                {"name": "Year", "type": "integer", "nullable": false},
+                {"name": "Price", "type": "float", "nullable": true},
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_format_geojson() {
+        let df = DataFrame::from_vec(vec![
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AK".into()])),
+            Column::new("geom".into(), ColumnData::NullableText(vec![Some(r#"{"type":"Point","coordinates":[1,2]}"#.into()), None])),
+            Column::new("m0".into(), ColumnData::Int64(vec![1, 2])),
+        ]);
+        let headers: Vec<String> = vec!["State".into(), GEOMETRY_HEADER.into(), "Quantity".into()];
+
+        let res = format_records(&headers, df, FormatType::GeoJson, None, None, false).unwrap();
+        let res: Value = serde_json::from_str(&res).unwrap();
+
+        assert_eq!(res, json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": [1, 2]},
+                    "properties": {"State": "AL", "Quantity": 1},
+                },
+                {
+                    "type": "Feature",
+                    "geometry": null,
+                    "properties": {"State": "AK", "Quantity": 2},
+                },
+            ],
+        }));
+    }
+}
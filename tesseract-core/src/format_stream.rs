@@ -233,6 +233,7 @@ fn format_csv_body(df: DataFrame) -> Result<Bytes, Error>
                 ColumnData::Float32(ref ns) => ns[row_idx].to_string(),
                 ColumnData::Float64(ref ns) => ns[row_idx].to_string(),
                 ColumnData::Text(ref ss) =>    ss[row_idx].to_string(),
+                ColumnData::DictText(ref d) =>  d.get(row_idx).to_string(),
                 ColumnData::NullableInt8(ref ns) =>    ns[row_idx].map(|n| n.to_string()).unwrap_or("".into()),
                 ColumnData::NullableInt16(ref ns) =>   ns[row_idx].map(|n| n.to_string()).unwrap_or("".into()),
                 ColumnData::NullableInt32(ref ns) =>   ns[row_idx].map(|n| n.to_string()).unwrap_or("".into()),
@@ -284,6 +285,7 @@ fn format_jsonrecords_body(headers: &[String], df: DataFrame, lead_byte: u8) ->
                 ColumnData::Float32(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Float64(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Text(ref ss) =>    ss[row_idx].clone().into(),
+                ColumnData::DictText(ref d) =>  d.get(row_idx).into(),
                 ColumnData::NullableInt8(ref ns) =>    ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt16(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt32(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
@@ -343,6 +345,7 @@ fn format_jsonarrays_body(_headers: &[String], df: DataFrame, lead_byte: u8) ->
                 ColumnData::Float32(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Float64(ref ns) => ns[row_idx].clone().into(),
                 ColumnData::Text(ref ss) =>    ss[row_idx].clone().into(),
+                ColumnData::DictText(ref d) =>  d.get(row_idx).into(),
                 ColumnData::NullableInt8(ref ns) =>    ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt16(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
                 ColumnData::NullableInt32(ref ns) =>   ns[row_idx].map(|n| n.clone().into()).unwrap_or(Value::Null),
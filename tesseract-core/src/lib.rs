@@ -1,11 +1,17 @@
 mod backend;
+mod chunked;
 mod dataframe;
+pub mod dialect;
+mod numeric;
 mod sql;
+mod text_dict;
 pub mod format;
 pub mod format_stream;
+pub mod mdx;
 pub mod names;
 pub mod schema;
 pub mod query;
+pub mod query_body;
 pub mod query_ir;
 
 use failure::{Error, format_err, bail};
@@ -14,10 +20,15 @@ use serde_xml_rs as serde_xml;
 use serde_xml::from_reader;
 use std::collections::{HashSet, HashMap};
 use std::str::FromStr;
+use std::path::Path;
+use crate::format::NumberFormat;
 use crate::schema::{SchemaConfigJson, SchemaConfigXML};
+use crate::dialect::{plain_identifier, quote_column, quote_qualified, SqlDialect};
 
-pub use self::backend::Backend;
-pub use self::dataframe::{DataFrame, Column, ColumnData, is_same_columndata_type};
+pub use self::backend::{Backend, Capabilities, TableInfo, ColumnInfo, CancelOnDrop, table_filter, tag_sql, two_string_columns};
+pub use self::chunked::{DataFrameBuilder, DEFAULT_CHUNK_ROWS};
+pub use self::dataframe::{DataFrame, Column, ColumnData, GrowthColumns, ShareColumns, PreviousYearColumns, PeriodCut, PeriodLevel, CalculationColumns, ResultCalculation, TimeFill, Unit, UnitConversion, is_same_columndata_type};
+pub use self::text_dict::{TextDict, TextDictBuilder};
 
 pub static DEFAULT_ALLOWED_ACCESS: i32 = 0;
 
@@ -29,9 +40,11 @@ use self::names::{
     LevelName,
     Mask,
 };
-pub use self::schema::{Schema, Cube, Dimension, Table, Aggregator};
+pub use self::schema::{Schema, Cube, Dimension, Table, Aggregator, NullPolicy};
+use self::schema::DimensionType;
 use self::schema::metadata::{SchemaMetadata, CubeMetadata};
 use self::query_ir::{
+    member_literal,
     CutSql,
     DrilldownSql,
     MeasureSql,
@@ -46,9 +59,11 @@ use self::query_ir::{
     GrowthSql,
     RateSql,
     FilterSql,
+    RowFilterSql,
 };
-pub use self::query::{Query, MeaOrCalc, FilterQuery};
-pub use self::query_ir::QueryIr;
+pub use self::query::{Query, MeaOrCalc, FilterQuery, PropertyFilter, QueryPriority, ShareQuery, ShowColumns};
+use self::query::{SortKey, SortQuery};
+pub use self::query_ir::{QueryIr, AllMemberColumn};
 macro_rules! mea_or_calc {
     ($m_or_c:expr, $query:expr) => {
         match $m_or_c {
@@ -72,14 +87,38 @@ macro_rules! mea_or_calc {
 }
 
 impl Schema {
-    /// Deserializes JSON schema into a `Schema`.
+    /// Deserializes JSON schema into a `Schema`. Relative csv paths of inline tables are
+    /// relative to the working directory.
     pub fn from_json(raw_schema: &str) -> Result<Self, Error> {
+        Schema::from_json_at(raw_schema, Path::new(""))
+    }
+
+    /// Deserializes JSON schema into a `Schema`. Relative csv paths of inline tables are
+    /// relative to `base_dir`, usually the directory of the schema file.
+    pub fn from_json_at(raw_schema: &str, base_dir: &Path) -> Result<Self, Error> {
         let schema_config = serde_json::from_str::<SchemaConfigJson>(raw_schema)?;
-        Ok(schema_config.into())
+        let mut schema: Schema = schema_config.into();
+
+        for cube in &mut schema.cubes {
+            for hier in cube.dimensions.iter_mut().flat_map(|dim| dim.hierarchies.iter_mut()) {
+                if let Some(ref mut inline_table) = hier.inline_table {
+                    inline_table.load(base_dir)?;
+                }
+            }
+        }
+
+        Ok(schema)
     }
 
-    /// Deserializes XML schema into a `Schema`.
+    /// Deserializes XML schema into a `Schema`. Relative csv paths of inline tables are
+    /// relative to the working directory.
     pub fn from_xml(raw_schema: &str) -> Result<Self, Error> {
+        Schema::from_xml_at(raw_schema, Path::new(""))
+    }
+
+    /// Deserializes XML schema into a `Schema`. Relative csv paths of inline tables are
+    /// relative to `base_dir`, usually the directory of the schema file.
+    pub fn from_xml_at(raw_schema: &str, base_dir: &Path) -> Result<Self, Error> {
         let schema_config: SchemaConfigXML = match from_reader(raw_schema.as_bytes()) {
             Ok(schema_config_xml) => schema_config_xml,
             Err(err) => return Err(format_err!("Error reading XML schema: {}", err))
@@ -87,7 +126,7 @@ impl Schema {
 
         // Serialize XML to JSON as intermediary step
         let serialized = serde_json::to_string(&schema_config)?;
-        Schema::from_json(&serialized)
+        Schema::from_json_at(&serialized, base_dir)
     }
 
     /// schema validation
@@ -114,6 +153,120 @@ impl Schema {
             }
         };
 
+        // Required cuts must reference a dimension (and level, if given) of the cube
+        for cube in &self.cubes {
+            if let Some(ref required_cuts) = cube.required_cuts {
+                for required_cut in required_cuts {
+                    let dim = cube.dimensions.iter()
+                        .find(|dim| dim.name == required_cut.dimension)
+                        .ok_or_else(|| format_err!("Required cut dimension {} not found in cube {}", required_cut.dimension, cube.name))?;
+
+                    if let Some(ref level) = required_cut.level {
+                        let has_level = dim.hierarchies.iter()
+                            .flat_map(|hier| hier.levels.iter())
+                            .any(|lvl| &lvl.name == level);
+
+                        if !has_level {
+                            bail!("Required cut level {} not found in dimension {} of cube {}", level, dim.name, cube.name);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Snowflake joins hang off a dimension table, so the hierarchy needs its
+        // own table, which can't be the fact table or an inline table.
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                for hier in &dim.hierarchies {
+                    if hier.joins.is_none() {
+                        continue;
+                    }
+
+                    let has_dim_table = hier.table.as_ref()
+                        .map(|table| table.name != cube.table.name)
+                        .unwrap_or(false);
+
+                    if !has_dim_table || hier.inline_table.is_some() {
+                        bail!("Joins in hierarchy {} of cube {} require a dimension table", hier.name, cube.name);
+                    }
+                }
+            }
+        }
+
+        // Zero-padded keys are numbers
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                for hier in &dim.hierarchies {
+                    for level in &hier.levels {
+                        if level.key_width.is_some() && level.key_type == Some(MemberType::Text) {
+                            bail!("Level {} of cube {} has a key width, so its key_type can't be text", level.name, cube.name);
+                        }
+                    }
+                }
+            }
+        }
+
+        // A level's geometry property must be one of its properties
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                for hier in &dim.hierarchies {
+                    for level in &hier.levels {
+                        if let Some(ref geometry_property) = level.geometry_property {
+                            let has_property = level.properties.iter()
+                                .flatten()
+                                .any(|p| p.name == *geometry_property);
+
+                            if !has_property {
+                                bail!("Geometry property {} not found in level {} of cube {}", geometry_property, level.name, cube.name);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // The All level is queried by name, so it can't be the name of a level of the cube.
+        // All levels can share a name (usually Mondrian's default `(All)`); the logic layer
+        // tells them apart by their hierarchy.
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                for hier in &dim.hierarchies {
+                    if hier.all_level_name.is_some() && hier.all_member_name.is_none() {
+                        bail!("Hierarchy {} of cube {} has an all_level_name without an all_member_name", hier.name, cube.name);
+                    }
+                    if let Some(all_level) = hier.all_level() {
+                        let is_level = cube.dimensions.iter()
+                            .flat_map(|dim| dim.hierarchies.iter())
+                            .flat_map(|hier| hier.levels.iter())
+                            .any(|level| level.name == all_level);
+
+                        if is_level {
+                            bail!("All level {} of hierarchy {} of cube {} has the name of a level", all_level, hier.name, cube.name);
+                        }
+                    }
+                }
+            }
+        }
+
+        for cube in &self.cubes {
+            if let Some(ref default_sort) = cube.default_sort {
+                default_sort.parse::<SortQuery>()
+                    .map_err(|err| format_err!("Default sort of cube {}: {}", cube.name, err))?;
+            }
+        }
+
+        // Measure format templates must parse, so that bad ones are caught on startup
+        // rather than when formatting a response.
+        for cube in &self.cubes {
+            for mea in &cube.measures {
+                if let Some(ref format_template) = mea.format_template {
+                    format_template.parse::<NumberFormat>()
+                        .map_err(|err| format_err!("Measure {} of cube {}: {}", mea.name, cube.name, err))?;
+                }
+            }
+        }
+
         // if there's multiple hierarchies in a dim, there must be a default hierarchy.
         // also, the default hierarchy must match names with an actual hierarchy.
         //
@@ -165,6 +318,44 @@ impl Schema {
         schema_metadata
     }
 
+    /// Cubes which use the same shared dimension, one relation per level of the dimension.
+    /// Relations go both ways, so the relations of a cube are those with it as `cube`.
+    pub fn cube_relations(&self) -> Vec<CubeRelation> {
+        let mut relations = vec![];
+
+        for cube in &self.cubes {
+            for related_cube in self.cubes.iter().filter(|c| c.name != cube.name) {
+                for dimension in &cube.dimensions {
+                    let shared_source = match dimension.shared_source {
+                        Some(ref shared_source) => shared_source,
+                        None => continue,
+                    };
+
+                    let related_dims = related_cube.dimensions.iter()
+                        .filter(|d| d.shared_source.as_ref() == Some(shared_source));
+
+                    for related_dim in related_dims {
+                        for hierarchy in &dimension.hierarchies {
+                            for level in &hierarchy.levels {
+                                relations.push(CubeRelation {
+                                    cube: cube.name.clone(),
+                                    dimension: dimension.name.clone(),
+                                    related_cube: related_cube.name.clone(),
+                                    related_dimension: related_dim.name.clone(),
+                                    shared_dimension: shared_source.clone(),
+                                    hierarchy: hierarchy.name.clone(),
+                                    level: level.name.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        relations
+    }
+
     pub fn has_unique_levels_properties(&self) -> CubeHasUniqueLevelsAndProperties {
         for cube in &self.cubes {
             let mut levels = HashSet::new();
@@ -208,31 +399,35 @@ impl Schema {
         CubeHasUniqueLevelsAndProperties::True
     }
 
+    /// Generates SQL to resolve a members query.
+    /// If a `parent` cut is given, only the members under those parent members are returned.
     pub fn members_sql(
         &self,
         cube: &str,
         level_name: &LevelName,
+        parent: Option<&Cut>,
+        properties: &[String],
         ) -> Result<(String, Vec<String>), Error> // Sql and then Header
     {
         let members_query_ir = self.get_dim_col_table(cube, level_name)?;
+        let parent_filter = self.members_filter(cube, level_name, parent)?;
+        let property_columns = self.members_property_columns(cube, level_name, properties)?;
 
-        let header = if members_query_ir.name_column.is_some() {
-            vec!["ID".into(), "Label".into()]
-        } else {
-            vec!["ID".into()]
-        };
+        let mut header = vec!["ID".to_owned()];
+        let mut columns = vec![members_query_ir.key_column];
 
-        let name_col = if let Some(ref col) = members_query_ir.name_column {
-           col.to_owned()
-        } else {
-            "".into()
-        };
+        if let Some(name_column) = members_query_ir.name_column {
+            header.push("Label".into());
+            columns.push(name_column);
+        }
+
+        header.extend_from_slice(properties);
+        columns.extend(property_columns);
 
-        let sql = format!("select distinct {}{}{} from {}",
-            members_query_ir.key_column,
-            if members_query_ir.name_column.is_some() { ", " } else { "" },
-            name_col,
+        let sql = format!("select distinct {} from {}{}",
+            columns.join(", "),
             members_query_ir.table_sql,
+            parent_filter,
         );
 
         Ok((sql, header))
@@ -244,10 +439,14 @@ impl Schema {
         &self,
         cube_name: &str,
         level_name: &LevelName,
-        locale: &str
+        locale: &str,
+        parent: Option<&Cut>,
+        properties: &[String],
     ) -> Result<(String, Vec<String>), Error> // Sql and then Header
     {
         let locales: Vec<String> = locale.split(",").map(|s| s.to_string()).collect();
+        let parent_filter = self.members_filter(cube_name, level_name, parent)?;
+        let property_columns = self.members_property_columns(cube_name, level_name, properties)?;
 
         let cube = self.cubes.iter()
             .find(|cube| &cube.name == &cube_name)
@@ -307,20 +506,218 @@ impl Schema {
                 }
             }
 
-            table.full_name()
+            table.join_chain_sql(&hier.joins.clone().unwrap_or_default())
         };
 
-        let sql = format!("select distinct {}{}{} from {} order by {}",
+        header.extend_from_slice(properties);
+        name_columns.extend(property_columns);
+
+        let sql = format!("select distinct {}{}{} from {}{} order by {}",
             key_column,
             if name_columns.len() > 0 { ", " } else { "" },
             name_columns.join(", "),
             table_sql,
+            parent_filter,
             key_column
         );
 
         Ok((sql, header))
     }
 
+    /// Generates SQL for the min, max, avg and count of a measure's column in the fact table,
+    /// optionally per member of the top level of the cube's time dimension. Table and column
+    /// names are quoted for the `dialect`. Cubes with required cuts can't be read whole, so
+    /// their stats are an error.
+    pub fn measure_stats_sql(
+        &self,
+        cube_name: &str,
+        measure_name: &str,
+        by_time: bool,
+        dialect: &dyn SqlDialect,
+        ) -> Result<(String, Vec<String>), Error> // Sql and then Header
+    {
+        let cube = self.get_cube_by_name(cube_name)?;
+        cube.check_required_cuts(&[])?;
+        let measure = cube.measures.iter()
+            .find(|mea| mea.name == measure_name)
+            .ok_or(format_err!("could not find measure {}", measure_name))?;
+
+        let measure_column = quote_column(dialect, &measure.column);
+        let fact_table = quote_qualified(dialect, &cube.table.full_name());
+
+        let stats = format!("min({0}), max({0}), avg({0}), count({0})", measure_column);
+        let stats_header = vec!["min".into(), "max".into(), "avg".into(), "count".into()];
+
+        let fact_where = cube.sql_where.as_ref()
+            .map(|sql_where| format!(" where ({})", sql_where))
+            .unwrap_or_default();
+
+        if !by_time {
+            let sql = format!("select {} from {}{}", stats, fact_table, fact_where);
+            return Ok((sql, stats_header));
+        }
+
+        let dim = cube.dimensions.iter()
+            .find(|dim| dim.dim_type == DimensionType::Time)
+            .ok_or(format_err!("cube {} has no time dimension", cube_name))?;
+        let hier = match dim.default_hierarchy {
+            Some(ref hier_name) => dim.hierarchies.iter().find(|hier| &hier.name == hier_name),
+            None => dim.hierarchies.first(),
+        }.ok_or(format_err!("could not find hierarchy for time dimension"))?;
+        let level = hier.levels.first()
+            .ok_or(format_err!("time hierarchy {} has no levels", hier.name))?;
+        let key_column = quote_column(dialect, &level.key_column);
+
+        let from_sql = if hier.table.is_none() && hier.inline_table.is_none() {
+            // time levels are columns of the fact table
+            format!("(select {} as stats_time, {} from {}{}) as f",
+                key_column,
+                measure_column,
+                fact_table,
+                fact_where,
+            )
+        } else {
+            let foreign_key = dim.foreign_key.as_ref()
+                .map(|foreign_key| quote_column(dialect, foreign_key))
+                .ok_or(format_err!("time dimension {} has no foreign key", dim.name))?;
+            let table_sql = if let Some(ref inline) = hier.inline_table {
+                format!("({})", inline.sql_string())
+            } else {
+                let table = hier.table.as_ref().expect("checked above");
+                format!("{}{}",
+                    quote_qualified(dialect, &table.full_name()),
+                    table.joins_sql(&hier.joins.clone().unwrap_or_default()),
+                )
+            };
+
+            format!("(select {}, {} from {}{}) as f inner join (select distinct {} as stats_pk, {} as stats_time from {}) as t on f.{} = t.stats_pk",
+                foreign_key,
+                measure_column,
+                fact_table,
+                fact_where,
+                quote_column(dialect, &hier.primary_key),
+                key_column,
+                table_sql,
+                foreign_key,
+            )
+        };
+
+        let sql = format!("select stats_time, {} from {} group by stats_time order by stats_time",
+            stats,
+            from_sql,
+        );
+
+        let mut header = vec![level.name.clone()];
+        header.extend(stats_header);
+
+        Ok((sql, header))
+    }
+
+    /// Columns of the properties of a level to list with its members, by property name.
+    fn members_property_columns(
+        &self,
+        cube_name: &str,
+        level_name: &LevelName,
+        properties: &[String],
+    ) -> Result<Vec<String>, Error>
+    {
+        if properties.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let level = self.get_cube_by_name(cube_name)?
+            .get_level(level_name)
+            .ok_or_else(|| format_err!("could not find level for level name"))?;
+
+        properties.iter()
+            .map(|property| {
+                level.properties.iter()
+                    .flatten()
+                    .find(|p| &p.name == property)
+                    .map(|p| p.column.clone())
+                    .ok_or_else(|| format_err!("could not find property {} for level {}", property, level_name))
+            })
+            .collect()
+    }
+
+    /// The `where` clause of a members query: the `parent` cut, and the cube's `sql_where` when
+    /// the level's columns are in the fact table.
+    fn members_filter(
+        &self,
+        cube_name: &str,
+        level_name: &LevelName,
+        parent: Option<&Cut>,
+    ) -> Result<String, Error>
+    {
+        let cube = self.get_cube_by_name(cube_name)?;
+
+        let mut predicates = vec![];
+
+        if let Some(parent) = parent {
+            predicates.push(self.members_parent_filter(cube, level_name, parent)?);
+        }
+
+        if let Some(ref sql_where) = cube.sql_where {
+            let in_fact_table = cube.dimensions.iter()
+                .filter(|dim| dim.name == level_name.dimension)
+                .flat_map(|dim| dim.hierarchies.iter())
+                .any(|hier| {
+                    hier.name == level_name.hierarchy &&
+                        hier.table.is_none() &&
+                        hier.inline_table.is_none()
+                });
+
+            if in_fact_table {
+                predicates.push(format!("({})", sql_where));
+            }
+        }
+
+        if predicates.is_empty() {
+            Ok("".into())
+        } else {
+            Ok(format!(" where {}", predicates.join(" and ")))
+        }
+    }
+
+    /// Generates the where clause restricting a members query to the children
+    /// of the members in a `parent` cut. The parent level must be above the
+    /// members level in the same hierarchy, since the filter is done on the
+    /// dimension table.
+    fn members_parent_filter(
+        &self,
+        cube: &Cube,
+        level_name: &LevelName,
+        parent: &Cut,
+    ) -> Result<String, Error>
+    {
+
+        let is_ancestor = cube.get_level_parents(level_name)?.iter()
+            .any(|lvl| lvl.name == parent.level_name.level);
+
+        if parent.level_name.dimension != level_name.dimension ||
+            parent.level_name.hierarchy != level_name.hierarchy ||
+            !is_ancestor
+        {
+            bail!("Parent level {} is not above level {} in the same hierarchy", parent.level_name, level_name);
+        }
+
+        let parent_level = cube.get_level(&parent.level_name)
+            .ok_or(format_err!("could not find level for parent level name"))?;
+
+        // members sql isn't generated by a dialect, so members get standard escaping, and
+        // backslashes (escapes in some dialects' literals) aren't allowed
+        if let Some(member) = parent.members.iter().find(|m| m.contains('\\')) {
+            bail!("Parent member {} can't contain a backslash", member);
+        }
+        let member_type = parent_level.key_type.clone().unwrap_or(MemberType::NonText);
+        let members = parent.members.iter()
+            .map(|m| member_literal(&parent_level.unpad_key(m).replace('\'', "''"), &member_type))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Ok(format!("{} in ({})", parent_level.key_column, members))
+    }
+
     /// Convert user parameters into required default member cuts based on cube definition.
     ///
     /// Given a cube and user supplied Query parameters and a boolean for negate mode, this function will:
@@ -373,7 +770,9 @@ impl Schema {
                 let dim_contains_drill = query.drilldowns.iter()
                     .any(|drill| dim.name == drill.0.dimension());
                 let dim_contains_cut = query.cuts.iter()
-                    .any(|c| dim.name == c.level_name.dimension());
+                    .any(|c| dim.name == c.level_name.dimension())
+                    || query.property_filters.iter()
+                        .any(|f| dim.name == f.property.level_name.dimension());
                 match negate {
                     false => !(dim_contains_drill || dim_contains_cut),
                     true => dim_contains_drill && !dim_contains_cut
@@ -398,13 +797,41 @@ impl Schema {
         // at the beginning of sql_query, (or maybe on cut parsing?), to make
         // clear that blank members will trigger default hierarchy behavior in sql generation
 
-        // First do checks, like making sure there's a measure, and that there's
-        // either a cut or drilldown
-        if query.measures.is_empty() && query.rca.is_none() {
-            return Err(format_err!("No measure found; please specify at least one"));
+        // the previous year is queried along with the query's own periods
+        if let Some(ref mea) = query.vs_previous_year {
+            return self.previous_year_sql_query(cube, query, mea, unique_header_map);
+        }
+
+        // drilldowns on All levels aren't grouped by
+        let all_drills: Vec<(usize, String)> = self.cubes.iter()
+            .find(|c| c.name == cube)
+            .map(|schema_cube| {
+                query.drilldowns.iter()
+                    .enumerate()
+                    .filter_map(|(i, drill)| schema_cube.get_all_member(&drill.0).map(|member| (i, member)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !all_drills.is_empty() {
+            return self.all_members_sql_query(cube, query, &all_drills, unique_header_map);
         }
-        if query.drilldowns.is_empty() && query.cuts.is_empty(){
-            return Err(format_err!("Either a drilldown or cut is required"));
+
+        // First do checks, like making sure there's a measure. Drilldowns are
+        // optional; without any, the measures are aggregated into one row.
+        // Distinct queries are the opposite: drilldowns only, which the sql generators
+        // select distinct when there are no measures.
+        if query.distinct {
+            let has_calculations = query.rca.is_some() || query.growth.is_some() || query.rate.is_some()
+                || query.top.is_some() || !query.filters.is_empty();
+            if !query.measures.is_empty() || has_calculations {
+                return Err(format_err!("Distinct queries can't have measures or calculations"));
+            }
+            if query.drilldowns.is_empty() {
+                return Err(format_err!("Distinct queries need at least one drilldown"));
+            }
+        } else if query.measures.is_empty() && query.rca.is_none() {
+            return Err(format_err!("No measure found; please specify at least one"));
         }
 
         // also check that properties have a matching drilldown
@@ -435,6 +862,8 @@ impl Schema {
             .find(|c| c.name == cube)
             .ok_or_else(|| format_err!("schema does not contain cube"))?;
 
+        schema_cube.check_required_cuts(&query.cuts)?;
+
         // Note that the marker for a default hierarchy cuts query is that there are no members
         let default_hierarchy_cuts_query: Result<Vec<_>, Error> = schema_cube.dimensions.iter()
             .filter(|dim| {
@@ -443,7 +872,9 @@ impl Schema {
                     .any(|drill| dim.name == drill.0.dimension());
 
                 let dim_contains_cut = query.cuts.iter()
-                    .any(|c| dim.name == c.level_name.dimension());
+                    .any(|c| dim.name == c.level_name.dimension())
+                    || query.property_filters.iter()
+                        .any(|f| dim.name == f.property.level_name.dimension());
 
                 !(dim_contains_drill || dim_contains_cut)
             })
@@ -483,6 +914,24 @@ impl Schema {
             }
         }
 
+        // share is calculated on the results, which rca and growth rearrange
+        if let Some(ref share) = query.share {
+            if query.rca.is_some() || query.growth.is_some() {
+                bail!("Share cannot be combined with rca or growth");
+            }
+            if !query.drilldowns.contains(&share.drill) {
+                bail!("Share drilldown {} is not in drilldowns", share.drill);
+            }
+            if !query.measures.contains(&share.mea) {
+                bail!("Share measure {} is not in measures", share.mea);
+            }
+        }
+
+        // rca, growth and rate query their drilldowns again, with the name columns
+        if query.show == ShowColumns::Ids && (query.rca.is_some() || query.growth.is_some() || query.rate.is_some()) {
+            bail!("Showing only ids cannot be combined with rca, growth or rate");
+        }
+
         // for rca, disallow cuts on the second drilldown for now, until better system
         // is figured out.
         // There is internal filtering of cuts internally also, which should follow the
@@ -497,9 +946,17 @@ impl Schema {
             }
         }
 
+        if let Some(sample) = query.sample {
+            if !(sample > 0.0 && sample <= 1.0) {
+                bail!("Sample must be between 0 and 1, got {}", sample);
+            }
+        }
+
         // now get the database metadata
-        let table = self.cube_table(&cube)
+        let mut table = self.cube_table(&cube)
             .ok_or(format_err!("No table found for cube {}", cube))?;
+        table.sample = query.sample;
+        let distributed = schema_cube.distributed.clone();
 
         let mut cut_cols = self.cube_cut_cols(&cube, &query.cuts)
             .map_err(|err| format_err!("Error getting cut cols: {}", err))?;
@@ -509,6 +966,10 @@ impl Schema {
 
         cut_cols.extend_from_slice(&default_hierarchy_cut_cols);
 
+        let property_filter_cols = self.cube_property_filter_cols(&cube, &query.property_filters)
+            .map_err(|err| format_err!("Error getting property filter cols: {}", err))?;
+        cut_cols.extend(property_filter_cols);
+
         let default_member_cuts_query = self.build_default_member_cuts(schema_cube, query, false)?;
         let default_member_cut_cols = self.cube_cut_cols(&cube, &default_member_cuts_query)
             .map_err(|err| format_err!("Error creating cuts for default member: {}", err))?;
@@ -522,10 +983,11 @@ impl Schema {
         }
 
 
-        let drill_cols = self.cube_drill_cols(&cube, &query.drilldowns, &query.properties, &query.captions, query.parents)
+        let mut drill_cols = self.cube_drill_cols(&cube, &query.drilldowns, &query.properties, &query.captions, query.parents, &query.parent_levels)
             .map_err(|err| format_err!("Error getting drill cols: {}", err))?;
+        check_drill_aliases(&query.drilldowns, &drill_cols)?;
 
-        let mea_cols = self.cube_mea_cols(&cube, &query.measures)
+        let mea_cols = self.cube_mea_cols(&cube, &query.measures, query.treat_null_as.as_ref())
             .map_err(|err| format_err!("Error getting mea cols: {}", err))?;
 
         // special case for "hidden dimension" used for grouped median. This is where there
@@ -547,7 +1009,7 @@ impl Schema {
             .collect::<Result<_,_>>()
             .map_err(|err| format_err!("Error parsing hidden grouping drill level: {}", err))?;
 
-        let hidden_drill_cols: Vec<_> = self.cube_drill_cols(&cube, &hidden_dims, &[], &[], false)
+        let hidden_drill_cols: Vec<_> = self.cube_drill_cols(&cube, &hidden_dims, &[], &[], false, &[])
             .map_err(|err| format_err!("Error getting hidden grouping drill cols: {}", err))?
             .iter()
             .map(|dim_col| HiddenDrilldownSql { drilldown_sql: dim_col.clone() })
@@ -623,24 +1085,34 @@ impl Schema {
             .collect::<Result<Vec<_>,_>>();
         let filters = filters?;
 
-        let sort = if let Some(ref s) = query.sort {
-            // sort column needs to be named by alias
-            let sort_column = mea_or_calc!(&s.measure, query)?;
-            Some(SortSql {
-                direction: s.direction.clone(),
-                column: sort_column,
+        // Row filters constrain the measure's column in the fact table, so they can't be on
+        // calculations
+        let row_filters = query.row_filters.iter()
+            .map(|filter| {
+                let measure = match filter.by_mea_or_calc {
+                    MeaOrCalc::Mea(ref measure) => measure,
+                    MeaOrCalc::Calc(_) => bail!("Row filters can only be on measures, not calculations"),
+                };
+                let mea = schema_cube.measures.iter()
+                    .find(|m| m.name == measure.0)
+                    .ok_or_else(|| format_err!("could not find measure for {}", measure.0))?;
+
+                Ok(RowFilterSql {
+                    column: mea.column.clone(),
+                    constraint: filter.constraint.clone(),
+                    operator: filter.operator.clone(),
+                    constraint2: filter.constraint2.clone(),
+                })
             })
-        } else {
-            None
-        };
+            .collect::<Result<Vec<_>, Error>>()?;
 
         // TODO check that no overlapping dim or mea cols between rca and others
         let rca = if let Some(ref rca) = query.rca {
-            let drill_1 = self.cube_drill_cols(&cube, &[rca.drill_1.clone()], &query.properties, &query.captions, query.parents)?;
-            let drill_2 = self.cube_drill_cols(&cube, &[rca.drill_2.clone()], &query.properties, &query.captions, query.parents)?;
+            let drill_1 = self.cube_drill_cols(&cube, &[rca.drill_1.clone()], &query.properties, &query.captions, query.parents, &query.parent_levels)?;
+            let drill_2 = self.cube_drill_cols(&cube, &[rca.drill_2.clone()], &query.properties, &query.captions, query.parents, &query.parent_levels)?;
 
-            let mea = self.cube_mea_cols(&cube, &[rca.mea.clone()])?
-                .get(0)
+            let mea = self.cube_mea_cols(&cube, &[rca.mea.clone()], query.treat_null_as.as_ref())?
+                .first()
                 .ok_or(format_err!("no measure found for rca"))?
                 .clone();
 
@@ -655,8 +1127,8 @@ impl Schema {
         };
 
         let growth = if let Some(ref growth) = query.growth {
-            let time_drill = self.cube_drill_cols(&cube, &[growth.time_drill.clone()], &query.properties, &query.captions, query.parents)?
-                .get(0)
+            let time_drill = self.cube_drill_cols(&cube, &[growth.time_drill.clone()], &query.properties, &query.captions, query.parents, &query.parent_levels)?
+                .first()
                 .ok_or(format_err!("no measure found for growth"))?
                 .clone();
 
@@ -694,28 +1166,58 @@ impl Schema {
 
             let drilldown_sql = self.cube_drill_cols(
                 &cube, &[Drilldown(rate.level_name.clone())],
-                &query.properties, &query.captions, query.parents
+                &query.properties, &query.captions, query.parents, &query.parent_levels
             )?;
 
+            let rate_level = schema_cube.get_level(&rate.level_name)
+                .ok_or_else(|| format_err!("could not find level for rate {}", rate.level_name))?;
+            let member_type = rate_level.key_type.clone()
+                .unwrap_or(MemberType::NonText);
+
             Some(RateSql {
                 drilldown_sql: drilldown_sql[0].clone(),
-                members: rate.values.clone(),
+                members: rate.values.iter().map(|v| rate_level.unpad_key(v)).collect(),
+                member_type,
             })
         } else {
             None
         };
 
         // getting headers, not for sql but needed for formatting
-        let mut drill_headers = self.cube_drill_headers(&cube, &query.drilldowns, &query.properties, query.parents, unique_header_map)
+        let mut drill_headers = self.cube_drill_headers(&cube, &query.drilldowns, &query.properties, query.parents, &query.parent_levels, unique_header_map)
             .map_err(|err| format_err!("Error getting drill headers: {}", err))?;
 
         let mut mea_headers = self.cube_mea_headers(&cube, &query.measures)
             .map_err(|err| format_err!("Error getting mea headers: {}", err))?;
 
+        // name columns aren't queried at all; labels only are dropped from the results,
+        // since members are grouped by their keys
+        if query.show == ShowColumns::Ids {
+            drop_name_columns(&mut drill_cols, &mut drill_headers);
+        }
+
+        // sort columns need to be named by alias; drilldown columns are found by header
+        let sort = match query.sort {
+            Some(ref s) => {
+                s.keys.iter()
+                    .map(|key| sort_key_sql(key, query, &drill_cols, &drill_headers))
+                    .collect::<Result<Vec<_>, _>>()?
+            },
+            // keys of the cube's default sort on columns not in the query are skipped
+            None => match schema_cube.default_sort {
+                Some(ref s) => {
+                    s.parse::<SortQuery>()?.keys.iter()
+                        .filter_map(|key| sort_key_sql(key, query, &drill_cols, &drill_headers).ok())
+                        .collect()
+                },
+                None => vec![],
+            },
+        };
+
         // rca mea will always be first, so just put
         // in `Mea RCA` second
         if let Some(ref rca) = query.rca {
-            let rca_drill_headers = self.cube_drill_headers(&cube, &[rca.drill_1.clone(), rca.drill_2.clone()], &query.properties, query.parents, unique_header_map)
+            let rca_drill_headers = self.cube_drill_headers(&cube, &[rca.drill_1.clone(), rca.drill_2.clone()], &query.properties, query.parents, &query.parent_levels, unique_header_map)
                 .map_err(|err| format_err!("Error getting rca drill headers: {}", err))?;
 
             drill_headers.extend_from_slice(&rca_drill_headers);
@@ -727,6 +1229,16 @@ impl Schema {
             mea_headers.insert(0, format!("{} RCA", rca.mea.0.clone()));
         }
 
+        // rca's drilldowns come after the query's
+        let mut key_widths = match (&query.rca, &rca) {
+            (Some(rca_query), Some(rca_sql)) => {
+                let drills = [&query.drilldowns[..], &[rca_query.drill_1.clone(), rca_query.drill_2.clone()]].concat();
+                let cols = [&drill_cols[..], &rca_sql.drill_1[..], &rca_sql.drill_2[..]].concat();
+                key_width_idxs(schema_cube, &drills, &cols)
+            },
+            _ => key_width_idxs(schema_cube, &query.drilldowns, &drill_cols),
+        };
+
         // Be careful with other calculations.
         // TODO figure out a more composable system.
         let mut headers = if let Some(ref growth) = query.growth {
@@ -741,7 +1253,7 @@ impl Schema {
             mea_headers.push(format!("{} Growth Value", growth.mea.0));
 
             // swapping around drilldown headers. Move time to back
-            let time_headers = self.cube_drill_headers(&cube, &[growth.time_drill.clone()], &[], query.parents, unique_header_map)
+            let time_headers = self.cube_drill_headers(&cube, &[growth.time_drill.clone()], &[], query.parents, &query.parent_levels, unique_header_map)
                 .map_err(|err| format_err!("Error getting time drill headers for Growth: {}", err))?;
 
             let time_header_idxs: Result<Vec<_>,_> = time_headers.iter()
@@ -753,6 +1265,15 @@ impl Schema {
                 .collect();
             let time_header_idxs = time_header_idxs?;
 
+            // the time columns move to the back of the drilldowns, and the padded keys with them
+            let drill_count = drill_headers.len();
+            for (idx, _) in key_widths.iter_mut() {
+                *idx = match time_header_idxs.iter().position(|i| i == idx) {
+                    Some(k) => drill_count - time_header_idxs.len() + k,
+                    None => *idx - time_header_idxs.iter().filter(|i| *i < idx).count(),
+                };
+            }
+
             // TODO figure out a better way to move headers
             let mut temp_time_headers = vec![];
             for idx in time_header_idxs.iter().rev() {
@@ -766,11 +1287,67 @@ impl Schema {
             [&drill_headers[..], &mea_headers[..]].concat()
         };
 
-        // Rate calculations always come last
+        // Rate calculations always come last, except for share, which is calculated on
+        // the results
         if query.rate.is_some() {
             headers.push("Rate".to_string());
         }
 
+        let share = match query.share {
+            Some(ref share) => {
+                let mut drill_count = 0;
+                let mut group_idxs = vec![];
+                for (drill, drill_sql) in query.drilldowns.iter().zip(&drill_cols) {
+                    let col_count = drill_sql.col_count();
+                    if *drill != share.drill {
+                        group_idxs.extend(drill_count..drill_count + col_count);
+                    }
+                    drill_count += col_count;
+                }
+
+                let mea_idx = query.measures.iter()
+                    .position(|mea| *mea == share.mea)
+                    .ok_or(format_err!("measure for Share must be in measures"))?;
+
+                headers.push(format!("{} Share", share.mea.0));
+
+                Some(ShareColumns {
+                    group_idxs,
+                    mea_idx: drill_count + mea_idx,
+                })
+            },
+            None => None,
+        };
+
+        // rca and growth move measure columns around
+        let null_zero_idxs = if query.rca.is_none() && query.growth.is_none() {
+            let drill_count: usize = drill_cols.iter().map(|d| d.col_count()).sum();
+
+            let null_zeros: Vec<bool> = query.measures.iter()
+                .map(|mea| {
+                    let policy = schema_cube.measures.iter()
+                        .find(|m| m.name == mea.0)
+                        .map(|m| &m.treat_null_as);
+                    query.treat_null_as.as_ref().or(policy) == Some(&NullPolicy::Zero)
+                })
+                .collect();
+
+            let mut null_zero_idxs: Vec<usize> = null_zeros.iter()
+                .enumerate()
+                .filter(|(_, null_zero)| **null_zero)
+                .map(|(i, _)| drill_count + i)
+                .collect();
+
+            // the rate column, after the measure, is a rate of the query's only measure
+            if query.rate.is_some() && null_zeros.first() == Some(&true) {
+                null_zero_idxs.push(drill_count + query.measures.len());
+            }
+
+            null_zero_idxs
+        } else {
+            vec![]
+        };
+
         Ok((
             QueryIr {
                 table,
@@ -779,65 +1356,283 @@ impl Schema {
                 meas: mea_cols,
                 hidden_drills: hidden_drill_cols,
                 filters,
+                row_filters,
                 top,
                 top_where,
                 sort,
                 limit,
                 rca,
                 growth,
+                share,
+                previous_year: None,
                 rate,
                 sparse: query.sparse,
+                priority: query.priority.clone(),
+                distributed,
+                null_zero_idxs,
+                key_widths,
+                all_members: vec![],
             },
             headers,
         ))
     }
-}
 
-impl Schema {
-    fn cube_table(&self, cube_name: &str) -> Option<TableSql> {
-        self.cubes.iter()
-            .find(|cube| &cube.name == &cube_name)
-            .map(|cube| {
-                TableSql {
-                    name: cube.table.name.clone(),
-                    primary_key: cube.table.primary_key.clone(),
-                }
-            })
-    }
+    /// `sql_query` for a query with drilldowns on All levels, at `all_drills` in its
+    /// drilldowns with their All members. The query runs without them, and their columns
+    /// are added to the results. Their hierarchies are cut without members instead, so that
+    /// the All member totals the whole hierarchy, rather than the default member (or the
+    /// default hierarchy) the dimension would otherwise be cut on.
+    fn all_members_sql_query(
+        &self,
+        cube: &str,
+        query: &Query,
+        all_drills: &[(usize, String)],
+        unique_header_map: Option<&HashMap<String, String>>,
+        ) -> Result<(QueryIr, Vec<String>), Error>
+    {
+        // rca and growth move the drilldown columns around
+        if query.rca.is_some() || query.growth.is_some() {
+            bail!("Drilldowns on an All level can't be combined with rca or growth");
+        }
 
-    fn cube_cut_cols(&self, cube_name: &str, cuts: &[Cut]) -> Result<Vec<CutSql>, Error> {
-        let cube = self.cubes.iter()
-            .find(|cube| &cube.name == &cube_name)
-            .ok_or(format_err!("Could not find cube"))?;
+        let schema_cube = self.cubes.iter()
+            .find(|c| c.name == cube)
+            .ok_or_else(|| format_err!("schema does not contain cube"))?;
 
-        let mut res = vec![];
+        // the cuts added below don't count as required cuts
+        schema_cube.check_required_cuts(&query.cuts)?;
 
-        for cut in cuts {
-            let dim = cube.dimensions.iter()
-                .find(|dim| dim.name == cut.level_name.dimension)
-                .ok_or(format_err!("could not find dimension for cut {}", cut.level_name))?;
-            let hier = dim.hierarchies.iter()
-                .find(|hier| hier.name == cut.level_name.hierarchy)
-                .ok_or(format_err!("could not find hierarchy for cut {}", cut.level_name))?;
-            let level = hier.levels.iter()
-                .find(|lvl| lvl.name == cut.level_name.level)
-                .ok_or(format_err!("could not find level for cut {}", cut.level_name))?;
+        let mut drill_query = query.clone();
+        drill_query.drilldowns = query.drilldowns.iter()
+            .enumerate()
+            .filter(|(i, _)| !all_drills.iter().any(|(all_i, _)| all_i == i))
+            .map(|(_, drill)| drill.clone())
+            .collect();
 
-            // No table (means inline table) will replace with fact table
-            let table = hier.table
-                .clone()
-                .unwrap_or(cube.table.clone());
+        for (drill_idx, _) in all_drills {
+            let level_name = &query.drilldowns[*drill_idx].0;
 
-            // primary key is currently required in hierarchy. because inline dim is not yet
-            // allowed
-            let primary_key = hier.primary_key.clone();
+            let dim_is_cut = drill_query.cuts.iter()
+                .any(|cut| cut.level_name.dimension == level_name.dimension);
+            if dim_is_cut {
+                continue;
+            }
 
-            let foreign_key = dim.foreign_key
-                .clone()
-                .ok_or(format_err!("No foreign key; it's required for now (until inline dim implemented)"))?;
+            let lowest_level = schema_cube.get_hierarchy(level_name)
+                .and_then(|hier| hier.levels.last().map(|level| level.name.clone()))
+                .ok_or_else(|| format_err!("Could not find a level in hierarchy of {}", level_name))?;
+
+            drill_query.cuts.push(Cut::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                lowest_level,
+                vec![],
+                Mask::Include,
+                false,
+            ));
+        }
 
-            let column = if cut.for_match {
-                level.name_column.clone().unwrap_or(level.key_column.clone())
+        let (mut query_ir, mut headers) = self.sql_query(cube, &drill_query, unique_header_map)?;
+
+        let mut drill_col_counts = query_ir.drills.iter().map(|drill| drill.col_count());
+        let mut idx = 0;
+
+        for (drill_idx, drill) in query.drilldowns.iter().enumerate() {
+            let member = match all_drills.iter().find(|(all_i, _)| *all_i == drill_idx) {
+                Some((_, member)) => member,
+                None => {
+                    idx += drill_col_counts.next().unwrap_or(0);
+                    continue;
+                },
+            };
+
+            // the indexes of the columns after it move over
+            let shift = |i: &mut usize| if *i >= idx { *i += 1 };
+            query_ir.null_zero_idxs.iter_mut().for_each(&shift);
+            query_ir.key_widths.iter_mut().for_each(|(i, _)| shift(i));
+            if let Some(ref mut share) = query_ir.share {
+                share.group_idxs.iter_mut().for_each(&shift);
+                shift(&mut share.mea_idx);
+            }
+
+            // All levels are usually all named `(All)`, so clashes are prefixed with
+            // their hierarchy, as for other levels
+            let header = if headers.contains(&drill.0.level) {
+                format!("{} {}", drill.0.hierarchy, drill.0.level)
+            } else {
+                drill.0.level.clone()
+            };
+            headers.insert(idx, header);
+
+            query_ir.all_members.push(AllMemberColumn {
+                idx,
+                drill_idx,
+                member: member.clone(),
+            });
+            idx += 1;
+        }
+
+        Ok((query_ir, headers))
+    }
+
+    /// `sql_query` for a query comparing `mea` with the same period of the previous year,
+    /// on a drilldown on a month or quarter level. Cuts on the year, quarter or month
+    /// levels of its hierarchy also get the periods of the previous year, to be compared to,
+    /// which are dropped afterwards, either in the sql (`SqlDialect::previous_year_sql`) or
+    /// from the results (`DataFrame::add_previous_year`).
+    fn previous_year_sql_query(
+        &self,
+        cube: &str,
+        query: &Query,
+        mea: &Measure,
+        unique_header_map: Option<&HashMap<String, String>>,
+        ) -> Result<(QueryIr, Vec<String>), Error>
+    {
+        // the comparison is calculated on the results, which rca and growth rearrange
+        if query.rca.is_some() || query.growth.is_some() || query.share.is_some() {
+            bail!("Previous year comparisons can't be combined with rca, growth or share");
+        }
+        if !query.measures.contains(mea) {
+            bail!("Previous year measure {} is not in measures", mea);
+        }
+        // the rows of the previous years are dropped after the query, so a limit in the sql
+        // would count them
+        if query.limit.is_some() || query.top.is_some() || query.top_where.is_some() {
+            bail!("Previous year comparisons can't be combined with limit or top");
+        }
+
+        let schema_cube = self.cubes.iter()
+            .find(|c| c.name == cube)
+            .ok_or_else(|| format_err!("schema does not contain cube"))?;
+        let is_time_dim = |level_name: &LevelName| schema_cube.dimensions.iter()
+            .any(|dim| dim.name == level_name.dimension && dim.dim_type == DimensionType::Time);
+
+        let (time_drill_idx, period_level) = query.drilldowns.iter()
+            .enumerate()
+            .filter(|(_, drill)| is_time_dim(&drill.0))
+            .filter_map(|(i, drill)| PeriodLevel::from_level_name(&drill.0.level).map(|level| (i, level)))
+            .find(|(_, level)| *level != PeriodLevel::Year)
+            .ok_or_else(|| format_err!("Previous year comparisons need a drilldown on a Month or Quarter level of a time dimension"))?;
+        let time_level = &query.drilldowns[time_drill_idx].0;
+
+        let mut compare_query = query.clone();
+        compare_query.vs_previous_year = None;
+
+        let mut period_cuts = vec![];
+
+        for cut in &mut compare_query.cuts {
+            let is_time_cut = cut.level_name.dimension == time_level.dimension
+                && cut.level_name.hierarchy == time_level.hierarchy
+                && cut.mask == Mask::Include
+                && !cut.for_match;
+
+            let cut_level = match PeriodLevel::from_level_name(&cut.level_name.level) {
+                Some(cut_level) if is_time_cut => cut_level,
+                _ => continue,
+            };
+            if cut_level > period_level {
+                bail!("Previous year comparisons by {} can't be combined with a cut on {}", period_level, cut.level_name);
+            }
+
+            let periods = cut.members.iter()
+                .map(|member| {
+                    member.parse::<i64>()
+                        .ok()
+                        .filter(|key| cut_level.is_key(*key))
+                        .ok_or_else(|| format_err!("Cut member {} is not a {} period ({})", member, cut_level, cut_level.key_format()))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            cut.members.extend(periods.iter().map(|period| (period - cut_level.year_offset()).to_string()));
+            period_cuts.push(PeriodCut { level: cut_level, periods });
+        }
+
+        let (mut query_ir, mut headers) = self.sql_query(cube, &compare_query, unique_header_map)?;
+
+        // the key of the level comes after its parents' columns
+        let columns = query_ir.calculation_columns(Some(time_drill_idx));
+        let drill_sql_idx = time_drill_idx - query_ir.all_members.iter()
+            .filter(|all| all.drill_idx < time_drill_idx)
+            .count();
+        let key_offset: usize = query_ir.drills.get(drill_sql_idx)
+            .ok_or_else(|| format_err!("no drilldown found for previous year time level"))?
+            .level_columns.iter()
+            .rev()
+            .skip(1)
+            .map(|level_col| if level_col.name_column.is_some() { 2 } else { 1 })
+            .sum();
+        let time_idx = columns.time_idxs.first()
+            .ok_or_else(|| format_err!("no columns found for previous year time level"))?
+            + key_offset;
+
+        let mea_idx = query.measures.iter()
+            .position(|m| m == mea)
+            .map(|i| columns.mea_idxs[i])
+            .ok_or(format_err!("measure for Previous Year must be in measures"))?;
+
+        headers.push(format!("{} Previous Year", mea.0));
+        headers.push(format!("{} Previous Year Difference", mea.0));
+        headers.push(format!("{} Previous Year Growth", mea.0));
+
+        query_ir.previous_year = Some(PreviousYearColumns {
+            group_idxs: (0..columns.drill_count).filter(|i| !columns.time_idxs.contains(i)).collect(),
+            time_idx,
+            period_level,
+            mea_idx,
+            period_cuts,
+        });
+
+        Ok((query_ir, headers))
+    }
+}
+
+impl Schema {
+    fn cube_table(&self, cube_name: &str) -> Option<TableSql> {
+        self.cubes.iter()
+            .find(|cube| &cube.name == &cube_name)
+            .map(|cube| {
+                TableSql {
+                    name: cube.table.name.clone(),
+                    primary_key: cube.table.primary_key.clone(),
+                    sample: None,
+                    sql_where: cube.sql_where.clone(),
+                }
+            })
+    }
+
+    fn cube_cut_cols(&self, cube_name: &str, cuts: &[Cut]) -> Result<Vec<CutSql>, Error> {
+        let cube = self.cubes.iter()
+            .find(|cube| &cube.name == &cube_name)
+            .ok_or(format_err!("Could not find cube"))?;
+
+        let mut res = vec![];
+
+        for cut in cuts {
+            let dim = cube.dimensions.iter()
+                .find(|dim| dim.name == cut.level_name.dimension)
+                .ok_or(format_err!("could not find dimension for cut {}", cut.level_name))?;
+            let hier = dim.hierarchies.iter()
+                .find(|hier| hier.name == cut.level_name.hierarchy)
+                .ok_or(format_err!("could not find hierarchy for cut {}", cut.level_name))?;
+            let level = hier.levels.iter()
+                .find(|lvl| lvl.name == cut.level_name.level)
+                .ok_or(format_err!("could not find level for cut {}", cut.level_name))?;
+
+            // No table (means inline table) will replace with fact table
+            let table = hier.table
+                .clone()
+                .unwrap_or(cube.table.clone());
+
+            // primary key is currently required in hierarchy. because inline dim is not yet
+            // allowed
+            let primary_key = hier.primary_key.clone();
+
+            let foreign_key = dim.foreign_key
+                .clone()
+                .ok_or(format_err!("No foreign key; it's required for now (until inline dim implemented)"))?;
+
+            let column = if cut.for_match {
+                level.name_column.clone().unwrap_or(level.key_column.clone())
             } else {
                 level.key_column.clone()
             };
@@ -848,22 +1643,68 @@ impl Schema {
                 level.key_type.clone().unwrap_or(MemberType::NonText)
             };
 
+            // Zero-padded members are cut on the numeric keys
+            let members = if cut.for_match {
+                cut.members.clone()
+            } else {
+                cut.members.iter().map(|m| level.unpad_key(m)).collect()
+            };
+
             res.push(CutSql {
                 table,
                 primary_key,
                 foreign_key,
                 column,
                 member_type,
-                members: cut.members.clone(),
+                members,
                 mask: cut.mask.clone(),
                 for_match: cut.for_match,
-                inline_table: hier.inline_table.clone(),
+                inline_table: hier.inline_table.clone().or_else(|| hier.cached_table.clone()),
+                joins: hier.joins.clone().unwrap_or_default(),
             });
         }
 
         Ok(res)
     }
 
+    /// Property filters are cuts on the property column of the level's table.
+    fn cube_property_filter_cols(&self, cube_name: &str, filters: &[PropertyFilter]) -> Result<Vec<CutSql>, Error> {
+        let cube = self.cubes.iter()
+            .find(|cube| cube.name == cube_name)
+            .ok_or_else(|| format_err!("Could not find cube"))?;
+
+        let mut res = vec![];
+
+        for filter in filters {
+            let level_name = &filter.property.level_name;
+            let column = cube.get_level(level_name)
+                .ok_or_else(|| format_err!("could not find level for property filter {}", filter.property))?
+                .properties
+                .unwrap_or_default()
+                .into_iter()
+                .find(|p| p.name == filter.property.property)
+                .map(|p| p.column)
+                .ok_or_else(|| format_err!("could not find property for property filter {}", filter.property))?;
+
+            let level_cut = Cut::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                level_name.level.clone(),
+                vec![],
+                Mask::Include,
+                false,
+            );
+            let mut cut_sql = self.cube_cut_cols(cube_name, &[level_cut])?.remove(0);
+
+            cut_sql.column = column;
+            cut_sql.members = filter.values.clone();
+            cut_sql.member_type = MemberType::Text;
+            res.push(cut_sql);
+        }
+
+        Ok(res)
+    }
+
     // TODO as currently written, properties that don't get picked up by a drilldown
     // will just silently fail.
     fn cube_drill_cols(
@@ -873,6 +1714,7 @@ impl Schema {
         properties: &[Property],
         captions: &[Property],
         parents: bool,
+        parent_levels: &[LevelName],
         ) -> Result<Vec<DrilldownSql>, Error>
     {
         let cube = self.cubes.iter()
@@ -923,7 +1765,10 @@ impl Schema {
             // let through parent captions only if parent == true
             let captions_filtered = if parents {
                 Box::new(captions.iter()
-                    .filter(|p| p.level_name.dimension == drill.0.dimension)
+                    .filter(|p| {
+                        p.level_name.dimension == drill.0.dimension &&
+                        p.level_name.hierarchy == drill.0.hierarchy
+                    })
                 ) as Box<dyn Iterator<Item=&Property>>
             } else {
                 Box::new(captions.iter()
@@ -977,7 +1822,7 @@ impl Schema {
             let mut level_columns = vec![];
 
             if parents {
-                for i in 0..=level_idx {
+                for i in parent_level_idxs(&drill.0, levels, level_idx, parent_levels) {
                     // caption replaces name_column with the col from property.
                     let caption = if let Some(caption_col) = caption_cols.get(&levels[i].name) {
                         Some(caption_col.clone())
@@ -1003,7 +1848,7 @@ impl Schema {
                 });
             }
 
-            let alias_postfix = dim.name.replace(" ", "_");
+            let alias_postfix = drill_alias_postfix(dim, &hier.name);
 
             res.push(DrilldownSql {
                 alias_postfix,
@@ -1012,14 +1857,23 @@ impl Schema {
                 foreign_key,
                 level_columns,
                 property_columns,
-                inline_table: hier.inline_table.clone()
+                inline_table: hier.inline_table.clone().or_else(|| hier.cached_table.clone()),
+                joins: hier.joins.clone().unwrap_or_default(),
             });
         }
 
         Ok(res)
     }
 
-    fn cube_mea_cols(&self, cube_name: &str, meas: &[Measure]) -> Result<Vec<MeasureSql>, Error> {
+    /// Measures whose nulls are zeros (by `treat_null_as`, or their own policy) are
+    /// aggregated with their nulls replaced by zeros.
+    fn cube_mea_cols(
+        &self,
+        cube_name: &str,
+        meas: &[Measure],
+        treat_null_as: Option<&NullPolicy>,
+        ) -> Result<Vec<MeasureSql>, Error>
+    {
         let cube = self.cubes.iter()
             .find(|cube| &cube.name == &cube_name)
             .ok_or(format_err!("Could not find cube"))?;
@@ -1031,9 +1885,15 @@ impl Schema {
                 .find(|m| m.name == measure.0)
                 .ok_or(format_err!("could not find measure for {}", measure.0))?;
 
+            let null_zero = match treat_null_as.unwrap_or(&mea.treat_null_as) {
+                NullPolicy::Zero => true,
+                NullPolicy::Skip => false,
+            };
+
             res.push(MeasureSql {
                 column: mea.column.clone(),
                 aggregator: mea.aggregator.clone(),
+                null_zero,
             });
         }
 
@@ -1049,6 +1909,7 @@ impl Schema {
         drills: &[Drilldown],
         properties: &[Property],
         parents: bool,
+        parent_levels: &[LevelName],
         unique_header_map: Option<&HashMap<String, String>>,
         ) -> Result<Vec<String>, Error>
     {
@@ -1059,6 +1920,16 @@ impl Schema {
         let mut level_headers = vec![];
         let mut unique_level_headers = vec![];
 
+        // Dimensions drilled down on through more than one hierarchy
+        let multi_hier_dims: HashSet<&str> = drills.iter()
+            .filter(|d| {
+                drills.iter().any(|other| {
+                    other.0.dimension == d.0.dimension && other.0.hierarchy != d.0.hierarchy
+                })
+            })
+            .map(|d| d.0.dimension.as_str())
+            .collect();
+
         for drill in drills {
             let dim = cube.dimensions.iter()
                 .find(|dim| dim.name == drill.0.dimension)
@@ -1068,6 +1939,17 @@ impl Schema {
                 .ok_or(format_err!("could not find hierarchy for drill"))?;
             let levels = &hier.levels;
 
+            // when headers clash, and there's no unique name for the level in the schema,
+            // levels of a dimension drilled down on through several hierarchies are
+            // prefixed with their hierarchy
+            let unique_default = |header: &str| {
+                if multi_hier_dims.contains(drill.0.dimension.as_str()) {
+                    format!("{} {}", hier.name, header)
+                } else {
+                    header.to_owned()
+                }
+            };
+
             // logic for getting level names.
             // if parents = true, then get all columns down to level
             // if not,then just level name
@@ -1079,7 +1961,7 @@ impl Schema {
             // In this section, need to watch out for whether there's both a
             // key column and a name column and add ID to the first if necessary
             if parents {
-                for i in 0..=level_idx {
+                for i in parent_level_idxs(&drill.0, &hier.levels, level_idx, parent_levels) {
                     let level_str = format!("{}.{}.{}", dim.name, hier.name, levels[i].name).to_string();
 
                     if levels[i].name_column.is_some() {
@@ -1091,10 +1973,10 @@ impl Schema {
                             Some(unique_header_map) => {
                                 match unique_header_map.get(&level_str) {
                                     Some(unique_header) => unique_level_headers.push(unique_header.clone() + " ID"),
-                                    None => unique_level_headers.push(default_header_name.clone())
+                                    None => unique_level_headers.push(unique_default(&default_header_name))
                                 }
                             },
-                            None => unique_level_headers.push(default_header_name.clone())
+                            None => unique_level_headers.push(unique_default(&default_header_name))
                         }
                     }
 
@@ -1106,10 +1988,10 @@ impl Schema {
                         Some(unique_header_map) => {
                             match unique_header_map.get(&level_str) {
                                 Some(unique_header) => unique_level_headers.push(unique_header.clone()),
-                                None => unique_level_headers.push(default_header_name.clone())
+                                None => unique_level_headers.push(unique_default(&default_header_name))
                             }
                         },
-                        None => unique_level_headers.push(default_header_name.clone())
+                        None => unique_level_headers.push(unique_default(&default_header_name))
                     }
                 }
             } else {
@@ -1124,10 +2006,10 @@ impl Schema {
                         Some(unique_header_map) => {
                             match unique_header_map.get(&level_str) {
                                 Some(unique_header) => unique_level_headers.push(unique_header.clone() + " ID"),
-                                None => unique_level_headers.push(default_header_name.clone())
+                                None => unique_level_headers.push(unique_default(&default_header_name))
                             }
                         },
-                        None => unique_level_headers.push(default_header_name.clone())
+                        None => unique_level_headers.push(unique_default(&default_header_name))
                     }
                 }
 
@@ -1139,10 +2021,10 @@ impl Schema {
                     Some(unique_header_map) => {
                         match unique_header_map.get(&level_str) {
                             Some(unique_header) => unique_level_headers.push(unique_header.clone()),
-                            None => unique_level_headers.push(default_header_name.clone())
+                            None => unique_level_headers.push(unique_default(&default_header_name))
                         }
                     },
-                    None => unique_level_headers.push(default_header_name.clone())
+                    None => unique_level_headers.push(unique_default(&default_header_name))
                 }
             }
 
@@ -1171,7 +2053,8 @@ impl Schema {
                 .collect();
             let property_columns = property_columns?;
 
-            level_headers.extend(property_columns);
+            level_headers.extend(property_columns.clone());
+            unique_level_headers.extend(property_columns);
         }
 
         let hash_set: HashSet<String> = level_headers.clone().into_iter().collect();
@@ -1223,7 +2106,7 @@ impl Schema {
         let table_sql = if let Some(ref inline) = hier.inline_table {
             format!("({})", inline.sql_string())
         } else {
-            table.full_name()
+            table.join_chain_sql(&hier.joins.clone().unwrap_or_default())
         };
 
         let key_column = level.key_column.clone();
@@ -1251,22 +2134,7 @@ impl Schema {
             .find(|lvl| lvl.name == level_name.level)
             .ok_or(format_err!("could not find level for level name"))?;
 
-        // TODO centralize where to get the alias
-        let column = format!("{}_{}", level.key_column, dim.name.replace(" ", "_"));
-
-        Ok(column)
-    }
-
-    fn get_mea_col(&self, cube_name: &str, measure: &Measure) -> Result<String, Error> {
-        let cube = self.cubes.iter()
-            .find(|cube| &cube.name == &cube_name)
-            .ok_or(format_err!("Could not find cube"))?;
-
-        let mea = cube.measures.iter()
-            .find(|m| m.name == measure.0)
-            .ok_or(format_err!("could not find level for level name"))?;
-
-        let column = mea.column.clone();
+        let column = format!("{}_{}", plain_identifier(&level.key_column), drill_alias_postfix(dim, &hier.name));
 
         Ok(column)
     }
@@ -1286,6 +2154,18 @@ struct MembersQueryIR {
 }
 
 
+/// A level of a shared dimension used by two cubes, possibly under different dimension names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeRelation {
+    pub cube: String,
+    pub dimension: String,
+    pub related_cube: String,
+    pub related_dimension: String,
+    pub shared_dimension: String,
+    pub hierarchy: String,
+    pub level: String,
+}
+
 #[derive(Debug, Clone)]
 pub enum CubeHasUniqueLevelsAndProperties {
     True,
@@ -1295,10 +2175,179 @@ pub enum CubeHasUniqueLevelsAndProperties {
     }
 }
 
+/// The columns of all drilldowns are selected side by side under their aliases (see
+/// `DrilldownSql::col_aliases`), so the aliases must be unique. They end with the alias
+/// postfix of the drilldown's dimension, and name columns which are also key columns get
+/// their own alias, so only drilldowns on the same hierarchy can clash, e.g. two levels
+/// with a property on the same column.
+fn check_drill_aliases(drills: &[Drilldown], drill_cols: &[DrilldownSql]) -> Result<(), Error> {
+    let mut aliases: HashMap<String, &LevelName> = HashMap::new();
+
+    for (drill, drill_col) in drills.iter().zip(drill_cols) {
+        for alias in drill_col.col_aliases() {
+            match aliases.insert(alias.clone(), &drill.0) {
+                Some(other) if *other == drill.0 => {
+                    bail!("Drilldown {} selects the column {} more than once", drill.0, alias);
+                },
+                Some(other) => {
+                    bail!("Drilldowns {} and {} both select a column named {}; leave out one of their properties", other, drill.0, alias);
+                },
+                None => (),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Suffix of a drilldown's column aliases. For dimensions with more than one hierarchy, it
+/// includes the hierarchy, so that several hierarchies of a dimension can be drilled down on
+/// in one query without their aliases clashing.
+fn drill_alias_postfix(dim: &Dimension, hierarchy: &str) -> String {
+    if dim.hierarchies.len() > 1 {
+        format!("{}_{}", dim.name, hierarchy).replace(" ", "_")
+    } else {
+        dim.name.replace(" ", "_")
+    }
+}
+
+/// Indexes of the levels from the top of the hierarchy down to a drilldown's level, for
+/// `parents`. If `parent_levels` is not empty, only those ancestors are kept.
+fn parent_level_idxs(
+    drill: &LevelName,
+    levels: &[schema::Level],
+    level_idx: usize,
+    parent_levels: &[LevelName],
+    ) -> Vec<usize>
+{
+    (0..=level_idx)
+        .filter(|&i| {
+            i == level_idx ||
+                parent_levels.is_empty() ||
+                parent_levels.iter().any(|p| {
+                    p.dimension == drill.dimension &&
+                        p.hierarchy == drill.hierarchy &&
+                        p.level == levels[i].name
+                })
+        })
+        .collect()
+}
+
+
+/// Result columns of the keys of drilldown levels with a `key_width`, with the width.
+fn key_width_idxs(
+    cube: &Cube,
+    drills: &[Drilldown],
+    drill_cols: &[DrilldownSql],
+    ) -> Vec<(usize, usize)>
+{
+    let mut res = vec![];
+    let mut idx = 0;
+
+    for (drill, drill_col) in drills.iter().zip(drill_cols) {
+        let levels = cube.get_hierarchy(&drill.0)
+            .map(|hier| hier.levels)
+            .unwrap_or_default();
+
+        for level_col in &drill_col.level_columns {
+            let key_width = levels.iter()
+                .find(|lvl| lvl.key_column == level_col.key_column)
+                .and_then(|lvl| lvl.key_width);
+
+            if let Some(width) = key_width {
+                res.push((idx, width));
+            }
+
+            idx += if level_col.name_column.is_some() { 2 } else { 1 };
+        }
+        idx += drill_col.property_columns.len();
+    }
+
+    res
+}
+
+/// Drops the name columns of the drilldowns, and their headers, which come right after the
+/// header of their key column.
+fn drop_name_columns(drill_cols: &mut [DrilldownSql], drill_headers: &mut Vec<String>) {
+    let mut header_idx = 0;
+
+    for drill_col in drill_cols.iter_mut() {
+        for level_col in drill_col.level_columns.iter_mut() {
+            header_idx += 1;
+            if level_col.name_column.take().is_some() {
+                drill_headers.remove(header_idx);
+            }
+        }
+        header_idx += drill_col.property_columns.len();
+    }
+}
+
+/// Resolves a sort key to a measure or calculation (if it parses as one in the query), or
+/// else to a drilldown column by its header.
+fn sort_key_sql(
+    key: &SortKey,
+    query: &Query,
+    drill_cols: &[DrilldownSql],
+    drill_headers: &[String],
+    ) -> Result<SortSql, Error>
+{
+    let mea_or_calc = key.column.parse::<MeaOrCalc>().ok()
+        .filter(|m_or_c| {
+            match m_or_c {
+                MeaOrCalc::Mea(m) => query.measures.contains(m),
+                MeaOrCalc::Calc(_) => true,
+            }
+        });
+
+    if let Some(m_or_c) = mea_or_calc {
+        let column = mea_or_calc!(&m_or_c, query)?;
+        let column_idx = match m_or_c {
+            MeaOrCalc::Mea(ref m) => {
+                query.measures.iter()
+                    .position(|col| col == m)
+                    .map(|idx| drill_headers.len() + idx)
+            },
+            MeaOrCalc::Calc(_) => None,
+        };
+
+        return Ok(SortSql {
+            direction: key.direction.clone(),
+            column,
+            column_idx,
+        });
+    }
+
+    let header_idx = drill_headers.iter()
+        .position(|h| *h == key.column)
+        .ok_or_else(|| format_err!("Sort {} must be a measure, calculation or drilldown column", key.column))?;
+
+    // rca and growth move drilldown columns around
+    if query.rca.is_some() || query.growth.is_some() {
+        bail!("Sort on drilldown column {} is not supported with rca or growth", key.column);
+    }
+
+    // one alias per header
+    let drill_aliases: Vec<String> = drill_cols.iter()
+        .flat_map(|d| d.col_aliases())
+        .collect();
+
+    let column = drill_aliases.get(header_idx)
+        .filter(|_| drill_aliases.len() == drill_headers.len())
+        .ok_or_else(|| format_err!("Could not find column for sort on {}", key.column))?
+        .clone();
+
+    Ok(SortSql {
+        direction: key.direction.clone(),
+        column,
+        column_idx: Some(header_idx),
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     // use serde_json;
+    use crate::dialect::{ResultOrder, SqlDialect, StandardSql};
     use crate::query::*;
 
     const SCHEMA_STR_MULTIPLE_HIER_NO_DEFAULT: &str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales", "primary_key": "product_id" }, "dimensions": [{ "name": "Geography", "foreign_key": "customer_id", "hierarchies": [ { "name": "Tract", "table": { "name": "customer_geo" }, "primary_key": "customer_id", "levels": [ { "name": "State", "key_column": "state_id", "name_column": "state_name", "key_type": "text" }, { "name": "County", "key_column": "county_id", "name_column": "county_name", "key_type": "text" }, { "name": "Tract", "key_column": "tract_id", "name_column": "tract_name", "key_type": "text" } ] }, { "name": "Place", "table": { "name": "customer_geo" }, "primary_key": "customer_id", "levels": [ { "name": "Place", "key_column": "place_id", "name_column": "place_name", "key_type": "text" } ] } ] } ], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
@@ -1320,6 +2369,128 @@ mod test {
         schema.validate().unwrap();
     }
 
+    #[test]
+    fn test_members_sql_parent() {
+        let schema: Schema = Schema::from_json(SCHEMA_STR_MULTIPLE_HIER_DEFAULT).unwrap();
+        let county = LevelName::new("Geography", "Tract", "County");
+        let parent = Cut::new("Geography", "Tract", "State", vec!["01", "02"], Mask::Include, false);
+
+        let (sql, header) = schema.members_sql("sales", &county, Some(&parent), &[]).unwrap();
+        assert_eq!(sql, "select distinct county_id, county_name from customer_geo where state_id in ('01', '02')");
+        assert_eq!(header, vec!["ID".to_string(), "Label".to_string()]);
+
+        // parent must be above the level in the same hierarchy
+        let not_parent = Cut::new("Geography", "Tract", "Tract", vec!["01"], Mask::Include, false);
+        assert!(schema.members_sql("sales", &county, Some(&not_parent), &[]).is_err());
+        let other_hier = Cut::new("Geography", "Place", "Place", vec!["01"], Mask::Include, false);
+        assert!(schema.members_sql("sales", &county, Some(&other_hier), &[]).is_err());
+    }
+
+    #[test]
+    fn test_members_sql_properties() {
+        let schema_str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales" }, "dimensions": [{ "name": "Geography", "foreign_key": "country_id", "hierarchies": [ { "name": "Geography", "table": { "name": "countries" }, "primary_key": "country_id", "levels": [ { "name": "Country", "key_column": "country_id", "name_column": "country_name", "properties": [ { "name": "ISO3", "column": "iso3" }, { "name": "Color", "column": "color" } ] } ] } ] }], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
+        let schema: Schema = Schema::from_json(schema_str).unwrap();
+        let country = LevelName::new("Geography", "Geography", "Country");
+
+        let properties = vec!["ISO3".to_owned(), "Color".to_owned()];
+        let (sql, header) = schema.members_sql("sales", &country, None, &properties).unwrap();
+        assert_eq!(sql, "select distinct country_id, country_name, iso3, color from countries");
+        assert_eq!(header, vec!["ID", "Label", "ISO3", "Color"]);
+
+        assert!(schema.members_sql("sales", &country, None, &["Flag".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_property_filter() {
+        use crate::dialect::{SqlDialect, StandardSql};
+
+        let schema_str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales" }, "dimensions": [{ "name": "Geography", "foreign_key": "country_id", "hierarchies": [ { "name": "Geography", "table": { "name": "countries" }, "primary_key": "country_id", "levels": [ { "name": "Country", "key_column": "country_id", "name_column": "country_name", "properties": [ { "name": "Region", "column": "region" } ] } ] } ] }, { "name": "Year", "foreign_key": "year", "hierarchies": [ { "name": "Year", "levels": [ { "name": "Year", "key_column": "year" } ] } ] }], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
+        let schema: Schema = Schema::from_json(schema_str).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec![Drilldown(LevelName::new("Year", "Year", "Year"))];
+        query.measures = vec![Measure("Quantity".to_string())];
+        query.property_filters = vec!["Geography.Country.Region:Europe,O'Land".parse().unwrap()];
+
+        let (query_ir, headers) = schema.sql_query("sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Year".to_owned(), "Quantity".into()]);
+        assert_eq!(query_ir.cuts.len(), 1);
+        assert_eq!(query_ir.cuts[0].column, "region");
+
+        let sql = StandardSql.generate_sql(query_ir);
+        assert!(sql.contains("region in ('Europe', 'O''Land')"));
+
+        query.property_filters = vec!["Geography.Country.Flag:Red".parse().unwrap()];
+        assert!(schema.sql_query("sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_measure_stats_sql() {
+        let schema_str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales" }, "dimensions": [{ "name": "Year", "foreign_key": "year", "type": "time", "hierarchies": [ { "name": "Year", "levels": [ { "name": "Year", "key_column": "year" } ] } ] }, { "name": "Date", "foreign_key": "date_id", "type": "time", "hierarchies": [ { "name": "Date", "table": { "name": "dates" }, "primary_key": "date_id", "levels": [ { "name": "Year", "key_column": "year" }, { "name": "Month", "key_column": "month" } ] } ] }], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
+        let mut schema: Schema = Schema::from_json(schema_str).unwrap();
+
+        let (sql, header) = schema.measure_stats_sql("sales", "Quantity", false, &StandardSql).unwrap();
+        assert_eq!(sql, "select min(quantity), max(quantity), avg(quantity), count(quantity) from sales");
+        assert_eq!(header, vec!["min", "max", "avg", "count"]);
+
+        let (sql, header) = schema.measure_stats_sql("sales", "Quantity", true, &StandardSql).unwrap();
+        assert_eq!(sql, "select stats_time, min(quantity), max(quantity), avg(quantity), count(quantity) from (select year as stats_time, quantity from sales) as f group by stats_time order by stats_time");
+        assert_eq!(header, vec!["Year", "min", "max", "avg", "count"]);
+
+        // time levels in a dimension table are joined
+        schema.cubes[0].dimensions.remove(0);
+        let (sql, _) = schema.measure_stats_sql("sales", "Quantity", true, &StandardSql).unwrap();
+        assert_eq!(sql, "select stats_time, min(quantity), max(quantity), avg(quantity), count(quantity) from (select date_id, quantity from sales) as f inner join (select distinct date_id as stats_pk, year as stats_time from dates) as t on f.date_id = t.stats_pk group by stats_time order by stats_time");
+
+        assert!(schema.measure_stats_sql("sales", "Price", false, &StandardSql).is_err());
+    
+        // names are quoted for the dialect
+        schema.cubes[0].measures[0].column = "units sold".to_owned();
+        let (sql, _) = schema.measure_stats_sql("sales", "Quantity", false, &StandardSql).unwrap();
+        assert_eq!(sql, "select min(\"units sold\"), max(\"units sold\"), avg(\"units sold\"), count(\"units sold\") from sales");
+
+        // cubes with required cuts can't be read whole
+        schema.cubes[0].required_cuts = Some(vec![crate::schema::RequiredCut { dimension: "Date".to_owned(), level: None, max_members: None }]);
+        assert!(schema.measure_stats_sql("sales", "Quantity", false, &StandardSql).is_err());
+    }
+
+    #[test]
+    fn test_metadata_remove_hidden() {
+        let schema_str = r#"{ "name": "test", "cubes": [ { "name": "sales", "table": { "name": "sales" }, "dimensions": [{ "name": "Geography", "foreign_key": "customer_id", "annotations": [{ "name": "hidden", "text": "true" }], "hierarchies": [ { "name": "Geography", "levels": [ { "name": "State", "key_column": "state_id" } ] } ] }], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" }, { "name": "Internal", "column": "internal", "aggregator": "sum", "annotations": [{ "name": "hidden", "text": "true" }] } ] }, { "name": "staging", "table": { "name": "staging" }, "annotations": [{ "name": "hidden", "text": "true" }], "dimensions": [], "measures": [ { "name": "Quantity", "column": "quantity", "aggregator": "sum" } ] } ] }"#;
+        let schema: Schema = Schema::from_json(schema_str).unwrap();
+
+        assert!(schema.get_cube_by_name("staging").unwrap().is_hidden());
+
+        let mut metadata = schema.metadata(None);
+        metadata.remove_hidden();
+
+        assert_eq!(metadata.cubes.len(), 1);
+        assert_eq!(metadata.cubes[0].name, "sales");
+        assert!(metadata.cubes[0].dimensions.is_empty());
+        assert_eq!(metadata.cubes[0].measures.len(), 1);
+        assert_eq!(metadata.cubes[0].measures[0].name, "Quantity");
+    }
+
+    #[test]
+    fn test_metadata_depth() {
+        let schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        let mut metadata = schema.metadata(None).cubes.remove(0);
+
+        let levels = metadata.levels_only();
+        assert!(levels.levels.iter().any(|l| l.depth > 1));
+
+        metadata.trim_depth(1);
+        for dimension in metadata.dimensions.iter() {
+            for hierarchy in dimension.hierarchies.iter() {
+                assert!(hierarchy.levels.len() <= 1);
+            }
+        }
+
+        let levels = metadata.levels_only();
+        assert!(levels.levels.iter().all(|l| l.depth == 1));
+        assert_eq!(levels.measures, metadata.measures.iter().map(|m| m.name.clone()).collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_validate_schema_single_hier_no_default() {
         let mut schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
@@ -1373,6 +2544,58 @@ mod test {
         assert_eq!(schema.cubes[0].dimensions[1].name, "Export Countries".to_owned());
     }
 
+    #[test]
+    fn test_cube_relations() {
+        let s = r##"
+            <Schema name="my_schema">
+                <SharedDimension name="Geo">
+                    <Hierarchy name="Geo">
+                        <Level name="Country" key_column="country_id" />
+                        <Level name="State" key_column="state_id" />
+                    </Hierarchy>
+                </SharedDimension>
+                <Cube name="trade">
+                    <Table name="trade" />
+                    <DimensionUsage name="Origin" source="Geo" foreign_key="origin_id" />
+                    <Measure name="Value" column="value" aggregator="sum" />
+                </Cube>
+                <Cube name="population">
+                    <Table name="population" />
+                    <DimensionUsage source="Geo" foreign_key="geo_id" />
+                    <Measure name="Population" column="population" aggregator="sum" />
+                </Cube>
+                <Cube name="sales">
+                    <Table name="sales" />
+                    <Dimension name="Geo" foreign_key="geo_id">
+                        <Hierarchy name="Geo">
+                            <Level name="Country" key_column="country_id" />
+                        </Hierarchy>
+                    </Dimension>
+                    <Measure name="Quantity" column="quantity" aggregator="sum" />
+                </Cube>
+            </Schema>
+        "##;
+        let schema: Schema = Schema::from_xml(s).unwrap();
+        let relations = schema.cube_relations();
+
+        // sales has its own Geo dimension, which isn't shared
+        assert_eq!(relations.len(), 4);
+        assert!(relations.iter().all(|r| r.cube != "sales" && r.related_cube != "sales"));
+
+        assert_eq!(relations[0], CubeRelation {
+            cube: "trade".to_owned(),
+            dimension: "Origin".to_owned(),
+            related_cube: "population".to_owned(),
+            related_dimension: "Geo".to_owned(),
+            shared_dimension: "Geo".to_owned(),
+            hierarchy: "Geo".to_owned(),
+            level: "Country".to_owned(),
+        });
+        assert_eq!(relations[3].cube, "population");
+        assert_eq!(relations[3].related_dimension, "Origin");
+        assert_eq!(relations[3].level, "State");
+    }
+
     #[test]
     #[should_panic]
     fn shared_dim_validate_duplicate_name() {
@@ -1516,13 +2739,18 @@ mod test {
             measures: [Measure("Price Total".to_string())].to_vec(),
             properties: vec![],
             filters: vec![],
+            row_filters: vec![],
+            property_filters: vec![],
             captions: vec![],
             parents: false,
+            parent_levels: vec![],
             top: None,
             top_where: None,
             sort: Some(SortQuery{
-                direction: SortDirection::Asc,
-                measure: MeaOrCalc::Mea(Measure("Price Total".to_string()))
+                keys: vec![SortKey {
+                    direction: SortDirection::Asc,
+                    column: "Price Total".to_string(),
+                }],
             }),
             limit: None,
             rca: Some(RcaQuery{
@@ -1539,14 +2767,21 @@ mod test {
                 mea: Measure("Price Total".to_string())
             }),
             growth: None,
+            share: None,
+            vs_previous_year: None,
             rate: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
+            priority: None,
+            sample: None,
+            distinct: false,
+            show: ShowColumns::Both,
+            treat_null_as: None,
         };
         let query_ir_headers = Schema::from_xml(s).unwrap().sql_query("Sales", &query, None);
         let (query_ir, _headers) = query_ir_headers.unwrap();
-        assert_eq!(query_ir.sort, Some(SortSql{direction: SortDirection::Asc, column: "final_m0".to_string()}))
+        assert_eq!(query_ir.sort, vec![SortSql{direction: SortDirection::Asc, column: "final_m0".to_string(), column_idx: Some(2)}])
     }
 
     #[test]
@@ -1677,13 +2912,18 @@ mod test {
                 constraint2: None,
             }
             ].to_vec(),
+            row_filters: vec!["Quantity.gt.1000".parse().unwrap()],
+            property_filters: vec![],
             captions: vec![],
             parents: false,
+            parent_levels: vec![],
             top: None,
             top_where: None,
             sort: Some(SortQuery{
-                direction: SortDirection::Asc,
-                measure: MeaOrCalc::Mea(Measure("Price Total".to_string()))
+                keys: vec![SortKey {
+                    direction: SortDirection::Asc,
+                    column: "Price Total".to_string(),
+                }],
             }),
             limit: None,
             rca: Some(RcaQuery{
@@ -1700,10 +2940,17 @@ mod test {
                 mea: Measure("Price Total".to_string())
             }),
             growth: None,
+            share: None,
+            vs_previous_year: None,
             rate: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
+            priority: None,
+            sample: None,
+            distinct: false,
+            show: ShowColumns::Both,
+            treat_null_as: None,
         };
         let query_ir_headers = Schema::from_xml(s).unwrap().sql_query("Sales", &query, None);
         let (query_ir, _headers) = query_ir_headers.unwrap();
@@ -1740,6 +2987,940 @@ mod test {
             },
             operator: None,
             constraint2: None,
-        }].to_vec())
+        }].to_vec());
+        assert_eq!(query_ir.row_filters, vec![RowFilterSql {
+            column: "quantity".to_string(),
+            constraint: Constraint {
+                comparison: Comparison::GreaterThan,
+                n: 1000.0,
+            },
+            operator: None,
+            constraint2: None,
+        }]);
+    }
+
+    #[test]
+    fn test_rate_query() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="tesseract_webshop_categories" />
+                        <Level name="Category" key_column="category_code" key_type="text" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Price Total" column="price_total" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query {
+            drilldowns: [Drilldown(LevelName::new("Year", "Year", "Year"))].to_vec(),
+            cuts: [Cut::new("Year", "Year", "Year", vec!["2018", "2019"], Mask::Include, false)].to_vec(),
+            measures: [Measure("Quantity".to_string())].to_vec(),
+            properties: vec![],
+            filters: vec![],
+            row_filters: vec![],
+            property_filters: vec![],
+            captions: vec![],
+            parents: false,
+            parent_levels: vec![],
+            top: None,
+            top_where: None,
+            sort: None,
+            limit: Some(LimitQuery { offset: None, n: 1 }),
+            rca: None,
+            growth: None,
+            share: None,
+            vs_previous_year: None,
+            rate: Some(RateQuery::new(LevelName::new("Category", "Category", "Category"), vec!["a".to_string()])),
+            debug: false,
+            sparse: false,
+            exclude_default_members: false,
+            priority: None,
+            sample: None,
+            distinct: false,
+            show: ShowColumns::Both,
+            treat_null_as: None,
+        };
+        let schema = Schema::from_xml(s).unwrap();
+
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Year".to_string(), "Quantity".to_string(), "Rate".to_string()]);
+        assert_eq!(query_ir.cuts.len(), 1);
+        assert_eq!(query_ir.limit.map(|l| l.n), Some(1));
+
+        let rate = query_ir.rate.unwrap();
+        assert_eq!(rate.members_string(), "'a'");
+        assert_eq!(rate.drilldown_sql.table.name, "tesseract_webshop_categories");
+
+        // rate is only allowed on a single sum or count measure
+        query.measures.push(Measure("Price Total".to_string()));
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_escape_query_ir() {
+        use crate::dialect::{SqlDialect, StandardSql};
+
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="webshop sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="user" />
+                        <Level name="Category" key_column="category code" key_type="text">
+                            <Property name="Group" column="group" />
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="units sold" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![Drilldown(LevelName::new("Category", "Category", "Category"))];
+        query.properties = vec![Property::new("Category", "Category", "Category", "Group")];
+        query.cuts = vec![
+            Cut::new("Year", "Year", "Year", vec!["2018", "2019) or (1=1"], Mask::Include, false),
+            Cut::new("Category", "Category", "Category", vec!["O'Brien"], Mask::Include, false),
+        ];
+        query.measures = vec![Measure("Quantity".to_string())];
+        let schema = Schema::from_xml(s).unwrap();
+
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        let sql = StandardSql.generate_sql(query_ir);
+
+        assert!(sql.contains("from \"webshop sales\""));
+        assert!(sql.contains("inner join \"user\""));
+        assert!(sql.contains("in (2018, '2019) or (1=1')"));
+        assert!(sql.contains("in ('O''Brien')"));
+        assert!(sql.contains("select \"user\".\"category code\", \"user\".\"group\", sum(\"units sold\")"));
+        assert!(sql.contains("on \"user\".\"category code\" = \"webshop sales\".category_id"));
+
+        // aliases are built from plain identifiers
+        let level_col = LevelColumn { key_column: "\"category code\"".to_owned(), name_column: None };
+        assert_eq!(level_col.key_alias("Category"), "category_code_Category");
+    }
+
+    #[test]
+    fn test_all_member_drilldown() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Geography" foreign_key="state_id">
+                    <Hierarchy name="Geography" all_member_name="All States">
+                        <Level name="State" key_column="state_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" treat_null_as="zero" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+        let all_level = LevelName::new("Geography", "Geography", "(All)");
+        assert_eq!(schema.cubes[0].get_all_member(&all_level), Some("All States".to_owned()));
+
+        let mut query = Query::new();
+        query.drilldowns = vec![
+            Drilldown(LevelName::new("Year", "Year", "Year")),
+            Drilldown(all_level),
+        ];
+        query.measures = vec![Measure("Quantity".to_string())];
+
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Year".to_owned(), "(All)".into(), "Quantity".into()]);
+        assert_eq!(query_ir.drills.len(), 1);
+        assert_eq!(query_ir.all_members, vec![AllMemberColumn { idx: 1, drill_idx: 1, member: "All States".into() }]);
+        assert_eq!(query_ir.null_zero_idxs, vec![2]);
+        assert_eq!(query_ir.calculation_columns(Some(0)).time_idxs, vec![0]);
+
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("year".into(), ColumnData::Int32(vec![2019, 2020])),
+            Column::new("quantity".into(), ColumnData::Float64(vec![1.0, 2.0])),
+        ]);
+        df.insert_all_members(&query_ir.all_members).unwrap();
+        assert_eq!(df.columns[1].stringify_column_data(), vec!["All States".to_owned(), "All States".into()]);
+        assert_eq!(df.columns[2].column_data, ColumnData::Float64(vec![1.0, 2.0]));
+
+        query.growth = Some(GrowthQuery::new("Year", "Year", "Year", "Quantity"));
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_all_member_drilldown_default_member() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Geography" foreign_key="state_id">
+                    <Hierarchy name="Geography" primary_key="state_id" all_member_name="All States" default_member="Geography.Geography.State.1">
+                        <Table name="states" />
+                        <Level name="State" key_column="state_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        // without a drilldown on the dimension, it's cut on the default member
+        let mut query = Query::new();
+        query.measures = vec![Measure("Quantity".to_string())];
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.cuts.len(), 1);
+        assert_eq!(query_ir.cuts[0].members, vec!["1".to_owned()]);
+
+        // the All member totals all the members, not only the default one
+        query.drilldowns = vec![Drilldown(LevelName::new("Geography", "Geography", "(All)"))];
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["(All)".to_owned(), "Quantity".into()]);
+        assert_eq!(query_ir.cuts.len(), 1);
+        assert!(query_ir.cuts[0].members.is_empty());
+        assert_eq!(query_ir.cuts[0].column, "state_id");
+    }
+
+    #[test]
+    fn test_validate_all_levels() {
+        let schema_with_all_levels = |all_level_names: (&str, &str)| format!(r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Geography" foreign_key="state_id">
+                    <Hierarchy name="Geography" all_member_name="All States"{}>
+                        <Level name="State" key_column="state_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category" all_member_name="All Categories"{}>
+                        <Level name="Category" key_column="category_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##, all_level_names.0, all_level_names.1);
+
+        // both hierarchies have an `(All)` level
+        let mut schema = Schema::from_xml(&schema_with_all_levels(("", ""))).unwrap();
+        assert!(schema.validate().is_ok());
+
+        let mut schema = Schema::from_xml(&schema_with_all_levels((r#" all_level_name="All Geographies""#, ""))).unwrap();
+        assert!(schema.validate().is_ok());
+
+        let mut schema = Schema::from_xml(&schema_with_all_levels((r#" all_level_name="Category""#, ""))).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn test_capabilities_check() {
+        use crate::query_ir::TopSql;
+
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![Drilldown(LevelName::new("Year", "Year", "Year"))];
+        query.measures = vec![Measure("Quantity".to_string())];
+        let schema = Schema::from_xml(s).unwrap();
+
+        let (mut query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        let standard = StandardSql.capabilities();
+        assert!(standard.check(&query_ir).is_ok());
+
+        // sampling syntax varies, so backends opt into it
+        query_ir.table.sample = Some(0.1);
+        assert!(standard.check(&query_ir).is_ok());
+        assert!(Capabilities::default().check(&query_ir).is_err());
+
+        query_ir.top = Some(TopSql {
+            n: 1,
+            by_column: "year".to_string(),
+            sort_columns: vec!["final_m0".to_string()],
+            sort_direction: SortDirection::Desc,
+        });
+        assert!(standard.check(&query_ir).is_err());
+        assert!(Capabilities { limit_by: true, ..standard }.check(&query_ir).is_ok());
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="tesseract_webshop_categories" />
+                        <Level name="Category" key_column="category_id" name_column="category_name" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Price Total" column="price_total" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![
+            Drilldown(LevelName::new("Year", "Year", "Year")),
+            Drilldown(LevelName::new("Category", "Category", "Category")),
+        ];
+        query.measures = vec![Measure("Quantity".to_string()), Measure("Price Total".to_string())];
+        query.sort = Some("Year.asc,Category ID.desc,Price Total.desc".parse().unwrap());
+
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Year", "Category ID", "Category", "Quantity", "Price Total"]);
+        assert_eq!(query_ir.sort, vec![
+            SortSql { direction: SortDirection::Asc, column: "year_Year".to_string(), column_idx: Some(0) },
+            SortSql { direction: SortDirection::Desc, column: "category_id_Category".to_string(), column_idx: Some(1) },
+            SortSql { direction: SortDirection::Desc, column: "final_m1".to_string(), column_idx: Some(4) },
+        ]);
+
+        // previews number the rows in the same order, with the drilldown keys breaking ties
+        // under a limit
+        query.sort = Some("Price Total.desc".parse().unwrap());
+        query.limit = Some("10".parse().unwrap());
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.result_order(), ResultOrder {
+            column_count: 5,
+            sort: vec![(4, SortDirection::Desc), (0, SortDirection::Asc), (1, SortDirection::Asc)],
+        });
+        query.limit = None;
+
+        // not a measure in the query, nor a drilldown column
+        query.sort = Some("Nope.asc".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        assert!("Year".parse::<SortQuery>().is_err());
+    }
+
+    #[test]
+    fn test_drill_aliases() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Country" foreign_key="country_id">
+                    <Hierarchy name="Country">
+                        <Table name="tesseract_webshop_countries" />
+                        <Level name="Country" key_column="country" name_column="country">
+                            <Property name="Name ES" column="name_es" />
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="tesseract_webshop_categories" />
+                        <Level name="Category" key_column="category_id" name_column="category_name">
+                            <Property name="Name ES" column="name_es" />
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![
+            Drilldown(LevelName::new("Country", "Country", "Country")),
+            Drilldown(LevelName::new("Category", "Category", "Category")),
+        ];
+        query.measures = vec![Measure("Quantity".to_string())];
+        query.properties = vec!["Country.Country.Country.Name ES".parse().unwrap()];
+
+        // a name column which is also the key column gets its own alias
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.drills[0].col_aliases(), vec!["country_Country", "country_Country_label", "name_es_Country"]);
+
+        // the same property column in two drilldowns gets an alias for each
+        query.properties.push("Category.Category.Category.Name ES".parse().unwrap());
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Country ID", "Country", "Name ES", "Category ID", "Category", "Name ES", "Quantity"]);
+        assert_eq!(query_ir.drills[1].col_aliases(), vec!["category_id_Category", "category_name_Category", "name_es_Category"]);
+    }
+
+    #[test]
+    fn test_share() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="tesseract_webshop_categories" />
+                        <Level name="Category" key_column="category_id" name_column="category_name" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Price Total" column="price_total" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![
+            Drilldown(LevelName::new("Year", "Year", "Year")),
+            Drilldown(LevelName::new("Category", "Category", "Category")),
+        ];
+        query.measures = vec![Measure("Quantity".to_string()), Measure("Price Total".to_string())];
+        query.share = Some("Category.Category,Price Total".parse().unwrap());
+
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Year", "Category ID", "Category", "Quantity", "Price Total", "Price Total Share"]);
+        assert_eq!(query_ir.share, Some(ShareColumns { group_idxs: vec![0], mea_idx: 4 }));
+
+        // the measure must be in the query
+        query.share = Some("Category.Category,Nope".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_previous_year() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Date" foreign_key="month_id" type="time">
+                    <Hierarchy name="Date">
+                        <Level name="Year" key_column="year" />
+                        <Level name="Month" key_column="month_id" name_column="month_name" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="tesseract_webshop_categories" />
+                        <Level name="Category" key_column="category_id" name_column="category_name" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Price Total" column="price_total" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![
+            Drilldown(LevelName::new("Date", "Date", "Month")),
+            Drilldown(LevelName::new("Category", "Category", "Category")),
+        ];
+        query.cuts = vec!["Date.Date.Year.2020".parse().unwrap()];
+        query.measures = vec![Measure("Quantity".to_string()), Measure("Price Total".to_string())];
+        query.parents = true;
+        query.vs_previous_year = Some(Measure("Price Total".to_string()));
+
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec![
+            "Year", "Month ID", "Month", "Category ID", "Category", "Quantity", "Price Total",
+            "Price Total Previous Year", "Price Total Previous Year Difference", "Price Total Previous Year Growth",
+        ]);
+        assert_eq!(query_ir.cuts[0].members, vec!["2020".to_owned(), "2019".to_owned()]);
+        assert_eq!(query_ir.previous_year, Some(PreviousYearColumns {
+            group_idxs: vec![3, 4],
+            time_idx: 1,
+            period_level: PeriodLevel::Month,
+            mea_idx: 6,
+            period_cuts: vec![PeriodCut { level: PeriodLevel::Year, periods: vec![2020] }],
+        }));
+
+        // the previous year's rows would count against a limit
+        let mut limit_query = query.clone();
+        limit_query.limit = Some("10".parse().unwrap());
+        assert!(schema.sql_query("Sales", &limit_query, None).is_err());
+
+        // cut members must be periods in the format of their level
+        let mut cut_query = query.clone();
+        cut_query.cuts = vec!["Date.Date.Month.7".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &cut_query, None).is_err());
+
+        // only levels of time dimensions are periods
+        let not_time_schema = Schema::from_xml(&s.replace(r#" type="time""#, "")).unwrap();
+        assert!(not_time_schema.sql_query("Sales", &query, None).is_err());
+
+        // the time drilldown must be on months or quarters
+        query.drilldowns.remove(0);
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_show_ids() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Category" foreign_key="category_id">
+                    <Hierarchy name="Category">
+                        <Table name="tesseract_webshop_categories" />
+                        <Level name="Category" key_column="category_id" name_column="category_name" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![
+            Drilldown(LevelName::new("Category", "Category", "Category")),
+            Drilldown(LevelName::new("Year", "Year", "Year")),
+        ];
+        query.measures = vec![Measure("Quantity".to_string())];
+        query.show = "ids".parse().unwrap();
+
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Category ID", "Year", "Quantity"]);
+        assert_eq!(query_ir.drills[0].level_columns[0].name_column, None);
+        assert_eq!(query_ir.drills[0].col_count(), 1);
+    }
+
+    #[test]
+    fn test_treat_null_as() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="tesseract_webshop_sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" treat_null_as="zero" />
+                <Measure name="Price" column="price" aggregator="avg" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![Drilldown(LevelName::new("Year", "Year", "Year"))];
+        query.measures = vec![Measure("Quantity".to_string()), Measure("Price".to_string())];
+
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.meas[0].column, "quantity");
+        assert!(query_ir.meas[0].null_zero);
+        assert!(!query_ir.meas[1].null_zero);
+        assert_eq!(query_ir.null_zero_idxs, vec![1]);
+
+        // the column is quoted inside the coalesce, spaces included
+        let mut escaped = query_ir.clone();
+        escaped.meas[0].column = "order quantity".to_owned();
+        escaped.escape(&StandardSql);
+        assert_eq!(escaped.meas[0].column, "coalesce(\"order quantity\", 0)");
+        assert_eq!(escaped.meas[1].column, "price");
+
+        // the query overrides the measures' policies
+        query.treat_null_as = Some("skip".parse().unwrap());
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert!(!query_ir.meas[0].null_zero);
+        assert!(query_ir.null_zero_idxs.is_empty());
+
+        // the rate column follows its measure's policy
+        query.treat_null_as = None;
+        query.measures = vec![Measure("Quantity".to_string())];
+        query.rate = Some(RateQuery::new(LevelName::new("Year", "Year", "Year"), vec!["2019".to_string()]));
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.null_zero_idxs, vec![1, 2]);
+
+        query.treat_null_as = Some("skip".parse().unwrap());
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert!(query_ir.null_zero_idxs.is_empty());
+    }
+
+    #[test]
+    fn test_key_width() {
+        let s = r##"
+        <Schema name="Census">
+            <Cube name="Population">
+                <Table name="population" />
+
+                <Dimension name="Geography" foreign_key="county_fips">
+                    <Hierarchy name="County" primary_key="county_fips">
+                        <Table name="dim_county" />
+                        <Level name="State" key_column="state_fips" key_width="2" />
+                        <Level name="County" key_column="county_fips" name_column="county_name" key_width="5" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Population" column="population" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut query = Query::new();
+        query.drilldowns = vec![Drilldown(LevelName::new("Geography", "County", "County"))];
+        query.cuts = vec!["Geography.County.State.01,48".parse().unwrap()];
+        query.measures = vec![Measure("Population".to_string())];
+
+        let schema = Schema::from_xml(s).unwrap();
+        let (query_ir, _) = schema.sql_query("Population", &query, None).unwrap();
+        assert_eq!(query_ir.cuts[0].members_string(), "1, 48");
+        assert_eq!(query_ir.key_widths, vec![(0, 5)]);
+        assert_eq!(query_ir.drill_key_idxs(), vec![0]);
+
+        // the level's key comes after its parents
+        query.parents = true;
+        let (query_ir, _) = schema.sql_query("Population", &query, None).unwrap();
+        assert_eq!(query_ir.drill_key_idxs(), vec![1]);
+        assert_eq!(query_ir.drill_col_idxs(), vec![vec![0, 1, 2]]);
+        query.parents = false;
+
+        // growth moves the time columns to the back, and the padded keys with them
+        query.drilldowns.insert(0, Drilldown(LevelName::new("Year", "Year", "Year")));
+        query.growth = Some(GrowthQuery::new("Year", "Year", "Year", "Population"));
+        let (query_ir, headers) = schema.sql_query("Population", &query, None).unwrap();
+        assert_eq!(headers[..3], ["County ID".to_owned(), "County".to_owned(), "Year".to_owned()]);
+        assert_eq!(query_ir.key_widths, vec![(0, 5)]);
+
+        // leading zeros of other non-text members are compared as numbers
+        assert_eq!(member_literal("06", &MemberType::NonText), "06");
+        assert_eq!(member_literal("06", &MemberType::Text), "'06'");
+
+        let s = s.replace(r#"key_width="2""#, r#"key_width="2" key_type="text""#);
+        let mut schema = Schema::from_xml(&s).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn test_snowflake_dimension() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Product" foreign_key="product_id">
+                    <Hierarchy name="Product" primary_key="product_id">
+                        <Table name="dim_product" />
+                        <Join foreign_key="group_id" primary_key="id">
+                            <Table name="dim_product_group" />
+                        </Join>
+                        <Level name="Group" key_column="group_code" name_column="group_name" />
+                        <Level name="Product" key_column="product_code" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut schema = Schema::from_xml(s).unwrap();
+        schema.validate().unwrap();
+
+        let group = LevelName::new("Product", "Product", "Group");
+        let mut query = Query::new();
+        query.drilldowns.push(Drilldown(group.clone()));
+        query.measures.push(Measure("Quantity".to_string()));
+
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        let dim_sql = query_ir::dim_subquery(query_ir.drills.first(), None).sql;
+        assert_eq!(dim_sql, "select group_code as group_code_Product, group_name as group_name_Product, product_id as product_id from dim_product inner join dim_product_group on dim_product.group_id = dim_product_group.id");
+
+        let (members_sql, _header) = schema.members_sql("Sales", &group, None, &[]).unwrap();
+        assert_eq!(members_sql, "select distinct group_code, group_name from dim_product inner join dim_product_group on dim_product.group_id = dim_product_group.id");
+    }
+
+    #[test]
+    fn test_parent_levels() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Geography" foreign_key="city_id">
+                    <Hierarchy name="Geography" primary_key="city_id">
+                        <Table name="dim_geo" />
+                        <Level name="Continent" key_column="continent_id" name_column="continent_name" />
+                        <Level name="Country" key_column="country_id" />
+                        <Level name="City" key_column="city_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns.push(Drilldown::new("Geography", "Geography", "City"));
+        query.measures.push(Measure("Quantity".to_string()));
+        query.parents = true;
+
+        let (_query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Continent ID", "Continent", "Country", "City", "Quantity"]);
+
+        query.parent_levels = vec![LevelName::new("Geography", "Geography", "Country")];
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Country", "City", "Quantity"]);
+        assert_eq!(query_ir.drills[0].level_columns.len(), 2);
+    }
+
+    #[test]
+    fn test_multiple_hierarchies_of_dimension() {
+        use crate::dialect::{SqlDialect, StandardSql};
+
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+
+                <Dimension name="Geography" foreign_key="city_id">
+                    <Hierarchy name="Political" primary_key="city_id">
+                        <Table name="dim_geo" />
+                        <Level name="Country" key_column="country_id" />
+                        <Level name="State" key_column="state_id" name_column="state_name" />
+                    </Hierarchy>
+                    <Hierarchy name="Census" primary_key="city_id">
+                        <Table name="dim_geo" />
+                        <Level name="Region" key_column="region_id" />
+                        <Level name="State" key_column="state_id" name_column="state_name" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns.push(Drilldown::new("Geography", "Political", "State"));
+        query.drilldowns.push(Drilldown::new("Geography", "Census", "Region"));
+        query.measures.push(Measure("Quantity".to_string()));
+
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["State ID", "State", "Region", "Quantity"]);
+        assert_eq!(
+            StandardSql.generate_sql(query_ir),
+            "select dim_geo.state_id, dim_geo.state_name, dim_geo.region_id, sum(quantity) from sales inner join dim_geo on dim_geo.city_id = sales.city_id group by dim_geo.state_id, dim_geo.state_name, dim_geo.region_id;"
+        );
+
+        // the same level in both hierarchies
+        query.drilldowns[1] = Drilldown::new("Geography", "Census", "State");
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(headers, vec!["Political State ID", "Political State", "Census State ID", "Census State", "Quantity"]);
+        assert_eq!(
+            query_ir.drills[0].col_alias_only_vec(),
+            vec!["state_id_Geography_Political", "state_name_Geography_Political"]
+        );
+        assert_eq!(
+            query_ir.drills[1].col_alias_only_vec(),
+            vec!["state_id_Geography_Census", "state_name_Geography_Census"]
+        );
+    }
+
+    #[test]
+    fn test_default_sort() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales" default_sort="Year.asc,Quantity.desc">
+                <Table name="sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Product" foreign_key="product_id">
+                    <Hierarchy name="Product">
+                        <Level name="Product" key_column="product_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut schema = Schema::from_xml(s).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns.push(Drilldown::new("Year", "Year", "Year"));
+        query.measures.push(Measure("Quantity".to_string()));
+
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        let sort: Vec<_> = query_ir.sort.iter().map(|s| (s.column.as_str(), s.column_idx)).collect();
+        assert_eq!(sort, vec![("year_Year", Some(0)), ("final_m0", Some(1))]);
+
+        // the year isn't in the query, so only the measure is sorted on
+        query.drilldowns[0] = Drilldown::new("Product", "Product", "Product");
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.sort.len(), 1);
+        assert_eq!(query_ir.sort[0].direction, SortDirection::Desc);
+
+        // a sort in the query replaces the default
+        query.sort = Some("Product.asc".parse().unwrap());
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.sort.len(), 1);
+        assert_eq!(query_ir.sort[0].column_idx, Some(0));
+    }
+
+    #[test]
+    fn test_data_source() {
+        let s = r##"
+        <Schema name="Reference">
+            <Cube name="Countries" data_source="Reference">
+                <Table name="countries" />
+                <Dimension name="Country" foreign_key="country_id">
+                    <Hierarchy name="Country">
+                        <Level name="Country" key_column="country_id" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Population" column="population" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+        assert_eq!(schema.cubes[0].data_source, Some("reference".to_owned()));
+    }
+
+    #[test]
+    fn test_sql_where() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales" sql_where="status = 'final'">
+                <Table name="sales" />
+
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Product" foreign_key="product_id">
+                    <Hierarchy name="Product">
+                        <Table name="products" />
+                        <Level name="Product" key_column="product_id" />
+                    </Hierarchy>
+                </Dimension>
+
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.measures.push(Measure("Quantity".to_string()));
+
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.table.sql_where, Some("status = 'final'".to_owned()));
+
+        let (sql, _header) = schema.measure_stats_sql("Sales", "Quantity", false, &StandardSql).unwrap();
+        assert_eq!(sql, "select min(quantity), max(quantity), avg(quantity), count(quantity) from sales where (status = 'final')");
+
+        // members of levels in the fact table are filtered, those of dimension tables aren't
+        let year = LevelName::new("Year", "Year", "Year");
+        let (sql, _header) = schema.members_sql("Sales", &year, None, &[]).unwrap();
+        assert_eq!(sql, "select distinct year from sales where (status = 'final')");
+
+        let product = LevelName::new("Product", "Product", "Product");
+        let (sql, _header) = schema.members_sql("Sales", &product, None, &[]).unwrap();
+        assert_eq!(sql, "select distinct product_id from products");
+
+        let cube = schema.get_cube_by_name("Sales").unwrap();
+        assert_eq!(cube.fact_table_sql(&StandardSql), "(select * from sales where (status = 'final')) as fact_rows");
+        let unfiltered = Cube { sql_where: None, ..cube.clone() };
+        assert_eq!(unfiltered.fact_table_sql(&StandardSql), "sales");
+    }
+
+    #[test]
+    fn test_distinct_query() {
+        let schema: Schema = Schema::from_json(SCHEMA_STR_SINGLE_HIER_NO_DEFAULT).unwrap();
+        let cube = &schema.cubes[0];
+        let dimension = &cube.dimensions[0];
+        let level = LevelName::new(&dimension.name, &dimension.hierarchies[0].name, &dimension.hierarchies[0].levels[0].name);
+
+        let mut query = Query::new();
+        query.distinct = true;
+        assert!(schema.sql_query(&cube.name, &query, None).is_err());
+
+        query.drilldowns.push(Drilldown(level));
+        let (query_ir, headers) = schema.sql_query(&cube.name, &query, None).unwrap();
+        assert!(query_ir.meas.is_empty());
+        assert!(!headers.is_empty());
+
+        query.measures.push(Measure(cube.measures[0].name.clone()));
+        assert!(schema.sql_query(&cube.name, &query, None).is_err());
     }
 }
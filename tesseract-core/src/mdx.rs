@@ -0,0 +1,398 @@
+//! Translation of a restricted MDX `SELECT` into a `Query`, for clients migrating from
+//! Mondrian.
+//!
+//! Supported:
+//! - axes (`ON COLUMNS`, `ON ROWS`, `ON AXIS(n)` or `ON n`), optionally `NON EMPTY`
+//! - sets in braces, `CrossJoin(a, b)` and `a * b`
+//! - `[Measures].[Name]` as a measure
+//! - `[Dimension].[Hierarchy].[Level].Members` (or `[Dimension].[Level].Members`) as a drilldown
+//! - `[Dimension].[Hierarchy].[Level].&[key]` as a member; on an axis it drills down on the
+//!   level and cuts on the members, in the `WHERE` slicer it's only a cut
+//!
+//! Calculated members, functions other than `CrossJoin`, and member names (instead of `&[key]`)
+//! are not supported.
+
+use failure::{Error, bail, format_err};
+
+use crate::names::{Cut, Drilldown, LevelName, Mask, Measure};
+use crate::query::Query;
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// `[bracketed]` or bare identifier; bare identifiers are also keywords.
+    Ident { name: String, bracketed: bool },
+    Dot,
+    Comma,
+    Amp,
+    Star,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+}
+
+
+fn tokenize(mdx: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = mdx.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '.' => tokens.push(Token::Dot),
+            ',' => tokens.push(Token::Comma),
+            '&' => tokens.push(Token::Amp),
+            '*' => tokens.push(Token::Star),
+            '{' => tokens.push(Token::LBrace),
+            '}' => tokens.push(Token::RBrace),
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '[' => {
+                // `]]` is an escaped `]`
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => {
+                            if chars.peek() == Some(&']') {
+                                chars.next();
+                                name.push(']');
+                            } else {
+                                break;
+                            }
+                        },
+                        Some(c) => name.push(c),
+                        None => bail!("MDX: unclosed `[`"),
+                    }
+                }
+                tokens.push(Token::Ident { name, bracketed: true });
+            },
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident { name, bracketed: false });
+            },
+            c => bail!("MDX: unexpected character `{}`", c),
+        }
+    }
+
+    Ok(tokens)
+}
+
+
+/// An element of a set.
+#[derive(Debug, Clone, PartialEq)]
+enum SetItem {
+    Measure(String),
+    Level(LevelName),
+    Member(LevelName, String),
+}
+
+
+/// Deepest nesting of sets allowed, so that a query can't overflow the stack.
+const MAX_SET_DEPTH: usize = 64;
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Nesting of the set list being parsed.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), Error> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format_err!("MDX: expected {:?}, found {:?}", expected, token)),
+            None => Err(format_err!("MDX: expected {:?}, found end of query", expected)),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident { name, bracketed: false }) => name.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format_err!("MDX: expected {}, found {:?}", keyword, self.peek()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, Error> {
+        match self.next() {
+            Some(Token::Ident { name, .. }) => Ok(name),
+            token => Err(format_err!("MDX: expected a name, found {:?}", token)),
+        }
+    }
+
+    /// `set := term (* term)*`
+    fn set(&mut self) -> Result<Vec<SetItem>, Error> {
+        let mut items = self.term()?;
+        while self.peek() == Some(&Token::Star) {
+            self.next();
+            items.extend(self.term()?);
+        }
+        Ok(items)
+    }
+
+    /// `set (, set)*`, up to the closing token.
+    fn set_list(&mut self, close: Token) -> Result<Vec<SetItem>, Error> {
+        if self.depth >= MAX_SET_DEPTH {
+            bail!("MDX: sets are nested deeper than {}", MAX_SET_DEPTH);
+        }
+        self.depth += 1;
+        let items = self.set_list_items(close);
+        self.depth -= 1;
+        items
+    }
+
+    fn set_list_items(&mut self, close: Token) -> Result<Vec<SetItem>, Error> {
+        let mut items = vec![];
+        if self.peek() == Some(&close) {
+            self.next();
+            return Ok(items);
+        }
+        loop {
+            items.extend(self.set()?);
+            match self.next() {
+                Some(Token::Comma) => (),
+                Some(ref token) if *token == close => return Ok(items),
+                token => bail!("MDX: expected `,` or {:?}, found {:?}", close, token),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Vec<SetItem>, Error> {
+        match self.peek() {
+            Some(Token::LBrace) => {
+                self.next();
+                self.set_list(Token::RBrace)
+            },
+            Some(Token::LParen) => {
+                self.next();
+                self.set_list(Token::RParen)
+            },
+            _ if self.is_keyword("crossjoin") => {
+                self.next();
+                self.expect(Token::LParen)?;
+                self.set_list(Token::RParen)
+            },
+            _ => Ok(vec![self.member_expr()?]),
+        }
+    }
+
+    /// A dotted path, ending in `.Members` for a level or `.&[key]` for a member.
+    fn member_expr(&mut self) -> Result<SetItem, Error> {
+        let mut path = vec![self.ident()?];
+
+        while self.peek() == Some(&Token::Dot) {
+            self.next();
+
+            if self.peek() == Some(&Token::Amp) {
+                self.next();
+                let key = self.ident()?;
+                let level_name = LevelName::from_vec(path.clone())
+                    .map_err(|_| format_err!("MDX: member {} needs a level", key))?;
+                return Ok(SetItem::Member(level_name, key));
+            }
+
+            if self.is_keyword("members") {
+                self.next();
+                let level_name = LevelName::from_vec(path.clone())
+                    .map_err(|_| format_err!("MDX: [{}].Members needs a level", path.join("].[")))?;
+                return Ok(SetItem::Level(level_name));
+            }
+
+            path.push(self.ident()?);
+        }
+
+        if path.len() == 2 && path[0].eq_ignore_ascii_case("measures") {
+            return Ok(SetItem::Measure(path[1].clone()));
+        }
+
+        bail!("MDX: unsupported expression [{}]; use a measure, a level's .Members, or a member's .&[key]", path.join("].["))
+    }
+
+    /// `[NON EMPTY] set ON axis`
+    fn axis(&mut self) -> Result<(Vec<SetItem>, bool), Error> {
+        let non_empty = if self.is_keyword("non") {
+            self.next();
+            self.expect_keyword("empty")?;
+            true
+        } else {
+            false
+        };
+
+        let items = self.set()?;
+
+        self.expect_keyword("on")?;
+        if self.is_keyword("axis") {
+            self.next();
+            self.expect(Token::LParen)?;
+            self.ident()?;
+            self.expect(Token::RParen)?;
+        } else {
+            let axis = self.ident()?;
+            let valid = ["columns", "rows", "pages", "chapters", "sections"].iter()
+                .any(|a| axis.eq_ignore_ascii_case(a)) || axis.parse::<u32>().is_ok();
+            if !valid {
+                bail!("MDX: unknown axis {}", axis);
+            }
+        }
+
+        Ok((items, non_empty))
+    }
+}
+
+
+/// Adds a member to the cut on its level, creating the cut if needed.
+fn add_cut_member(cuts: &mut Vec<Cut>, level_name: LevelName, member: String) {
+    match cuts.iter_mut().find(|cut| cut.level_name == level_name) {
+        Some(cut) => {
+            if !cut.members.contains(&member) {
+                cut.members.push(member);
+            }
+        },
+        None => cuts.push(Cut {
+            level_name,
+            members: vec![member],
+            mask: Mask::Include,
+            for_match: false,
+        }),
+    }
+}
+
+
+/// Parses an MDX `SELECT` into the cube name and the `Query` on it.
+pub fn parse_mdx(mdx: &str) -> Result<(String, Query), Error> {
+    let mut parser = Parser { tokens: tokenize(mdx)?, pos: 0, depth: 0 };
+    let mut query = Query::new();
+
+    parser.expect_keyword("select")?;
+
+    let mut axis_items = vec![];
+    loop {
+        let (items, non_empty) = parser.axis()?;
+        axis_items.extend(items);
+        query.sparse |= non_empty;
+
+        if parser.peek() == Some(&Token::Comma) {
+            parser.next();
+        } else {
+            break;
+        }
+    }
+
+    parser.expect_keyword("from")?;
+    let cube = parser.ident()?;
+
+    let slicer_items = if parser.is_keyword("where") {
+        parser.next();
+        parser.set()?
+    } else {
+        vec![]
+    };
+
+    if let Some(token) = parser.peek() {
+        bail!("MDX: unexpected {:?} after the query", token);
+    }
+
+    for item in axis_items {
+        match item {
+            SetItem::Measure(measure) => query.measures.push(Measure::new(measure)),
+            SetItem::Level(level_name) => {
+                if !query.drilldowns.iter().any(|d| d.0 == level_name) {
+                    query.drilldowns.push(Drilldown(level_name));
+                }
+            },
+            SetItem::Member(level_name, member) => {
+                if !query.drilldowns.iter().any(|d| d.0 == level_name) {
+                    query.drilldowns.push(Drilldown(level_name.clone()));
+                }
+                add_cut_member(&mut query.cuts, level_name, member);
+            },
+        }
+    }
+
+    for item in slicer_items {
+        match item {
+            SetItem::Measure(measure) => query.measures.push(Measure::new(measure)),
+            SetItem::Member(level_name, member) => add_cut_member(&mut query.cuts, level_name, member),
+            SetItem::Level(level_name) => bail!("MDX: the WHERE slicer can't have a whole level ({})", level_name),
+        }
+    }
+
+    if query.measures.is_empty() {
+        bail!("MDX: at least one [Measures].[...] is required");
+    }
+
+    Ok((cube, query))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_mdx() {
+        let mdx = "
+            SELECT NON EMPTY {[Measures].[Quantity], [Measures].[Price]} ON COLUMNS,
+                CrossJoin([Geography].[State].Members, {[Product].[Product].[Category].&[1], [Product].[Product].[Category].&[4]}) ON ROWS
+            FROM [Sales]
+            WHERE ([Year].[Year].[Year].&[2018])
+        ";
+        let (cube, query) = parse_mdx(mdx).unwrap();
+
+        assert_eq!(cube, "Sales");
+        assert!(query.sparse);
+        assert_eq!(query.measures, vec![Measure::new("Quantity"), Measure::new("Price")]);
+        assert_eq!(query.drilldowns, vec![
+            Drilldown::new("Geography", "Geography", "State"),
+            Drilldown::new("Product", "Product", "Category"),
+        ]);
+        assert_eq!(query.cuts, vec![
+            Cut::new("Product", "Product", "Category", vec!["1", "4"], Mask::Include, false),
+            Cut::new("Year", "Year", "Year", vec!["2018"], Mask::Include, false),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_mdx_errors() {
+        assert!(parse_mdx("SELECT [Geography].[State].Members ON ROWS FROM [Sales]").is_err());
+        assert!(parse_mdx("SELECT [Measures].[Quantity] ON COLUMNS FROM [Sales] WHERE [Year].[Year].Members").is_err());
+        assert!(parse_mdx("SELECT Filter([Measures].[Quantity]) ON COLUMNS FROM [Sales]").is_err());
+        assert!(parse_mdx("SELECT [Measures].[Quantity] ON COLUMNS FROM [Sales").is_err());
+
+        let nested = format!("SELECT {}[Measures].[Quantity] ON COLUMNS FROM [Sales]", "{(".repeat(5000));
+        assert!(parse_mdx(&nested).is_err());
+
+        let (_, query) = parse_mdx("select [Measures].[Quantity] on 0 from Sales").unwrap();
+        assert!(!query.sparse);
+        assert!(query.drilldowns.is_empty());
+    }
+}
@@ -19,6 +19,9 @@
 // - Dimension.Hierarchy.Level
 // - Dimension.Level
 // etc.
+//
+// Names and members containing `.` or `,` can be quoted with
+// backticks: Geography.City.`Washington, D.C.`
 
 use failure::{Error, bail, format_err, ensure};
 use serde_derive::{Deserialize, Serialize};
@@ -94,7 +97,7 @@ impl FromStr for LevelName {
     /// first char is a bracket to determine whether to
     /// parse using brackets or not.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let name_iter = if s.chars().nth(0).unwrap() == '[' {
+        if s.chars().nth(0).unwrap() == '[' {
             // check if starts with '[', then assume
             // that this means that it's a qualified name
             // with [] wrappers. This means that can't just
@@ -102,12 +105,11 @@ impl FromStr for LevelName {
             // outside the []
             let pattern: &[_] = &['[', ']'];
             let s = s.trim_matches(pattern);
-            s.split("].[")
+            LevelName::from_vec(s.split("].[").collect())
         } else {
-            s.split(".")
-        };
-
-        LevelName::from_vec(name_iter.collect())
+            let names: Vec<_> = split_quoted(s, '.')?.into_iter().map(unquote).collect();
+            LevelName::from_vec(names)
+        }
     }
 }
 
@@ -336,19 +338,23 @@ impl FromStr for Cut {
                 .map(|s| s.trim_start_matches('['))
                 .collect()
         } else {
-            s.split(".")
-                .collect()
+            split_quoted(s, '.')?
         };
 
-        let members: Vec<_> = name_vec[name_vec.len()-1]
+        let members_str = name_vec[name_vec.len()-1]
             .trim_start_matches('&')
-            .trim_start_matches('[')
-            .split(',')
-            .map(|s| s.trim_start_matches('&').to_owned())
+            .trim_start_matches('[');
+        let members: Vec<_> = split_quoted(members_str, ',')?
+            .into_iter()
+            .map(|s| unquote(s.trim_start_matches('&')))
+            .collect();
+
+        let level_names: Vec<_> = name_vec[0..name_vec.len()-1].iter()
+            .map(|name| unquote(name))
             .collect();
 
         Ok(Cut {
-            level_name: LevelName::from_vec(name_vec[0..name_vec.len()-1].to_vec())?,
+            level_name: LevelName::from_vec(level_names)?,
             members,
             mask,
             for_match,
@@ -432,7 +438,7 @@ impl FromStr for Property {
     /// first char is a bracket to determine whether to
     /// parse using brackets or not.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let name_vec: Vec<_> = (if s.chars().nth(0).unwrap() == '[' {
+        let name_vec: Vec<String> = if s.chars().nth(0).unwrap() == '[' {
             // check if starts with '[', then assume
             // that this means that it's a qualified name
             // with [] wrappers. This means that can't just
@@ -440,10 +446,10 @@ impl FromStr for Property {
             // outside the []
             let pattern: &[_] = &['[', ']'];
             let s = s.trim_matches(pattern);
-            s.split("].[")
+            s.split("].[").map(|name| name.to_owned()).collect()
         } else {
-            s.split(".")
-        }).collect();
+            split_quoted(s, '.')?.into_iter().map(unquote).collect()
+        };
 
         Ok(Property {
             level_name: LevelName::from_vec(name_vec[0..name_vec.len() - 1].to_vec())?,
@@ -453,6 +459,40 @@ impl FromStr for Property {
 }
 
 
+/// Splits `s` on `sep`, except within backtick quotes, so that names and members can contain
+/// the separators. The quotes are kept, for `unquote`.
+pub fn split_quoted(s: &str, sep: char) -> Result<Vec<&str>, Error> {
+    let mut parts = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        if c == '`' {
+            // an escaped backtick (doubled) closes and reopens the quotes
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+
+    ensure!(!quoted, "Unclosed backtick quote in {}", s);
+    parts.push(&s[start..]);
+
+    Ok(parts)
+}
+
+/// Removes the backtick quotes around a name or member. Within quotes, a backtick is
+/// escaped by doubling it. Unquoted names are returned as is.
+pub fn unquote(s: &str) -> String {
+    if s.len() >= 2 && s.starts_with('`') && s.ends_with('`') {
+        s[1..s.len()-1].replace("``", "`")
+    } else {
+        s.to_owned()
+    }
+}
+
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -594,5 +634,28 @@ mod test {
         assert_eq!(property, property_test_2);
         assert_eq!(property, property_test_3);
     }
+
+    #[test]
+    fn test_parse_quoted() {
+        let level = "Geography.`St. Louis Area`.`County, Parish`".parse::<LevelName>().unwrap();
+        assert_eq!(level, LevelName::new("Geography", "St. Louis Area", "County, Parish"));
+
+        let cut = "~Geography.City.`Washington, D.C.`,&Boston,`Rock ``n`` Roll`".parse::<Cut>().unwrap();
+        assert_eq!(cut, Cut::new(
+            "Geography", "Geography", "City",
+            vec!["Washington, D.C.", "Boston", "Rock `n` Roll"],
+            Mask::Exclude,
+            false,
+        ));
+
+        let cut = "[Geography].[City].&[`Washington, D.C.`,Boston]".parse::<Cut>().unwrap();
+        assert_eq!(cut.members, vec!["Washington, D.C.".to_owned(), "Boston".to_owned()]);
+
+        let property = "Geography.City.`Pop. (2010)`".parse::<Property>().unwrap();
+        assert_eq!(property, Property::new("Geography", "Geography", "City", "Pop. (2010)"));
+
+        assert!("Geography.City.`Washington, D.C.".parse::<Cut>().is_err());
+        assert_eq!(split_quoted("a.`b.c`.d", '.').unwrap(), vec!["a", "`b.c`", "d"]);
+    }
 }
 
@@ -0,0 +1,192 @@
+//! Checked conversions of database numbers into `ColumnData`.
+//!
+//! Backends read integers and floats as the widest type of their driver (e.g. `i64` for
+//! mysql), and push them into a column of the type the database reported. A value which
+//! doesn't fit the column's type widens the column instead of being truncated, and values
+//! which can't be represented at all (or numbers pushed into a text column) are an error.
+
+use failure::{Error, bail};
+
+use crate::dataframe::ColumnData;
+
+
+impl ColumnData {
+    /// Pushes a signed integer, widening the column to `Int64` if the value doesn't fit.
+    pub fn push_i64(&mut self, value: i64) -> Result<(), Error> {
+        macro_rules! push_or_widen {
+            ($v:expr, $t:ty) => {
+                match <$t as std::convert::TryFrom<i64>>::try_from(value) {
+                    Ok(x) => $v.push(x),
+                    Err(_) => {
+                        self.widen_int()?;
+                        return self.push_i64(value);
+                    },
+                }
+            };
+        }
+
+        match self {
+            ColumnData::Int8(v) => push_or_widen!(v, i8),
+            ColumnData::Int16(v) => push_or_widen!(v, i16),
+            ColumnData::Int32(v) => push_or_widen!(v, i32),
+            ColumnData::Int64(v) => v.push(value),
+            ColumnData::UInt8(v) => push_or_widen!(v, u8),
+            ColumnData::UInt16(v) => push_or_widen!(v, u16),
+            ColumnData::UInt32(v) => push_or_widen!(v, u32),
+            ColumnData::UInt64(v) => push_or_widen!(v, u64),
+            ColumnData::Float32(v) => v.push(value as f32),
+            ColumnData::Float64(v) => v.push(value as f64),
+            _ => bail!("Cannot push integer {} into a {} column", value, self.type_name()),
+        }
+
+        Ok(())
+    }
+
+    /// Pushes an unsigned integer, widening the column to `Int64` if the value doesn't fit.
+    pub fn push_u64(&mut self, value: u64) -> Result<(), Error> {
+        macro_rules! push_or_widen {
+            ($v:expr, $t:ty) => {
+                match <$t as std::convert::TryFrom<u64>>::try_from(value) {
+                    Ok(x) => $v.push(x),
+                    Err(_) => {
+                        self.widen_int()?;
+                        return self.push_u64(value);
+                    },
+                }
+            };
+        }
+
+        match self {
+            ColumnData::Int8(v) => push_or_widen!(v, i8),
+            ColumnData::Int16(v) => push_or_widen!(v, i16),
+            ColumnData::Int32(v) => push_or_widen!(v, i32),
+            ColumnData::Int64(v) => match <i64 as std::convert::TryFrom<u64>>::try_from(value) {
+                Ok(x) => v.push(x),
+                Err(_) => bail!("Integer {} is too large for a signed column", value),
+            },
+            ColumnData::UInt8(v) => push_or_widen!(v, u8),
+            ColumnData::UInt16(v) => push_or_widen!(v, u16),
+            ColumnData::UInt32(v) => push_or_widen!(v, u32),
+            ColumnData::UInt64(v) => v.push(value),
+            ColumnData::Float32(v) => v.push(value as f32),
+            ColumnData::Float64(v) => v.push(value as f64),
+            _ => bail!("Cannot push integer {} into a {} column", value, self.type_name()),
+        }
+
+        Ok(())
+    }
+
+    /// Pushes a float, widening a `Float32` column to `Float64` if the value is out of its
+    /// range. Integer columns only take floats without a fractional part.
+    pub fn push_f64(&mut self, value: f64) -> Result<(), Error> {
+        match self {
+            ColumnData::Float32(v) => {
+                if value.is_finite() && value.abs() > f64::from(std::f32::MAX) {
+                    let widened = v.iter().map(|x| f64::from(*x)).chain(Some(value)).collect();
+                    *self = ColumnData::Float64(widened);
+                } else {
+                    v.push(value as f32);
+                }
+            },
+            ColumnData::Float64(v) => v.push(value),
+            ColumnData::Int8(_) | ColumnData::Int16(_) | ColumnData::Int32(_) | ColumnData::Int64(_) => {
+                if value.fract() != 0.0 || value < std::i64::MIN as f64 || value >= std::i64::MAX as f64 {
+                    bail!("Cannot push {} into an integer column", value);
+                }
+                self.push_i64(value as i64)?;
+            },
+            ColumnData::UInt8(_) | ColumnData::UInt16(_) | ColumnData::UInt32(_) | ColumnData::UInt64(_) => {
+                if value.fract() != 0.0 || value < 0.0 || value >= std::u64::MAX as f64 {
+                    bail!("Cannot push {} into an unsigned integer column", value);
+                }
+                self.push_u64(value as u64)?;
+            },
+            _ => bail!("Cannot push number {} into a {} column", value, self.type_name()),
+        }
+
+        Ok(())
+    }
+
+    /// Widens a non-nullable integer column narrower than 64 bits to `Int64`, which holds
+    /// all of their values.
+    fn widen_int(&mut self) -> Result<(), Error> {
+        let widened = match self {
+            ColumnData::Int8(v) => ColumnData::Int64(v.iter().map(|x| i64::from(*x)).collect()),
+            ColumnData::Int16(v) => ColumnData::Int64(v.iter().map(|x| i64::from(*x)).collect()),
+            ColumnData::Int32(v) => ColumnData::Int64(v.iter().map(|x| i64::from(*x)).collect()),
+            ColumnData::UInt8(v) => ColumnData::Int64(v.iter().map(|x| i64::from(*x)).collect()),
+            ColumnData::UInt16(v) => ColumnData::Int64(v.iter().map(|x| i64::from(*x)).collect()),
+            ColumnData::UInt32(v) => ColumnData::Int64(v.iter().map(|x| i64::from(*x)).collect()),
+            _ => bail!("Cannot widen a {} column", self.type_name()),
+        };
+
+        *self = widened;
+
+        Ok(())
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            ColumnData::Int8(_) => "Int8",
+            ColumnData::Int16(_) => "Int16",
+            ColumnData::Int32(_) => "Int32",
+            ColumnData::Int64(_) => "Int64",
+            ColumnData::UInt8(_) => "UInt8",
+            ColumnData::UInt16(_) => "UInt16",
+            ColumnData::UInt32(_) => "UInt32",
+            ColumnData::UInt64(_) => "UInt64",
+            ColumnData::Float32(_) => "Float32",
+            ColumnData::Float64(_) => "Float64",
+            ColumnData::Text(_) => "Text",
+            ColumnData::DictText(_) => "DictText",
+            ColumnData::NullableInt8(_) => "NullableInt8",
+            ColumnData::NullableInt16(_) => "NullableInt16",
+            ColumnData::NullableInt32(_) => "NullableInt32",
+            ColumnData::NullableInt64(_) => "NullableInt64",
+            ColumnData::NullableUInt8(_) => "NullableUInt8",
+            ColumnData::NullableUInt16(_) => "NullableUInt16",
+            ColumnData::NullableUInt32(_) => "NullableUInt32",
+            ColumnData::NullableUInt64(_) => "NullableUInt64",
+            ColumnData::NullableFloat32(_) => "NullableFloat32",
+            ColumnData::NullableFloat64(_) => "NullableFloat64",
+            ColumnData::NullableText(_) => "NullableText",
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_widens() {
+        let mut column = ColumnData::Int32(vec![1]);
+        column.push_i64(2).unwrap();
+        assert_eq!(column, ColumnData::Int32(vec![1, 2]));
+
+        column.push_i64(5_000_000_000).unwrap();
+        assert_eq!(column, ColumnData::Int64(vec![1, 2, 5_000_000_000]));
+
+        let mut column = ColumnData::UInt8(vec![1]);
+        column.push_i64(-1).unwrap();
+        assert_eq!(column, ColumnData::Int64(vec![1, -1]));
+
+        let mut column = ColumnData::Float32(vec![1.5]);
+        column.push_f64(1e100).unwrap();
+        assert_eq!(column, ColumnData::Float64(vec![1.5, 1e100]));
+    }
+
+    #[test]
+    fn test_push_errors() {
+        let mut column = ColumnData::Int64(vec![]);
+        assert!(column.push_u64(std::u64::MAX).is_err());
+        assert!(column.push_f64(1.5).is_err());
+
+        let mut column = ColumnData::UInt64(vec![]);
+        assert!(column.push_i64(-1).is_err());
+
+        let mut column = ColumnData::Text(vec![]);
+        assert!(column.push_i64(1).is_err());
+    }
+}
@@ -9,7 +9,10 @@ use crate::names::{
     Measure,
     Property,
     LevelName,
+    split_quoted,
+    unquote,
 };
+use crate::schema::NullPolicy;
 
 #[derive(Debug, Clone)]
 pub struct Query {
@@ -18,18 +21,38 @@ pub struct Query {
     pub measures: Vec<Measure>,
     pub properties: Vec<Property>,
     pub filters: Vec<FilterQuery>,
+    /// Filters on the measure columns of fact table rows, applied before aggregation.
+    pub row_filters: Vec<FilterQuery>,
+    /// Filters on the property columns of dimension tables.
+    pub property_filters: Vec<PropertyFilter>,
     pub captions: Vec<Property>,
     pub parents: bool,
+    /// With `parents`, only include these ancestor levels; all ancestors if empty.
+    pub parent_levels: Vec<LevelName>,
     pub top: Option<TopQuery>,
     pub top_where: Option<TopWhereQuery>,
     pub sort: Option<SortQuery>,
     pub limit: Option<LimitQuery>,
     pub rca: Option<RcaQuery>,
     pub growth: Option<GrowthQuery>,
+    /// Share of a measure's total, calculated on the results.
+    pub share: Option<ShareQuery>,
+    /// Compares a measure with its value in the same period of the previous year, for a
+    /// drilldown on a month or quarter level. Calculated on the results.
+    pub vs_previous_year: Option<Measure>,
     pub rate: Option<RateQuery>,
     pub debug: bool,
     pub sparse: bool,
     pub exclude_default_members: bool,
+    pub priority: Option<QueryPriority>,
+    /// Fraction of the fact table to sample, between 0 and 1.
+    pub sample: Option<f64>,
+    /// Returns the distinct combinations of the drilldowns, without measures.
+    pub distinct: bool,
+    /// Which columns of drilldown levels with a name column are returned.
+    pub show: ShowColumns,
+    /// Null handling for all the measures, instead of each measure's own.
+    pub treat_null_as: Option<NullPolicy>,
 }
 
 impl Query {
@@ -40,18 +63,28 @@ impl Query {
             measures: vec![],
             properties: vec![],
             filters: vec![],
+            row_filters: vec![],
+            property_filters: vec![],
             captions: vec![],
             parents: false,
+            parent_levels: vec![],
             top: None,
             top_where: None,
             sort: None,
             limit: None,
             rca: None,
             growth: None,
+            share: None,
+            vs_previous_year: None,
             rate: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
+            priority: None,
+            sample: None,
+            distinct: false,
+            show: ShowColumns::Both,
+            treat_null_as: None,
         }
     }
 }
@@ -88,12 +121,12 @@ impl FromStr for TopQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
+        match &split_quoted(s, ',')?[..] {
             [n, by_dimension, sort_measure, sort_direction] => {
 
                 let n = n.parse::<u64>()?;
                 let by_dimension = by_dimension.parse::<LevelName>()?;
-                let sort_mea_or_calc = vec![sort_measure.parse::<MeaOrCalc>()?];
+                let sort_mea_or_calc = vec![unquote(sort_measure).parse::<MeaOrCalc>()?];
                 let sort_direction = sort_direction.parse::<SortDirection>()?;
 
                 Ok(TopQuery {
@@ -170,10 +203,10 @@ impl FromStr for TopWhereQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
+        match &split_quoted(s, ',')?[..] {
             [by_mea, constraint] => {
 
-                let by_mea_or_calc = by_mea.parse::<MeaOrCalc>()?;
+                let by_mea_or_calc = unquote(by_mea).parse::<MeaOrCalc>()?;
                 let constraint = constraint.parse::<Constraint>()?;
 
                 Ok(TopWhereQuery {
@@ -270,7 +303,7 @@ impl FromStr for LimitQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
+        match &split_quoted(s, ',')?[..] {
             [offset, n] => {
                 Ok(LimitQuery {
                     offset: Some(offset.parse::<u64>()?),
@@ -288,28 +321,48 @@ impl FromStr for LimitQuery {
     }
 }
 
+/// One or more sort keys, applied in order. Drilldown columns are sorted on after them.
 #[derive(Debug, Clone)]
 pub struct SortQuery {
-    pub direction: SortDirection,
-    pub measure: MeaOrCalc,
+    pub keys: Vec<SortKey>,
 }
 
 impl FromStr for SortQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(".").collect::<Vec<_>>()[..] {
-            [measure, direction] => {
-                let measure = measure.parse::<MeaOrCalc>()?;
+        let keys = split_quoted(s, ',')?.into_iter()
+            .map(|key| key.trim().parse::<SortKey>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(SortQuery {
+            keys,
+        })
+    }
+}
+
+/// A measure, calculation or drilldown column (by its header, e.g. `Year` or
+/// `Month ID`), and a direction: `Exports.desc`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub direction: SortDirection,
+    pub column: String,
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.rsplitn(2, ".").collect::<Vec<_>>()[..] {
+            [direction, column] if !column.is_empty() => {
                 let direction = direction.parse::<SortDirection>()?;
-                Ok(SortQuery {
+                Ok(SortKey {
                     direction,
-                    measure,
+                    column: unquote(column),
                 })
             },
             _ => bail!("Could not parse a sort query"),
         }
-
     }
 }
 
@@ -369,11 +422,11 @@ impl FromStr for RcaQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
+        match &split_quoted(s, ',')?[..] {
             [drill_1, drill_2, measure] => {
                 let drill_1 = drill_1.parse::<Drilldown>()?;
                 let drill_2 = drill_2.parse::<Drilldown>()?;
-                let mea = measure.parse::<Measure>()?;
+                let mea = unquote(measure).parse::<Measure>()?;
 
                 Ok(RcaQuery {
                     drill_1,
@@ -409,10 +462,10 @@ impl FromStr for GrowthQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
+        match &split_quoted(s, ',')?[..] {
             [time_drill, measure] => {
                 let time_drill = time_drill.parse::<Drilldown>()?;
-                let mea = measure.parse::<Measure>()?;
+                let mea = unquote(measure).parse::<Measure>()?;
 
                 Ok(GrowthQuery {
                     time_drill,
@@ -425,6 +478,67 @@ impl FromStr for GrowthQuery {
     }
 }
 
+/// Each row's share of the total of `mea` among the members of the `drill` level, within
+/// the same members of the other drilldowns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareQuery {
+    pub drill: Drilldown,
+    pub mea: Measure,
+}
+
+impl FromStr for ShareQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &split_quoted(s, ',')?[..] {
+            [drill, measure] => {
+                let drill = drill.parse::<Drilldown>()?;
+                let mea = unquote(measure).parse::<Measure>()?;
+
+                Ok(ShareQuery {
+                    drill,
+                    mea,
+                })
+            },
+            _ => bail!("Could not parse a share query, wrong number of args"),
+        }
+    }
+}
+
+/// Constrains the rows to the members of a level whose property is one of `values`, e.g.
+/// `Geography.Country.Region:Europe,Asia`. The level doesn't need to be drilled down on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyFilter {
+    pub property: Property,
+    pub values: Vec<String>,
+}
+
+impl FromStr for PropertyFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &split_quoted(s, ':')?[..] {
+            [property, values] => {
+                let property = property.parse::<Property>()?;
+                let values: Vec<String> = split_quoted(values, ',')?
+                    .into_iter()
+                    .map(unquote)
+                    .collect();
+
+                if values.iter().any(|value| value.is_empty()) {
+                    bail!("Property filter on {} has an empty value", property);
+                }
+
+                Ok(PropertyFilter {
+                    property,
+                    values,
+                })
+            },
+            _ => bail!("Could not parse a property filter, expected `property:value,value`"),
+        }
+    }
+}
+
 /// For using an operator such as AND and OR in a sql query
 /// Currently used for the Filter and inner queries only
 #[derive(Debug, Clone, PartialEq)]
@@ -492,30 +606,23 @@ impl FromStr for FilterQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.contains(".and.") || s.contains(".or.") {
-            let filter_split: Vec<String> = s.split(".").map(|f| f.to_string()).collect();
-            let length = filter_split.len();
-            if length >= 6 || length <= 8 {
-                let op_index = filter_split.iter().position(|s| s == "and" || s=="or").unwrap();
-                get_filter(filter_split, op_index)
-            } else {
-                bail!("Could not parse a filter query")
-            }
-        } else {
-            match &s.splitn(2, ".").collect::<Vec<_>>()[..] {
-                [by_mea, constraint] => {
-                    let by_mea_or_calc = by_mea.parse::<MeaOrCalc>()?;
-                    let constraint = constraint.parse::<Constraint>()?;
-
-                    Ok(FilterQuery {
-                        by_mea_or_calc,
-                        constraint,
-                        operator: None,
-                        constraint2: None
-                    })
-                },
-                _ => bail!("Could not parse a filter query"),
-            }
+        let filter_split: Vec<String> = split_quoted(s, '.')?.into_iter().map(unquote).collect();
+
+        // the measure comes first, so it can be named `and` or `or`
+        match filter_split.iter().skip(1).position(|s| s == "and" || s == "or") {
+            Some(op_index) => get_filter(filter_split, op_index + 1),
+            None if filter_split.len() >= 2 => {
+                let by_mea_or_calc = filter_split[0].parse::<MeaOrCalc>()?;
+                let constraint = join(&filter_split[1..], ".").parse::<Constraint>()?;
+
+                Ok(FilterQuery {
+                    by_mea_or_calc,
+                    constraint,
+                    operator: None,
+                    constraint2: None
+                })
+            },
+            None => bail!("Could not parse a filter query"),
         }
     }
 }
@@ -540,7 +647,7 @@ impl FromStr for RateQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let rate_split: Vec<String> = s.split(".").map(|x| x.to_string()).collect();
+        let rate_split = split_quoted(s, '.')?;
         let n = rate_split.len();
 
         if n <= 2 || n >= 5 {
@@ -549,7 +656,10 @@ impl FromStr for RateQuery {
 
         let level = join(rate_split[0..n-1].iter(), ".");
         let level_name = level.parse::<LevelName>()?;
-        let values: Vec<String> = rate_split[n-1].split(",").map(|s| s.to_string()).collect();
+        let values: Vec<String> = split_quoted(rate_split[n-1], ',')?
+            .into_iter()
+            .map(unquote)
+            .collect();
 
         Ok(RateQuery{
             level_name,
@@ -559,10 +669,63 @@ impl FromStr for RateQuery {
 }
 
 
+/// Resource hint for the backend. Only backends that support it (currently
+/// ClickHouse) use it; others ignore it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl FromStr for QueryPriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(QueryPriority::Low),
+            "normal" => Ok(QueryPriority::Normal),
+            "high" => Ok(QueryPriority::High),
+            _ => Err(format_err!("Priority must be one of low, normal or high; found {}", s)),
+        }
+    }
+}
+
+
+/// Columns returned for drilldown levels with both a key and a name column. Levels
+/// without a name column always return their key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShowColumns {
+    /// Only the key columns, e.g. `State ID`; the name columns are not queried.
+    Ids,
+    /// Only the name columns. Keys are still queried, since members are grouped by
+    /// them, and dropped from the results.
+    Labels,
+    Both,
+}
+
+impl FromStr for ShowColumns {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ids" => Ok(ShowColumns::Ids),
+            "labels" => Ok(ShowColumns::Labels),
+            "both" => Ok(ShowColumns::Both),
+            _ => Err(format_err!("Show must be one of ids, labels or both; found {}", s)),
+        }
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::FilterQuery;
+    use super::PropertyFilter;
+    use super::RateQuery;
+    use super::ShareQuery;
     use super::Measure;
+    use crate::names::{Drilldown, LevelName};
     use crate::query::MeaOrCalc;
     use crate::query::{Constraint, Comparison};
     use std::str::FromStr;
@@ -600,4 +763,70 @@ mod tests {
         };
         assert_eq!(filter, target);
     }
+
+    #[test]
+    fn test_rate_query() {
+        let rate = RateQuery::from_str("Category.Category.Category.1,4").unwrap();
+        assert_eq!(rate.level_name, LevelName::new("Category", "Category", "Category"));
+        assert_eq!(rate.values, vec!["1".to_owned(), "4".to_owned()]);
+
+        // hierarchy may be dropped
+        let rate = RateQuery::from_str("Category.Category.1").unwrap();
+        assert_eq!(rate.level_name, LevelName::new("Category", "Category", "Category"));
+
+        assert!(RateQuery::from_str("Category.1").is_err());
+
+        // members with dots and commas are quoted
+        let rate = RateQuery::from_str("Geography.City.`Washington, D.C.`,Boston").unwrap();
+        assert_eq!(rate.level_name, LevelName::new("Geography", "Geography", "City"));
+        assert_eq!(rate.values, vec!["Washington, D.C.".to_owned(), "Boston".to_owned()]);
+    }
+
+    #[test]
+    fn test_share_query() {
+        let share = ShareQuery::from_str("Geography.Geography.State,Population").unwrap();
+        assert_eq!(share.drill, Drilldown(LevelName::new("Geography", "Geography", "State")));
+        assert_eq!(share.mea, Measure("Population".to_owned()));
+
+        // names with commas are quoted
+        let share = ShareQuery::from_str("Geography.Geography.`State, Territory`,`Population, Total`").unwrap();
+        assert_eq!(share.drill, Drilldown(LevelName::new("Geography", "Geography", "State, Territory")));
+        assert_eq!(share.mea, Measure("Population, Total".to_owned()));
+
+        assert!(ShareQuery::from_str("Geography.Geography.State").is_err());
+    }
+
+    #[test]
+    fn test_property_filter() {
+        let filter = PropertyFilter::from_str("Geography.Country.Region:Europe,`Asia: East`").unwrap();
+        assert_eq!(filter.property.level_name, LevelName::new("Geography", "Geography", "Country"));
+        assert_eq!(filter.property.property, "Region");
+        assert_eq!(filter.values, vec!["Europe".to_owned(), "Asia: East".to_owned()]);
+
+        assert!(PropertyFilter::from_str("Geography.Country.Region").is_err());
+        assert!(PropertyFilter::from_str("Geography.Country.Region:").is_err());
+    }
+    #[test]
+    fn test_quoted_calculation_names() {
+        let mea = || Measure("Exports, F.O.B.".to_owned());
+
+        let filter = FilterQuery::from_str("`Exports, F.O.B.`.gt.10.and.lt.20").unwrap();
+        assert_eq!(filter.by_mea_or_calc, MeaOrCalc::Mea(mea()));
+        assert_eq!(filter.constraint, Constraint { comparison: Comparison::GreaterThan, n: 10.0 });
+        assert_eq!(filter.constraint2, Some(Constraint { comparison: Comparison::LessThan, n: 20.0 }));
+
+        let growth = super::GrowthQuery::from_str("Time.Year,`Exports, F.O.B.`").unwrap();
+        assert_eq!(growth.mea, mea());
+
+        let top = super::TopQuery::from_str("10,Geography.`State, Territory`,`Exports, F.O.B.`,desc").unwrap();
+        assert_eq!(top.by_dimension, LevelName::new("Geography", "Geography", "State, Territory"));
+        assert_eq!(top.sort_mea_or_calc, vec![MeaOrCalc::Mea(mea())]);
+
+        let sort = super::SortQuery::from_str("`Exports, F.O.B.`.desc,Year.asc").unwrap();
+        assert_eq!(sort.keys[0].column, "Exports, F.O.B.");
+        assert_eq!(sort.keys[1].column, "Year");
+
+        let top_where = super::TopWhereQuery::from_str("`Exports, F.O.B.`,gt.10").unwrap();
+        assert_eq!(top_where.by_mea_or_calc, MeaOrCalc::Mea(mea()));
+    }
 }
@@ -0,0 +1,463 @@
+//! Structured query format: a JSON body with typed fields, mapped directly into a `Query`.
+//!
+//! Query params separate names with commas and dots, which breaks on names containing
+//! them; here each level is an object with its `dimension`, `hierarchy` and `level`, and
+//! members and measures are plain strings.
+//!
+//! ```json
+//! {
+//!     "version": 2,
+//!     "drilldowns": [{"dimension": "Date", "hierarchy": "Date", "level": "Year"}],
+//!     "cuts": [{
+//!         "level": {"dimension": "Geography", "hierarchy": "Geography", "level": "State"},
+//!         "members": ["04000US06"]
+//!     }],
+//!     "measures": ["Population"],
+//!     "filters": [{
+//!         "measure": "Population",
+//!         "constraints": [{"comparison": "lt", "value": 1000}, {"comparison": "gt", "value": 5000}],
+//!         "combinator": "or"
+//!     }]
+//! }
+//! ```
+//!
+//! The format is versioned, so that it can change without breaking clients; the only
+//! version so far is 2 (version 1 being the query params).
+
+use failure::{Error, bail};
+use serde_derive::Deserialize;
+use std::convert::TryFrom;
+
+use crate::names::{Cut, Drilldown, LevelName, Mask, Measure, Property};
+use crate::query::{
+    Constraint,
+    FilterQuery,
+    GrowthQuery,
+    LimitQuery,
+    MeaOrCalc,
+    PropertyFilter,
+    Query,
+    RateQuery,
+    RcaQuery,
+    ShareQuery,
+    ShowColumns,
+    SortKey,
+    SortQuery,
+    TopQuery,
+    TopWhereQuery,
+};
+use crate::schema::NullPolicy;
+
+
+pub const QUERY_BODY_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryBody {
+    pub version: u32,
+    #[serde(default)]
+    pub drilldowns: Vec<LevelName>,
+    #[serde(default)]
+    pub cuts: Vec<CutBody>,
+    #[serde(default)]
+    pub measures: Vec<String>,
+    #[serde(default)]
+    pub properties: Vec<PropertyBody>,
+    #[serde(default)]
+    pub captions: Vec<PropertyBody>,
+    #[serde(default)]
+    pub filters: Vec<FilterBody>,
+    /// Filters on the fact table rows, before aggregation.
+    #[serde(default, rename="where")]
+    pub row_filters: Vec<FilterBody>,
+    /// Filters on the property columns of dimension tables.
+    #[serde(default)]
+    pub property_filters: Vec<PropertyFilterBody>,
+    #[serde(default)]
+    pub parents: bool,
+    /// With `parents`, only include these ancestor levels.
+    #[serde(default)]
+    pub parent_levels: Vec<LevelName>,
+    pub top: Option<TopBody>,
+    pub top_where: Option<TopWhereBody>,
+    #[serde(default)]
+    pub sort: Vec<SortBody>,
+    pub limit: Option<LimitBody>,
+    pub growth: Option<GrowthBody>,
+    pub rca: Option<RcaBody>,
+    pub rate: Option<RateBody>,
+    pub share: Option<ShareBody>,
+    /// Measure compared with its value in the same period of the previous year.
+    pub vs_previous_year: Option<String>,
+    /// Calculates growth on the results instead of in the database; the server's default
+    /// if not set.
+    pub dataframe_growth: Option<bool>,
+    #[serde(default)]
+    pub sparse: bool,
+    #[serde(default)]
+    pub exclude_default_members: bool,
+    #[serde(default)]
+    pub distinct: bool,
+    #[serde(default)]
+    pub debug: bool,
+    /// `low`, `normal` or `high`.
+    pub priority: Option<String>,
+    /// `ids`, `labels` or `both`.
+    pub show: Option<String>,
+    pub treat_null_as: Option<NullPolicy>,
+    /// Headers of the columns to keep in the results, in order. Not part of the `Query`,
+    /// since the server drops the other columns from the results.
+    pub columns: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CutBody {
+    pub level: LevelName,
+    pub members: Vec<String>,
+    /// Excludes the members instead, keeping all the others.
+    #[serde(default)]
+    pub exclude: bool,
+    /// Matches members which contain each of `members`, instead of equal ones.
+    #[serde(default)]
+    pub for_match: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PropertyBody {
+    pub level: LevelName,
+    pub property: String,
+}
+
+/// A measure (or `rca`/`growth`) and one or two constraints on it. Two constraints need a
+/// `combinator`, `and` or `or`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterBody {
+    pub measure: String,
+    pub constraints: Vec<ConstraintBody>,
+    pub combinator: Option<String>,
+}
+
+/// `comparison` is one of `eq`, `neq`, `lt`, `lte`, `gt` or `gte`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConstraintBody {
+    pub comparison: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopBody {
+    pub n: u64,
+    pub by: LevelName,
+    pub measure: String,
+    pub direction: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopWhereBody {
+    pub measure: String,
+    pub constraint: ConstraintBody,
+}
+
+/// `column` is a measure, calculation or drilldown column header.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SortBody {
+    pub column: String,
+    pub direction: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitBody {
+    pub n: u64,
+    pub offset: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrowthBody {
+    pub level: LevelName,
+    pub measure: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RcaBody {
+    pub level_1: LevelName,
+    pub level_2: LevelName,
+    pub measure: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PropertyFilterBody {
+    pub level: LevelName,
+    pub property: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareBody {
+    pub level: LevelName,
+    pub measure: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateBody {
+    pub level: LevelName,
+    pub members: Vec<String>,
+}
+
+
+impl TryFrom<QueryBody> for Query {
+    type Error = Error;
+
+    fn try_from(body: QueryBody) -> Result<Self, Self::Error> {
+        if body.version != QUERY_BODY_VERSION {
+            bail!("Unsupported query version {}; the supported version is {}", body.version, QUERY_BODY_VERSION);
+        }
+
+        let mut query = Query::new();
+
+        query.drilldowns = body.drilldowns.into_iter().map(Drilldown).collect();
+        query.cuts = body.cuts.into_iter()
+            .map(|cut| {
+                if cut.members.is_empty() {
+                    bail!("Cut on {} has no members", cut.level);
+                }
+                Ok(Cut {
+                    level_name: cut.level,
+                    members: cut.members,
+                    mask: if cut.exclude { Mask::Exclude } else { Mask::Include },
+                    for_match: cut.for_match,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        query.measures = body.measures.into_iter().map(Measure).collect();
+        query.properties = body.properties.into_iter().map(PropertyBody::into_property).collect();
+        query.captions = body.captions.into_iter().map(PropertyBody::into_property).collect();
+        query.filters = body.filters.into_iter().map(FilterBody::into_filter).collect::<Result<_, _>>()?;
+        query.row_filters = body.row_filters.into_iter().map(FilterBody::into_filter).collect::<Result<_, _>>()?;
+        query.property_filters = body.property_filters.into_iter()
+            .map(|filter| {
+                if filter.values.is_empty() {
+                    bail!("Property filter on {} has no values", filter.property);
+                }
+                Ok(PropertyFilter {
+                    property: Property {
+                        level_name: filter.level,
+                        property: filter.property,
+                    },
+                    values: filter.values,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        query.parents = body.parents;
+        query.parent_levels = body.parent_levels;
+
+        query.top = body.top
+            .map(|top| -> Result<_, Error> {
+                Ok(TopQuery::new(
+                    top.n,
+                    top.by,
+                    vec![top.measure.parse::<MeaOrCalc>()?],
+                    top.direction.parse()?,
+                ))
+            })
+            .transpose()?;
+        query.top_where = body.top_where
+            .map(|top_where| -> Result<_, Error> {
+                Ok(TopWhereQuery {
+                    by_mea_or_calc: top_where.measure.parse()?,
+                    constraint: top_where.constraint.into_constraint()?,
+                })
+            })
+            .transpose()?;
+
+        if !body.sort.is_empty() {
+            let keys = body.sort.into_iter()
+                .map(|key| -> Result<_, Error> {
+                    Ok(SortKey {
+                        direction: key.direction.parse()?,
+                        column: key.column,
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            query.sort = Some(SortQuery { keys });
+        }
+
+        query.limit = body.limit.map(|limit| LimitQuery { offset: limit.offset, n: limit.n });
+        query.growth = body.growth.map(|growth| GrowthQuery {
+            time_drill: Drilldown(growth.level),
+            mea: Measure(growth.measure),
+        });
+        query.rca = body.rca.map(|rca| RcaQuery {
+            drill_1: Drilldown(rca.level_1),
+            drill_2: Drilldown(rca.level_2),
+            mea: Measure(rca.measure),
+        });
+        query.rate = body.rate.map(|rate| RateQuery::new(rate.level, rate.members));
+        query.share = body.share.map(|share| ShareQuery {
+            drill: Drilldown(share.level),
+            mea: Measure(share.measure),
+        });
+        query.vs_previous_year = body.vs_previous_year.map(Measure);
+
+        query.sparse = body.sparse;
+        query.exclude_default_members = body.exclude_default_members;
+        query.distinct = body.distinct;
+        query.debug = body.debug;
+        query.priority = body.priority.map(|p| p.parse()).transpose()?;
+        query.show = body.show.map(|show| show.parse()).transpose()?.unwrap_or(ShowColumns::Both);
+        query.treat_null_as = body.treat_null_as;
+
+        Ok(query)
+    }
+}
+
+impl PropertyBody {
+    fn into_property(self) -> Property {
+        Property {
+            level_name: self.level,
+            property: self.property,
+        }
+    }
+}
+
+impl FilterBody {
+    fn into_filter(self) -> Result<FilterQuery, Error> {
+        let by_mea_or_calc = self.measure.parse::<MeaOrCalc>()?;
+        let mut constraints = self.constraints.into_iter();
+
+        let (constraint, constraint2) = match (constraints.next(), constraints.next(), constraints.next()) {
+            (Some(constraint), constraint2, None) => (constraint.into_constraint()?, constraint2),
+            (None, _, _) => bail!("Filter on {} has no constraints", self.measure),
+            _ => bail!("Filter on {} has more than two constraints", self.measure),
+        };
+
+        let (operator, constraint2) = match (self.combinator, constraint2) {
+            (Some(combinator), Some(constraint2)) => (Some(combinator.parse()?), Some(constraint2.into_constraint()?)),
+            (None, None) => (None, None),
+            (None, Some(_)) => bail!("Filter on {} needs a combinator for its two constraints", self.measure),
+            (Some(_), None) => bail!("Filter on {} has a combinator but only one constraint", self.measure),
+        };
+
+        Ok(FilterQuery {
+            by_mea_or_calc,
+            constraint,
+            operator,
+            constraint2,
+        })
+    }
+}
+
+impl ConstraintBody {
+    fn into_constraint(self) -> Result<Constraint, Error> {
+        Ok(Constraint {
+            comparison: self.comparison.parse()?,
+            n: self.value,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query::{Comparison, Operator, SortDirection};
+
+    #[test]
+    fn test_query_body() {
+        let body: QueryBody = serde_json::from_str(r#"{
+            "version": 2,
+            "drilldowns": [{"dimension": "Geography", "hierarchy": "Geography", "level": "County, State"}],
+            "cuts": [{
+                "level": {"dimension": "Date", "hierarchy": "Date", "level": "Year"},
+                "members": ["2018", "2019"],
+                "exclude": true
+            }],
+            "measures": ["Pop. Total"],
+            "filters": [{
+                "measure": "Pop. Total",
+                "constraints": [{"comparison": "lt", "value": 10}, {"comparison": "gt", "value": 20.5}],
+                "combinator": "or"
+            }],
+            "sort": [{"column": "Pop. Total", "direction": "desc"}],
+            "limit": {"n": 10},
+            "priority": "low"
+        }"#).unwrap();
+
+        let query = Query::try_from(body).unwrap();
+
+        assert_eq!(query.drilldowns, vec![Drilldown::new("Geography", "Geography", "County, State")]);
+        assert_eq!(query.cuts[0].level_name, LevelName::new("Date", "Date", "Year"));
+        assert_eq!(query.cuts[0].members, vec!["2018".to_owned(), "2019".to_owned()]);
+        assert_eq!(query.cuts[0].mask, Mask::Exclude);
+        assert_eq!(query.measures, vec![Measure::new("Pop. Total")]);
+        assert_eq!(query.filters[0].by_mea_or_calc, MeaOrCalc::Mea(Measure::new("Pop. Total")));
+        assert_eq!(query.filters[0].constraint.comparison, Comparison::LessThan);
+        assert_eq!(query.filters[0].operator, Some(Operator::Or));
+        assert_eq!(query.filters[0].constraint2.as_ref().unwrap().n, 20.5);
+        assert_eq!(query.sort.unwrap().keys[0].direction, SortDirection::Desc);
+        assert_eq!(query.limit.unwrap().n, 10);
+        assert!(query.priority.is_some());
+    }
+
+    #[test]
+    fn test_query_body_calculations() {
+        let body: QueryBody = serde_json::from_str(r#"{
+            "version": 2,
+            "drilldowns": [{"dimension": "Date", "hierarchy": "Date", "level": "Month"}],
+            "measures": ["Sales"],
+            "property_filters": [{
+                "level": {"dimension": "Geography", "hierarchy": "Geography", "level": "State"},
+                "property": "Region",
+                "values": ["West"]
+            }],
+            "share": {"level": {"dimension": "Date", "hierarchy": "Date", "level": "Month"}, "measure": "Sales"},
+            "vs_previous_year": "Sales",
+            "dataframe_growth": true,
+            "show": "labels",
+            "treat_null_as": "zero",
+            "columns": ["Month", "Sales"]
+        }"#).unwrap();
+
+        assert_eq!(body.dataframe_growth, Some(true));
+        assert_eq!(body.columns, Some(vec!["Month".to_owned(), "Sales".to_owned()]));
+
+        let query = Query::try_from(body).unwrap();
+
+        assert_eq!(query.property_filters[0].property.property, "Region");
+        assert_eq!(query.property_filters[0].values, vec!["West".to_owned()]);
+        assert_eq!(query.share.unwrap().mea, Measure::new("Sales"));
+        assert_eq!(query.vs_previous_year, Some(Measure::new("Sales")));
+        assert_eq!(query.show, ShowColumns::Labels);
+        assert_eq!(query.treat_null_as, Some(NullPolicy::Zero));
+    }
+
+    #[test]
+    fn test_query_body_errors() {
+        let query = |json: &str| -> Result<Query, Error> {
+            let body: QueryBody = serde_json::from_str(json)?;
+            Query::try_from(body)
+        };
+
+        assert!(query(r#"{"version": 1, "measures": ["Sales"]}"#).is_err());
+        assert!(query(r#"{"version": 2, "measure": ["Sales"]}"#).is_err());
+        assert!(query(r#"{"version": 2, "filters": [{"measure": "Sales", "constraints": []}]}"#).is_err());
+        assert!(query(r#"{"version": 2, "filters": [{
+            "measure": "Sales",
+            "constraints": [{"comparison": "lt", "value": 1}, {"comparison": "gt", "value": 2}]
+        }]}"#).is_err());
+        assert!(query(r#"{"version": 2, "sort": [{"column": "Sales", "direction": "up"}]}"#).is_err());
+        assert!(query(r#"{"version": 2, "show": "names"}"#).is_err());
+    }
+}
@@ -1,13 +1,15 @@
 use itertools::join;
 use serde_derive::{Deserialize, Serialize};
 
+use crate::dataframe::{CalculationColumns, GrowthColumns, PreviousYearColumns, ShareColumns};
+use crate::dialect::{ResultOrder, SqlDialect, plain_identifier, quote_column, quote_qualified, sample_percent};
 use crate::names::Mask;
-use crate::query::{LimitQuery, SortDirection, Constraint, Operator};
-use crate::schema::{Table, InlineTable};
+use crate::query::{Calculation, LimitQuery, SortDirection, Constraint, Operator, QueryPriority};
+use crate::schema::{DistributedTable, Table, TableJoin, InlineTable};
 use crate::schema::aggregator::Aggregator;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct QueryIr {
     pub table: TableSql,
     pub cuts: Vec<CutSql>,
@@ -15,21 +17,288 @@ pub struct QueryIr {
     pub meas: Vec<MeasureSql>,
     pub hidden_drills: Vec<HiddenDrilldownSql>,
     pub filters: Vec<FilterSql>,
+    pub row_filters: Vec<RowFilterSql>,
     // TODO put Filters and Calculations into own structs
     pub top: Option<TopSql>,
     pub top_where: Option<TopWhereSql>,
-    pub sort: Option<SortSql>,
+    pub sort: Vec<SortSql>,
     pub limit: Option<LimitSql>,
     pub rca: Option<RcaSql>,
     pub growth: Option<GrowthSql>,
+    /// Not in the sql; handlers take it to calculate on the results, with
+    /// `DataFrame::add_share`.
+    pub share: Option<ShareColumns>,
+    /// Not in the sql; handlers take it to compare with the previous year, by wrapping the
+    /// sql with `SqlDialect::previous_year_sql`, or else on the results, with
+    /// `DataFrame::add_previous_year`.
+    pub previous_year: Option<PreviousYearColumns>,
     pub rate: Option<RateSql>,
     pub sparse: bool,
+    pub priority: Option<QueryPriority>,
+    /// Set when the fact table is distributed, for backends with settings for them.
+    pub distributed: Option<DistributedTable>,
+    /// Result columns of the measures whose nulls are returned as zeros. Not in the sql;
+    /// handlers take it to fill the results, with `DataFrame::fill_null_zeros`.
+    pub null_zero_idxs: Vec<usize>,
+    /// Result columns of the keys of levels with a `key_width`, with the width. Not in the
+    /// sql; handlers take it to pad the results, with `DataFrame::pad_keys`.
+    pub key_widths: Vec<(usize, usize)>,
+    /// Drilldowns on the All level of a hierarchy, which aren't in the sql. Handlers take
+    /// them to add the All member columns to the results, with `DataFrame::insert_all_members`.
+    /// The other column indexes are of the results with these columns.
+    pub all_members: Vec<AllMemberColumn>,
+}
+
+impl QueryIr {
+    /// Removes the growth calculation, for dialects which can't generate its sql. Returns
+    /// the columns of the results needed to calculate it with `DataFrame::with_growth`,
+    /// and the sort and limit to apply to them. These are removed from the sql too, since
+    /// growth needs all the rows, and the sort can be on the growth itself.
+    pub fn take_growth(&mut self) -> Option<GrowthColumns> {
+        let growth = self.growth.take()?;
+
+        let mut drill_count = 0;
+        let mut time_idxs = vec![];
+
+        for drill in &self.drills {
+            let col_count = drill.col_count();
+            if *drill == growth.time_drill {
+                time_idxs.extend(drill_count..drill_count + col_count);
+            }
+            drill_count += col_count;
+        }
+
+        let mea_idx: usize = growth.mea.trim_start_matches("final_m")
+            .parse()
+            .expect("growth.mea must be final_m{idx}");
+
+        let mut growth_columns = GrowthColumns {
+            drill_count,
+            time_idxs,
+            mea_idx: drill_count + mea_idx,
+            mea_count: self.meas.len(),
+            sort: vec![],
+            limit: self.limit.take(),
+        };
+
+        let mea_count = self.meas.len();
+        growth_columns.sort = self.sort.drain(..)
+            .filter_map(|s| {
+                let idx = match s.column_idx {
+                    Some(idx) => growth_columns.result_idx(idx),
+                    None if s.column == Calculation::Growth.sql_string() => drill_count + mea_count,
+                    None => return None,
+                };
+                Some((idx, s.direction))
+            })
+            .collect();
+
+        Some(growth_columns)
+    }
+
+    /// Columns of the results used by `DataFrame::add_calculations`, where `time_drill` is
+    /// the index of the time drilldown in the query, if there is one.
+    pub fn calculation_columns(&self, time_drill: Option<usize>) -> CalculationColumns {
+        let mut drill_count = 0;
+        let mut time_idxs = vec![];
+
+        // drilldowns on All levels have one column, and aren't in `drills`
+        let mut drills = self.drills.iter();
+
+        for i in 0..self.drills.len() + self.all_members.len() {
+            let col_count = if self.all_members.iter().any(|all| all.drill_idx == i) {
+                1
+            } else {
+                drills.next().map(|drill| drill.col_count()).unwrap_or(0)
+            };
+            if Some(i) == time_drill {
+                time_idxs.extend(drill_count..drill_count + col_count);
+            }
+            drill_count += col_count;
+        }
+
+        CalculationColumns {
+            drill_count,
+            time_idxs,
+            mea_idxs: (drill_count..drill_count + self.meas.len()).collect(),
+        }
+    }
+
+    /// Columns of the results with the key of each drilldown's level, which the drilldown's
+    /// other columns (its parents, names and properties) depend on. Drilldowns on All levels
+    /// have one column, and aren't in `drills`.
+    pub fn drill_key_idxs(&self) -> Vec<usize> {
+        let mut drills = self.drills.iter();
+        let mut idx = 0;
+        let mut key_idxs = vec![];
+
+        for i in 0..self.drills.len() + self.all_members.len() {
+            if self.all_members.iter().any(|all| all.drill_idx == i) {
+                key_idxs.push(idx);
+                idx += 1;
+            } else if let Some(drill) = drills.next() {
+                // the level's columns come after its parents'
+                let parent_cols: usize = drill.level_columns.iter()
+                    .rev()
+                    .skip(1)
+                    .map(|l| if l.name_column.is_some() { 2 } else { 1 })
+                    .sum();
+
+                key_idxs.push(idx + parent_cols);
+                idx += drill.col_count();
+            }
+        }
+
+        key_idxs
+    }
+
+    /// Columns of the results of each drilldown: its level's key, names, parents and
+    /// properties. Drilldowns on All levels have one column, and aren't in `drills`.
+    pub fn drill_col_idxs(&self) -> Vec<Vec<usize>> {
+        let mut drills = self.drills.iter();
+        let mut idx = 0;
+        let mut col_idxs = vec![];
+
+        for i in 0..self.drills.len() + self.all_members.len() {
+            let col_count = if self.all_members.iter().any(|all| all.drill_idx == i) {
+                1
+            } else {
+                drills.next().map(|drill| drill.col_count()).unwrap_or(0)
+            };
+            col_idxs.push((idx..idx + col_count).collect());
+            idx += col_count;
+        }
+
+        col_idxs
+    }
+
+    /// The sort of the results of the query's sql, for `SqlDialect::preview_sql`: its sort
+    /// on result columns, then, with a limit, the drilldown keys which break its ties.
+    pub fn result_order(&self) -> ResultOrder {
+        let drill_count: usize = self.drills.iter().map(|drill| drill.col_count()).sum();
+        let rate_count = if self.rate.is_some() { 1 } else { 0 };
+
+        let mut sort: Vec<_> = self.sort.iter()
+            .filter_map(|s| s.column_idx.map(|idx| (idx, s.direction.clone())))
+            .collect();
+
+        if self.limit.is_some() {
+            let mut idx = 0;
+            for drill in &self.drills {
+                for level_col in &drill.level_columns {
+                    sort.push((idx, SortDirection::Asc));
+                    idx += if level_col.name_column.is_some() { 2 } else { 1 };
+                }
+                idx += drill.property_columns.len();
+            }
+        }
+
+        ResultOrder {
+            column_count: drill_count + self.meas.len() + rate_count,
+            sort,
+        }
+    }
+
+    /// Escapes the cut and rate members for the dialect's string literals, and quotes the
+    /// table and column names which need it (see `quote_column`). Column aliases are built
+    /// from plain identifiers, so they don't change. Dialects call this once, before
+    /// generating sql.
+    pub fn escape(&mut self, dialect: &dyn SqlDialect) {
+        let quote_col = |col: &mut String| *col = quote_column(dialect, col);
+        let quote_mea = |mea: &mut MeasureSql| {
+            mea.column = quote_column(dialect, &mea.column);
+            if mea.null_zero {
+                mea.column = format!("coalesce({}, 0)", mea.column);
+                mea.null_zero = false;
+            }
+        };
+        let quote_table = |table: &mut Table| {
+            table.name = quote_qualified(dialect, &table.name);
+            table.schema = table.schema.as_ref().map(|schema| quote_qualified(dialect, schema));
+        };
+        let quote_joins = |joins: &mut Vec<TableJoin>| {
+            for join in joins {
+                quote_table(&mut join.table);
+                quote_col(&mut join.foreign_key);
+                quote_col(&mut join.primary_key);
+            }
+        };
+        let quote_drill = |drill: &mut DrilldownSql| {
+            quote_table(&mut drill.table);
+            quote_joins(&mut drill.joins);
+            quote_col(&mut drill.primary_key);
+            quote_col(&mut drill.foreign_key);
+            for level_col in &mut drill.level_columns {
+                quote_col(&mut level_col.key_column);
+                level_col.name_column.iter_mut().for_each(|col| quote_col(col));
+            }
+            drill.property_columns.iter_mut().for_each(|col| quote_col(col));
+        };
+
+        self.table.name = quote_qualified(dialect, &self.table.name);
+        self.table.primary_key.iter_mut().for_each(|col| quote_col(col));
+
+        self.drills.iter_mut().for_each(|drill| quote_drill(drill));
+        self.hidden_drills.iter_mut().for_each(|drill| quote_drill(&mut drill.drilldown_sql));
+        for cut in &mut self.cuts {
+            quote_table(&mut cut.table);
+            quote_joins(&mut cut.joins);
+            quote_col(&mut cut.primary_key);
+            quote_col(&mut cut.foreign_key);
+            quote_col(&mut cut.column);
+            cut.members = cut.members.iter().map(|m| dialect.escape_literal(m)).collect();
+        }
+        self.meas.iter_mut().for_each(|mea| quote_mea(mea));
+        self.row_filters.iter_mut().for_each(|filter| quote_col(&mut filter.column));
+        // sorts are on the aliases of the selected columns: measures' `final_m` aliases, or
+        // drilldown column aliases, which have the drilldown's alias postfix, for properties
+        // too (see `DrilldownSql::property_alias`)
+        self.sort.iter_mut().for_each(|sort| sort.column = dialect.quote_identifier(&sort.column));
+        if let Some(ref mut rca) = self.rca {
+            rca.drill_1.iter_mut().chain(rca.drill_2.iter_mut()).for_each(|drill| quote_drill(drill));
+            quote_mea(&mut rca.mea);
+        }
+        if let Some(ref mut growth) = self.growth {
+            quote_drill(&mut growth.time_drill);
+        }
+        if let Some(ref mut rate) = self.rate {
+            quote_drill(&mut rate.drilldown_sql);
+            rate.members = rate.members.iter().map(|m| dialect.escape_literal(m)).collect();
+        }
+    }
+}
+
+/// A member as a sql literal. Members of non-text levels go in as is, unless they aren't
+/// numbers; then they're quoted, so that they can't change the sql. Leading zeros of
+/// non-text members are kept as numbers, compared against a numeric column: codes like
+/// FIPS keep their zeros with a text `key_type`, whose members are always quoted, or with
+/// a `key_width`, whose members are unpadded before they get here.
+/// Text members must have been escaped already (see `QueryIr::escape`).
+pub fn member_literal(member: &str, member_type: &MemberType) -> String {
+    let is_number = member.parse::<f64>().is_ok()
+        && member.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+
+    match member_type {
+        MemberType::NonText if is_number => member.to_owned(),
+        _ => format!("'{}'", member),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct TableSql {
     pub name: String,
     pub primary_key: Option<String>,
+    /// Fraction of the fact table rows to read, for backends which can sample tables.
+    pub sample: Option<f64>,
+    /// Predicate on the fact table from the cube's `sql_where`, applied to every scan of it.
+    pub sql_where: Option<String>,
+}
+
+impl TableSql {
+    /// The sample as a percentage, rounded to 4 decimals so it formats cleanly.
+    pub fn sample_percent(&self) -> Option<f64> {
+        self.sample.map(sample_percent)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -41,6 +310,7 @@ pub struct DrilldownSql {
     pub level_columns: Vec<LevelColumn>,
     pub property_columns: Vec<String>,
     pub inline_table: Option<InlineTable>,
+    pub joins: Vec<TableJoin>,
 }
 
 impl DrilldownSql {
@@ -57,26 +327,26 @@ impl DrilldownSql {
         let mut cols: Vec<_> = self.level_columns.iter()
             .map(|l| {
                 if let Some(ref name_col) = l.name_column {
-                    format!("{} as {}_{}, {} as {}_{}",
-                        l.key_column,
+                    format!("{} as {}, {} as {}",
                         l.key_column,
-                        self.alias_postfix,
+                        l.key_alias(&self.alias_postfix),
                         name_col,
-                        name_col,
-                        self.alias_postfix,
+                        l.name_alias(&self.alias_postfix).unwrap_or_default(),
                     )
                 } else {
-                    format!("{} as {}_{}",
-                        l.key_column,
+                    format!("{} as {}",
                         l.key_column,
-                        self.alias_postfix,
+                        l.key_alias(&self.alias_postfix),
                     )
                 }
             }).collect();
 
         if self.property_columns.len() != 0 {
+            let prop_cols = self.property_columns.iter()
+                .map(|p| format!("{} as {}", p, self.property_alias(p)));
+
             cols.push(
-                join(&self.property_columns, ", ")
+                join(prop_cols, ", ")
             );
         }
 
@@ -96,47 +366,83 @@ impl DrilldownSql {
         // This matters because growth needs the vec
         // version to map onto single cols.
         for l in self.level_columns.iter() {
-            cols.push(format!("{}_{}",
-                l.key_column,
-                self.alias_postfix,
-            ));
-
-            if let Some(ref name_col) = l.name_column {
-                cols.push(format!("{}_{}",
-                    name_col,
-                    self.alias_postfix,
-                ));
+            cols.push(l.key_alias(&self.alias_postfix));
+
+            if let Some(name_alias) = l.name_alias(&self.alias_postfix) {
+                cols.push(name_alias);
             }
         }
 
         if self.property_columns.len() != 0 {
+            let prop_aliases = self.property_columns.iter()
+                .map(|p| self.property_alias(p));
+
             cols.push(
-                join(&self.property_columns, ", ")
+                join(prop_aliases, ", ")
             );
         }
 
         cols
     }
 
+    /// The alias of each column selected for the drilldown, in the order of `col_count`.
+    pub fn col_aliases(&self) -> Vec<String> {
+        let mut aliases = vec![];
+
+        for l in self.level_columns.iter() {
+            aliases.push(l.key_alias(&self.alias_postfix));
+            aliases.extend(l.name_alias(&self.alias_postfix));
+        }
+        aliases.extend(self.property_columns.iter().map(|p| self.property_alias(p)));
+
+        aliases
+    }
+
+    /// Alias of a property column, with the drilldown's alias postfix like the level
+    /// columns, so that the same property column can be selected in several drilldowns.
+    pub fn property_alias(&self, property_column: &str) -> String {
+        format!("{}_{}", plain_identifier(property_column), self.alias_postfix)
+    }
+
+    /// Number of columns selected for the drilldown: the key column of each level, its
+    /// name column if any, and the property columns.
+    pub fn col_count(&self) -> usize {
+        let name_cols = self.level_columns.iter()
+            .filter(|l| l.name_column.is_some())
+            .count();
+
+        self.level_columns.len() + name_cols + self.property_columns.len()
+    }
+
     pub fn col_qual_string(&self) -> String {
         let cols = self.col_qual_vec();
         join(cols, ", ")
     }
 
+    /// Columns of snowflake dimensions may live in any table of the join
+    /// chain, so they are left unqualified (and must be unambiguous).
+    pub fn col_qual(&self, col: &str) -> String {
+        if self.joins.is_empty() {
+            format!("{}.{}", self.table.name, col)
+        } else {
+            col.to_owned()
+        }
+    }
+
     fn col_qual_vec(&self) -> Vec<String> {
         let mut cols: Vec<_> = self.level_columns.iter()
             .map(|l| {
                 if let Some(ref name_col) = l.name_column {
-                    format!("{}.{}, {}.{}", self.table.name, l.key_column, self.table.name, name_col)
+                    format!("{}, {}", self.col_qual(&l.key_column), self.col_qual(name_col))
                 } else {
-                    format!("{}.{}", self.table.name, l.key_column)
+                    self.col_qual(&l.key_column)
                 }
             }).collect();
 
         if self.property_columns.len() != 0 {
             let prop_cols_qual = self.property_columns.iter()
                 .map(|p| {
-                    format!("{}.{}", self.table.name, p)
+                    self.col_qual(p)
                 });
 
             cols.push(
@@ -161,6 +467,26 @@ pub struct LevelColumn {
     pub name_column: Option<String>,
 }
 
+impl LevelColumn {
+    /// Alias of the key column in a drilldown with the alias postfix `postfix`.
+    pub fn key_alias(&self, postfix: &str) -> String {
+        format!("{}_{}", plain_identifier(&self.key_column), postfix)
+    }
+
+    /// Alias of the name column in a drilldown with the alias postfix `postfix`. A level
+    /// whose name column is also its key column gets a `_label` alias, so that the two
+    /// don't clash.
+    pub fn name_alias(&self, postfix: &str) -> Option<String> {
+        self.name_column.as_ref().map(|name_col| {
+            if *name_col == self.key_column {
+                format!("{}_{}_label", plain_identifier(name_col), postfix)
+            } else {
+                format!("{}_{}", plain_identifier(name_col), postfix)
+            }
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CutSql {
     pub table: Table,
@@ -174,20 +500,15 @@ pub struct CutSql {
     // if for_match, then use LIKE syntax
     pub for_match: bool,
     pub inline_table: Option<InlineTable>,
+    pub joins: Vec<TableJoin>,
 }
 
 impl CutSql {
     pub fn members_string(&self) -> String {
-        let members = match self.member_type {
-            MemberType::NonText => join(&self.members, ", "),
-            MemberType::Text => {
-                let quoted = self.members.iter()
-                .map(|m| format!("'{}'", m));
-                join(quoted, ", ")
-            }
-        };
+        let members = self.members.iter()
+            .map(|m| member_literal(m, &self.member_type));
 
-        format!("{}", members)
+        join(members, ", ")
     }
 
     pub fn members_like_string(&self) -> String {
@@ -196,7 +517,7 @@ impl CutSql {
                 // this behavior doesn't really make sense; it should be for
                 // labels only, which are almost always strings.
                 let unquoted = self.members.iter()
-                    .map(|m| format!("{} {} {}", self.column, self.mask_sql_like_string(), m));
+                    .map(|m| format!("{} {} {}", self.column, self.mask_sql_like_string(), member_literal(m, &self.member_type)));
 
                 match self.mask {
                     Mask::Include => format!("({})", join(unquoted, " or ")),
@@ -216,7 +537,12 @@ impl CutSql {
     }
 
     pub fn col_qual_string(&self) -> String {
-        format!("{}.{}", self.table.name, self.column)
+        // see DrilldownSql::col_qual
+        if self.joins.is_empty() {
+            format!("{}.{}", self.table.name, self.column)
+        } else {
+            self.column.clone()
+        }
     }
 
     pub fn mask_sql_in_string(&self) -> String {
@@ -246,6 +572,9 @@ pub enum MemberType {
 pub struct MeasureSql {
     pub aggregator: Aggregator,
     pub column: String,
+    /// Nulls are aggregated as zeros. `QueryIr::escape` wraps the quoted column in a
+    /// `coalesce`, since the expression itself wouldn't be quoted.
+    pub null_zero: bool,
 }
 
 // NOTE: This is now specific to each db, because of the custom aggregators
@@ -256,6 +585,16 @@ pub struct MeasureSql {
 //    }
 //}
 
+/// The column of the results with the All member of a drilldown on an All level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllMemberColumn {
+    /// Index of the column in the results.
+    pub idx: usize,
+    /// Index of the drilldown in the query.
+    pub drill_idx: usize,
+    pub member: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct TopSql {
     pub n: u64,
@@ -280,7 +619,32 @@ pub struct FilterSql {
 }
 
 
-#[derive(Debug, Clone)]
+/// A filter on a measure's column in each row of the fact table, before aggregation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowFilterSql {
+    pub column: String,
+    pub constraint: Constraint,
+    pub operator: Option<Operator>,
+    pub constraint2: Option<Constraint>,
+}
+
+impl RowFilterSql {
+    pub fn sql_string(&self) -> String {
+        match (&self.operator, &self.constraint2) {
+            (Some(operator), Some(constraint2)) => format!("({} {} {} {} {})",
+                self.column,
+                self.constraint.sql_string(),
+                operator.sql_string(),
+                self.column,
+                constraint2.sql_string(),
+            ),
+            _ => format!("{} {}", self.column, self.constraint.sql_string()),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LimitSql {
     pub offset: Option<u64>,
     pub n: u64,
@@ -298,7 +662,11 @@ impl From<LimitQuery> for LimitSql {
 #[derive(Debug, Clone, PartialEq)]
 pub struct SortSql {
     pub direction: SortDirection,
+    /// Column alias in the final select.
     pub column: String,
+    /// Position of the column in the results, for sorting by ordinal.
+    /// `None` for calculations.
+    pub column_idx: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -321,6 +689,16 @@ pub struct GrowthSql {
 pub struct RateSql {
     pub drilldown_sql: DrilldownSql,
     pub members: Vec<String>,
+    pub member_type: MemberType,
+}
+
+impl RateSql {
+    pub fn members_string(&self) -> String {
+        let members = self.members.iter()
+            .map(|m| member_literal(m, &self.member_type));
+
+        join(members, ", ")
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -343,7 +721,7 @@ pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSu
                     let inline_table_sql = it.sql_string();
                     format!("({}) as {}", inline_table_sql, it.alias)
                 },
-                None => drill.table.full_name()
+                None => drill.table.join_chain_sql(&drill.joins)
             };
 
             // TODO
@@ -380,7 +758,7 @@ pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSu
                 let sql = format!("select {} as {} from {} where {} in ({})",
                     cut.primary_key.clone(),
                     cut.foreign_key.clone(),
-                    cut.table.full_name(),
+                    cut.table.join_chain_sql(&cut.joins),
                     cut.column.clone(),
                     cut.members_string(),
                 );
@@ -1,9 +1,13 @@
 use serde_derive::{Serialize, Deserialize};
 use std::convert::From;
-use failure::{Error, format_err};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use failure::{Error, bail, format_err};
 
 pub mod aggregator;
 pub mod metadata;
+pub mod scaffold;
 mod json;
 mod xml;
 
@@ -17,8 +21,10 @@ pub use crate::schema::{
     json::LevelConfigJson,
     json::MeasureConfigJson,
     json::TableConfigJson,
+    json::TableJoinConfigJson,
     json::PropertyConfigJson,
     json::AnnotationConfigJson,
+    json::RequiredCutConfigJson,
     json::InlineTableJson,
     json::InlineTableColumnDefinitionJson,
     json::InlineTableRowJson,
@@ -29,9 +35,14 @@ pub use crate::schema::{
     xml::LevelConfigXML,
     xml::MeasureConfigXML,
     xml::TableConfigXML,
+    xml::TableJoinConfigXML,
     xml::PropertyConfigXML,
 };
-use crate::names::{LevelName, Measure as MeasureName, Property as TsProperty};
+use itertools::join;
+
+use crate::dataframe::{ColumnData, DataFrame, pad_key};
+use crate::dialect::{SqlDialect, quote_qualified};
+use crate::names::{Cut, LevelName, Mask, Measure as MeasureName, Property as TsProperty};
 use crate::query_ir::MemberType;
 pub use self::aggregator::Aggregator;
 use crate::DEFAULT_ALLOWED_ACCESS;
@@ -117,7 +128,8 @@ impl From<SchemaConfigJson> for Schema {
                                     default_hierarchy: shared_dim_config.default_hierarchy.clone(),
                                     dim_type,
                                     annotations: dim_annotations,
-                                    is_shared: true
+                                    is_shared: true,
+                                    shared_source: Some(shared_dim_config.name.clone()),
                                 });
                             }
                         }
@@ -133,15 +145,36 @@ impl From<SchemaConfigJson> for Schema {
 
             let min_auth_level = cube_config.min_auth_level.unwrap_or(DEFAULT_ALLOWED_ACCESS);
 
+            let required_cuts = cube_config.required_cuts
+                .map(|required_cuts| {
+                    required_cuts.into_iter()
+                        .map(|required_cut| required_cut.into())
+                        .collect()
+                });
+
+            let table = cube_config.table;
+            let distributed = table.local_name.clone()
+                .map(|local_name| DistributedTable {
+                    local_name,
+                    cluster: table.cluster.clone(),
+                });
+
             cubes.push(Cube {
                 name: cube_config.name,
                 public,
                 min_auth_level,
-                table: cube_config.table.into(),
+                table: table.into(),
+                distributed,
                 can_aggregate: false,
                 dimensions,
                 measures,
                 annotations: cube_annotations,
+                required_cuts,
+                // data source names come from env vars, which are matched case-insensitively
+                data_source: cube_config.data_source.map(|data_source| data_source.to_lowercase()),
+                updated_column: cube_config.updated_column,
+                default_sort: cube_config.default_sort,
+                sql_where: cube_config.sql_where,
             });
         }
 
@@ -169,13 +202,37 @@ pub struct Cube {
     pub public: bool,
     pub min_auth_level: i32,
     pub table: Table,
+    /// Set when the fact table is a ClickHouse `Distributed` table.
+    pub distributed: Option<DistributedTable>,
     pub can_aggregate: bool,
     pub dimensions: Vec<Dimension>,
     pub measures: Vec<Measure>,
     pub annotations: Option<Vec<Annotation>>,
+    pub required_cuts: Option<Vec<RequiredCut>>,
+    /// Name of the database the cube lives on, if not the default one; lowercased, like the
+    /// names of the data sources.
+    pub data_source: Option<String>,
+    /// Timestamp column of the fact table; its max is when the cube's data was last updated.
+    pub updated_column: Option<String>,
+    /// Sort for queries without one, in the format of the `sort` query param. Keys on
+    /// columns that aren't in a query are skipped.
+    pub default_sort: Option<String>,
+    /// SQL predicate on the fact table, e.g. `status = 'final'`, added to every scan of it.
+    pub sql_where: Option<String>,
 }
 
 impl Cube {
+    /// The fact table for a `from` clause outside of aggregate queries, like the members
+    /// cache and diagnosis: its quoted name, or a subquery of its `sql_where` rows.
+    pub fn fact_table_sql(&self, dialect: &dyn SqlDialect) -> String {
+        let table = quote_qualified(dialect, &self.table.full_name());
+
+        match self.sql_where {
+            Some(ref sql_where) => format!("(select * from {} where ({})) as fact_rows", table, sql_where),
+            None => table,
+        }
+    }
+
     /// Returns a Vec<String> of all the dimension name options for a given Cube.
     pub fn get_all_level_names(&self) -> Vec<LevelName> {
         let mut dimension_names: Vec<LevelName> = vec![];
@@ -288,6 +345,17 @@ impl Cube {
         None
     }
 
+    /// The All member, if `level_name` is the All level of its hierarchy.
+    pub fn get_all_member(&self, level_name: &LevelName) -> Option<String> {
+        let hierarchy = self.get_hierarchy(level_name)?;
+
+        if hierarchy.all_level() == Some(level_name.level.as_str()) {
+            hierarchy.all_member_name.clone()
+        } else {
+            None
+        }
+    }
+
     /// Returns a Level object corresponding to a provided LevelName.
     pub fn get_level(&self, level_name: &LevelName) -> Option<Level> {
         for dimension in &self.dimensions {
@@ -337,6 +405,85 @@ impl Cube {
         }
         None
     }
+
+    /// Hidden cubes are left out of metadata and logic layer responses,
+    /// but can still be queried directly.
+    pub fn is_hidden(&self) -> bool {
+        is_hidden(&self.annotations)
+    }
+
+    /// Checks that the cuts of a query satisfy all of the cube's required cuts.
+    /// Errors with a list of every requirement that isn't met.
+    pub fn check_required_cuts(&self, cuts: &[Cut]) -> Result<(), Error> {
+        let required_cuts = match self.required_cuts {
+            Some(ref required_cuts) => required_cuts,
+            None => return Ok(()),
+        };
+
+        let unmet: Vec<String> = required_cuts.iter()
+            .filter(|required_cut| !cuts.iter().any(|cut| required_cut.is_satisfied_by(cut)))
+            .map(|required_cut| required_cut.to_string())
+            .collect();
+
+        if !unmet.is_empty() {
+            return Err(format_err!("Cube {} requires the following cuts: {}", self.name, unmet.join("; ")));
+        }
+
+        Ok(())
+    }
+}
+
+/// A cut which must be present in every query on a cube.
+/// Optionally restricted to a level, and to a maximum number of members.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequiredCut {
+    pub dimension: String,
+    pub level: Option<String>,
+    pub max_members: Option<usize>,
+}
+
+impl RequiredCut {
+    /// Exclude cuts never satisfy a requirement, since they widen the query instead
+    /// of restricting it.
+    pub fn is_satisfied_by(&self, cut: &Cut) -> bool {
+        if cut.level_name.dimension != self.dimension || cut.mask == Mask::Exclude {
+            return false;
+        }
+        if let Some(ref level) = self.level {
+            if &cut.level_name.level != level {
+                return false;
+            }
+        }
+        if let Some(max_members) = self.max_members {
+            if cut.members.len() > max_members {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Display for RequiredCut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dimension {}", self.dimension)?;
+        if let Some(ref level) = self.level {
+            write!(f, " on level {}", level)?;
+        }
+        if let Some(max_members) = self.max_members {
+            write!(f, " with at most {} member(s)", max_members)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<RequiredCutConfigJson> for RequiredCut {
+    fn from(required_cut_config: RequiredCutConfigJson) -> Self {
+        RequiredCut {
+            dimension: required_cut_config.dimension,
+            level: required_cut_config.level,
+            max_members: required_cut_config.max_members,
+        }
+    }
 }
 
 
@@ -349,6 +496,9 @@ pub struct Dimension {
     pub dim_type: DimensionType,
     pub annotations: Option<Vec<Annotation>>,
     pub is_shared: bool,
+    /// Name of the shared dimension this dimension uses, which may be named differently
+    /// in the cube.
+    pub shared_source: Option<String>,
 }
 
 impl From<DimensionConfigJson> for Dimension {
@@ -372,7 +522,8 @@ impl From<DimensionConfigJson> for Dimension {
             hierarchies,
             dim_type,
             annotations,
-            is_shared: false
+            is_shared: false,
+            shared_source: None,
         }
     }
 }
@@ -400,7 +551,67 @@ pub struct Hierarchy {
     pub levels: Vec<Level>,
     pub annotations: Option<Vec<Annotation>>,
     pub inline_table: Option<InlineTable>,
+    /// For snowflake dimensions: tables joined onto the hierarchy table, in order.
+    pub joins: Option<Vec<TableJoin>>,
     pub default_member: Option<String>,
+    /// Name of the member totalling the whole hierarchy. Drilling down on the hierarchy's
+    /// All level returns it in place of the members, without grouping by the dimension.
+    pub all_member_name: Option<String>,
+    /// Name of the All level, `(All)` by default.
+    pub all_level_name: Option<String>,
+    /// Contents of the hierarchy table, for dimensions with an `inline_cache` annotation.
+    /// Set when the cache is populated, and used in place of joining the table.
+    #[serde(skip)]
+    pub cached_table: Option<InlineTable>,
+}
+
+impl Hierarchy {
+    /// Name of the All level, for hierarchies with an All member.
+    pub fn all_level(&self) -> Option<&str> {
+        self.all_member_name.as_ref()?;
+        Some(self.all_level_name.as_deref().unwrap_or("(All)"))
+    }
+
+    /// Sql selecting every column of the hierarchy table that queries may use, to cache the
+    /// table as an inline table. `None` for hierarchies without their own table.
+    pub fn inline_cache_sql(&self) -> Option<String> {
+        let table = self.table.as_ref()?;
+
+        if self.inline_table.is_some() {
+            return None;
+        }
+
+        let mut columns = vec![self.primary_key.clone()];
+
+        for level in &self.levels {
+            let level_columns = Some(&level.key_column).into_iter()
+                .chain(level.name_column.as_ref())
+                .chain(level.properties.iter().flat_map(|props| props.iter().map(|p| &p.column)));
+
+            for column in level_columns {
+                if !columns.contains(column) {
+                    columns.push(column.clone());
+                }
+            }
+        }
+
+        // the primary key is qualified, as it's the one column which is likely to be in
+        // more than one table of a snowflake dimension
+        let select_cols = columns.iter()
+            .enumerate()
+            .map(|(i, column)| {
+                if i == 0 {
+                    format!("{}.{} as {}", table.full_name(), column, column)
+                } else {
+                    column.clone()
+                }
+            });
+
+        Some(format!("select distinct {} from {}",
+            join(select_cols, ", "),
+            table.join_chain_sql(&self.joins.clone().unwrap_or_default()),
+        ))
+    }
 }
 
 impl From<HierarchyConfigJson> for Hierarchy {
@@ -432,7 +643,11 @@ impl From<HierarchyConfigJson> for Hierarchy {
             levels,
             annotations,
             inline_table: hierarchy_config.inline_table.map(|t| t.into()),
-            default_member: hierarchy_config.default_member
+            joins: hierarchy_config.joins.map(|js| js.into_iter().map(|j| j.into()).collect()),
+            default_member: hierarchy_config.default_member,
+            all_member_name: hierarchy_config.all_member_name,
+            all_level_name: hierarchy_config.all_level_name,
+            cached_table: None,
         }
     }
 }
@@ -441,11 +656,97 @@ impl From<HierarchyConfigJson> for Hierarchy {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InlineTable {
     pub alias: String,
+    /// Path of a csv file with the rows, read when the schema is loaded. Its header has the
+    /// names of the columns.
+    pub csv: Option<String>,
     pub column_definitions: Vec<InlineTableColumnDefinition>,
     pub rows: Vec<InlineTableRow>,
 }
 
 impl InlineTable {
+    /// Reads the rows from the `csv` source, if any, and checks that every value has a
+    /// column definition and can be written as a literal of the column's type. A relative
+    /// `csv` path is relative to `base_dir`, the directory of the schema file.
+    pub fn load(&mut self, base_dir: &Path) -> Result<(), Error> {
+        if let Some(ref csv_path) = self.csv {
+            if !self.rows.is_empty() {
+                bail!("Inline table {} cannot have both rows and a csv source", self.alias);
+            }
+
+            let path = base_dir.join(csv_path);
+            let mut reader = csv::Reader::from_path(&path)
+                .map_err(|err| format_err!("Could not read csv {} for inline table {}: {}", path.display(), self.alias, err))?;
+            let headers = reader.headers()?.clone();
+
+            for record in reader.records() {
+                let record = record?;
+
+                self.rows.push(InlineTableRow {
+                    row_values: headers.iter()
+                        .zip(record.iter())
+                        .map(|(column, value)| InlineTableRowValue { column: column.to_owned(), value: value.to_owned() })
+                        .collect(),
+                });
+            }
+        }
+
+        for row in &self.rows {
+            for row_value in &row.row_values {
+                let col_def = self.column_definitions.iter()
+                    .find(|col_def| col_def.name == row_value.column)
+                    .ok_or_else(|| format_err!("Inline table {} has no definition for column {}", self.alias, row_value.column))?;
+
+                // other values of a non-text column need a type to be cast to
+                let is_literal = non_text_literal(&row_value.value).is_some();
+                if col_def.key_type == MemberType::NonText && !is_literal && col_def.key_column_type.is_none() {
+                    bail!("Inline table {} value {} of column {} is not a number, boolean or null, and the column has no key_column_type",
+                        self.alias, row_value.value, row_value.column);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inline table with the rows of a query result, for `Hierarchy::cached_table`. Numeric
+    /// columns can't hold nulls, as there's no literal to write them as.
+    pub fn from_dataframe(alias: &str, df: &DataFrame) -> Result<Self, Error> {
+        let mut column_definitions = vec![];
+        let mut rows = vec![InlineTableRow { row_values: vec![] }; df.len()];
+
+        for column in &df.columns {
+            let key_type = match column.column_data {
+                ColumnData::Text(_) | ColumnData::DictText(_) | ColumnData::NullableText(_) => MemberType::Text,
+                _ => MemberType::NonText,
+            };
+
+            let values = column.stringify_column_data();
+
+            // numbers never stringify to an empty string, only nulls do
+            if key_type == MemberType::NonText && values.iter().any(|v| v.is_empty()) {
+                bail!("Column {} of {} has nulls, which can't be inlined", column.name, alias);
+            }
+
+            for (row, value) in rows.iter_mut().zip(values) {
+                row.row_values.push(InlineTableRowValue { column: column.name.clone(), value });
+            }
+
+            column_definitions.push(InlineTableColumnDefinition {
+                name: column.name.clone(),
+                key_type,
+                key_column_type: None,
+                caption_set: None,
+            });
+        }
+
+        Ok(InlineTable {
+            alias: alias.to_owned(),
+            csv: None,
+            column_definitions,
+            rows,
+        })
+    }
+
     /// Transforms an InlineTable object into a SQL string.
     pub fn sql_string(&self) -> String {
         let mut curr_sql = "".to_string();
@@ -456,12 +757,14 @@ impl InlineTable {
             for (j, row) in table_row.row_values.iter().enumerate() {
                 for col_def in self.column_definitions.iter() {
                     if col_def.name == row.column {
-                        match col_def.key_type {
-                            MemberType::Text => curr_sql += &format!("'{}'", row.value),
-                            MemberType::NonText => match &col_def.key_column_type {
-                                Some(t) => curr_sql += &format!("cast({} as {})", row.value, t),
-                                None => curr_sql += &format!("{}", row.value)
-                            }
+                        let literal = match (&col_def.key_type, non_text_literal(&row.value)) {
+                            (MemberType::NonText, Some(literal)) => literal.to_owned(),
+                            _ => format!("'{}'", row.value.replace("'", "''")),
+                        };
+
+                        match &col_def.key_column_type {
+                            Some(t) => curr_sql += &format!("cast({} as {})", literal, t),
+                            None => curr_sql += &literal,
                         }
                         break
                     }
@@ -485,10 +788,24 @@ impl InlineTable {
     }
 }
 
+/// A value of a non-text column which can be written into sql as is: a number, a boolean
+/// or null.
+fn non_text_literal(value: &str) -> Option<&str> {
+    let value = value.trim();
+    let is_keyword = ["null", "true", "false"].iter().any(|k| value.eq_ignore_ascii_case(k));
+
+    if is_keyword || value.parse::<f64>().is_ok() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
 impl From<InlineTableJson> for InlineTable {
     fn from(inline_table_config: InlineTableJson) -> Self {
         InlineTable {
             alias: inline_table_config.alias,
+            csv: inline_table_config.csv,
             column_definitions: inline_table_config.column_definitions.into_iter()
                 .map(|l| l.into())
                 .collect(),
@@ -559,10 +876,37 @@ pub struct Level {
     pub name_column: Option<String>,
     pub properties: Option<Vec<Property>>,
     pub key_type: Option<MemberType>,
+    /// Width the keys of a numeric key column are zero-padded to, for codes like FIPS codes
+    /// stored as integers. The level's members are then the padded keys.
+    pub key_width: Option<usize>,
+    /// Name of the property holding each member's GeoJSON geometry, for the `geojson` format.
+    pub geometry_property: Option<String>,
     pub annotations: Option<Vec<Annotation>>,
 }
 
 impl Level {
+    /// A key of the level's key column as a member, zero-padded to the `key_width`.
+    pub fn pad_key(&self, key: String) -> String {
+        match self.key_width {
+            Some(width) => pad_key(key, width),
+            None => key,
+        }
+    }
+
+    /// A member of the level as a key of its key column, without the `key_width` padding.
+    pub fn unpad_key(&self, member: &str) -> String {
+        let is_padded = self.key_width.is_some()
+            && !member.is_empty()
+            && member.chars().all(|c| c.is_ascii_digit());
+
+        if is_padded {
+            let key = member.trim_start_matches('0');
+            if key.is_empty() { "0".to_owned() } else { key.to_owned() }
+        } else {
+            member.to_owned()
+        }
+    }
+
     pub fn get_captions(&self, level_name: &LevelName, locales: &Vec<String>) -> Vec<TsProperty> {
         let mut captions: Vec<TsProperty> = vec![];
 
@@ -610,6 +954,8 @@ impl From<LevelConfigJson> for Level {
             name_column: level_config.name_column,
             properties,
             key_type: level_config.key_type,
+            key_width: level_config.key_width,
+            geometry_property: level_config.geometry_property,
             annotations,
         }
     }
@@ -622,7 +968,11 @@ pub struct Measure{
     pub column: String,
     pub aggregator: Aggregator,
     pub measure_type: MeasureType,
+    /// Number format for CSV output, e.g. `#,##0.00`. See `format::NumberFormat`.
+    pub format_template: Option<String>,
     pub annotations: Option<Vec<Annotation>>,
+    /// How the measure's nulls are aggregated and returned; queries can override it.
+    pub treat_null_as: NullPolicy,
 }
 
 impl From<MeasureConfigJson> for Measure {
@@ -639,7 +989,9 @@ impl From<MeasureConfigJson> for Measure {
             column: measure_config.column,
             aggregator: measure_config.aggregator,
             measure_type: measure_config.measure_type.unwrap_or_else(|| MeasureType::default()),
+            format_template: measure_config.format_template,
             annotations,
+            treat_null_as: measure_config.treat_null_as.unwrap_or_default(),
         }
     }
 }
@@ -665,6 +1017,35 @@ impl Default for MeasureType {
     }
 }
 
+/// How null measure values are handled. Statistical agencies differ on whether a missing
+/// value is a zero or unknown.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NullPolicy {
+    /// Nulls are zeros: they're aggregated as zeros, e.g. counted and averaged, and null
+    /// results are returned as zeros.
+    #[serde(rename="zero")]
+    Zero,
+    /// Nulls are left out of aggregations, as sql does, and null results stay null.
+    #[serde(rename="skip")]
+    Skip,
+}
+
+impl Default for NullPolicy {
+    fn default() -> Self { NullPolicy::Skip }
+}
+
+impl FromStr for NullPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(NullPolicy::Zero),
+            "skip" => Ok(NullPolicy::Skip),
+            _ => Err(format_err!("treat_null_as must be zero or skip; found {}", s)),
+        }
+    }
+}
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Table{
@@ -683,6 +1064,15 @@ impl From<TableConfigJson> for Table {
     }
 }
 
+/// A ClickHouse `Distributed` fact table, over a table on each shard of a cluster.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistributedTable {
+    /// Name of the table on each shard.
+    pub local_name: String,
+    /// Cluster the shards are in, as named in the ClickHouse config.
+    pub cluster: Option<String>,
+}
+
 impl Table {
     pub fn full_name(&self) -> String {
         if let Some(ref schema) = self.schema {
@@ -691,6 +1081,50 @@ impl Table {
             self.name.to_owned()
         }
     }
+
+    /// The table with its join chain, for use in a `from` clause.
+    pub fn join_chain_sql(&self, joins: &[TableJoin]) -> String {
+        format!("{}{}", self.full_name(), self.joins_sql(joins))
+    }
+
+    /// Only the join clauses of the chain. Each table in the chain is
+    /// joined on the table before it.
+    pub fn joins_sql(&self, joins: &[TableJoin]) -> String {
+        let mut sql = "".to_owned();
+        let mut prev_table = self;
+
+        for table_join in joins {
+            sql.push_str(&format!(" inner join {} on {}.{} = {}.{}",
+                table_join.table.full_name(),
+                prev_table.full_name(),
+                table_join.foreign_key,
+                table_join.table.full_name(),
+                table_join.primary_key,
+            ));
+            prev_table = &table_join.table;
+        }
+
+        sql
+    }
+}
+
+/// A table joined onto the previous table of a snowflake dimension. `foreign_key`
+/// is the column of the previous table, and `primary_key` the column of this one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableJoin {
+    pub table: Table,
+    pub foreign_key: String,
+    pub primary_key: String,
+}
+
+impl From<TableJoinConfigJson> for TableJoin {
+    fn from(join_config: TableJoinConfigJson) -> Self {
+        TableJoin {
+            table: join_config.table.into(),
+            foreign_key: join_config.foreign_key,
+            primary_key: join_config.primary_key,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -725,6 +1159,20 @@ pub struct Annotation{
     pub text: String,
 }
 
+/// Checks for an `inline_cache` annotation set to `true`.
+pub fn is_inline_cached(annotations: &Option<Vec<Annotation>>) -> bool {
+    annotations.as_ref()
+        .map(|anns| anns.iter().any(|ann| ann.name == "inline_cache" && ann.text == "true"))
+        .unwrap_or(false)
+}
+
+/// Checks for a `hidden` annotation set to `true`.
+pub fn is_hidden(annotations: &Option<Vec<Annotation>>) -> bool {
+    annotations.as_ref()
+        .map(|anns| anns.iter().any(|ann| ann.name == "hidden" && ann.text == "true"))
+        .unwrap_or(false)
+}
+
 impl From<AnnotationConfigJson> for Annotation {
     fn from(annotation_config: AnnotationConfigJson) -> Self {
         Annotation {
@@ -762,6 +1210,8 @@ mod test {
                                 name: "geo_table".into(),
                                 schema: None,
                                 primary_key: None,
+                                local_name: None,
+                                cluster: None,
                             }),
                             primary_key: Some("geoid".into()),
                             levels: vec![
@@ -771,12 +1221,17 @@ mod test {
                                     name_column: None,
                                     properties: None,
                                     key_type: None,
+                                    key_width: None,
+                                    geometry_property: None,
                                     annotations: None,
                                 },
                             ],
                             annotations: None,
                             inline_table: None,
+                            joins: None,
                             default_member: None,
+                            all_member_name: None,
+                            all_level_name: None,
                         },
                     ],
                     default_hierarchy: None,
@@ -793,6 +1248,8 @@ mod test {
                         name: "fact_table".into(),
                         schema: None,
                         primary_key: None,
+                        local_name: None,
+                        cluster: None,
                     },
                     dimensions: Some(vec![]),
                     dimension_usages: Some(vec![
@@ -805,6 +1262,11 @@ mod test {
                     ]),
                     measures: vec![],
                     annotations: None,
+                    required_cuts: None,
+                    data_source: None,
+                    updated_column: None,
+                    default_sort: None,
+                    sql_where: None,
                 }
             ],
             annotations: None,
@@ -815,6 +1277,25 @@ mod test {
         assert_eq!(schema.cubes[0].dimensions.len(), 1);
     }
 
+    #[test]
+    fn test_required_cuts() {
+        let required_cut = RequiredCut {
+            dimension: "Year".into(),
+            level: Some("Year".into()),
+            max_members: Some(1),
+        };
+
+        let one_year = Cut::new("Year", "Year", "Year", vec!["2018"], Mask::Include, false);
+        let two_years = Cut::new("Year", "Year", "Year", vec!["2017", "2018"], Mask::Include, false);
+        let excluded_year = Cut::new("Year", "Year", "Year", vec!["2018"], Mask::Exclude, false);
+        let geo = Cut::new("Geo", "Geo", "State", vec!["01"], Mask::Include, false);
+
+        assert!(required_cut.is_satisfied_by(&one_year));
+        assert!(!required_cut.is_satisfied_by(&two_years));
+        assert!(!required_cut.is_satisfied_by(&excluded_year));
+        assert!(!required_cut.is_satisfied_by(&geo));
+    }
+
     // End to end, from xml
     use serde_xml_rs::from_reader;
 
@@ -835,6 +1316,7 @@ mod test {
                         </Hierarchy>
                     </Dimension>
                     <Measure name="my_mea" column="mea" aggregator="sum" />
+                    <RequiredCut dimension="my_dim" max_members="1" />
                 </Cube>
             </Schema>
         "##;
@@ -842,5 +1324,100 @@ mod test {
         let json_str = serde_json::to_string(&xml_schema_config).unwrap();
         let json_schema_config: SchemaConfigJson = serde_json::from_str(&json_str).unwrap();
         println!("{:#?}", json_schema_config);
+        assert_eq!(
+            json_schema_config.cubes[0].required_cuts,
+            Some(vec![RequiredCutConfigJson { dimension: "my_dim".into(), level: None, max_members: Some(1) }])
+        );
+    }
+
+    #[test]
+    fn test_inline_table_csv() {
+        // the csv path is relative to the schema's directory
+        let dir = std::env::temp_dir();
+        let path = dir.join("tesseract_test_inline_table.csv");
+        std::fs::write(&path, "id,name,founded,active\n1,Acme,2001-05-01,true\n2,O'Brien,1999-01-01,NULL\n").unwrap();
+
+        let s = r##"
+            <Schema name="my_schema">
+                <Cube name="my_cube">
+                    <Table name="my_table" />
+                    <Dimension name="Company" foreign_key="company_id">
+                        <Hierarchy name="Company" primary_key="id">
+                            <InlineTable alias="companies" csv="tesseract_test_inline_table.csv">
+                                <ColumnDef name="id" key_type="nontext" key_column_type="UInt8" />
+                                <ColumnDef name="name" key_type="text" />
+                                <ColumnDef name="founded" key_type="nontext" key_column_type="Date" />
+                                <ColumnDef name="active" key_type="nontext" />
+                            </InlineTable>
+                            <Level name="Company" key_column="id" name_column="name" />
+                        </Hierarchy>
+                    </Dimension>
+                    <Measure name="my_mea" column="mea" aggregator="sum" />
+                </Cube>
+            </Schema>
+        "##;
+        let schema = crate::Schema::from_xml_at(s, &dir).unwrap();
+        let inline_table = schema.cubes[0].dimensions[0].hierarchies[0].inline_table.as_ref().unwrap();
+
+        assert_eq!(
+            inline_table.sql_string(),
+            "select cast(1 as UInt8) as id, 'Acme' as name, cast('2001-05-01' as Date) as founded, true as active union all select cast(2 as UInt8), 'O''Brien', cast('1999-01-01' as Date), NULL"
+        );
+
+        // an empty table still loads
+        std::fs::write(&path, "id,name,founded,active\n").unwrap();
+        assert!(crate::Schema::from_xml_at(s, &dir).is_ok());
+
+        // other values need a type to be cast to
+        std::fs::write(&path, "id,name,founded,active\nx,Acme,2001-05-01,false\n").unwrap();
+        let s = s.replace(r#"key_column_type="UInt8" "#, "");
+        assert!(crate::Schema::from_xml_at(&s, &dir).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_inline_cache() {
+        use crate::dataframe::Column;
+
+        let s = r##"
+            <Schema name="my_schema">
+                <Cube name="my_cube">
+                    <Table name="my_table" />
+                    <Dimension name="Product" foreign_key="product_id">
+                        <Annotation name="inline_cache">true</Annotation>
+                        <Hierarchy name="Product" primary_key="product_id">
+                            <Table name="dim_product" />
+                            <Level name="Group" key_column="group_id" name_column="group_name" />
+                            <Level name="Product" key_column="product_id" />
+                        </Hierarchy>
+                    </Dimension>
+                    <Measure name="my_mea" column="mea" aggregator="sum" />
+                </Cube>
+            </Schema>
+        "##;
+        let schema = crate::Schema::from_xml(s).unwrap();
+        let dim = &schema.cubes[0].dimensions[0];
+        assert!(is_inline_cached(&dim.annotations));
+        assert_eq!(
+            dim.hierarchies[0].inline_cache_sql().unwrap(),
+            "select distinct dim_product.product_id as product_id, group_id, group_name from dim_product"
+        );
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("product_id".into(), ColumnData::UInt32(vec![1, 2])),
+            Column::new("group_id".into(), ColumnData::UInt8(vec![1, 1])),
+            Column::new("group_name".into(), ColumnData::Text(vec!["Tools".into(), "Tools".into()])),
+        ]);
+        let inline_table = InlineTable::from_dataframe("dim_product", &df).unwrap();
+        assert_eq!(
+            inline_table.sql_string(),
+            "select 1 as product_id, 1 as group_id, 'Tools' as group_name union all select 2, 1, 'Tools'"
+        );
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("product_id".into(), ColumnData::NullableUInt32(vec![Some(1), None])),
+        ]);
+        assert!(InlineTable::from_dataframe("dim_product", &df).is_err());
     }
 }
@@ -20,6 +20,13 @@ pub enum Aggregator {
     Max,
     #[serde(rename="min")]
     Min,
+    /// Exact count of distinct values of the column.
+    #[serde(rename="distinct_count")]
+    DistinctCount,
+    /// Approximate (HyperLogLog style) count of distinct values, for backends
+    /// which support it. Others fall back to an exact distinct count.
+    #[serde(rename="approx_distinct_count")]
+    ApproxDistinctCount,
     /// Median
     ///
     /// Needs two steps. It's slow because there won't be aggregation on the first step, only
@@ -109,6 +116,10 @@ mod test {
         let sum = r#"{ "col": "testcol", "aggregator": "sum" }"#;
         let parsed: Measure = serde_json::from_str(sum).unwrap();
         assert_eq!(parsed.aggregator, Aggregator::Sum);
+
+        let distinct_count = r#"{ "col": "testcol", "aggregator": "approx_distinct_count" }"#;
+        let parsed: Measure = serde_json::from_str(distinct_count).unwrap();
+        assert_eq!(parsed.aggregator, Aggregator::ApproxDistinctCount);
     }
 
     #[test]
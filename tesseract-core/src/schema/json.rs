@@ -2,7 +2,7 @@ use serde_derive::Deserialize;
 
 use crate::query_ir::MemberType;
 use super::aggregator::Aggregator;
-use super::{DimensionType, MeasureType};
+use super::{DimensionType, MeasureType, NullPolicy};
 
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -24,6 +24,18 @@ pub struct CubeConfigJson {
     pub dimension_usages: Option<Vec<DimensionUsageJson>>,
     pub measures: Vec<MeasureConfigJson>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
+    pub required_cuts: Option<Vec<RequiredCutConfigJson>>,
+    pub data_source: Option<String>,
+    pub updated_column: Option<String>,
+    pub default_sort: Option<String>,
+    pub sql_where: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RequiredCutConfigJson {
+    pub dimension: String,
+    pub level: Option<String>,
+    pub max_members: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -63,13 +75,18 @@ pub struct HierarchyConfigJson {
     pub levels: Vec<LevelConfigJson>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
     pub inline_table: Option<InlineTableJson>,
+    pub joins: Option<Vec<TableJoinConfigJson>>,
     pub default_member: Option<String>,
+    pub all_member_name: Option<String>,
+    pub all_level_name: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct InlineTableJson {
     pub alias: String,
+    pub csv: Option<String>,
     pub column_definitions: Vec<InlineTableColumnDefinitionJson>,
+    #[serde(default)]
     pub rows: Vec<InlineTableRowJson>,
 }
 
@@ -99,6 +116,8 @@ pub struct LevelConfigJson {
     pub name_column: Option<String>,
     pub properties: Option<Vec<PropertyConfigJson>>,
     pub key_type: Option<MemberType>,
+    pub key_width: Option<usize>,
+    pub geometry_property: Option<String>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
@@ -109,7 +128,9 @@ pub struct MeasureConfigJson {
     pub aggregator: Aggregator,
     #[serde(rename="type")]
     pub measure_type: Option<MeasureType>,
+    pub format_template: Option<String>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
+    pub treat_null_as: Option<NullPolicy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -117,6 +138,15 @@ pub struct TableConfigJson {
     pub name: String,
     pub schema: Option<String>,
     pub primary_key: Option<String>,
+    pub local_name: Option<String>,
+    pub cluster: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TableJoinConfigJson {
+    pub table: TableConfigJson,
+    pub foreign_key: String,
+    pub primary_key: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -23,6 +23,16 @@ pub struct SchemaMetadata {
     pub annotations: AnnotationMetadata,
 }
 
+impl SchemaMetadata {
+    /// Removes cubes, dimensions and measures annotated with `hidden=true`.
+    pub fn remove_hidden(&mut self) {
+        self.cubes.retain(|cube| !cube.annotations.is_hidden());
+        for cube in self.cubes.iter_mut() {
+            cube.remove_hidden();
+        }
+    }
+}
+
 impl From<&Schema> for SchemaMetadata {
     fn from(schema: &Schema) -> Self {
         let annotations = (&schema.annotations).into();
@@ -43,6 +53,58 @@ pub struct CubeMetadata {
     pub annotations: AnnotationMetadata,
     pub alias: Option<Vec<String>>,
     pub min_auth_level: i32,
+    /// When the cube's data was last updated, if known. Filled in by the server.
+    pub last_updated: Option<String>,
+}
+
+impl CubeMetadata {
+    /// Returns true if the cube has every one of the `(name, value)` annotations.
+    pub fn has_annotations(&self, annotations: &[(String, String)]) -> bool {
+        annotations.iter()
+            .all(|(name, value)| self.annotations.get(name) == Some(value))
+    }
+
+    /// Removes dimensions and measures annotated with `hidden=true`.
+    pub fn remove_hidden(&mut self) {
+        self.dimensions.retain(|dim| !dim.annotations.is_hidden());
+        self.measures.retain(|mea| !mea.annotations.is_hidden());
+    }
+
+    /// Keeps only the first `depth` levels of each hierarchy.
+    pub fn trim_depth(&mut self, depth: usize) {
+        for dimension in self.dimensions.iter_mut() {
+            for hierarchy in dimension.hierarchies.iter_mut() {
+                hierarchy.levels.truncate(depth);
+            }
+        }
+    }
+
+    /// A compact description of the cube: its levels in a flat list, and its measure names.
+    pub fn levels_only(&self) -> CubeLevelsMetadata {
+        let mut levels = vec![];
+        for dimension in self.dimensions.iter() {
+            for hierarchy in dimension.hierarchies.iter() {
+                for (depth, level) in hierarchy.levels.iter().enumerate() {
+                    levels.push(FlatLevelMetadata {
+                        dimension: dimension.name.clone(),
+                        hierarchy: hierarchy.name.clone(),
+                        level: level.name.clone(),
+                        depth: depth + 1,
+                        unique_name: level.unique_name.clone(),
+                        member_count: level.member_count,
+                    });
+                }
+            }
+        }
+
+        CubeLevelsMetadata {
+            name: self.name.clone(),
+            alias: self.alias.clone(),
+            levels,
+            measures: self.measures.iter().map(|m| m.name.clone()).collect(),
+            last_updated: self.last_updated.clone(),
+        }
+    }
 }
 
 impl From<&Cube> for CubeMetadata {
@@ -56,10 +118,32 @@ impl From<&Cube> for CubeMetadata {
             annotations,
             alias: None,
             min_auth_level: cube.min_auth_level,
+            last_updated: None,
         }
     }
 }
 
+/// Cube metadata with the levels flattened, from `CubeMetadata::levels_only`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CubeLevelsMetadata {
+    pub name: String,
+    pub alias: Option<Vec<String>>,
+    pub levels: Vec<FlatLevelMetadata>,
+    pub measures: Vec<String>,
+    pub last_updated: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlatLevelMetadata {
+    pub dimension: String,
+    pub hierarchy: String,
+    pub level: String,
+    /// Position of the level in its hierarchy, starting at 1.
+    pub depth: usize,
+    pub unique_name: Option<String>,
+    pub member_count: Option<usize>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct DimensionMetadata {
     pub name: String,
@@ -89,6 +173,8 @@ pub struct HierarchyMetadata {
     pub name: String,
     pub levels: Vec<LevelMetadata>,
     pub annotations: AnnotationMetadata,
+    pub all_member_name: Option<String>,
+    pub all_level_name: Option<String>,
 }
 
 impl From<&Hierarchy> for HierarchyMetadata {
@@ -99,6 +185,8 @@ impl From<&Hierarchy> for HierarchyMetadata {
             name: hierarchy.name.clone(),
             levels: hierarchy.levels.iter().map(|l| l.into()).collect(),
             annotations,
+            all_member_name: hierarchy.all_member_name.clone(),
+            all_level_name: hierarchy.all_level().map(|name| name.to_owned()),
         }
     }
 }
@@ -109,6 +197,8 @@ pub struct LevelMetadata {
     pub properties: Option<Vec<PropertyMetadata>>,
     pub annotations: AnnotationMetadata,
     pub unique_name: Option<String>,
+    /// Number of members of the level, if known. Filled in by the server.
+    pub member_count: Option<usize>,
 }
 
 impl From<&Level> for LevelMetadata {
@@ -123,6 +213,7 @@ impl From<&Level> for LevelMetadata {
             properties,
             annotations,
             unique_name: None,
+            member_count: None,
         }
     }
 }
@@ -201,6 +292,16 @@ impl From<&Property> for PropertyMetadata {
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct AnnotationMetadata(HashMap<String, String>);
 
+impl AnnotationMetadata {
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.0.get(name)
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.get("hidden").map(|text| text == "true").unwrap_or(false)
+    }
+}
+
 impl From<&Option<Vec<Annotation>>> for AnnotationMetadata {
     fn from(annotations: &Option<Vec<Annotation>>) -> Self {
         let res = if let Some(anns) = annotations {
@@ -228,6 +329,8 @@ impl From<&Aggregator> for AggregatorMetadata {
             Aggregator::Average => "avg".into(),
             Aggregator::Max => "max".into(),
             Aggregator::Min => "min".into(),
+            Aggregator::DistinctCount => "distinct_count".into(),
+            Aggregator::ApproxDistinctCount => "approx_distinct_count".into(),
             Aggregator::BasicGroupedMedian { .. } => "basic_grouped_median".into(),
             Aggregator::WeightedAverage { ..} => "weighted_average".into(),
             Aggregator::WeightedSum { ..} => "weighted_sum".into(),
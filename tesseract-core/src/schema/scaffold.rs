@@ -0,0 +1,178 @@
+//! Draft cube schemas from an existing fact table.
+//!
+//! The fact table's columns (from `Backend::inspect_columns`) are classified by type and
+//! number of distinct values: floating point columns become `sum` measures, text, date and
+//! integer columns with few distinct values become degenerate dimensions (keyed on the fact
+//! table itself), and other integer columns become `sum` measures. Columns of other types,
+//! and text columns with too many distinct values, are left out.
+//!
+//! The draft is meant to be edited by hand, e.g. to move dimensions to their own tables.
+
+use serde_json::{json, Value};
+
+use crate::backend::ColumnInfo;
+use crate::dialect::SqlDialect;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Integer,
+    Float,
+    Text,
+    Other,
+}
+
+/// Classifies a column by its database type name, e.g. `Nullable(UInt32)` or `varchar`.
+fn column_kind(column_type: &str) -> ColumnKind {
+    let mut t = column_type.trim().to_lowercase();
+
+    // clickhouse wrappers
+    for wrapper in &["nullable(", "lowcardinality("] {
+        while t.starts_with(wrapper) && t.ends_with(')') {
+            t = t[wrapper.len()..t.len() - 1].to_owned();
+        }
+    }
+
+    let starts_with_any = |prefixes: &[&str]| prefixes.iter().any(|p| t.starts_with(p));
+
+    if t.starts_with("interval") {
+        ColumnKind::Other
+    } else if starts_with_any(&["int", "uint", "bigint", "smallint", "tinyint", "mediumint", "serial", "bigserial"]) {
+        ColumnKind::Integer
+    } else if starts_with_any(&["float", "double", "real", "decimal", "numeric"]) {
+        ColumnKind::Float
+    } else if starts_with_any(&["string", "fixedstring", "text", "varchar", "char", "character", "enum", "date", "timestamp"]) {
+        ColumnKind::Text
+    } else {
+        ColumnKind::Other
+    }
+}
+
+/// Sql counting the distinct values of each column in `table`, in one row. Column names are
+/// quoted by the `dialect`, as they come from the database and needn't be plain identifiers.
+pub fn distinct_counts_sql(dialect: &dyn SqlDialect, table: &str, columns: &[ColumnInfo]) -> String {
+    let counts: Vec<_> = columns.iter()
+        .map(|col| format!("count(distinct {})", dialect.quote_identifier(&col.name)))
+        .collect();
+
+    format!("select {} from {}", counts.join(", "), table)
+}
+
+/// Draft cube for `table` (optionally qualified by its schema, as `schema.table`), in the
+/// json schema format. `distinct_counts` are the number of distinct values of each of the
+/// `columns`; a column is only made a dimension if it has at most `max_dimension_members`.
+pub fn scaffold_cube(
+    table: &str,
+    columns: &[ColumnInfo],
+    distinct_counts: &[u64],
+    max_dimension_members: u64,
+    ) -> Value
+{
+    let mut dimensions = vec![];
+    let mut measures = vec![];
+
+    for (col, distinct_count) in columns.iter().zip(distinct_counts) {
+        let low_cardinality = *distinct_count <= max_dimension_members;
+
+        match (column_kind(&col.column_type), low_cardinality) {
+            (ColumnKind::Integer, true) | (ColumnKind::Text, true) => {
+                dimensions.push(json!({
+                    "name": col.name,
+                    "foreign_key": col.name,
+                    "hierarchies": [{
+                        "name": col.name,
+                        "levels": [{
+                            "name": col.name,
+                            "key_column": col.name,
+                        }],
+                    }],
+                }));
+            },
+            (ColumnKind::Integer, false) | (ColumnKind::Float, _) => {
+                measures.push(json!({
+                    "name": col.name,
+                    "column": col.name,
+                    "aggregator": "sum",
+                }));
+            },
+            _ => (),
+        }
+    }
+
+    let mut parts = table.rsplitn(2, '.');
+    let name = parts.next().unwrap_or(table);
+    let table_json = match parts.next() {
+        Some(schema) => json!({ "name": name, "schema": schema }),
+        None => json!({ "name": name }),
+    };
+
+    json!({
+        "name": name,
+        "table": table_json,
+        "dimensions": dimensions,
+        "measures": measures,
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dialect::StandardSql;
+    use crate::schema::{Schema, SchemaConfigJson};
+
+    fn col(name: &str, column_type: &str) -> ColumnInfo {
+        ColumnInfo { name: name.to_owned(), column_type: column_type.to_owned() }
+    }
+
+    #[test]
+    fn test_column_kind() {
+        assert_eq!(column_kind("UInt32"), ColumnKind::Integer);
+        assert_eq!(column_kind("Nullable(Int8)"), ColumnKind::Integer);
+        assert_eq!(column_kind("bigint"), ColumnKind::Integer);
+        assert_eq!(column_kind("double precision"), ColumnKind::Float);
+        assert_eq!(column_kind("Nullable(Float64)"), ColumnKind::Float);
+        assert_eq!(column_kind("LowCardinality(String)"), ColumnKind::Text);
+        assert_eq!(column_kind("character varying"), ColumnKind::Text);
+        assert_eq!(column_kind("Array(UInt8)"), ColumnKind::Other);
+        assert_eq!(column_kind("interval"), ColumnKind::Other);
+    }
+
+    #[test]
+    fn test_scaffold_cube() {
+        let columns = vec![
+            col("year", "UInt16"),
+            col("product name", "String"),
+            col("description", "String"),
+            col("quantity", "UInt32"),
+            col("price", "Float64"),
+        ];
+
+        assert_eq!(
+            distinct_counts_sql(&StandardSql, "sales", &columns[..2]),
+            r#"select count(distinct year), count(distinct "product name") from sales"#
+        );
+
+        let cube = scaffold_cube("public.sales", &columns, &[10, 50, 100000, 5000, 8000], 1000);
+
+        let dims: Vec<_> = cube["dimensions"].as_array().unwrap().iter()
+            .map(|dim| dim["name"].as_str().unwrap())
+            .collect();
+        let meas: Vec<_> = cube["measures"].as_array().unwrap().iter()
+            .map(|mea| mea["name"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(dims, vec!["year", "product name"]);
+        assert_eq!(meas, vec!["quantity", "price"]);
+        assert_eq!(cube["table"], json!({ "name": "sales", "schema": "public" }));
+
+        // the draft is a valid schema
+        let schema_config: SchemaConfigJson = serde_json::from_value(json!({
+            "name": "draft",
+            "cubes": [cube],
+        })).unwrap();
+        let schema: Schema = schema_config.into();
+        assert_eq!(schema.cubes[0].dimensions.len(), 2);
+        assert_eq!(schema.cubes[0].measures.len(), 2);
+    }
+}
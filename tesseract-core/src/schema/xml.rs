@@ -14,7 +14,7 @@ use serde_derive::Serialize;
 
 use crate::query_ir::MemberType;
 use super::aggregator::Aggregator;
-use super::{DimensionType, MeasureType};
+use super::{DimensionType, MeasureType, NullPolicy};
 
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -45,6 +45,19 @@ pub struct CubeConfigXML {
     pub measures: Vec<MeasureConfigXML>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
+    #[serde(rename(deserialize="RequiredCut"))]
+    pub required_cuts: Option<Vec<RequiredCutConfigXML>>,
+    pub data_source: Option<String>,
+    pub updated_column: Option<String>,
+    pub default_sort: Option<String>,
+    pub sql_where: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RequiredCutConfigXML {
+    pub dimension: String,
+    pub level: Option<String>,
+    pub max_members: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -93,15 +106,20 @@ pub struct HierarchyConfigXML {
     pub annotations: Option<Vec<AnnotationConfigXML>>,
     #[serde(rename(deserialize="InlineTable"))]
     pub inline_table: Option<InlineTableXML>,
+    #[serde(rename(deserialize="Join"))]
+    pub joins: Option<Vec<TableJoinConfigXML>>,
     pub default_member: Option<String>,
+    pub all_member_name: Option<String>,
+    pub all_level_name: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct InlineTableXML {
     pub alias: String,
+    pub csv: Option<String>,
     #[serde(rename(deserialize="ColumnDef"))]
     pub column_definitions: Vec<InlineTableColumnDefinitionXML>,
-    #[serde(rename(deserialize="Row"))]
+    #[serde(rename(deserialize="Row"), default)]
     pub rows: Vec<InlineTableRowXML>,
 }
 
@@ -134,6 +152,8 @@ pub struct LevelConfigXML {
     #[serde(rename(deserialize="Property"))]
     pub properties: Option<Vec<PropertyConfigXML>>,
     pub key_type: Option<MemberType>,
+    pub key_width: Option<usize>,
+    pub geometry_property: Option<String>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
@@ -145,8 +165,10 @@ pub struct MeasureConfigXML {
     pub aggregator: Aggregator,
     #[serde(rename="type")]
     pub measure_type: Option<MeasureType>,
+    pub format_template: Option<String>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
+    pub treat_null_as: Option<NullPolicy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -154,6 +176,16 @@ pub struct TableConfigXML {
     pub name: String,
     pub schema: Option<String>,
     pub primary_key: Option<String>,
+    pub local_name: Option<String>,
+    pub cluster: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TableJoinConfigXML {
+    #[serde(rename(deserialize="Table"))]
+    pub table: TableConfigXML,
+    pub foreign_key: String,
+    pub primary_key: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
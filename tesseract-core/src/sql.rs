@@ -2,34 +2,19 @@ use itertools::join;
 
 use crate::Aggregator;
 use crate::query_ir::{
-    TableSql,
-    CutSql,
+    QueryIr,
     DrilldownSql,
     MeasureSql,
-    TopSql,
-    SortSql,
-    LimitSql,
-    RcaSql,
-    GrowthSql,
 };
 
 /// Error checking is done before this point. This string formatter
 /// accepts any input
-/// Currently just does the standard aggregation.
-/// No calculations, primary aggregation is not split out.
-pub(crate) fn standard_sql(
-    table: &TableSql,
-    cuts: &[CutSql],
-    drills: &[DrilldownSql],
-    meas: &[MeasureSql],
-    // TODO put Filters and Calculations into own structs
-    _top: &Option<TopSql>,
-    _sort: &Option<SortSql>,
-    _limit: &Option<LimitSql>,
-    _rca: &Option<RcaSql>,
-    _growth: &Option<GrowthSql>,
-    ) -> String
-{
+/// Currently just does the standard aggregation, plus rate and limit.
+/// No other calculations, primary aggregation is not split out.
+pub(crate) fn standard_sql(query_ir: &QueryIr) -> String {
+    // top, rca and growth aren't supported
+    let QueryIr { ref table, ref cuts, ref row_filters, ref drills, ref meas, ref sort, ref limit, ref rate, sparse, .. } = *query_ir;
+
     // hack for now... remove later
     // it's unneeded, except for standard_sql
     fn agg_sql_string(m: &MeasureSql) -> String {
@@ -39,6 +24,9 @@ pub(crate) fn standard_sql(
             Aggregator::Average => format!("avg({})", &m.column),
             Aggregator::Max => format!("max({})", &m.column),
             Aggregator::Min => format!("min({})", &m.column),
+            // no standard approximate distinct count, so both are exact
+            Aggregator::DistinctCount => format!("count(distinct {})", &m.column),
+            Aggregator::ApproxDistinctCount => format!("count(distinct {})", &m.column),
             // median doesn't work like this
             Aggregator::BasicGroupedMedian { .. } => format!("median"),
             Aggregator::WeightedAverage {..} => format!("avg"),
@@ -52,9 +40,29 @@ pub(crate) fn standard_sql(
 
     // --------------------------------------------------
     // copied from primary_agg for clickhouse
-    let ext_drills: Vec<_> = drills.iter()
-        .filter(|d| d.table.name != table.name)
-        .collect();
+    let mut ext_drills: Vec<&DrilldownSql> = vec![];
+
+    // drilldowns on several hierarchies of one dimension can share its table, which is
+    // only joined once
+    for drill in drills.iter().filter(|d| d.table.name != table.name) {
+        let is_joined = ext_drills.iter()
+            .any(|d| d.table == drill.table && d.foreign_key == drill.foreign_key);
+
+        if !is_joined {
+            ext_drills.push(drill);
+        }
+    }
+
+    // the rate level needs to be joined, even if it's not drilled down on
+    if let Some(rate) = rate {
+        let rate_drill = &rate.drilldown_sql;
+        let is_joined = ext_drills.iter()
+            .any(|d| d.table == rate_drill.table);
+
+        if rate_drill.table.name != table.name && !is_joined {
+            ext_drills.push(rate_drill);
+        }
+    }
 
     //let ext_cuts: Vec<_> = cuts.iter()
     //    .filter(|c| c.table.name != table.name)
@@ -71,46 +79,165 @@ pub(crate) fn standard_sql(
     // --------------------------------------------------
 
     let drill_cols = join(drills.iter().map(|d| d.col_qual_string()), ", ");
-    let mea_cols = join(meas.iter().map(|m| agg_sql_string(m)), ", ");
+    let mut mea_cols = join(meas.iter().map(|m| agg_sql_string(m)), ", ");
+
+    // Rate is the share of the measure (only one is allowed) which falls
+    // within the rate members. Only sum and count are allowed, so the
+    // aggregator can be applied on the conditional directly.
+    if let Some(rate) = rate {
+        let rate_col = rate.drilldown_sql.level_columns.last()
+            .map(|l| rate.drilldown_sql.col_qual(&l.key_column))
+            .unwrap_or_default();
+
+        let rate_mea = MeasureSql {
+            aggregator: meas[0].aggregator.clone(),
+            column: format!("case when {} in ({}) then {} end", rate_col, rate.members_string(), meas[0].column),
+            null_zero: false,
+        };
+
+        mea_cols = format!("{}, 1.0 * {} / nullif({}, 0)",
+            mea_cols,
+            agg_sql_string(&rate_mea),
+            agg_sql_string(&meas[0]),
+        );
+    }
 
-    let mut final_sql = format!("select {}, {} from {}",
-        drill_cols,
-        mea_cols,
+    // measure-only queries have no drill cols, and aggregate into a single row; distinct
+    // queries have no measures, and select the distinct drill cols instead of grouping
+    let distinct = meas.is_empty();
+    let select_cols = if drill_cols.is_empty() {
+        mea_cols
+    } else if distinct {
+        drill_cols.clone()
+    } else {
+        format!("{}, {}", drill_cols, mea_cols)
+    };
+
+    let mut final_sql = format!("select {}{} from {}",
+        if distinct { "distinct " } else { "" },
+        select_cols,
         table.name,
     );
 
+    if let Some(percent) = table.sample_percent() {
+        final_sql = format!("{} tablesample system ({})", final_sql, percent);
+    }
+
     // join external dims
     if !ext_drills.is_empty() {
         let join_ext_dim_clauses = join(ext_drills.iter()
             .map(|d| {
-                format!("inner join {} on {}.{} = {}.{}",
+                format!("inner join {} on {}.{} = {}.{}{}",
                     d.table.full_name(),
                     d.table.full_name(),
                     d.primary_key,
                     table.name,
                     d.foreign_key,
+                    d.table.joins_sql(&d.joins),
                 )
-        }), ", ");
+        }), " ");
 
         final_sql = format!("{} {}", final_sql, join_ext_dim_clauses);
     }
 
-    if !cuts.is_empty() {
-        let cut_clauses = join(cuts.iter().map(|c| format!("{} in ({})", c.col_qual_string(), c.members_string())), " and ");
-        final_sql = format!("{} where {}", final_sql, cut_clauses);
+    // cuts without members only keep the rows of their hierarchy (a default hierarchy, or
+    // an All drilldown's); inline tables have all of them
+    let mut where_clauses: Vec<_> = cuts.iter()
+        .filter_map(|c| {
+            if !c.members.is_empty() {
+                Some(format!("{} {} ({})", c.col_qual_string(), c.mask_sql_in_string(), c.members_string()))
+            } else if c.inline_table.is_none() {
+                Some(format!("{}.{} in (select {} from {})", table.name, c.foreign_key, c.primary_key, c.table.join_chain_sql(&c.joins)))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if let Some(ref sql_where) = table.sql_where {
+        where_clauses.push(format!("({})", sql_where));
+    }
+
+    where_clauses.extend(row_filters.iter().map(|f| f.sql_string()));
+
+    if !where_clauses.is_empty() {
+        final_sql = format!("{} where {}", final_sql, join(where_clauses, " and "));
+    }
+
+    if !drill_cols.is_empty() && !distinct {
+        final_sql = format!("{} group by {}", final_sql, drill_cols);
+    }
+
+    // sparse omits rows where all the measures are null or zero
+    if sparse && !meas.is_empty() {
+        let sparse_clauses = meas.iter().map(|m| format!("coalesce({}, 0) <> 0", agg_sql_string(m)));
+        final_sql = format!("{} having {}", final_sql, join(sparse_clauses, " or "));
     }
 
-    final_sql = format!("{} group by {};", final_sql, drill_cols);
-    final_sql
+    // calculations aren't supported here, so only sort by result columns
+    let mut sort_cols: Vec<_> = sort.iter()
+        .filter_map(|s| {
+            s.column_idx.map(|idx| format!("{} {}", idx + 1, s.direction.sql_string()))
+        })
+        .collect();
+
+    // with a limit, break ties on the drilldown key columns so that pages are stable
+    if limit.is_some() {
+        let key_cols = drills.iter()
+            .flat_map(|d| d.level_columns.iter().map(move |l| d.col_qual(&l.key_column)));
+        sort_cols.extend(key_cols);
+    }
+
+    if !sort_cols.is_empty() {
+        final_sql = format!("{} order by {}", final_sql, join(sort_cols, ", "));
+    }
+
+    if let Some(limit) = limit {
+        final_sql = match limit.offset {
+            Some(offset) => format!("{} limit {} offset {}", final_sql, limit.n, offset),
+            None => format!("{} limit {}", final_sql, limit.n),
+        };
+    }
+
+    format!("{};", final_sql)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::names::Mask;
-    use crate::query_ir::{MemberType, LevelColumn};
+    use crate::query_ir::{CutSql, LevelColumn, LimitSql, MemberType, RateSql, RowFilterSql, SortSql, TableSql};
+    use crate::query::{Comparison, Constraint, Operator, SortDirection};
     use crate::Table;
 
+    /// A query on `table` without cuts, drilldowns, measures or calculations.
+    fn query_ir(table: &TableSql) -> QueryIr {
+        QueryIr {
+            table: table.clone(),
+            cuts: vec![],
+            drills: vec![],
+            meas: vec![],
+            hidden_drills: vec![],
+            filters: vec![],
+            row_filters: vec![],
+            top: None,
+            top_where: None,
+            sort: vec![],
+            limit: None,
+            rca: None,
+            growth: None,
+            share: None,
+            previous_year: None,
+            rate: None,
+            sparse: false,
+            priority: None,
+            distributed: None,
+            null_zero_idxs: vec![],
+            key_widths: vec![],
+            all_members: vec![],
+        }
+    }
+
     #[test]
     /// Tests:
     /// - basic standard sql generation
@@ -123,12 +250,15 @@ mod test {
         let table = TableSql {
             name: "project_facts".into(),
             primary_key: Some("id".into()),
+            sample: None,
+            sql_where: None,
         };
         let cuts = vec![
             CutSql {
                 foreign_key: "project_id".into(),
                 primary_key: "id".into(),
                 inline_table: None,
+                joins: vec![],
                 table: Table { name: "valid_projects".into(), schema: None, primary_key: None },
                 column: "id".into(),
                 members: vec!["3".into()],
@@ -145,6 +275,7 @@ mod test {
                 foreign_key: "project_id".into(),
                 primary_key: "id".into(),
                 inline_table: None,
+                joins: vec![],
                 table: Table { name: "valid_projects".into(), schema: None, primary_key: None },
                 level_columns: vec![
                     LevelColumn {
@@ -156,13 +287,147 @@ mod test {
             },
         ];
         let meas = vec![
-            MeasureSql { aggregator: Aggregator::Sum, column: "commits".into() }
+            MeasureSql { aggregator: Aggregator::Sum, column: "commits".into(), null_zero: false }
         ];
 
         assert_eq!(
-            standard_sql(&table, &cuts, &drills, &meas, &None, &None, &None, &None, &None),
+            standard_sql(&QueryIr { cuts: cuts.clone(), drills: drills.clone(), meas: meas.clone(), ..query_ir(&table) }),
             "select valid_projects.id, valid_projects.name, sum(commits) from project_facts inner join valid_projects on valid_projects.id = project_facts.project_id where valid_projects.id in (3) group by valid_projects.id, valid_projects.name;".to_owned()
         );
+
+        let sort = vec![
+            SortSql { direction: SortDirection::Desc, column: "final_m0".into(), column_idx: Some(2) },
+            SortSql { direction: SortDirection::Asc, column: "name_".into(), column_idx: Some(1) },
+        ];
+        assert_eq!(
+            standard_sql(&QueryIr { drills: drills.clone(), meas: meas.clone(), sort, ..query_ir(&table) }),
+            "select valid_projects.id, valid_projects.name, sum(commits) from project_facts inner join valid_projects on valid_projects.id = project_facts.project_id group by valid_projects.id, valid_projects.name order by 3 desc, 2 asc;".to_owned()
+        );
+
+        // without measures, the distinct drilldown combinations
+        assert_eq!(
+            standard_sql(&QueryIr { cuts, drills, ..query_ir(&table) }),
+            "select distinct valid_projects.id, valid_projects.name from project_facts inner join valid_projects on valid_projects.id = project_facts.project_id where valid_projects.id in (3);".to_owned()
+        );
     }
-}
 
+    #[test]
+    /// Tests:
+    /// - rate on a level that's not drilled down on, so it gets joined
+    /// - rate with text members
+    /// - cuts and limit together with rate
+    /// - limit orders by drill key columns
+    fn test_standard_sql_rate() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+            sample: None,
+            sql_where: None,
+        };
+        let cuts = vec![
+            CutSql {
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                joins: vec![],
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                column: "year".into(),
+                members: vec!["2019".into()],
+                member_type: MemberType::NonText,
+                mask: Mask::Include,
+                for_match: false
+            },
+        ];
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                joins: vec![],
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), null_zero: false }
+        ];
+        let rate = RateSql {
+            drilldown_sql: DrilldownSql {
+                alias_postfix: "".into(),
+                foreign_key: "category_id".into(),
+                primary_key: "category_id".into(),
+                inline_table: None,
+                joins: vec![],
+                table: Table { name: "dim_category".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "category_id".into(),
+                        name_column: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            members: vec!["a".into(), "b".into()],
+            member_type: MemberType::Text,
+        };
+        let limit = LimitSql { offset: Some(10), n: 5 };
+
+        assert_eq!(
+            standard_sql(&QueryIr { cuts, drills, meas, limit: Some(limit), rate: Some(rate), ..query_ir(&table) }),
+            "select sales.year, sum(quantity), 1.0 * sum(case when dim_category.category_id in ('a', 'b') then quantity end) / nullif(sum(quantity), 0) from sales inner join dim_category on dim_category.category_id = sales.category_id where sales.year in (2019) group by sales.year order by sales.year limit 5 offset 10;".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_measures_only() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+            sample: None,
+            sql_where: None,
+        };
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into(), null_zero: false }
+        ];
+
+        assert_eq!(
+            standard_sql(&QueryIr { meas: meas.clone(), ..query_ir(&table) }),
+            "select sum(quantity) from sales;".to_owned()
+        );
+
+        assert_eq!(
+            standard_sql(&QueryIr { meas: meas.clone(), sparse: true, ..query_ir(&table) }),
+            "select sum(quantity) from sales having coalesce(sum(quantity), 0) <> 0;".to_owned()
+        );
+
+        let table = TableSql { sample: Some(0.07), ..table };
+        assert_eq!(
+            standard_sql(&QueryIr { meas: meas.clone(), ..query_ir(&table) }),
+            "select sum(quantity) from sales tablesample system (7);".to_owned()
+        );
+
+        let table = TableSql { sample: None, sql_where: Some("status = 'final'".into()), ..table };
+        assert_eq!(
+            standard_sql(&QueryIr { meas: meas.clone(), ..query_ir(&table) }),
+            "select sum(quantity) from sales where (status = 'final');".to_owned()
+        );
+
+        let row_filters = vec![RowFilterSql {
+            column: "quantity".into(),
+            constraint: Constraint { comparison: Comparison::GreaterThan, n: 10.0 },
+            operator: Some(Operator::And),
+            constraint2: Some(Constraint { comparison: Comparison::LessThan, n: 100.0 }),
+        }];
+        assert_eq!(
+            standard_sql(&QueryIr { row_filters, meas: meas.clone(), ..query_ir(&table) }),
+            "select sum(quantity) from sales where (status = 'final') and (quantity > 10 and quantity < 100);".to_owned()
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Dictionary encoded text columns.
+//!
+//! Drilldowns on large cubes return the same few dimension labels over and over. A
+//! `TextDict` stores each distinct value once, and each row as an index into them, so that
+//! a column of millions of rows holds only as many strings as it has members. Backends
+//! build them with a `TextDictBuilder` straight from the driver's bytes, which also saves
+//! allocating a string per row.
+
+use std::collections::HashMap;
+
+use crate::dataframe::ColumnData;
+
+
+/// A text column, as its distinct `values` and the index into them of each row.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TextDict {
+    pub values: Vec<String>,
+    pub codes: Vec<u32>,
+}
+
+impl TextDict {
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// The value of row `idx`.
+    pub fn get(&self, idx: usize) -> &str {
+        &self.values[self.codes[idx] as usize]
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=&'a str> + 'a {
+        self.codes.iter().map(move |code| self.values[*code as usize].as_str())
+    }
+
+    /// Decodes into one string per row.
+    pub fn to_text(&self) -> Vec<String> {
+        self.iter().map(|s| s.to_owned()).collect()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.codes.truncate(len);
+    }
+
+    /// Appends the rows of `other`, adding the values it doesn't have yet.
+    pub fn append(&mut self, other: TextDict) {
+        let mut codes: HashMap<String, u32> = self.values.iter()
+            .enumerate()
+            .map(|(code, value)| (value.clone(), code as u32))
+            .collect();
+
+        let other_codes: Vec<u32> = other.values.into_iter()
+            .map(|value| {
+                let next_code = self.values.len() as u32;
+                let code = *codes.entry(value.clone()).or_insert(next_code);
+                if code == next_code {
+                    self.values.push(value);
+                }
+                code
+            })
+            .collect();
+
+        self.codes.extend(other.codes.into_iter().map(|code| other_codes[code as usize]));
+    }
+
+    /// Sorts the rows by their values.
+    pub fn sort(&mut self) {
+        let values = &self.values;
+        self.codes.sort_by(|a, b| values[*a as usize].cmp(&values[*b as usize]));
+    }
+}
+
+impl From<Vec<String>> for TextDict {
+    fn from(text: Vec<String>) -> Self {
+        let mut builder = TextDictBuilder::with_capacity(text.len());
+        for s in &text {
+            builder.push(s);
+        }
+        builder.finish()
+    }
+}
+
+
+/// Builds a `TextDict` row by row, keeping one copy of each distinct value.
+#[derive(Debug, Default)]
+pub struct TextDictBuilder {
+    dict: TextDict,
+    codes: HashMap<String, u32>,
+}
+
+impl TextDictBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(rows: usize) -> Self {
+        TextDictBuilder {
+            dict: TextDict {
+                values: vec![],
+                codes: Vec::with_capacity(rows),
+            },
+            codes: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: &str) {
+        let code = match self.codes.get(value) {
+            Some(code) => *code,
+            None => {
+                let code = self.dict.values.len() as u32;
+                self.dict.values.push(value.to_owned());
+                self.codes.insert(value.to_owned(), code);
+                code
+            },
+        };
+        self.dict.codes.push(code);
+    }
+
+    pub fn finish(self) -> TextDict {
+        self.dict
+    }
+
+    /// Finishes into a `DictText` column if values repeat, on average, at least twice;
+    /// otherwise the codes would only add to the strings, and it's a `Text` column.
+    pub fn finish_column(self) -> ColumnData {
+        let dict = self.dict;
+        if dict.values.len() * 2 <= dict.len() {
+            ColumnData::DictText(dict)
+        } else {
+            ColumnData::Text(dict.to_text())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_dict() {
+        let text: Vec<String> = vec!["AL".into(), "AK".into(), "AL".into(), "AL".into()];
+        let mut dict = TextDict::from(text.clone());
+
+        assert_eq!(dict.values, vec!["AL".to_owned(), "AK".to_owned()]);
+        assert_eq!(dict.codes, vec![0, 1, 0, 0]);
+        assert_eq!(dict.get(1), "AK");
+        assert_eq!(dict.to_text(), text);
+
+        dict.sort();
+        assert_eq!(dict.to_text(), vec!["AK".to_owned(), "AL".into(), "AL".into(), "AL".into()]);
+
+        dict.truncate(2);
+        assert_eq!(dict.len(), 2);
+
+        dict.append(TextDict::from(vec!["AZ".to_owned(), "AL".into()]));
+        assert_eq!(dict.values, vec!["AL".to_owned(), "AK".into(), "AZ".into()]);
+        assert_eq!(dict.to_text(), vec!["AK".to_owned(), "AL".into(), "AZ".into(), "AL".into()]);
+    }
+
+    #[test]
+    fn test_finish_column() {
+        let mut builder = TextDictBuilder::new();
+        for s in &["AL", "AK", "AL", "AK"] {
+            builder.push(s);
+        }
+        match builder.finish_column() {
+            ColumnData::DictText(dict) => assert_eq!(dict.values.len(), 2),
+            column => panic!("expected a DictText column, got {:?}", column),
+        }
+
+        let mut builder = TextDictBuilder::new();
+        for s in &["AL", "AK", "AZ"] {
+            builder.push(s);
+        }
+        assert_eq!(
+            builder.finish_column(),
+            ColumnData::Text(vec!["AL".into(), "AK".into(), "AZ".into()])
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Convert DuckDB rows to tesseract_core::DataFrame
+//!
+//! DuckDB values are read dynamically, so each column's type is the type of its first
+//! non-null value; a column with nulls is nullable.
+//!
+//! Dates, times and timestamps become text columns, in ISO 8601 format (e.g. `2020-03-01`,
+//! `12:30:00` and `2020-03-01 12:30:00`), like other databases return them.
+
+use duckdb::Statement;
+use duckdb::types::{TimeUnit, Value};
+use failure::{Error, format_err};
+use std::convert::TryFrom;
+use tesseract_core::{DataFrame, Column, ColumnData};
+
+
+pub fn rows_to_df(stmt: &mut Statement) -> Result<DataFrame, Error> {
+    let mut value_rows: Vec<Vec<Value>> = vec![];
+
+    {
+        let mut rows = stmt.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let column_count = row.as_ref().column_count();
+            let values = (0..column_count)
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            value_rows.push(values);
+        }
+    }
+
+    let names = stmt.column_names();
+
+    let mut columns = vec![];
+    for (i, name) in names.into_iter().enumerate() {
+        let values: Vec<Value> = value_rows.iter_mut()
+            .map(|row| std::mem::replace(&mut row[i], Value::Null))
+            .collect();
+
+        columns.push(Column::new(name, values_to_column_data(values)?));
+    }
+
+    Ok(DataFrame::from_vec(columns))
+}
+
+fn values_to_column_data(values: Vec<Value>) -> Result<ColumnData, Error> {
+    let nullable = values.iter().any(|v| *v == Value::Null);
+    let first = values.iter().find(|v| **v != Value::Null).cloned();
+
+    macro_rules! column {
+        ($variant:ident, $nullable_variant:ident, $convert:expr) => {{
+            let converted = values.into_iter()
+                .map(|v| match v {
+                    Value::Null => Ok(None),
+                    v => $convert(v).map(Some),
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            if nullable {
+                ColumnData::$nullable_variant(converted)
+            } else {
+                ColumnData::$variant(converted.into_iter().map(|v| v.unwrap()).collect())
+            }
+        }};
+    }
+
+    let column_data = match first {
+        Some(Value::Boolean(_)) | Some(Value::TinyInt(_)) => column!(Int8, NullableInt8, |v| match v {
+            Value::Boolean(b) => Ok(b as i8),
+            Value::TinyInt(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::SmallInt(_)) => column!(Int16, NullableInt16, |v| match v {
+            Value::SmallInt(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::Int(_)) => column!(Int32, NullableInt32, |v| match v {
+            Value::Int(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::BigInt(_)) | Some(Value::HugeInt(_)) => column!(Int64, NullableInt64, |v| match v {
+            Value::BigInt(n) => Ok(n),
+            Value::HugeInt(n) => i64::try_from(n)
+                .map_err(|_| format_err!("DuckDB hugeint {} is too large", n)),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::UTinyInt(_)) => column!(UInt8, NullableUInt8, |v| match v {
+            Value::UTinyInt(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::USmallInt(_)) => column!(UInt16, NullableUInt16, |v| match v {
+            Value::USmallInt(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::UInt(_)) => column!(UInt32, NullableUInt32, |v| match v {
+            Value::UInt(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::UBigInt(_)) => column!(UInt64, NullableUInt64, |v| match v {
+            Value::UBigInt(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::Float(_)) => column!(Float32, NullableFloat32, |v| match v {
+            Value::Float(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::Double(_)) | Some(Value::Decimal(_)) => column!(Float64, NullableFloat64, |v| match v {
+            Value::Double(n) => Ok(n),
+            Value::Decimal(d) => d.to_string().parse::<f64>()
+                .map_err(|_| format_err!("Could not read DuckDB decimal {}", d)),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::Text(_)) => column!(Text, NullableText, |v| match v {
+            Value::Text(s) => Ok(s),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(Value::Date32(_)) | Some(Value::Time64(..)) | Some(Value::Timestamp(..)) => column!(Text, NullableText, |v| match v {
+            Value::Date32(days) => Ok(date_string(days as i64)),
+            Value::Time64(unit, t) => Ok(time_string(micros(unit, t))),
+            Value::Timestamp(unit, t) => Ok(timestamp_string(micros(unit, t))),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(v) => return Err(format_err!("DuckDB type not yet supported: {:?}", v)),
+        // only nulls, or no rows
+        None => column!(Text, NullableText, |v| Err(mixed_types(&v))),
+    };
+
+    Ok(column_data)
+}
+
+fn mixed_types(value: &Value) -> Error {
+    format_err!("DuckDB column has values of different types: {:?}", value)
+}
+
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+fn micros(unit: TimeUnit, t: i64) -> i64 {
+    match unit {
+        TimeUnit::Second => t * 1_000_000,
+        TimeUnit::Millisecond => t * 1_000,
+        TimeUnit::Microsecond => t,
+        TimeUnit::Nanosecond => t / 1_000,
+    }
+}
+
+/// `YYYY-MM-DD` of a number of days since 1970-01-01, in the proleptic Gregorian calendar.
+fn date_string(days: i64) -> String {
+    // shifted to start on 0000-03-01, so that leap days end the year
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// `HH:MM:SS` of microseconds since midnight, with the fraction of a second if any.
+fn time_string(micros: i64) -> String {
+    let secs = micros / 1_000_000;
+    let fraction = micros % 1_000_000;
+
+    let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+
+    if fraction == 0 {
+        time
+    } else {
+        format!("{}.{:06}", time, fraction)
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS` of microseconds since 1970-01-01 00:00:00.
+fn timestamp_string(micros: i64) -> String {
+    format!(
+        "{} {}",
+        date_string(micros.div_euclid(MICROS_PER_DAY)),
+        time_string(micros.rem_euclid(MICROS_PER_DAY)),
+    )
+}
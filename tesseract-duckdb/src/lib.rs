@@ -0,0 +1,206 @@
+use duckdb::Connection;
+use failure::{Error, format_err};
+use futures::future::{self, Future};
+use futures::sync::oneshot;
+use log::*;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use tesseract_core::{Backend, Capabilities, DataFrame, QueryIr};
+use tesseract_core::dialect::{registered_or, sample_percent, SqlDialect, StandardSql};
+
+mod df;
+
+use self::df::rows_to_df;
+
+
+/// DuckDB sql dialect, registered as `duckdb`. DuckDB follows postgres closely, so this is
+/// standard sql, except that a `tablesample` size without a `%` is a number of rows.
+pub struct DuckDbSql;
+
+impl SqlDialect for DuckDbSql {
+    fn name(&self) -> &str {
+        "duckdb"
+    }
+
+    fn generate_sql(&self, query_ir: QueryIr) -> String {
+        let sample_percent = query_ir.table.sample_percent();
+        let sql = StandardSql.generate_sql(query_ir);
+
+        match sample_percent {
+            Some(percent) => sql.replacen(
+                &format!(" tablesample system ({})", percent),
+                &format!(" tablesample system ({}%)", percent),
+                1,
+            ),
+            None => sql,
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sampling: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn sample_table_sql(&self, table: &str, sample: f64) -> String {
+        format!("{} tablesample system ({}%)", table, sample_percent(sample))
+    }
+}
+
+
+#[derive(Clone)]
+pub struct DuckDb {
+    // each query runs on a clone of this connection, on its own thread
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl DuckDb {
+    /// `path` is a directory of Parquet and CSV files, each of which becomes a view named
+    /// after the file (e.g. `sales.parquet` is `sales`), a DuckDB database file, or `:memory:`.
+    pub fn from_path(path: &str) -> Result<Self, Error> {
+        let path = path.trim_start_matches("duckdb://");
+
+        let conn = if path.is_empty() || path == ":memory:" {
+            Connection::open_in_memory()?
+        } else if Path::new(path).is_dir() {
+            let conn = Connection::open_in_memory()?;
+            create_file_views(&conn, Path::new(path))?;
+            conn
+        } else {
+            Connection::open(path)?
+        };
+
+        Ok(DuckDb {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+/// Creates a view for each Parquet or CSV file in `dir`.
+fn create_file_views(conn: &Connection, dir: &Path) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let reader = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => "read_parquet",
+            Some("csv") => "read_csv_auto",
+            _ => continue,
+        };
+
+        let name = path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format_err!("Invalid file name {:?}", path))?;
+        let file = path.to_str()
+            .ok_or_else(|| format_err!("Invalid file path {:?}", path))?;
+
+        info!("DuckDB view {} for {}", name, file);
+
+        conn.execute_batch(&format!(
+            "create view \"{}\" as select * from {}('{}')",
+            name.replace('"', "\"\""), reader, file.replace('\'', "''"),
+        ))?;
+    }
+
+    Ok(())
+}
+
+fn query(conn: &Arc<Mutex<Connection>>, sql: &str) -> Result<DataFrame, Error> {
+    let conn = conn.lock()
+        .map_err(|_| format_err!("DuckDB connection lock poisoned"))?
+        .try_clone()?;
+
+    let mut stmt = conn.prepare(sql)?;
+
+    rows_to_df(&mut stmt)
+}
+
+impl Backend for DuckDb {
+    fn exec_sql(&self, sql: String) -> Box<dyn Future<Item=DataFrame, Error=Error>> {
+        let time_start = Instant::now();
+        let conn = self.conn.clone();
+
+        // duckdb is blocking, so queries don't run on the server's event loop
+        let (tx, rx) = oneshot::channel();
+        let spawned = thread::Builder::new()
+            .name("duckdb-query".to_owned())
+            .spawn(move || {
+                let _ = tx.send(query(&conn, &sql));
+            });
+
+        if let Err(err) = spawned {
+            return Box::new(future::err(format_err!("Could not start DuckDB query: {}", err)));
+        }
+
+        let fut = rx
+            .map_err(|_| format_err!("DuckDB query was cancelled"))
+            .and_then(move |res| {
+                let timing = time_start.elapsed();
+                info!("Time for sql execution: {}.{:03}", timing.as_secs(), timing.subsec_millis());
+
+                res
+            });
+
+        Box::new(fut)
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        registered_or(DuckDbSql)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tesseract_core::ColumnData;
+
+    #[test]
+    fn test_simple_query() {
+        let duckdb = DuckDb::from_path(":memory:").unwrap();
+
+        let sql = "select * from (values (2019, 'a', 1.5), (2020, null, 2.5)) t(year, name, quantity)";
+        let df = duckdb.exec_sql(sql.to_owned()).wait().unwrap();
+
+        assert_eq!(df.columns[0].name, "year");
+        assert_eq!(df.columns[0].column_data, ColumnData::Int32(vec![2019, 2020]));
+        assert_eq!(df.columns[1].column_data, ColumnData::NullableText(vec![Some("a".to_owned()), None]));
+        assert_eq!(df.columns[2].column_data, ColumnData::Float64(vec![1.5, 2.5]));
+    }
+
+    #[test]
+    fn test_dates() {
+        let duckdb = DuckDb::from_path(":memory:").unwrap();
+
+        let sql = "select date '2020-02-29' as day, timestamp '1969-12-31 23:59:59.5' as ts, time '12:30:00' as t";
+        let df = duckdb.exec_sql(sql.to_owned()).wait().unwrap();
+
+        assert_eq!(df.columns[0].column_data, ColumnData::Text(vec!["2020-02-29".to_owned()]));
+        assert_eq!(df.columns[1].column_data, ColumnData::Text(vec!["1969-12-31 23:59:59.500000".to_owned()]));
+        assert_eq!(df.columns[2].column_data, ColumnData::Text(vec!["12:30:00".to_owned()]));
+    }
+
+    #[test]
+    fn test_file_views() {
+        let dir = std::env::temp_dir().join("tesseract-duckdb-test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sales.csv"), "year,quantity\n2019,1\n2019,2\n2020,3\n").unwrap();
+
+        let duckdb = DuckDb::from_path(dir.to_str().unwrap()).unwrap();
+
+        let sql = "select year, sum(quantity) from sales group by year order by year";
+        let df = duckdb.exec_sql(sql.to_owned()).wait().unwrap();
+
+        // sums of integers are hugeints in duckdb
+        assert_eq!(df.columns[1].column_data, ColumnData::Int64(vec![3, 3]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
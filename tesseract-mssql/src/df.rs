@@ -0,0 +1,112 @@
+//! Convert sql server rows to tesseract_core::DataFrame
+//!
+//! Nullable integer columns are all reported as `intn`, so each column's type is the type of
+//! its first non-null value; a column with nulls is nullable.
+
+use failure::{Error, format_err};
+use tesseract_core::{DataFrame, Column, ColumnData};
+use tiberius::{Column as MsColumn, ColumnData as MsValue, Row};
+
+
+pub fn rows_to_df(columns: &[MsColumn], rows: Vec<Row>) -> Result<DataFrame, Error> {
+    let mut column_values: Vec<Vec<MsValue<'static>>> = (0..columns.len())
+        .map(|_| Vec::with_capacity(rows.len()))
+        .collect();
+
+    for row in rows {
+        for (i, value) in row.into_iter().enumerate() {
+            if let Some(values) = column_values.get_mut(i) {
+                values.push(value);
+            }
+        }
+    }
+
+    let df_columns = columns.iter()
+        .zip(column_values)
+        .map(|(col, values)| Ok(Column::new(col.name().to_owned(), values_to_column_data(values)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(DataFrame::from_vec(df_columns))
+}
+
+fn is_null(value: &MsValue) -> bool {
+    match value {
+        MsValue::U8(v) => v.is_none(),
+        MsValue::I16(v) => v.is_none(),
+        MsValue::I32(v) => v.is_none(),
+        MsValue::I64(v) => v.is_none(),
+        MsValue::F32(v) => v.is_none(),
+        MsValue::F64(v) => v.is_none(),
+        MsValue::Bit(v) => v.is_none(),
+        MsValue::String(v) => v.is_none(),
+        MsValue::Numeric(v) => v.is_none(),
+        // other types are not supported, so they don't decide a column's type
+        _ => false,
+    }
+}
+
+fn values_to_column_data(values: Vec<MsValue<'static>>) -> Result<ColumnData, Error> {
+    let nullable = values.iter().any(is_null);
+    let first = values.iter().find(|v| !is_null(v));
+
+    macro_rules! column {
+        ($variant:ident, $nullable_variant:ident, $convert:expr) => {{
+            let converted = values.into_iter()
+                .map($convert)
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            if nullable {
+                ColumnData::$nullable_variant(converted)
+            } else {
+                ColumnData::$variant(converted.into_iter().map(|v| v.unwrap_or_default()).collect())
+            }
+        }};
+    }
+
+    let column_data = match first {
+        Some(MsValue::U8(_)) => column!(UInt8, NullableUInt8, |v| match v {
+            MsValue::U8(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::Bit(_)) => column!(UInt8, NullableUInt8, |v| match v {
+            MsValue::Bit(b) => Ok(b.map(|b| b as u8)),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::I16(_)) => column!(Int16, NullableInt16, |v| match v {
+            MsValue::I16(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::I32(_)) => column!(Int32, NullableInt32, |v| match v {
+            MsValue::I32(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::I64(_)) => column!(Int64, NullableInt64, |v| match v {
+            MsValue::I64(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::F32(_)) => column!(Float32, NullableFloat32, |v| match v {
+            MsValue::F32(n) => Ok(n),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::F64(_)) | Some(MsValue::Numeric(_)) => column!(Float64, NullableFloat64, |v| match v {
+            MsValue::F64(n) => Ok(n),
+            MsValue::Numeric(n) => Ok(n.map(f64::from)),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(MsValue::String(_)) => column!(Text, NullableText, |v| match v {
+            MsValue::String(s) => Ok(s.map(|s| s.into_owned())),
+            v => Err(mixed_types(&v)),
+        }),
+        Some(v) => return Err(format_err!("Sql server type not yet supported: {:?}", v)),
+        // only nulls, or no rows
+        None => column!(Text, NullableText, |v: MsValue<'static>| {
+            if is_null(&v) { Ok(None) } else { Err(mixed_types(&v)) }
+        }),
+    };
+
+    Ok(column_data)
+}
+
+fn mixed_types(value: &MsValue) -> Error {
+    format_err!("Sql server column has values of different types: {:?}", value)
+}
@@ -0,0 +1,118 @@
+use failure::{Error, format_err};
+use futures::future::Future;
+use futures::sync::oneshot;
+use log::*;
+use std::sync::Arc;
+use std::time::Instant;
+use tesseract_core::{Backend, DataFrame};
+use tesseract_core::dialect::{registered_or, SqlDialect};
+use tiberius::Config;
+use tokio::runtime::Runtime;
+
+mod df;
+mod pool;
+mod sql;
+
+use self::df::rows_to_df;
+use self::pool::Pool;
+pub use self::sql::MsSqlSql;
+
+
+/// Tiberius runs on a newer tokio than the server, so queries run on a runtime of their own,
+/// shared by the clones of the backend along with the connection pool.
+#[derive(Clone)]
+pub struct MsSql {
+    pool: Arc<Pool>,
+    runtime: Arc<Runtime>,
+}
+
+impl MsSql {
+    /// `address` is an ADO.NET connection string, optionally prefixed with `mssql://`, e.g.
+    /// `server=tcp:localhost,1433;database=db;user=sa;password=...`
+    pub fn from_addr(address: &str) -> Result<Self, Error> {
+        let address = address.trim_start_matches("mssql://");
+        let config = Config::from_ado_string(address)
+            .map_err(|err| format_err!("Invalid sql server connection string: {}", err))?;
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("mssql")
+            .enable_all()
+            .build()
+            .map_err(|err| format_err!("Could not start sql server runtime: {}", err))?;
+
+        Ok(MsSql {
+            pool: Arc::new(Pool::new(config)),
+            runtime: Arc::new(runtime),
+        })
+    }
+}
+
+async fn query(pool: Arc<Pool>, sql: String) -> Result<DataFrame, Error> {
+    let mut conn = pool.get().await?;
+
+    let (columns, rows) = {
+        let mut stream = conn.simple_query(sql).await?;
+
+        // kept separately, as there are no rows to read them from when the result is empty
+        let columns = stream.columns().await?
+            .map(|columns| columns.to_vec())
+            .unwrap_or_default();
+        let rows = stream.into_first_result().await?;
+
+        (columns, rows)
+    };
+
+    // only once the results are read, so that a failed query's connection is dropped
+    conn.release();
+
+    rows_to_df(&columns, rows)
+}
+
+impl Backend for MsSql {
+    fn exec_sql(&self, sql: String) -> Box<dyn Future<Item=DataFrame, Error=Error>> {
+        let time_start = Instant::now();
+        let pool = self.pool.clone();
+
+        let (tx, rx) = oneshot::channel();
+        self.runtime.spawn(async move {
+            let _ = tx.send(query(pool, sql).await);
+        });
+
+        let fut = rx
+            .map_err(|_| format_err!("Sql server query was cancelled"))
+            .and_then(move |res| {
+                let timing = time_start.elapsed();
+                info!("Time for sql execution: {}.{:03}", timing.as_secs(), timing.subsec_millis());
+
+                res
+            });
+
+        Box::new(fut)
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        registered_or(MsSqlSql)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // TODO move to integration tests
+    #[test]
+    #[ignore]
+    fn test_simple_query() {
+        let mssql_db = env::var("MSSQL_DATABASE_URL").unwrap();
+        let sql = r"SELECT 1 as example_int, 'hello' as example_name, 0.5 as example_float;";
+        let mssql = MsSql::from_addr(&mssql_db).unwrap();
+        let r = mssql.exec_sql(sql.to_string()).wait().unwrap();
+        println!("{:?}", r);
+    }
+}
@@ -0,0 +1,88 @@
+//! A pool of sql server connections.
+//!
+//! Connections are reused across queries, instead of logging in for each, and at most
+//! `MAX_CONNECTIONS` are open at once; further queries wait for one to be released.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use failure::{Error, format_err};
+use tiberius::{Client, Config};
+use tokio::net::TcpStream;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+
+
+/// Max open connections, as in the default of the postgres backend's pool.
+const MAX_CONNECTIONS: usize = 10;
+
+pub type Connection = Client<Compat<TcpStream>>;
+
+pub struct Pool {
+    config: Config,
+    idle: Mutex<Vec<Connection>>,
+    permits: Semaphore,
+}
+
+impl Pool {
+    pub fn new(config: Config) -> Self {
+        Pool {
+            config,
+            idle: Mutex::new(vec![]),
+            permits: Semaphore::new(MAX_CONNECTIONS),
+        }
+    }
+
+    /// An idle connection, or a new one if there's none, once fewer than `MAX_CONNECTIONS`
+    /// are in use.
+    pub async fn get(&self) -> Result<PooledConnection<'_>, Error> {
+        let permit = self.permits.acquire().await
+            .map_err(|_| format_err!("Sql server connection pool is closed"))?;
+
+        let idle = self.idle.lock().unwrap().pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => self.connect().await?,
+        };
+
+        Ok(PooledConnection { pool: self, conn: Some(conn), _permit: permit })
+    }
+
+    async fn connect(&self) -> Result<Connection, Error> {
+        let tcp = TcpStream::connect(self.config.get_addr()).await?;
+        tcp.set_nodelay(true)?;
+
+        Ok(Client::connect(self.config.clone(), tcp.compat_write()).await?)
+    }
+}
+
+
+/// A connection of the pool. It's only returned to the pool with `release`; when dropped
+/// otherwise, e.g. after an error left it in an unknown state, it's closed.
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<'a> PooledConnection<'a> {
+    pub fn release(mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.idle.lock().unwrap().push(conn);
+        }
+    }
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection is only taken on release")
+    }
+}
+
+impl<'a> DerefMut for PooledConnection<'a> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("connection is only taken on release")
+    }
+}
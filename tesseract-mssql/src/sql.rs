@@ -0,0 +1,199 @@
+//! T-SQL flavor of the standard sql: sql server has no `limit`, so the limit becomes a `top`
+//! (or `offset ... fetch`, with an offset), table names are quoted with brackets, and
+//! `tablesample` needs a unit.
+
+use tesseract_core::{Capabilities, PreviousYearColumns, QueryIr};
+use tesseract_core::dialect::{preview_sql, previous_year_sql, sample_percent, ResultOrder, SqlDialect, StandardSql};
+use tesseract_core::query_ir::LimitSql;
+use tesseract_core::schema::Table;
+
+
+/// T-SQL dialect, registered as `mssql`.
+pub struct MsSqlSql;
+
+impl SqlDialect for MsSqlSql {
+    fn name(&self) -> &str {
+        "mssql"
+    }
+
+    fn generate_sql(&self, mut query_ir: QueryIr) -> String {
+        let limit = query_ir.limit.clone();
+        let sample_percent = query_ir.table.sample_percent();
+
+        bracket_tables(&mut query_ir);
+
+        let mut sql = StandardSql.generate_sql(query_ir);
+
+        if let Some(percent) = sample_percent {
+            sql = sql.replacen(
+                &format!(" tablesample system ({})", percent),
+                &format!(" tablesample system ({} percent)", percent),
+                1,
+            );
+        }
+
+        match limit {
+            Some(limit) => paginate(&sql, &limit),
+            None => sql,
+        }
+    }
+
+    /// `row_number` needs an `order by`.
+    fn preview_sql(&self, sql: &str, rows: u64, order: &ResultOrder) -> String {
+        preview_sql(&subquery_sql(sql), rows, &order.row_number_sql("(order by (select null))"), Some(order.column_count))
+    }
+
+    fn previous_year_sql(&self, sql: &str, columns: &PreviousYearColumns, order: &ResultOrder, limit: Option<u64>) -> String {
+        let sql = previous_year_sql(&subquery_sql(sql), columns, order);
+        match limit {
+            Some(n) => paginate(&sql, &LimitSql { offset: None, n }),
+            None => sql,
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sampling: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn sample_table_sql(&self, table: &str, sample: f64) -> String {
+        format!("{} tablesample system ({} percent)", table, sample_percent(sample))
+    }
+
+    /// T-SQL has no `create table ... as`; `select ... into` creates the table.
+    fn create_table_as_sql(&self, table: &str, sql: &str) -> String {
+        format!("select * into {} from ({}) as preagg;", bracket(table), sql.trim_end_matches(';'))
+    }
+}
+
+
+/// The sql of a query to use as a subquery: an `order by` in a subquery needs a `top` or an
+/// `offset`.
+fn subquery_sql(sql: &str) -> String {
+    let sql = sql.trim_end_matches(';');
+    let has_top = sql.starts_with("select top ") || sql.starts_with("select distinct top ");
+    if sql.contains(" order by ") && !has_top && !sql.contains(" offset ") {
+        format!("{} offset 0 rows", sql)
+    } else {
+        sql.to_owned()
+    }
+}
+
+/// Quotes an identifier with brackets; `]` is escaped by doubling it.
+pub fn bracket(name: &str) -> String {
+    if name.starts_with('[') && name.ends_with(']') {
+        name.to_owned()
+    } else {
+        format!("[{}]", name.replace(']', "]]"))
+    }
+}
+
+fn bracket_table(table: &mut Table) {
+    table.name = bracket(&table.name);
+    table.schema = table.schema.as_ref().map(|schema| bracket(schema));
+}
+
+/// Quotes the fact and dimension table names. The fact table name may be qualified by its
+/// schema.
+fn bracket_tables(query_ir: &mut QueryIr) {
+    query_ir.table.name = query_ir.table.name
+        .split('.')
+        .map(bracket)
+        .collect::<Vec<_>>()
+        .join(".");
+
+    for drill in &mut query_ir.drills {
+        bracket_table(&mut drill.table);
+    }
+    for cut in &mut query_ir.cuts {
+        bracket_table(&mut cut.table);
+    }
+    if let Some(ref mut rate) = query_ir.rate {
+        bracket_table(&mut rate.drilldown_sql.table);
+    }
+}
+
+/// Replaces the trailing `limit n [offset m]` of standard sql. Without an offset, it's a
+/// `top (n)`; with one, `offset m rows fetch next n rows only`, which requires an `order by`.
+pub fn paginate(sql: &str, limit: &LimitSql) -> String {
+    let limit_clause = match limit.offset {
+        Some(offset) => format!(" limit {} offset {};", limit.n, offset),
+        None => format!(" limit {};", limit.n),
+    };
+
+    let sql = match sql.rfind(&limit_clause) {
+        Some(idx) if idx + limit_clause.len() == sql.len() => &sql[..idx],
+        _ => sql.trim_end_matches(';'),
+    };
+
+    match limit.offset {
+        Some(offset) => {
+            let order_by = if sql.contains(" order by ") { "" } else { " order by (select null)" };
+            format!("{}{} offset {} rows fetch next {} rows only;", sql, order_by, offset, limit.n)
+        },
+        None => {
+            // `top` comes after `distinct`
+            let (select, rest) = match sql.strip_prefix("select distinct ") {
+                Some(rest) => ("select distinct", rest),
+                None => ("select", sql.trim_start_matches("select ")),
+            };
+            format!("{} top ({}) {};", select, limit.n, rest)
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::query::SortDirection;
+
+    #[test]
+    fn test_bracket() {
+        assert_eq!(bracket("sales"), "[sales]");
+        assert_eq!(bracket("[sales]"), "[sales]");
+        assert_eq!(bracket("odd]name"), "[odd]]name]");
+    }
+
+    #[test]
+    fn test_paginate() {
+        let sql = "select [sales].year, sum(quantity) from [sales] group by [sales].year order by [sales].year";
+
+        assert_eq!(
+            paginate(&format!("{} limit 5;", sql), &LimitSql { offset: None, n: 5 }),
+            "select top (5) [sales].year, sum(quantity) from [sales] group by [sales].year order by [sales].year;"
+        );
+        assert_eq!(
+            paginate(&format!("{} limit 5 offset 10;", sql), &LimitSql { offset: Some(10), n: 5 }),
+            format!("{} offset 10 rows fetch next 5 rows only;", sql)
+        );
+        assert_eq!(
+            paginate("select sum(quantity) from [sales] limit 1 offset 1;", &LimitSql { offset: Some(1), n: 1 }),
+            "select sum(quantity) from [sales] order by (select null) offset 1 rows fetch next 1 rows only;"
+        );
+        assert_eq!(
+            paginate("select distinct [products].name from [products] order by [products].name limit 10;", &LimitSql { offset: None, n: 10 }),
+            "select distinct top (10) [products].name from [products] order by [products].name;"
+        );
+    }
+
+    #[test]
+    fn test_sample_table_sql() {
+        assert_eq!(MsSqlSql.sample_table_sql("[sales]", 0.07), "[sales] tablesample system (7 percent)");
+    }
+
+    #[test]
+    fn test_preview_sql() {
+        let order = ResultOrder { column_count: 2, sort: vec![(0, SortDirection::Asc)] };
+        let sql = MsSqlSql.preview_sql("select [sales].year, sum(quantity) from [sales] group by [sales].year order by 1 asc;", 50, &order);
+
+        assert_eq!(
+            sql,
+            "select * from (select preview_q.*, row_number() over (order by preview_c0 asc) as preview_row, count(*) over () as preview_total \
+            from (select [sales].year, sum(quantity) from [sales] group by [sales].year order by 1 asc offset 0 rows) as preview_q (preview_c0, preview_c1)) as preview_p \
+            where preview_row <= 50 or preview_row > preview_total - 50 order by preview_row;"
+        );
+    }
+}
@@ -6,7 +6,7 @@ use mysql_async::{QueryResult, BinaryProtocol, Conn};
 use mysql_async::consts::ColumnType::*;
 use mysql_async::Value::*;
 use std::str;
-use tesseract_core::{DataFrame, Column, ColumnData};
+use tesseract_core::{DataFrame, DataFrameBuilder, Column, ColumnData};
 
 pub fn rows_to_df(query_result: QueryResult<Conn, BinaryProtocol>) -> Box<Future<Item=DataFrame, Error=Error>> {
     let mut tcolumn_list = vec![];
@@ -66,97 +66,54 @@ pub fn rows_to_df(query_result: QueryResult<Conn, BinaryProtocol>) -> Box<Future
         }
     }
 
-    let df = DataFrame::from_vec(tcolumn_list);
+    // mysql doesn't report the number of rows before reading them, so they're read in chunks
+    let df = DataFrameBuilder::new(tcolumn_list);
 
-    let future = query_result.reduce(df, |mut df_accum, r| {
+    // the accumulator is a Result, so that a value which can't be converted fails the query
+    let future = query_result.reduce(Ok(df), |df_accum: Result<DataFrameBuilder, Error>, r| {
+        let mut df_accum = df_accum?;
         let row = r.unwrap();
 
-        for col_idx in 0..df_accum.columns.len() {
-            let column_data = df_accum.columns
-                .get_mut(col_idx)
-                .expect("logic checked?")
-                .column_data();
+        for (col_idx, column) in df_accum.columns_mut().iter_mut().enumerate() {
+            let column_data = column.column_data();
+            let raw_value = row.get(col_idx).unwrap();
             match column_data {
-                ColumnData::Int8(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
-                    match raw_value {
-                        Int(y) => {
-                            let raw_val: i8 = *y as i8;
-                            Some(col_data.push(raw_val))
-                        },
-                        _s => None
-                    };
-                },
-                ColumnData::Int16(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
-                    match raw_value {
-                        Int(y) => {
-                            let raw_val: i16 = *y as i16;
-                            Some(col_data.push(raw_val))
-                        },
-                        _s => None
-                    };
-                },
-                ColumnData::Int32(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
-                    match raw_value {
-                        Int(y) => {
-                            let raw_val: i32 = *y as i32;
-                            Some(col_data.push(raw_val))
-                        },
-                        _s => None
-                    };
-                },
-                ColumnData::Int64(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
-                    match raw_value {
-                        Int(y) => Some(col_data.push(*y)),
-                        _s => None
-                    };
-                },
-                ColumnData::Float32(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
-                    match raw_value {
-                        Float(y) => {
-                            let raw_val: f32 = *y as f32;
-                            Some(col_data.push(raw_val))
-                        },
-                        _s => None
-                    };
-                },
-                ColumnData::Float64(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
+                ColumnData::Text(col_data) => {
                     match raw_value {
-                        Float(y) => Some(col_data.push(*y)),
                         Bytes(y) => {
-                            let tmp_val = str::from_utf8(y).unwrap().parse().unwrap();
-                            Some(col_data.push(tmp_val))
+                            let tmp_str = str::from_utf8(y).unwrap();
+                            // TODO is there a more memory efficient way to handle this
+                            // other than copying the strings into the dataframe
+                            col_data.push(tmp_str.to_string())
                         },
-                        _s => None
+                        _s => (),
                     };
                 },
-                ColumnData::Text(col_data) => {
-                    let raw_value = row.get(col_idx).unwrap();
+                // numbers are pushed with checked conversions, which widen the column
+                // instead of truncating values that don't fit
+                column_data => {
                     match raw_value {
+                        Int(y) => column_data.push_i64(*y)?,
+                        UInt(y) => column_data.push_u64(*y)?,
+                        Float(y) => column_data.push_f64(*y)?,
+                        // decimals
                         Bytes(y) => {
-                            let tmp_str = str::from_utf8(y).unwrap();
-                            // TODO is there a more memory efficient way to handle this
-                            // other than copying the strings into the dataframe
-                            Some(col_data.push(tmp_str.to_string()))
+                            let tmp_val = str::from_utf8(y)?.parse()
+                                .map_err(|_| format_err!("Could not parse mysql number {:?}", y))?;
+                            column_data.push_f64(tmp_val)?
                         },
-                        _s => None
+                        _s => (),
                     };
                 },
-                _s => {
-                    println!("Unable to match column index {}!", col_idx);
-                }
             }
         }
 
-        df_accum
+        df_accum.end_row();
+
+        Ok(df_accum)
     })
-    .map(|(_, df)| df)
-    .map_err(|err| format_err!("mysql err {}", err));
+    .map_err(|err| format_err!("mysql err {}", err))
+    .and_then(|(_, df)| df?.finish());
 
     Box::new(future)
 }
@@ -1,6 +1,8 @@
 use failure::{Error, format_err};
 use futures::future::Future;
-use tesseract_core::{Backend, DataFrame};
+use std::sync::Arc;
+use tesseract_core::{Backend, Capabilities, DataFrame, QueryIr};
+use tesseract_core::dialect::{escaped_standard_sql, quote_identifier, registered_or, SqlDialect};
 
 extern crate futures;
 extern crate mysql_async as my;
@@ -10,6 +12,43 @@ use self::df::{rows_to_df};
 
 use my::prelude::*;
 
+/// MySQL sql dialect, registered as `mysql`: standard sql, except that string literals
+/// read backslash escapes, and identifiers are quoted with backticks.
+pub struct MySqlSql;
+
+impl SqlDialect for MySqlSql {
+    fn name(&self) -> &str {
+        "mysql"
+    }
+
+    fn generate_sql(&self, mut query_ir: QueryIr) -> String {
+        query_ir.escape(self);
+        escaped_standard_sql(query_ir)
+    }
+
+    fn escape_literal(&self, s: &str) -> String {
+        s.replace('\\', "\\\\").replace('\'', "''")
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_identifier(name, '`', '`')
+    }
+
+    /// MySQL casts to `char`, not `varchar`.
+    fn cast_to_text_sql(&self, expr: &str) -> String {
+        format!("cast({} as char)", expr)
+    }
+
+    /// MySQL has no `tablesample`.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sampling: false,
+            ..Capabilities::default()
+        }
+    }
+}
+
+
 #[derive(Clone)]
 pub struct MySql {
     pool: my::Pool
@@ -43,6 +82,10 @@ impl Backend for MySql {
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
         Box::new((*self).clone())
     }
+
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        registered_or(MySqlSql)
+    }
 }
 
 
@@ -1,96 +1,126 @@
+//! Convert postgres rows to tesseract_core::DataFrame
+//!
+//! Values are read as `Option`s, so a column with nulls is nullable. Types which aren't
+//! supported fail the query, rather than being skipped.
+
+use failure::{Error, bail};
 use tesseract_core::Column as TesseractColumn;
 use tesseract_core::DataFrame;
 use tesseract_core::ColumnData;
-use tokio_postgres::{Column , Row};
-
-// TODO: numeric type not supported!
-// TODO: boolean support
-
-pub fn rows_to_df(rows: Vec<Row>, columns: &[Column]) -> DataFrame {
-    let mut tcolumn_list = vec![];
-    // For each column in the dataframe, setup the appropriate column vector
-    // based on the underlying postgres types so that we will be able to add the values
-    for col in columns.iter() {
-        let col_type_name = col.type_().name();
-        let col_name = col.name();
-        match col_type_name {
-            "int4" => { // 4 bytes
-                tcolumn_list.push(TesseractColumn::new(
-                    col_name.to_string(),
-                    ColumnData::Int32(vec![]),
-                ))
-            },
-            "int8" => { // 8 bytes
-                tcolumn_list.push(TesseractColumn::new(
-                    col_name.to_string(),
-                    ColumnData::Int64(vec![]),
-                ))
-            },
-            "float4" | "real" => {
-                tcolumn_list.push(TesseractColumn::new(
-                    col_name.to_string(),
-                    ColumnData::Float32(vec![]),
-                ));
-            },
-            "float8" => {
-                tcolumn_list.push(TesseractColumn::new(
-                    col_name.to_string(),
-                    ColumnData::Float64(vec![]),
-                ));
-            },
-            "numeric" => {
-                tcolumn_list.push(TesseractColumn::new(
-                    col_name.to_string(),
-                    ColumnData::Float64(vec![]),
-                ));
-            },
-            "text" => {
-                tcolumn_list.push(TesseractColumn::new(
-                    col_name.to_string(),
-                    ColumnData::Text(vec![]),
-                ));
-            },
-            _ => {
-                println!("UNKNOWN TYPE {}", col_type_name);
+use tokio_postgres::{Column, Row};
+use tokio_postgres::types::{FromSql, Type};
+
+
+pub fn rows_to_df(rows: Vec<Row>, columns: &[Column]) -> Result<DataFrame, Error> {
+    let df_columns = columns.iter()
+        .enumerate()
+        .map(|(col_idx, col)| {
+            let column_data = read_column(&rows, col_idx, col.type_().name())?;
+            Ok(TesseractColumn::new(col.name().to_string(), column_data))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(DataFrame::from_vec(df_columns))
+}
+
+fn read_column(rows: &[Row], col_idx: usize, col_type_name: &str) -> Result<ColumnData, Error> {
+    // The types are matched by name below, so `get` can't fail on a mismatch, and reading
+    // through `Option` returns nulls as `None`.
+    macro_rules! column {
+        ($t:ty, $variant:ident, $nullable_variant:ident) => {
+            column!($t, $variant, $nullable_variant, |v| v)
+        };
+        ($t:ty, $variant:ident, $nullable_variant:ident, $convert:expr) => {{
+            let values = rows.iter()
+                .map(|row| row.get::<_, Option<$t>>(col_idx).map($convert))
+                .collect::<Vec<_>>();
+
+            if values.iter().any(|v| v.is_none()) {
+                ColumnData::$nullable_variant(values)
+            } else {
+                ColumnData::$variant(values.into_iter().map(|v| v.unwrap_or_default()).collect())
             }
-        }
+        }};
     }
 
-    let mut df = DataFrame::from_vec(tcolumn_list);
-
-    for row in &rows {
-        for col_idx in 0..df.columns.len() {
-            let column_data = df.columns
-                .get_mut(col_idx)
-                .expect("logic checked?")
-                .column_data();
-            match column_data {
-                ColumnData::Int32(col_data) => {
-                    let value = row.get::<_, i32>(col_idx);
-                    col_data.push(value);
-                },
-                ColumnData::Int64(col_data) => {
-                    let value = row.get::<_, i64>(col_idx);
-                    col_data.push(value);
-                },
-                ColumnData::Float32(col_data) => {
-                    let value = row.get::<_, f32>(col_idx);
-                    col_data.push(value);
-                },
-                ColumnData::Float64(col_data) => {
-                    let value = row.get::<_, f64>(col_idx);
-                    col_data.push(value);
-                },
-                ColumnData::Text(col_data) => {
-                    let value = row.get::<_, String>(col_idx);
-                    col_data.push(value);
-                },
-                _ => {
-                    println!("NO MATCH!");
-                }
-            }
-        }
+    let column_data = match col_type_name {
+        "bool" => column!(bool, UInt8, NullableUInt8, |b| b as u8),
+        "int2" => column!(i16, Int16, NullableInt16),
+        "int4" => column!(i32, Int32, NullableInt32),
+        "int8" => column!(i64, Int64, NullableInt64),
+        "float4" => column!(f32, Float32, NullableFloat32),
+        "float8" => column!(f64, Float64, NullableFloat64),
+        "numeric" => column!(Numeric, Float64, NullableFloat64, |n| n.0),
+        "text" | "varchar" | "bpchar" | "name" => column!(String, Text, NullableText),
+        t => bail!("Postgres type not yet supported: {}", t),
+    };
+
+    Ok(column_data)
+}
+
+/// A `numeric`, read as the nearest float.
+struct Numeric(f64);
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        numeric_to_f64(raw)
+            .map(Numeric)
+            .map_err(|err| err.to_string().into())
     }
 
-    df
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "numeric"
+    }
+}
+
+/// Decodes the binary format of a `numeric`: the number of digits, the weight of the first
+/// digit, the sign and the display scale, followed by the digits, in base 10000.
+fn numeric_to_f64(raw: &[u8]) -> Result<f64, Error> {
+    let read_u16 = |i: usize| u16::from(raw[i]) << 8 | u16::from(raw[i + 1]);
+
+    if raw.len() < 8 {
+        bail!("Invalid postgres numeric");
+    }
+    let digit_count = read_u16(0) as usize;
+    let weight = read_u16(2) as i16;
+    let sign = read_u16(4);
+    if raw.len() < 8 + 2 * digit_count {
+        bail!("Invalid postgres numeric");
+    }
+
+    let value = match sign {
+        0x0000 | 0x4000 => {
+            let abs: f64 = (0..digit_count)
+                .map(|i| f64::from(read_u16(8 + 2 * i)) * 10_000f64.powi(i32::from(weight) - i as i32))
+                .sum();
+
+            if sign == 0x4000 { -abs } else { abs }
+        },
+        0xC000 => std::f64::NAN,
+        _ => bail!("Invalid postgres numeric sign {:#x}", sign),
+    };
+
+    Ok(value)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_numeric_to_f64() {
+        // 12345.678: digits 1, 2345, 6780, weight 1, scale 3
+        let raw = [0, 3, 0, 1, 0, 0, 0, 3, 0, 1, 0x09, 0x29, 0x1a, 0x7c];
+        assert!((numeric_to_f64(&raw).unwrap() - 12345.678).abs() < 1e-9);
+
+        // -0.05: digit 500, weight -1
+        let raw = [0, 1, 0xff, 0xff, 0x40, 0, 0, 2, 0x01, 0xf4];
+        assert!((numeric_to_f64(&raw).unwrap() + 0.05).abs() < 1e-9);
+
+        // zero has no digits
+        assert_eq!(numeric_to_f64(&[0, 0, 0, 0, 0, 0, 0, 0]).unwrap(), 0.0);
+
+        assert!(numeric_to_f64(&[0, 2, 0, 0, 0, 0, 0, 0, 0, 1]).is_err());
+    }
 }
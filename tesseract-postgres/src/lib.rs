@@ -1,6 +1,9 @@
 use failure::{Error, format_err};
-use tesseract_core::{Backend, DataFrame};
+use log::warn;
+use tesseract_core::{Backend, CancelOnDrop, ColumnInfo, DataFrame, TableInfo};
+use tesseract_core::{table_filter, tag_sql, two_string_columns};
 use futures::{Future, Stream};
+use tokio::executor::{DefaultExecutor, Executor};
 use tokio_postgres::NoTls;
 extern crate futures;
 extern crate tokio_postgres;
@@ -48,34 +51,94 @@ impl Postgres {
     pub fn hangup() {
         println!("Done with connection! TODO!");
     }
+
+    /// Cancels the query tagged with `tag` (see `tag_sql`), from another connection, e.g.
+    /// when the client of the request is gone. The cancel runs in the background.
+    fn cancel_query(&self, tag: String) {
+        let sql = format!(
+            "select pg_cancel_backend(pid) from pg_stat_activity where position('{}' in query) = 1",
+            tag,
+        );
+
+        let cancel = self.pool.run(move |mut connection| {
+            connection.simple_query(&sql)
+                .collect()
+                .then(move |r| match r {
+                    Ok(_) => Ok(((), connection)),
+                    Err(e) => Err((e, connection)),
+                })
+        }).map_err(|err| warn!("Could not cancel postgres query: {:?}", err));
+
+        if let Err(err) = DefaultExecutor::current().spawn(Box::new(cancel)) {
+            warn!("Could not cancel postgres query: {:?}", err);
+        }
+    }
 }
 
 // TODO:
 // 1. better connection lifecycle management!
-// 2. dataframe creation
 
 impl Backend for Postgres {
     fn exec_sql(&self, sql: String) -> Box<Future<Item=DataFrame, Error=Error>> {
+        let (tag, sql) = tag_sql(&sql);
+        let pg = self.clone();
+
         let fut = self.pool.run(move |mut connection| {
             connection.prepare(&sql).then( |r| match r {
                 Ok(select) => {
                     let f = connection.query(&select, &[])
                         .collect()
-                        .then(move |r| {
-                            let df = rows_to_df(r.expect("Unable to retrieve rows"), select.columns());
-                            Ok((df, connection))
+                        .then(move |r| match r {
+                            Ok(rows) => Ok((rows_to_df(rows, select.columns()), connection)),
+                            Err(e) => Err((e, connection)),
                         });
                     Either::A(f)
                 }
                 Err(e) => Either::B(err((e, connection))),
             })
-        }).map_err(|err| format_err!("Postgres error {:?}", err));
-        Box::new(fut)
+        })
+        .map_err(|err| format_err!("Postgres error {:?}", err))
+        .and_then(|df| df);
+        Box::new(CancelOnDrop::new(fut, move || pg.cancel_query(tag)))
     }
 
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
         Box::new((*self).clone())
     }
+
+    // information_schema columns are `sql_identifier`s, which `rows_to_df` doesn't read,
+    // so they're cast to text.
+    fn inspect_tables(&self) -> Box<Future<Item=Vec<TableInfo>, Error=Error>> {
+        let sql = "select table_schema::text, table_name::text from information_schema.tables \
+            where table_schema not in ('information_schema', 'pg_catalog') \
+            order by table_schema, table_name".to_owned();
+
+        Box::new(self.exec_sql(sql).map(|df| {
+            let (schemas, names) = two_string_columns(&df);
+
+            schemas.into_iter()
+                .zip(names)
+                .map(|(schema, name)| TableInfo { schema: Some(schema), name })
+                .collect()
+        }))
+    }
+
+    fn inspect_columns(&self, table: &str) -> Box<Future<Item=Vec<ColumnInfo>, Error=Error>> {
+        let sql = format!(
+            "select column_name::text, data_type::text from information_schema.columns \
+            where {} order by ordinal_position",
+            table_filter(&*self.dialect(), table, "table_schema", "table_name"),
+        );
+
+        Box::new(self.exec_sql(sql).map(|df| {
+            let (names, types) = two_string_columns(&df);
+
+            names.into_iter()
+                .zip(types)
+                .map(|(name, column_type)| ColumnInfo { name, column_type })
+                .collect()
+        }))
+    }
 }
 
 
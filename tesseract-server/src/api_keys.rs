@@ -0,0 +1,393 @@
+//! API key middleware, with per-key daily quotas.
+//!
+//! Keys are read on startup, either from a JSON file or from a database table. Requests to
+//! the data endpoints (core aggregate and export, logic layer `/data`, `/mdx` and `/ws`)
+//! must send a known key in the `x-api-key` header; other endpoints are left open. Each key
+//! can have a daily limit on the number of requests and on the number of rows returned.
+//! Requests over either quota are rejected with `429 Too Many Requests` until the next day
+//! (UTC).
+//!
+//! Rows are counted by the handlers once results are ready, so the request that crosses the
+//! row quota is still served in full. Results served from the redis cache or as a `304` are
+//! counted as requests only.
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix::SystemRunner;
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use actix_web::middleware::{Middleware, Started};
+use failure::{Error, format_err};
+use serde_derive::{Deserialize, Serialize};
+
+use tesseract_core::Backend;
+
+use crate::app::AppState;
+
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+
+/// One API key and its quotas, as configured. Quotas left as `None` are unlimited.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub name: Option<String>,
+    pub daily_requests: Option<u64>,
+    pub daily_rows: Option<u64>,
+}
+
+
+/// Usage of one key over the current day.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct KeyUsage {
+    day: u64,
+    requests: u64,
+    rows: u64,
+}
+
+impl KeyUsage {
+    /// Starts counting over if the day has changed.
+    fn roll_over(&mut self, day: u64) {
+        if self.day != day {
+            *self = KeyUsage { day, requests: 0, rows: 0 };
+        }
+    }
+}
+
+
+/// Result of counting one request against a key's quotas.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeyStatus {
+    Allowed,
+    Missing,
+    Unknown,
+    OverQuota(String),
+}
+
+
+/// Usage report for one key, for the `/keys/usage` endpoint. The key itself is masked.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyUsageReport {
+    pub name: Option<String>,
+    pub key: String,
+    pub requests: u64,
+    pub rows: u64,
+    pub daily_requests: Option<u64>,
+    pub daily_rows: Option<u64>,
+}
+
+
+/// Configured keys and their usage counters. Cloning is cheap; all clones (one per server
+/// worker) count against the same usage map.
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    keys: Arc<HashMap<String, ApiKeyConfig>>,
+    usage: Arc<Mutex<HashMap<String, KeyUsage>>>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: Vec<ApiKeyConfig>) -> Self {
+        ApiKeys {
+            keys: Arc::new(keys.into_iter().map(|k| (k.key.clone(), k)).collect()),
+            usage: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    /// Counts a request for `key` on `day` (days since the epoch), if it's within quota.
+    pub fn check(&self, key: Option<&str>, day: u64) -> ApiKeyStatus {
+        let key = match key {
+            Some(key) if !key.is_empty() => key,
+            _ => return ApiKeyStatus::Missing,
+        };
+
+        let config = match self.keys.get(key) {
+            Some(config) => config,
+            None => return ApiKeyStatus::Unknown,
+        };
+
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(key.to_owned()).or_insert_with(KeyUsage::default);
+        usage.roll_over(day);
+
+        if let Some(limit) = config.daily_requests {
+            if usage.requests >= limit {
+                return ApiKeyStatus::OverQuota(format!("Daily request quota of {} exceeded", limit));
+            }
+        }
+        if let Some(limit) = config.daily_rows {
+            if usage.rows >= limit {
+                return ApiKeyStatus::OverQuota(format!("Daily row quota of {} exceeded", limit));
+            }
+        }
+
+        usage.requests += 1;
+
+        ApiKeyStatus::Allowed
+    }
+
+    /// Adds the rows returned for a request to `key`'s usage on `day`.
+    pub fn record_rows(&self, key: &str, rows: usize, day: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(key.to_owned()).or_insert_with(KeyUsage::default);
+        usage.roll_over(day);
+        usage.rows += rows as u64;
+    }
+
+    /// Usage of all keys on `day`, sorted by name then key.
+    pub fn usage_report(&self, day: u64) -> Vec<KeyUsageReport> {
+        let usage = self.usage.lock().unwrap();
+
+        let mut report: Vec<_> = self.keys.values()
+            .map(|config| {
+                let (requests, rows) = match usage.get(&config.key) {
+                    Some(u) if u.day == day => (u.requests, u.rows),
+                    _ => (0, 0),
+                };
+
+                KeyUsageReport {
+                    name: config.name.clone(),
+                    key: mask_key(&config.key),
+                    requests,
+                    rows,
+                    daily_requests: config.daily_requests,
+                    daily_rows: config.daily_rows,
+                }
+            })
+            .collect();
+
+        report.sort_by(|a, b| (&a.name, &a.key).cmp(&(&b.name, &b.key)));
+        report
+    }
+}
+
+
+/// Handle for a handler to record the rows returned to the request's API key.
+/// Only present on requests that went through the API key middleware.
+#[derive(Debug, Clone)]
+pub struct ApiKeyMeter {
+    api_keys: ApiKeys,
+    key: String,
+}
+
+impl ApiKeyMeter {
+    pub fn from_request(req: &HttpRequest<AppState>) -> Option<Self> {
+        req.extensions().get::<ApiKeyMeter>().cloned()
+    }
+
+    pub fn record_rows(&self, rows: usize) {
+        self.api_keys.record_rows(&self.key, rows, current_day());
+    }
+
+    /// Counts another request to the key, like the queries of a WebSocket subscription,
+    /// which come after the request that opened it. Errors once the key is over quota.
+    pub fn count_request(&self) -> Result<(), String> {
+        match self.api_keys.check(Some(&self.key), current_day()) {
+            ApiKeyStatus::Allowed => Ok(()),
+            ApiKeyStatus::OverQuota(msg) => Err(msg),
+            _ => Err("Invalid API key".to_owned()),
+        }
+    }
+}
+
+
+/// Reads keys from a JSON file holding an array of `ApiKeyConfig`.
+pub fn read_api_keys_file(path: &str) -> Result<Vec<ApiKeyConfig>, Error> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format_err!("Could not read API keys file {}: {}", path, err))?;
+
+    serde_json::from_str(&content)
+        .map_err(|err| format_err!("Could not parse API keys file {}: {}", path, err))
+}
+
+
+/// Reads keys from a table with columns `key`, `name`, `daily_requests` and `daily_rows`.
+/// Empty (or null) quotas are unlimited.
+pub fn read_api_keys_table(
+    table: &str,
+    backend: Box<dyn Backend + Sync + Send>,
+    sys: &mut SystemRunner,
+) -> Result<Vec<ApiKeyConfig>, Error>
+{
+    // `key` is a reserved word in MySQL
+    let sql = format!(
+        "select {}, name, daily_requests, daily_rows from {}",
+        backend.dialect().quote_identifier("key"), table,
+    );
+    let future = backend.exec_sql(sql);
+
+    let df = sys.block_on(future)
+        .map_err(|err| format_err!("Error reading API keys table {}: {}", table, err))?;
+
+    if df.columns.len() != 4 {
+        return Err(format_err!("API keys table {} should have 4 columns", table));
+    }
+
+    let keys = df.columns[0].stringify_column_data();
+    let names = df.columns[1].stringify_column_data();
+    let daily_requests = df.columns[2].stringify_column_data();
+    let daily_rows = df.columns[3].stringify_column_data();
+
+    let parse_quota = |val: &str| -> Result<Option<u64>, Error> {
+        if val.is_empty() {
+            Ok(None)
+        } else {
+            val.parse::<u64>()
+                .map(Some)
+                .map_err(|_| format_err!("Could not parse quota {} in API keys table {}", val, table))
+        }
+    };
+
+    let mut res = vec![];
+    for i in 0..keys.len() {
+        res.push(ApiKeyConfig {
+            key: keys[i].clone(),
+            name: if names[i].is_empty() { None } else { Some(names[i].clone()) },
+            daily_requests: parse_quota(&daily_requests[i])?,
+            daily_rows: parse_quota(&daily_rows[i])?,
+        });
+    }
+
+    Ok(res)
+}
+
+
+/// Days since the epoch (UTC).
+pub fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+
+/// Only the data endpoints are metered.
+fn is_metered(path: &str) -> bool {
+    path.starts_with("/data") ||
+        path.starts_with("/mdx") ||
+        path.starts_with("/ws") ||
+        (path.starts_with("/cubes/") && (path.contains("/aggregate") || path.contains("/export")))
+}
+
+
+fn mask_key(key: &str) -> String {
+    let prefix: String = key.chars().take(4).collect();
+    format!("{}...", prefix)
+}
+
+
+impl Middleware<AppState> for ApiKeys {
+    fn start(&self, req: &HttpRequest<AppState>) -> ActixResult<Started> {
+        if !is_metered(req.path()) {
+            return Ok(Started::Done);
+        }
+
+        let key = req.headers().get(API_KEY_HEADER)
+            .and_then(|val| val.to_str().ok())
+            .map(|val| val.trim().to_owned());
+
+        match self.check(key.as_ref().map(|k| k.as_str()), current_day()) {
+            ApiKeyStatus::Allowed => {
+                req.extensions_mut().insert(ApiKeyMeter {
+                    api_keys: self.clone(),
+                    key: key.unwrap_or_default(),
+                });
+                Ok(Started::Done)
+            },
+            ApiKeyStatus::Missing => {
+                Ok(Started::Response(HttpResponse::Unauthorized()
+                    .json(format!("An API key is required in the {} header", API_KEY_HEADER))))
+            },
+            ApiKeyStatus::Unknown => {
+                Ok(Started::Response(HttpResponse::Unauthorized()
+                    .json("Invalid API key".to_string())))
+            },
+            ApiKeyStatus::OverQuota(msg) => {
+                Ok(Started::Response(HttpResponse::TooManyRequests().json(msg)))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn api_keys() -> ApiKeys {
+        ApiKeys::new(vec![
+            ApiKeyConfig {
+                key: "abcdef".to_owned(),
+                name: Some("a".to_owned()),
+                daily_requests: Some(2),
+                daily_rows: Some(100),
+            },
+            ApiKeyConfig {
+                key: "unlimited".to_owned(),
+                name: None,
+                daily_requests: None,
+                daily_rows: None,
+            },
+        ])
+    }
+
+    #[test]
+    fn test_api_key_quotas() {
+        let keys = api_keys();
+
+        assert_eq!(keys.check(None, 0), ApiKeyStatus::Missing);
+        assert_eq!(keys.check(Some("nope"), 0), ApiKeyStatus::Unknown);
+
+        assert_eq!(keys.check(Some("abcdef"), 0), ApiKeyStatus::Allowed);
+        assert_eq!(keys.check(Some("abcdef"), 0), ApiKeyStatus::Allowed);
+        match keys.check(Some("abcdef"), 0) {
+            ApiKeyStatus::OverQuota(_) => (),
+            status => panic!("expected over quota, got {:?}", status),
+        }
+
+        // quotas reset the next day
+        assert_eq!(keys.check(Some("abcdef"), 1), ApiKeyStatus::Allowed);
+        keys.record_rows("abcdef", 100, 1);
+        match keys.check(Some("abcdef"), 1) {
+            ApiKeyStatus::OverQuota(_) => (),
+            status => panic!("expected over quota, got {:?}", status),
+        }
+
+        for _ in 0..10 {
+            assert_eq!(keys.check(Some("unlimited"), 1), ApiKeyStatus::Allowed);
+        }
+
+        let report = keys.usage_report(1);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].name, None);
+        assert_eq!(report[0].requests, 10);
+        assert_eq!(report[1].key, "abcd...");
+        assert_eq!(report[1].requests, 1);
+        assert_eq!(report[1].rows, 100);
+    }
+
+    #[test]
+    fn test_api_key_meter_count_request() {
+        let meter = ApiKeyMeter { api_keys: api_keys(), key: "abcdef".to_owned() };
+
+        assert!(meter.count_request().is_ok());
+        assert!(meter.count_request().is_ok());
+        assert!(meter.count_request().is_err());
+    }
+}
@@ -1,11 +1,13 @@
 use actix_web::{
     http::Method,
     middleware,
+    middleware::cors::Cors,
     App,
     http::NormalizePath,
 };
 use tesseract_core::{Backend, Schema, CubeHasUniqueLevelsAndProperties};
-use crate::db_config::Database;
+use tesseract_core::schema::Cube;
+use crate::db_config::{Database, DataSources, cube_backend};
 use crate::handlers::{
     aggregate_handler,
     aggregate_default_handler,
@@ -13,6 +15,10 @@ use crate::handlers::{
     aggregate_stream_default_handler,
     diagnosis_handler,
     diagnosis_default_handler,
+    export_handler,
+    export_default_handler,
+    job_handler,
+    job_file_handler,
     logic_layer_default_handler,
     logic_layer_handler,
     logic_layer_non_unique_levels_handler,
@@ -20,23 +26,55 @@ use crate::handlers::{
     logic_layer_members_handler,
     logic_layer_members_default_handler,
     flush_handler,
+    flush_logic_layer_handler,
     index_handler,
+    keys_usage_handler,
+    mdx_handler,
+    mdx_default_handler,
+    query_handler,
+    query_default_handler,
     metadata_handler,
     metadata_all_handler,
+    openapi_handler,
+    preaggregations_handler,
+    scaffold_handler,
+    scaffold_tables_handler,
     members_handler,
     members_default_handler,
+    measure_stats_handler,
+    measure_stats_default_handler,
     logic_layer_relations_handler,
     logic_layer_relations_default_handler,
+    logic_layer_cube_relations_handler,
+    logic_layer_cube_relations_default_handler,
     logic_layer_relations_non_unique_levels_default_handler,
-    logic_layer_relations_non_unique_levels_handler
+    logic_layer_relations_non_unique_levels_handler,
+    ws_handler,
+    VaryHeader,
 };
+use crate::api_keys::ApiKeys;
+use crate::export::ExportJobs;
 use crate::logic_layer::{Cache, LogicLayerConfig};
+use crate::query_stats::QueryStats;
+use crate::rate_limit::RateLimiter;
+use crate::request_id::RequestId;
+use crate::request_limits::RequestLimits;
+use crate::shutdown::InFlightRequests;
+use crate::subscriptions::Subscriptions;
 
+use actix_web::http::header::HttpDate;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
 use url::Url;
 use r2d2_redis::{r2d2, RedisConnectionManager};
 
 
+// Actix's default access log format, plus the request ID.
+const ACCESS_LOG_FORMAT: &str = r#"%a %t "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}o"#;
+
 /// Holds data about the source of a schema file.
 #[derive(Debug, Clone)]
 pub enum SchemaSource {
@@ -45,6 +83,33 @@ pub enum SchemaSource {
     RemoteSchema { endpoint: String },
 }
 
+/// Number of schema versions made, so that each reload gets a new version.
+static SCHEMA_VERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies the loaded schema, for `ETag` and `Last-Modified` headers. Replaced on every
+/// successful flush, of the schema or of the logic layer config, so that clients re-fetch
+/// after a reload. The hash changes on each reload even if the schema didn't, since what it
+/// serves (e.g. logic layer aliases or cached tables) may have.
+#[derive(Debug, Clone)]
+pub struct SchemaVersion {
+    pub hash: u64,
+    /// HTTP date of when the schema was loaded.
+    pub last_modified: String,
+}
+
+impl SchemaVersion {
+    pub fn new(schema: &Schema) -> Self {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(schema).unwrap_or_default().hash(&mut hasher);
+        SCHEMA_VERSIONS.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+
+        SchemaVersion {
+            hash: hasher.finish(),
+            last_modified: HttpDate::from(SystemTime::now()).to_string(),
+        }
+    }
+}
+
 /// Holds a struct of environment variables that will be accessed through the `AppState`.
 #[derive(Debug, Clone)]
 pub struct EnvVars {
@@ -53,55 +118,158 @@ pub struct EnvVars {
     pub schema_source: SchemaSource,
     pub jwt_secret: Option<String>,
     pub flush_secret: Option<String>,
+    pub query_limits: QueryLimits,
+    pub request_limits: RequestLimits,
+    /// Logic layer config file, reloaded by `/flush/logic_layer`.
+    pub logic_layer_config_path: Option<String>,
+    /// Calculate growth on query results for every backend, not only for those whose sql
+    /// dialect can't.
+    pub dataframe_growth: bool,
+}
+
+/// Server-side limits on query size, to protect the database from accidental
+/// cartesian-explosion queries. Limits left as `None` are not enforced.
+#[derive(Debug, Clone, Default)]
+pub struct QueryLimits {
+    pub max_drilldowns: Option<usize>,
+    /// Total number of members across all cuts.
+    pub max_cut_members: Option<usize>,
+    /// Estimated from the number of members of each drilldown level in the cache.
+    pub max_result_cells: Option<u64>,
+    /// Default limit for queries without one; results over it are truncated.
+    pub max_rows: Option<u64>,
+}
+
+/// Holds CORS settings. Origins, methods, or headers left as `None` are all allowed.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+}
+
+impl CorsConfig {
+    /// Builds the CORS middleware. Needs to be done per app instance, because `Cors` can't be
+    /// shared across server workers.
+    fn to_cors(&self) -> Cors {
+        let mut cors = Cors::build();
+
+        if let Some(ref origins) = self.allowed_origins {
+            for origin in origins {
+                cors.allowed_origin(origin);
+            }
+        }
+        if let Some(ref methods) = self.allowed_methods {
+            cors.allowed_methods(methods.iter().map(|m| m.as_str()));
+        }
+        if let Some(ref headers) = self.allowed_headers {
+            cors.allowed_headers(headers.iter().map(|h| h.as_str()));
+        }
+
+        cors.finish()
+    }
 }
 
 /// Holds [ActixWeb State](https://actix.rs/docs/application/).
 pub struct AppState {
     pub debug: bool,
     pub backend: Box<dyn Backend + Sync + Send>,
+    /// Named backends, for cubes with a `data_source`.
+    pub data_sources: DataSources,
     pub redis_pool: Option<r2d2::Pool<RedisConnectionManager>>,
     // TODO this is a hack, until a better interface is set up with the Backend Trait
     // to generate its own sql.
     pub db_type: Database,
     pub env_vars: EnvVars,
     pub schema: Arc<RwLock<Schema>>,
+    pub schema_version: Arc<RwLock<SchemaVersion>>,
     pub cache: Arc<RwLock<Cache>>,
     pub logic_layer_config: Option<Arc<RwLock<LogicLayerConfig>>>,
     // TODO is there a way to access this that's not through state? Tried using closures to
     // capture, but the handlers need to implement Fn, not FnOnce (which happens once capturing
     // variables from environment
     pub has_unique_levels_properties: CubeHasUniqueLevelsAndProperties,
+    pub api_keys: Option<ApiKeys>,
+    /// Also counts the queries subscribed to over a WebSocket, which come after its request.
+    pub rate_limiter: Option<RateLimiter>,
+    pub export_jobs: Option<ExportJobs>,
+    pub subscriptions: Subscriptions,
+    pub query_stats: QueryStats,
+}
+
+impl AppState {
+    /// Backend that a cube's queries should be run on.
+    pub fn cube_backend(&self, cube: &Cube) -> &Box<dyn Backend + Sync + Send> {
+        cube_backend(cube, &self.backend, &self.data_sources)
+    }
 }
 
 /// Creates an ActixWeb application with an `AppState`.
 pub fn create_app(
         debug: bool,
         backend: Box<dyn Backend + Sync + Send>,
+        data_sources: DataSources,
         redis_pool: Option<r2d2::Pool<RedisConnectionManager>>,
         db_type: Database,
         env_vars: EnvVars,
         schema: Arc<RwLock<Schema>>,
+        schema_version: Arc<RwLock<SchemaVersion>>,
         cache: Arc<RwLock<Cache>>,
         logic_layer_config: Option<Arc<RwLock<LogicLayerConfig>>>,
         streaming_response: bool,
         has_unique_levels_properties: CubeHasUniqueLevelsAndProperties,
+        rate_limiter: Option<RateLimiter>,
+        api_keys: Option<ApiKeys>,
+        cors_config: Option<CorsConfig>,
+        export_jobs: Option<ExportJobs>,
+        subscriptions: Subscriptions,
+        query_stats: QueryStats,
     ) -> App<AppState>
 {
+    let request_limits = env_vars.request_limits.clone();
+    let query_request_limits = request_limits.clone();
+
     let app = App::with_state(
             AppState {
                 debug,
                 backend,
+                data_sources,
                 redis_pool,
                 db_type,
                 env_vars,
                 schema,
+                schema_version,
                 cache,
                 logic_layer_config,
                 has_unique_levels_properties: has_unique_levels_properties.clone(),
+                api_keys: api_keys.clone(),
+                rate_limiter: rate_limiter.clone(),
+                export_jobs,
+                subscriptions,
+                query_stats,
         })
-        .middleware(middleware::Logger::default())
-        .middleware(middleware::DefaultHeaders::new().header("Vary", "Accept-Encoding"))
+        .middleware(InFlightRequests)
+        .middleware(RequestId)
+        .middleware(request_limits.clone())
+        .middleware(middleware::Logger::new(ACCESS_LOG_FORMAT))
+        .middleware(VaryHeader);
+
+    let app = match cors_config {
+        Some(cors_config) => app.middleware(cors_config.to_cors()),
+        None => app,
+    };
+
+    let app = match rate_limiter {
+        Some(rate_limiter) => app.middleware(rate_limiter),
+        None => app,
+    };
+
+    let app = match api_keys {
+        Some(api_keys) => app.middleware(api_keys),
+        None => app,
+    };
 
+    let app = app
         // Metadata
         .resource("/", |r| {
             r.method(Method::GET).with(index_handler)
@@ -120,6 +288,12 @@ pub fn create_app(
         .resource("/cubes/{cube}/members.{format}", |r| {
             r.method(Method::GET).with(members_handler)
         })
+        .resource("/cubes/{cube}/measures/{measure}/stats", |r| {
+            r.method(Method::GET).with(measure_stats_default_handler)
+        })
+        .resource("/cubes/{cube}/measures/{measure}/stats.{format}", |r| {
+            r.method(Method::GET).with(measure_stats_handler)
+        })
 
         // Data Quality Assurance
         .resource("/diagnosis", |r| {
@@ -129,9 +303,66 @@ pub fn create_app(
             r.method(Method::GET).with(diagnosis_handler)
         })
 
+        // Export jobs
+        .resource("/cubes/{cube}/export", |r| {
+            r.method(Method::POST).with(export_default_handler)
+        })
+        .resource("/cubes/{cube}/export.{format}", |r| {
+            r.method(Method::POST).with(export_handler)
+        })
+        .resource("/jobs/{id}", |r| {
+            r.method(Method::GET).with(job_handler)
+        })
+        .resource("/jobs/{id}/file", |r| {
+            r.method(Method::GET).with(job_file_handler)
+        })
+
+        // MDX translation
+        .resource("/mdx", |r| {
+            r.method(Method::GET).with(mdx_default_handler)
+        })
+        .resource("/mdx.{format}", |r| {
+            r.method(Method::GET).with(mdx_handler)
+        })
+
+        // Structured queries, as a JSON body
+        .resource("/cubes/{cube}/query", move |r| {
+            r.method(Method::POST).with_config(query_default_handler, move |cfg| {
+                request_limits.json_config(&mut cfg.2);
+            })
+        })
+        .resource("/cubes/{cube}/query.{format}", move |r| {
+            r.method(Method::POST).with_config(query_handler, move |cfg| {
+                query_request_limits.json_config(&mut cfg.2);
+            })
+        })
+
+        // Query subscriptions, pushed on flush and cache refresh
+        .resource("/ws", |r| {
+            r.method(Method::GET).f(ws_handler)
+        })
+
         .resource("/flush", |r| {
             r.method(Method::POST).with(flush_handler)
         })
+        .resource("/flush/logic_layer", |r| {
+            r.method(Method::POST).with(flush_logic_layer_handler)
+        })
+        .resource("/keys/usage", |r| {
+            r.method(Method::GET).with(keys_usage_handler)
+        })
+        .resource("/stats/preaggregations", |r| {
+            r.method(Method::GET).with(preaggregations_handler)
+        })
+        .resource("/scaffold", |r| {
+            r.method(Method::GET).with(scaffold_tables_handler)
+        })
+        .resource("/scaffold/{table}", |r| {
+            r.method(Method::GET).with(scaffold_handler)
+        })
+        .resource("/openapi.json", |r| {
+            r.method(Method::GET).with(openapi_handler)
+        })
         // Allow the API to accept /my-path or /my-path/ for all requests
         .default_resource(|r| r.h(NormalizePath::default()));
 
@@ -175,6 +406,12 @@ pub fn create_app(
                 .resource("/relations.{foramt}", |r| {
                     r.method(Method::GET).with(logic_layer_relations_handler)
                 })
+                .resource("/relations/cubes", |r| {
+                    r.method(Method::GET).with(logic_layer_cube_relations_default_handler)
+                })
+                .resource("/relations/cubes.{format}", |r| {
+                    r.method(Method::GET).with(logic_layer_cube_relations_handler)
+                })
         },
         CubeHasUniqueLevelsAndProperties::False { .. } => {
             // No Logic Layer, give error instead
@@ -197,6 +434,12 @@ pub fn create_app(
                 .resource("/relations.{foramt}", |r| {
                     r.method(Method::GET).with(logic_layer_relations_non_unique_levels_handler)
                 })
+                .resource("/relations/cubes", |r| {
+                    r.method(Method::GET).with(logic_layer_relations_non_unique_levels_default_handler)
+                })
+                .resource("/relations/cubes.{format}", |r| {
+                    r.method(Method::GET).with(logic_layer_relations_non_unique_levels_handler)
+                })
         },
     }
 
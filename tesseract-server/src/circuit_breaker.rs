@@ -0,0 +1,242 @@
+//! Health checks and circuit breakers for backends.
+//!
+//! `CircuitBreakerBackend` wraps a `Backend`, and counts consecutive transient errors (see
+//! `retry::is_transient`). After `failure_threshold` of them the circuit opens: queries fail
+//! immediately with `CircuitOpen`, which handlers return as a `503` with a `Retry-After`
+//! header, instead of queueing requests on a dead database.
+//!
+//! A background thread probes each backend on an interval, bypassing its breaker. A probe
+//! that succeeds closes the circuit again, and probes that fail also count as failures, so
+//! a database that goes down is noticed even without traffic.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use failure::{Error, Fail, format_err};
+use futures::{future, stream, Future, Stream};
+use log::{info, warn};
+use tokio_timer::Timeout;
+
+use tesseract_core::{Backend, Capabilities, ColumnInfo, DataFrame, QueryIr, TableInfo};
+use tesseract_core::dialect::SqlDialect;
+
+use crate::retry::is_transient;
+
+
+const HEALTH_CHECK_SQL: &str = "select 1";
+
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures which open the circuit.
+    pub failure_threshold: u32,
+    /// Time between health checks, also sent as `Retry-After` while the circuit is open.
+    pub check_interval: Duration,
+}
+
+
+/// Error of the queries on a backend whose circuit is open.
+#[derive(Debug, Fail)]
+#[fail(display = "Database {} is unavailable", backend)]
+pub struct CircuitOpen {
+    pub backend: String,
+    /// Seconds until the next health check.
+    pub retry_after: u64,
+}
+
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open: bool,
+}
+
+/// Failures of one backend. Cloning is cheap; all clones share the same state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    /// Backend name for logs, `default` or a data source name.
+    name: String,
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            name: name.to_owned(),
+            config,
+            state: Arc::new(Mutex::new(BreakerState::default())),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open
+    }
+
+    /// Closes the circuit, if it was open.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.open {
+            info!("Database {} is available again, closing circuit", self.name);
+        }
+        state.consecutive_failures = 0;
+        state.open = false;
+    }
+
+    /// Opens the circuit once there have been `failure_threshold` consecutive failures.
+    pub fn record_failure(&self, err: &Error) {
+        let mut state = self.state.lock().unwrap();
+
+        state.consecutive_failures += 1;
+        if !state.open && state.consecutive_failures >= self.config.failure_threshold {
+            warn!(
+                "Database {} failed {} times in a row, opening circuit: {}",
+                self.name, state.consecutive_failures, err,
+            );
+            state.open = true;
+        }
+    }
+
+    fn open_error(&self) -> Error {
+        CircuitOpen {
+            backend: self.name.clone(),
+            retry_after: self.config.check_interval.as_secs().max(1),
+        }.into()
+    }
+}
+
+
+#[derive(Clone)]
+pub struct CircuitBreakerBackend {
+    inner: Box<dyn Backend + Send + Sync>,
+    breaker: CircuitBreaker,
+}
+
+impl CircuitBreakerBackend {
+    pub fn new(inner: Box<dyn Backend + Send + Sync>, breaker: CircuitBreaker) -> Self {
+        CircuitBreakerBackend { inner, breaker }
+    }
+}
+
+impl Backend for CircuitBreakerBackend {
+    fn exec_sql(&self, sql: String) -> Box<dyn Future<Item=DataFrame, Error=Error>> {
+        if self.breaker.is_open() {
+            return Box::new(future::err(self.breaker.open_error()));
+        }
+
+        let breaker = self.breaker.clone();
+
+        Box::new(self.inner.exec_sql(sql).then(move |res| {
+            match res {
+                // other errors, e.g. sql errors, mean the database is up
+                Err(ref err) if is_transient(err) => breaker.record_failure(err),
+                _ => breaker.record_success(),
+            }
+            res
+        }))
+    }
+
+    fn exec_sql_stream(&self, sql: String) -> Box<dyn Stream<Item=Result<DataFrame, Error>, Error=Error>> {
+        if self.breaker.is_open() {
+            return Box::new(stream::once(Err(self.breaker.open_error())));
+        }
+
+        let breaker = self.breaker.clone();
+
+        // errors can come as the stream's error, or as an item
+        Box::new(self.inner.exec_sql_stream(sql).then(move |res| {
+            match res {
+                Err(ref err) | Ok(Err(ref err)) if is_transient(err) => breaker.record_failure(err),
+                _ => breaker.record_success(),
+            }
+            res
+        }))
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        self.inner.dialect()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn generate_sql(&self, query_ir: QueryIr) -> String {
+        self.inner.generate_sql(query_ir)
+    }
+
+    fn inspect_tables(&self) -> Box<dyn Future<Item=Vec<TableInfo>, Error=Error>> {
+        self.inner.inspect_tables()
+    }
+
+    fn inspect_columns(&self, table: &str) -> Box<dyn Future<Item=Vec<ColumnInfo>, Error=Error>> {
+        self.inner.inspect_columns(table)
+    }
+}
+
+
+/// Starts the health check thread, which probes each backend (not wrapped in its breaker)
+/// every `check_interval`, in its own actix system.
+pub fn start_health_checks(
+    backends: Vec<(CircuitBreaker, Box<dyn Backend + Send + Sync>)>,
+    check_interval: Duration,
+) -> thread::JoinHandle<()>
+{
+    info!("Database health checks every {}s", check_interval.as_secs());
+
+    thread::spawn(move || {
+        let mut sys = actix::System::new("tesseract-health-check");
+
+        loop {
+            thread::sleep(check_interval);
+
+            for (breaker, backend) in &backends {
+                // a probe on a hanging database shouldn't hold up the others
+                let probe = Timeout::new(backend.exec_sql(HEALTH_CHECK_SQL.to_owned()), check_interval)
+                    .map_err(|err| match err.into_inner() {
+                        Some(err) => err,
+                        None => format_err!("health check timed out"),
+                    });
+
+                match sys.block_on(probe) {
+                    Ok(_) => breaker.record_success(),
+                    Err(err) => breaker.record_failure(&err),
+                }
+            }
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let config = CircuitBreakerConfig { failure_threshold: 2, check_interval: Duration::from_secs(5) };
+        let breaker = CircuitBreaker::new("default", config);
+        let err = format_err!("connection refused");
+
+        breaker.record_failure(&err);
+        assert!(!breaker.is_open());
+        breaker.record_success();
+        breaker.record_failure(&err);
+        assert!(!breaker.is_open());
+        breaker.record_failure(&err);
+        assert!(breaker.is_open());
+
+        let open_err = breaker.open_error();
+        let circuit_open = open_err.downcast_ref::<CircuitOpen>().unwrap();
+        assert_eq!(circuit_open.retry_after, 5);
+
+        breaker.record_success();
+        assert!(!breaker.is_open());
+    }
+}
@@ -0,0 +1,110 @@
+//! Subcommands which check a deployment offline, without starting the server: for CI
+//! pipelines, which want to validate a schema or look at the sql of a query.
+
+use actix::SystemRunner;
+use failure::{Error, format_err};
+use serde_qs as qs;
+use std::convert::TryInto;
+use tesseract_core::{Query as TsQuery, Schema};
+
+use crate::config_file::Config;
+use crate::db_config::{self, DataSources};
+use crate::handlers::{AggregateQueryOpt, PostProcess};
+use crate::schema_config;
+
+
+/// Reads and validates a schema file, as the server does on startup.
+pub fn validate_schema(schema_path: &str) -> Result<(), Error> {
+    let schema = read_schema(schema_path)?;
+
+    println!("Schema {} is valid: {} cubes", schema.name, schema.cubes.len());
+
+    Ok(())
+}
+
+/// Prints the sql of an aggregate query, given as the query string of `/cubes/<cube>/aggregate`,
+/// in the dialect of the cube's database. The query is not run.
+///
+/// The sql is prepared as the aggregate endpoint does: on the cube's backend, with the
+/// calculations done on the results taken out of it, and checked against the backend's
+/// capabilities. The server's retry and circuit breaker wrappers only change how sql is run,
+/// so they're left out.
+pub fn generate_sql(config: &Config, schema_path: &str, db_url_full: &str, cube_name: &str, query: &str) -> Result<(), Error> {
+    let schema = read_schema(schema_path)?;
+    let cube = schema.get_cube_by_name(cube_name)?;
+
+    let (db, _, _) = db_config::get_db(config, db_url_full)?;
+    let data_sources: DataSources = db_config::get_data_sources(config)?.into_iter()
+        .map(|(name, db, _, _)| (name, db))
+        .collect();
+    db_config::validate_data_sources(&schema, &data_sources)?;
+    let backend = db_config::cube_backend(cube, &db, &data_sources);
+
+    let dataframe_growth = match config.var("TESSERACT_DATAFRAME_GROWTH") {
+        Ok(val) => val.parse::<bool>()
+            .map_err(|_| format_err!("could not parse bool from env_var TESSERACT_DATAFRAME_GROWTH"))?,
+        Err(_) => false,
+    };
+
+    let agg_query_opt = qs::Config::new(5, false)
+        .deserialize_str::<AggregateQueryOpt>(query.trim_start_matches('?'))
+        .map_err(|err| format_err!("Could not parse query: {}", err))?;
+    let sample = agg_query_opt.shared().sample;
+    // growth on a sample is calculated on the scaled up measures, as in the aggregate handler
+    let dataframe_growth = sample.is_some() || agg_query_opt.shared().dataframe_growth.unwrap_or(dataframe_growth);
+    let mut ts_query: TsQuery = agg_query_opt.try_into()?;
+    ts_query.sample = sample;
+
+    let (mut query_ir, headers) = schema.sql_query(cube_name, &ts_query, None)?;
+
+    let (steps, sql_previous_year) = PostProcess::take(&mut query_ir, &**backend, dataframe_growth, None);
+    backend.capabilities().check(&query_ir)?;
+
+    let result_order = query_ir.result_order();
+    let sql = backend.generate_sql(query_ir);
+    let sql = match sql_previous_year {
+        Some(ref columns) => backend.dialect().previous_year_sql(&sql, columns, &result_order, None),
+        None => sql,
+    };
+
+    println!("-- {} sql", backend.dialect().name());
+    println!("-- headers: {}", headers.join(", "));
+    if steps.has_calculations() {
+        println!("-- growth, share and vs_previous_year are calculated on the results");
+    }
+    println!("{}", sql);
+
+    Ok(())
+}
+
+/// Runs a trivial query on the database and each data source. Fails if any can't be queried.
+pub fn check_db(config: &Config, db_url_full: &str, sys: &mut SystemRunner) -> Result<(), Error> {
+    let (db, db_url, db_type) = db_config::get_db(config, db_url_full)?;
+
+    let mut databases = vec![("default".to_owned(), db, db_url, db_type)];
+    databases.extend(db_config::get_data_sources(config)?);
+
+    let mut failed = 0;
+    for (name, db, db_url, db_type) in databases {
+        match sys.block_on(db.exec_sql("select 1".to_owned())) {
+            Ok(_) => println!("{} ({}, {}): OK", name, db_url, db_type),
+            Err(err) => {
+                println!("{} ({}, {}): FAILED: {}", name, db_url, db_type, err);
+                failed += 1;
+            },
+        }
+    }
+
+    if failed > 0 {
+        return Err(format_err!("{} database(s) could not be queried", failed));
+    }
+
+    Ok(())
+}
+
+fn read_schema(schema_path: &str) -> Result<Schema, Error> {
+    let mut schema = schema_config::read_schema(schema_path)?;
+    schema.validate()?;
+
+    Ok(schema)
+}
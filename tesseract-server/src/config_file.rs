@@ -0,0 +1,178 @@
+//! Config file, as an alternative to setting each env var.
+//!
+//! The file is toml, with the env vars as keys: lowercase and without the `TESSERACT_` prefix.
+//! Tables group keys under a prefix, e.g. `[export] s3_bucket = "..."` sets
+//! `TESSERACT_EXPORT_S3_BUCKET`, and lists are comma-separated. Data sources, whose env vars
+//! are `TESSERACT_DATABASE_URL_<NAME>`, go in a `[data_sources]` table.
+//!
+//! Configuration is read through `Config`, which looks up the env var first, so env vars
+//! (also from `.env`) override the file. The file's values are never set as env vars.
+
+use failure::{Error, bail, format_err};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use toml::Value;
+
+
+const ENV_PREFIX: &str = "TESSERACT_";
+
+/// Configuration values: the env vars, and the config file's values for the ones not set.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    file_vars: HashMap<String, String>,
+}
+
+impl Config {
+    /// Reads the config file, if there is one.
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format_err!("Could not read config file {}: {}", path, err))?;
+        let config = contents.parse::<Value>()
+            .map_err(|err| format_err!("Could not parse config file {}: {}", path, err))?;
+
+        Ok(Config {
+            file_vars: config_vars(&config)?.into_iter().collect(),
+        })
+    }
+
+    /// Value of `var`: the env var if it's set, else the config file's.
+    pub fn var(&self, var: &str) -> Result<String, env::VarError> {
+        env::var(var)
+            .or_else(|err| self.file_vars.get(var).cloned().ok_or(err))
+    }
+
+    /// All values, env vars overriding the config file's.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let mut vars = self.file_vars.clone();
+        vars.extend(env::vars());
+        vars.into_iter().collect()
+    }
+}
+
+/// The env vars set by a config file, with their values.
+fn config_vars(config: &Value) -> Result<Vec<(String, String)>, Error> {
+    let table = match config.as_table() {
+        Some(table) => table,
+        None => bail!("Config file must be a table"),
+    };
+
+    let mut vars = vec![];
+
+    for (key, val) in table {
+        if key == "data_sources" {
+            let data_sources = match val.as_table() {
+                Some(data_sources) => data_sources,
+                None => bail!("Config data_sources must be a table of names to database urls"),
+            };
+            for (name, url) in data_sources {
+                let var = format!("{}DATABASE_URL_{}", ENV_PREFIX, name.to_uppercase());
+                vars.push((var, env_value(name, url)?));
+            }
+        } else {
+            push_vars(&mut vars, ENV_PREFIX, key, val)?;
+        }
+    }
+
+    Ok(vars)
+}
+
+fn push_vars(vars: &mut Vec<(String, String)>, prefix: &str, key: &str, val: &Value) -> Result<(), Error> {
+    let var = format!("{}{}", prefix, key.to_uppercase());
+
+    match val {
+        Value::Table(table) => {
+            let prefix = format!("{}_", var);
+            for (key, val) in table {
+                push_vars(vars, &prefix, key, val)?;
+            }
+        },
+        val => vars.push((var, env_value(key, val)?)),
+    }
+
+    Ok(())
+}
+
+fn env_value(key: &str, val: &Value) -> Result<String, Error> {
+    let env_value = match val {
+        Value::String(s) => s.clone(),
+        Value::Integer(n) => n.to_string(),
+        Value::Float(n) => n.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(items) => {
+            items.iter()
+                .map(|item| match item {
+                    Value::Array(_) | Value::Table(_) => bail!("Config list {} can only have plain values", key),
+                    item => env_value(key, item),
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+                .join(",")
+        },
+        _ => bail!("Config value of {} is not supported: {}", key, val),
+    };
+
+    Ok(env_value)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_vars() {
+        let config = r#"
+            database_url = "clickhouse://localhost:9000/db"
+            schema_filepath = "schema.json"
+            streaming_response = true
+            rate_limit = 60
+
+            [cors]
+            allowed_origins = ["https://a.org", "https://b.org"]
+
+            [export]
+            s3_bucket = "exports"
+
+            [data_sources]
+            sales = "postgres://localhost:5432/sales"
+        "#.parse::<Value>().unwrap();
+
+        let mut vars = config_vars(&config).unwrap();
+        vars.sort();
+
+        assert_eq!(vars, vec![
+            ("TESSERACT_CORS_ALLOWED_ORIGINS".to_owned(), "https://a.org,https://b.org".to_owned()),
+            ("TESSERACT_DATABASE_URL".to_owned(), "clickhouse://localhost:9000/db".to_owned()),
+            ("TESSERACT_DATABASE_URL_SALES".to_owned(), "postgres://localhost:5432/sales".to_owned()),
+            ("TESSERACT_EXPORT_S3_BUCKET".to_owned(), "exports".to_owned()),
+            ("TESSERACT_RATE_LIMIT".to_owned(), "60".to_owned()),
+            ("TESSERACT_SCHEMA_FILEPATH".to_owned(), "schema.json".to_owned()),
+            ("TESSERACT_STREAMING_RESPONSE".to_owned(), "true".to_owned()),
+        ]);
+
+        let config = "data_sources = \"sales\"".parse::<Value>().unwrap();
+        assert!(config_vars(&config).is_err());
+    }
+
+    #[test]
+    fn test_config_var() {
+        let mut config = Config::default();
+        config.file_vars.insert("TESSERACT_TEST_CONFIG_FILE_ONLY".to_owned(), "file".to_owned());
+        config.file_vars.insert("TESSERACT_TEST_CONFIG_OVERRIDDEN".to_owned(), "file".to_owned());
+        env::set_var("TESSERACT_TEST_CONFIG_OVERRIDDEN", "env");
+
+        assert_eq!(config.var("TESSERACT_TEST_CONFIG_FILE_ONLY"), Ok("file".to_owned()));
+        assert_eq!(config.var("TESSERACT_TEST_CONFIG_OVERRIDDEN"), Ok("env".to_owned()));
+        assert!(config.var("TESSERACT_TEST_CONFIG_MISSING").is_err());
+        assert!(env::var("TESSERACT_TEST_CONFIG_FILE_ONLY").is_err());
+
+        let vars = config.vars();
+        assert!(vars.contains(&("TESSERACT_TEST_CONFIG_OVERRIDDEN".to_owned(), "env".to_owned())));
+        assert!(vars.contains(&("TESSERACT_TEST_CONFIG_FILE_ONLY".to_owned(), "file".to_owned())));
+    }
+}
@@ -1,7 +1,10 @@
 use actix_web::{
+    http::StatusCode,
     HttpResponse,
 };
-use failure::Fail;
+use failure::{Error, Fail};
+
+use crate::circuit_breaker::CircuitOpen;
 
 #[derive(Debug, Fail)]
 pub enum ServerError {
@@ -24,6 +27,44 @@ pub enum ServerError {
     #[fail(display="Internal Server Error {}", code)]
     ErrorCode {
         code: String,
+    },
+
+    #[fail(display="Database unavailable")]
+    Unavailable {
+        retry_after: u64,
+    },
+}
+
+impl ServerError {
+    /// Error for a failed query: `Unavailable` if the database's circuit breaker is open,
+    /// otherwise `Db`, whose cause is only shown in debug mode.
+    pub fn from_query_error(err: &Error, debug: bool) -> ServerError {
+        if let Some(circuit_open) = err.downcast_ref::<CircuitOpen>() {
+            return ServerError::Unavailable { retry_after: circuit_open.retry_after };
+        }
+
+        if debug {
+            ServerError::Db { cause: err.to_string() }
+        } else {
+            ServerError::Db { cause: "Internal Server Error 1010".to_owned() }
+        }
+    }
+
+    /// Status of the error's response; also reported where there's no http response, like
+    /// websocket messages and export jobs.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServerError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Body of the error's response.
+    pub fn message(&self) -> String {
+        match self {
+            ServerError::Db { cause } => cause.clone(),
+            _ => self.to_string(),
+        }
     }
 }
 
@@ -33,6 +74,11 @@ impl actix_web::error::ResponseError for ServerError {
             ServerError::Db { cause } => HttpResponse::InternalServerError().body(cause.clone()),
             ServerError::LogicLayerDuplicateNames { .. } => HttpResponse::InternalServerError().body(self.to_string()),
             ServerError::ErrorCode { .. } => HttpResponse::InternalServerError().body(self.to_string()),
+            ServerError::Unavailable { retry_after } => {
+                HttpResponse::ServiceUnavailable()
+                    .header("Retry-After", retry_after.to_string())
+                    .body(self.to_string())
+            },
         }
     }
 }
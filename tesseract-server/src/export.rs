@@ -0,0 +1,480 @@
+//! Export jobs, for extracts too big to finish within an HTTP request.
+//!
+//! `POST /cubes/{cube}/export` takes the same query as the aggregate endpoint and queues it
+//! as a job, which runs in the background. `GET /jobs/{id}` reports the job's status; once
+//! completed, `GET /jobs/{id}/file` serves the result from the spool directory.
+//!
+//! Results are written to a temporary file which is renamed when complete, so a partial
+//! file is never served. Job statuses are only kept in memory and are lost on restart.
+//! Finished jobs are dropped, along with their files, a day after they finish; files left
+//! from before a restart are not cleaned up by the server.
+//!
+//! The number of queued and running jobs can be capped, past which new exports are rejected
+//! with `503 Service Unavailable`.
+//!
+//! Job ids are random, and both job routes check the cube's `min_auth_level`, like the
+//! export itself.
+//!
+//! With an S3 target, results are uploaded to the bucket instead, and the job reports a
+//! presigned URL to download them from, so large files don't go through the server. The URL
+//! is signed again each time the job is fetched, so it's valid for the target's `expires`
+//! from then, not from when the job finished.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{Error, format_err};
+use futures::future::{self, Future};
+use futures::sync::oneshot;
+use log::*;
+use rusoto_core::Region;
+use rusoto_core::credential::{DefaultCredentialsProvider, ProvideAwsCredentials};
+use rusoto_s3::{GetObjectRequest, PutObjectRequest, S3, S3Client};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use serde_derive::Serialize;
+use uuid::Uuid;
+
+use tesseract_core::Backend;
+use tesseract_core::format::{format_records, FormatType};
+use tesseract_core::schema::metadata::SourceMetadata;
+
+use crate::api_keys::ApiKeyMeter;
+use crate::errors::ServerError;
+use crate::handlers::{post_process, select_columns, truncate_rows, PostProcess};
+
+
+/// How long finished jobs, and their files, are kept.
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+
+/// One export job, as reported by `/jobs/{id}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportJob {
+    pub id: String,
+    pub cube: String,
+    pub format: String,
+    pub status: ExportStatus,
+    /// Number of rows exported, once completed.
+    pub rows: Option<usize>,
+    /// Whether rows were dropped at `TESSERACT_MAX_ROWS`, once completed.
+    pub truncated: bool,
+    pub error: Option<String>,
+    /// Http status of the error, once failed: `503` when the database is unavailable.
+    pub error_status: Option<u16>,
+    /// Presigned URL of the result, when delivered to S3; filled in when the job is fetched.
+    pub url: Option<String>,
+    /// Key of the result in the S3 bucket, when delivered to S3.
+    #[serde(skip)]
+    pub s3_key: Option<String>,
+    /// Seconds since the epoch.
+    pub created: u64,
+    /// Seconds since the epoch, once completed or failed.
+    pub finished: Option<u64>,
+    /// Of the exported cube, checked when the job is fetched.
+    #[serde(skip)]
+    pub min_auth_level: i32,
+}
+
+impl ExportJob {
+    fn is_finished(&self) -> bool {
+        self.status == ExportStatus::Completed || self.status == ExportStatus::Failed
+    }
+}
+
+
+/// A query to run as an export job, already translated to sql.
+pub struct ExportQuery {
+    pub sql: String,
+    pub headers: Vec<String>,
+    pub format: FormatType,
+    pub source_data: Option<SourceMetadata>,
+    /// Steps on the results, whose `max_rows` (from `apply_max_rows`) they're cut at.
+    pub steps: PostProcess,
+    /// Drops the ID columns of levels with labels.
+    pub show_labels: bool,
+    /// Headers of the columns to keep.
+    pub columns: Option<Vec<String>>,
+}
+
+
+/// S3 bucket (and key prefix) that export results are uploaded to.
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    pub bucket: String,
+    pub prefix: String,
+    pub region: Region,
+    /// How long presigned URLs are valid for.
+    pub expires: Duration,
+}
+
+impl S3Target {
+    fn key(&self, file_name: &str) -> String {
+        if self.prefix.is_empty() || self.prefix.ends_with('/') {
+            format!("{}{}", self.prefix, file_name)
+        } else {
+            format!("{}/{}", self.prefix, file_name)
+        }
+    }
+
+    /// Uploads `body` to `file_name` under the prefix, and returns its key.
+    fn upload(&self, file_name: &str, body: Vec<u8>) -> Box<dyn Future<Item=String, Error=Error>> {
+        let key = self.key(file_name);
+
+        let put_req = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.clone(),
+            body: Some(body.into()),
+            ..Default::default()
+        };
+
+        let fut = S3Client::new(self.region.clone())
+            .put_object(put_req)
+            .map_err(|err| format_err!("Could not upload to S3: {}", err))
+            .map(move |_| key);
+
+        Box::new(fut)
+    }
+
+    /// Presigns a URL to get `key`, valid for `expires` from now.
+    fn presign(&self, key: &str) -> Box<dyn Future<Item=String, Error=Error>> {
+        let provider = match DefaultCredentialsProvider::new() {
+            Ok(provider) => provider,
+            Err(err) => return Box::new(future::err(format_err!("Could not get AWS credentials: {}", err))),
+        };
+
+        let get_req = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_owned(),
+            ..Default::default()
+        };
+        let region = self.region.clone();
+        let options = PreSignedRequestOption { expires_in: self.expires };
+
+        let fut = provider.credentials()
+            .map_err(|err| format_err!("Could not get AWS credentials: {}", err))
+            .map(move |credentials| get_req.get_presigned_url(&region, &credentials, &options));
+
+        Box::new(fut)
+    }
+}
+
+
+/// Export jobs and their spool directory. Cloning is cheap; all clones (one per server
+/// worker) share the same jobs.
+#[derive(Debug, Clone)]
+pub struct ExportJobs {
+    dir: PathBuf,
+    s3: Option<S3Target>,
+    /// Maximum number of queued and running jobs.
+    max_active: Option<usize>,
+    jobs: Arc<Mutex<HashMap<String, ExportJob>>>,
+}
+
+impl ExportJobs {
+    /// Creates the spool directory if it doesn't exist yet. At most `max_active` jobs are
+    /// queued or running at once.
+    pub fn new(dir: &str, s3: Option<S3Target>, max_active: Option<usize>) -> Result<Self, Error> {
+        fs::create_dir_all(dir)
+            .map_err(|err| format_err!("Could not create export directory {}: {}", dir, err))?;
+
+        Ok(ExportJobs {
+            dir: PathBuf::from(dir),
+            s3,
+            max_active,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Adds a queued job, with a new random id, for a cube readable at `min_auth_level`.
+    /// Finished jobs past their retention are dropped first. `None` if there are already
+    /// `max_active` jobs queued or running.
+    pub fn create(&self, cube: &str, format: &str, min_auth_level: i32) -> Option<ExportJob> {
+        let now = now_secs();
+        self.prune(now);
+
+        let job = ExportJob {
+            id: Uuid::new_v4().to_simple().to_string(),
+            cube: cube.to_owned(),
+            format: format.to_owned(),
+            status: ExportStatus::Queued,
+            rows: None,
+            truncated: false,
+            error: None,
+            error_status: None,
+            url: None,
+            s3_key: None,
+            created: now,
+            finished: None,
+            min_auth_level,
+        };
+
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(max_active) = self.max_active {
+            if active_count(&jobs) >= max_active {
+                return None;
+            }
+        }
+        jobs.insert(job.id.clone(), job.clone());
+
+        Some(job)
+    }
+
+    /// Drops the jobs which finished more than `FINISHED_JOB_RETENTION` before `now`, and
+    /// removes their files in the background.
+    fn prune(&self, now: u64) {
+        let retention = FINISHED_JOB_RETENTION.as_secs();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let expired: Vec<String> = jobs.values()
+            .filter(|job| job.is_finished())
+            .filter(|job| job.finished.map(|finished| finished + retention <= now).unwrap_or(false))
+            .map(|job| job.id.clone())
+            .collect();
+
+        let paths: Vec<PathBuf> = expired.iter()
+            .filter_map(|id| jobs.remove(id))
+            .filter(|job| job.status == ExportStatus::Completed && job.s3_key.is_none())
+            .map(|job| self.file_path(&job))
+            .collect();
+
+        if !paths.is_empty() {
+            thread::spawn(move || {
+                for path in paths {
+                    if let Err(err) = fs::remove_file(&path) {
+                        warn!("Could not remove export file {}: {}", path.display(), err);
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<ExportJob> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// Number of jobs that are queued or running.
+    pub fn active_count(&self) -> usize {
+        active_count(&self.jobs.lock().unwrap())
+    }
+
+    /// Presigns a URL to the job's result, when it was delivered to S3.
+    pub fn presigned_url(&self, job: &ExportJob) -> Option<Box<dyn Future<Item=String, Error=Error>>> {
+        match (&self.s3, &job.s3_key) {
+            (Some(s3), Some(key)) => Some(s3.presign(key)),
+            _ => None,
+        }
+    }
+
+    fn update<F: FnOnce(&mut ExportJob)>(&self, id: &str, f: F) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            f(job);
+        }
+    }
+
+    fn file_name(job: &ExportJob) -> String {
+        format!("{}.{}", job.id, job.format)
+    }
+
+    /// Path of the job's result in the spool directory.
+    pub fn file_path(&self, job: &ExportJob) -> PathBuf {
+        self.dir.join(Self::file_name(job))
+    }
+
+    /// Runs the job in the background on the current arbiter. With `debug`, a failed job
+    /// reports the database error; otherwise it's only logged.
+    pub fn run(
+        &self,
+        job: &ExportJob,
+        backend: Box<dyn Backend + Sync + Send>,
+        query: ExportQuery,
+        api_key_meter: Option<ApiKeyMeter>,
+        debug: bool,
+    )
+    {
+        let ExportQuery { sql, mut headers, format, source_data, steps, show_labels, columns } = query;
+
+        let running_jobs = self.clone();
+        let done_jobs = self.clone();
+        let running_id = job.id.clone();
+        let id = job.id.clone();
+        let path = self.file_path(job);
+        let file_name = Self::file_name(job);
+        let s3 = self.s3.clone();
+
+        let fut = future::lazy(move || {
+                running_jobs.update(&running_id, |job| job.status = ExportStatus::Running);
+                backend.exec_sql(sql)
+            })
+            .and_then(move |df| -> Box<dyn Future<Item=(usize, bool, Option<String>), Error=Error>> {
+                let mut df = match post_process(df, &steps) {
+                    Ok(df) => df,
+                    Err(err) => return Box::new(future::err(err.into())),
+                };
+
+                let truncated = truncate_rows(&mut df, steps.max_rows);
+
+                if let Err(err) = select_columns(&mut df, &mut headers, show_labels, columns.as_ref().map(|c| c.as_slice())) {
+                    return Box::new(future::err(err));
+                }
+
+                let rows = df.len();
+
+                if let Some(ref api_key_meter) = api_key_meter {
+                    api_key_meter.record_rows(rows);
+                }
+
+                let res = match format_records(&headers, df, format, None, source_data, false) {
+                    Ok(res) => res,
+                    Err(err) => return Box::new(future::err(err)),
+                };
+
+                match s3 {
+                    Some(s3) => {
+                        Box::new(s3.upload(&file_name, res.into_bytes()).map(move |key| (rows, truncated, Some(key))))
+                    },
+                    None => {
+                        Box::new(write_file(path, res).map(move |_| (rows, truncated, None)))
+                    },
+                }
+            })
+            .then(move |res| {
+                match res {
+                    Ok((rows, truncated, s3_key)) => {
+                        info!("Export job {} completed, {} rows", id, rows);
+                        done_jobs.update(&id, |job| {
+                            job.status = ExportStatus::Completed;
+                            job.rows = Some(rows);
+                            job.truncated = truncated;
+                            job.s3_key = s3_key;
+                            job.finished = Some(now_secs());
+                        });
+                    },
+                    Err(err) => {
+                        error!("Export job {} failed: {}", id, err);
+                        let err = ServerError::from_query_error(&err, debug);
+                        done_jobs.update(&id, |job| {
+                            job.status = ExportStatus::Failed;
+                            job.error = Some(err.message());
+                            job.error_status = Some(err.status().as_u16());
+                            job.finished = Some(now_secs());
+                        });
+                    },
+                }
+                Ok(())
+            });
+
+        actix::Arbiter::spawn(fut);
+    }
+}
+
+
+fn active_count(jobs: &HashMap<String, ExportJob>) -> usize {
+    jobs.values()
+        .filter(|job| job.status == ExportStatus::Queued || job.status == ExportStatus::Running)
+        .count()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Writes the file in its own thread, so that large files don't block the event loop.
+fn write_file(path: PathBuf, contents: String) -> impl Future<Item=(), Error=Error> {
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let _ = tx.send(write_file_sync(&path, contents));
+    });
+
+    rx.map_err(|_| format_err!("Export file writer stopped"))
+        .and_then(future::result)
+}
+
+/// Writes to a temporary file first, so that a partial file is never served.
+fn write_file_sync(path: &Path, contents: String) -> Result<(), Error> {
+    let part_path = path.with_extension("part");
+    fs::write(&part_path, contents)?;
+    fs::rename(&part_path, path)?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_jobs() {
+        let dir = std::env::temp_dir().join("tesseract-export-test");
+        let jobs = ExportJobs::new(dir.to_str().unwrap(), None, None).unwrap();
+
+        let job_1 = jobs.create("Sales", "csv", 0).unwrap();
+        let job_2 = jobs.create("Sales", "csv", 0).unwrap();
+        assert_ne!(job_1.id, job_2.id);
+        assert_eq!(job_1.id.len(), 32);
+        assert_eq!(jobs.get(&job_1.id).unwrap().status, ExportStatus::Queued);
+        assert!(jobs.get("nope").is_none());
+
+        jobs.update(&job_1.id, |job| job.status = ExportStatus::Completed);
+        assert_eq!(jobs.get(&job_1.id).unwrap().status, ExportStatus::Completed);
+        assert_eq!(jobs.get(&job_2.id).unwrap().status, ExportStatus::Queued);
+        assert_eq!(jobs.active_count(), 1);
+
+        assert_eq!(jobs.file_path(&job_1), dir.join(format!("{}.csv", job_1.id)));
+
+        // finished jobs are dropped after their retention
+        let finished = now_secs() - FINISHED_JOB_RETENTION.as_secs();
+        jobs.update(&job_1.id, |job| job.finished = Some(finished));
+        jobs.prune(finished + FINISHED_JOB_RETENTION.as_secs() - 1);
+        assert!(jobs.get(&job_1.id).is_some());
+        jobs.prune(finished + FINISHED_JOB_RETENTION.as_secs());
+        assert!(jobs.get(&job_1.id).is_none());
+        assert!(jobs.get(&job_2.id).is_some());
+    }
+
+    #[test]
+    fn test_export_jobs_max_active() {
+        let dir = std::env::temp_dir().join("tesseract-export-test");
+        let jobs = ExportJobs::new(dir.to_str().unwrap(), None, Some(1)).unwrap();
+
+        let job_1 = jobs.create("Sales", "csv", 0).unwrap();
+        assert!(jobs.create("Sales", "csv", 0).is_none());
+
+        // finished jobs don't count
+        jobs.update(&job_1.id, |job| job.status = ExportStatus::Failed);
+        assert!(jobs.create("Sales", "csv", 0).is_some());
+    }
+
+    #[test]
+    fn test_s3_key() {
+        let mut target = S3Target {
+            bucket: "exports".to_owned(),
+            prefix: "".to_owned(),
+            region: Region::UsEast1,
+            expires: Duration::from_secs(3600),
+        };
+        assert_eq!(target.key("a.csv"), "a.csv");
+
+        target.prefix = "tesseract".to_owned();
+        assert_eq!(target.key("a.csv"), "tesseract/a.csv");
+
+        target.prefix = "tesseract/".to_owned();
+        assert_eq!(target.key("a.csv"), "tesseract/a.csv");
+    }
+}
@@ -6,24 +6,35 @@ use actix_web::{
     Path,
 };
 
-use failure::Error;
+use failure::{Error, bail, format_err};
 use futures::future::{self, Future};
 use lazy_static::lazy_static;
 use log::*;
 use serde_derive::{Serialize, Deserialize};
 use serde_qs as qs;
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
-use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::Query as TsQuery;
+use std::time::Instant;
+use tesseract_core::format::{apply_number_formats, format_records, format_jsonrecords_with_schema, FormatType, FormatLocale, NumberFormat, GEOMETRY_HEADER};
+use tesseract_core::{DataFrame, ShowColumns, TimeFill, Query as TsQuery};
+use tesseract_core::names::{LevelName, Mask, Property};
+use tesseract_core::schema::{Aggregator, Cube, DimensionType};
 
-use crate::handlers::util::validate_members;
+use crate::handlers::util::{check_sample, validate_members, validate_query_limits};
 
+use crate::api_keys::ApiKeyMeter;
 use crate::app::AppState;
 use crate::errors::ServerError;
+use crate::logic_layer::CubeCache;
+use crate::request_id::with_request_id;
 use super::util::{
-    boxed_error_http_response, verify_authorization,
+    boxed_error_http_response, verify_authorization, negotiate_format,
     format_to_content_type, generate_source_data,
-    get_redis_cache_key, check_redis_cache, insert_into_redis_cache
+    get_redis_cache_key, check_redis_cache, insert_into_redis_cache,
+    get_etag, check_not_modified, apply_max_rows, truncate_rows, truncate_rows_before_totals,
+    post_process, select_columns, PostProcess, TRUNCATED_HEADER,
+    DATA_UPDATED_HEADER, SAMPLE_HEADER, UNITS_HEADER, units_header,
+    PREVIEW_ROWS, PREVIEW_TOTAL_HEADER, set_query_headers,
 };
 use r2d2_redis::{redis};
 
@@ -33,7 +44,11 @@ pub fn aggregate_default_handler(
     (req, cube): (HttpRequest<AppState>, Path<String>)
     ) -> FutureResponse<HttpResponse>
 {
-    let cube_format = (cube.into_inner(), "csv".to_owned());
+    let format = match negotiate_format(&req, "csv") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    let cube_format = (cube.into_inner(), format);
     do_aggregate(req, cube_format)
 }
 
@@ -77,91 +92,654 @@ pub fn do_aggregate(
 
     info!("query opts:{:?}", agg_query);
 
+    // Results change with the schema, the cube's data (as far as the cache knows) or the query
+    let (etag, last_modified) = get_etag(&req, &format!("aggregate/{}/{:?}?{}", cube, format, query), Some(cube.as_str()));
+    if let Some(res) = check_not_modified(&req, &etag, &last_modified) {
+        return boxed_error_http_response(res);
+    }
+
     // Check if this query is already cached
     let redis_pool = req.state().redis_pool.clone();
     let redis_cache_key = get_redis_cache_key("core", &req, &cube, &format);
 
-    if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key) {
+    if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key, &cube) {
         return res;
     }
 
     // Gets the Source Data
     let source_data = Some(generate_source_data(&cube_obj));
 
+    let pivot = agg_query.pivot.clone();
+    let totals = agg_query.totals.unwrap_or(false);
+    let time_fill = agg_query.time_fill.as_ref()
+        .map(|f| f.parse::<TimeFill>())
+        .transpose();
+    let time_fill = ok_or_404!(time_fill);
+    let format_locale = agg_query.locale.as_ref()
+        .map(|l| l.parse::<FormatLocale>())
+        .transpose();
+    let format_locale = ok_or_404!(format_locale);
+    let sample = agg_query.shared().sample;
+    let column_types = ok_or_404!(agg_query.shared().column_types(&format));
+    let conversions = ok_or_400!(agg_query.shared().conversions());
+    let columns = ok_or_400!(agg_query.shared().columns());
+    let show_labels = ok_or_404!(agg_query.shared().show()) == ShowColumns::Labels;
+    // growth on a sample is calculated on the scaled up measures, so not in the database
+    let dataframe_growth = sample.is_some() || agg_query.shared().dataframe_growth
+        .unwrap_or(req.state().env_vars.dataframe_growth);
+    let preview = agg_query.preview.unwrap_or(false);
+    if preview {
+        ok_or_400!(check_preview(&agg_query));
+    }
+
     // Turn AggregateQueryOpt into Query
     let ts_query: Result<TsQuery, _> = agg_query.try_into();
-    let ts_query = ok_or_404!(ts_query);
+    let mut ts_query = ok_or_404!(ts_query);
+    ts_query.sample = sample;
+    ok_or_400!(check_sample(&ts_query));
+    if time_fill.is_some() {
+        ok_or_400!(check_time_fill(&ts_query));
+    }
+
+    // geojson needs the geometry of the drilldown level
+    let geometry_property = match format {
+        FormatType::GeoJson => Some(ok_or_404!(add_geometry_property(&cube_obj, &mut ts_query))),
+        _ => None,
+    };
 
     // sql injection mitigation on query:
     // - Check that cut members exist in members cache
     // this is in braces to explicitly the scope in which
     // req is borrowed, since req is moved later in the `map_err`
-    {
+    let (last_updated, time_fill_periods) = {
         let cache = req.state().cache.read().unwrap();
         let cube_cache = some_or_404!(cache.find_cube_info(&cube), format!("Cube {} not found", cube));
         ok_or_404!(validate_members(&ts_query.cuts, &cube_cache));
-    }
+        ok_or_400!(validate_query_limits(&ts_query, &cube_cache, &req.state().env_vars.query_limits));
+
+        let time_fill_periods = match time_fill {
+            Some(_) => Some(ok_or_404!(time_fill_periods(&cube_obj, &ts_query, &cube_cache))),
+            None => None,
+        };
+
+        (cube_cache.last_updated.clone(), time_fill_periods)
+    };
+
+    // previews are already limited
+    let max_rows = if preview {
+        None
+    } else {
+        apply_max_rows(&mut ts_query, &req.state().env_vars.query_limits)
+    };
 
     let query_ir_headers = schema.sql_query(&cube, &ts_query, None);
-    let (query_ir, headers) = ok_or_404!(query_ir_headers);
+    let (mut query_ir, mut headers) = ok_or_404!(query_ir_headers);
 
+    let (mut steps, sql_previous_year) = PostProcess::take(&mut query_ir, &**req.state().cube_backend(&cube_obj), dataframe_growth, max_rows);
+    if preview && (steps.has_calculations() || sql_previous_year.is_some()) {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("preview can't be combined with growth or share calculated on the results, or vs_previous_year")
+        );
+    }
+
+    let capabilities = req.state().cube_backend(&cube_obj).capabilities();
+    ok_or_422!(capabilities.check(&query_ir));
+    if preview && !capabilities.window_functions {
+        return boxed_error_http_response(
+            HttpResponse::UnprocessableEntity().json("Preview is not supported on this backend")
+        );
+    }
+
+    if let Some(geometry_property) = geometry_property {
+        let drill_count = headers.len() - ts_query.measures.len();
+        let geometry_idx = headers[..drill_count].iter()
+            .rposition(|h| *h == geometry_property);
+        let geometry_idx = some_or_404!(geometry_idx, "Could not find geometry column in results");
+        headers[geometry_idx] = GEOMETRY_HEADER.to_owned();
+    }
+
+    let result_order = query_ir.result_order();
+    let drill_key_idxs = query_ir.drill_key_idxs();
+    let drill_col_idxs = query_ir.drill_col_idxs();
     let sql = req.state()
-        .backend
+        .cube_backend(&cube_obj)
         .generate_sql(query_ir);
-
-    info!("Sql query: {}", sql);
+    let sql = match sql_previous_year {
+        Some(ref columns) => req.state().cube_backend(&cube_obj).dialect()
+            .previous_year_sql(&sql, columns, &result_order, max_rows.map(|max_rows| max_rows.saturating_add(1))),
+        None => sql,
+    };
+
+    let sql = if preview {
+        req.state().cube_backend(&cube_obj).dialect().preview_sql(&sql, PREVIEW_ROWS, &result_order)
+    } else {
+        sql
+    };
+
+    info!("Sql query ({} dialect): {}", req.state().cube_backend(&cube_obj).dialect().name(), sql);
     info!("Headers: {:?}", headers);
-    
-    req.state()
-        .backend
-        .exec_sql(sql)
-        .and_then(move |df| {
+
+    let pivot_idxs = match pivot {
+        Some(pivot) => Some(ok_or_400!(pivot_idxs(&pivot, &ts_query, &headers))),
+        None => None,
+    };
+
+    // number formats are for people reading CSVs; other formats keep raw numbers
+    let number_formats = match format {
+        FormatType::Csv => ok_or_404!(number_formats(&cube_obj, &ts_query, headers.len())),
+        _ => None,
+    };
+
+    let time_fill = match (time_fill, time_fill_periods) {
+        (Some(fill), Some((level_name, periods))) => {
+            let (time_idxs, key_idxs, mea_idxs) = ok_or_404!(time_fill_idxs(&level_name, &ts_query, &headers, &drill_key_idxs));
+            Some((fill, time_idxs, key_idxs, mea_idxs, periods))
+        },
+        _ => None,
+    };
+
+    // sums and counts on a sample are scaled up to estimate the totals
+    if let Some(sample) = sample {
+        steps.sample_scale = Some((1.0 / sample, ok_or_404!(sample_scale_idxs(&cube_obj, &ts_query, &headers))));
+    }
+
+    let totals_idxs = if totals {
+        Some(ok_or_400!(totals_idxs(&cube_obj, &ts_query, &headers, drill_col_idxs, pivot_idxs.is_some())))
+    } else {
+        None
+    };
+
+    let api_key_meter = ApiKeyMeter::from_request(&req);
+
+    // drilldown levels and duration of the query, to suggest pre-aggregations
+    let query_stats = req.state().query_stats.clone();
+    let stats_cube = cube.clone();
+    let stats_levels: Vec<LevelName> = ts_query.drilldowns.iter().map(|d| d.0.clone()).collect();
+    let query_start = Instant::now();
+
+    let fut = req.state().cube_backend(&cube_obj).exec_sql(sql);
+
+    with_request_id(&req, fut)
+        .and_then(move |mut df| {
+            let query_time = query_start.elapsed();
+            // previews only read a few rows, so their time says nothing of the query's
+            if !preview {
+                query_stats.record(&stats_cube, &stats_levels, query_time);
+            }
+
             let content_type = format_to_content_type(&format);
 
-            match format_records(&headers, df, format, source_data, false) {
-                Ok(res) => {
-                    // Try to insert this result in the Redis cache, if available
-                    insert_into_redis_cache(&res, &redis_pool, &redis_cache_key);
+            let preview_total = if preview {
+                match df.take_preview_total() {
+                    Ok(total) => Some(total),
+                    Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+                }
+            } else {
+                None
+            };
+
+            // calculations are of all the rows, so they're added before truncating
+            let mut df = match post_process(df, &steps) {
+                Ok(df) => df,
+                Err(err) => return Ok(err.response()),
+            };
+
+            if let Some((fill, ref time_idxs, ref key_idxs, ref mea_idxs, ref periods)) = time_fill {
+                // periods cut off by the row limit would be filled in as missing
+                if max_rows.map(|max_rows| df.len() as u64 > max_rows).unwrap_or(false) {
+                    return Ok(HttpResponse::BadRequest().json("time_fill results exceed the server's max rows; add cuts to the query"));
+                }
+                df = match fill_time_periods(&df, time_idxs, key_idxs, mea_idxs, periods, fill) {
+                    Ok(df) => df,
+                    Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+                };
+            }
 
-                    Ok(HttpResponse::Ok()
-                        .set(content_type)
-                        .body(res))
+            if let Err(err) = df.convert_units(&headers, &conversions) {
+                return Ok(HttpResponse::BadRequest().json(err.to_string()));
+            }
+
+            let data_len = df.len();
+            let has_totals = totals_idxs.is_some();
+
+            let mut df = match totals_idxs {
+                Some((drill_idxs, mea_idxs)) => {
+                    match df.with_totals(&drill_idxs, &mea_idxs) {
+                        Ok(df) => df,
+                        Err(err) => return Ok(HttpResponse::InternalServerError().json(err.to_string())),
+                    }
                 },
-                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+                None => df,
+            };
+
+            // truncated last, so that calculations and totals are of all the rows
+            let truncated = if has_totals {
+                truncate_rows_before_totals(&mut df, max_rows, data_len)
+            } else {
+                truncate_rows(&mut df, max_rows)
+            };
+
+            if let Some(ref api_key_meter) = api_key_meter {
+                api_key_meter.record_rows(df.len());
             }
-        })
-        .map_err(move |e| {
-            if req.state().debug {
-                ServerError::Db { cause: e.to_string() }.into()
+
+            if let Some(ref number_formats) = number_formats {
+                let locale = format_locale.clone().unwrap_or_default();
+                if let Err(err) = apply_number_formats(&mut df, number_formats, &locale) {
+                    return Ok(HttpResponse::NotFound().json(err.to_string()));
+                }
+            }
+
+            let (mut headers, mut df) = match pivot_idxs {
+                Some((pivot_idx, drop_idxs, mea_idxs)) => {
+                    match df.pivot(&headers, pivot_idx, &drop_idxs, &mea_idxs) {
+                        Ok(pivoted) => pivoted,
+                        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+                    }
+                },
+                None => (headers, df),
+            };
+
+            if let Err(err) = select_columns(&mut df, &mut headers, show_labels, columns.as_ref().map(|c| c.as_slice())) {
+                return Ok(HttpResponse::BadRequest().json(err.to_string()));
+            }
+
+            let row_count = df.len();
+
+            let formatted = if column_types {
+                format_jsonrecords_with_schema(&headers, df, source_data)
             } else {
-                ServerError::Db { cause: "Internal Server Error 1010".to_owned() }.into()
+                format_records(&headers, df, format, format_locale.as_ref(), source_data, false)
+            };
+
+            match formatted {
+                Ok(res) => {
+                    let mut response = HttpResponse::Ok();
+                    response
+                        .set(content_type)
+                        .header("ETag", etag)
+                        .header("Last-Modified", last_modified);
+                    set_query_headers(&mut response, &stats_cube, row_count, query_time);
+
+                    if let Some(last_updated) = last_updated {
+                        response.header(DATA_UPDATED_HEADER, last_updated);
+                    }
+
+                    if let Some(sample) = sample {
+                        response.header(SAMPLE_HEADER, sample.to_string());
+                    }
+
+                    if !conversions.is_empty() {
+                        response.header(UNITS_HEADER, units_header(&conversions));
+                    }
+
+                    if let Some(preview_total) = preview_total {
+                        response.header(PREVIEW_TOTAL_HEADER, preview_total.to_string());
+                    }
+
+                    // Try to insert this result in the Redis cache, if available.
+                    // Truncated, sampled, converted and preview results aren't cached, since
+                    // the cache drops the header.
+                    if truncated {
+                        response.header(TRUNCATED_HEADER, max_rows.unwrap_or(0).to_string());
+                    } else if sample.is_none() && conversions.is_empty() && !preview {
+                        insert_into_redis_cache(&res, row_count, query_time, &redis_pool, &redis_cache_key);
+                    }
+
+                    Ok(response.body(res))
+                },
+                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
             }
         })
+        .map_err(move |e| ServerError::from_query_error(&e, req.state().debug).into())
         .responder()
 }
 
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct AggregateQueryOpt {
-    drilldowns: Option<Vec<String>>,
-    cuts: Option<Vec<String>>,
-    measures: Option<Vec<String>>,
-    properties: Option<Vec<String>>,
-    filters: Option<Vec<String>>,
-    captions: Option<Vec<String>>,
-    parents: Option<bool>,
-    top: Option<String>,
-    top_where: Option<String>,
-    sort: Option<String>,
-    limit: Option<String>,
-    growth: Option<String>,
-    rca: Option<String>,
-    rate: Option<String>,
-    debug: Option<bool>,
-    exclude_default_members: Option<bool>,
-//    distinct: Option<bool>,
-//    nonempty: Option<bool>,
-    sparse: Option<bool>,
+/// Previews only return the first and last rows, so they can't be reshaped or filled in.
+fn check_preview(agg_query: &AggregateQueryOpt) -> Result<(), Error> {
+    if agg_query.pivot.is_some() || agg_query.totals.unwrap_or(false) || agg_query.time_fill.is_some() {
+        bail!("preview can't be combined with pivot, totals or time_fill");
+    }
+
+    Ok(())
+}
+
+
+/// Options which reshape or estimate the results on the aggregate endpoint; an export
+/// would silently return a different file without them.
+pub(crate) fn check_export(agg_query: &AggregateQueryOpt) -> Result<(), Error> {
+    let unsupported = [
+        ("pivot", agg_query.pivot.is_some()),
+        ("totals", agg_query.totals.unwrap_or(false)),
+        ("time_fill", agg_query.time_fill.is_some()),
+        ("locale", agg_query.locale.is_some()),
+        ("sample", agg_query.shared().sample.is_some()),
+        ("preview", agg_query.preview.unwrap_or(false)),
+    ];
+
+    let unsupported: Vec<&str> = unsupported.iter()
+        .filter(|(_, is_set)| *is_set)
+        .map(|(name, _)| *name)
+        .collect();
+
+    if !unsupported.is_empty() {
+        bail!("Exports don't support {}", unsupported.join(", "));
+    }
+
+    Ok(())
+}
+
+
+/// Finds the header indexes used to pivot the results on the `pivot` level:
+/// the level's label column, its ID column (dropped, if any), and the measures.
+fn pivot_idxs(
+    pivot: &str,
+    ts_query: &TsQuery,
+    headers: &[String],
+    ) -> Result<(usize, Vec<usize>, Vec<usize>), Error>
+{
+    if ts_query.growth.is_some() || ts_query.rca.is_some() || ts_query.rate.is_some() || ts_query.share.is_some() || ts_query.vs_previous_year.is_some() {
+        bail!("Pivot cannot be combined with growth, rca, rate, share or vs_previous_year");
+    }
+
+    let level_name: LevelName = pivot.parse()?;
+
+    if !ts_query.drilldowns.iter().any(|d| d.0 == level_name) {
+        bail!("Pivot level {} must be one of the drilldowns", pivot);
+    }
+
+    let pivot_idx = headers.iter()
+        .position(|h| *h == level_name.level)
+        .ok_or_else(|| format_err!("Could not find pivot level {} in results", pivot))?;
+
+    let drop_idxs = headers.iter()
+        .position(|h| *h == format!("{} ID", level_name.level))
+        .into_iter()
+        .collect();
+
+    // measures are always the last headers
+    let mea_count = ts_query.measures.len();
+    let mea_idxs = (headers.len() - mea_count..headers.len()).collect();
+
+    Ok((pivot_idx, drop_idxs, mea_idxs))
+}
+
+
+/// Adds the geometry property of the geo-enabled drilldown to the query, if it's not
+/// already requested, and returns the property's name.
+fn add_geometry_property(cube: &Cube, ts_query: &mut TsQuery) -> Result<String, Error> {
+    for drill in &ts_query.drilldowns {
+        let level = cube.dimensions.iter()
+            .find(|dim| dim.name == drill.0.dimension)
+            .and_then(|dim| dim.hierarchies.iter().find(|hier| hier.name == drill.0.hierarchy))
+            .and_then(|hier| hier.levels.iter().find(|lvl| lvl.name == drill.0.level))
+            .ok_or_else(|| format_err!("Could not find level for drilldown {}", drill.0))?;
+
+        if let Some(ref geometry_property) = level.geometry_property {
+            let property = Property {
+                level_name: drill.0.clone(),
+                property: geometry_property.clone(),
+            };
+
+            if !ts_query.properties.contains(&property) {
+                ts_query.properties.push(property);
+            }
+
+            return Ok(geometry_property.clone());
+        }
+    }
+
+    bail!("GeoJSON format requires a drilldown on a level with a geometry property");
+}
+
+
+/// Gets the number format of each result column from the measures' `format_template`.
+/// Returns `None` when no measure has one, or when growth, rca or rate change the
+/// measure columns.
+fn number_formats(
+    cube: &Cube,
+    ts_query: &TsQuery,
+    headers_len: usize,
+    ) -> Result<Option<Vec<Option<NumberFormat>>>, Error>
+{
+    if ts_query.growth.is_some() || ts_query.rca.is_some() || ts_query.rate.is_some() || ts_query.share.is_some() || ts_query.vs_previous_year.is_some() {
+        return Ok(None);
+    }
+
+    let mut mea_formats = vec![];
+    for measure in &ts_query.measures {
+        let mea = cube.measures.iter()
+            .find(|m| m.name == measure.0)
+            .ok_or_else(|| format_err!("Could not find measure {}", measure.0))?;
+
+        let mea_format = mea.format_template.as_ref()
+            .map(|t| t.parse())
+            .transpose()?;
+
+        mea_formats.push(mea_format);
+    }
+
+    if mea_formats.iter().all(|f| f.is_none()) {
+        return Ok(None);
+    }
+
+    // measures are always the last headers
+    let mut formats = vec![None; headers_len - mea_formats.len()];
+    formats.extend(mea_formats);
+
+    Ok(Some(formats))
+}
+
+
+/// Header indexes of the sum and count measures, which are scaled up when the query is run on
+/// a sample. Other aggregations are estimated by their value on the sample.
+pub(crate) fn sample_scale_idxs(
+    cube: &Cube,
+    ts_query: &TsQuery,
+    headers: &[String],
+    ) -> Result<Vec<usize>, Error>
+{
+    let mut idxs = vec![];
+    for measure in &ts_query.measures {
+        let mea = cube.measures.iter()
+            .find(|m| m.name == measure.0)
+            .ok_or_else(|| format_err!("Could not find measure {}", measure.0))?;
+
+        match mea.aggregator {
+            Aggregator::Sum | Aggregator::Count => {
+                if let Some(idx) = headers.iter().position(|h| *h == measure.0) {
+                    idxs.push(idx);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(idxs)
+}
+
+
+/// Filled periods are added after the query, so calculations on the rows and limits on them
+/// would be wrong for the filled results.
+fn check_time_fill(ts_query: &TsQuery) -> Result<(), Error> {
+    if ts_query.growth.is_some() || ts_query.rca.is_some() || ts_query.rate.is_some() || ts_query.share.is_some() || ts_query.vs_previous_year.is_some() {
+        bail!("time_fill cannot be combined with growth, rca, rate, share or vs_previous_year");
+    }
+
+    if ts_query.sort.is_some() || ts_query.limit.is_some() || ts_query.top.is_some() || ts_query.top_where.is_some() {
+        bail!("time_fill cannot be combined with sort, limit, top or top_where");
+    }
+
+    Ok(())
+}
+
+
+/// Finds the time drilldown whose missing periods are filled, and its periods, sorted: the
+/// members of its level, or those selected by a cut on it.
+fn time_fill_periods(
+    cube: &Cube,
+    ts_query: &TsQuery,
+    cube_cache: &CubeCache,
+    ) -> Result<(LevelName, Vec<String>), Error>
+{
+    let mut time_drills = ts_query.drilldowns.iter()
+        .filter(|drill| {
+            cube.dimensions.iter()
+                .any(|dim| dim.name == drill.0.dimension && dim.dim_type == DimensionType::Time)
+        });
+
+    let level_name = match (time_drills.next(), time_drills.next()) {
+        (Some(drill), None) => drill.0.clone(),
+        (None, _) => bail!("time_fill requires a drilldown on a time dimension"),
+        (Some(_), Some(_)) => bail!("time_fill requires a single drilldown on a time dimension"),
+    };
+
+    let members = cube_cache.members_for_level(&level_name)
+        .ok_or_else(|| format_err!("Members of time level {} are not cached", level_name))?;
+
+    let mut periods: Vec<String> = match ts_query.cuts.iter().find(|cut| cut.level_name == level_name) {
+        Some(cut) if cut.mask == Mask::Include => cut.members.clone(),
+        Some(cut) => members.iter().filter(|m| !cut.members.contains(m)).cloned().collect(),
+        None => members.iter().cloned().collect(),
+    };
+
+    if periods.iter().all(|p| p.parse::<i64>().is_ok()) {
+        periods.sort_by_key(|p| p.parse::<i64>().unwrap_or(0));
+    } else {
+        periods.sort();
+    }
+    periods.dedup();
+
+    Ok((level_name, periods))
+}
+
+
+/// Finds the header indexes used to fill missing periods: the time level's ID column
+/// (its label column if it has none) and label column, the keys of the other drilldowns,
+/// out of the `drill_key_idxs` of the query, and the measures.
+fn time_fill_idxs(
+    level_name: &LevelName,
+    ts_query: &TsQuery,
+    headers: &[String],
+    drill_key_idxs: &[usize],
+    ) -> Result<(Vec<usize>, Vec<usize>, Vec<usize>), Error>
+{
+    let id_idx = headers.iter().position(|h| *h == format!("{} ID", level_name.level));
+    let label_idx = headers.iter().position(|h| *h == level_name.level);
+
+    // with `show=ids`, levels with a name column only have their ID column
+    let time_idxs: Vec<usize> = id_idx.into_iter().chain(label_idx).collect();
+    if time_idxs.is_empty() {
+        bail!("Could not find time level {} in results", level_name);
+    }
+
+    let key_idxs = drill_key_idxs.iter()
+        .filter(|i| !time_idxs.contains(i))
+        .cloned()
+        .collect();
+
+    // measures are always the last headers
+    let mea_idxs = (headers.len() - ts_query.measures.len()..headers.len()).collect();
+
+    Ok((time_idxs, key_idxs, mea_idxs))
+}
+
+
+/// Fills the periods missing from the results, between the first and last periods in them;
+/// periods outside of the results' range are not added.
+fn fill_time_periods(
+    df: &DataFrame,
+    time_idxs: &[usize],
+    key_idxs: &[usize],
+    mea_idxs: &[usize],
+    periods: &[String],
+    fill: TimeFill,
+    ) -> Result<DataFrame, Error>
+{
+    let observed: HashSet<String> = df.columns[time_idxs[0]].stringify_column_data()
+        .into_iter()
+        .collect();
+
+    let observed_positions: Vec<usize> = periods.iter()
+        .enumerate()
+        .filter(|(_, p)| observed.contains(*p))
+        .map(|(i, _)| i)
+        .collect();
+
+    let periods = match (observed_positions.first(), observed_positions.last()) {
+        (Some(first), Some(last)) => &periods[*first..=*last],
+        _ => &periods[..0],
+    };
+
+    df.fill_periods(time_idxs, key_idxs, mea_idxs, periods, fill)
+}
+
+
+/// Finds the header indexes used to add totals to the results: the columns of each
+/// drilldown level (its ID and label), and the measures.
+fn totals_idxs(
+    cube: &Cube,
+    ts_query: &TsQuery,
+    headers: &[String],
+    drill_col_idxs: Vec<Vec<usize>>,
+    pivot: bool,
+    ) -> Result<(Vec<Vec<usize>>, Vec<usize>), Error>
+{
+    if pivot || ts_query.growth.is_some() || ts_query.rca.is_some() || ts_query.rate.is_some() || ts_query.share.is_some() || ts_query.vs_previous_year.is_some() {
+        bail!("Totals cannot be combined with pivot, growth, rca, rate, share or vs_previous_year");
+    }
+
+    // totals are sums of the rows, so they're only right for additive measures
+    for measure in &ts_query.measures {
+        let mea = cube.measures.iter()
+            .find(|m| m.name == measure.0)
+            .ok_or_else(|| format_err!("Could not find measure {}", measure.0))?;
+
+        match mea.aggregator {
+            Aggregator::Sum | Aggregator::Count => (),
+            _ => bail!("Totals are only supported for sum and count measures, {} is not", measure.0),
+        }
+    }
+
+    // measures are always the last headers
+    let drill_count = headers.len() - ts_query.measures.len();
+    if drill_col_idxs.iter().map(|idxs| idxs.len()).sum::<usize>() != drill_count {
+        bail!("Drilldown columns do not match the results");
+    }
+
+    let mea_idxs = (drill_count..headers.len()).collect();
+
+    Ok((drill_col_idxs, mea_idxs))
+}
+
+
+query_opt! {
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct AggregateQueryOpt {
+        drilldowns: Option<Vec<String>>,
+        cuts: Option<Vec<String>>,
+        measures: Option<Vec<String>>,
+        properties: Option<Vec<String>>,
+        filters: Option<Vec<String>>,
+        #[serde(rename="where")]
+        row_filters: Option<Vec<String>>,
+        property_filters: Option<Vec<String>>,
+        captions: Option<Vec<String>>,
+        parents: Option<bool>,
+        top: Option<String>,
+        growth: Option<String>,
+        share: Option<String>,
+        vs_previous_year: Option<String>,
+        rca: Option<String>,
+        rate: Option<String>,
+    //    nonempty: Option<bool>,
+        pivot: Option<String>,
+        totals: Option<bool>,
+        time_fill: Option<String>,
+        locale: Option<String>,
+        preview: Option<bool>,
+    }
 }
 
 impl TryFrom<AggregateQueryOpt> for TsQuery {
@@ -198,6 +776,18 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             })
             .unwrap_or(Ok(vec![]));
 
+        let row_filters: Result<Vec<_>, _> = agg_query_opt.row_filters
+            .map(|fs| {
+                fs.iter().map(|f| f.parse()).collect()
+            })
+            .unwrap_or(Ok(vec![]));
+
+        let property_filters: Result<Vec<_>, _> = agg_query_opt.property_filters
+            .map(|fs| {
+                fs.iter().map(|f| f.parse()).collect()
+            })
+            .unwrap_or(Ok(vec![]));
+
         let captions: Result<Vec<_>, _> = agg_query_opt.captions
             .map(|cs| {
                 cs.iter().map(|c| c.parse()).collect()
@@ -209,6 +799,8 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
         let measures = measures?;
         let properties = properties?;
         let filters = filters?;
+        let row_filters = row_filters?;
+        let property_filters = property_filters?;
         let captions = captions?;
 
         let parents = agg_query_opt.parents.unwrap_or(false);
@@ -216,18 +808,17 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
         let top = agg_query_opt.top
             .map(|t| t.parse())
             .transpose()?;
-        let top_where = agg_query_opt.top_where
-            .map(|t| t.parse())
+
+        let growth = agg_query_opt.growth
+            .map(|g| g.parse())
             .transpose()?;
-        let sort = agg_query_opt.sort
+
+        let share = agg_query_opt.share
             .map(|s| s.parse())
             .transpose()?;
-        let limit = agg_query_opt.limit
-            .map(|l| l.parse())
-            .transpose()?;
 
-        let growth = agg_query_opt.growth
-            .map(|g| g.parse())
+        let vs_previous_year = agg_query_opt.vs_previous_year
+            .map(|m| m.parse())
             .transpose()?;
 
         let rca = agg_query_opt.rca
@@ -238,30 +829,33 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             .map(|r| r.parse())
             .transpose()?;
 
-        let debug = agg_query_opt.debug.unwrap_or(false);
-        let sparse = agg_query_opt.sparse.unwrap_or(false);
-        let exclude_default_members = agg_query_opt.exclude_default_members.unwrap_or(false);
+        let shared = agg_query_opt.shared();
 
         // TODO: deserialize rate
-        Ok(TsQuery {
+        let mut ts_query = TsQuery {
             drilldowns,
             cuts,
             measures,
             parents,
+            parent_levels: vec![],
             properties,
             filters,
+            row_filters,
+            property_filters,
             captions,
             top,
-            top_where,
-            sort,
-            limit,
             rca,
             growth,
-            debug,
+            share,
+            vs_previous_year,
             rate,
-            sparse,
-            exclude_default_members,
-        })
+            // the shared options are set below, and `sample` only by the handlers which
+            // scale the results
+            ..TsQuery::new()
+        };
+        shared.apply(&mut ts_query)?;
+
+        Ok(ts_query)
     }
 }
 
@@ -4,18 +4,18 @@ use actix_web::{
     HttpResponse,
     Path,
 };
-use futures::future;
+use futures::{future, Stream};
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
 use std::convert::TryInto;
 use tesseract_core::format::FormatType;
 use tesseract_core::format_stream::format_records_stream;
-use tesseract_core::Query as TsQuery;
+use tesseract_core::{Query as TsQuery, ShowColumns};
 
 use crate::app::AppState;
 use super::aggregate::AggregateQueryOpt;
-use super::util::{boxed_error_http_response, verify_authorization, format_to_content_type};
+use super::util::{boxed_error_http_response, verify_authorization, format_to_content_type, negotiate_format, validate_query_limits};
 
 
 /// Handles default aggregation when a format is not specified.
@@ -24,7 +24,11 @@ pub fn aggregate_default_handler(
     (req, cube): (HttpRequest<AppState>, Path<String>)
     ) -> FutureResponse<HttpResponse>
 {
-    let cube_format = (cube.into_inner(), "csv".to_owned());
+    let format = match negotiate_format(&req, "csv") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    let cube_format = (cube.into_inner(), format);
     do_aggregate(req, cube_format)
 }
 
@@ -67,10 +71,24 @@ pub fn do_aggregate(
 
     info!("query opts:{:?}", agg_query);
 
+    // columns are projected, and ID columns dropped, on whole results
+    let show = ok_or_404!(agg_query.shared().show());
+    if agg_query.shared().columns.is_some() || show == ShowColumns::Labels {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("Columns and show=labels cannot be streamed")
+        );
+    }
+
     // Turn AggregateQueryOpt into Query
     let ts_query: Result<TsQuery, _> = agg_query.try_into();
     let ts_query = ok_or_404!(ts_query);
 
+    {
+        let cache = req.state().cache.read().unwrap();
+        let cube_cache = some_or_404!(cache.find_cube_info(&cube), format!("Cube {} not found", cube));
+        ok_or_400!(validate_query_limits(&ts_query, &cube_cache, &req.state().env_vars.query_limits));
+    }
+
     let query_ir_headers = req
         .state()
         .schema.read().unwrap()
@@ -78,16 +96,56 @@ pub fn do_aggregate(
 
     let (query_ir, headers) = ok_or_404!(query_ir_headers);
 
+    let capabilities = req.state().cube_backend(&cube_obj).capabilities();
+    if !capabilities.streaming {
+        return boxed_error_http_response(
+            HttpResponse::UnprocessableEntity().json("Streaming is not supported on this backend")
+        );
+    }
+    ok_or_422!(capabilities.check(&query_ir));
+    let null_zero_idxs = query_ir.null_zero_idxs.clone();
+    let key_widths = query_ir.key_widths.clone();
+    let all_members = query_ir.all_members.clone();
+
+    // growth without sql is calculated on whole results, which streaming doesn't have
+    if query_ir.growth.is_some() && !req.state().cube_backend(&cube_obj).dialect().supports_growth() {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("Growth cannot be streamed with this database")
+        );
+    }
+
+    // share is also calculated on whole results
+    if query_ir.share.is_some() {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("Share cannot be streamed")
+        );
+    }
+
+    // and so is the previous year comparison, which also drops rows
+    if query_ir.previous_year.is_some() {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("vs_previous_year cannot be streamed")
+        );
+    }
+
     let sql = req.state()
-        .backend
+        .cube_backend(&cube_obj)
         .generate_sql(query_ir);
 
-    info!("Sql query: {}", sql);
+    info!("Sql query ({} dialect): {}", req.state().cube_backend(&cube_obj).dialect().name(), sql);
     info!("Headers: {:?}", headers);
 
     let df_stream = req.state()
-        .backend
-        .exec_sql_stream(sql);
+        .cube_backend(&cube_obj)
+        .exec_sql_stream(sql)
+        .map(move |df| {
+            df.and_then(|mut df| {
+                df.insert_all_members(&all_members)?;
+                df.fill_null_zeros(&null_zero_idxs)?;
+                df.pad_keys(&key_widths)?;
+                Ok(df)
+            })
+        });
 
     let content_type = format_to_content_type(&format);
 
@@ -1,27 +1,32 @@
 use std::collections::HashMap;
 use std::str;
+use std::time::Instant;
 
 use actix_web::{
+    AsyncResponder,
+    FutureResponse,
     HttpRequest,
     HttpResponse,
     Path,
-    Result as ActixResult,
 };
 use failure::{Error, format_err};
-use futures::future::Future;
+use futures::future::{self, Future, IntoFuture};
+use futures::stream::{self, Stream};
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
 use tesseract_core::names::{Property, LevelName};
 use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::{DataFrame, Column, ColumnData};
-use tesseract_core::schema::{Cube, DimensionType, Level};
+use tesseract_core::{Backend, DataFrame, Column, ColumnData};
+use tesseract_core::dialect::SqlDialect;
+use tesseract_core::schema::{Cube, DimensionType};
 use crate::app::AppState;
+use crate::errors::ServerError;
 use crate::logic_layer::{LogicLayerConfig, CubeCache};
-use crate::handlers::util::{verify_authorization, format_to_content_type};
+use crate::handlers::util::{verify_authorization, format_to_content_type, boxed_error_string, boxed_error_http_response};
 use crate::handlers::logic_layer::{query_geoservice, GeoserviceQuery};
 
 
@@ -29,7 +34,7 @@ use crate::handlers::logic_layer::{query_geoservice, GeoserviceQuery};
 /// Default format is jsonrecords.
 pub fn diagnosis_default_handler(
     (req, _cube): (HttpRequest<AppState>, Path<()>)
-) -> ActixResult<HttpResponse>
+) -> FutureResponse<HttpResponse>
 {
     perform_diagnosis(req, "jsonrecords".to_owned())
 }
@@ -38,7 +43,7 @@ pub fn diagnosis_default_handler(
 /// Handles aggregation when a format is specified.
 pub fn diagnosis_handler(
     (req, cube_format): (HttpRequest<AppState>, Path<(String)>)
-) -> ActixResult<HttpResponse>
+) -> FutureResponse<HttpResponse>
 {
     perform_diagnosis(req, cube_format.to_owned())
 }
@@ -47,25 +52,42 @@ pub fn diagnosis_handler(
 #[derive(Debug, Clone, Deserialize)]
 pub struct DiagnosisQueryOpt {
     pub cube: Option<String>,
+    /// Fraction of the fact table rows the checks read, for big fact tables.
+    pub sample: Option<f64>,
+    /// Most rows of the fact or dimension table each check reads.
+    pub check_rows: Option<u64>,
 }
 
 
+/// Runs data quality checks on a cube, or on all cubes the user has access to, and reports
+/// the failed checks with the number of affected IDs or rows:
+/// - `MissingDimensionIDs`: foreign keys in the fact table with no match in the dimension table
+/// - `NonUniqueDimensionIDs`: duplicate primary keys in a dimension table
+/// - `NullMeasureValues`: null values in a measure column
+/// - `MissingLocalTables`: replicas of a distributed cube's cluster without its local table
+///
+/// A check whose query fails is reported as failed, with the error as its message.
+///
+/// With `sample` or `check_rows`, the checks read part of the tables only, so that they fit in
+/// a request's timeout on big cubes; counts are then of the rows read. Cubes whose backend
+/// can't sample are read in full, and listed in the `X-Tesseract-Sample-Skipped` header.
+/// The time taken by each check is in the body of a successful report, and summed by check
+/// in the `Server-Timing` header.
 pub fn perform_diagnosis(
     req: HttpRequest<AppState>,
     format: String,
-) -> ActixResult<HttpResponse>
+) -> FutureResponse<HttpResponse>
 {
     let format = format.parse::<FormatType>();
     let format = match format {
         Ok(f) => f,
-        Err(err) =>return Ok(HttpResponse::NotFound().json(err.to_string())),
+        Err(err) => return boxed_error_string(err.to_string()),
     };
 
     info!("Format: {:?}", format);
 
     let query = req.query_string();
     let schema = req.state().schema.read().unwrap();
-    let _debug = req.state().debug;
 
     lazy_static! {
         static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
@@ -73,213 +95,464 @@ pub fn perform_diagnosis(
 
     let query_opt = match QS_NON_STRICT.deserialize_str::<DiagnosisQueryOpt>(query) {
         Ok(q) => q,
-        Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string()))
+        Err(err) => return boxed_error_string(err.to_string()),
     };
 
+    if let Some(sample) = query_opt.sample {
+        if sample <= 0.0 || sample > 1.0 {
+            return boxed_error_http_response(
+                HttpResponse::BadRequest().json(format!("Sample must be between 0 and 1, got {}", sample))
+            );
+        }
+    }
+
     // If a cube name was provided, we try to match that,
     // otherwise we will diagnose all cubes this user has access to
-    match query_opt.cube {
-        Some(cube_name) => {
-            match schema.get_cube_by_name(&cube_name) {
-                Ok(cube) => {
-                    if let Err(err) = verify_authorization(&req, cube.min_auth_level) {
-                        return Ok(err);
-                    }
-
-                    let (error_types, error_messages) = diagnose_cube(&req, cube);
-
-                    format_diagnosis_response(error_types, error_messages, format, None)
-                },
-                Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string()))
+    let (checks, with_cubes, sample_skipped) = match query_opt.cube {
+        Some(ref cube_name) => {
+            let cube = match schema.get_cube_by_name(cube_name) {
+                Ok(cube) => cube,
+                Err(err) => return boxed_error_string(err.to_string()),
+            };
+            if let Err(err) = verify_authorization(&req, cube.min_auth_level) {
+                return boxed_error_http_response(err);
             }
+            if query_opt.sample.is_some() && !req.state().cube_backend(cube).capabilities().sampling {
+                return boxed_error_http_response(
+                    HttpResponse::UnprocessableEntity().json("Sample is not supported on this backend")
+                );
+            }
+
+            (diagnose_cube(&req, cube, &query_opt), false, vec![])
         },
         None => {
-            let mut error_cubes: Vec<String> = vec![];
-            let mut error_types: Vec<String> = vec![];
-            let mut error_messages: Vec<String> = vec![];
+            let cubes: Vec<&Cube> = schema.cubes.iter()
+                .filter(|cube| verify_authorization(&req, cube.min_auth_level).is_ok())
+                .collect();
 
-            for cube in &schema.cubes {
-                if let Err(err) = verify_authorization(&req, cube.min_auth_level) {
-                    continue;
-                }
+            // cubes on backends which can't sample are read in full
+            let sample_skipped = cubes.iter()
+                .filter(|cube| query_opt.sample.is_some() && !req.state().cube_backend(cube).capabilities().sampling)
+                .map(|cube| cube.name.clone())
+                .collect();
 
-                let (new_error_types, new_error_messages) = diagnose_cube(&req, &cube);
+            let checks = cubes.iter()
+                .map(|cube| diagnose_cube(&req, cube, &query_opt))
+                .collect();
 
-                // Add these to the overall list
-                if new_error_types.len() != 0 {
-                    let mut new_error_cubes: Vec<String> = vec![];
+            (run_in_order(checks), true, sample_skipped)
+        }
+    };
 
-                    for i in 0..new_error_types.len() {
-                        new_error_cubes.push(cube.name.clone());
-                    }
+    let debug = req.state().debug;
 
-                    error_cubes.extend(new_error_cubes);
-                    error_types.extend(new_error_types);
-                    error_messages.extend(new_error_messages);
-                }
-            }
+    checks
+        .map(move |(issues, timings)| format_diagnosis_response(issues, &timings, &sample_skipped, format, with_cubes))
+        .map_err(move |err| ServerError::from_query_error(&err, debug).into())
+        .responder()
+}
 
-            format_diagnosis_response(error_types, error_messages, format, Some(error_cubes))
-        }
-    }
+
+/// A failed check.
+#[derive(Debug, Clone)]
+struct DiagnosisIssue {
+    cube: String,
+    check: &'static str,
+    /// Number of affected IDs or rows.
+    count: u64,
+    /// Time taken by the check, in milliseconds.
+    ms: u64,
+    message: String,
+}
+
+// IDs listed in the message of a failed check.
+const SAMPLE_SIZE: usize = 10;
+
+const SERVER_TIMING_HEADER: &str = "Server-Timing";
+
+/// Cubes whose checks read all the rows, as their backend can't `sample`.
+const SAMPLE_SKIPPED_HEADER: &str = "X-Tesseract-Sample-Skipped";
+
+
+/// Time taken by a check, passed or failed.
+#[derive(Debug, Clone, Serialize)]
+struct CheckTiming {
+    cube: String,
+    #[serde(rename = "type")]
+    check: &'static str,
+    ms: u64,
 }
 
+/// Body of a successful report.
+#[derive(Debug, Serialize)]
+struct DiagnosisSuccess<'a> {
+    message: &'static str,
+    checks: &'a [CheckTiming],
+    sample_skipped: &'a [String],
+}
+
+
+/// Failed checks, and the time taken by each check.
+type Checks = (Vec<DiagnosisIssue>, Vec<CheckTiming>);
+
+/// Checks which haven't run yet. Failed queries are failed checks, so these don't error.
+type ChecksFuture = Box<dyn Future<Item=Checks, Error=Error>>;
+
 
-fn diagnose_cube(req: &HttpRequest<AppState>, cube: &Cube) -> (Vec<String>, Vec<String>) {
-    let mut error_types: Vec<String> = vec![];
-    let mut error_messages: Vec<String> = vec![];
+/// The checks of a cube, run one after the other.
+fn diagnose_cube(
+    req: &HttpRequest<AppState>,
+    cube: &Cube,
+    opts: &DiagnosisQueryOpt,
+    ) -> ChecksFuture
+{
+    let mut checks = vec![];
+    let fact_table = cube.table.full_name();
+    let backend = req.state().cube_backend(cube).clone();
+    let dialect = backend.dialect();
 
     for dimension in &cube.dimensions {
         for hierarchy in &dimension.hierarchies {
-            let last_level: &Level = &hierarchy.levels[hierarchy.levels.len() - 1];
-
             if let Some(ref foreign_key) = dimension.foreign_key {
+                // TODO: Deal with the case where there is an inline table.
                 if let Some(ref dimension_table) = hierarchy.table {
+                    let dimension_table = dimension_table.full_name();
+                    let primary_key = &hierarchy.primary_key;
+
                     // Check for `MissingDimensionIDs`
-                    // TODO: Deal with the case where there is an inline table.
-                    let sql_str: String = format!(
-                        "SELECT DISTINCT {} FROM {} WHERE {} NOT IN (SELECT {} FROM {})",
-                        foreign_key,
-                        cube.table.name,
-                        foreign_key,
-                        last_level.key_column,
-                        dimension_table.name,
+                    let missing_condition = format!(
+                        "FROM {} WHERE {} NOT IN (SELECT {} FROM {})",
+                        check_rows(&*dialect, &fact_table, &[foreign_key.as_str()], opts, Some(cube)),
+                        foreign_key, primary_key, dimension_table,
+                    );
+                    let sql_str = format!("SELECT count(DISTINCT {}) {}", foreign_key, missing_condition);
+                    let sample_sql_str = format!(
+                        "SELECT DISTINCT {} {} LIMIT {}",
+                        foreign_key, missing_condition, SAMPLE_SIZE,
                     );
 
-                    match get_res_df(&req, sql_str) {
-                        Ok(res_df) => {
-                            match res_df.columns.get(0) {
-                                Some(column) => {
-                                    // This is used here as a hack to extract the data inside
-                                    // the enum as a vector instead of using a match on every
-                                    // single data type supported.
-                                    let column_data = column.stringify_column_data();
-
-                                    if column_data.len() > 0 {
-                                        error_types.push("MissingDimensionIDs".to_string());
-                                        error_messages.push(
-                                            format!(
-                                                "The following IDs for [{}].[{}].[{}] are not present in its dimension table: {}.",
-                                                dimension.name,
-                                                hierarchy.name,
-                                                last_level.name,
-                                                column_data.join(", ")
-                                            )
-                                        );
-                                    }
-                                },
-                                None => ()
-                            }
-                        },
-                        Err(_) => ()
-                    }
+                    let sample_backend = backend.clone();
+                    let dimension_name = dimension.name.clone();
+                    let hierarchy_name = hierarchy.name.clone();
+                    let table_name = dimension_table.clone();
+
+                    checks.push(run_check(backend.clone(), &cube.name, "MissingDimensionIDs", sql_str, move |df| {
+                        let count = df_count(&df);
+                        let sample: Box<dyn Future<Item=Vec<String>, Error=Error>> = if count > 0 {
+                            // the sample is only for the message
+                            Box::new(query_df(&sample_backend, sample_sql_str)
+                                .map(|df| df.columns.get(0).map(|col| col.stringify_column_data()).unwrap_or_default())
+                                .or_else(|_| Ok::<_, Error>(vec![])))
+                        } else {
+                            Box::new(future::ok(vec![]))
+                        };
+
+                        sample.map(move |sample| {
+                            vec![(
+                                count,
+                                format!(
+                                    "{} IDs for [{}].[{}] are not present in its dimension table {}, e.g.: {}.",
+                                    count,
+                                    dimension_name,
+                                    hierarchy_name,
+                                    table_name,
+                                    sample.join(", "),
+                                ),
+                            )]
+                        })
+                    }));
 
                     // Check for `NonUniqueDimensionIDs`
-                    // TODO: Deal with the case where there is an inline table.
-                    let sql_str: String = format!(
-                        "SELECT {} FROM (select {}, count(*) as unique_count FROM {} GROUP BY {}) where unique_count > 1",
-                        last_level.key_column,
-                        last_level.key_column,
-                        dimension_table.name,
-                        last_level.key_column,
+                    let sql_str = format!(
+                        "SELECT count(*) FROM (SELECT {} FROM {} GROUP BY {} HAVING count(*) > 1) AS duplicates",
+                        primary_key,
+                        check_rows(&*dialect, &dimension_table, &[primary_key.as_str()], opts, None),
+                        primary_key,
                     );
 
-                    match get_res_df(&req, sql_str) {
-                        Ok(res_df) => {
-                            match res_df.columns.get(0) {
-                                Some(column) => {
-                                    let column_data = column.stringify_column_data();
-
-                                    if column_data.len() > 0 {
-                                        error_types.push("NonUniqueDimensionIDs".to_string());
-                                        error_messages.push(
-                                            format!(
-                                                "There are duplicate entries for the following IDs in the {} dimension table: {}.",
-                                                dimension_table.name,
-                                                column_data.join(", ")
-                                            )
-                                        );
-                                    }
-                                },
-                                None => ()
-                            }
-                        },
-                        Err(_) => ()
-                    }
+                    checks.push(run_check(backend.clone(), &cube.name, "NonUniqueDimensionIDs", sql_str, move |df| {
+                        let count = df_count(&df);
+                        Ok::<_, Error>(vec![(
+                            count,
+                            format!(
+                                "There are duplicate entries for {} IDs in the {} dimension table.",
+                                count,
+                                dimension_table,
+                            ),
+                        )])
+                    }));
                 }
             }
         }
     }
 
-    (error_types, error_messages)
+    // Check for `NullMeasureValues`, with one query for all measures
+    if !cube.measures.is_empty() {
+        let null_counts: Vec<_> = cube.measures.iter()
+            .map(|measure| format!("count(*) - count({})", measure.column))
+            .collect();
+        let mut columns: Vec<&str> = cube.measures.iter().map(|measure| measure.column.as_str()).collect();
+        columns.sort();
+        columns.dedup();
+        let sql_str = format!(
+            "SELECT {} FROM {}",
+            null_counts.join(", "),
+            check_rows(&*dialect, &fact_table, &columns, opts, Some(cube)),
+        );
+
+        let measure_names: Vec<String> = cube.measures.iter().map(|measure| measure.name.clone()).collect();
+
+        checks.push(run_check(backend.clone(), &cube.name, "NullMeasureValues", sql_str, move |df| {
+            let issues = measure_names.iter().zip(&df.columns)
+                .map(|(measure_name, column)| {
+                    let count = first_count(column);
+                    (count, format!("Measure {} has {} rows with null values.", measure_name, count))
+                })
+                .collect();
+            Ok::<_, Error>(issues)
+        }));
+    }
+
+    // Check for `MissingLocalTables`, on ClickHouse clusters
+    if let Some(ref distributed) = cube.distributed {
+        if let Some(ref cluster) = distributed.cluster {
+            let cluster = cluster.replace('\'', "''");
+            let local_name = distributed.local_name.rsplit('.').next()
+                .unwrap_or(&distributed.local_name)
+                .replace('\'', "''");
+
+            let sql_str = format!(
+                "SELECT (SELECT count(*) FROM system.clusters WHERE cluster = '{}') - \
+                (SELECT count(*) FROM clusterAllReplicas('{}', system.tables) WHERE name = '{}')",
+                cluster, cluster, local_name,
+            );
+
+            let distributed_local_name = distributed.local_name.clone();
+
+            checks.push(run_check(backend.clone(), &cube.name, "MissingLocalTables", sql_str, move |df| {
+                let count = df_count(&df);
+                Ok::<_, Error>(vec![(
+                    count,
+                    format!(
+                        "{} replicas of cluster {} have no local table {}.",
+                        count,
+                        cluster,
+                        distributed_local_name,
+                    ),
+                )])
+            }));
+        }
+    }
+
+    run_in_order(checks)
+}
+
+
+/// Runs the checks one at a time, so that a diagnosis doesn't load the database with all of
+/// its queries at once.
+fn run_in_order(checks: Vec<ChecksFuture>) -> ChecksFuture {
+    Box::new(
+        stream::iter_ok::<_, Error>(checks)
+            .and_then(|checks| checks)
+            .fold((vec![], vec![]), |(mut issues, mut timings), (check_issues, check_timings)| {
+                issues.extend(check_issues);
+                timings.extend(check_timings);
+                Ok::<_, Error>((issues, timings))
+            })
+    )
+}
+
+
+/// A check, which runs `sql_str` and makes the `(count, message)` of its issues from the
+/// results with `to_issues`; issues with a count of 0 are passed checks. If the check can't
+/// be run, that's an issue too, so that a broken check isn't reported as a success.
+fn run_check<F, R>(
+    backend: Box<dyn Backend + Sync + Send>,
+    cube_name: &str,
+    check: &'static str,
+    sql_str: String,
+    to_issues: F,
+    ) -> ChecksFuture
+    where
+        F: FnOnce(DataFrame) -> R + 'static,
+        R: IntoFuture<Item=Vec<(u64, String)>, Error=Error> + 'static,
+{
+    let cube_name = cube_name.to_owned();
+
+    Box::new(future::lazy(move || {
+        let start = Instant::now();
+
+        query_df(&backend, sql_str)
+            .and_then(to_issues)
+            .then(move |res| {
+                let ms = start.elapsed().as_millis() as u64;
+                let timing = CheckTiming { cube: cube_name.clone(), check, ms };
+
+                let issues = match res {
+                    Ok(issues) => {
+                        issues.into_iter()
+                            .filter(|(count, _)| *count > 0)
+                            .map(|(count, message)| DiagnosisIssue { cube: cube_name.clone(), check, count, ms, message })
+                            .collect()
+                    },
+                    Err(err) => {
+                        vec![DiagnosisIssue {
+                            cube: cube_name,
+                            check,
+                            count: 0,
+                            ms,
+                            message: format!("The check could not be run: {}", err),
+                        }]
+                    },
+                };
+
+                Ok::<_, Error>((issues, vec![timing]))
+            })
+    }))
+}
+
+
+/// The rows of `table` a check reads: the table itself, or a subquery of its `columns`
+/// reading part of it: with `sample` (on fact tables, for dialects which can sample) or
+/// `check_rows`, and, on the fact table of a `fact_cube` with a `sql_where`, only the rows
+/// of the cube.
+fn check_rows(
+    dialect: &dyn SqlDialect,
+    table: &str,
+    columns: &[&str],
+    opts: &DiagnosisQueryOpt,
+    fact_cube: Option<&Cube>,
+    ) -> String
+{
+    let sample = opts.sample.filter(|_| fact_cube.is_some() && dialect.capabilities().sampling);
+    let sql_where = fact_cube.and_then(|cube| cube.sql_where.as_ref());
+    if sample.is_none() && opts.check_rows.is_none() && sql_where.is_none() {
+        return table.to_owned();
+    }
+
+    let table = match sample {
+        Some(sample) => dialect.sample_table_sql(table, sample),
+        None => table.to_owned(),
+    };
+    let filter = match sql_where {
+        Some(sql_where) => format!(" WHERE ({})", sql_where),
+        None => "".to_owned(),
+    };
+    let limit = match opts.check_rows {
+        Some(rows) => format!(" LIMIT {}", rows),
+        None => "".to_owned(),
+    };
+
+    format!("(SELECT {} FROM {}{}{}) AS check_rows", columns.join(", "), table, filter, limit)
+}
+
+
+/// `Server-Timing` header of the time taken by each check, summed over dimensions and cubes,
+/// e.g. `MissingDimensionIDs;dur=1520, NullMeasureValues;dur=310`.
+fn server_timing(timings: &[CheckTiming]) -> String {
+    let mut totals: Vec<(&'static str, u64)> = vec![];
+
+    for timing in timings {
+        match totals.iter_mut().find(|(name, _)| *name == timing.check) {
+            Some((_, total)) => *total += timing.ms,
+            None => totals.push((timing.check, timing.ms)),
+        }
+    }
+
+    totals.iter()
+        .map(|(check, total)| format!("{};dur={}", check, total))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 
 fn format_diagnosis_response(
-        error_types: Vec<String>,
-        error_messages: Vec<String>,
+        issues: Vec<DiagnosisIssue>,
+        timings: &[CheckTiming],
+        sample_skipped: &[String],
         format: FormatType,
-        error_cubes: Option<Vec<String>>
-) -> ActixResult<HttpResponse> {
-    if error_messages.len() == 0 {
-        Ok(HttpResponse::Ok().json("Success.".to_string()))
+        with_cubes: bool,
+) -> HttpResponse {
+    let server_timing = server_timing(timings);
+    let sample_skipped_header = sample_skipped.join(", ");
+
+    if issues.is_empty() {
+        let mut response = HttpResponse::Ok();
+        response.header(SERVER_TIMING_HEADER, server_timing);
+        if !sample_skipped.is_empty() {
+            response.header(SAMPLE_SKIPPED_HEADER, sample_skipped_header);
+        }
+
+        response.json(DiagnosisSuccess { message: "Success.", checks: timings, sample_skipped })
     } else {
-        let headers = if error_cubes.is_some() {
-            vec!["cube".to_string(), "type".to_string(), "message".to_string()]
-        } else {
-            vec!["type".to_string(), "message".to_string()]
-        };
-
-        let df = match error_cubes {
-            Some(error_cubes) => {
-                DataFrame { columns: vec![
-                    Column {
-                        name: "cube".to_string(),
-                        column_data: ColumnData::Text(error_cubes)
-                    },
-                    Column {
-                        name: "type".to_string(),
-                        column_data: ColumnData::Text(error_types)
-                    },
-                    Column {
-                        name: "message".to_string(),
-                        column_data: ColumnData::Text(error_messages)
-                    }
-                ] }
-            },
-            None => {
-                DataFrame { columns: vec![
-                    Column {
-                        name: "type".to_string(),
-                        column_data: ColumnData::Text(error_types)
-                    },
-                    Column {
-                        name: "message".to_string(),
-                        column_data: ColumnData::Text(error_messages)
-                    }
-                ] }
-            }
-        };
+        let mut columns = vec![];
+
+        if with_cubes {
+            columns.push(Column {
+                name: "cube".to_string(),
+                column_data: ColumnData::Text(issues.iter().map(|i| i.cube.clone()).collect()),
+            });
+        }
+
+        columns.push(Column {
+            name: "type".to_string(),
+            column_data: ColumnData::Text(issues.iter().map(|i| i.check.to_owned()).collect()),
+        });
+        columns.push(Column {
+            name: "count".to_string(),
+            column_data: ColumnData::UInt64(issues.iter().map(|i| i.count).collect()),
+        });
+        columns.push(Column {
+            name: "ms".to_string(),
+            column_data: ColumnData::UInt64(issues.iter().map(|i| i.ms).collect()),
+        });
+        columns.push(Column {
+            name: "message".to_string(),
+            column_data: ColumnData::Text(issues.iter().map(|i| i.message.clone()).collect()),
+        });
+
+        let headers: Vec<String> = columns.iter().map(|col| col.name.clone()).collect();
+        let df = DataFrame { columns };
 
         let content_type = format_to_content_type(&format);
 
-        match format_records(&headers, df, format, None, true) {
+        match format_records(&headers, df, format, None, None, true) {
             Ok(res) => {
-                Ok(HttpResponse::ExpectationFailed()
+                let mut response = HttpResponse::ExpectationFailed();
+                response
                     .set(content_type)
-                    .body(res))
+                    .header(SERVER_TIMING_HEADER, server_timing);
+                if !sample_skipped.is_empty() {
+                    response.header(SAMPLE_SKIPPED_HEADER, sample_skipped_header);
+                }
+
+                response.body(res)
             },
-            Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+            Err(err) => HttpResponse::NotFound().json(err.to_string()),
         }
     }
 }
 
 
-fn get_res_df(req: &HttpRequest<AppState>, sql_str: String) -> Result<DataFrame, Error> {
-    req.state().backend
-        .exec_sql(sql_str)
-        .wait()
-        .and_then(move |df| {
-            Ok(df)
+/// Result of a `count` query.
+fn df_count(df: &DataFrame) -> u64 {
+    df.columns.get(0).map(first_count).unwrap_or(0)
+}
+
+
+fn first_count(column: &Column) -> u64 {
+    column.stringify_column_data().get(0)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0)
+}
+
+
+/// Failed queries are logged.
+fn query_df(backend: &Box<dyn Backend + Sync + Send>, sql_str: String) -> impl Future<Item=DataFrame, Error=Error> {
+    backend
+        .exec_sql(sql_str.clone())
+        .map_err(move |err| {
+            warn!("Diagnosis query failed: {}, sql: {}", err, sql_str);
+            err
         })
 }
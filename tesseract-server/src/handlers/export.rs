@@ -0,0 +1,258 @@
+use actix_web::{
+    fs::NamedFile,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+    Responder,
+    Result as ActixResult,
+};
+use futures::future::{self, Future};
+use lazy_static::lazy_static;
+use log::*;
+use serde_qs as qs;
+use std::convert::TryInto;
+use tesseract_core::format::FormatType;
+use tesseract_core::{Query as TsQuery, ShowColumns};
+
+use crate::api_keys::ApiKeyMeter;
+use crate::app::AppState;
+use crate::export::{ExportJob, ExportJobs, ExportQuery, ExportStatus};
+use super::aggregate::{AggregateQueryOpt, check_export};
+use super::util::{
+    boxed_error_http_response, verify_authorization, format_to_content_type, generate_source_data, validate_members,
+    validate_query_limits, apply_max_rows, negotiate_format, PostProcess,
+};
+
+
+/// Queues an export when a format is not specified.
+/// Default format is CSV.
+pub fn export_default_handler(
+    (req, cube): (HttpRequest<AppState>, Path<String>)
+    ) -> ActixResult<HttpResponse>
+{
+    let format = match negotiate_format(&req, "csv") {
+        Ok(format) => format,
+        Err(res) => return Ok(res),
+    };
+    let cube_format = (cube.into_inner(), format);
+    do_export(req, cube_format)
+}
+
+
+/// Queues an export when a format is specified.
+pub fn export_handler(
+    (req, cube_format): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> ActixResult<HttpResponse>
+{
+    do_export(req, cube_format.into_inner())
+}
+
+
+/// Translates an aggregate query to sql, and queues it as an export job.
+/// Responds with the job, whose status can then be polled at `/jobs/{id}`.
+///
+/// `pivot`, `totals`, `time_fill`, `locale`, `sample` and `preview` are not supported, and are
+/// rejected rather than ignored. Query limits and `TESSERACT_MAX_ROWS` apply as for aggregates.
+pub fn do_export(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    ) -> ActixResult<HttpResponse>
+{
+    let (cube, format_str) = cube_format;
+
+    let export_jobs = match export_jobs(&req) {
+        Ok(export_jobs) => export_jobs,
+        Err(res) => return Ok(res),
+    };
+
+    let schema = req.state().schema.read().unwrap().clone();
+    let cube_obj = match schema.get_cube_by_name(&cube) {
+        Ok(cube_obj) => cube_obj.clone(),
+        Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+    };
+
+    if let Err(err) = verify_authorization(&req, cube_obj.min_auth_level) {
+        return Ok(err);
+    }
+
+    let format = match format_str.parse::<FormatType>() {
+        Ok(format) => format,
+        Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+    };
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let agg_query = match QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(req.query_string()) {
+        Ok(agg_query) => agg_query,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+    if let Err(err) = check_export(&agg_query) {
+        return Ok(HttpResponse::BadRequest().json(err.to_string()));
+    }
+
+    let columns = match agg_query.shared().columns() {
+        Ok(columns) => columns,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+    let ts_query: Result<TsQuery, _> = agg_query.try_into();
+    let mut ts_query = match ts_query {
+        Ok(ts_query) => ts_query,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+
+    // sql injection mitigation on query:
+    // - Check that cut members exist in members cache
+    {
+        let cache = req.state().cache.read().unwrap();
+        let cube_cache = match cache.find_cube_info(&cube) {
+            Some(cube_cache) => cube_cache,
+            None => return Ok(HttpResponse::NotFound().json(format!("Cube {} not found", cube))),
+        };
+        if let Err(err) = validate_members(&ts_query.cuts, &cube_cache) {
+            return Ok(HttpResponse::NotFound().json(err.to_string()));
+        }
+        if let Err(err) = validate_query_limits(&ts_query, &cube_cache, &req.state().env_vars.query_limits) {
+            return Ok(HttpResponse::BadRequest().json(err.to_string()));
+        }
+    }
+
+    let max_rows = apply_max_rows(&mut ts_query, &req.state().env_vars.query_limits);
+
+    let (mut query_ir, headers) = match schema.sql_query(&cube, &ts_query, None) {
+        Ok(query_ir_headers) => query_ir_headers,
+        Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+    };
+
+    let backend = req.state().cube_backend(&cube_obj).clone();
+    let (steps, sql_previous_year) = PostProcess::take(&mut query_ir, &*backend, req.state().env_vars.dataframe_growth, max_rows);
+    if let Err(err) = backend.capabilities().check(&query_ir) {
+        return Ok(HttpResponse::UnprocessableEntity().json(err.to_string()));
+    }
+    let result_order = query_ir.result_order();
+    let sql = backend.generate_sql(query_ir);
+    let sql = match sql_previous_year {
+        Some(ref columns) => backend.dialect()
+            .previous_year_sql(&sql, columns, &result_order, max_rows.map(|max_rows| max_rows.saturating_add(1))),
+        None => sql,
+    };
+
+    let job = match export_jobs.create(&cube, &format_str, cube_obj.min_auth_level) {
+        Some(job) => job,
+        None => {
+            return Ok(HttpResponse::ServiceUnavailable()
+                .header("Retry-After", "60")
+                .json("Too many export jobs are running, please try again later".to_string()));
+        },
+    };
+
+    info!("Export job {} queued, cube: {}, format: {:?}", job.id, cube, format);
+    info!("Sql query ({} dialect): {}", backend.dialect().name(), sql);
+
+    let export_query = ExportQuery {
+        sql,
+        headers,
+        format,
+        source_data: Some(generate_source_data(&cube_obj)),
+        steps,
+        show_labels: ts_query.show == ShowColumns::Labels,
+        columns,
+    };
+
+    export_jobs.run(&job, backend, export_query, ApiKeyMeter::from_request(&req), req.state().debug);
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+
+/// Reports the status of an export job, with a freshly presigned URL when it was
+/// delivered to S3.
+pub fn job_handler(
+    (req, id): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let (export_jobs, mut job) = match authorized_job(&req, &id) {
+        Ok(export_jobs_job) => export_jobs_job,
+        Err(res) => return boxed_error_http_response(res),
+    };
+
+    match export_jobs.presigned_url(&job) {
+        Some(url) => {
+            Box::new(
+                url
+                    .map(move |url| {
+                        job.url = Some(url);
+                        HttpResponse::Ok().json(job)
+                    })
+                    .or_else(move |err| Ok(presign_error(&id, err)))
+            )
+        },
+        None => boxed_error_http_response(HttpResponse::Ok().json(job)),
+    }
+}
+
+
+/// Serves the file of a completed export job, or redirects to a freshly presigned URL
+/// when it was delivered to S3.
+pub fn job_file_handler(
+    (req, id): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let (export_jobs, job) = match authorized_job(&req, &id) {
+        Ok(export_jobs_job) => export_jobs_job,
+        Err(res) => return boxed_error_http_response(res),
+    };
+
+    if job.status != ExportStatus::Completed {
+        return boxed_error_http_response(
+            HttpResponse::Conflict().json(format!("Job {} is not completed", id))
+        );
+    }
+
+    // delivered to S3, so the file isn't served from here
+    if let Some(url) = export_jobs.presigned_url(&job) {
+        return Box::new(
+            url
+                .map(|url| HttpResponse::Found().header("Location", url.as_str()).finish())
+                .or_else(move |err| Ok(presign_error(&id, err)))
+        );
+    }
+
+    Box::new(future::result(serve_job_file(&req, &export_jobs, &job)))
+}
+
+
+fn export_jobs(req: &HttpRequest<AppState>) -> Result<ExportJobs, HttpResponse> {
+    req.state().export_jobs.clone()
+        .ok_or_else(|| HttpResponse::NotFound().json("Export jobs are not enabled".to_string()))
+}
+
+/// The job, if it exists and the request is authorized for its cube.
+fn authorized_job(req: &HttpRequest<AppState>, id: &str) -> Result<(ExportJobs, ExportJob), HttpResponse> {
+    let export_jobs = export_jobs(req)?;
+
+    let job = export_jobs.get(id)
+        .ok_or_else(|| HttpResponse::NotFound().json(format!("Job {} not found", id)))?;
+
+    verify_authorization(req, job.min_auth_level)?;
+
+    Ok((export_jobs, job))
+}
+
+fn serve_job_file(req: &HttpRequest<AppState>, export_jobs: &ExportJobs, job: &ExportJob) -> ActixResult<HttpResponse> {
+    let content_type = match job.format.parse::<FormatType>() {
+        Ok(format) => format_to_content_type(&format),
+        Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+    };
+
+    let file = NamedFile::open(export_jobs.file_path(job))?
+        .set_content_type(content_type.0);
+
+    Ok(file.respond_to(req)?)
+}
+
+fn presign_error(id: &str, err: failure::Error) -> HttpResponse {
+    error!("Could not presign the URL of export job {}: {}", id, err);
+    HttpResponse::InternalServerError().json(format!("Could not get the URL of job {}", id))
+}
@@ -4,22 +4,45 @@ use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
 
+use std::thread;
+
 use actix_web::{
+    Form,
     HttpRequest,
     HttpResponse,
     Result as ActixResult,
 };
 
-use crate::app::{AppState, SchemaSource};
+use tesseract_core::CubeHasUniqueLevelsAndProperties;
+
+use crate::app::{AppState, SchemaSource, SchemaVersion};
+use crate::db_config::validate_data_sources;
+use crate::logic_layer::{
+    populate_cube_inline_tables, populate_inline_tables, read_config, refresh_cube_cache,
+    set_cached_tables,
+};
 use crate::schema_config;
+use super::util::check_secret;
 
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct FlushQueryOpt {
     pub secret: String,
+    pub cube: Option<String>,
+}
+
+/// Form body of the flush request.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FlushBodyOpt {
+    pub cube: Option<String>,
 }
 
-pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+/// Flushes internal state. With a `cube` (in the form body or query), only that cube is
+/// reloaded from the schema file and its cache refreshed, keeping the rest warm.
+pub fn flush_handler(
+    (req, body): (HttpRequest<AppState>, Option<Form<FlushBodyOpt>>)
+    ) -> ActixResult<HttpResponse>
+{
     let query = req.query_string();
 
     lazy_static!{
@@ -34,49 +57,244 @@ pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
         },
     };
 
-    let db_secret = match &req.state().env_vars.flush_secret {
-        Some(db_secret) => db_secret,
-        None => { return Ok(HttpResponse::Unauthorized().finish()); }
+    if let Err(res) = check_secret(&req, &query.secret) {
+        return Ok(res);
+    }
+
+    let cube = body.and_then(|body| body.into_inner().cube)
+        .or(query.cube);
+
+    if let Some(cube) = cube {
+        return flush_cube(&req, cube);
+    }
+
+    info!("Flush internal state");
+
+    // Read schema again
+    // NOTE: This logic will change once we start supporting remote schemas
+    let schema_path = match &req.state().env_vars.schema_source {
+        SchemaSource::LocalSchema { ref filepath } => filepath,
+        SchemaSource::RemoteSchema { ref endpoint } => endpoint,
+    };
+
+    let schema = match schema_config::read_schema(&schema_path) {
+        Ok(val) => val,
+        Err(err) => {
+            error!("{}", err);
+            return Ok(HttpResponse::InternalServerError().finish());
+        },
     };
 
-    if query.secret == *db_secret {
-        info!("Flush internal state");
-
-        // Read schema again
-        // NOTE: This logic will change once we start supporting remote schemas
-        let schema_path = match &req.state().env_vars.schema_source {
-            SchemaSource::LocalSchema { ref filepath } => filepath,
-            SchemaSource::RemoteSchema { ref endpoint } => endpoint,
-        };
-
-        let schema = match schema_config::read_schema(&schema_path) {
-            Ok(val) => val,
-            Err(err) => {
-                error!("{}", err);
-                return Ok(HttpResponse::InternalServerError().finish());
-            },
-        };
-
-        // Update shared schema
-        let mut w = req.state().schema.write().unwrap();
-        *w = schema.clone();
-
-        // TODO: Uncomment when issue with SystemRunner is solved
-//        // Re-populate cache with the new schema
-//        let cache = match populate_cache(schema, req.state().backend.clone()) {
-//            Ok(cache) => cache,
-//            Err(err) => {
-//                error!("{}", err);
-//                return Ok(HttpResponse::InternalServerError().finish());
-//            },
-//        };
+    if let Err(err) = validate_data_sources(&schema, &req.state().data_sources) {
+        error!("{}", err);
+        return Ok(HttpResponse::InternalServerError().finish());
+    }
+
+    // Update shared schema
+    *req.state().schema_version.write().unwrap() = SchemaVersion::new(&schema);
+    *req.state().schema.write().unwrap() = schema.clone();
+
+    // TODO: Uncomment when issue with SystemRunner is solved
+//    // Re-populate cache with the new schema
+//    let cache = match populate_cache(schema, req.state().backend.clone()) {
+//        Ok(cache) => cache,
+//        Err(err) => {
+//            error!("{}", err);
+//            return Ok(HttpResponse::InternalServerError().finish());
+//        },
+//    };
 //
-//        // Update shared cache
-//        let mut w = req.state().cache.write().unwrap();
-//        *w = cache;
+//    // Update shared cache
+//    let mut w = req.state().cache.write().unwrap();
+//    *w = cache;
+
+    // Dimension tables are inlined again in the background, as for a single cube; until
+    // then, the reloaded cubes join them
+    let backend = req.state().backend.clone();
+    let data_sources = req.state().data_sources.clone();
+    let shared_schema = req.state().schema.clone();
+    let subscriptions = req.state().subscriptions.clone();
+
+    thread::spawn(move || {
+        let mut sys = actix::System::new("tesseract-flush");
 
-        Ok(HttpResponse::Ok().finish())
-    } else {
-        Ok(HttpResponse::Unauthorized().finish())
+        let mut schema = schema;
+        populate_inline_tables(&mut schema, &backend, &data_sources, &mut sys);
+
+        for cube in &schema.cubes {
+            if let Some(c) = shared_schema.write().unwrap().cubes.iter_mut().find(|c| c.name == cube.name) {
+                set_cached_tables(c, cube);
+            }
+        }
+
+        subscriptions.refresh(None);
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+
+/// Reloads a single cube from the schema file, and refreshes its cache in the background.
+fn flush_cube(req: &HttpRequest<AppState>, cube_name: String) -> ActixResult<HttpResponse> {
+    info!("Flush cube {}", cube_name);
+
+    let schema_path = match &req.state().env_vars.schema_source {
+        SchemaSource::LocalSchema { ref filepath } => filepath,
+        SchemaSource::RemoteSchema { ref endpoint } => endpoint,
+    };
+
+    let new_schema = match schema_config::read_schema(&schema_path) {
+        Ok(val) => val,
+        Err(err) => {
+            error!("{}", err);
+            return Ok(HttpResponse::InternalServerError().finish());
+        },
+    };
+
+    let cube = match new_schema.cubes.into_iter().find(|cube| cube.name == cube_name) {
+        Some(cube) => cube,
+        None => return Ok(HttpResponse::NotFound().json(format!("Cube {} not found", cube_name))),
+    };
+
+    if let Some(ref data_source) = cube.data_source {
+        if !req.state().data_sources.contains_key(data_source) {
+            error!("Flush: cube {} has unknown data source {}", cube_name, data_source);
+            return Ok(HttpResponse::InternalServerError().finish());
+        }
     }
+
+    // Update only this cube in the shared schema
+    {
+        let mut schema = req.state().schema.write().unwrap();
+
+        match schema.cubes.iter_mut().find(|c| c.name == cube_name) {
+            Some(c) => *c = cube.clone(),
+            None => schema.cubes.push(cube.clone()),
+        }
+
+        *req.state().schema_version.write().unwrap() = SchemaVersion::new(&schema);
+    }
+
+    // The cache is populated by blocking on backend queries, which can't be done from a
+    // handler, so it's refreshed in its own thread and system.
+    let ll_config = req.state().logic_layer_config.clone();
+    let cache = req.state().cache.clone();
+    let backend = req.state().cube_backend(&cube).clone();
+    let subscriptions = req.state().subscriptions.clone();
+    let schema = req.state().schema.clone();
+    let schema_version = req.state().schema_version.clone();
+
+    thread::spawn(move || {
+        let mut sys = actix::System::new("tesseract-flush");
+
+        // until its dimension tables are inlined again, the reloaded cube joins them
+        let mut cube = cube;
+        populate_cube_inline_tables(&mut cube, &backend, &mut sys);
+
+        {
+            let mut schema = schema.write().unwrap();
+            let inlined = schema.cubes.iter_mut()
+                .find(|c| c.name == cube_name)
+                .map(|c| set_cached_tables(c, &cube))
+                .unwrap_or(false);
+
+            // inlined tables change the sql, which cached responses don't show
+            if inlined {
+                *schema_version.write().unwrap() = SchemaVersion::new(&schema);
+            }
+        }
+
+        match refresh_cube_cache(cube, &ll_config, &cache, backend, &mut sys) {
+            Ok(()) => subscriptions.refresh(Some(&cube_name)),
+            Err(err) => error!("Flush: cache refresh for {} failed: {}", cube_name, err),
+        }
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+
+/// Reloads the logic layer config from its file, without restarting, and refreshes the
+/// cache of every cube in the background, since level and property aliases are cached.
+///
+/// Whether all levels and properties have unique names decides which logic layer routes are
+/// served, so a config that changes it is rejected; that needs a restart.
+pub fn flush_logic_layer_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let query = match QS_NON_STRICT.deserialize_str::<FlushQueryOpt>(req.query_string()) {
+        Ok(q) => q,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+
+    if let Err(res) = check_secret(&req, &query.secret) {
+        return Ok(res);
+    }
+
+    let (ll_config, config_path) = match (&req.state().logic_layer_config, &req.state().env_vars.logic_layer_config_path) {
+        (Some(ll_config), Some(config_path)) => (ll_config.clone(), config_path),
+        _ => return Ok(HttpResponse::NotFound().json("Logic layer is not enabled".to_string())),
+    };
+
+    info!("Flush logic layer config");
+
+    let config = match read_config(config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            return Ok(HttpResponse::InternalServerError().finish());
+        },
+    };
+
+    let schema = req.state().schema.read().unwrap().clone();
+
+    let has_unique_levels_properties = match config.has_unique_levels_properties(&schema) {
+        Ok(has_unique) => has_unique,
+        Err(err) => {
+            error!("{}", err);
+            return Ok(HttpResponse::InternalServerError().finish());
+        },
+    };
+
+    let was_unique = match req.state().has_unique_levels_properties {
+        CubeHasUniqueLevelsAndProperties::True => true,
+        CubeHasUniqueLevelsAndProperties::False { .. } => false,
+    };
+    let is_unique = match has_unique_levels_properties {
+        CubeHasUniqueLevelsAndProperties::True => true,
+        CubeHasUniqueLevelsAndProperties::False { .. } => false,
+    };
+    if was_unique != is_unique {
+        return Ok(HttpResponse::Conflict().json(
+            "The new config changes whether level and property names are unique, restart to apply it".to_string()
+        ));
+    }
+
+    *ll_config.write().unwrap() = config;
+    *req.state().schema_version.write().unwrap() = SchemaVersion::new(&schema);
+
+    let ll_config = Some(ll_config);
+    let cache = req.state().cache.clone();
+    let backends: Vec<_> = schema.cubes.iter()
+        .map(|cube| req.state().cube_backend(cube).clone())
+        .collect();
+    let subscriptions = req.state().subscriptions.clone();
+
+    thread::spawn(move || {
+        let mut sys = actix::System::new("tesseract-flush");
+
+        for (cube, backend) in schema.cubes.into_iter().zip(backends) {
+            let cube_name = cube.name.clone();
+
+            if let Err(err) = refresh_cube_cache(cube, &ll_config, &cache, backend, &mut sys) {
+                error!("Flush: cache refresh for {} failed: {}", cube_name, err);
+            }
+        }
+
+        subscriptions.refresh(None);
+    });
+
+    Ok(HttpResponse::Ok().finish())
 }
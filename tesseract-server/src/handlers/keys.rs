@@ -0,0 +1,41 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use serde_qs as qs;
+
+use crate::api_keys::current_day;
+use crate::app::AppState;
+use super::util::check_secret;
+
+
+#[derive(Debug, Deserialize)]
+pub struct KeysUsageQueryOpt {
+    pub secret: String,
+}
+
+/// Reports today's usage of each API key. Admin only; protected by the flush secret.
+pub fn keys_usage_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let query = req.query_string();
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let query = match QS_NON_STRICT.deserialize_str::<KeysUsageQueryOpt>(&query) {
+        Ok(q) => q,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+
+    if let Err(res) = check_secret(&req, &query.secret) {
+        return Ok(res);
+    }
+
+    match &req.state().api_keys {
+        Some(api_keys) => Ok(HttpResponse::Ok().json(api_keys.usage_report(current_day()))),
+        None => Ok(HttpResponse::NotFound().json("API keys are not enabled".to_string())),
+    }
+}
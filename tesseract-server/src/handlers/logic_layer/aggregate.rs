@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::str;
+use std::time::Instant;
 
 use actix_web::{AsyncResponder, FutureResponse, HttpRequest, HttpResponse, Path, Request};
 use failure::{Error, format_err, bail};
@@ -12,21 +13,33 @@ use serde_qs as qs;
 use serde_derive::Deserialize;
 use url::Url;
 
-use tesseract_core::names::{Cut, Drilldown, Property, Measure, LevelName, Mask};
-use tesseract_core::format::{format_records, FormatType};
+use tesseract_core::names::{Cut, Drilldown, Property, Measure, LevelName, Mask, split_quoted, unquote};
+use tesseract_core::format::{format_records, format_jsonrecords_with_schema, FormatLocale, FormatType};
 use tesseract_core::query::{FilterQuery, GrowthQuery, RcaQuery, TopQuery, RateQuery};
-use tesseract_core::{Query as TsQuery, MeaOrCalc, DataFrame, Column, ColumnData, is_same_columndata_type};
+use tesseract_core::{Query as TsQuery, MeaOrCalc, DataFrame, Column, ColumnData, ResultCalculation, ShowColumns, is_same_columndata_type};
+use tesseract_core::dialect::SqlDialect;
+use tesseract_core::query_ir::MemberType;
 use tesseract_core::schema::{Cube, DimensionType};
 
+use crate::api_keys::ApiKeyMeter;
 use crate::app::AppState;
 use crate::errors::ServerError;
-use crate::logic_layer::{LogicLayerConfig, CubeCache, Time};
+use crate::logic_layer::{
+    LogicLayerConfig, CubeCache, MemberLookups, Time, TimePrecision,
+    cube_not_found_message, not_found_message,
+};
+use crate::request_id::with_request_id;
 use super::super::util::{
     boxed_error_string, boxed_error_http_response,
-    verify_authorization, format_to_content_type, generate_source_data,
-    validate_members,
-    get_redis_cache_key, check_redis_cache, insert_into_redis_cache
+    verify_authorization, format_to_content_type, generate_source_data, negotiate_format,
+    validate_member, validate_members, validate_query_limits, check_sample,
+    get_redis_cache_key, check_redis_cache, insert_into_redis_cache,
+    get_etag, check_not_modified, apply_max_rows, truncate_rows, post_process, select_columns,
+    PostProcess,
+    TRUNCATED_HEADER, DATA_UPDATED_HEADER, SAMPLE_HEADER, UNITS_HEADER, units_header,
+    set_query_headers,
 };
+use super::super::aggregate::sample_scale_idxs;
 use crate::handlers::logic_layer::{query_geoservice, GeoserviceQuery};
 
 
@@ -55,7 +68,11 @@ pub fn logic_layer_default_handler(
     (req, _cube): (HttpRequest<AppState>, Path<()>)
 ) -> FutureResponse<HttpResponse>
 {
-    logic_layer_aggregation(req, "jsonrecords".to_owned())
+    let format = match negotiate_format(&req, "jsonrecords") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    logic_layer_aggregation(req, format)
 }
 
 
@@ -68,31 +85,34 @@ pub fn logic_layer_handler(
 }
 
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct LogicLayerQueryOpt {
-    pub cube: String,
-    pub drilldowns: Option<String>,
-    #[serde(flatten)]
-    pub cuts: Option<HashMap<String, String>>,
-    pub time: Option<String>,
-    measures: Option<String>,
-    properties: Option<String>,
-    filters: Option<String>,
-    parents: Option<bool>,
-    top: Option<String>,
-    top_where: Option<String>,
-    sort: Option<String>,
-    limit: Option<String>,
-    growth: Option<String>,
-    rca: Option<String>,
-    debug: Option<bool>,
-    exclude: Option<String>,
-    exclude_default_members: Option<bool>,
-    locale: Option<String>,
-    //    distinct: Option<bool>,
-    //    nonempty: Option<bool>,
-    sparse: Option<bool>,
-    rate: Option<String>,
+query_opt! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct LogicLayerQueryOpt {
+        pub cube: String,
+        pub drilldowns: Option<String>,
+        #[serde(flatten)]
+        pub cuts: Option<HashMap<String, String>>,
+        pub time: Option<String>,
+        measures: Option<String>,
+        properties: Option<String>,
+        filters: Option<String>,
+        #[serde(rename="where")]
+        row_filters: Option<String>,
+        parents: Option<String>,
+        top: Option<String>,
+        growth: Option<String>,
+        rca: Option<String>,
+        exclude: Option<String>,
+        locale: Option<String>,
+        format_locale: Option<String>,
+        //    nonempty: Option<bool>,
+        rate: Option<String>,
+        calculations: Option<String>,
+        // Only parsed so they're rejected, instead of being taken for cuts
+        pivot: Option<String>,
+        totals: Option<bool>,
+        time_fill: Option<String>,
+    }
 }
 
 
@@ -148,9 +168,10 @@ impl LogicLayerQueryOpt {
                         None => continue
                     };
 
-                    let level_ids: Vec<&str> = level_ids.split(",").collect();
+                    let level_ids: Vec<&str> = split_quoted(level_ids, ',')
+                        .unwrap_or_else(|_| level_ids.split(',').collect());
 
-                    let level_ids_set: HashSet<String> = level_ids.into_iter().map(|s| s.to_string()).collect();
+                    let level_ids_set: HashSet<String> = level_ids.into_iter().map(unquote).collect();
 
                     // Since we are filtering on IDs, we need to add an `ID` suffix here.
                     excludes.insert(
@@ -195,7 +216,19 @@ pub fn logic_layer_aggregation(
     format: String,
 ) -> FutureResponse<HttpResponse>
 {
-    let format = ok_or_404!(format.parse::<FormatType>());
+    run_logic_layer_aggregation(req, format, true)
+}
+
+
+/// With `lookup_members`, members of levels too large to cache which the cuts need are
+/// looked up first, and then the aggregation is run again without lookups.
+fn run_logic_layer_aggregation(
+    req: HttpRequest<AppState>,
+    format_str: String,
+    lookup_members: bool,
+) -> FutureResponse<HttpResponse>
+{
+    let format = ok_or_404!(format_str.parse::<FormatType>());
 
     info!("Format: {:?}", format);
 
@@ -215,6 +248,43 @@ pub fn logic_layer_aggregation(
     let agg_query_res = QS_NON_STRICT.deserialize_str::<LogicLayerQueryOpt>(query);
     let agg_query = ok_or_404!(agg_query_res);
 
+    if agg_query.pivot.is_some() || agg_query.totals.is_some() || agg_query.time_fill.is_some() {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("pivot, totals and time_fill are not supported by the logic layer".to_owned())
+        );
+    }
+
+    // number locale for CSV, separate from the captions locale
+    let format_locale = agg_query.format_locale.as_ref()
+        .map(|l| l.parse::<FormatLocale>())
+        .transpose();
+    let format_locale = ok_or_404!(format_locale);
+
+    let sample = agg_query.shared().sample;
+    let column_types = ok_or_404!(agg_query.shared().column_types(&format));
+    let conversions = ok_or_400!(agg_query.shared().conversions());
+    let columns = ok_or_400!(agg_query.shared().columns());
+    let show_labels = ok_or_404!(agg_query.shared().show()) == ShowColumns::Labels;
+    // growth on a sample is calculated on the scaled up measures, so not in the database
+    let dataframe_growth = sample.is_some() || agg_query.shared().dataframe_growth
+        .unwrap_or(req.state().env_vars.dataframe_growth);
+
+    // growth and share columns calculated on the results
+    let calculations = agg_query.calculations.as_ref()
+        .map(|calcs| {
+            calcs.split(',')
+                .map(|calc| calc.trim().parse::<ResultCalculation>())
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose();
+    let calculations = ok_or_400!(calculations);
+
+    if calculations.is_some() && (agg_query.growth.is_some() || agg_query.rca.is_some()) {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json("calculations can't be combined with growth or rca".to_owned())
+        );
+    }
+
     // Check to see if the logic layer config has a alias with the
     // provided cube name
     let cube_name = match logic_layer_config.clone() {
@@ -227,17 +297,27 @@ pub fn logic_layer_aggregation(
         None => agg_query.cube.clone()
     };
 
-    let cube = ok_or_404!(schema.get_cube_by_name(&cube_name));
+    let cube = match schema.get_cube_by_name(&cube_name) {
+        // Hidden cubes are only available through the logic layer in debug mode
+        Ok(cube) if debug || !cube.is_hidden() => cube,
+        _ => return boxed_error_string(cube_not_found_message(&agg_query.cube, &schema, &logic_layer_config, debug)),
+    };
 
     if let Err(err) = verify_authorization(&req, cube.min_auth_level) {
         return boxed_error_http_response(err);
     }
 
+    // Results change with the schema, the cube's data (as far as the cache knows) or the query
+    let (etag, last_modified) = get_etag(&req, &format!("data/{:?}?{}", format, query), Some(cube_name.as_str()));
+    if let Some(res) = check_not_modified(&req, &etag, &last_modified) {
+        return boxed_error_http_response(res);
+    }
+
     // Check if this query is already cached
     let redis_pool = req.state().redis_pool.clone();
     let redis_cache_key = get_redis_cache_key("logic-layer", &req, &cube_name, &format);
 
-    if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key) {
+    if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key, &cube_name) {
         return res;
     }
 
@@ -248,17 +328,56 @@ pub fn logic_layer_aggregation(
         None => return boxed_error_string("Unable to access cube cache".to_string())
     };
 
+    let last_updated = cube_cache.last_updated.clone();
+
     info!("Aggregate query: {:?}", agg_query);
 
     // Gets the Source Data
     let source_data = Some(generate_source_data(&cube));
 
     // Turn AggregateQueryOpt into TsQuery
+    let mut missing_lookups = vec![];
+    let dialect = req.state().cube_backend(&cube).dialect();
     let ts_queries = generate_ts_queries(
         agg_query.clone(), &cube, &cube_cache,
-        &logic_layer_config, &req.state().env_vars.geoservice_url
+        &logic_layer_config, &req.state().env_vars.geoservice_url,
+        &*dialect, &cache.member_lookups, &mut missing_lookups,
     );
-    let (ts_queries, header_map) = ok_or_404!(ts_queries);
+    let (mut ts_queries, header_map) = ok_or_404!(ts_queries);
+
+    // lookups were already run, so cuts still missing theirs would be dropped from the query
+    if !lookup_members && !missing_lookups.is_empty() {
+        return boxed_error_string("Could not look up the members of a cut; please try again".to_owned());
+    }
+
+    if lookup_members && !missing_lookups.is_empty() {
+        let member_lookups = cache.member_lookups.clone();
+        let backend = req.state().cube_backend(&cube).clone();
+
+        let lookups: Vec<_> = missing_lookups.into_iter()
+            .map(|sql| {
+                let member_lookups = member_lookups.clone();
+
+                backend.exec_sql(sql.clone())
+                    .map(move |df| {
+                        let members = df.columns.get(0)
+                            .map(|col| col.stringify_column_data())
+                            .unwrap_or_default();
+                        member_lookups.insert(sql, members);
+                    })
+            })
+            .collect();
+
+        let req = req.clone();
+
+        return Box::new(
+            join_all(lookups)
+                .then(move |res| match res {
+                    Ok(_) => run_logic_layer_aggregation(req, format_str, false),
+                    Err(err) => boxed_error_string(err.to_string()),
+                })
+        );
+    }
 
     if ts_queries.len() == 0 {
         return boxed_error_string("Unable to generate queries".to_string())
@@ -274,9 +393,21 @@ pub fn logic_layer_aggregation(
     let mut sql_strings: Vec<String> = vec![];
     let mut final_headers: Vec<String> = vec![];
 
-    for ts_query in &ts_queries {
+    let mut max_rows = None;
+    // same for every query, since they only differ in their cuts
+    let mut steps = PostProcess::default();
+    let mut calculation_columns = None;
+    let mut measure_headers = vec![];
+
+    for ts_query in &mut ts_queries {
+        ts_query.sample = sample;
+        ok_or_400!(check_sample(&ts_query));
+
         // SQL injection mitigation
         ok_or_404!(validate_members(&ts_query.cuts, &cube_cache));
+        ok_or_400!(validate_query_limits(&ts_query, &cube_cache, &req.state().env_vars.query_limits));
+
+        max_rows = apply_max_rows(ts_query, &req.state().env_vars.query_limits);
 
         debug!("Tesseract query: {:?}", ts_query);
 
@@ -285,15 +416,37 @@ pub fn logic_layer_aggregation(
             .schema.read().unwrap()
             .sql_query(&cube_name, &ts_query, Some(&unique_header_map));
 
-        let (query_ir, headers) = ok_or_404!(query_ir_headers);
+        let (mut query_ir, headers) = ok_or_404!(query_ir_headers);
 
         debug!("Query IR: {:?}", query_ir);
 
+        // measures are converted by the headers of the query, before the unique names
+        // substitution
+        measure_headers = headers.clone();
+
+        // the logic layer doesn't compare with the previous year, so it's never in the sql
+        steps = PostProcess::take(&mut query_ir, &**req.state().cube_backend(&cube), dataframe_growth, max_rows).0;
+
+        // sums and counts on a sample are scaled up to estimate the totals
+        if let Some(sample) = sample {
+            steps.sample_scale = Some((1.0 / sample, ok_or_404!(sample_scale_idxs(&cube, &ts_query, &headers))));
+        }
+        ok_or_422!(req.state().cube_backend(&cube).capabilities().check(&query_ir));
+
+        if calculations.is_some() {
+            let time_drill = ts_query.drilldowns.iter()
+                .position(|drill| {
+                    cube.dimensions.iter()
+                        .any(|dim| dim.name == drill.0.dimension && dim.dim_type == DimensionType::Time)
+                });
+            calculation_columns = Some(query_ir.calculation_columns(time_drill));
+        }
+
         let sql = req.state()
-            .backend
+            .cube_backend(&cube)
             .generate_sql(query_ir);
 
-        debug!("SQL query: {}", sql);
+        debug!("SQL query ({} dialect): {}", req.state().cube_backend(&cube).dialect().name(), sql);
 
         // Substitute header names (only need to do this once)
         if final_headers.len() == 0 {
@@ -317,22 +470,46 @@ pub fn logic_layer_aggregation(
 
     let exclude_map = agg_query.deserialize_exclude();
 
+    let api_key_meter = ApiKeyMeter::from_request(&req);
+
+    // drilldown levels and duration of the queries, to suggest pre-aggregations
+    let query_stats = req.state().query_stats.clone();
+    let stats_levels: Vec<LevelName> = ts_queries[0].drilldowns.iter().map(|d| d.0.clone()).collect();
+    let query_start = Instant::now();
+
     // Joins all the futures for each TsQuery
     let futs: JoinAll<Vec<Box<dyn Future<Item=DataFrame, Error=Error>>>> = join_all(sql_strings
             .iter()
             .map(|sql| {
                 req.state()
-                    .backend
+                    .cube_backend(&cube)
                     .exec_sql(sql.clone())
             })
             .collect()
         );
 
     // Process data received once all futures are resolved and return response
-    futs
+    with_request_id(&req, futs)
         .and_then(move |dfs| {
+            let query_time = query_start.elapsed();
+            query_stats.record(&cube_name, &stats_levels, query_time);
+
+            let mut dfs = match dfs.into_iter().map(|df| post_process(df, &steps)).collect::<Result<Vec<_>, _>>() {
+                Ok(dfs) => dfs,
+                Err(err) => return Ok(err.response()),
+            };
+
+            for df in &mut dfs {
+                df.convert_units(&measure_headers, &conversions)?;
+            }
+
             let mut final_columns: Vec<Column> = vec![];
 
+            // each query asks for one row over the limit, to tell if there were more
+            let query_truncated = max_rows
+                .map(|max_rows| dfs.iter().any(|df| df.len() as u64 > max_rows))
+                .unwrap_or(false);
+
             let num_cols = match dfs.get(0) {
                 Some(df) => df.columns.len(),
                 None => return Err(format_err!("No dataframes were returned."))
@@ -531,29 +708,71 @@ pub fn logic_layer_aggregation(
                 }
             }
 
-            let final_df = DataFrame { columns: final_columns };
+            let mut final_df = DataFrame { columns: final_columns };
+
+            // calculations like rankings need every row, so they go before truncating
+            let mut final_headers = final_headers;
+            if let (Some(calculations), Some(calculation_columns)) = (&calculations, &calculation_columns) {
+                if let Err(err) = final_df.add_calculations(&mut final_headers, calculation_columns, calculations) {
+                    return Ok(HttpResponse::BadRequest().json(err.to_string()));
+                }
+            }
+
+            let truncated = truncate_rows(&mut final_df, max_rows) || query_truncated;
+
+            if let Err(err) = select_columns(&mut final_df, &mut final_headers, show_labels, columns.as_ref().map(|c| c.as_slice())) {
+                return Ok(HttpResponse::BadRequest().json(err.to_string()));
+            }
+
+            if let Some(ref api_key_meter) = api_key_meter {
+                api_key_meter.record_rows(final_df.len());
+            }
 
             let content_type = format_to_content_type(&format);
+            let row_count = final_df.len();
 
-            match format_records(&final_headers, final_df, format, source_data, false) {
-                Ok(res) => {
-                    // Try to insert this result in the Redis cache, if available
-                    insert_into_redis_cache(&res, &redis_pool, &redis_cache_key);
+            let formatted = if column_types {
+                format_jsonrecords_with_schema(&final_headers, final_df, source_data)
+            } else {
+                format_records(&final_headers, final_df, format, format_locale.as_ref(), source_data, false)
+            };
 
-                    Ok(HttpResponse::Ok()
+            match formatted {
+                Ok(res) => {
+                    let mut response = HttpResponse::Ok();
+                    response
                         .set(content_type)
-                        .body(res))
+                        .header("ETag", etag)
+                        .header("Last-Modified", last_modified);
+                    set_query_headers(&mut response, &cube_name, row_count, query_time);
+
+                    if let Some(last_updated) = last_updated {
+                        response.header(DATA_UPDATED_HEADER, last_updated);
+                    }
+
+                    if let Some(sample) = sample {
+                        response.header(SAMPLE_HEADER, sample.to_string());
+                    }
+
+                    if !conversions.is_empty() {
+                        response.header(UNITS_HEADER, units_header(&conversions));
+                    }
+
+                    // Try to insert this result in the Redis cache, if available.
+                    // Truncated, sampled and converted results aren't cached, since the
+                    // cache drops the header.
+                    if truncated {
+                        response.header(TRUNCATED_HEADER, max_rows.unwrap_or(0).to_string());
+                    } else if sample.is_none() && conversions.is_empty() {
+                        insert_into_redis_cache(&res, row_count, query_time, &redis_pool, &redis_cache_key);
+                    }
+
+                    Ok(response.body(res))
                 },
                 Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
             }
         })
-        .map_err(move |e| {
-            if debug {
-                ServerError::Db { cause: e.to_string() }.into()
-            } else {
-                ServerError::Db { cause: "Internal Server Error 1010".to_owned() }.into()
-            }
-        })
+        .map_err(move |e| ServerError::from_query_error(&e, debug).into())
         .responder()
 }
 
@@ -565,12 +784,17 @@ pub fn generate_ts_queries(
         cube: &Cube,
         cube_cache: &CubeCache,
         ll_config: &Option<LogicLayerConfig>,
-        geoservice_url: &Option<Url>
+        geoservice_url: &Option<Url>,
+        dialect: &dyn SqlDialect,
+        member_lookups: &MemberLookups,
+        missing_lookups: &mut Vec<String>,
 ) -> Result<(Vec<TsQuery>, HashMap<String, String>), Error> {
 
     let level_map = &cube_cache.level_map;
     let property_map = &cube_cache.property_map;
 
+    let shared = agg_query_opt.shared();
+
     let mut captions: Vec<Property> = vec![];
     let locales: Vec<String> = match &agg_query_opt.locale {
         Some(locale) => locale.split(",").map(|s| s.to_string()).collect(),
@@ -579,10 +803,24 @@ pub fn generate_ts_queries(
 
     let mut cuts_map = clean_cuts_map(&agg_query_opt, &cube_cache, &ll_config)?;
 
-    let parents = agg_query_opt.parents.unwrap_or(false);
+    // `parents` is either `true`, or the ancestor levels to include
+    let (parents, parent_levels) = match agg_query_opt.parents.as_ref().map(|p| p.as_str()) {
+        None | Some("false") => (false, vec![]),
+        Some("true") => (true, vec![]),
+        Some(levels) => {
+            let parent_levels = LogicLayerQueryOpt::deserialize_args(levels.to_owned()).iter()
+                .map(|level| {
+                    level_map.get(level)
+                        .cloned()
+                        .ok_or_else(|| format_err!("{}", not_found_message("parents level", level, level_map.keys())))
+                })
+                .collect::<Result<Vec<LevelName>, _>>()?;
+            (true, parent_levels)
+        },
+    };
 
     let drilldowns: Vec<_> = agg_query_opt.drilldowns
-        .map(|ds| {
+        .map(|ds| -> Result<Vec<Drilldown>, Error> {
             let mut drilldowns: Vec<Drilldown> = vec![];
 
             for level_value in LogicLayerQueryOpt::deserialize_args(ds) {
@@ -602,7 +840,14 @@ pub fn generate_ts_queries(
                     None => level_value.clone()
                 };
 
-                let level_name = some_or_break!(level_map.get(&level_key));
+                let level_name = level_map.get(&level_key)
+                    .ok_or_else(|| format_err!("{}", not_found_message("level", &level_value, level_map.keys())))?;
+
+                // All levels have a single member, without captions or parents
+                if cube.get_all_member(level_name).is_some() {
+                    drilldowns.push(Drilldown(level_name.clone()));
+                    continue;
+                }
 
                 let level = some_or_break!(cube.get_level(level_name));
 
@@ -619,10 +864,20 @@ pub fn generate_ts_queries(
                 }
             }
 
-            drilldowns
+            Ok(drilldowns)
         })
+        .transpose()?
         .unwrap_or(vec![]);
 
+    for parent_level in &parent_levels {
+        let in_drilldown_hierarchy = drilldowns.iter()
+            .any(|d| d.0.dimension == parent_level.dimension && d.0.hierarchy == parent_level.hierarchy);
+
+        if !in_drilldown_hierarchy {
+            bail!("Parents level {} is not in the hierarchy of any drilldown", parent_level);
+        }
+    }
+
     let measures: Vec<_> = agg_query_opt.measures
         .map(|ms| {
             let mut measures: Vec<Measure> = vec![];
@@ -657,12 +912,13 @@ pub fn generate_ts_queries(
     let filters: Vec<FilterQuery> = agg_query_opt.filters
         .map(|fs| LogicLayerQueryOpt::deserialize_args(fs).iter().map(|f| {
             // Validate that the measure provided is an actual measure for this cube
-            match &f.splitn(2, ".").collect::<Vec<_>>()[..] {
-                [filter_measure, _] => {
+            match &split_quoted(f, '.')?[..] {
+                [filter_measure, _, ..] => {
+                    let filter_measure = unquote(filter_measure);
                     let mut found = false;
 
                     for mea in &cube.measures {
-                        if &mea.name == filter_measure {
+                        if mea.name == filter_measure {
                             found = true;
                             break;
                         }
@@ -679,9 +935,14 @@ pub fn generate_ts_queries(
         }).collect())
         .unwrap_or(Ok(vec![]))?;
 
+    // measures are checked when the query is turned into sql
+    let row_filters: Vec<FilterQuery> = agg_query_opt.row_filters
+        .map(|fs| LogicLayerQueryOpt::deserialize_args(fs).iter().map(|f| f.parse()).collect())
+        .unwrap_or(Ok(vec![]))?;
+
     let top: Option<TopQuery> = agg_query_opt.top.clone()
         .map(|t| {
-            let top_split: Vec<String> = t.split(',').map(|s| s.to_string()).collect();
+            let top_split: Vec<String> = split_quoted(&t, ',')?.into_iter().map(unquote).collect();
 
             if top_split.len() != 4 {
                 return Err(format_err!("Bad formatting for top param."));
@@ -699,19 +960,10 @@ pub fn generate_ts_queries(
             ))
         })
         .transpose()?;
-    let top_where = agg_query_opt.top_where
-        .map(|t| t.parse())
-        .transpose()?;
-    let sort = agg_query_opt.sort
-        .map(|s| s.parse())
-        .transpose()?;
-    let limit = agg_query_opt.limit
-        .map(|l| l.parse())
-        .transpose()?;
 
     let growth = match agg_query_opt.growth {
         Some(g) => {
-            let gro_split: Vec<String> = g.split(',').map(|s| s.to_string()).collect();
+            let gro_split: Vec<String> = split_quoted(&g, ',')?.into_iter().map(unquote).collect();
 
             if gro_split.len() == 1 {
                 return Err(format_err!("Please provide a growth measure name."));
@@ -738,7 +990,7 @@ pub fn generate_ts_queries(
 
     let rca = match agg_query_opt.rca {
         Some(r) => {
-            let rca_split: Vec<String> = r.split(",").map(|s| s.to_string()).collect();
+            let rca_split: Vec<String> = split_quoted(&r, ',')?.into_iter().map(unquote).collect();
 
             if rca_split.len() != 3 {
                 return Err(format_err!("Bad formatting for RCA param."));
@@ -781,37 +1033,41 @@ pub fn generate_ts_queries(
         None => None
     };
 
-    // TODO: Resolve named sets
     let rate = match agg_query_opt.rate {
         Some(rate) => {
-            let level_value_split: Vec<String> = rate.split('.').map(|s| s.to_string()).collect();
+            // Only split on the first `.`, members may contain periods
+            let rate_split = split_quoted(&rate, '.')?;
 
-            if level_value_split.len() != 2 {
+            if rate_split.len() < 2 {
                 bail!("Bad formatting for rate calculation.");
             }
+            let level_value_split = vec![unquote(rate_split[0]), rate_split[1..].join(".")];
 
             let level_name = match level_map.get(&level_value_split[0]) {
                 Some(level_name) => level_name.clone(),
                 None => bail!("Unrecognized level in rate calculation.")
             };
-            let value = level_value_split[1].clone();
 
-            let values: Vec<String> = value.split(",").map(|s| s.to_string()).collect();
+            // Rate members may reference named sets, same as cuts
+            let value = match ll_config.clone() {
+                Some(ll_conf) => ll_conf.substitute_cut(level_value_split[0].clone(), level_value_split[1].clone()),
+                None => level_value_split[1].clone()
+            };
+
+            let values: Vec<String> = split_quoted(&value, ',')?.into_iter().map(unquote).collect();
 
             Some(RateQuery::new(level_name, values))
         },
         None => None
     };
 
-    let debug = agg_query_opt.debug.unwrap_or(false);
-    let sparse = agg_query_opt.sparse.unwrap_or(false);
-    let exclude_default_members = agg_query_opt.exclude_default_members.unwrap_or(false);
 
     // This is where all the different queries are ACTUALLY generated.
     // Everything before this is common to all queries being generated.
 
     let (dimension_cuts_map, header_map) = resolve_cuts(
-        &cuts_map, &cube, &cube_cache, &level_map, &property_map, &geoservice_url
+        &cuts_map, &cube, &cube_cache, &level_map, &property_map, &geoservice_url,
+        dialect, member_lookups, missing_lookups,
     )?;
 
     // Groups together cuts for the same dimension
@@ -859,19 +1115,16 @@ pub fn generate_ts_queries(
             cuts: vec![],
             measures: measures.clone(),
             parents: parents.clone(),
+            parent_levels: parent_levels.clone(),
             properties: properties.clone(),
             captions: captions.clone(),
             top: top.clone(),
-            top_where: top_where.clone(),
-            sort: sort.clone(),
-            limit: limit.clone(),
             rca: rca.clone(),
             growth: growth.clone(),
-            debug: debug.clone(),
-            exclude_default_members: exclude_default_members.clone(),
             filters: filters.clone(),
+            row_filters: row_filters.clone(),
             rate: rate.clone(),
-            sparse: sparse.clone(),
+            ..TsQuery::new()
         });
     } else {
         // Create a TsQuery for each cut combination
@@ -898,23 +1151,25 @@ pub fn generate_ts_queries(
                 cuts: cut_combination.clone(),
                 measures: measures.clone(),
                 parents: parents.clone(),
+                parent_levels: parent_levels.clone(),
                 properties: properties.clone(),
                 captions: caps,
                 top: top.clone(),
-                top_where: top_where.clone(),
-                sort: sort.clone(),
-                limit: limit.clone(),
                 rca: rca.clone(),
                 growth: growth.clone(),
-                debug: debug.clone(),
-                exclude_default_members: exclude_default_members.clone(),
                 filters: filters.clone(),
+                row_filters: row_filters.clone(),
                 rate: rate.clone(),
-                sparse: sparse.clone(),
+                ..TsQuery::new()
             });
         }
     }
 
+    // `sample` is set by the handler, which scales the results
+    for query in &mut queries {
+        shared.apply(query)?;
+    }
+
     Ok((queries, header_map))
 
 }
@@ -965,34 +1220,48 @@ pub fn clean_cuts_map(
         None => HashMap::new()
     };
 
+    // Time cuts resolved to level members. Several time cuts on one level are combined.
+    let mut time_level_cuts: HashMap<String, Vec<String>> = HashMap::new();
+
     // Process `time` param (latest/oldest)
     match &agg_query_opt.time {
         Some(time_param) => {
             let time_cuts: Vec<String> = time_param.split(",").map(|s| s.to_string()).collect();
 
             for time_cut in time_cuts {
-                let tc: Vec<String> = time_cut.split(".").map(|s| s.to_string()).collect();
+                // `precision.value`, or `precision.value.count`
+                let tc: Vec<String> = time_cut.splitn(2, ".").map(|s| s.to_string()).collect();
 
                 if tc.len() != 2 {
                     return Err(format_err!("Malformatted time cut"));
                 }
 
-                let time = match Time::from_key_value(tc[0].clone(), tc[1].clone()) {
-                    Ok(time) => time,
-                    Err(err) => return Err(format_err!("{}", err.to_string()))
-                };
-
-                let (cut, cut_value) = match cube_cache.get_time_cut(time) {
-                    Ok(cut) => cut,
-                    Err(err) => return Err(format_err!("{}", err.to_string()))
-                };
-
-                agg_query_opt_cuts.insert(cut, cut_value);
+                let (cut, cut_value) = resolve_time_cut(cube_cache, &tc[0], &tc[1])?;
+                time_level_cuts.entry(cut).or_insert_with(Vec::new).push(cut_value);
             }
         },
         None => ()
     };
 
+    // Process params named after a time precision, e.g. `year=latest.5` or
+    // `year=2012,2014,2016`, unless a level has the same unique name
+    for (cut_key, cut_values) in agg_query_opt_cuts.clone().iter() {
+        if cube_cache.level_map.contains_key(cut_key) || TimePrecision::from_str(cut_key.clone()).is_err() {
+            continue;
+        }
+
+        for cut_value in split_quoted(cut_values, ',')? {
+            let (cut, cut_value) = resolve_time_cut(cube_cache, cut_key, unquote(cut_value.trim()).as_str())?;
+            time_level_cuts.entry(cut).or_insert_with(Vec::new).push(cut_value);
+        }
+
+        agg_query_opt_cuts.remove(cut_key);
+    }
+
+    for (cut, cut_values) in time_level_cuts {
+        agg_query_opt_cuts.insert(cut, cut_values.join(","));
+    }
+
     // Find and perform any named set substitutions
     for (cut_key, cut_values) in agg_query_opt_cuts.clone().iter() {
         if cut_values.is_empty() {
@@ -1001,7 +1270,8 @@ pub fn clean_cuts_map(
 
         let mut final_cuts: Vec<String> = vec![];
 
-        let cut_values_split: Vec<String> = cut_values.split(",").map(|s| s.to_string()).collect();
+        // members stay quoted until the cuts are resolved
+        let cut_values_split: Vec<String> = split_quoted(cut_values, ',')?.into_iter().map(|s| s.to_string()).collect();
 
         for cut_value in &cut_values_split {
             match ll_config.clone() {
@@ -1009,7 +1279,7 @@ pub fn clean_cuts_map(
                     let new_cut_values = ll_conf.substitute_cut(cut_key.clone(), cut_value.clone());
 
                     if &new_cut_values != cut_value {
-                        let new_cut_values_split: Vec<String> = new_cut_values.split(",").map(|s| s.to_string()).collect();
+                        let new_cut_values_split: Vec<String> = split_quoted(&new_cut_values, ',')?.into_iter().map(|s| s.to_string()).collect();
 
                         final_cuts = [&final_cuts[..], &new_cut_values_split[..]].concat();
                     } else {
@@ -1029,6 +1299,17 @@ pub fn clean_cuts_map(
 }
 
 
+/// Resolves a time cut, as a precision and a value (`latest`, `oldest` or a time value), to
+/// a cut on the precision's level.
+fn resolve_time_cut(cube_cache: &CubeCache, precision: &str, value: &str) -> Result<(String, String), Error> {
+    let time = Time::from_key_value(precision.to_string(), value.to_string())
+        .map_err(|err| format_err!("{}", err.to_string()))?;
+
+    cube_cache.get_time_cut(time)
+        .map_err(|err| format_err!("{}", err.to_string()))
+}
+
+
 /// Implements logic to resolve logic layer cuts (including those with operations)
 /// into a HashMap separating cuts for each dimension. Doing so helps generate all
 /// the possible cut combinations in the next step.
@@ -1040,7 +1321,10 @@ pub fn resolve_cuts(
         cube_cache: &CubeCache,
         level_map: &HashMap<String, LevelName>,
         _property_map: &HashMap<String, Property>,
-        geoservice_url: &Option<Url>
+        geoservice_url: &Option<Url>,
+        dialect: &dyn SqlDialect,
+        member_lookups: &MemberLookups,
+        missing_lookups: &mut Vec<String>,
 ) -> Result<(HashMap<String, HashMap<LevelName, Vec<String>>>, HashMap<String, String>), Error> {
     // HashMap of cuts for each dimension.
     // In the outer HashMap, the keys are dimension names as string and the
@@ -1064,10 +1348,10 @@ pub fn resolve_cuts(
         }
 
         // Each of these cut_values needs to be matched to a `LevelName` object
-        let cut_values: Vec<String> = cut_values.split(",").map(|s| s.to_string()).collect();
+        let cut_values = split_quoted(cut_values, ',')?;
 
-        for cut_value in &cut_values {
-            let elements: Vec<String> = cut_value.clone().split(":").map(|s| s.to_string()).collect();
+        for cut_value in cut_values {
+            let elements: Vec<String> = split_quoted(cut_value, ':')?.into_iter().map(unquote).collect();
 
             let cut = match elements.get(0) {
                 Some(cut) => cut,
@@ -1089,7 +1373,11 @@ pub fn resolve_cuts(
                                 None => return Err(format_err!("{} matches no levels in this dimension.", cut))
                             }
                         },
-                        None => continue
+                        // members of a level too large to cache aren't in the id map
+                        None => match cube_cache.uncached_dimension_level(cut_key) {
+                            Some(ln) => ln,
+                            None => continue
+                        }
                     }
                 },
                 None => {
@@ -1143,7 +1431,23 @@ pub fn resolve_cuts(
                                 None => continue
                             }
                         },
-                        None => continue
+                        // the child level is too large to cache
+                        None => {
+                            let children_ids = lookup_members(
+                                cube, cube_cache, &level_name, &child_level_name, cut,
+                                dialect, member_lookups, missing_lookups,
+                            )?;
+
+                            match children_ids {
+                                // an empty cut would not cut at all
+                                Some(ref children_ids) if children_ids.is_empty() => {
+                                    return Err(format_err!("Could not find children for {} {}.", level_name.level, cut));
+                                },
+                                Some(children_ids) => children_ids,
+                                // looked up before the query is run again
+                                None => continue
+                            }
+                        }
                     };
 
                     // Add children IDs to the `dimension_cuts_map`
@@ -1182,7 +1486,22 @@ pub fn resolve_cuts(
                                     None => continue
                                 }
                             },
-                            None => continue
+                            // the level is too large to cache
+                            None => {
+                                let parent_ids = lookup_members(
+                                    cube, cube_cache, &level_name, &parent_level_name, &search_id,
+                                    dialect, member_lookups, missing_lookups,
+                                )?;
+
+                                match parent_ids.map(|ids| ids.into_iter().next()) {
+                                    Some(Some(parent_id)) => parent_id,
+                                    Some(None) => {
+                                        return Err(format_err!("Could not find parent for {} {}.", level_name.level, search_id));
+                                    },
+                                    // looked up before the query is run again
+                                    None => continue
+                                }
+                            }
                         };
 
                         // Add parent ID to the `dimension_cuts_map`
@@ -1261,6 +1580,66 @@ pub fn resolve_cuts(
 }
 
 
+/// Members of `target_level_name` related to a member of `level_name`, for levels too large
+/// to cache. Lookups which haven't been done yet are added to `missing_lookups`, and give
+/// `None`.
+pub fn lookup_members(
+        cube: &Cube,
+        cube_cache: &CubeCache,
+        level_name: &LevelName,
+        target_level_name: &LevelName,
+        member: &str,
+        dialect: &dyn SqlDialect,
+        member_lookups: &MemberLookups,
+        missing_lookups: &mut Vec<String>,
+) -> Result<Option<Vec<String>>, Error> {
+    // the member goes into sql
+    validate_member(level_name, member, cube_cache)?;
+
+    let hierarchy = cube.get_hierarchy(level_name)
+        .ok_or_else(|| format_err!("Could not find hierarchy for {}.", level_name.level))?;
+    let level = cube.get_level(level_name)
+        .ok_or_else(|| format_err!("Could not find level {}.", level_name.level))?;
+    let target_level = cube.get_level(target_level_name)
+        .ok_or_else(|| format_err!("Could not find level {}.", target_level_name.level))?;
+
+    let table = match hierarchy.table {
+        Some(table) => table.full_name(),
+        None => cube.fact_table_sql(dialect),
+    };
+
+    // zero-padded members are looked up by their numeric keys. Members of levels which
+    // aren't cached weren't validated against the cache, so text is escaped.
+    let key = level.unpad_key(member);
+    let member_sql = match level.key_type {
+        Some(MemberType::Text) => format!("'{}'", dialect.escape_literal(&key)),
+        _ => {
+            if key.parse::<f64>().is_err() {
+                bail!("Cut member not found");
+            }
+            key
+        },
+    };
+
+    let sql = format!("select distinct {} from {} where {} = {}",
+        target_level.key_column,
+        table,
+        level.key_column,
+        member_sql,
+    );
+
+    match member_lookups.get(&sql) {
+        Some(members) => Ok(Some(members.into_iter().map(|m| target_level.pad_key(m)).collect())),
+        None => {
+            if !missing_lookups.contains(&sql) {
+                missing_lookups.push(sql);
+            }
+            Ok(None)
+        },
+    }
+}
+
+
 /// Adds cut entries to the dimension_cuts_map HashMap.
 pub fn add_cut_entries(
     mut dimension_cuts_map: HashMap<String, HashMap<LevelName, Vec<String>>>,
@@ -5,7 +5,7 @@ use actix_web::{
     HttpResponse,
     Path,
 };
-use failure::Error;
+use failure::{Error, format_err};
 use futures::future::{self, Future};
 use lazy_static::lazy_static;
 use log::*;
@@ -13,14 +13,17 @@ use serde_derive::{Serialize, Deserialize};
 use serde_qs as qs;
 
 use crate::app::AppState;
-use crate::logic_layer::{LogicLayerConfig};
+use crate::logic_layer::{LogicLayerConfig, CubeCache, cube_not_found_message, not_found_message};
+use crate::request_id::with_request_id;
 
 use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::names::LevelName;
+use tesseract_core::names::{Cut, LevelName, Mask};
+use tesseract_core::schema::Cube;
+use tesseract_core::{DataFrame, Column, ColumnData};
 
 use super::super::util::{
     boxed_error_string, boxed_error_http_response,
-    verify_authorization, format_to_content_type
+    verify_authorization, format_to_content_type, negotiate_format, MEMBER_COUNT_HEADER,
 };
 
 
@@ -30,7 +33,11 @@ pub fn logic_layer_members_default_handler(
     (req, _cube): (HttpRequest<AppState>, Path<()>)
 ) -> FutureResponse<HttpResponse>
 {
-    get_members(req, "jsonrecords".to_owned())
+    let format = match negotiate_format(&req, "jsonrecords") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    get_members(req, format)
 }
 
 
@@ -55,7 +62,7 @@ pub fn get_members(
 
     let query = req.query_string();
     let schema = req.state().schema.read().unwrap();
-    let _debug = req.state().debug;
+    let debug = req.state().debug;
 
     let logic_layer_config: Option<LogicLayerConfig> = match &req.state().logic_layer_config {
         Some(llc) => Some(llc.read().unwrap().clone()),
@@ -73,7 +80,11 @@ pub fn get_members(
     let mut level_name: Option<LevelName> = None;
 
     // Get cube object to check for API key
-    let cube_obj = ok_or_404!(schema.get_cube_by_name(&cube_name));
+    let cube_obj = match schema.get_cube_by_name(&cube_name) {
+        // Hidden cubes are only available through the logic layer in debug mode
+        Ok(cube_obj) if debug || !cube_obj.is_hidden() => cube_obj,
+        _ => return boxed_error_string(cube_not_found_message(&cube_name, &schema, &logic_layer_config, debug)),
+    };
 
     if let Err(err) = verify_authorization(&req, cube_obj.min_auth_level) {
         return boxed_error_http_response(err);
@@ -139,15 +150,96 @@ pub fn get_members(
 
     let level_name = match level_name {
         Some(level_name) => level_name,
-        None => return boxed_error_string("Unable to find a level with the name provided".to_string())
+        None => {
+            let mut level_names: Vec<String> = cube_obj.dimensions.iter()
+                .flat_map(|dim| dim.hierarchies.iter())
+                .flat_map(|hier| hier.levels.iter())
+                .map(|level| level.name.clone())
+                .collect();
+
+            let alias_levels = logic_layer_config.as_ref()
+                .and_then(|llc| llc.aliases.as_ref())
+                .and_then(|aliases| aliases.cubes.as_ref())
+                .and_then(|cubes| cubes.iter().find(|cube| cube.name == cube_name))
+                .and_then(|cube| cube.levels.as_ref());
+            if let Some(alias_levels) = alias_levels {
+                level_names.extend(alias_levels.iter().map(|level| level.unique_name.clone()));
+            }
+
+            return boxed_error_string(not_found_message("level", &members_query.level, &level_names));
+        }
     };
 
     debug!("{:?}", cube_name);
     debug!("{:?}", level_name);
 
+    // Ancestors of a member are resolved from the cache, no need to hit the database
+    if let Some(member_id) = &members_query.parents_of {
+        let cache = req.state().cache.read().unwrap();
+
+        let cube_cache = match cache.find_cube_info(&cube_name) {
+            Some(cube_cache) => cube_cache,
+            None => return boxed_error_string("Unable to access cube cache".to_string())
+        };
+
+        let parents = ok_or_404!(get_member_parents(&cube_obj, cube_cache, &level_name, member_id));
+
+        let df = DataFrame::from_vec(vec![
+            Column::new(
+                "Level".to_string(),
+                ColumnData::Text(parents.iter().map(|(level, _)| level.clone()).collect())
+            ),
+            Column::new(
+                "ID".to_string(),
+                ColumnData::Text(parents.into_iter().map(|(_, id)| id).collect())
+            ),
+        ]);
+
+        let header = vec!["Level".to_string(), "ID".to_string()];
+        let content_type = format_to_content_type(&format);
+
+        return match format_records(&header, df, format, None, None, false) {
+            Ok(res) => Box::new(future::result(Ok(HttpResponse::Ok().set(content_type).body(res)))),
+            Err(err) => boxed_error_string(err.to_string()),
+        };
+    }
+
+    // Restrict members to the children of a parent member, in the format `Level:ID`
+    let parent_cut = match &members_query.parent {
+        Some(parent) => {
+            let parent_split: Vec<&str> = parent.splitn(2, ':').collect();
+
+            if parent_split.len() != 2 {
+                return boxed_error_string("Bad formatting for parent param, should be `Level:ID`".to_string());
+            }
+
+            let cache = req.state().cache.read().unwrap();
+
+            let parent_level_name = match cache.find_cube_info(&cube_name).and_then(|c| c.level_map.get(parent_split[0])) {
+                Some(parent_level_name) => parent_level_name.clone(),
+                None => return boxed_error_string(format!("Unable to find parent level {}", parent_split[0]))
+            };
+
+            let members: Vec<String> = parent_split[1].split(',').map(|s| s.to_string()).collect();
+
+            Some(Cut {
+                level_name: parent_level_name,
+                members,
+                mask: Mask::Include,
+                for_match: false,
+            })
+        },
+        None => None
+    };
+
+    let properties: Vec<String> = match &members_query.properties {
+        Some(properties) => properties.split(',').map(|s| s.trim().to_string()).collect(),
+        None => vec![],
+    };
+
     let members_sql_and_headers = match members_query.locale {
-        Some(locale) => schema.members_locale_sql(&cube_name, &level_name, &locale),
-        None => schema.members_sql(&cube_name, &level_name)
+        Some(locale) => schema.members_locale_sql(&cube_name, &level_name, &locale, parent_cut.as_ref(), &properties),
+        None => schema.members_sql(&cube_name, &level_name, parent_cut.as_ref(), &properties)
     };
 
     let (members_sql, header) = match members_sql_and_headers {
@@ -164,15 +256,36 @@ pub fn get_members(
     debug!("{:?}", members_sql);
     debug!("{:?}", header);
 
-    req.state()
-        .backend
-        .exec_sql(members_sql)
+    let member_count = req.state().cache.read().unwrap()
+        .find_cube_info(&cube_name)
+        .and_then(|cube_cache| cube_cache.member_count(&level_name));
+
+    let key_widths: Vec<_> = cube_obj.get_level(&level_name)
+        .and_then(|level| level.key_width)
+        .map(|width| (0, width))
+        .into_iter()
+        .collect();
+
+    let fut = req.state().cube_backend(&cube_obj).exec_sql(members_sql);
+
+    with_request_id(&req, fut)
         .from_err()
-        .and_then(move |df| {
+        .and_then(move |mut df| {
             let content_type = format_to_content_type(&format);
 
-            match format_records(&header, df, format, None, false) {
-                Ok(res) => Ok(HttpResponse::Ok().set(content_type).body(res)),
+            if let Err(err) = df.pad_keys(&key_widths) {
+                return Ok(HttpResponse::NotFound().json(err.to_string()));
+            }
+
+            match format_records(&header, df, format, None, None, false) {
+                Ok(res) => {
+                    let mut response = HttpResponse::Ok();
+                    response.set(content_type);
+                    if let Some(member_count) = member_count {
+                        response.header(MEMBER_COUNT_HEADER, member_count.to_string());
+                    }
+                    Ok(response.body(res))
+                },
                 Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
             }
         })
@@ -180,9 +293,63 @@ pub fn get_members(
 }
 
 
+/// Returns the ancestors of a member as (unique level name, member ID) pairs,
+/// ordered from the top of the hierarchy down to the member's parent.
+pub fn get_member_parents(
+    cube: &Cube,
+    cube_cache: &CubeCache,
+    level_name: &LevelName,
+    member_id: &str,
+) -> Result<Vec<(String, String)>, Error> {
+    let level_cache = cube_cache.level_caches.get(level_name)
+        .ok_or_else(|| format_err!("Could not find cached entries for {}.", level_name.level))?;
+
+    // members of levels too large to cache can't be checked
+    if level_cache.members_cached && !level_cache.members.contains(member_id) {
+        return Err(format_err!("Member {} not found in level {}.", member_id, level_cache.unique_name));
+    }
+
+    let parent_levels = cube.get_level_parents(level_name)?;
+
+    let mut parents: Vec<(String, String)> = vec![];
+    let mut current_level_name = level_name.clone();
+    let mut search_id = member_id.to_string();
+
+    for parent_level in parent_levels.iter().rev() {
+        let current_cache = cube_cache.level_caches.get(&current_level_name)
+            .ok_or_else(|| format_err!("Could not find cached entries for {}.", current_level_name.level))?;
+
+        let parent_id = match current_cache.parent_map.as_ref().and_then(|parent_map| parent_map.get(&search_id)) {
+            Some(parent_id) => parent_id.clone(),
+            None => break
+        };
+
+        current_level_name = LevelName::new(
+            level_name.dimension.clone(),
+            level_name.hierarchy.clone(),
+            parent_level.name.clone()
+        );
+
+        let parent_cache = cube_cache.level_caches.get(&current_level_name)
+            .ok_or_else(|| format_err!("Could not find cached entries for {}.", parent_level.name))?;
+
+        parents.push((parent_cache.unique_name.clone(), parent_id.clone()));
+        search_id = parent_id;
+    }
+
+    // Ancestors were collected bottom up
+    parents.reverse();
+
+    Ok(parents)
+}
+
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MembersQueryOpt {
     pub cube: String,
     pub level: String,
     pub locale: Option<String>,
+    pub parents_of: Option<String>,
+    pub parent: Option<String>,
+    pub properties: Option<String>,
 }
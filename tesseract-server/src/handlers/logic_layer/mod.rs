@@ -10,8 +10,11 @@ pub use self::geoservice::GeoServiceResponseJson;
 pub use self::geoservice::query_geoservice;
 pub use self::metadata::logic_layer_members_handler;
 pub use self::metadata::logic_layer_members_default_handler;
+pub use self::metadata::MembersQueryOpt;
 pub use self::relations::logic_layer_relations_handler;
 pub use self::relations::logic_layer_relations_default_handler;
+pub use self::relations::logic_layer_cube_relations_handler;
+pub use self::relations::logic_layer_cube_relations_default_handler;
 
 use actix_web::{HttpRequest, HttpResponse, Path, ResponseError};
 use crate::app::AppState;
@@ -43,6 +43,121 @@ pub fn logic_layer_relations_handler(
 }
 
 
+/// Handles cube relations when a format is not specified.
+/// Default format is jsonrecords.
+pub fn logic_layer_cube_relations_default_handler(
+    (req, _cube): (HttpRequest<AppState>, Path<()>)
+) -> ActixResult<HttpResponse>
+{
+    logic_layer_cube_relations(req, "jsonrecords".to_owned())
+}
+
+
+/// Handles cube relations when a format is specified.
+pub fn logic_layer_cube_relations_handler(
+    (req, format): (HttpRequest<AppState>, Path<(String)>)
+) -> ActixResult<HttpResponse>
+{
+    logic_layer_cube_relations(req, format.to_owned())
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogicLayerCubeRelationsQueryOpt {
+    pub cube: Option<String>,
+}
+
+
+/// Cubes which share dimensions, one row per level of each shared dimension that two cubes
+/// use, for finding datasets to compare with. With `cube`, only that cube's relations.
+pub fn logic_layer_cube_relations(
+    req: HttpRequest<AppState>,
+    format: String,
+) -> ActixResult<HttpResponse>
+{
+    let format = match format.parse::<FormatType>() {
+        Ok(f) => f,
+        Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+    };
+
+    lazy_static! {
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let query = match QS_NON_STRICT.deserialize_str::<LogicLayerCubeRelationsQueryOpt>(req.query_string()) {
+        Ok(q) => q,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+
+    let schema = req.state().schema.read().unwrap();
+    let debug = req.state().debug;
+
+    let cube_name = match query.cube {
+        Some(cube) => {
+            let cube_name = match &req.state().logic_layer_config {
+                Some(llc) => llc.read().unwrap().clone().substitute_cube_name(cube.clone()).unwrap_or(cube),
+                None => cube,
+            };
+
+            let cube = match schema.get_cube_by_name(&cube_name) {
+                Ok(c) => c,
+                Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string())),
+            };
+            if cube.is_hidden() && !debug {
+                return Ok(HttpResponse::NotFound().json("Could not find cube".to_owned()));
+            }
+            if let Err(err) = verify_authorization(&req, cube.min_auth_level) {
+                return Ok(err);
+            }
+
+            Some(cube_name)
+        },
+        None => None,
+    };
+
+    // Relations to hidden cubes, or to cubes the user can't access, are left out
+    let is_visible = |name: &str| {
+        schema.get_cube_by_name(name)
+            .map(|cube| (debug || !cube.is_hidden()) && verify_authorization(&req, cube.min_auth_level).is_ok())
+            .unwrap_or(false)
+    };
+
+    let relations: Vec<_> = schema.cube_relations().into_iter()
+        .filter(|r| cube_name.as_ref().map(|name| &r.cube == name).unwrap_or(true))
+        .filter(|r| is_visible(&r.cube) && is_visible(&r.related_cube))
+        .collect();
+
+    let headers: Vec<String> = [
+        "cube", "dimension", "related_cube", "related_dimension", "shared_dimension", "hierarchy", "level",
+    ].iter().map(|h| h.to_string()).collect();
+
+    let column = |name: &str, values: Vec<String>| Column {
+        name: name.to_string(),
+        column_data: ColumnData::Text(values),
+    };
+    let df = DataFrame { columns: vec![
+        column("cube", relations.iter().map(|r| r.cube.clone()).collect()),
+        column("dimension", relations.iter().map(|r| r.dimension.clone()).collect()),
+        column("related_cube", relations.iter().map(|r| r.related_cube.clone()).collect()),
+        column("related_dimension", relations.iter().map(|r| r.related_dimension.clone()).collect()),
+        column("shared_dimension", relations.iter().map(|r| r.shared_dimension.clone()).collect()),
+        column("hierarchy", relations.iter().map(|r| r.hierarchy.clone()).collect()),
+        column("level", relations.iter().map(|r| r.level.clone()).collect()),
+    ]};
+
+    let content_type = format_to_content_type(&format);
+
+    match format_records(&headers, df, format, None, None, false) {
+        Ok(res) => {
+            Ok(HttpResponse::Ok()
+                .set(content_type)
+                .body(res))
+        },
+        Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+    }
+}
+
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct LogicLayerRelationQueryOpt {
     pub cube: String,
@@ -67,7 +182,7 @@ pub fn logic_layer_relations(
 
     let query = req.query_string();
     let schema = req.state().schema.read().unwrap();
-    let _debug = req.state().debug;
+    let debug = req.state().debug;
 
     lazy_static! {
         static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
@@ -98,6 +213,11 @@ pub fn logic_layer_relations(
         Err(err) => return Ok(HttpResponse::NotFound().json(err.to_string()))
     };
 
+    // Hidden cubes are only available through the logic layer in debug mode
+    if cube.is_hidden() && !debug {
+        return Ok(HttpResponse::NotFound().json("Could not find cube".to_owned()));
+    }
+
     if let Err(err) = verify_authorization(&req, cube.min_auth_level) {
         return Ok(err);
     }
@@ -156,7 +276,7 @@ pub fn logic_layer_relations(
 
     let content_type = format_to_content_type(&format);
 
-    match format_records(&final_headers, final_df, format, None, false) {
+    match format_records(&final_headers, final_df, format, None, None, false) {
         Ok(res) => {
             Ok(HttpResponse::Ok()
                 .set(content_type)
@@ -0,0 +1,163 @@
+use actix_web::{
+    AsyncResponder,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+};
+use futures::future::{self, Future};
+use lazy_static::lazy_static;
+use log::*;
+use std::time::Instant;
+use serde_derive::Deserialize;
+use serde_qs as qs;
+use tesseract_core::format::{format_records, FormatType};
+use tesseract_core::mdx::parse_mdx;
+
+use crate::api_keys::ApiKeyMeter;
+use crate::app::AppState;
+use crate::errors::ServerError;
+use crate::request_id::with_request_id;
+use super::util::{
+    boxed_error_http_response, verify_authorization, format_to_content_type,
+    generate_source_data, validate_members, validate_query_limits,
+    apply_max_rows, truncate_rows, post_process, PostProcess, negotiate_format,
+    TRUNCATED_HEADER, DATA_UPDATED_HEADER, set_query_headers,
+};
+
+
+/// Handles an MDX query when a format is not specified.
+/// Default format is CSV.
+pub fn mdx_default_handler(
+    (req, _path): (HttpRequest<AppState>, Path<()>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let format = match negotiate_format(&req, "csv") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    do_mdx(req, format)
+}
+
+
+/// Handles an MDX query when a format is specified.
+pub fn mdx_handler(
+    (req, format): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_mdx(req, format.into_inner())
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MdxQueryOpt {
+    pub query: String,
+}
+
+
+/// Translates a restricted MDX `SELECT` (see `tesseract_core::mdx`) into a query, and
+/// runs it like an aggregate query.
+pub fn do_mdx(
+    req: HttpRequest<AppState>,
+    format: String,
+    ) -> FutureResponse<HttpResponse>
+{
+    let format = format.parse::<FormatType>();
+    let format = ok_or_404!(format);
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let mdx_query_res = QS_NON_STRICT.deserialize_str::<MdxQueryOpt>(req.query_string());
+    let mdx_query = ok_or_400!(mdx_query_res);
+
+    info!("mdx: {}", mdx_query.query);
+
+    let (cube, mut ts_query) = ok_or_400!(parse_mdx(&mdx_query.query));
+
+    let schema = &req.state().schema.read().unwrap().clone();
+    let cube_obj = ok_or_404!(schema.get_cube_by_name(&cube));
+
+    if let Err(err) = verify_authorization(&req, cube_obj.min_auth_level) {
+        return boxed_error_http_response(err);
+    }
+
+    info!("cube: {}, format: {:?}, query: {:?}", cube, format, ts_query);
+
+    let source_data = Some(generate_source_data(&cube_obj));
+
+    // sql injection mitigation on query:
+    // - Check that cut members exist in members cache
+    let last_updated = {
+        let cache = req.state().cache.read().unwrap();
+        let cube_cache = some_or_404!(cache.find_cube_info(&cube), format!("Cube {} not found", cube));
+        ok_or_404!(validate_members(&ts_query.cuts, &cube_cache));
+        ok_or_400!(validate_query_limits(&ts_query, &cube_cache, &req.state().env_vars.query_limits));
+        cube_cache.last_updated.clone()
+    };
+
+    let max_rows = apply_max_rows(&mut ts_query, &req.state().env_vars.query_limits);
+
+    let query_ir_headers = schema.sql_query(&cube, &ts_query, None);
+    let (mut query_ir, headers) = ok_or_404!(query_ir_headers);
+    let (steps, sql_previous_year) = PostProcess::take(&mut query_ir, &**req.state().cube_backend(&cube_obj), req.state().env_vars.dataframe_growth, max_rows);
+    ok_or_422!(req.state().cube_backend(&cube_obj).capabilities().check(&query_ir));
+
+    let result_order = query_ir.result_order();
+    let sql = req.state()
+        .cube_backend(&cube_obj)
+        .generate_sql(query_ir);
+    let sql = match sql_previous_year {
+        Some(ref columns) => req.state().cube_backend(&cube_obj).dialect()
+            .previous_year_sql(&sql, columns, &result_order, max_rows.map(|max_rows| max_rows.saturating_add(1))),
+        None => sql,
+    };
+
+    info!("Sql query ({} dialect): {}", req.state().cube_backend(&cube_obj).dialect().name(), sql);
+    info!("Headers: {:?}", headers);
+
+    let api_key_meter = ApiKeyMeter::from_request(&req);
+
+    let query_start = Instant::now();
+    let fut = req.state().cube_backend(&cube_obj).exec_sql(sql);
+
+    with_request_id(&req, fut)
+        .and_then(move |df| {
+            let query_time = query_start.elapsed();
+            let content_type = format_to_content_type(&format);
+
+            let mut df = match post_process(df, &steps) {
+                Ok(df) => df,
+                Err(err) => return Ok(err.response()),
+            };
+
+            let truncated = truncate_rows(&mut df, max_rows);
+
+            if let Some(ref api_key_meter) = api_key_meter {
+                api_key_meter.record_rows(df.len());
+            }
+
+            let row_count = df.len();
+
+            match format_records(&headers, df, format, None, source_data, false) {
+                Ok(res) => {
+                    let mut response = HttpResponse::Ok();
+                    response.set(content_type);
+                    set_query_headers(&mut response, &cube, row_count, query_time);
+
+                    if let Some(last_updated) = last_updated {
+                        response.header(DATA_UPDATED_HEADER, last_updated);
+                    }
+
+                    if truncated {
+                        response.header(TRUNCATED_HEADER, max_rows.unwrap_or(0).to_string());
+                    }
+
+                    Ok(response.body(res))
+                },
+                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+            }
+        })
+        .map_err(move |e| ServerError::from_query_error(&e, req.state().debug).into())
+        .responder()
+}
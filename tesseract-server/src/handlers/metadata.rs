@@ -18,8 +18,12 @@ use tesseract_core::schema::metadata::{CubeMetadata, PropertyMetadata};
 use tesseract_core::DEFAULT_ALLOWED_ACCESS;
 
 use crate::app::AppState;
-use crate::logic_layer::LogicLayerConfig;
-use super::util::{boxed_error_http_response, verify_authorization, get_user_auth_level};
+use crate::logic_layer::{Cache, LogicLayerConfig};
+use crate::request_id::with_request_id;
+use super::util::{
+    boxed_error_http_response, verify_authorization, get_user_auth_level,
+    get_etag, check_not_modified, negotiate_format,
+};
 
 
 pub fn metadata_handler(
@@ -27,7 +31,25 @@ pub fn metadata_handler(
     ) -> ActixResult<HttpResponse>
 {
     info!("Metadata for cube: {}", cube);
-    let cube = match req.state().schema.read().unwrap().cube_metadata(&cube){
+
+    // metadata changes on flush and with the cache, and depends on the user's auth level
+    let (etag, last_modified) = get_etag(&req, &format!("cubes/{}/{:?}?{}", cube, get_user_auth_level(&req), req.query_string()), Some(cube.as_str()));
+    if let Some(res) = check_not_modified(&req, &etag, &last_modified) {
+        return Ok(res);
+    }
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let metadata_query = match QS_NON_STRICT.deserialize_str::<MetadataQueryOpt>(req.query_string()) {
+        Ok(q) => q,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+    if metadata_query.depth == Some(0) {
+        return Ok(HttpResponse::BadRequest().json("depth must be at least 1".to_string()));
+    }
+
+    let mut cube = match req.state().schema.read().unwrap().cube_metadata(&cube){
         Some(c) => c,
         None => return Ok(HttpResponse::NotFound().finish()),
     };
@@ -36,12 +58,34 @@ pub fn metadata_handler(
         return Ok(err);
     }
 
-    let ll_config = match &req.state().logic_layer_config {
-        Some(llc) => llc.read().unwrap().clone(),
-        None => return  Ok(HttpResponse::Ok().json(cube))
+    // Hidden cubes, dimensions and measures are only shown in debug mode
+    if !req.state().debug {
+        if cube.annotations.is_hidden() {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        cube.remove_hidden();
+    }
+
+    add_cache_info(&mut cube, &req.state().cache.read().unwrap());
+
+    let mut cube_details = match &req.state().logic_layer_config {
+        Some(llc) => get_cube_metadata(cube, &llc.read().unwrap()),
+        None => cube,
     };
-    let cube_details = get_cube_metadata(cube, &ll_config);
-    Ok(HttpResponse::Ok().json(cube_details))
+
+    if let Some(depth) = metadata_query.depth {
+        cube_details.trim_depth(depth);
+    }
+
+    let mut res = HttpResponse::Ok();
+    res.header("ETag", etag)
+        .header("Last-Modified", last_modified);
+
+    if metadata_query.levels_only.unwrap_or(false) {
+        Ok(res.json(cube_details.levels_only()))
+    } else {
+        Ok(res.json(cube_details))
+    }
 }
 
 
@@ -51,11 +95,58 @@ pub fn metadata_all_handler(
 {
     info!("Metadata for all");
     let user_auth_level = get_user_auth_level(&req);
+
+    let (etag, last_modified) = get_etag(&req, &format!("cubes/{:?}?{}", user_auth_level, req.query_string()), None);
+    if let Some(res) = check_not_modified(&req, &etag, &last_modified) {
+        return Ok(res);
+    }
     let mut schema_details = req.state().schema.read().unwrap().metadata(user_auth_level);
+
+    // Hidden cubes, dimensions and measures are only shown in debug mode
+    if !req.state().debug {
+        schema_details.remove_hidden();
+    }
+
+    {
+        let cache = req.state().cache.read().unwrap();
+        for cube in schema_details.cubes.iter_mut() {
+            add_cache_info(cube, &cache);
+        }
+    }
+
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let metadata_query_res = QS_NON_STRICT.deserialize_str::<MetadataAllQueryOpt>(&query);
+    let metadata_query = match metadata_query_res {
+        Ok(q) => q,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+
+    // Only keep cubes tagged with all the requested annotations, in the format `key:value,key:value`
+    if let Some(annotations) = metadata_query.annotations {
+        let mut annotation_filters = vec![];
+        for annotation in annotations.split(',') {
+            let annotation_split: Vec<&str> = annotation.splitn(2, ':').collect();
+            if annotation_split.len() != 2 {
+                return Ok(HttpResponse::BadRequest().json(
+                    "Bad formatting for annotations param, should be `key:value`".to_string()
+                ));
+            }
+            annotation_filters.push((annotation_split[0].to_owned(), annotation_split[1].to_owned()));
+        }
+
+        schema_details.cubes.retain(|cube| cube.has_annotations(&annotation_filters));
+    }
+
     let ll_config = match &req.state().logic_layer_config {
         Some(llc) => llc.read().unwrap().clone(),
         None => {
-            return  Ok(HttpResponse::Ok().json(schema_details))
+            return Ok(HttpResponse::Ok()
+                .header("ETag", etag)
+                .header("Last-Modified", last_modified)
+                .json(schema_details))
         }
     };
     let mut cubes: Vec<CubeMetadata> = Vec::new();
@@ -72,7 +163,10 @@ pub fn metadata_all_handler(
         }
     }
     schema_details.cubes = cubes;
-    Ok(HttpResponse::Ok().json(schema_details))
+    Ok(HttpResponse::Ok()
+        .header("ETag", etag)
+        .header("Last-Modified", last_modified)
+        .json(schema_details))
 }
 
 
@@ -80,7 +174,11 @@ pub fn members_default_handler(
     (req, cube): (HttpRequest<AppState>, Path<String>)
     ) -> FutureResponse<HttpResponse>
 {
-    let cube_format = (cube.into_inner(), "csv".to_owned());
+    let format = match negotiate_format(&req, "csv") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    let cube_format = (cube.into_inner(), format);
     do_members(req, cube_format)
 }
 
@@ -93,6 +191,27 @@ pub fn members_handler(
 }
 
 
+/// Fills in when the cube was last updated and the member counts of its levels, which are
+/// known once the cube is cached.
+fn add_cache_info(cube: &mut CubeMetadata, cache: &Cache) {
+    let cube_cache = match cache.find_cube_info(&cube.name) {
+        Some(cube_cache) => cube_cache,
+        None => return,
+    };
+
+    cube.last_updated = cube_cache.last_updated.clone();
+
+    for dimension in cube.dimensions.iter_mut() {
+        for hierarchy in dimension.hierarchies.iter_mut() {
+            for level in hierarchy.levels.iter_mut() {
+                let level_name = LevelName::new(&dimension.name, &hierarchy.name, &level.name);
+                level.member_count = cube_cache.member_count(&level_name);
+            }
+        }
+    }
+}
+
+
 pub fn get_cube_metadata(
     mut cube_details: CubeMetadata,
     ll_config: &LogicLayerConfig,
@@ -179,17 +298,106 @@ pub fn do_members(
 
     info!("Members for cube: {}, level: {}", cube, level);
 
+    let properties = query.properties.unwrap_or_default();
+
     let members_sql_and_headers = req.state().schema.read().unwrap()
-        .members_sql(&cube, &level);
+        .members_sql(&cube, &level, None, &properties);
 
     let (members_sql, header) = ok_or_400!(members_sql_and_headers);
 
-    req.state()
-        .backend
-        .exec_sql(members_sql)
+    let key_widths: Vec<_> = cube_obj.get_level(&level)
+        .and_then(|level| level.key_width)
+        .map(|width| (0, width))
+        .into_iter()
+        .collect();
+
+    let fut = req.state().cube_backend(&cube_obj).exec_sql(members_sql);
+
+    with_request_id(&req, fut)
+        .from_err()
+        .and_then(move |mut df| {
+            if let Err(err) = df.pad_keys(&key_widths) {
+                return Ok(HttpResponse::NotFound().json(err.to_string()));
+            }
+
+            match format_records(&header, df, format, None, None, false) {
+                Ok(res) => Ok(HttpResponse::Ok().body(res)),
+                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+            }
+        })
+        .responder()
+}
+
+
+pub fn measure_stats_default_handler(
+    (req, cube_measure): (HttpRequest<AppState>, Path<(String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let format = match negotiate_format(&req, "jsonrecords") {
+        Ok(format) => format,
+        Err(res) => return boxed_error_http_response(res),
+    };
+    let (cube, measure) = cube_measure.into_inner();
+    do_measure_stats(req, (cube, measure, format))
+}
+
+
+pub fn measure_stats_handler(
+    (req, cube_measure_format): (HttpRequest<AppState>, Path<(String, String, String)>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_measure_stats(req, cube_measure_format.into_inner())
+}
+
+
+/// Min, max, avg and count of a measure's values in the fact table, e.g. for the range of a
+/// measure filter. With `by_time=true`, there is a row per member of the top level of the
+/// cube's time dimension.
+pub fn do_measure_stats(
+    req: HttpRequest<AppState>,
+    cube_measure_format: (String, String, String),
+    ) -> FutureResponse<HttpResponse>
+{
+    let (cube, measure, format) = cube_measure_format;
+
+    let schema = &req.state().schema.read().unwrap().clone();
+    let cube_obj = ok_or_404!(schema.get_cube_by_name(&cube));
+
+    if let Err(err) = verify_authorization(&req, cube_obj.min_auth_level) {
+        return boxed_error_http_response(err);
+    }
+
+    let format = ok_or_404!(format.parse::<FormatType>());
+
+    let query = req.query_string();
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let query_res = QS_NON_STRICT.deserialize_str::<MeasureStatsQueryOpt>(&query);
+    let query = ok_or_400!(query_res);
+
+    info!("Stats for cube: {}, measure: {}", cube, measure);
+
+    // the stats are of the whole fact table, which confidential cubes don't allow
+    ok_or_400!(cube_obj.check_required_cuts(&[]));
+
+    let stats_sql_and_headers = schema.measure_stats_sql(
+        &cube,
+        &measure,
+        query.by_time.unwrap_or(false),
+        &*req.state().cube_backend(&cube_obj).dialect(),
+    );
+
+    let (stats_sql, header) = ok_or_404!(stats_sql_and_headers);
+
+    let fut = req.state().cube_backend(&cube_obj).exec_sql(stats_sql);
+
+    with_request_id(&req, fut)
         .from_err()
         .and_then(move |df| {
-            match format_records(&header, df, format, None, false) {
+            match format_records(&header, df, format, None, None, false) {
                 Ok(res) => Ok(HttpResponse::Ok().body(res)),
                 Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
             }
@@ -199,6 +407,29 @@ pub fn do_members(
 
 
 #[derive(Debug, Deserialize)]
-struct MembersQueryOpt {
+pub struct MeasureStatsQueryOpt {
+    by_time: Option<bool>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct MembersQueryOpt {
     level: String,
+    properties: Option<Vec<String>>,
+}
+
+
+/// Options of `/cubes/{cube}`, for a more compact description of the cube.
+#[derive(Debug, Deserialize)]
+pub struct MetadataQueryOpt {
+    /// Keeps only the first `depth` levels of each hierarchy.
+    depth: Option<usize>,
+    /// Lists the levels flat instead of nested in dimensions and hierarchies.
+    levels_only: Option<bool>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataAllQueryOpt {
+    annotations: Option<String>,
 }
@@ -1,19 +1,34 @@
 #[macro_use]
 mod util;
+#[macro_use]
+mod query_opt;
 mod aggregate;
 mod aggregate_stream;
 mod diagnosis;
+mod export;
 mod flush;
 mod index;
+mod keys;
+mod mdx;
 mod metadata;
+mod openapi;
+mod preaggregations;
+mod query;
+mod scaffold;
+mod ws;
 pub mod logic_layer;
 
+pub use self::aggregate::AggregateQueryOpt;
 pub use self::aggregate::aggregate_handler;
 pub use self::aggregate::aggregate_default_handler;
 pub use self::aggregate_stream::aggregate_handler as aggregate_stream_handler;
 pub use self::aggregate_stream::aggregate_default_handler as aggregate_stream_default_handler;
 pub use self::diagnosis::diagnosis_handler;
 pub use self::diagnosis::diagnosis_default_handler;
+pub use self::export::export_handler;
+pub use self::export::export_default_handler;
+pub use self::export::job_handler;
+pub use self::export::job_file_handler;
 pub use self::logic_layer::logic_layer_handler;
 pub use self::logic_layer::logic_layer_default_handler;
 pub use self::logic_layer::logic_layer_non_unique_levels_handler;
@@ -21,12 +36,29 @@ pub use self::logic_layer::logic_layer_non_unique_levels_default_handler;
 pub use self::logic_layer::logic_layer_members_handler;
 pub use self::logic_layer::logic_layer_members_default_handler;
 pub use self::flush::flush_handler;
+pub use self::flush::flush_logic_layer_handler;
 pub use self::index::index_handler;
+pub use self::keys::keys_usage_handler;
+pub use self::mdx::mdx_handler;
+pub use self::mdx::mdx_default_handler;
+pub use self::metadata::measure_stats_handler;
+pub use self::metadata::measure_stats_default_handler;
 pub use self::metadata::members_handler;
 pub use self::metadata::members_default_handler;
 pub use self::metadata::metadata_handler;
 pub use self::metadata::metadata_all_handler;
+pub use self::openapi::openapi_handler;
+pub use self::preaggregations::preaggregations_handler;
+pub use self::query::query_handler;
+pub use self::query::query_default_handler;
+pub use self::scaffold::scaffold_handler;
+pub use self::scaffold::scaffold_tables_handler;
+pub use self::ws::ws_handler;
 pub use self::logic_layer::logic_layer_relations_handler;
 pub use self::logic_layer::logic_layer_relations_default_handler;
+pub use self::logic_layer::logic_layer_cube_relations_handler;
+pub use self::logic_layer::logic_layer_cube_relations_default_handler;
 pub use self::logic_layer::logic_layer_relations_non_unique_levels_default_handler;
 pub use self::logic_layer::logic_layer_relations_non_unique_levels_handler;
+pub use self::util::VaryHeader;
+pub use self::util::{post_process, select_columns, truncate_rows, PostProcess};
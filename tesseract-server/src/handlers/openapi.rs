@@ -0,0 +1,528 @@
+//! OpenAPI 3 document for the server.
+//!
+//! Query params are read from the fields of each handler's query struct, so a new field shows
+//! up in the document as soon as it's added; its description comes from `PARAM_DOCS` (a test
+//! checks that every field has one). The logic layer structs flatten their cuts into the
+//! query, which hides their fields from serde, so their params are listed by hand.
+
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::de::value::Error as DeError;
+use serde::forward_to_deserialize_any;
+use serde_json::{json, Map, Value};
+use structopt::clap::crate_version;
+
+use crate::app::AppState;
+use super::aggregate::AggregateQueryOpt;
+use super::diagnosis::DiagnosisQueryOpt;
+use super::flush::FlushQueryOpt;
+use super::keys::KeysUsageQueryOpt;
+use super::scaffold::ScaffoldQueryOpt;
+use super::logic_layer::MembersQueryOpt as LogicLayerMembersQueryOpt;
+use super::mdx::MdxQueryOpt;
+use super::metadata::{MeasureStatsQueryOpt, MembersQueryOpt, MetadataAllQueryOpt, MetadataQueryOpt};
+use super::preaggregations::PreaggregationsQueryOpt;
+
+
+// Formats accepted in `.{format}` paths.
+const FORMATS: &[&str] = &["csv", "jsonrecords", "jsonarrays", "geojson"];
+
+// (name, type, description) of query params. Array params are sent as `name[]=...`.
+const PARAM_DOCS: &[(&str, &str, &str)] = &[
+    ("annotations", "string", "Only cubes with all these annotations, as `key:value,key:value`."),
+    ("by_time", "boolean", "One row per member of the top level of the cube's time dimension."),
+    ("captions", "array", "Caption properties to use for level names, as `Dimension.Hierarchy.Level.Property`."),
+    ("check_rows", "integer", "Most rows of the fact or dimension table each diagnosis check reads."),
+    ("column_types", "boolean", "Add a `schema` block with each column's name, type and nullability to `jsonrecords` results."),
+    ("columns", "string", "Comma separated headers of the columns to keep in the results, in that order."),
+    ("convert", "string", "Comma separated unit conversions of measures, as `Measure:unit`; units are `thousands`, `millions`, `billions` and `percent`."),
+    ("cube", "string", "Cube name."),
+    ("cuts", "array", "Cuts, as `Dimension.Hierarchy.Level.member1,member2`; prefix with `~` to exclude the members."),
+    ("dataframe_growth", "boolean", "Calculate growth and vs_previous_year on the results instead of in SQL; always done for databases without growth SQL."),
+    ("data_source", "string", "Named data source (`TESSERACT_DATABASE_URL_<NAME>`) to use instead of the default database."),
+    ("debug", "boolean", "Return the generated SQL and extra error details."),
+    ("depth", "integer", "Only the first `depth` levels of each hierarchy."),
+    ("distinct", "boolean", "Return the distinct combinations of the drilldowns, without measures."),
+    ("drilldowns", "array", "Levels to group by, as `Dimension.Hierarchy.Level`."),
+    ("exclude_default_members", "boolean", "Leave out members set as a level's default member."),
+    ("filters", "array", "Filters on measures, as `Measure.gt.value`, optionally combined with `.and.`/`.or.`."),
+    ("growth", "string", "Growth calculation, as `TimeDrill,Measure`."),
+    ("level", "string", "Level to list the members of."),
+    ("levels_only", "boolean", "List the levels of the cube flat, with the measure names, instead of the full nested description."),
+    ("limit", "string", "Row limit, as `n` or `offset,n`."),
+    ("locale", "string", "Locale for number formatting, e.g. `de-DE`."),
+    ("max_members", "integer", "Columns with more distinct values than this are not made dimensions (default 1000)."),
+    ("max_tables", "integer", "Most pre-aggregation tables suggested (default 10)."),
+    ("measures", "array", "Measures to aggregate."),
+    ("min_count", "integer", "Only suggest pre-aggregations of level combinations queried at least this many times (default 10)."),
+    ("parents", "boolean", "Include the parent levels of each drilldown."),
+    ("pivot", "string", "Level to pivot the results on, one column per member and measure."),
+    ("preview", "boolean", "Return only the first and last 50 rows, with the total number of rows in the `X-Tesseract-Total-Rows` header."),
+    ("priority", "string", "Query priority: `low`, `normal` or `high`."),
+    ("properties", "array", "Properties to include, as `Dimension.Hierarchy.Level.Property`; for members, property names of the level."),
+    ("property_filters", "array", "Only members of a level with one of these property values, as `Dimension.Hierarchy.Level.Property:value1,value2`; values are compared as text."),
+    ("query", "string", "An MDX `SELECT` with measures and levels (`.Members`) or members (`.&[key]`) on axes, and members in `WHERE`."),
+    ("rate", "string", "Rate calculation, as `Level.member1,member2`."),
+    ("rca", "string", "RCA calculation, as `Drill1,Drill2,Measure`."),
+    ("sample", "number", "Run the query on this fraction of the fact table, scaling up sums and counts; for diagnosis, run the checks on it."),
+    ("secret", "string", "The server's flush secret."),
+    ("share", "string", "Share calculation, as `Level,Measure`: each row's part of the measure's total among the level's members."),
+    ("show", "string", "Columns of levels with a name column: `ids`, `labels` or `both` (default)."),
+    ("sort", "string", "Comma separated sort keys, as `Column.asc` or `Column.desc`; a column is a measure, calculation or drilldown column header."),
+    ("sparse", "boolean", "Leave out rows where all measures are null or zero."),
+    ("time_fill", "string", "Add rows for periods missing from the results of a time drilldown, with `zero` or `null` measures."),
+    ("top", "string", "Top calculation, as `n,GroupDrill,Measure,asc|desc`."),
+    ("top_where", "string", "Filter applied before the top calculation, as `Measure.gt.value`."),
+    ("totals", "boolean", "Add subtotal and grand total rows."),
+    ("treat_null_as", "string", "Null measure values as `zero` (aggregated and returned as zeros) or `skip`, for all measures instead of each measure's own."),
+    ("vs_previous_year", "string", "Measure to compare with the same period of the previous year, for a drilldown on a `Month` or `Quarter` level; adds its previous year value, the difference and the growth."),
+    ("where", "array", "Filters on the measure columns of fact table rows, applied before aggregation, as `Measure.gt.value`, optionally combined with `.and.`/`.or.`."),
+];
+
+// (name, type, description) of logic layer `/data` params. Cuts are any other param, named
+// after a level's unique name, e.g. `Country=usa,can`.
+const LOGIC_LAYER_DATA_PARAMS: &[(&str, &str, &str)] = &[
+    ("cube", "string", "Cube name, or an alias from the logic layer config."),
+    ("drilldowns", "string", "Comma-separated level (unique) names."),
+    ("measures", "string", "Comma-separated measures."),
+    ("time", "string", "Time cut, as `precision.latest` or `precision.oldest`, optionally `.n` for the n latest/oldest periods. Precisions include `fiscal_year` and `fiscal_quarter`."),
+    ("properties", "string", "Comma-separated property (unique) names."),
+    ("filters", "string", "Filters on measures, as `Measure.gt.value`."),
+    ("where", "string", "Comma separated filters on fact table rows, applied before aggregation, as `Measure.gt.value`."),
+    ("parents", "string", "`true` to include the parent levels of each drilldown, or comma-separated parent levels to include only those."),
+    ("top", "string", "Top calculation, as `n,Level,Measure,asc|desc`."),
+    ("top_where", "string", "Filter applied before the top calculation."),
+    ("sort", "string", "Comma separated sort keys, as `Column.asc` or `Column.desc`; a column is a measure, calculation or drilldown column header."),
+    ("limit", "string", "Row limit, as `n` or `offset,n`."),
+    ("growth", "string", "Growth calculation, as `TimeLevel,Measure`."),
+    ("dataframe_growth", "boolean", "Calculate growth on the results instead of in SQL."),
+    ("rca", "string", "RCA calculation, as `Level1,Level2,Measure`."),
+    ("rate", "string", "Rate calculation, as `Level.member1,member2`."),
+    ("calculations", "string", "Comma separated calculations on the results, `growth` and `share`, appended as a column per measure."),
+    ("exclude", "string", "Members to exclude, as `Level:member1,member2;Level2:member3`."),
+    ("exclude_default_members", "boolean", "Leave out members set as a level's default member."),
+    ("locale", "string", "Comma-separated locales for captions."),
+    ("format_locale", "string", "Locale for number formatting, e.g. `de-DE`."),
+    ("sparse", "boolean", "Leave out rows where all measures are null or zero."),
+    ("treat_null_as", "string", "Null measure values as `zero` (aggregated and returned as zeros) or `skip`, for all measures instead of each measure's own."),
+    ("priority", "string", "Query priority: `low`, `normal` or `high`."),
+    ("sample", "number", "Run the query on this fraction of the fact table, scaling up sums and counts."),
+    ("column_types", "boolean", "Add a `schema` block with each column's name, type and nullability to `jsonrecords` results."),
+    ("convert", "string", "Comma separated unit conversions of measures, as `Measure:unit`; units are `thousands`, `millions`, `billions` and `percent`."),
+    ("columns", "string", "Comma separated headers of the columns to keep in the results, in that order."),
+    ("show", "string", "Columns of levels with a name column: `ids`, `labels` or `both` (default)."),
+    ("distinct", "boolean", "Return the distinct combinations of the drilldowns, without measures."),
+    ("debug", "boolean", "Return extra error details."),
+];
+
+const LOGIC_LAYER_RELATIONS_PARAMS: &[(&str, &str, &str)] = &[
+    ("cube", "string", "Cube name, or an alias from the logic layer config."),
+    ("debug", "boolean", "Return extra error details."),
+];
+
+const LOGIC_LAYER_CUBE_RELATIONS_PARAMS: &[(&str, &str, &str)] = &[
+    ("cube", "string", "Only the relations of this cube; name or alias from the logic layer config."),
+];
+
+const LOGIC_LAYER_MEMBERS_DOCS: &[(&str, &str, &str)] = &[
+    ("cube", "string", "Cube name, or an alias from the logic layer config."),
+    ("level", "string", "Level (unique) name to list the members of."),
+    ("locale", "string", "Comma-separated locales for captions."),
+    ("parents_of", "string", "Member to get the parents of."),
+    ("parent", "string", "Only members that are children of this member."),
+    ("properties", "string", "Comma-separated property names of the level to include."),
+];
+
+
+/// Serves the OpenAPI document. Logic layer endpoints are only included when the logic
+/// layer is available.
+pub fn openapi_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let has_logic_layer = req.state().logic_layer_config.is_some();
+
+    Ok(HttpResponse::Ok().json(openapi_spec(has_logic_layer)))
+}
+
+
+fn openapi_spec(has_logic_layer: bool) -> Value {
+    let mut paths = Map::new();
+
+    paths.insert("/".to_owned(), json!({
+        "get": operation("Server status and version", vec![], json_response("Status")),
+    }));
+    paths.insert("/cubes".to_owned(), json!({
+        "get": operation(
+            "Metadata of all cubes",
+            query_params(struct_fields::<MetadataAllQueryOpt>(), PARAM_DOCS, &[]),
+            json_response("Schema metadata"),
+        ),
+    }));
+    paths.insert("/cubes/{cube}".to_owned(), json!({
+        "get": operation(
+            "Metadata of a cube",
+            {
+                let mut params = vec![path_param("cube")];
+                params.extend(query_params(struct_fields::<MetadataQueryOpt>(), PARAM_DOCS, &[]));
+                params
+            },
+            json_response("Cube metadata"),
+        ),
+    }));
+
+    add_formatted_paths(&mut paths, "/cubes/{cube}/members", "Members of a level", {
+        let mut params = vec![path_param("cube")];
+        params.extend(query_params(struct_fields::<MembersQueryOpt>(), PARAM_DOCS, &["level"]));
+        params
+    });
+    add_formatted_paths(&mut paths, "/cubes/{cube}/measures/{measure}/stats", "Min, max, avg and count of a measure", {
+        let mut params = vec![path_param("cube"), path_param("measure")];
+        params.extend(query_params(struct_fields::<MeasureStatsQueryOpt>(), PARAM_DOCS, &[]));
+        params
+    });
+    add_formatted_paths(&mut paths, "/cubes/{cube}/aggregate", "Aggregate a cube", {
+        let mut params = vec![path_param("cube")];
+        params.extend(query_params(struct_fields::<AggregateQueryOpt>(), PARAM_DOCS, &[]));
+        params
+    });
+
+    // exports take the aggregate query, but are queued with a POST
+    let export_params = {
+        let mut params = vec![path_param("cube")];
+        params.extend(query_params(struct_fields::<AggregateQueryOpt>(), PARAM_DOCS, &[]));
+        params
+    };
+    let export_response = json!({ "202": { "description": "Queued job" } });
+    paths.insert("/cubes/{cube}/export".to_owned(), json!({
+        "post": operation("Queue an aggregate query as an export job", export_params.clone(), export_response.clone()),
+    }));
+    let mut export_params = export_params;
+    export_params.insert(0, format_param());
+    paths.insert("/cubes/{cube}/export.{format}".to_owned(), json!({
+        "post": operation("Queue an aggregate query as an export job", export_params, export_response),
+    }));
+
+    // structured queries are a json body instead of params, see the server readme for its fields
+    let query_summary = "Run a structured query, posted as a JSON body";
+    let query_body = json!({
+        "required": true,
+        "content": {
+            "application/json": {
+                "schema": { "type": "object", "required": ["version"] },
+            },
+        },
+    });
+    let mut query_operation = operation(query_summary, vec![path_param("cube")], formatted_response());
+    query_operation["requestBody"] = query_body.clone();
+    paths.insert("/cubes/{cube}/query".to_owned(), json!({ "post": query_operation }));
+    let mut query_operation = operation(query_summary, vec![format_param(), path_param("cube")], formatted_response());
+    query_operation["requestBody"] = query_body;
+    paths.insert("/cubes/{cube}/query.{format}".to_owned(), json!({ "post": query_operation }));
+
+    paths.insert("/jobs/{id}".to_owned(), json!({
+        "get": operation("Status of an export job", vec![path_param("id")], json_response("Job status")),
+    }));
+    paths.insert("/jobs/{id}/file".to_owned(), json!({
+        "get": operation("File of a completed export job", vec![path_param("id")], formatted_response()),
+    }));
+
+    add_formatted_paths(
+        &mut paths,
+        "/diagnosis",
+        "Check dimension tables for data issues",
+        query_params(struct_fields::<DiagnosisQueryOpt>(), PARAM_DOCS, &[]),
+    );
+    add_formatted_paths(
+        &mut paths,
+        "/mdx",
+        "Run a restricted MDX query",
+        query_params(struct_fields::<MdxQueryOpt>(), PARAM_DOCS, &["query"]),
+    );
+
+    paths.insert("/ws".to_owned(), json!({
+        "get": operation(
+            "WebSocket for query subscriptions, see the server readme for the protocol",
+            vec![],
+            json!({ "101": { "description": "Switching protocols" } }),
+        ),
+    }));
+    paths.insert("/flush".to_owned(), json!({
+        "post": operation(
+            "Reload the schema, or one cube with a `cube` param or form field",
+            query_params(struct_fields::<FlushQueryOpt>(), PARAM_DOCS, &["secret"]),
+            json!({ "200": { "description": "Flushed" }, "401": { "description": "Wrong secret" } }),
+        ),
+    }));
+    paths.insert("/flush/logic_layer".to_owned(), json!({
+        "post": operation(
+            "Reload the logic layer config",
+            query_params(&["secret"], PARAM_DOCS, &["secret"]),
+            json!({
+                "200": { "description": "Flushed" },
+                "401": { "description": "Wrong secret" },
+                "409": { "description": "The new config needs a restart" },
+            }),
+        ),
+    }));
+    paths.insert("/keys/usage".to_owned(), json!({
+        "get": operation(
+            "Today's usage of each API key",
+            query_params(struct_fields::<KeysUsageQueryOpt>(), PARAM_DOCS, &["secret"]),
+            json_response("Usage per key"),
+        ),
+    }));
+    paths.insert("/stats/preaggregations".to_owned(), json!({
+        "get": operation(
+            "Suggested pre-aggregation tables, from the aggregate queries run",
+            query_params(struct_fields::<PreaggregationsQueryOpt>(), PARAM_DOCS, &["secret"]),
+            json_response("Suggested tables, with the sql creating them"),
+        ),
+    }));
+    let mut scaffold_params = vec![path_param("table")];
+    scaffold_params.extend(query_params(struct_fields::<ScaffoldQueryOpt>(), PARAM_DOCS, &["secret"]));
+    paths.insert("/scaffold/{table}".to_owned(), json!({
+        "get": operation(
+            "Draft cube schema (json) for an existing fact table",
+            scaffold_params,
+            json_response("Draft cube"),
+        ),
+    }));
+
+    if has_logic_layer {
+        let data_params = LOGIC_LAYER_DATA_PARAMS.iter().map(|p| p.0).collect::<Vec<_>>();
+        add_formatted_paths(
+            &mut paths,
+            "/data",
+            "Aggregate a cube through the logic layer. Cuts are params named after a level, e.g. `Country=usa,can`",
+            query_params(&data_params, LOGIC_LAYER_DATA_PARAMS, &["cube"]),
+        );
+
+        add_formatted_paths(
+            &mut paths,
+            "/members",
+            "Members of a level, through the logic layer",
+            query_params(struct_fields::<LogicLayerMembersQueryOpt>(), LOGIC_LAYER_MEMBERS_DOCS, &["cube", "level"]),
+        );
+
+        let relations_params = LOGIC_LAYER_RELATIONS_PARAMS.iter().map(|p| p.0).collect::<Vec<_>>();
+        add_formatted_paths(
+            &mut paths,
+            "/relations",
+            "Parents, children and neighbors of members. Members are params named after a level",
+            query_params(&relations_params, LOGIC_LAYER_RELATIONS_PARAMS, &["cube"]),
+        );
+
+        let cube_relations_params = LOGIC_LAYER_CUBE_RELATIONS_PARAMS.iter().map(|p| p.0).collect::<Vec<_>>();
+        add_formatted_paths(
+            &mut paths,
+            "/relations/cubes",
+            "Cubes which share dimensions, one row per shared level",
+            query_params(&cube_relations_params, LOGIC_LAYER_CUBE_RELATIONS_PARAMS, &[]),
+        );
+    }
+
+    paths.insert("/openapi.json".to_owned(), json!({
+        "get": operation("This document", vec![], json_response("OpenAPI document")),
+    }));
+
+    json!({
+        "openapi": "3.0.0",
+        "info": {
+            "title": "Tesseract",
+            "version": crate_version!(),
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "jwt": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                "apiKey": { "type": "apiKey", "in": "header", "name": "x-api-key" },
+            },
+        },
+    })
+}
+
+
+/// Adds `path`, which defaults to csv, and `path.{format}`.
+fn add_formatted_paths(paths: &mut Map<String, Value>, path: &str, summary: &str, params: Vec<Value>) {
+    paths.insert(path.to_owned(), json!({
+        "get": operation(summary, params.clone(), formatted_response()),
+    }));
+
+    let mut params = params;
+    params.insert(0, format_param());
+    paths.insert(format!("{}.{{format}}", path), json!({
+        "get": operation(summary, params, formatted_response()),
+    }));
+}
+
+
+fn format_param() -> Value {
+    json!({
+        "name": "format",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string", "enum": FORMATS },
+    })
+}
+
+
+fn operation(summary: &str, params: Vec<Value>, responses: Value) -> Value {
+    json!({
+        "summary": summary,
+        "parameters": params,
+        "responses": responses,
+    })
+}
+
+
+fn path_param(name: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    })
+}
+
+
+fn query_params(fields: &[&str], docs: &[(&str, &str, &str)], required: &[&str]) -> Vec<Value> {
+    fields.iter()
+        .map(|field| {
+            let (param_type, description) = docs.iter()
+                .find(|(name, _, _)| name == field)
+                .map(|(_, param_type, description)| (*param_type, *description))
+                .unwrap_or(("string", ""));
+
+            let (name, schema) = if param_type == "array" {
+                (format!("{}[]", field), json!({ "type": "array", "items": { "type": "string" } }))
+            } else {
+                (field.to_string(), json!({ "type": param_type }))
+            };
+
+            json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(field),
+                "description": description,
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+
+fn json_response(description: &str) -> Value {
+    json!({
+        "200": {
+            "description": description,
+            "content": { "application/json": { "schema": { "type": "object" } } },
+        },
+    })
+}
+
+
+fn formatted_response() -> Value {
+    json!({
+        "200": {
+            "description": "Results, in the requested format",
+            "content": {
+                "text/csv": { "schema": { "type": "string" } },
+                "application/json": {
+                    "schema": {
+                        "type": "object",
+                        "properties": {
+                            "data": { "type": "array", "items": {} },
+                            "source": { "type": "array", "items": { "type": "object" } },
+                        },
+                    },
+                },
+                "application/geo+json": { "schema": { "type": "object" } },
+            },
+        },
+        "400": { "description": "Invalid query" },
+        "404": { "description": "Cube, level or member not found" },
+    })
+}
+
+
+/// Deserializer that only records the field names of the struct it's asked to deserialize.
+struct FieldsCollector<'a>(&'a mut &'static [&'static str]);
+
+impl<'de, 'a> Deserializer<'de> for FieldsCollector<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, DeError>
+    {
+        *self.0 = fields;
+        Err(de::Error::custom("fields collected"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Field names of a (non-flattened) struct deriving `Deserialize`.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsCollector(&mut fields));
+    fields
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::format::FormatType;
+
+    #[test]
+    fn test_openapi_params_documented() {
+        let fields = [
+            struct_fields::<AggregateQueryOpt>(),
+            struct_fields::<MembersQueryOpt>(),
+            struct_fields::<MeasureStatsQueryOpt>(),
+            struct_fields::<MetadataAllQueryOpt>(),
+            struct_fields::<MetadataQueryOpt>(),
+            struct_fields::<DiagnosisQueryOpt>(),
+            struct_fields::<FlushQueryOpt>(),
+            struct_fields::<KeysUsageQueryOpt>(),
+            struct_fields::<MdxQueryOpt>(),
+            struct_fields::<PreaggregationsQueryOpt>(),
+            struct_fields::<ScaffoldQueryOpt>(),
+        ];
+        let logic_layer_members_fields = struct_fields::<LogicLayerMembersQueryOpt>();
+
+        assert!(struct_fields::<AggregateQueryOpt>().contains(&"drilldowns"));
+
+        for field in fields.iter().flat_map(|fields| fields.iter()) {
+            assert!(
+                PARAM_DOCS.iter().any(|(name, _, _)| name == field),
+                "query param {} is missing from PARAM_DOCS", field,
+            );
+        }
+
+        for field in logic_layer_members_fields {
+            assert!(
+                LOGIC_LAYER_MEMBERS_DOCS.iter().any(|(name, _, _)| name == field),
+                "query param {} is missing from LOGIC_LAYER_MEMBERS_DOCS", field,
+            );
+        }
+
+        for format in FORMATS {
+            assert!(format.parse::<FormatType>().is_ok());
+        }
+    }
+}
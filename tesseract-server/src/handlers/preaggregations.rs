@@ -0,0 +1,153 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use failure::Error;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_qs as qs;
+use tesseract_core::{Aggregator, Backend, Schema, Query as TsQuery};
+use tesseract_core::names::{Drilldown, LevelName, Measure};
+use tesseract_core::schema::Cube;
+
+use crate::app::AppState;
+use super::util::check_secret;
+
+
+const DEFAULT_MIN_COUNT: u64 = 10;
+const DEFAULT_MAX_TABLES: usize = 10;
+
+
+#[derive(Debug, Deserialize)]
+pub struct PreaggregationsQueryOpt {
+    pub secret: String,
+    /// Only suggest level combinations queried at least this many times.
+    pub min_count: Option<u64>,
+    /// Most suggestions returned.
+    pub max_tables: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct Preaggregation {
+    cube: String,
+    levels: Vec<String>,
+    measures: Vec<String>,
+    count: u64,
+    total_ms: u64,
+    avg_ms: u64,
+    table: String,
+    sql: String,
+}
+
+/// Suggests pre-aggregation tables from the statistics of the aggregate queries run: the
+/// level combinations queried most, by the total time spent on them, with the statement
+/// creating each table in the dialect of its cube's backend. Admin only; protected by the
+/// flush secret.
+pub fn preaggregations_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let query = req.query_string();
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let query = match QS_NON_STRICT.deserialize_str::<PreaggregationsQueryOpt>(&query) {
+        Ok(q) => q,
+        Err(err) => return Ok(HttpResponse::BadRequest().json(err.to_string())),
+    };
+
+    if let Err(res) = check_secret(&req, &query.secret) {
+        return Ok(res);
+    }
+
+    let top = req.state().query_stats.top(
+        query.min_count.unwrap_or(DEFAULT_MIN_COUNT),
+        query.max_tables.unwrap_or(DEFAULT_MAX_TABLES),
+    );
+
+    let schema = req.state().schema.read().unwrap();
+
+    let mut preaggregations = vec![];
+    for (cube_name, levels, stat) in top {
+        // cubes can change on flush, since the queries were recorded
+        let cube = match schema.get_cube_by_name(&cube_name) {
+            Ok(cube) => cube,
+            Err(_) => continue,
+        };
+
+        let measures = additive_measures(cube);
+        if measures.is_empty() {
+            continue;
+        }
+
+        let mut ts_query = TsQuery::new();
+        ts_query.drilldowns = levels.iter().map(|level| Drilldown(level.clone())).collect();
+        ts_query.measures = measures.iter().map(|mea| Measure(mea.clone())).collect();
+
+        let backend = req.state().cube_backend(cube);
+        let (table, sql) = match preaggregation_sql(&schema, &**backend, cube, &ts_query, &levels) {
+            Ok(table_sql) => table_sql,
+            Err(err) => {
+                debug!("Could not generate pre-aggregation for cube {}: {}", cube_name, err);
+                continue;
+            },
+        };
+
+        preaggregations.push(Preaggregation {
+            cube: cube_name,
+            levels: levels.iter().map(|level| level.to_string()).collect(),
+            measures,
+            count: stat.count,
+            total_ms: stat.total_ms,
+            avg_ms: stat.total_ms / stat.count,
+            table,
+            sql,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(preaggregations))
+}
+
+/// Measures whose aggregations can be aggregated again, from a pre-aggregation table.
+fn additive_measures(cube: &Cube) -> Vec<String> {
+    cube.measures.iter()
+        .filter(|mea| match mea.aggregator {
+            Aggregator::Sum | Aggregator::Count | Aggregator::Max | Aggregator::Min => true,
+            _ => false,
+        })
+        .map(|mea| mea.name.clone())
+        .collect()
+}
+
+/// Name of the pre-aggregation table and the statement creating it.
+fn preaggregation_sql(
+    schema: &Schema,
+    backend: &dyn Backend,
+    cube: &Cube,
+    ts_query: &TsQuery,
+    levels: &[LevelName],
+    ) -> Result<(String, String), Error>
+{
+    let (query_ir, _headers) = schema.sql_query(&cube.name, ts_query, None)?;
+
+    let table = table_name(&cube.name, levels);
+    let sql = backend.generate_sql(query_ir);
+
+    let create_sql = backend.dialect().create_table_as_sql(&table, &sql);
+    Ok((table, create_sql))
+}
+
+/// Table name from the cube and level names, e.g. `sales_agg_year_category`.
+fn table_name(cube: &str, levels: &[LevelName]) -> String {
+    let name = Some(cube)
+        .into_iter()
+        .chain(Some("agg"))
+        .chain(levels.iter().map(|level| level.level.as_str()))
+        .collect::<Vec<_>>()
+        .join("_");
+
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
@@ -0,0 +1,167 @@
+//! Query options shared by the aggregate and logic layer endpoints.
+//!
+//! Both endpoints declare their options struct through `query_opt!`, which appends the shared
+//! options, so an option added here is parsed (and documented) by both. Options which name
+//! levels, like drilldowns, cuts or growth, are read differently by each endpoint and stay on
+//! its own struct.
+//!
+//! The shared options are appended as plain fields instead of a `#[serde(flatten)]` struct,
+//! so that the OpenAPI docs, which read the fields of each struct, still list them.
+
+use failure::{Error, bail};
+use tesseract_core::{Query as TsQuery, ShowColumns, UnitConversion};
+use tesseract_core::format::FormatType;
+use tesseract_core::names::{split_quoted, unquote};
+
+
+/// Declares a query options struct, with the shared options added after its own fields.
+macro_rules! query_opt {
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                $field_vis:vis $field:ident: $field_ty:ty,
+            )*
+        }
+    ) => {
+        $(#[$struct_attr])*
+        pub struct $name {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $field_ty,
+            )*
+            top_where: Option<String>,
+            sort: Option<String>,
+            limit: Option<String>,
+            debug: Option<bool>,
+            exclude_default_members: Option<bool>,
+            sparse: Option<bool>,
+            priority: Option<String>,
+            sample: Option<f64>,
+            dataframe_growth: Option<bool>,
+            column_types: Option<bool>,
+            convert: Option<String>,
+            distinct: Option<bool>,
+            columns: Option<String>,
+            show: Option<String>,
+            treat_null_as: Option<String>,
+        }
+
+        impl $name {
+            /// The options shared by the aggregate and logic layer endpoints.
+            pub fn shared(&self) -> $crate::handlers::query_opt::SharedQueryOpt {
+                $crate::handlers::query_opt::SharedQueryOpt {
+                    top_where: self.top_where.clone(),
+                    sort: self.sort.clone(),
+                    limit: self.limit.clone(),
+                    debug: self.debug,
+                    exclude_default_members: self.exclude_default_members,
+                    sparse: self.sparse,
+                    priority: self.priority.clone(),
+                    sample: self.sample,
+                    dataframe_growth: self.dataframe_growth,
+                    column_types: self.column_types,
+                    convert: self.convert.clone(),
+                    distinct: self.distinct,
+                    columns: self.columns.clone(),
+                    show: self.show.clone(),
+                    treat_null_as: self.treat_null_as.clone(),
+                }
+            }
+        }
+    };
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct SharedQueryOpt {
+    pub top_where: Option<String>,
+    pub sort: Option<String>,
+    pub limit: Option<String>,
+    pub debug: Option<bool>,
+    pub exclude_default_members: Option<bool>,
+    pub sparse: Option<bool>,
+    pub priority: Option<String>,
+    /// Only the aggregate and logic layer handlers run queries on a sample, since they scale
+    /// the results; it's not set on the query by `apply`.
+    pub sample: Option<f64>,
+    pub dataframe_growth: Option<bool>,
+    pub column_types: Option<bool>,
+    pub convert: Option<String>,
+    pub distinct: Option<bool>,
+    /// Only the handlers formatting results project them; it's not set on the query by
+    /// `apply`.
+    pub columns: Option<String>,
+    pub show: Option<String>,
+    pub treat_null_as: Option<String>,
+}
+
+impl SharedQueryOpt {
+    /// Sets the shared options on a query.
+    pub fn apply(&self, ts_query: &mut TsQuery) -> Result<(), Error> {
+        ts_query.top_where = self.top_where.as_ref()
+            .map(|t| t.parse())
+            .transpose()?;
+        ts_query.sort = self.sort.as_ref()
+            .map(|s| s.parse())
+            .transpose()?;
+        ts_query.limit = self.limit.as_ref()
+            .map(|l| l.parse())
+            .transpose()?;
+        ts_query.debug = self.debug.unwrap_or(false);
+        ts_query.exclude_default_members = self.exclude_default_members.unwrap_or(false);
+        ts_query.sparse = self.sparse.unwrap_or(false);
+        ts_query.priority = self.priority.as_ref()
+            .map(|p| p.parse())
+            .transpose()?;
+        ts_query.distinct = self.distinct.unwrap_or(false);
+        ts_query.show = self.show()?;
+        ts_query.treat_null_as = self.treat_null_as.as_ref()
+            .map(|t| t.parse())
+            .transpose()?;
+
+        Ok(())
+    }
+
+    /// Unit conversions of measures, comma separated as `Measure:unit`.
+    pub fn conversions(&self) -> Result<Vec<UnitConversion>, Error> {
+        match self.convert {
+            Some(ref convert) => convert.split(',').map(|c| c.parse()).collect(),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Columns of the drilldown levels to return, `ids`, `labels` or `both` (default).
+    pub fn show(&self) -> Result<ShowColumns, Error> {
+        match self.show {
+            Some(ref show) => show.parse(),
+            None => Ok(ShowColumns::Both),
+        }
+    }
+
+    /// Headers of the columns to keep in the results, comma separated. Headers with commas
+    /// are quoted in backticks.
+    pub fn columns(&self) -> Result<Option<Vec<String>>, Error> {
+        let columns = match self.columns {
+            Some(ref columns) => columns,
+            None => return Ok(None),
+        };
+
+        let columns = split_quoted(columns, ',')?.into_iter()
+            .map(|column| unquote(column.trim()))
+            .filter(|column| !column.is_empty())
+            .collect();
+
+        Ok(Some(columns))
+    }
+
+    /// Whether the column types are added to the results, which only `jsonrecords` supports.
+    pub fn column_types(&self, format: &FormatType) -> Result<bool, Error> {
+        match (self.column_types.unwrap_or(false), format) {
+            (false, _) => Ok(false),
+            (true, FormatType::JsonRecords) => Ok(true),
+            (true, _) => bail!("Column types are only supported by the jsonrecords format"),
+        }
+    }
+}
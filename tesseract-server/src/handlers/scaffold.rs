@@ -0,0 +1,134 @@
+use actix_web::{
+    AsyncResponder,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+};
+use failure::Error;
+use futures::future::{self, Future};
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::Deserialize;
+use serde_qs as qs;
+use tesseract_core::{Backend, ColumnInfo};
+use tesseract_core::schema::scaffold::{distinct_counts_sql, scaffold_cube};
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use super::util::check_secret;
+
+
+const DEFAULT_MAX_DIMENSION_MEMBERS: u64 = 1000;
+
+
+#[derive(Debug, Deserialize)]
+pub struct ScaffoldQueryOpt {
+    pub secret: String,
+    /// Named data source the table is in; the default database if not set.
+    pub data_source: Option<String>,
+    /// Columns with more distinct values than this are not made dimensions.
+    pub max_members: Option<u64>,
+}
+
+/// Lists the tables of the database, to pick one to scaffold a cube for. Admin only;
+/// protected by the flush secret.
+pub fn scaffold_tables_handler(req: HttpRequest<AppState>) -> FutureResponse<HttpResponse> {
+    let (_, backend) = match scaffold_backend(&req) {
+        Ok(query_backend) => query_backend,
+        Err(res) => return Box::new(future::ok(res)),
+    };
+
+    backend
+        .inspect_tables()
+        .map(|tables| HttpResponse::Ok().json(tables))
+        .map_err(move |e| ServerError::from_query_error(&e, req.state().debug).into())
+        .responder()
+}
+
+/// Drafts a cube schema (in the json format) for an existing fact table, from its columns
+/// and their number of distinct values. Admin only; protected by the flush secret.
+pub fn scaffold_handler(
+    (req, table): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let table = table.into_inner();
+
+    let (query, backend) = match scaffold_backend(&req) {
+        Ok(query_backend) => query_backend,
+        Err(res) => return Box::new(future::ok(res)),
+    };
+
+    // the table name goes into sql
+    if !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        return Box::new(future::ok(
+            HttpResponse::BadRequest().json(format!("Invalid table name {}", table))
+        ));
+    }
+
+    let max_members = query.max_members.unwrap_or(DEFAULT_MAX_DIMENSION_MEMBERS);
+
+    info!("Scaffold cube for table {}", table);
+
+    let counts_backend = backend.clone();
+    let counts_table = table.clone();
+
+    backend
+        .inspect_columns(&table)
+        .and_then(move |columns| -> Box<dyn Future<Item=Option<(Vec<ColumnInfo>, Vec<u64>)>, Error=Error>> {
+            // table not found
+            if columns.is_empty() {
+                return Box::new(future::ok(None));
+            }
+
+            let sql = distinct_counts_sql(&*counts_backend.dialect(), &counts_table, &columns);
+
+            Box::new(counts_backend.exec_sql(sql).map(move |df| {
+                let distinct_counts: Vec<u64> = df.columns.iter()
+                    .map(|col| {
+                        col.stringify_column_data().get(0)
+                            .and_then(|count| count.parse().ok())
+                            .unwrap_or(0)
+                    })
+                    .collect();
+
+                Some((columns, distinct_counts))
+            }))
+        })
+        .map(move |res| {
+            match res {
+                Some((columns, distinct_counts)) => {
+                    HttpResponse::Ok().json(scaffold_cube(&table, &columns, &distinct_counts, max_members))
+                },
+                None => HttpResponse::NotFound().json(format!("Table {} not found", table)),
+            }
+        })
+        .map_err(move |e| ServerError::from_query_error(&e, req.state().debug).into())
+        .responder()
+}
+
+
+/// Checks the secret of a scaffold request, and picks the backend of its data source.
+fn scaffold_backend(
+    req: &HttpRequest<AppState>,
+    ) -> Result<(ScaffoldQueryOpt, Box<dyn Backend + Sync + Send>), HttpResponse>
+{
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let query = QS_NON_STRICT.deserialize_str::<ScaffoldQueryOpt>(req.query_string())
+        .map_err(|err| HttpResponse::BadRequest().json(err.to_string()))?;
+
+    check_secret(req, &query.secret)?;
+
+    let backend = match query.data_source {
+        Some(ref data_source) => match req.state().data_sources.get(&data_source.to_lowercase()) {
+            Some(backend) => backend.clone(),
+            None => return Err(HttpResponse::NotFound().json(format!("Data source {} not found", data_source))),
+        },
+        None => req.state().backend.clone(),
+    };
+
+    Ok((query, backend))
+}
@@ -1,12 +1,21 @@
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 use actix_web::{
     FutureResponse,
     HttpRequest,
     HttpResponse,
+    Result as ActixResult,
 };
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header::{HeaderValue, VARY};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{Middleware, Response};
 use futures::future::{self};
 use actix_web::http::header::ContentType;
 use log::*;
+use serde_derive::{Serialize, Deserialize};
 use mime;
 use r2d2_redis::{r2d2, redis, RedisConnectionManager};
 
@@ -14,10 +23,16 @@ use tesseract_core::format::FormatType;
 use tesseract_core::schema::Cube;
 use tesseract_core::schema::metadata::SourceMetadata;
 
-use crate::app::AppState;
+use crate::app::{AppState, QueryLimits};
 
-use failure::{bail, format_err, Error};
-use tesseract_core::names::Cut;
+use failure::{bail, format_err, Error, Fail};
+use tesseract_core::{
+    AllMemberColumn, Backend, Column, DataFrame, GrowthColumns, PreviousYearColumns, QueryIr,
+    Query as TsQuery, ShareColumns, UnitConversion,
+};
+use tesseract_core::query_ir::LimitSql;
+use tesseract_core::query::LimitQuery;
+use tesseract_core::names::{Cut, LevelName, Mask};
 use crate::logic_layer::CubeCache;
 use crate::auth::{validate_web_token, extract_token, user_auth_level};
 
@@ -26,9 +41,100 @@ pub(crate) fn format_to_content_type(format_type: &FormatType) -> ContentType {
         FormatType::Csv => ContentType(mime::TEXT_CSV_UTF_8),
         FormatType::JsonRecords => ContentType(mime::APPLICATION_JSON),
         FormatType::JsonArrays => ContentType(mime::APPLICATION_JSON),
+        FormatType::GeoJson => ContentType("application/geo+json".parse().expect("valid mime type")),
+    }
+}
+
+
+/// Media types accepted for formats in `Accept` headers, and the format of each.
+const ACCEPT_FORMATS: &[(&str, &str)] = &[
+    ("text/csv", "csv"),
+    ("text/*", "csv"),
+    ("application/json", "jsonrecords"),
+    ("application/geo+json", "geojson"),
+    ("application/*", "jsonrecords"),
+];
+
+/// Picks the format of a request without a `.{format}` suffix from its `Accept` header, for
+/// clients that can't change the path. Without the header, the format is `default`. Returns
+/// a `406 Not Acceptable` response if none of the accepted media types are supported.
+pub fn negotiate_format(req: &HttpRequest<AppState>, default: &str) -> Result<String, HttpResponse> {
+    req.extensions_mut().insert(NegotiatedFormat);
+
+    let accept = match req.headers().get("accept").and_then(|h| h.to_str().ok()) {
+        Some(accept) => accept,
+        None => return Ok(default.to_owned()),
+    };
+
+    accept_format(accept, default)
+        .map(|format| format.to_owned())
+        .ok_or_else(|| {
+            let supported: Vec<_> = ACCEPT_FORMATS.iter().map(|(media_type, _)| *media_type).collect();
+            HttpResponse::NotAcceptable()
+                .json(format!("None of the accepted media types are supported: {}", supported.join(", ")))
+        })
+}
+
+/// Marks a request whose format is picked from its `Accept` header.
+struct NegotiatedFormat;
+
+/// The `Vary` header of a response: all responses may be compressed, and those of requests
+/// with a negotiated format also depend on their `Accept` header, which caches must key on.
+fn vary_header(negotiated: bool) -> &'static str {
+    if negotiated {
+        "Accept, Accept-Encoding"
+    } else {
+        "Accept-Encoding"
+    }
+}
+
+/// Middleware setting the `Vary` header on responses which don't set their own.
+pub struct VaryHeader;
+
+impl<S> Middleware<S> for VaryHeader {
+    fn response(&self, req: &HttpRequest<S>, mut resp: HttpResponse) -> ActixResult<Response> {
+        if !resp.headers().contains_key(VARY) {
+            let negotiated = req.extensions().get::<NegotiatedFormat>().is_some();
+            resp.headers_mut().insert(VARY, HeaderValue::from_static(vary_header(negotiated)));
+        }
+
+        Ok(Response::Done(resp))
     }
 }
 
+/// The format of the supported media type with the highest quality in an `Accept` header,
+/// or `default` for `*/*`.
+fn accept_format<'a>(accept: &str, default: &'a str) -> Option<&'a str> {
+    let mut media_ranges: Vec<(String, f32)> = accept.split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let media_type = params.next()?.trim().to_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .next()
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            Some((media_type, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    // stable, so types of the same quality keep their order
+    media_ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    media_ranges.iter()
+        .find_map(|(media_type, _)| {
+            if media_type == "*/*" {
+                return Some(default);
+            }
+
+            ACCEPT_FORMATS.iter()
+                .find(|(accepted, _)| accepted == media_type)
+                .map(|(_, format)| *format)
+        })
+}
+
 
 /// Helper method to return errors (FutureResponse<HttpResponse>) from String.
 pub fn boxed_error_string(message: String) -> FutureResponse<HttpResponse> {
@@ -84,6 +190,15 @@ pub fn verify_authorization(req: &HttpRequest<AppState>, min_auth_level: i32) ->
     Ok(())
 }
 
+/// Checks the `secret` of an admin request against the server's flush secret; admin
+/// endpoints are disabled without one.
+pub fn check_secret(req: &HttpRequest<AppState>, secret: &str) -> Result<(), HttpResponse> {
+    match &req.state().env_vars.flush_secret {
+        Some(flush_secret) if flush_secret == secret => Ok(()),
+        _ => Err(HttpResponse::Unauthorized().finish()),
+    }
+}
+
 
 #[macro_export]
 macro_rules! ok_or_400 {
@@ -119,6 +234,23 @@ macro_rules! ok_or_404 {
 }
 
 
+#[macro_export]
+macro_rules! ok_or_422 {
+    ($expr:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => {
+                return Box::new(
+                    future::result(
+                        Ok(HttpResponse::UnprocessableEntity().json(err.to_string()))
+                    )
+                );
+            }
+        }
+    };
+}
+
+
 #[macro_export]
 macro_rules! some_or_404 {
     ($expr:expr, $note:expr) => {
@@ -138,18 +270,451 @@ macro_rules! some_or_404 {
 
 pub fn validate_members(cuts: &[Cut], cube_cache: &CubeCache) -> Result<(), Error> {
     for cut in cuts {
-        // get level cache
-        let member_cache = cube_cache.members_for_level(&cut.level_name)
-            .ok_or_else(|| format_err!("Level not found in cache"))?;
         for member in &cut.members {
-            if !member_cache.contains(member) {
-                bail!("Cut member not found");
-            }
+            validate_member(&cut.level_name, member, cube_cache)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a member is in the level's cache. Members of levels too large to cache can't
+/// be checked, so they may only have characters which are safe to put into sql.
+pub fn validate_member(level_name: &LevelName, member: &str, cube_cache: &CubeCache) -> Result<(), Error> {
+    if let Some(member_cache) = cube_cache.members_for_level(level_name) {
+        if !member_cache.contains(member) {
+            bail!("Cut member not found");
+        }
+    } else if cube_cache.member_count(level_name).is_some() {
+        let is_safe = !member.is_empty() && member.chars().all(|c| c.is_alphanumeric() || "-_. ".contains(c));
+        if !is_safe {
+            bail!("Cut member not found");
+        }
+    } else {
+        bail!("Level not found in cache");
+    }
+    Ok(())
+}
+
+
+/// Checks the query against the server's `QueryLimits`. The result size is estimated as the
+/// product of the number of members of each drilldown level (or of its cut, if the level is
+/// also cut), times the number of measures.
+pub fn validate_query_limits(ts_query: &TsQuery, cube_cache: &CubeCache, limits: &QueryLimits) -> Result<(), Error> {
+    if let Some(max_drilldowns) = limits.max_drilldowns {
+        if ts_query.drilldowns.len() > max_drilldowns {
+            bail!("Query has {} drilldowns, the maximum is {}", ts_query.drilldowns.len(), max_drilldowns);
+        }
+    }
+
+    if let Some(max_cut_members) = limits.max_cut_members {
+        let cut_members: usize = ts_query.cuts.iter().map(|cut| cut.members.len()).sum();
+        if cut_members > max_cut_members {
+            bail!("Query cuts on {} members, the maximum is {}", cut_members, max_cut_members);
+        }
+    }
+
+    if let Some(max_result_cells) = limits.max_result_cells {
+        let mut cells = ts_query.measures.len().max(1) as u64;
+
+        for drilldown in &ts_query.drilldowns {
+            let cut_members = ts_query.cuts.iter()
+                .find(|cut| cut.level_name == drilldown.0 && cut.mask == Mask::Include)
+                .map(|cut| cut.members.len());
+
+            // levels missing from the cache can't be estimated
+            let members = cut_members
+                .or_else(|| cube_cache.member_count(&drilldown.0))
+                .unwrap_or(1);
+
+            cells = cells.saturating_mul(members as u64);
+        }
+
+        if cells > max_result_cells {
+            bail!(
+                "Query could return up to {} cells, the maximum is {}; try fewer drilldowns or adding cuts",
+                cells, max_result_cells,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Measures are scaled up after a sampled query, but filters, `top_where` and `sparse` are
+/// applied in the database on the unscaled values, so they can't be combined with `sample`.
+pub fn check_sample(ts_query: &TsQuery) -> Result<(), Error> {
+    if ts_query.sample.is_some() && (!ts_query.filters.is_empty() || ts_query.top_where.is_some() || ts_query.sparse) {
+        bail!("sample cannot be combined with filters, top_where or sparse");
+    }
+
+    Ok(())
+}
+
+/// Response header with when the cube's data was last updated, if known.
+pub const DATA_UPDATED_HEADER: &str = "X-Data-Updated";
+
+/// Response header with the fraction of the fact table the results were computed on.
+pub const SAMPLE_HEADER: &str = "X-Tesseract-Sample";
+
+/// Response header with the number of members of a level, including members not returned,
+/// e.g. those outside a `parent`.
+pub const MEMBER_COUNT_HEADER: &str = "X-Tesseract-Member-Count";
+
+/// Rows returned from each end of the results by `preview`.
+pub const PREVIEW_ROWS: u64 = 50;
+
+/// Response header with the total number of rows of a `preview` query.
+pub const PREVIEW_TOTAL_HEADER: &str = "X-Tesseract-Total-Rows";
+
+/// Response header with the units measures were converted to, as `Measure:unit,Measure:unit`.
+pub const UNITS_HEADER: &str = "X-Tesseract-Units";
+
+/// Value of the `UNITS_HEADER`.
+pub fn units_header(conversions: &[UnitConversion]) -> String {
+    conversions.iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Response header set when results were cut at the server's `max_rows`.
+pub const TRUNCATED_HEADER: &str = "X-Tesseract-Truncated";
+
+/// Response header with the cube a data query ran on.
+pub const CUBE_HEADER: &str = "X-Tesseract-Cube";
+
+/// Response header with the number of rows of a data query's results.
+pub const ROW_COUNT_HEADER: &str = "X-Tesseract-Row-Count";
+
+/// Response header with the time the database took to run a data query, in milliseconds.
+pub const QUERY_MS_HEADER: &str = "X-Tesseract-Query-Ms";
+
+/// Response header with `hit` when a data query's results came from the Redis cache,
+/// and `miss` otherwise.
+pub const CACHE_HEADER: &str = "X-Tesseract-Cache";
+
+/// Sets the headers with a data query's metadata, so that gateways and clients can log
+/// queries without parsing the results.
+pub fn set_query_headers(response: &mut HttpResponseBuilder, cube: &str, rows: usize, query_time: Duration) {
+    set_cached_query_headers(response, cube, rows, query_time.as_millis() as u64, "miss");
+}
+
+fn set_cached_query_headers(response: &mut HttpResponseBuilder, cube: &str, rows: usize, query_ms: u64, cache: &str) {
+    response
+        .header(CUBE_HEADER, cube)
+        .header(ROW_COUNT_HEADER, rows.to_string())
+        .header(QUERY_MS_HEADER, query_ms.to_string())
+        .header(CACHE_HEADER, cache);
+}
+
+/// Sets the server's `max_rows` as the limit of a query without one, or with a larger one.
+/// One extra row is requested, so that `truncate_rows` can tell whether there were more
+/// results. Returns the number of rows to keep, if the limit was applied.
+pub fn apply_max_rows(ts_query: &mut TsQuery, limits: &QueryLimits) -> Option<u64> {
+    let max_rows = limits.max_rows?;
+
+    // previous year comparisons query extra rows, which are dropped from the results, so
+    // they're limited once compared (see `take_previous_year`)
+    if ts_query.vs_previous_year.is_some() {
+        return Some(max_rows);
+    }
+
+    match ts_query.limit {
+        Some(ref mut limit) if limit.n > max_rows => {
+            limit.n = max_rows.saturating_add(1);
+            Some(max_rows)
+        },
+        Some(_) => None,
+        None => {
+            ts_query.limit = Some(LimitQuery { offset: None, n: max_rows.saturating_add(1) });
+            Some(max_rows)
+        },
+    }
+}
+
+/// Cuts the results at `max_rows` (from `apply_max_rows`). Returns whether rows were dropped.
+pub fn truncate_rows(df: &mut DataFrame, max_rows: Option<u64>) -> bool {
+    match max_rows {
+        Some(max_rows) if df.len() as u64 > max_rows => {
+            df.truncate(max_rows as usize);
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Like `truncate_rows`, for results with the totals rows of `DataFrame::with_totals`
+/// appended after their first `data_len` rows. Only those rows are cut, and the totals,
+/// which are of all of them, are kept.
+pub fn truncate_rows_before_totals(df: &mut DataFrame, max_rows: Option<u64>, data_len: usize) -> bool {
+    match max_rows {
+        Some(max_rows) if data_len as u64 > max_rows => {
+            let rows: Vec<Option<usize>> = (0..max_rows as usize)
+                .chain(data_len..df.len())
+                .map(Some)
+                .collect();
+            let columns = df.columns.iter()
+                .map(|col| Column::new(col.name.clone(), col.column_data.take_nullable(&rows)))
+                .collect();
+
+            *df = DataFrame::from_vec(columns);
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Takes growth out of the query to calculate it on the results (with
+/// `DataFrame::with_growth`), when `dataframe_growth` is set or the backend's dialect can't
+/// generate growth sql.
+pub fn take_dataframe_growth(
+    query_ir: &mut QueryIr,
+    backend: &dyn Backend,
+    dataframe_growth: bool,
+    ) -> Option<GrowthColumns>
+{
+    if dataframe_growth || !backend.dialect().supports_growth() {
+        query_ir.take_growth()
+    } else {
+        None
+    }
+}
+
+/// Takes the previous year comparison out of the query. When the backend's dialect can
+/// compare in sql (see `SqlDialect::supports_previous_year`), `dataframe_growth` isn't set and
+/// the results don't get All member columns, which aren't in the sql, its columns are
+/// returned first, to wrap the query's sql with `SqlDialect::previous_year_sql`. Otherwise
+/// they're returned second, to compare on the results with `DataFrame::add_previous_year`,
+/// and the query reads at most twice `max_rows` rows, with the previous year's (see
+/// `check_previous_year_rows`).
+pub fn take_previous_year(
+    query_ir: &mut QueryIr,
+    backend: &dyn Backend,
+    dataframe_growth: bool,
+    max_rows: Option<u64>,
+    ) -> (Option<PreviousYearColumns>, Option<PreviousYearColumns>)
+{
+    let columns = query_ir.previous_year.take();
+
+    if !dataframe_growth && query_ir.all_members.is_empty() && backend.dialect().supports_previous_year() {
+        (columns, None)
+    } else {
+        if columns.is_some() {
+            query_ir.limit = max_rows.map(|max_rows| LimitSql { offset: None, n: max_rows.saturating_mul(2).saturating_add(1) });
+        }
+        (None, columns)
+    }
+}
+
+/// Errors when the rows read to compare with the previous year on the results reached the
+/// limit set by `take_previous_year`, since some of them couldn't be compared.
+pub fn check_previous_year_rows(df: &DataFrame, max_rows: Option<u64>) -> Result<(), Error> {
+    match max_rows {
+        Some(max_rows) if df.len() as u64 > max_rows.saturating_mul(2) => {
+            bail!("Too many rows to compare with the previous year (the limit is {}); add cuts to the query", max_rows)
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Steps run on the results of a query the same way on every endpoint, by `post_process`.
+/// Most are taken out of the query's `QueryIr` by `PostProcess::take`, since they're not
+/// in its sql.
+#[derive(Debug, Default)]
+pub struct PostProcess {
+    pub all_members: Vec<AllMemberColumn>,
+    pub null_zero_idxs: Vec<usize>,
+    pub key_widths: Vec<(usize, usize)>,
+    /// Factor by which the measures in these columns are scaled up, on a sample.
+    pub sample_scale: Option<(f64, Vec<usize>)>,
+    pub growth_columns: Option<GrowthColumns>,
+    pub previous_year_columns: Option<PreviousYearColumns>,
+    pub share_columns: Option<ShareColumns>,
+    /// Rows to cut the results at, which limits the rows compared with the previous year
+    /// (see `check_previous_year_rows`).
+    pub max_rows: Option<u64>,
+}
+
+impl PostProcess {
+    /// Takes the steps on the results out of the query, before its sql is generated. Also
+    /// returns the columns of a previous year comparison made in sql, if any (see
+    /// `take_previous_year`).
+    pub fn take(
+        query_ir: &mut QueryIr,
+        backend: &dyn Backend,
+        dataframe_growth: bool,
+        max_rows: Option<u64>,
+        ) -> (PostProcess, Option<PreviousYearColumns>)
+    {
+        let growth_columns = take_dataframe_growth(query_ir, backend, dataframe_growth);
+        let share_columns = query_ir.share.take();
+        // before the All member columns are taken, which keep the comparison out of sql
+        let (sql_previous_year, previous_year_columns) = take_previous_year(query_ir, backend, dataframe_growth, max_rows);
+
+        let post_process = PostProcess {
+            all_members: std::mem::replace(&mut query_ir.all_members, vec![]),
+            null_zero_idxs: std::mem::replace(&mut query_ir.null_zero_idxs, vec![]),
+            key_widths: std::mem::replace(&mut query_ir.key_widths, vec![]),
+            sample_scale: None,
+            growth_columns,
+            previous_year_columns,
+            share_columns,
+            max_rows,
+        };
+
+        (post_process, sql_previous_year)
+    }
+
+    /// Whether growth, share or the previous year comparison are calculated on the results.
+    pub fn has_calculations(&self) -> bool {
+        self.growth_columns.is_some() || self.share_columns.is_some() || self.previous_year_columns.is_some()
+    }
+}
+
+/// Error of a step of `post_process`, with the status of its response.
+#[derive(Debug, Fail)]
+#[fail(display="{}", error)]
+pub struct PostProcessError {
+    pub status: StatusCode,
+    pub error: Error,
+}
+
+impl PostProcessError {
+    fn with_status(status: StatusCode) -> impl Fn(Error) -> PostProcessError {
+        move |error| PostProcessError { status, error }
+    }
+
+    /// Response with the error's message, like the handlers' other errors.
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(self.error.to_string())
+    }
+}
+
+/// Runs the `steps` on the results of its query: All member columns, null
+/// zeros and key padding first, then the scaling of a sample's measures, which the
+/// calculations are of, then growth, the previous year comparison and share. The results
+/// aren't truncated, since the calculations are of all the rows.
+pub fn post_process(mut df: DataFrame, steps: &PostProcess) -> Result<DataFrame, PostProcessError> {
+    let not_found = PostProcessError::with_status(StatusCode::NOT_FOUND);
+    let internal = PostProcessError::with_status(StatusCode::INTERNAL_SERVER_ERROR);
+
+    df.insert_all_members(&steps.all_members).map_err(&not_found)?;
+    df.fill_null_zeros(&steps.null_zero_idxs).map_err(&internal)?;
+    df.pad_keys(&steps.key_widths).map_err(&internal)?;
+
+    if let Some((factor, ref mea_idxs)) = steps.sample_scale {
+        for &idx in mea_idxs {
+            df.columns[idx].column_data = df.columns[idx].column_data.scale(factor).map_err(&internal)?;
         }
     }
+
+    if let Some(ref growth_columns) = steps.growth_columns {
+        df = df.with_growth(growth_columns).map_err(&not_found)?;
+    }
+
+    if let Some(ref previous_year_columns) = steps.previous_year_columns {
+        check_previous_year_rows(&df, steps.max_rows)
+            .map_err(PostProcessError::with_status(StatusCode::BAD_REQUEST))?;
+        df.add_previous_year(previous_year_columns)
+            .map_err(PostProcessError::with_status(StatusCode::UNPROCESSABLE_ENTITY))?;
+    }
+
+    if let Some(ref share_columns) = steps.share_columns {
+        df.add_share(share_columns).map_err(&internal)?;
+    }
+
+    Ok(df)
+}
+
+/// Drops the ID columns of levels with labels when `show_labels` is set, then keeps only
+/// `columns`, if given; after `post_process` and any reshaping of the results.
+pub fn select_columns(
+    df: &mut DataFrame,
+    headers: &mut Vec<String>,
+    show_labels: bool,
+    columns: Option<&[String]>,
+    ) -> Result<(), Error>
+{
+    if show_labels {
+        df.drop_id_columns(headers)?;
+    }
+
+    if let Some(columns) = columns {
+        df.project(headers, columns)?;
+    }
+
     Ok(())
 }
 
+/// Gets the `ETag` and `Last-Modified` values of a response on `cube` (or on all cubes, if
+/// `None`). The ETag is derived from the schema version, the cached `last_updated` and level
+/// member counts of the cubes, and `key`, which should identify the response (e.g. the path
+/// and query), so it changes on flush, when a cache refresh finds newer data or other
+/// members (even for cubes without a known last update), or for a different request.
+pub fn get_etag(req: &HttpRequest<AppState>, key: &str, cube: Option<&str>) -> (String, String) {
+    let cache = req.state().cache.read().unwrap();
+    let cube_caches: Vec<&CubeCache> = cache.cubes.iter()
+        .filter(|cube_cache| cube.map(|cube| cube_cache.name == cube).unwrap_or(true))
+        .collect();
+
+    let last_updated: Vec<Option<String>> = cube_caches.iter()
+        .map(|cube_cache| cube_cache.last_updated.clone())
+        .collect();
+
+    // sorted, as the level caches are in a hash map
+    let mut member_counts: Vec<(String, usize)> = cube_caches.iter()
+        .flat_map(|cube_cache| {
+            cube_cache.level_caches.iter()
+                .map(move |(level_name, level_cache)| (format!("{}/{}", cube_cache.name, level_name), level_cache.member_count))
+        })
+        .collect();
+    member_counts.sort();
+
+    let schema_version = req.state().schema_version.read().unwrap();
+
+    (etag(schema_version.hash, &last_updated, &member_counts, key), schema_version.last_modified.clone())
+}
+
+fn etag(schema_hash: u64, last_updated: &[Option<String>], member_counts: &[(String, usize)], key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    schema_hash.hash(&mut hasher);
+    last_updated.hash(&mut hasher);
+    member_counts.hash(&mut hasher);
+    key.hash(&mut hasher);
+
+    format!("\"{:x}\"", hasher.finish())
+}
+
+
+/// Returns a `304 Not Modified` response if the client's copy is still fresh, according to
+/// `If-None-Match`, or `If-Modified-Since` when there's no `If-None-Match`.
+pub fn check_not_modified(req: &HttpRequest<AppState>, etag: &str, last_modified: &str) -> Option<HttpResponse> {
+    let headers = req.headers();
+
+    let fresh = match headers.get("if-none-match").and_then(|h| h.to_str().ok()) {
+        Some(if_none_match) => {
+            if_none_match.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        },
+        None => {
+            headers.get("if-modified-since")
+                .and_then(|h| h.to_str().ok())
+                .map(|if_modified_since| if_modified_since == last_modified)
+                .unwrap_or(false)
+        },
+    };
+
+    if fresh {
+        Some(HttpResponse::NotModified()
+            .header("ETag", etag)
+            .header("Last-Modified", last_modified)
+            .finish())
+    } else {
+        None
+    }
+}
+
 
 /// Gets the Redis cache key for a given query.
 /// The sorting of query param keys is an attempt to increase cache hits.
@@ -170,32 +735,45 @@ pub fn get_redis_cache_key(prefix: &str, req: &HttpRequest<AppState>, cube: &str
         FormatType::Csv => "csv",
         FormatType::JsonArrays => "jsonarrays",
         FormatType::JsonRecords => "jsonrecords",
+        FormatType::GeoJson => "geojson",
     };
 
     format!("{}/{}/{}/{}", prefix, cube, format_str, qry_strings.join("&"))
 }
 
 
-/// Checks if the current query is already cached in Redis.
+/// Results of a query in the Redis cache, with the metadata of the query which made them, so
+/// that a hit has the same headers as a miss. The query time is that of the cached query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CachedResults {
+    body: String,
+    rows: usize,
+    query_ms: u64,
+}
+
+/// Checks if the current query is already cached in Redis. Entries which can't be read, e.g.
+/// cached without their metadata by an older version, are misses, and are overwritten.
 pub fn check_redis_cache(
         format: &FormatType,
         redis_pool: &Option<r2d2::Pool<RedisConnectionManager>>,
-        redis_cache_key: &str
+        redis_cache_key: &str,
+        cube: &str,
 ) -> Option<FutureResponse<HttpResponse>> {
     if let Some(rpool) = redis_pool {
         let conn_result = rpool.get();
 
         if let Ok(mut conn) = conn_result {
-            let redis_cache_result = redis::cmd("GET").arg(redis_cache_key).query(&mut *conn);
+            let redis_cache_result: redis::RedisResult<String> = redis::cmd("GET").arg(redis_cache_key).query(&mut *conn);
+            let cached = redis_cache_result.ok()
+                .and_then(|entry| serde_json::from_str::<CachedResults>(&entry).ok());
 
-            if let Ok(result_str) = redis_cache_result {
-                let result_str: &String = &result_str;
+            if let Some(cached) = cached {
                 let content_type = format_to_content_type(&format);
-                let response = HttpResponse::Ok()
-                    .set(content_type)
-                    .body(result_str);
+                let mut response = HttpResponse::Ok();
+                response.set(content_type);
+                set_cached_query_headers(&mut response, cube, cached.rows, cached.query_ms, "hit");
 
-                return Some(Box::new(future::result(Ok(response))));
+                return Some(Box::new(future::result(Ok(response.body(cached.body)))));
             }
         } else {
             debug!("Failed to get redis pool handle!");
@@ -207,18 +785,189 @@ pub fn check_redis_cache(
 }
 
 
-/// Inserts a new entry into the Redis cache.
+/// Inserts a new entry into the Redis cache, with the row count and time of the query.
 pub fn insert_into_redis_cache(
     res: &str,
+    rows: usize,
+    query_time: Duration,
     redis_pool: &Option<r2d2::Pool<RedisConnectionManager>>,
     redis_cache_key: &str
 ) {
     if let Some(rpool) = redis_pool {
         if let Ok(mut conn) = rpool.get() {
-            let rs: redis::RedisResult<String> = redis::cmd("SET").arg(redis_cache_key).arg(res).query(&mut *conn);
+            let cached = CachedResults {
+                body: res.to_owned(),
+                rows,
+                query_ms: query_time.as_millis() as u64,
+            };
+            let entry = match serde_json::to_string(&cached) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    debug!("Could not serialize cache entry {}: {}", redis_cache_key, err);
+                    return;
+                },
+            };
+
+            let rs: redis::RedisResult<String> = redis::cmd("SET").arg(redis_cache_key).arg(entry).query(&mut *conn);
             if rs.is_err() {
                 debug!("Error occurred when trying to save key: {}", redis_cache_key);
             }
         }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_etag() {
+        let updated = vec![Some("2020-01-01".to_owned())];
+        let counts = vec![("sales/Geography.Geography.State".to_owned(), 50)];
+        let tag = etag(1, &updated, &counts, "aggregate/sales");
+
+        assert_eq!(tag, etag(1, &updated, &counts, "aggregate/sales"));
+        assert!(tag.starts_with('"') && tag.ends_with('"'));
+
+        // newer data, another schema or another request
+        assert_ne!(tag, etag(1, &[Some("2020-01-02".to_owned())], &counts, "aggregate/sales"));
+        assert_ne!(tag, etag(1, &[None], &counts, "aggregate/sales"));
+        assert_ne!(tag, etag(2, &updated, &counts, "aggregate/sales"));
+        assert_ne!(tag, etag(1, &updated, &counts, "aggregate/other"));
+
+        // other members, with the same last update
+        assert_ne!(tag, etag(1, &updated, &[("sales/Geography.Geography.State".to_owned(), 51)], "aggregate/sales"));
+    }
+
+    #[test]
+    fn test_check_sample() {
+        let mut ts_query = TsQuery::new();
+        ts_query.filters = vec!["Quantity.gt.100".parse().unwrap()];
+        assert!(check_sample(&ts_query).is_ok());
+
+        ts_query.sample = Some(0.1);
+        assert!(check_sample(&ts_query).is_err());
+
+        ts_query.filters.clear();
+        assert!(check_sample(&ts_query).is_ok());
+
+        ts_query.top_where = Some("Quantity,gt.100".parse().unwrap());
+        assert!(check_sample(&ts_query).is_err());
+
+        ts_query.top_where = None;
+        ts_query.sparse = true;
+        assert!(check_sample(&ts_query).is_err());
+    }
+
+    #[test]
+    fn test_apply_max_rows() {
+        let limits = QueryLimits { max_rows: Some(100), ..QueryLimits::default() };
+        let limit = |ts_query: &TsQuery| ts_query.limit.as_ref().map(|limit| (limit.offset, limit.n));
+
+        let mut ts_query = TsQuery::new();
+        assert_eq!(apply_max_rows(&mut ts_query, &limits), Some(100));
+        assert_eq!(limit(&ts_query), Some((None, 101)));
+
+        // a smaller limit is kept, a larger one is capped
+        ts_query.limit = Some(LimitQuery { offset: Some(20), n: 10 });
+        assert_eq!(apply_max_rows(&mut ts_query, &limits), None);
+        assert_eq!(limit(&ts_query), Some((Some(20), 10)));
+
+        ts_query.limit = Some(LimitQuery { offset: Some(20), n: 1000 });
+        assert_eq!(apply_max_rows(&mut ts_query, &limits), Some(100));
+        assert_eq!(limit(&ts_query), Some((Some(20), 101)));
+
+        // previous year comparisons are limited once compared
+        let mut ts_query = TsQuery::new();
+        ts_query.vs_previous_year = Some(tesseract_core::names::Measure("Quantity".to_owned()));
+        assert_eq!(apply_max_rows(&mut ts_query, &limits), Some(100));
+        assert_eq!(limit(&ts_query), None);
+    }
+
+    #[test]
+    fn test_truncate_rows_before_totals() {
+        use tesseract_core::ColumnData;
+
+        // three rows and their total
+        let mut df = DataFrame::from_vec(vec![
+            Column::new("state".into(), ColumnData::NullableText(vec![Some("AL".into()), Some("AK".into()), Some("AZ".into()), None])),
+            Column::new("m0".into(), ColumnData::NullableInt64(vec![Some(1), Some(2), Some(3), Some(6)])),
+        ]);
+
+        assert!(!truncate_rows_before_totals(&mut df, Some(3), 3));
+        assert_eq!(df.len(), 4);
+
+        assert!(truncate_rows_before_totals(&mut df, Some(2), 3));
+        assert_eq!(df.columns[1].column_data, ColumnData::NullableInt64(vec![Some(1), Some(2), Some(6)]));
+    }
+
+    #[test]
+    fn test_post_process() {
+        use tesseract_core::ColumnData;
+
+        let df = || DataFrame::from_vec(vec![
+            Column::new("state".into(), ColumnData::Text(vec!["AL".into(), "AK".into()])),
+            Column::new("m0".into(), ColumnData::NullableInt64(vec![Some(1), None])),
+        ]);
+
+        // nulls are filled before the sample's measures are scaled up, and shares are of them
+        let steps = PostProcess {
+            null_zero_idxs: vec![1],
+            sample_scale: Some((10.0, vec![1])),
+            share_columns: Some(ShareColumns { group_idxs: vec![], mea_idx: 1 }),
+            ..PostProcess::default()
+        };
+        let processed = post_process(df(), &steps).unwrap();
+        assert_eq!(processed.columns[1].column_data, ColumnData::NullableFloat64(vec![Some(10.0), Some(0.0)]));
+        assert_eq!(processed.columns[2].column_data, ColumnData::NullableFloat64(vec![Some(1.0), Some(0.0)]));
+
+        let steps = PostProcess {
+            share_columns: Some(ShareColumns { group_idxs: vec![], mea_idx: 2 }),
+            ..PostProcess::default()
+        };
+        let err = post_process(df(), &steps).unwrap_err();
+        assert_eq!(err.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_accept_format() {
+        assert_eq!(accept_format("text/csv", "jsonrecords"), Some("csv"));
+        assert_eq!(accept_format("application/json, text/csv;q=0.5", "csv"), Some("jsonrecords"));
+        assert_eq!(accept_format("application/json;q=0.2, text/csv;q=0.5", "csv"), Some("csv"));
+        assert_eq!(accept_format("application/vnd.apache.arrow.stream, */*;q=0.1", "csv"), Some("csv"));
+        assert_eq!(accept_format("text/html,application/xhtml+xml,*/*;q=0.8", "jsonrecords"), Some("jsonrecords"));
+        assert_eq!(accept_format("application/vnd.apache.arrow.stream", "csv"), None);
+        assert_eq!(accept_format("text/csv;q=0", "csv"), None);
+    }
+
+    #[test]
+    fn test_query_headers() {
+        let mut response = HttpResponse::Ok();
+        set_query_headers(&mut response, "Sales", 12, Duration::from_millis(1500));
+        let response = response.finish();
+
+        let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok());
+        assert_eq!(header(CUBE_HEADER), Some("Sales"));
+        assert_eq!(header(ROW_COUNT_HEADER), Some("12"));
+        assert_eq!(header(QUERY_MS_HEADER), Some("1500"));
+        assert_eq!(header(CACHE_HEADER), Some("miss"));
+    }
+
+    #[test]
+    fn test_cached_results() {
+        let cached = CachedResults { body: "[]".to_owned(), rows: 0, query_ms: 20 };
+        let entry = serde_json::to_string(&cached).unwrap();
+        assert_eq!(serde_json::from_str::<CachedResults>(&entry).unwrap(), cached);
+
+        // entries cached without their metadata are misses
+        assert!(serde_json::from_str::<CachedResults>(r#"{"data":[]}"#).is_err());
+        assert!(serde_json::from_str::<CachedResults>("a,b\n1,2").is_err());
+    }
+
+    #[test]
+    fn test_vary_header() {
+        assert_eq!(vary_header(true), "Accept, Accept-Encoding");
+        assert_eq!(vary_header(false), "Accept-Encoding");
+    }
+}
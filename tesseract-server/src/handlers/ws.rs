@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix_web::{
+    ws,
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use failure::{Error, bail, format_err};
+use futures::future::Future;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use serde_qs as qs;
+use tesseract_core::format::{format_records, FormatType};
+use tesseract_core::{Query as TsQuery, ShowColumns};
+
+use crate::api_keys::ApiKeyMeter;
+use crate::app::AppState;
+use crate::auth::{extract_token, validate_web_token};
+use crate::errors::ServerError;
+use crate::rate_limit::client_key;
+use crate::subscriptions::Refresh;
+use super::aggregate::AggregateQueryOpt;
+use super::util::{
+    generate_source_data, post_process, select_columns, validate_members, validate_query_limits,
+    PostProcess,
+};
+
+
+/// Subscriptions a session can hold at once.
+const MAX_SUBSCRIPTIONS: usize = 20;
+
+/// Time between re-running each of a session's queries on a refresh.
+const REFRESH_QUERY_STAGGER: Duration = Duration::from_millis(100);
+
+
+/// Opens a WebSocket session for query subscriptions.
+pub fn ws_handler(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let session = WsSession {
+        id: None,
+        token: extract_token(req),
+        client_key: client_key(req),
+        api_key_meter: ApiKeyMeter::from_request(req),
+        queries: HashMap::new(),
+    };
+
+    ws::start(req, session)
+}
+
+
+/// Messages from the client. The query is an aggregate query string for the cube, e.g.
+/// `drilldowns[]=Year&measures[]=Quantity`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe {
+        name: String,
+        cube: String,
+        query: String,
+    },
+    Unsubscribe {
+        name: String,
+    },
+}
+
+
+#[derive(Debug, Clone)]
+struct Subscription {
+    cube: String,
+    query: String,
+}
+
+
+/// A client's subscribed queries, by name. Results are pushed as `jsonrecords` when
+/// subscribing, and again on every refresh of the query's cube.
+///
+/// Each subscribe counts against the client's rate limit, and each run of a query against
+/// its API key's quota, like requests do.
+pub struct WsSession {
+    id: Option<usize>,
+    token: String,
+    /// The client, for the rate limiter.
+    client_key: String,
+    api_key_meter: Option<ApiKeyMeter>,
+    queries: HashMap<String, Subscription>,
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self, AppState>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let recipient = ctx.address().recipient();
+        self.id = Some(ctx.state().subscriptions.add(recipient));
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if let Some(id) = self.id {
+            ctx.state().subscriptions.remove(id);
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for WsSession {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Text(text) => self.handle_text(&text, ctx),
+            ws::Message::Close(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+impl Handler<Refresh> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Refresh, ctx: &mut Self::Context) {
+        let names: Vec<_> = self.queries.iter()
+            .filter(|(_, sub)| msg.cube.as_ref().map(|cube| *cube == sub.cube).unwrap_or(true))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for (idx, name) in names.into_iter().enumerate() {
+            let delay = msg.delay + REFRESH_QUERY_STAGGER * idx as u32;
+
+            ctx.run_later(delay, move |act, ctx| {
+                // it may have been unsubscribed or replaced since
+                if let Some(sub) = act.queries.get(&name).cloned() {
+                    act.run_query(name, &sub, ctx);
+                }
+            });
+        }
+    }
+}
+
+impl WsSession {
+    fn handle_text(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self, AppState>) {
+        let msg = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                ctx.text(json!({ "error": format!("Could not parse message: {}", err) }).to_string());
+                return;
+            },
+        };
+
+        match msg {
+            ClientMessage::Subscribe { name, cube, query } => {
+                if let Err(err) = self.check_subscribe(&name, ctx.state()) {
+                    ctx.text(json!({ "name": name, "error": err.to_string() }).to_string());
+                    return;
+                }
+
+                let sub = Subscription { cube, query };
+                self.run_query(name.clone(), &sub, ctx);
+                self.queries.insert(name, sub);
+            },
+            ClientMessage::Unsubscribe { name } => {
+                self.queries.remove(&name);
+            },
+        }
+    }
+
+    /// A subscribe counts as a request for the rate limiter, and can't go over the
+    /// session's number of subscriptions, unless it replaces one.
+    fn check_subscribe(&self, name: &str, state: &AppState) -> Result<(), Error> {
+        check_subscription_count(&self.queries, name)?;
+
+        if let Some(ref rate_limiter) = state.rate_limiter {
+            if !rate_limiter.check(&self.client_key, Instant::now()).allowed {
+                bail!("Rate limit exceeded, please try again later");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the query and pushes its results, or an error, tagged with the subscription name.
+    fn run_query(&self, name: String, sub: &Subscription, ctx: &mut ws::WebsocketContext<Self, AppState>) {
+        if let Some(ref api_key_meter) = self.api_key_meter {
+            if let Err(err) = api_key_meter.count_request() {
+                ctx.text(json!({ "name": name, "error": err }).to_string());
+                return;
+            }
+        }
+
+        let fut = match query_future(ctx.state(), sub, &self.token, self.api_key_meter.clone()) {
+            Ok(fut) => fut,
+            Err(err) => {
+                ctx.text(json!({ "name": name, "error": err.to_string() }).to_string());
+                return;
+            },
+        };
+
+        let debug = ctx.state().debug;
+
+        ctx.spawn(fut.into_actor(self).then(move |res, _act, ctx| {
+            let msg = match res.and_then(|res| Ok(serde_json::from_str::<Value>(&res)?)) {
+                Ok(result) => json!({ "name": name, "result": result }),
+                Err(err) => {
+                    error!("Subscription {}: {}", name, err);
+                    let err = ServerError::from_query_error(&err, debug);
+                    json!({ "name": name, "error": err.message(), "status": err.status().as_u16() })
+                },
+            };
+            ctx.text(msg.to_string());
+            actix::fut::ok(())
+        }));
+    }
+}
+
+
+fn check_subscription_count(queries: &HashMap<String, Subscription>, name: &str) -> Result<(), Error> {
+    if queries.len() >= MAX_SUBSCRIPTIONS && !queries.contains_key(name) {
+        bail!("A connection can't have more than {} subscriptions", MAX_SUBSCRIPTIONS);
+    }
+    Ok(())
+}
+
+
+/// Checks and translates the subscription's query like the aggregate endpoint does, and
+/// returns the future of its results as `jsonrecords`.
+fn query_future(
+    state: &AppState,
+    sub: &Subscription,
+    token: &str,
+    api_key_meter: Option<ApiKeyMeter>,
+    ) -> Result<Box<dyn Future<Item=String, Error=Error>>, Error>
+{
+    let schema = state.schema.read().unwrap().clone();
+    let cube_obj = schema.get_cube_by_name(&sub.cube)?.clone();
+
+    if !validate_web_token(&state.env_vars.jwt_secret, token, cube_obj.min_auth_level) {
+        return Err(format_err!("Not authorized for cube {}", sub.cube));
+    }
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let agg_query = QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&sub.query)
+        .map_err(|err| format_err!("Could not parse query: {}", err))?;
+    let columns = agg_query.shared().columns()?;
+    let ts_query: TsQuery = agg_query.try_into()?;
+    let show_labels = ts_query.show == ShowColumns::Labels;
+
+    // sql injection mitigation on query:
+    // - Check that cut members exist in members cache
+    {
+        let cache = state.cache.read().unwrap();
+        let cube_cache = cache.find_cube_info(&sub.cube)
+            .ok_or_else(|| format_err!("Cube {} not found", sub.cube))?;
+        validate_members(&ts_query.cuts, &cube_cache)?;
+        validate_query_limits(&ts_query, &cube_cache, &state.env_vars.query_limits)?;
+    }
+
+    let (mut query_ir, mut headers) = schema.sql_query(&sub.cube, &ts_query, None)?;
+
+    let backend = state.cube_backend(&cube_obj);
+    let (steps, sql_previous_year) = PostProcess::take(&mut query_ir, &**backend, state.env_vars.dataframe_growth, None);
+    backend.capabilities().check(&query_ir)?;
+    let result_order = query_ir.result_order();
+    let sql = backend.generate_sql(query_ir);
+    let sql = match sql_previous_year {
+        Some(ref columns) => backend.dialect().previous_year_sql(&sql, columns, &result_order, None),
+        None => sql,
+    };
+
+    info!("Subscription sql query: {}", sql);
+
+    let source_data = Some(generate_source_data(&cube_obj));
+
+    let fut = backend
+        .exec_sql(sql)
+        .and_then(move |df| {
+            let mut df = post_process(df, &steps)?;
+            select_columns(&mut df, &mut headers, show_labels, columns.as_ref().map(|c| c.as_slice()))?;
+
+            if let Some(ref api_key_meter) = api_key_meter {
+                api_key_meter.record_rows(df.len());
+            }
+
+            format_records(&headers, df, FormatType::JsonRecords, None, source_data, false)
+        });
+
+    Ok(Box::new(fut))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_subscription_count() {
+        let mut queries = HashMap::new();
+        for i in 0..MAX_SUBSCRIPTIONS {
+            assert!(check_subscription_count(&queries, &i.to_string()).is_ok());
+            queries.insert(i.to_string(), Subscription { cube: "Sales".to_owned(), query: "".to_owned() });
+        }
+
+        assert!(check_subscription_count(&queries, "new").is_err());
+        // replacing a subscription doesn't add one
+        assert!(check_subscription_count(&queries, "0").is_ok());
+    }
+}
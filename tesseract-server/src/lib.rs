@@ -1,7 +1,19 @@
+pub mod api_keys;
 pub mod app;
+pub mod circuit_breaker;
+pub mod config_file;
 pub mod db_config;
 pub mod handlers;
+pub mod logging;
 pub mod logic_layer;
+pub mod query_stats;
+pub mod rate_limit;
+pub mod request_id;
+pub mod request_limits;
+pub mod retry;
 pub mod schema_config;
+pub mod shutdown;
+pub mod subscriptions;
 pub mod errors;
+pub mod export;
 pub mod auth;
\ No newline at end of file
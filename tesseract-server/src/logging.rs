@@ -0,0 +1,99 @@
+//! Log output formats.
+//!
+//! `text` (the default) is `pretty_env_logger`'s output. `json` writes one JSON object per
+//! line, with the time, level, target, message and, while a request is being handled, its
+//! `request_id`, for log collectors.
+//!
+//! In both formats, `RUST_LOG` sets the log levels.
+
+use std::env;
+use std::io::Write;
+use std::str::FromStr;
+
+use failure::{Error, format_err};
+use log::Record;
+use serde_json::json;
+
+use crate::request_id::current_request_id;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format_err!("Log format {} not supported, use text or json", s)),
+        }
+    }
+}
+
+
+pub fn init(format: LogFormat) {
+    match format {
+        LogFormat::Text => pretty_env_logger::init(),
+        LogFormat::Json => {
+            let mut builder = env_logger::Builder::new();
+
+            if let Ok(filters) = env::var("RUST_LOG") {
+                builder.parse(&filters);
+            }
+
+            builder
+                .format(|buf, record| {
+                    let line = json_line(&buf.timestamp().to_string(), record, current_request_id());
+                    writeln!(buf, "{}", line)
+                })
+                .init();
+        },
+    }
+}
+
+
+fn json_line(time: &str, record: &Record, request_id: Option<String>) -> String {
+    json!({
+        "time": time,
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+        "request_id": request_id,
+    }).to_string()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_json_line() {
+        let line = json_line(
+            "2019-06-01T12:00:00Z",
+            &Record::builder()
+                .args(format_args!("Sql query: {}", "select 1"))
+                .level(Level::Info)
+                .target("tesseract_olap::handlers::aggregate")
+                .build(),
+            Some("abc".to_owned()),
+        );
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+            json!({
+                "time": "2019-06-01T12:00:00Z",
+                "level": "INFO",
+                "target": "tesseract_olap::handlers::aggregate",
+                "message": "Sql query: select 1",
+                "request_id": "abc",
+            })
+        );
+    }
+}
@@ -1,15 +1,18 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
 use actix::SystemRunner;
 use failure::{Error, format_err};
-use log::{info, debug};
+use log::{info, debug, warn};
 use std::time::Instant;
 
 use serde_derive::Deserialize;
 
-use tesseract_core::{Schema, Backend};
+use tesseract_core::{Schema, Backend, Column};
+use tesseract_core::dialect::quote_qualified;
 use tesseract_core::names::{LevelName, Property};
-use tesseract_core::schema::{Level, Cube, InlineTable};
+use tesseract_core::schema::{Level, Cube, InlineTable, is_inline_cached};
 
+use crate::db_config::{DataSources, cube_backend};
 use crate::logic_layer::{LogicLayerConfig};
 
 
@@ -44,6 +47,9 @@ pub enum TimePrecision {
     Month,
     Week,
     Day,
+    /// Fiscal years and quarters; see `CubeCache::get_fiscal_value`.
+    FiscalYear,
+    FiscalQuarter,
     // `Time` is a generic name that holds a column with a combination of time dimensions.
     // For example, a time column my hold the value `201801` for January 2018.
     // This allows for a but on the latest month of the latest year.
@@ -61,6 +67,8 @@ impl TimePrecision {
             "month" => Ok(TimePrecision::Month),
             "week" => Ok(TimePrecision::Week),
             "day" => Ok(TimePrecision::Day),
+            "fiscal_year" => Ok(TimePrecision::FiscalYear),
+            "fiscal_quarter" => Ok(TimePrecision::FiscalQuarter),
             "time" => Ok(TimePrecision::Time),
             _ => Err(format_err!("Wrong type for time precision argument."))
         }
@@ -72,6 +80,8 @@ impl TimePrecision {
 pub struct Time {
     pub precision: TimePrecision,
     pub value: TimeValue,
+    /// Number of periods for `latest`/`oldest`, e.g. the latest 3 months.
+    pub count: usize,
 }
 
 
@@ -79,33 +89,44 @@ impl Time {
     pub fn from_str(raw: String) -> Result<Self, Error> {
         let e: Vec<&str> = raw.split(".").collect();
 
-        if e.len() != 2 {
-            return Err(format_err!("Wrong format for time argument."));
+        match e.len() {
+            2 => Time::from_key_value(e[0].to_string(), e[1].to_string()),
+            3 => Time::from_key_value(e[0].to_string(), format!("{}.{}", e[1], e[2])),
+            _ => Err(format_err!("Wrong format for time argument.")),
         }
-
-        let precision = match TimePrecision::from_str(e[0].to_string()) {
-            Ok(precision) => precision,
-            Err(err) => return Err(err),
-        };
-        let value = match TimeValue::from_str(e[1].to_string()) {
-            Ok(value) => value,
-            Err(err) => return Err(err),
-        };
-
-        Ok(Time {precision, value})
     }
 
+    /// `value` is `latest`, `oldest` or a time value, and `latest` and `oldest` may be
+    /// followed by a number of periods, e.g. `latest.3`.
     pub fn from_key_value(key: String, value: String) -> Result<Self, Error> {
         let precision = match TimePrecision::from_str( key) {
             Ok(precision) => precision,
             Err(err) => return Err(err),
         };
-        let value = match TimeValue::from_str(value) {
+
+        let e: Vec<&str> = value.split(".").collect();
+
+        let value = match TimeValue::from_str(e[0].to_string()) {
             Ok(value) => value,
             Err(err) => return Err(err),
         };
 
-        Ok(Time {precision, value})
+        let count = match e.len() {
+            1 => 1,
+            2 => {
+                if let TimeValue::Value(_) = value {
+                    return Err(format_err!("A number of periods is only allowed for latest or oldest."));
+                }
+
+                match e[1].parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format_err!("Wrong number of periods for time argument.")),
+                }
+            },
+            _ => return Err(format_err!("Wrong format for time argument.")),
+        };
+
+        Ok(Time {precision, value, count})
     }
 }
 
@@ -114,6 +135,71 @@ impl Time {
 #[derive(Debug, Clone)]
 pub struct Cache {
     pub cubes: Vec<CubeCache>,
+    /// Members of levels too large to cache, as they're looked up.
+    pub member_lookups: MemberLookups,
+}
+
+
+/// Default number of lookups kept by `MemberLookups`.
+const DEFAULT_LOOKUP_CAPACITY: usize = 1000;
+
+/// Results of sql lookups of members, keyed by the lookup's sql. Holds up to `capacity`
+/// lookups, dropping the least recently used first.
+#[derive(Debug, Clone)]
+pub struct MemberLookups {
+    inner: Arc<Mutex<MemberLookupsInner>>,
+}
+
+#[derive(Debug)]
+struct MemberLookupsInner {
+    capacity: usize,
+    lookups: HashMap<String, Vec<String>>,
+    // least recently used at the front
+    order: VecDeque<String>,
+}
+
+impl MemberLookups {
+    pub fn new(capacity: usize) -> Self {
+        MemberLookups {
+            inner: Arc::new(Mutex::new(MemberLookupsInner {
+                capacity,
+                lookups: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn get(&self, sql: &str) -> Option<Vec<String>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let members = inner.lookups.get(sql).cloned()?;
+        inner.order.retain(|key| key != sql);
+        inner.order.push_back(sql.to_owned());
+
+        Some(members)
+    }
+
+    pub fn insert(&self, sql: String, members: Vec<String>) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.order.retain(|key| *key != sql);
+        inner.order.push_back(sql.clone());
+        inner.lookups.insert(sql, members);
+
+        while inner.lookups.len() > inner.capacity {
+            match inner.order.pop_front() {
+                Some(key) => { inner.lookups.remove(&key); },
+                None => break,
+            }
+        }
+    }
+
+    /// Drops all lookups, e.g. when the data they were looked up from is refreshed.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.lookups.clear();
+        inner.order.clear();
+    }
 }
 
 
@@ -150,6 +236,15 @@ pub struct CubeCache {
     pub day_level: Option<Level>,
     pub day_values: Option<Vec<String>>,
 
+    pub fiscal_year_level: Option<Level>,
+    pub fiscal_year_values: Option<Vec<String>>,
+
+    pub fiscal_quarter_level: Option<Level>,
+    pub fiscal_quarter_values: Option<Vec<String>>,
+
+    /// First month of the cube's fiscal year, from the logic layer config.
+    pub fiscal_start_month: Option<u32>,
+
     pub time_level: Option<Level>,
     pub time_values: Option<Vec<String>>,
 
@@ -161,6 +256,9 @@ pub struct CubeCache {
 
     // Maps a dimension name to a `DimensionCache` object
     pub dimension_caches: HashMap<String, DimensionCache>,
+
+    // When the cube's data was last updated, if known
+    pub last_updated: Option<String>,
 }
 
 
@@ -192,6 +290,34 @@ impl CubeCache {
                 let l = self.get_level_name(self.day_level.clone());
                 (v, l)
             },
+            TimePrecision::FiscalYear => {
+                match self.fiscal_year_level {
+                    Some(_) => {
+                        let v = self.get_value(&time, self.fiscal_year_values.clone());
+                        let l = self.get_level_name(self.fiscal_year_level.clone());
+                        (v, l)
+                    },
+                    None => {
+                        let v = self.get_fiscal_value(&time, false);
+                        let l = self.get_level_name(self.month_level.clone());
+                        (v, l)
+                    },
+                }
+            },
+            TimePrecision::FiscalQuarter => {
+                match self.fiscal_quarter_level {
+                    Some(_) => {
+                        let v = self.get_value(&time, self.fiscal_quarter_values.clone());
+                        let l = self.get_level_name(self.fiscal_quarter_level.clone());
+                        (v, l)
+                    },
+                    None => {
+                        let v = self.get_fiscal_value(&time, true);
+                        let l = self.get_level_name(self.month_level.clone());
+                        (v, l)
+                    },
+                }
+            },
             TimePrecision::Time => {
                 let v = self.get_value(&time, self.time_values.clone());
                 let l = self.get_level_name(self.time_level.clone());
@@ -219,29 +345,71 @@ impl CubeCache {
         }
     }
 
+    /// Resolves a time value against the cached values, which are sorted. `latest` and
+    /// `oldest` with a count resolve to a comma separated list of values.
     pub fn get_value(&self, time: &Time, opt: Option<Vec<String>>) -> Option<String> {
         match opt {
             Some(v) => {
+                if v.is_empty() {
+                    return None;
+                }
+
+                let count = time.count.min(v.len());
+
                 match time.value {
-                    TimeValue::First => {
-                        if v.len() >= 1 {
-                            return Some(v[0].clone());
-                        }
-                        None
-                    },
-                    TimeValue::Last => {
-                        if v.len() >= 1 {
-                            return Some(v.last().unwrap().clone())
-                        }
-                        None
-                    },
-                    TimeValue::Value(t) => return Some(t.to_string())
+                    TimeValue::First => Some(v[..count].join(",")),
+                    TimeValue::Last => Some(v[v.len() - count..].join(",")),
+                    TimeValue::Value(t) => Some(t.to_string()),
                 }
             },
             None => None
         }
     }
 
+    /// Resolves a fiscal year (or quarter) time value to the months in it, for cubes
+    /// without a fiscal level, from the cached `YYYYMM` month values and the configured
+    /// fiscal start month. Fiscal years are named after the calendar year they end in, and
+    /// fiscal quarters are written `YYYYQ`, e.g. `20192` for the second quarter of FY2019.
+    pub fn get_fiscal_value(&self, time: &Time, quarter: bool) -> Option<String> {
+        let start_month = self.fiscal_start_month?;
+        let month_values = self.month_values.as_ref()?;
+
+        let months: Vec<(u32, &String)> = month_values.iter()
+            .filter_map(|month| {
+                let (fiscal_year, fiscal_quarter) = fiscal_period(month, start_month)?;
+                let period = if quarter { fiscal_year * 10 + fiscal_quarter } else { fiscal_year };
+                Some((period, month))
+            })
+            .collect();
+
+        let mut periods: Vec<u32> = months.iter().map(|(period, _)| *period).collect();
+        periods.sort();
+        periods.dedup();
+
+        if periods.is_empty() {
+            return None;
+        }
+
+        let count = time.count.min(periods.len());
+
+        let periods = match time.value {
+            TimeValue::First => periods[..count].to_vec(),
+            TimeValue::Last => periods[periods.len() - count..].to_vec(),
+            TimeValue::Value(t) => vec![t],
+        };
+
+        let selected: Vec<&str> = months.iter()
+            .filter(|(period, _)| periods.contains(period))
+            .map(|(_, month)| month.as_str())
+            .collect();
+
+        if selected.is_empty() {
+            None
+        } else {
+            Some(selected.join(","))
+        }
+    }
+
     // TODO note that this is being used in core tesseract, but that the cache is created using
     // logic layer rules. This means that at the moment of this implementation, this will work in
     // core tesseract but only if the core tesseract schema can also be a logic layer schema (and
@@ -249,11 +417,54 @@ impl CubeCache {
     //
     // The next step is to figure out how to architecture this cache so that it can be used even in
     // non-logic layer setups, but also be used for members endpoint (which requires label also)
+    //
+    // Levels with too many members to cache have none.
     pub fn members_for_level(&self, level_name: &LevelName) -> Option<&HashSet<String>> {
         debug!("Level Caches: {:?}", self.level_caches);
         self.level_caches.get(level_name)
+            .filter(|level_cache| level_cache.members_cached)
             .map(|level_cache| &level_cache.members)
     }
+
+    /// Number of members of a level, including levels too large to cache.
+    pub fn member_count(&self, level_name: &LevelName) -> Option<usize> {
+        self.level_caches.get(level_name)
+            .map(|level_cache| level_cache.member_count)
+    }
+
+    /// The one level of a dimension whose members aren't cached, if there's exactly one.
+    /// Members missing from the dimension's `id_map` are assumed to belong to it.
+    pub fn uncached_dimension_level(&self, dimension: &str) -> Option<LevelName> {
+        let mut uncached = self.level_caches.iter()
+            .filter(|(level_name, level_cache)| level_name.dimension == dimension && !level_cache.members_cached)
+            .map(|(level_name, _)| level_name);
+
+        match (uncached.next(), uncached.next()) {
+            (Some(level_name), None) => Some(level_name.clone()),
+            _ => None,
+        }
+    }
+}
+
+
+/// Fiscal year and quarter of a `YYYYMM` month value, for a fiscal year starting in
+/// `start_month`.
+pub fn fiscal_period(month_value: &str, start_month: u32) -> Option<(u32, u32)> {
+    if month_value.len() != 6 {
+        return None;
+    }
+
+    let year = month_value[..4].parse::<u32>().ok()?;
+    let month = month_value[4..].parse::<u32>().ok()?;
+
+    if month < 1 || month > 12 || start_month < 1 || start_month > 12 {
+        return None;
+    }
+
+    let fiscal_year = if start_month > 1 && month >= start_month { year + 1 } else { year };
+    let fiscal_quarter = (month + 12 - start_month) % 12 / 3 + 1;
+
+    Some((fiscal_year, fiscal_quarter))
 }
 
 
@@ -266,6 +477,10 @@ pub struct LevelCache {
     // TODO to be able to use for /members endpoint, this will
     // need both ID and member label. Right now it's just ID
     pub members: HashSet<String>,
+    /// False for levels with more members than the members cache allows; their members,
+    /// neighbors and parents are empty, as are the children of their parent level.
+    pub members_cached: bool,
+    pub member_count: usize,
 }
 
 
@@ -280,262 +495,476 @@ pub fn populate_cache(
         schema: Schema,
         ll_config: &Option<LogicLayerConfig>,
         backend: Box<dyn Backend + Sync + Send>,
+        data_sources: &DataSources,
         sys: &mut SystemRunner
 ) -> Result<Cache, Error> {
     info!("Populating cache...");
     let time_start = Instant::now();
 
+    let mut cubes: Vec<CubeCache> = vec![];
+
+    for cube in schema.cubes {
+        let cube_backend = cube_backend(&cube, &backend, data_sources).clone();
+        cubes.push(populate_cube_cache(cube, ll_config, cube_backend, sys)?);
+    }
+
+    let lookup_capacity = ll_config.as_ref()
+        .and_then(|ll_config| ll_config.members_cache.as_ref())
+        .and_then(|members_cache| members_cache.lookup_capacity)
+        .unwrap_or(DEFAULT_LOOKUP_CAPACITY);
+
+    let timing = time_start.elapsed();
+    info!("Cache ready! (Time elapsed: {}.{:03})", timing.as_secs(), timing.subsec_millis());
+    Ok(Cache { cubes, member_lookups: MemberLookups::new(lookup_capacity) })
+}
+
+
+/// Max rows of a dimension table with an `inline_cache` annotation. Larger tables are
+/// joined as usual.
+const MAX_INLINE_CACHE_ROWS: usize = 10_000;
+
+/// Loads the tables of dimensions with an `inline_cache` annotation into their hierarchies,
+/// so that queries inline them instead of joining the table. A table which can't be loaded
+/// is joined as usual.
+pub fn populate_inline_tables(
+        schema: &mut Schema,
+        backend: &Box<dyn Backend + Sync + Send>,
+        data_sources: &DataSources,
+        sys: &mut SystemRunner
+) {
+    for cube in &mut schema.cubes {
+        let cube_backend = cube_backend(cube, backend, data_sources).clone();
+        populate_cube_inline_tables(cube, &cube_backend, sys);
+    }
+}
+
+
+/// Loads the inline cached dimension tables of a single cube.
+pub fn populate_cube_inline_tables(
+        cube: &mut Cube,
+        backend: &Box<dyn Backend + Sync + Send>,
+        sys: &mut SystemRunner
+) {
+    let cube_name = cube.name.clone();
+
+    for dimension in &mut cube.dimensions {
+        if !is_inline_cached(&dimension.annotations) {
+            continue;
+        }
+
+        let dimension_name = dimension.name.clone();
+
+        for hierarchy in &mut dimension.hierarchies {
+            hierarchy.cached_table = None;
+
+            let (sql, alias) = match (hierarchy.inline_cache_sql(), &hierarchy.table) {
+                (Some(sql), Some(table)) => (sql, table.name.clone()),
+                _ => continue,
+            };
+
+            let df = match sys.block_on(backend.exec_sql(sql)) {
+                Ok(df) => df,
+                Err(err) => {
+                    warn!("Could not inline {}.{}.{}: {}", cube_name, dimension_name, hierarchy.name, err);
+                    continue;
+                },
+            };
+
+            if df.len() > MAX_INLINE_CACHE_ROWS {
+                warn!("Not inlining {}.{}.{}: {} rows is over the limit of {}",
+                    cube_name, dimension_name, hierarchy.name, df.len(), MAX_INLINE_CACHE_ROWS);
+                continue;
+            }
+
+            match InlineTable::from_dataframe(&alias, &df) {
+                Ok(inline_table) => {
+                    info!("Inlined {}.{}.{} ({} rows)", cube_name, dimension_name, hierarchy.name, df.len());
+                    hierarchy.cached_table = Some(inline_table);
+                },
+                Err(err) => warn!("Could not inline {}.{}.{}: {}", cube_name, dimension_name, hierarchy.name, err),
+            }
+        }
+    }
+}
+
+
+/// Gives the hierarchies of a cube in the shared schema the tables inlined in a copy of it.
+/// Returns whether any of them changed.
+pub fn set_cached_tables(cube: &mut Cube, inlined: &Cube) -> bool {
+    let mut changed = false;
+
+    for dim in &mut cube.dimensions {
+        let inlined_dim = inlined.dimensions.iter().find(|d| d.name == dim.name);
+
+        for hier in &mut dim.hierarchies {
+            let cached_table = inlined_dim
+                .and_then(|d| d.hierarchies.iter().find(|h| h.name == hier.name))
+                .and_then(|h| h.cached_table.clone());
+
+            if hier.cached_table != cached_table {
+                hier.cached_table = cached_table;
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+
+/// Populates the `CubeCache` of a single cube.
+pub fn populate_cube_cache(
+        cube: Cube,
+        ll_config: &Option<LogicLayerConfig>,
+        backend: Box<dyn Backend + Sync + Send>,
+        sys: &mut SystemRunner
+) -> Result<CubeCache, Error> {
     let time_column_names = vec![
         "Year".to_string(),
         "Quarter".to_string(),
         "Month".to_string(),
         "Week".to_string(),
-        "Day".to_string()
+        "Day".to_string(),
+        "Fiscal Year".to_string(),
+        "Fiscal Quarter".to_string(),
     ];
 
-    let mut cubes: Vec<CubeCache> = vec![];
+    let mut year_level: Option<Level> = None;
+    let mut year_values: Option<Vec<String>> = None;
+    let mut quarter_level: Option<Level> = None;
+    let mut quarter_values: Option<Vec<String>> = None;
+    let mut month_level: Option<Level> = None;
+    let mut month_values: Option<Vec<String>> = None;
+    let mut week_level: Option<Level> = None;
+    let mut week_values: Option<Vec<String>> = None;
+    let mut day_level: Option<Level> = None;
+    let mut day_values: Option<Vec<String>> = None;
+    let mut fiscal_year_level: Option<Level> = None;
+    let mut fiscal_year_values: Option<Vec<String>> = None;
+    let mut fiscal_quarter_level: Option<Level> = None;
+    let mut fiscal_quarter_values: Option<Vec<String>> = None;
+    let mut time_level: Option<Level> = None;
+    let mut time_values: Option<Vec<String>> = None;
+
+    let mut level_caches: HashMap<LevelName, LevelCache> = HashMap::new();
+    let mut dimension_caches: HashMap<String, DimensionCache> = HashMap::new();
+
+    // members of levels in the fact table are only those of the cube's `sql_where` rows
+    let fact_table = cube.fact_table_sql(&*backend.dialect());
 
-    for cube in schema.cubes {
-        let mut year_level: Option<Level> = None;
-        let mut year_values: Option<Vec<String>> = None;
-        let mut quarter_level: Option<Level> = None;
-        let mut quarter_values: Option<Vec<String>> = None;
-        let mut month_level: Option<Level> = None;
-        let mut month_values: Option<Vec<String>> = None;
-        let mut week_level: Option<Level> = None;
-        let mut week_values: Option<Vec<String>> = None;
-        let mut day_level: Option<Level> = None;
-        let mut day_values: Option<Vec<String>> = None;
-        let mut time_level: Option<Level> = None;
-        let mut time_values: Option<Vec<String>> = None;
-
-        let mut level_caches: HashMap<LevelName, LevelCache> = HashMap::new();
-        let mut dimension_caches: HashMap<String, DimensionCache> = HashMap::new();
-
-        for dimension in &cube.dimensions {
-            let mut id_map: HashMap<String, Vec<LevelName>> = HashMap::new();
-
-            for hierarchy in &dimension.hierarchies {
-                let table = match &hierarchy.table {
-                    Some(t) => &t.name,
-                    None => &cube.table.name
-                };
+    for dimension in &cube.dimensions {
+        let mut id_map: HashMap<String, Vec<LevelName>> = HashMap::new();
 
-                for level in &hierarchy.levels {
-                    if time_column_names.contains(&level.name) {
-                        let val = get_distinct_values(
-                            &level.key_column, &table, backend.clone(), sys
-                        )?;
+        for hierarchy in &dimension.hierarchies {
+            let table = match &hierarchy.table {
+                Some(t) => &t.name,
+                None => &fact_table
+            };
+
+            // Levels with more members than the members cache allows are only counted.
+            // Inline tables are always cached.
+            let mut level_counts: HashMap<String, (bool, Option<usize>)> = HashMap::new();
+
+            for level in &hierarchy.levels {
+                let level_name = LevelName::new(
+                    dimension.name.clone(),
+                    hierarchy.name.clone(),
+                    level.name.clone()
+                );
+
+                let max_members = ll_config.as_ref()
+                    .and_then(|ll_config| ll_config.members_cache.as_ref())
+                    .and_then(|members_cache| members_cache.level_max_members(&level_name))
+                    .filter(|_| hierarchy.inline_table.is_none());
 
-                        if level.name == "Year" {
-                            year_level = Some(level.clone());
-                            year_values = Some(val);
-                        } else if level.name == "Quarter" {
-                            quarter_level = Some(level.clone());
-                            quarter_values = Some(val);
-                        } else if level.name == "Month" {
-                            month_level = Some(level.clone());
-                            month_values = Some(val);
-                        } else if level.name == "Week" {
-                            week_level = Some(level.clone());
-                            week_values = Some(val);
-                        } else if level.name == "Day" {
-                            day_level = Some(level.clone());
-                            day_values = Some(val);
+                let level_count = match max_members {
+                    Some(max_members) => {
+                        let count = get_distinct_count(&level.key_column, &table, backend.clone(), sys)?;
+
+                        if count > max_members {
+                            info!("Not caching the {} members of {}, over the limit of {}", count, level_name, max_members);
                         }
-                    } else if level.name == "Time" {
-                        // Identify what time of level this is based on the annotation name
-                        let mut found_time = false;
-
-                        // This is a hack for now. It handles the case where you
-                        // have a level called Time that is actually at a more
-                        // specific depth. It allows to cut on that depth using the
-                        // .latest/.oldest feature.
-                        match &level.annotations {
-                            Some(annotations) => {
-                                for annotation in annotations {
-                                    if annotation.name == "level" && time_column_names.contains(&annotation.text) {
-                                        let val = get_distinct_values(
-                                            &level.key_column, &table, backend.clone(), sys
-                                        )?;
-
-                                        if annotation.text == "Year" {
-                                            year_level = Some(level.clone());
-                                            year_values = Some(val);
-                                            found_time = true;
-                                        } else if annotation.text == "Quarter" {
-                                            quarter_level = Some(level.clone());
-                                            quarter_values = Some(val);
-                                            found_time = true;
-                                        } else if annotation.text == "Month" {
-                                            month_level = Some(level.clone());
-                                            month_values = Some(val);
-                                            found_time = true;
-                                        } else if annotation.text == "Week" {
-                                            week_level = Some(level.clone());
-                                            week_values = Some(val);
-                                            found_time = true;
-                                        } else if annotation.text == "Day" {
-                                            day_level = Some(level.clone());
-                                            day_values = Some(val);
-                                            found_time = true;
-                                        } else if annotation.text == "Time" {
-                                            time_level = Some(level.clone());
-                                            time_values = Some(val);
-                                            found_time = true;
-                                        }
+
+                        (count <= max_members, Some(count))
+                    },
+                    None => (true, None),
+                };
+
+                level_counts.insert(level.name.clone(), level_count);
+            }
+
+            for level in &hierarchy.levels {
+                if time_column_names.contains(&level.name) {
+                    let val = get_distinct_values(
+                        &level.key_column, &table, backend.clone(), sys
+                    )?;
+
+                    if level.name == "Year" {
+                        year_level = Some(level.clone());
+                        year_values = Some(val);
+                    } else if level.name == "Quarter" {
+                        quarter_level = Some(level.clone());
+                        quarter_values = Some(val);
+                    } else if level.name == "Month" {
+                        month_level = Some(level.clone());
+                        month_values = Some(val);
+                    } else if level.name == "Week" {
+                        week_level = Some(level.clone());
+                        week_values = Some(val);
+                    } else if level.name == "Day" {
+                        day_level = Some(level.clone());
+                        day_values = Some(val);
+                    } else if level.name == "Fiscal Year" {
+                        fiscal_year_level = Some(level.clone());
+                        fiscal_year_values = Some(val);
+                    } else if level.name == "Fiscal Quarter" {
+                        fiscal_quarter_level = Some(level.clone());
+                        fiscal_quarter_values = Some(val);
+                    }
+                } else if level.name == "Time" {
+                    // Identify what time of level this is based on the annotation name
+                    let mut found_time = false;
+
+                    // This is a hack for now. It handles the case where you
+                    // have a level called Time that is actually at a more
+                    // specific depth. It allows to cut on that depth using the
+                    // .latest/.oldest feature.
+                    match &level.annotations {
+                        Some(annotations) => {
+                            for annotation in annotations {
+                                if annotation.name == "level" && time_column_names.contains(&annotation.text) {
+                                    let val = get_distinct_values(
+                                        &level.key_column, &table, backend.clone(), sys
+                                    )?;
+
+                                    if annotation.text == "Year" {
+                                        year_level = Some(level.clone());
+                                        year_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Quarter" {
+                                        quarter_level = Some(level.clone());
+                                        quarter_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Month" {
+                                        month_level = Some(level.clone());
+                                        month_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Week" {
+                                        week_level = Some(level.clone());
+                                        week_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Day" {
+                                        day_level = Some(level.clone());
+                                        day_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Fiscal Year" {
+                                        fiscal_year_level = Some(level.clone());
+                                        fiscal_year_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Fiscal Quarter" {
+                                        fiscal_quarter_level = Some(level.clone());
+                                        fiscal_quarter_values = Some(val);
+                                        found_time = true;
+                                    } else if annotation.text == "Time" {
+                                        time_level = Some(level.clone());
+                                        time_values = Some(val);
+                                        found_time = true;
                                     }
                                 }
-                            },
-                            None => ()
-                        }
+                            }
+                        },
+                        None => ()
+                    }
 
-                        // Consider this to be a time generic Time level
-                        if !found_time {
-                            // Want to get distinct time values from the fact table
-                            let val = get_distinct_values(
-                                &level.key_column, &cube.table.name, backend.clone(), sys
-                            )?;
+                    // Consider this to be a time generic Time level
+                    if !found_time {
+                        // Want to get distinct time values from the fact table
+                        let val = get_distinct_values(
+                            &level.key_column, &fact_table, backend.clone(), sys
+                        )?;
 
-                            time_level = Some(level.clone());
-                            time_values = Some(val);
-                        }
+                        time_level = Some(level.clone());
+                        time_values = Some(val);
                     }
+                }
 
-                    let level_name = LevelName::new(
-                        dimension.name.clone(),
-                        hierarchy.name.clone(),
-                        level.name.clone()
-                    );
+                let level_name = LevelName::new(
+                    dimension.name.clone(),
+                    hierarchy.name.clone(),
+                    level.name.clone()
+                );
 
-                    // Get unique name for this level
-                    let unique_name = match get_unique_level_name(&cube, ll_config, &level_name)? {
-                        Some(name) => name,
-                        None => return Err(format_err!("Couldn't find unique name for {}", level.name.clone()))
-                    };
+                // Get unique name for this level
+                let unique_name = match get_unique_level_name(&cube, ll_config, &level_name)? {
+                    Some(name) => name,
+                    None => return Err(format_err!("Couldn't find unique name for {}", level.name.clone()))
+                };
 
-                    let mut parent_map: Option<HashMap<String, String>> = None;
-                    let mut children_map: Option<HashMap<String, Vec<String>>> = None;
+                let mut parent_map: Option<HashMap<String, String>> = None;
+                let mut children_map: Option<HashMap<String, Vec<String>>> = None;
 
-                    let parent_levels = cube.get_level_parents(&level_name)?;
-                    let child_level = cube.get_child_level(&level_name)?;
+                let parent_levels = cube.get_level_parents(&level_name)?;
+                let child_level = cube.get_child_level(&level_name)?;
 
-                    let mut distinct_ids: Vec<String> = vec![];
+                let (members_cached, member_count) = level_counts.get(&level.name)
+                    .cloned()
+                    .unwrap_or((true, None));
+                let children_cached = child_level.as_ref()
+                    .and_then(|child_level| level_counts.get(&child_level.name))
+                    .map(|(cached, _)| *cached)
+                    .unwrap_or(true);
 
-                    if hierarchy.inline_table.is_some() {
-                        // Inline table
+                let mut distinct_ids: Vec<String> = vec![];
 
-                        let inline_table = match &hierarchy.inline_table {
-                            Some(t) => t,
-                            None => return Err(format_err!("Could not get inline table for {}", level.name.clone()))
-                        };
+                if hierarchy.inline_table.is_some() {
+                    // Inline table
 
-                        if parent_levels.len() >= 1 {
-                            parent_map = Some(get_inline_parent_data(
-                                &parent_levels[parent_levels.len() - 1], &level,
-                                &inline_table
-                            ));
-                        }
+                    let inline_table = match &hierarchy.inline_table {
+                        Some(t) => t,
+                        None => return Err(format_err!("Could not get inline table for {}", level.name.clone()))
+                    };
 
-                        match child_level {
-                            Some(child_level) => {
-                                children_map = Some(get_inline_children_data(
-                                    &level, &child_level, &inline_table
-                                ));
-                            },
-                            None => ()
-                        }
+                    if parent_levels.len() >= 1 {
+                        parent_map = Some(get_inline_parent_data(
+                            &parent_levels[parent_levels.len() - 1], &level,
+                            &inline_table
+                        ));
+                    }
 
-                        // Get all IDs for this level
-                        for row in &inline_table.rows {
-                            for row_value in &row.row_values {
-                                if row_value.column == level.key_column {
-                                    distinct_ids.push(row_value.value.clone());
-                                }
+                    match child_level {
+                        Some(child_level) => {
+                            children_map = Some(get_inline_children_data(
+                                &level, &child_level, &inline_table
+                            ));
+                        },
+                        None => ()
+                    }
+
+                    // Get all IDs for this level
+                    for row in &inline_table.rows {
+                        for row_value in &row.row_values {
+                            if row_value.column == level.key_column {
+                                distinct_ids.push(row_value.value.clone());
                             }
                         }
-                    } else {
-                        // Database table
+                    }
+                } else {
+                    // Database table
+
+                    if parent_levels.len() >= 1 && members_cached {
+                        parent_map = Some(get_parent_data(
+                            &parent_levels[parent_levels.len() - 1], &level,
+                            table, backend.clone(), sys
+                        )?);
+                    }
 
-                        if parent_levels.len() >= 1 {
-                            parent_map = Some(get_parent_data(
-                                &parent_levels[parent_levels.len() - 1], &level,
+                    match child_level {
+                        Some(child_level) if children_cached => {
+                            children_map = Some(get_children_data(
+                                &level, &child_level,
                                 table, backend.clone(), sys
                             )?);
-                        }
-
-                        match child_level {
-                            Some(child_level) => {
-                                children_map = Some(get_children_data(
-                                    &level, &child_level,
-                                    table, backend.clone(), sys
-                                )?);
-                            },
-                            None => ()
-                        }
+                        },
+                        _ => ()
+                    }
 
-                        // Get all IDs for this level
+                    // Get all IDs for this level
+                    if members_cached {
                         distinct_ids = get_distinct_values(
                             &level.key_column, &table, backend.clone(), sys
                         )?;
+                        distinct_ids = distinct_ids.into_iter()
+                            .map(|id| level.pad_key(id))
+                            .collect();
                     }
+                }
 
-                    let neighbors_map = get_neighbors_map(&distinct_ids);
+                let member_count = member_count.unwrap_or(distinct_ids.len());
 
-                    // Add each distinct ID to the id_map HashMap
-                    for distinct_id in distinct_ids {
-                        id_map.entry(distinct_id.clone()).or_insert(vec![]);
-                        let map_entry = id_map.get_mut(&distinct_id).unwrap();
-                        map_entry.push(level_name.clone());
-                    }
+                let neighbors_map = get_neighbors_map(&distinct_ids);
 
-                    // neighbors are not optional, iterate over the keys of neighbors to get all
-                    // members.
-                    let members = neighbors_map.keys().cloned().collect();
-
-                    level_caches.insert(
-                        level_name,
-                        LevelCache {
-                            unique_name: unique_name.clone(),
-                            parent_map,
-                            children_map,
-                            neighbors_map,
-                            members
-                        }
-                    );
+                // Add each distinct ID to the id_map HashMap
+                for distinct_id in distinct_ids {
+                    id_map.entry(distinct_id.clone()).or_insert(vec![]);
+                    let map_entry = id_map.get_mut(&distinct_id).unwrap();
+                    map_entry.push(level_name.clone());
                 }
-            }
 
-            dimension_caches.insert(dimension.name.clone(), DimensionCache { id_map });
+                // neighbors are not optional, iterate over the keys of neighbors to get all
+                // members.
+                let members = neighbors_map.keys().cloned().collect();
+
+                level_caches.insert(
+                    level_name,
+                    LevelCache {
+                        unique_name: unique_name.clone(),
+                        parent_map,
+                        children_map,
+                        neighbors_map,
+                        members,
+                        members_cached,
+                        member_count,
+                    }
+                );
+            }
         }
 
-        let level_map = get_level_map(&cube, ll_config)?;
-        let property_map = get_property_map(&cube, ll_config)?;
-
-        cubes.push(CubeCache {
-            name: cube.name,
-            year_level,
-            year_values,
-            quarter_level,
-            quarter_values,
-            month_level,
-            month_values,
-            week_level,
-            week_values,
-            day_level,
-            day_values,
-            time_level,
-            time_values,
-            level_map,
-            property_map,
-            level_caches,
-            dimension_caches,
-        })
+        dimension_caches.insert(dimension.name.clone(), DimensionCache { id_map });
     }
 
-    let timing = time_start.elapsed();
-    info!("Cache ready! (Time elapsed: {}.{:03})", timing.as_secs(), timing.subsec_millis());
-    Ok(Cache { cubes })
+    let level_map = get_level_map(&cube, ll_config)?;
+    let property_map = get_property_map(&cube, ll_config)?;
+
+    // Either the max of the cube's timestamp column, or a `last_updated` annotation,
+    // which is picked up again on flush
+    let annotated_last_updated = || {
+        cube.annotations.iter()
+            .flatten()
+            .find(|annotation| annotation.name == "last_updated")
+            .map(|annotation| annotation.text.clone())
+    };
+    let last_updated = match &cube.updated_column {
+        Some(updated_column) => {
+            match get_max_value(updated_column, &fact_table, backend.clone(), sys) {
+                Ok(last_updated) => last_updated,
+                Err(err) => {
+                    warn!("Could not get the last update of cube {}: {}", cube.name, err);
+                    annotated_last_updated()
+                },
+            }
+        },
+        None => annotated_last_updated(),
+    };
+
+    let fiscal_start_month = ll_config.as_ref()
+        .and_then(|ll_config| ll_config.fiscal_calendar.as_ref())
+        .and_then(|fiscal_calendar| fiscal_calendar.cube_start_month(&cube.name));
+
+    Ok(CubeCache {
+        name: cube.name,
+        year_level,
+        year_values,
+        quarter_level,
+        quarter_values,
+        month_level,
+        month_values,
+        week_level,
+        week_values,
+        day_level,
+        day_values,
+        fiscal_year_level,
+        fiscal_year_values,
+        fiscal_quarter_level,
+        fiscal_quarter_values,
+        fiscal_start_month,
+        time_level,
+        time_values,
+        level_map,
+        property_map,
+        level_caches,
+        dimension_caches,
+        last_updated,
+    })
 }
 
 
@@ -617,6 +1046,27 @@ pub fn get_level_map(cube: &Cube, ll_config: &Option<LogicLayerConfig>) -> Resul
                     level_name
                 );
             }
+
+            // All levels are drilled down on by name, e.g. `All Geographies`. Names shared
+            // by several hierarchies, like Mondrian's default `(All)`, are prefixed with
+            // their hierarchy, as for the headers of the results
+            if let Some(all_level) = hierarchy.all_level() {
+                let is_shared = cube.dimensions.iter()
+                    .flat_map(|dim| dim.hierarchies.iter())
+                    .filter(|hier| hier.all_level() == Some(all_level))
+                    .count() > 1;
+
+                let unique_all_level = if is_shared {
+                    format!("{} {}", hierarchy.name, all_level)
+                } else {
+                    all_level.to_owned()
+                };
+
+                level_name_map.insert(
+                    unique_all_level,
+                    LevelName::new(dimension.name.clone(), hierarchy.name.clone(), all_level.to_owned())
+                );
+            }
         }
     }
 
@@ -686,9 +1136,9 @@ pub fn get_inline_parent_data(
     for row in &inline_table.rows {
         for row_value in &row.row_values {
             if row_value.column == parent_level.key_column {
-                parent_column.push(row_value.value.clone());
+                parent_column.push(parent_level.pad_key(row_value.value.clone()));
             } else if row_value.column == current_level.key_column {
-                current_column.push(row_value.value.clone());
+                current_column.push(current_level.pad_key(row_value.value.clone()));
             }
         }
     }
@@ -714,9 +1164,9 @@ pub fn get_inline_children_data(
     for row in &inline_table.rows {
         for row_value in &row.row_values {
             if row_value.column == current_level.key_column {
-                current_column.push(row_value.value.clone());
+                current_column.push(current_level.pad_key(row_value.value.clone()));
             } else if row_value.column == child_level.key_column {
-                children_column.push(row_value.value.clone());
+                children_column.push(child_level.pad_key(row_value.value.clone()));
             }
         }
     }
@@ -769,8 +1219,8 @@ pub fn get_parent_data(
         }
     };
 
-    let parent_column = df.columns[0].stringify_column_data();
-    let current_column = df.columns[1].stringify_column_data();
+    let parent_column = level_keys(parent_level, &df.columns[0]);
+    let current_column = level_keys(current_level, &df.columns[1]);
 
     for i in 0..current_column.len() {
         parent_data.insert(current_column[i].clone(), parent_column[i].clone());
@@ -804,8 +1254,8 @@ pub fn get_children_data(
         }
     };
 
-    let current_column = df.columns[0].stringify_column_data();
-    let children_column = df.columns[1].stringify_column_data();
+    let current_column = level_keys(current_level, &df.columns[0]);
+    let children_column = level_keys(child_level, &df.columns[1]);
 
     let mut current_value: String = "".to_string();
     let mut current_children: Vec<String> = vec![];
@@ -831,6 +1281,14 @@ pub fn get_children_data(
 }
 
 
+/// Members of a level from its key column, zero-padded to the level's `key_width`.
+fn level_keys(level: &Level, column: &Column) -> Vec<String> {
+    column.stringify_column_data().into_iter()
+        .map(|key| level.pad_key(key))
+        .collect()
+}
+
+
 /// Queries the database to get all the distinct values for a given level.
 pub fn get_distinct_values(
         column: &str,
@@ -860,6 +1318,63 @@ pub fn get_distinct_values(
 }
 
 
+/// Queries the database for the number of distinct values of a column.
+pub fn get_distinct_count(
+        column: &str,
+        table: &str,
+        backend: Box<dyn Backend + Sync + Send>,
+        sys: &mut SystemRunner
+) -> Result<usize, Error> {
+    let future = backend
+        .exec_sql(
+            format!("select count(distinct {}) from {}", column, table)
+        );
+
+    let df = match sys.block_on(future) {
+        Ok(df) => df,
+        Err(err) => {
+            return Err(format_err!("Error populating cache with backend data: {}", err));
+        }
+    };
+
+    df.columns.get(0)
+        .and_then(|col| col.stringify_column_data().into_iter().next())
+        .and_then(|value| value.parse::<usize>().ok())
+        .ok_or_else(|| format_err!("Could not count the distinct values of {}", column))
+}
+
+
+/// Queries the database for the max value of a column, e.g. a cube's last update. The max
+/// is cast to text in the query, so that any column type, like dates, can be read.
+pub fn get_max_value(
+        column: &str,
+        table: &str,
+        backend: Box<dyn Backend + Sync + Send>,
+        sys: &mut SystemRunner
+) -> Result<Option<String>, Error> {
+    let dialect = backend.dialect();
+    let future = backend
+        .exec_sql(
+            format!(
+                "select {} from {}",
+                dialect.cast_to_text_sql(&format!("max({})", column)),
+                quote_qualified(&*dialect, table),
+            )
+        );
+
+    let df = match sys.block_on(future) {
+        Ok(df) => df,
+        Err(err) => {
+            return Err(format_err!("Error populating cache with backend data: {}", err));
+        }
+    };
+
+    Ok(df.columns.get(0)
+        .and_then(|col| col.stringify_column_data().into_iter().next())
+        .filter(|value| !value.is_empty()))
+}
+
+
 pub fn get_neighbors_map(distinct_ids: &Vec<String>) -> HashMap<String, Vec<String>> {
     let mut neighbors_map: HashMap<String, Vec<String>> = HashMap::new();
 
@@ -902,3 +1417,39 @@ pub fn get_neighbors_map(distinct_ids: &Vec<String>) -> HashMap<String, Vec<Stri
 
     neighbors_map
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fiscal_period() {
+        assert_eq!(fiscal_period("201806", 7), Some((2018, 4)));
+        assert_eq!(fiscal_period("201807", 7), Some((2019, 1)));
+        assert_eq!(fiscal_period("201812", 7), Some((2019, 2)));
+        assert_eq!(fiscal_period("201901", 7), Some((2019, 3)));
+        assert_eq!(fiscal_period("201803", 1), Some((2018, 1)));
+        assert_eq!(fiscal_period("201810", 10), Some((2019, 1)));
+        assert_eq!(fiscal_period("2018", 7), None);
+        assert_eq!(fiscal_period("201813", 7), None);
+    }
+
+    #[test]
+    fn test_member_lookups() {
+        let lookups = MemberLookups::new(2);
+        lookups.insert("a".into(), vec!["1".into()]);
+        lookups.insert("b".into(), vec!["2".into()]);
+
+        // "a" becomes the most recently used, so "b" is dropped
+        assert_eq!(lookups.get("a"), Some(vec!["1".to_owned()]));
+        lookups.insert("c".into(), vec!["3".into()]);
+
+        assert_eq!(lookups.get("b"), None);
+        assert_eq!(lookups.get("a"), Some(vec!["1".to_owned()]));
+        assert_eq!(lookups.get("c"), Some(vec!["3".to_owned()]));
+
+        lookups.clear();
+        assert_eq!(lookups.get("a"), None);
+    }
+}
@@ -11,6 +11,91 @@ use tesseract_core::names::{LevelName, Property};
 pub struct LogicLayerConfig {
     pub aliases: Option<AliasConfig>,
     pub named_sets: Option<Vec<NamedSetsConfig>>,
+    pub cache_refresh: Option<CacheRefreshConfig>,
+    pub fiscal_calendar: Option<FiscalCalendarConfig>,
+    pub members_cache: Option<MembersCacheConfig>,
+}
+
+/// Limits on the number of members cached per level. The members of larger levels are only
+/// counted, and `children`/`parents` cuts on them are looked up with sql when queried.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MembersCacheConfig {
+    /// Limit for levels without their own; if not set, only the listed levels are limited.
+    pub default_max_members: Option<usize>,
+    pub levels: Option<Vec<LevelMembersCacheConfig>>,
+    /// Number of sql lookups kept, dropping the least recently used. Defaults to 1000.
+    pub lookup_capacity: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelMembersCacheConfig {
+    /// Fully qualified level name, e.g. `Geography.Geography.Tract`.
+    pub level: String,
+    pub max_members: usize,
+}
+
+impl MembersCacheConfig {
+    /// Max number of members of a level to cache, if it's limited at all.
+    pub fn level_max_members(&self, level_name: &LevelName) -> Option<usize> {
+        self.levels.iter()
+            .flatten()
+            .find(|l| l.level.parse::<LevelName>().ok().as_ref() == Some(level_name))
+            .map(|l| l.max_members)
+            .or(self.default_max_members)
+    }
+}
+
+/// Fiscal years, for `fiscal_year` and `fiscal_quarter` time cuts. Months are 1-12.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FiscalCalendarConfig {
+    /// Start month for cubes without their own.
+    pub default_start_month: Option<u32>,
+    pub cubes: Option<Vec<CubeFiscalCalendarConfig>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CubeFiscalCalendarConfig {
+    pub name: String,
+    pub start_month: u32,
+}
+
+impl FiscalCalendarConfig {
+    /// First month of a cube's fiscal year, if it has one.
+    pub fn cube_start_month(&self, cube_name: &str) -> Option<u32> {
+        self.cubes.iter()
+            .flatten()
+            .find(|c| c.name == cube_name)
+            .map(|c| c.start_month)
+            .or(self.default_start_month)
+    }
+}
+
+/// Background refresh of the cache. Intervals and jitter are in seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CacheRefreshConfig {
+    /// Interval for cubes without their own; if not set, only the listed cubes are refreshed.
+    pub default_interval: Option<u64>,
+    /// Maximum random delay added to each refresh, so that cubes don't all hit the database at once.
+    pub jitter: Option<u64>,
+    pub cubes: Option<Vec<CubeRefreshConfig>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CubeRefreshConfig {
+    pub name: String,
+    pub interval: u64,
+}
+
+impl CacheRefreshConfig {
+    /// Refresh interval for a cube, if it should be refreshed at all.
+    pub fn cube_interval(&self, cube_name: &str) -> Option<u64> {
+        self.cubes.iter()
+            .flatten()
+            .find(|c| c.name == cube_name)
+            .map(|c| c.interval)
+            .or(self.default_interval)
+            .filter(|interval| *interval > 0)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -133,6 +218,17 @@ pub fn read_config_str(config_str: &str) -> Result<LogicLayerConfig, Error> {
         }
     };
 
+    if let Some(fiscal_calendar) = &config.fiscal_calendar {
+        let start_months = fiscal_calendar.default_start_month.iter()
+            .chain(fiscal_calendar.cubes.iter().flatten().map(|c| &c.start_month));
+
+        for start_month in start_months {
+            if *start_month < 1 || *start_month > 12 {
+                return Err(format_err!("Fiscal calendar start month {} must be between 1 and 12", start_month))
+            }
+        }
+    }
+
     if let Some(named_sets) = &config.named_sets {
         let mut set_names = HashSet::new();
 
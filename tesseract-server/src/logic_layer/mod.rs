@@ -1,5 +1,20 @@
 mod cache;
 mod config;
+mod scheduler;
+mod suggest;
 
-pub use self::cache::{Cache, CubeCache, Time, TimePrecision, TimeValue, populate_cache};
-pub use self::config::{LogicLayerConfig, read_config, read_config_str};
+pub use self::cache::{
+    Cache,
+    CubeCache,
+    MemberLookups,
+    Time,
+    TimePrecision,
+    TimeValue,
+    populate_cache,
+    populate_cube_inline_tables,
+    populate_inline_tables,
+    set_cached_tables,
+};
+pub use self::config::{LogicLayerConfig, MembersCacheConfig, read_config, read_config_str};
+pub use self::scheduler::{refresh_cube_cache, start_cache_refresh};
+pub use self::suggest::{cube_not_found_message, near_matches, not_found_message};
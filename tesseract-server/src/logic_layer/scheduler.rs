@@ -0,0 +1,225 @@
+//! Background refresh of the logic layer cache.
+//!
+//! The cache is populated on startup, so on long-running servers it goes stale as new data
+//! lands (e.g. `time=year.latest` keeps resolving to last year). The scheduler refreshes each
+//! cube's `CubeCache` on its own interval from `LogicLayerConfig.cache_refresh`, in a
+//! separate thread with its own actix system, so that requests are never blocked on it.
+//!
+//! Inline cached dimension tables are reloaded along with the cache, and when they change
+//! the schema version is bumped, so that ETags of cached responses no longer match.
+//!
+//! That thread is watched by another, which logs if it panics and starts it again, after
+//! `RESTART_DELAY` so that a panic on every refresh doesn't spin.
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix::SystemRunner;
+use failure::Error;
+use log::{info, error};
+
+use tesseract_core::{Backend, Schema};
+use tesseract_core::schema::Cube;
+
+use crate::app::SchemaVersion;
+use crate::db_config::{DataSources, cube_backend};
+use crate::subscriptions::Subscriptions;
+
+use super::{Cache, LogicLayerConfig};
+use super::cache::{populate_cube_cache, populate_cube_inline_tables, set_cached_tables};
+use super::config::CacheRefreshConfig;
+
+
+/// How long to wait before restarting the refresh thread after it panicked.
+const RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Starts the refresh thread, and the thread restarting it if it panics, whose handle is
+/// returned. Does nothing if no cube has a refresh interval.
+pub fn start_cache_refresh(
+    refresh_config: CacheRefreshConfig,
+    schema: Arc<RwLock<Schema>>,
+    schema_version: Arc<RwLock<SchemaVersion>>,
+    ll_config: Option<Arc<RwLock<LogicLayerConfig>>>,
+    cache: Arc<RwLock<Cache>>,
+    backend: Box<dyn Backend + Sync + Send>,
+    data_sources: DataSources,
+    subscriptions: Subscriptions,
+) -> Option<thread::JoinHandle<()>>
+{
+    let refresh = CacheRefresh {
+        refresh_config, schema, schema_version, ll_config, cache, backend, data_sources, subscriptions,
+    };
+    let schedule = refresh.initial_schedule();
+
+    if schedule.is_empty() {
+        return None;
+    }
+
+    info!("Cache refresh scheduled for {} cubes", schedule.len());
+
+    let handle = thread::spawn(move || {
+        let mut schedule = schedule;
+
+        loop {
+            let worker = refresh.clone();
+            let worker = thread::spawn(move || worker.run(schedule));
+
+            match worker.join() {
+                // no cube left to refresh
+                Ok(()) => return,
+                Err(_) => {
+                    error!("Cache refresh thread panicked, restarting it in {}s", RESTART_DELAY.as_secs());
+                    thread::sleep(RESTART_DELAY);
+                    schedule = refresh.initial_schedule();
+                },
+            }
+        }
+    });
+
+    Some(handle)
+}
+
+
+/// What the refresh thread needs, so that it can be started again.
+#[derive(Clone)]
+struct CacheRefresh {
+    refresh_config: CacheRefreshConfig,
+    schema: Arc<RwLock<Schema>>,
+    schema_version: Arc<RwLock<SchemaVersion>>,
+    ll_config: Option<Arc<RwLock<LogicLayerConfig>>>,
+    cache: Arc<RwLock<Cache>>,
+    backend: Box<dyn Backend + Sync + Send>,
+    data_sources: DataSources,
+    subscriptions: Subscriptions,
+}
+
+
+impl CacheRefresh {
+    /// Next refresh time of each cube with a refresh interval.
+    fn initial_schedule(&self) -> HashMap<String, Instant> {
+        let now = Instant::now();
+
+        self.schema.read().unwrap().cubes.iter()
+            .filter_map(|cube| {
+                self.refresh_config.cube_interval(&cube.name)
+                    .map(|interval| (cube.name.clone(), now + next_delay(interval, &self.refresh_config)))
+            })
+            .collect()
+    }
+
+    /// Refreshes the cubes as they're due, until none is left in the schedule.
+    fn run(self, schedule: HashMap<String, Instant>) {
+        let CacheRefresh {
+            refresh_config, schema, schema_version, ll_config, cache, backend, data_sources, subscriptions,
+        } = self;
+        let mut schedule = schedule;
+        let mut sys = actix::System::new("tesseract-cache-refresh");
+
+        loop {
+            let (cube_name, due) = match schedule.iter().min_by_key(|(_, due)| **due) {
+                Some((cube_name, due)) => (cube_name.clone(), *due),
+                None => return,
+            };
+
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+
+            // the schema may have been flushed in the meantime
+            let cube = schema.read().unwrap().cubes.iter()
+                .find(|cube| cube.name == cube_name)
+                .cloned();
+
+            let mut cube = match cube {
+                Some(cube) => cube,
+                None => {
+                    info!("Cache refresh: cube {} no longer in schema, unscheduling", cube_name);
+                    schedule.remove(&cube_name);
+                    continue;
+                },
+            };
+
+            let cube_backend = cube_backend(&cube, &backend, &data_sources).clone();
+
+            populate_cube_inline_tables(&mut cube, &cube_backend, &mut sys);
+            {
+                let mut schema = schema.write().unwrap();
+                let inlined = schema.cubes.iter_mut()
+                    .find(|c| c.name == cube_name)
+                    .map(|c| set_cached_tables(c, &cube))
+                    .unwrap_or(false);
+
+                if inlined {
+                    info!("Cache refresh: inline tables of {} changed", cube_name);
+                    *schema_version.write().unwrap() = SchemaVersion::new(&schema);
+                }
+            }
+
+            match refresh_cube_cache(cube, &ll_config, &cache, cube_backend, &mut sys) {
+                Ok(()) => subscriptions.refresh(Some(&cube_name)),
+                // keep serving the old cache, and try again next interval
+                Err(err) => error!("Cache refresh: {} failed: {}", cube_name, err),
+            }
+
+            if let Some(interval) = refresh_config.cube_interval(&cube_name) {
+                schedule.insert(cube_name, Instant::now() + next_delay(interval, &refresh_config));
+            }
+        }
+    }
+}
+
+
+/// Repopulates the cache of one cube, replacing its `CubeCache` once it's ready. On error
+/// the old `CubeCache` is left as is.
+pub fn refresh_cube_cache(
+    cube: Cube,
+    ll_config: &Option<Arc<RwLock<LogicLayerConfig>>>,
+    cache: &Arc<RwLock<Cache>>,
+    backend: Box<dyn Backend + Sync + Send>,
+    sys: &mut SystemRunner,
+) -> Result<(), Error>
+{
+    let cube_name = cube.name.clone();
+
+    let ll_config_snapshot = ll_config.as_ref()
+        .map(|ll_config| ll_config.read().unwrap().clone());
+
+    let time_start = Instant::now();
+
+    let cube_cache = populate_cube_cache(cube, &ll_config_snapshot, backend, sys)?;
+
+    let mut cache = cache.write().unwrap();
+
+    match cache.cubes.iter_mut().find(|c| c.name == cube_name) {
+        Some(c) => *c = cube_cache,
+        None => cache.cubes.push(cube_cache),
+    }
+
+    // lookups aren't keyed by cube, so all are dropped
+    cache.member_lookups.clear();
+
+    let timing = time_start.elapsed();
+    info!("Cache refresh: {} ready (Time elapsed: {}.{:03})", cube_name, timing.as_secs(), timing.subsec_millis());
+
+    Ok(())
+}
+
+
+/// Interval plus a random jitter of up to `refresh_config.jitter` seconds.
+fn next_delay(interval: u64, refresh_config: &CacheRefreshConfig) -> Duration {
+    let jitter = match refresh_config.jitter {
+        Some(jitter) if jitter > 0 => {
+            // no rand dependency; RandomState is randomly seeded
+            let random = RandomState::new().build_hasher().finish();
+            random % (jitter * 1000)
+        },
+        _ => 0,
+    };
+
+    Duration::from_secs(interval) + Duration::from_millis(jitter)
+}
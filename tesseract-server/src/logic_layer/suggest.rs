@@ -0,0 +1,133 @@
+//! Near matches for names which aren't found, so that 404s can suggest what was meant,
+//! e.g. `Exports` for `Expors`.
+
+use tesseract_core::Schema;
+
+use super::config::LogicLayerConfig;
+
+
+/// Most suggestions listed in a message.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Edit distance between `a` and `b`, by chars.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + if a_char == *b_char { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The candidates close enough to `name` to be a typo of it, closest first. Case is
+/// ignored, and names may be a third of their length, at least two edits, away.
+pub fn near_matches<'a, I>(name: &str, candidates: I) -> Vec<String>
+    where I: IntoIterator<Item=&'a String>
+{
+    let name_lower = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut matches: Vec<(usize, &String)> = candidates.into_iter()
+        .map(|candidate| (levenshtein(&name_lower, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+
+    matches.sort();
+    matches.dedup_by(|a, b| a.1 == b.1);
+
+    matches.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.clone())
+        .collect()
+}
+
+/// A not found message for the `kind` of thing named `name`, listing its near matches.
+pub fn not_found_message<'a, I>(kind: &str, name: &str, candidates: I) -> String
+    where I: IntoIterator<Item=&'a String>
+{
+    let matches = near_matches(name, candidates);
+
+    if matches.is_empty() {
+        format!("Could not find {} {}", kind, name)
+    } else {
+        format!("Could not find {} {}. Did you mean: {}?", kind, name, matches.join(", "))
+    }
+}
+
+/// Not found message for a cube, suggesting the visible cubes and their aliases.
+pub fn cube_not_found_message(
+    cube_name: &str,
+    schema: &Schema,
+    ll_config: &Option<LogicLayerConfig>,
+    debug: bool,
+) -> String
+{
+    let mut candidates: Vec<String> = schema.cubes.iter()
+        .filter(|cube| debug || !cube.is_hidden())
+        .map(|cube| cube.name.clone())
+        .collect();
+
+    let alias_cubes = ll_config.as_ref()
+        .and_then(|llc| llc.aliases.as_ref())
+        .and_then(|aliases| aliases.cubes.as_ref());
+
+    if let Some(alias_cubes) = alias_cubes {
+        for alias_cube in alias_cubes {
+            if candidates.contains(&alias_cube.name) {
+                candidates.extend(alias_cube.alternatives.iter().cloned());
+            }
+        }
+    }
+
+    not_found_message("cube", cube_name, &candidates)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("Exports", "Exports"), 0);
+        assert_eq!(levenshtein("Expors", "Exports"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_not_found_message() {
+        let cubes: Vec<String> = vec![
+            "trade_i_baci_a_96".into(),
+            "trade_i_baci_a_02".into(),
+            "Exports".into(),
+            "Imports".into(),
+        ];
+
+        assert_eq!(near_matches("Expors", &cubes), vec!["Exports".to_owned()]);
+        assert_eq!(near_matches("exports", &cubes), vec!["Exports".to_owned(), "Imports".into()]);
+        assert_eq!(
+            near_matches("trade_i_baci_a_92", &cubes),
+            vec!["trade_i_baci_a_02".to_owned(), "trade_i_baci_a_96".into()]
+        );
+
+        assert_eq!(
+            not_found_message("cube", "Expors", &cubes),
+            "Could not find cube Expors. Did you mean: Exports?"
+        );
+        assert_eq!(
+            not_found_message("cube", "Population", &cubes),
+            "Could not find cube Population"
+        );
+    }
+}
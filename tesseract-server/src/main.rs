@@ -19,37 +19,92 @@
 //! The database is able to be declared in the schema, each fact table and dim can be from
 //! different databases. Supported: clickhouse, postgres, mysql, sqlite.
 
+mod api_keys;
 mod app;
+mod circuit_breaker;
 mod db_config;
 mod errors;
+mod export;
 mod auth;
+mod commands;
+mod config_file;
 pub mod handlers;
+mod logging;
 mod logic_layer;
+mod query_stats;
+mod rate_limit;
+mod request_id;
+mod request_limits;
+mod retry;
 mod schema_config;
+mod shutdown;
+mod subscriptions;
 
+use actix::Actor;
 use actix_web::server;
 use dotenv::dotenv;
 use failure::{Error, format_err};
 use log::*;
-use std::env;
+use std::net::IpAddr;
+use std::time::Duration;
 use structopt::StructOpt;
 use url::Url;
 
 use std::sync::{Arc, RwLock};
 
-use crate::app::{EnvVars, SchemaSource, create_app};
+use crate::api_keys::{ApiKeys, read_api_keys_file, read_api_keys_table};
+use crate::app::{CorsConfig, EnvVars, QueryLimits, SchemaSource, SchemaVersion, create_app};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerBackend, CircuitBreakerConfig, start_health_checks};
+use crate::config_file::Config;
+use crate::db_config::DataSources;
+use crate::logging::LogFormat;
+use crate::export::{ExportJobs, S3Target};
+use crate::rate_limit::RateLimiter;
+use crate::request_limits::RequestLimits;
+use crate::retry::{RetryBackend, RetryConfig};
+use crate::query_stats::QueryStats;
+use crate::shutdown::GracefulShutdown;
+use crate::subscriptions::Subscriptions;
 use r2d2_redis::{r2d2, RedisConnectionManager};
+use rusoto_core::Region;
+use tesseract_core::Backend;
 
 fn main() -> Result<(), Error> {
     // Configuration
 
-    pretty_env_logger::init();
     dotenv().ok();
     let opt = Opt::from_args();
 
+    // env vars (and .env) override the config file
+    let config = Config::load(opt.config_path.as_ref().map(|path| path.as_str()))?;
+
+    let log_format = config.var("TESSERACT_LOG_FORMAT")
+        .map(|format| format.parse::<LogFormat>())
+        .unwrap_or(Ok(LogFormat::Text))?;
+    logging::init(log_format);
+
+    match opt.command {
+        None | Some(Command::Serve) => (),
+        Some(Command::ValidateSchema { ref schema_path }) => {
+            return commands::validate_schema(schema_path);
+        },
+        Some(Command::GenerateSql { ref cube, ref query, ref schema_path }) => {
+            let schema_path = match schema_path {
+                Some(schema_path) => schema_path.clone(),
+                None => config.var("TESSERACT_SCHEMA_FILEPATH")
+                    .map_err(|_| format_err!("schema path not found; either TESSERACT_SCHEMA_FILEPATH or --schema required"))?,
+            };
+            return commands::generate_sql(&config, &schema_path, &database_url(&opt, &config)?, cube, query);
+        },
+        Some(Command::CheckDb) => {
+            let mut sys = actix::System::new("tesseract-check-db");
+            return commands::check_db(&config, &database_url(&opt, &config)?, &mut sys);
+        },
+    }
+
     // debug is boolean, but env var is Result.
     // cli opt overrides env var if env_var is false
-    let env_var_debug = env::var("TESSERACT_DEBUG")
+    let env_var_debug = config.var("TESSERACT_DEBUG")
         .map_err(|_| format_err!(""))
         .and_then(|d| {
              d.parse::<bool>()
@@ -69,7 +124,7 @@ fn main() -> Result<(), Error> {
     // cli is boolean, but env var is Result.
     // cli opt overrides env var if env_var is false
     // TODO this has the same logic as for debug. make util fn?
-    let env_var_streaming_response = env::var("TESSERACT_STREAMING_RESPONSE")
+    let env_var_streaming_response = config.var("TESSERACT_STREAMING_RESPONSE")
         .map_err(|_| format_err!(""))
         .and_then(|d| {
              d.parse::<bool>()
@@ -86,28 +141,85 @@ fn main() -> Result<(), Error> {
     };
 
     // address
-    let server_addr = opt.address.unwrap_or("127.0.0.1:7777".to_owned());
+    let server_addr = opt.address.clone().unwrap_or("127.0.0.1:7777".to_owned());
 
     // JSONWebToken Secret
-    let jwt_secret = env::var("TESSERACT_JWT_SECRET").ok();
+    let jwt_secret = config.var("TESSERACT_JWT_SECRET").ok();
 
     // flush
-    let flush_secret = env::var("TESSERACT_FLUSH_SECRET").ok();
+    let flush_secret = config.var("TESSERACT_FLUSH_SECRET").ok();
 
     // Database
-    let db_url_full = env::var("TESSERACT_DATABASE_URL")
-        .or(opt.database_url.ok_or(format_err!("")))
-        .map_err(|_| format_err!("database url not found; either TESSERACT_DATABASE_URL or cli option required"))?;
+    let db_url_full = database_url(&opt, &config)?;
+
+    let (db, db_url, db_type) = db_config::get_db(&config, &db_url_full)?;
+    let db_type_viz = format!("{}, {} sql", db_type, db.dialect().name());
+
+    // Other databases, for cubes with a `data_source`
+    let data_source_list = db_config::get_data_sources(&config)?;
+    let data_source_viz: Vec<String> = data_source_list.iter()
+        .map(|(name, db, url, db_type)| format!("{}: {}, {}, {} sql", name, url, db_type, db.dialect().name()))
+        .collect();
+    let data_sources: DataSources = data_source_list.into_iter()
+        .map(|(name, db, _, _)| (name, db))
+        .collect();
+
+    // Retries on transient database errors
+    let retry_config = match parse_env_limit::<u32>(&config, "TESSERACT_DB_MAX_RETRIES")? {
+        Some(max_retries) if max_retries > 0 => {
+            let backoff = parse_env_limit::<u64>(&config, "TESSERACT_DB_RETRY_BACKOFF_MS")?.unwrap_or(100);
+            Some(RetryConfig { max_retries, backoff: Duration::from_millis(backoff) })
+        },
+        _ => None,
+    };
+    let (db, data_sources) = match &retry_config {
+        Some(retry_config) => {
+            let with_retries = |db| Box::new(RetryBackend::new(db, retry_config.clone())) as Box<dyn Backend + Send + Sync>;
+            let data_sources = data_sources.into_iter()
+                .map(|(name, db)| (name, with_retries(db)))
+                .collect();
+            (with_retries(db), data_sources)
+        },
+        None => (db, data_sources),
+    };
 
-    let (db, db_url, db_type) = db_config::get_db(&db_url_full)?;
-    let db_type_viz = db_type.clone();
+    // Fail fast while a database is down, instead of queueing requests on it
+    let circuit_breaker_config = match parse_env_limit::<u32>(&config, "TESSERACT_DB_CIRCUIT_BREAKER_FAILURES")? {
+        Some(failure_threshold) if failure_threshold > 0 => {
+            let interval = parse_env_limit::<u64>(&config, "TESSERACT_DB_HEALTH_CHECK_INTERVAL")?.unwrap_or(10);
+            Some(CircuitBreakerConfig { failure_threshold, check_interval: Duration::from_secs(interval.max(1)) })
+        },
+        _ => None,
+    };
+    let (db, data_sources) = match circuit_breaker_config {
+        Some(config) => {
+            let mut probed = vec![];
+            let mut with_breaker = |name: &str, db: Box<dyn Backend + Send + Sync>| {
+                let breaker = CircuitBreaker::new(name, config.clone());
+                probed.push((breaker.clone(), db.clone()));
+                Box::new(CircuitBreakerBackend::new(db, breaker)) as Box<dyn Backend + Send + Sync>
+            };
+
+            let db = with_breaker("default", db);
+            let data_sources: DataSources = data_sources.into_iter()
+                .map(|(name, db)| {
+                    let db = with_breaker(&name, db);
+                    (name, db)
+                })
+                .collect();
+
+            start_health_checks(probed, config.check_interval);
+            (db, data_sources)
+        },
+        None => (db, data_sources),
+    };
 
     // Schema
-    let schema_path = env::var("TESSERACT_SCHEMA_FILEPATH")
+    let schema_path = config.var("TESSERACT_SCHEMA_FILEPATH")
         .expect("TESSERACT_SCHEMA_FILEPATH not found");
 
     // Geoservice
-    let geoservice_url = match env::var("TESSERACT_GEOSERVICE_URL") {
+    let geoservice_url = match config.var("TESSERACT_GEOSERVICE_URL") {
         Ok(geoservice_url) => {
             Some(Url::parse(&geoservice_url).unwrap())
         },
@@ -122,13 +234,36 @@ fn main() -> Result<(), Error> {
 
     let mut schema = schema_config::read_schema(&schema_path)?;
     schema.validate()?;
+    db_config::validate_data_sources(&schema, &data_sources)?;
     let mut has_unique_levels_properties = schema.has_unique_levels_properties();
     let schema_arc = Arc::new(RwLock::new(schema.clone()));
+    let schema_version_arc = Arc::new(RwLock::new(SchemaVersion::new(&schema)));
     let jwt_status = if jwt_secret.is_some() {
         "ON"
     } else {
         "OFF"
     };
+    // Query limits
+    let query_limits = QueryLimits {
+        max_drilldowns: parse_env_limit(&config, "TESSERACT_MAX_DRILLDOWNS")?,
+        max_cut_members: parse_env_limit(&config, "TESSERACT_MAX_CUT_MEMBERS")?,
+        max_result_cells: parse_env_limit(&config, "TESSERACT_MAX_RESULT_CELLS")?,
+        max_rows: parse_env_limit(&config, "TESSERACT_MAX_ROWS")?,
+    };
+
+    let request_limits = RequestLimits {
+        max_query_length: parse_env_limit(&config, "TESSERACT_MAX_QUERY_LENGTH")?,
+        max_body_size: parse_env_limit(&config, "TESSERACT_MAX_BODY_SIZE")?,
+    };
+
+    let logic_layer_config_path = config.var("TESSERACT_LOGIC_LAYER_CONFIG_FILEPATH").ok();
+
+    let dataframe_growth = match config.var("TESSERACT_DATAFRAME_GROWTH") {
+        Ok(val) => val.parse::<bool>()
+            .map_err(|_| format_err!("could not parse bool from env_var TESSERACT_DATAFRAME_GROWTH"))?,
+        Err(_) => false,
+    };
+
     // Env
     let env_vars = EnvVars {
         database_url: db_url.clone(),
@@ -136,11 +271,15 @@ fn main() -> Result<(), Error> {
         schema_source,
         jwt_secret,
         flush_secret,
+        query_limits,
+        request_limits,
+        logic_layer_config_path: logic_layer_config_path.clone(),
+        dataframe_growth,
     };
 
     // Logic Layer Config
-    let logic_layer_config = match env::var("TESSERACT_LOGIC_LAYER_CONFIG_FILEPATH") {
-        Ok(config_path) => {
+    let logic_layer_config = match logic_layer_config_path {
+        Some(config_path) => {
             match logic_layer::read_config(&config_path) {
                 Ok(config_obj) => {
                     has_unique_levels_properties = config_obj.has_unique_levels_properties(&schema)?;
@@ -149,32 +288,62 @@ fn main() -> Result<(), Error> {
                 Err(err) => return Err(err)
             }
         },
-        Err(_) => None
+        None => None
     };
 
     // Initialize actix system
     let mut sys = actix::System::new("tesseract");
 
+    // Inline small dimension tables, which needs the system to run queries
+    logic_layer::populate_inline_tables(&mut schema, &db, &data_sources, &mut sys);
+    *schema_arc.write().unwrap() = schema.clone();
+
     // Populate internal cache
     let cache = logic_layer::populate_cache(
-        schema.clone(), &logic_layer_config, db.clone(), &mut sys
+        schema.clone(), &logic_layer_config, db.clone(), &data_sources, &mut sys
     ).map_err(|err| format_err!("Cache population error: {}", err))?;
 
     let cache_arc = Arc::new(RwLock::new(cache));
 
+    let cache_refresh_config = logic_layer_config.as_ref()
+        .and_then(|ll_config| ll_config.cache_refresh.clone());
+
     // Create lock on logic layer config
     let logic_layer_config = match logic_layer_config {
         Some(ll_config) => Some(Arc::new(RwLock::new(ll_config))),
         None => None
     };
 
-    let redis_url = env::var("TESSERACT_REDIS_URL").ok();
+    // WebSocket sessions, whose subscribed queries are re-run when the cache is refreshed
+    let subscriptions = Subscriptions::new();
+
+    // Statistics of the aggregate queries, shared by the workers
+    let query_stats = QueryStats::new();
+
+    // Refresh the cache in the background, so that `latest` time cuts keep up with new data.
+    // The thread restarts the refresh if it panics, and runs for as long as the server does.
+    let _cache_refresh = if let Some(cache_refresh_config) = cache_refresh_config {
+        logic_layer::start_cache_refresh(
+            cache_refresh_config,
+            schema_arc.clone(),
+            schema_version_arc.clone(),
+            logic_layer_config.clone(),
+            cache_arc.clone(),
+            db.clone(),
+            data_sources.clone(),
+            subscriptions.clone(),
+        )
+    } else {
+        None
+    };
+
+    let redis_url = config.var("TESSERACT_REDIS_URL").ok();
 
     // Setup redis pool and settings if enabled by user
     let redis_pool = match redis_url {
         Some(conn_str) => {
-            let redis_connection_timeout = env::var("TESSERACT_REDIS_TIMEOUT").ok();
-            let redis_max_size = env::var("TESSERACT_REDIS_MAX_SIZE").ok();
+            let redis_connection_timeout = config.var("TESSERACT_REDIS_TIMEOUT").ok();
+            let redis_max_size = config.var("TESSERACT_REDIS_MAX_SIZE").ok();
 
             let manager = RedisConnectionManager::new(conn_str).expect("Failed to connect to redis");
             let pool: r2d2::Pool<RedisConnectionManager> = r2d2::Pool::builder()
@@ -195,11 +364,106 @@ fn main() -> Result<(), Error> {
         None => None,
     };
 
+    // Rate limiting, in requests per minute per client
+    let rate_limit = match config.var("TESSERACT_RATE_LIMIT") {
+        Ok(val) => {
+            Some(val.parse::<u32>()
+                .map_err(|_| format_err!("could not parse u32 from env_var TESSERACT_RATE_LIMIT"))?)
+        },
+        Err(_) => None,
+    };
+    // Proxies trusted to name the client in X-Forwarded-For, for rate limiting
+    let trusted_proxies = match config.var("TESSERACT_TRUSTED_PROXIES") {
+        Ok(val) => {
+            split_env_list(&val).iter()
+                .map(|proxy| proxy.parse::<IpAddr>()
+                    .map_err(|_| format_err!("could not parse IP address {} from env_var TESSERACT_TRUSTED_PROXIES", proxy)))
+                .collect::<Result<Vec<_>, _>>()?
+        },
+        Err(_) => Vec::new(),
+    };
+    let rate_limiter = rate_limit
+        .map(|limit| RateLimiter::new(limit).with_trusted_proxies(trusted_proxies));
+
+    // API keys, from a JSON file or a database table
+    let api_key_configs = match config.var("TESSERACT_API_KEYS_FILEPATH") {
+        Ok(path) => Some(read_api_keys_file(&path)?),
+        Err(_) => match config.var("TESSERACT_API_KEYS_TABLE") {
+            Ok(table) => Some(read_api_keys_table(&table, db.clone(), &mut sys)?),
+            Err(_) => None,
+        },
+    };
+    let api_keys = api_key_configs.map(ApiKeys::new);
+    let api_keys_status = match &api_keys {
+        Some(api_keys) => format!("ON ({} keys)", api_keys.len()),
+        None => "OFF".to_owned(),
+    };
+
+    // CORS, enabled by setting allowed origins (`*` allows any origin)
+    let cors_config = config.var("TESSERACT_CORS_ALLOWED_ORIGINS").ok()
+        .map(|origins| {
+            let allowed_origins = split_env_list(&origins);
+            CorsConfig {
+                allowed_origins: if allowed_origins.iter().any(|o| o == "*") {
+                    None
+                } else {
+                    Some(allowed_origins)
+                },
+                allowed_methods: config.var("TESSERACT_CORS_ALLOWED_METHODS").ok()
+                    .map(|methods| split_env_list(&methods)),
+                allowed_headers: config.var("TESSERACT_CORS_ALLOWED_HEADERS").ok()
+                    .map(|headers| split_env_list(&headers)),
+            }
+        });
+    let cors_status = if cors_config.is_some() {
+        "ON"
+    } else {
+        "OFF"
+    };
+
+    // Export jobs, enabled by setting a spool directory. Results can be delivered to S3 instead.
+    let export_s3 = match config.var("TESSERACT_EXPORT_S3_BUCKET") {
+        Ok(bucket) => {
+            let region = match config.var("TESSERACT_EXPORT_S3_ENDPOINT") {
+                Ok(endpoint) => Region::Custom {
+                    name: config.var("TESSERACT_EXPORT_S3_REGION").unwrap_or_else(|_| "us-east-1".to_owned()),
+                    endpoint,
+                },
+                Err(_) => match config.var("TESSERACT_EXPORT_S3_REGION") {
+                    Ok(region) => region.parse::<Region>()
+                        .map_err(|_| format_err!("could not parse region from env_var TESSERACT_EXPORT_S3_REGION"))?,
+                    Err(_) => Region::default(),
+                },
+            };
+            let expires = parse_env_limit::<u64>(&config, "TESSERACT_EXPORT_S3_URL_EXPIRES")?.unwrap_or(3600);
+
+            Some(S3Target {
+                bucket,
+                prefix: config.var("TESSERACT_EXPORT_S3_PREFIX").unwrap_or_default(),
+                region,
+                expires: Duration::from_secs(expires),
+            })
+        },
+        Err(_) => None,
+    };
+    let export_s3_viz = export_s3.as_ref()
+        .map(|s3| format!("s3://{}/{}", s3.bucket, s3.prefix));
+
+    let export_dir = config.var("TESSERACT_EXPORT_DIR").ok();
+    let export_max_jobs = parse_env_limit::<usize>(&config, "TESSERACT_EXPORT_MAX_JOBS")?;
+    let export_jobs = export_dir.as_ref()
+        .map(|dir| ExportJobs::new(dir, export_s3, export_max_jobs))
+        .transpose()?;
+
+    let shutdown_timeout = parse_env_limit::<u64>(&config, "TESSERACT_SHUTDOWN_TIMEOUT")?.unwrap_or(30);
+    let shutdown_export_jobs = export_jobs.clone();
+
     // Initialize Server
-    server::new(
+    let http_server = server::new(
         move|| create_app(
                 debug,
                 db.clone(),
+                data_sources.clone(),
                 match &redis_pool {
                     Some(pool) => Some(pool.clone()),
                     None => None
@@ -207,21 +471,58 @@ fn main() -> Result<(), Error> {
                 db_type.clone(),
                 env_vars.clone(),
                 schema_arc.clone(),
+                schema_version_arc.clone(),
                 cache_arc.clone(),
                 logic_layer_config.clone(),
                 streaming_response,
                 has_unique_levels_properties.clone(),
+                rate_limiter.clone(),
+                api_keys.clone(),
+                cors_config.clone(),
+                export_jobs.clone(),
+                subscriptions.clone(),
+                query_stats.clone(),
             )
         )
         .bind(&server_addr)
         .expect(&format!("cannot bind to {}", server_addr))
+        // signals are handled by `GracefulShutdown`
+        .disable_signals()
         .start();
 
+    GracefulShutdown::new(
+        http_server.clone().recipient(),
+        http_server.recipient(),
+        shutdown_export_jobs,
+        Duration::from_secs(shutdown_timeout),
+    ).start();
+
     println!("Tesseract listening on: {}", server_addr);
     println!("Tesseract database:     {}, {}", db_url, db_type_viz);
+    for data_source in &data_source_viz {
+        println!("Tesseract data source:  {}", data_source);
+    }
     println!("Tesseract schema path:  {}", schema_path);
 
     println!("Tesseract JWT token protection: {}", jwt_status);
+    println!("Tesseract CORS: {}", cors_status);
+    println!("Tesseract API keys: {}", api_keys_status);
+
+    if let Some(export_dir) = &export_dir {
+        println!("Tesseract export directory: {}", export_dir);
+
+        if let Some(export_s3) = &export_s3_viz {
+            println!("Tesseract export delivery: {}", export_s3);
+        }
+    }
+
+    if let Some(retry_config) = &retry_config {
+        println!("Tesseract database retries: {}", retry_config.max_retries);
+    }
+
+    if let Some(limit) = rate_limit {
+        println!("Tesseract rate limit: {} requests/min", limit);
+    }
 
     if debug {
         println!("Tesseract debug mode: ON");
@@ -236,6 +537,38 @@ fn main() -> Result<(), Error> {
 }
 
 
+/// Database url, from the cli option, else the env var, else the config file.
+fn database_url(opt: &Opt, config: &Config) -> Result<String, Error> {
+    match opt.database_url {
+        Some(ref database_url) => Ok(database_url.clone()),
+        None => config.var("TESSERACT_DATABASE_URL")
+            .map_err(|_| format_err!("database url not found; either TESSERACT_DATABASE_URL or cli option required")),
+    }
+}
+
+
+/// Splits a comma-separated env var value into its trimmed, non-empty items.
+fn split_env_list(val: &str) -> Vec<String> {
+    val.split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+
+/// Parses an optional numeric limit from an env var.
+fn parse_env_limit<T: std::str::FromStr>(config: &Config, var: &str) -> Result<Option<T>, Error> {
+    match config.var(var) {
+        Ok(val) => {
+            val.parse::<T>()
+                .map(Some)
+                .map_err(|_| format_err!("could not parse number from env_var {}", var))
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+
 /// CLI arguments helper.
 #[derive(Debug, StructOpt)]
 #[structopt(name="tesseract")]
@@ -254,4 +587,39 @@ struct Opt {
 
     #[structopt(long="streaming")]
     streaming_response: bool,
+
+    /// Toml config file, with the env vars as keys; env vars override its values
+    #[structopt(long="config")]
+    config_path: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// Subcommands; without one, the server is started.
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Starts the server (the default)
+    #[structopt(name="serve")]
+    Serve,
+
+    /// Reads and validates a schema file
+    #[structopt(name="validate-schema")]
+    ValidateSchema {
+        schema_path: String,
+    },
+
+    /// Prints the sql of an aggregate query string, without running it
+    #[structopt(name="generate-sql")]
+    GenerateSql {
+        cube: String,
+        query: String,
+        /// Defaults to TESSERACT_SCHEMA_FILEPATH
+        #[structopt(long="schema")]
+        schema_path: Option<String>,
+    },
+
+    /// Checks that the database and data sources can be queried
+    #[structopt(name="check-db")]
+    CheckDb,
 }
@@ -0,0 +1,104 @@
+//! Statistics of the aggregate queries run, by cube and combination of drilldown levels.
+//!
+//! The combinations queried most, weighted by the time spent on them, are the best candidates
+//! for pre-aggregation tables (see `handlers::preaggregations`). Statistics are only kept in
+//! memory, per server, and are reset on restart.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tesseract_core::names::LevelName;
+
+
+/// Most level combinations tracked; queries on new combinations past this aren't recorded.
+const MAX_COMBINATIONS: usize = 10_000;
+
+
+/// Number of queries on a level combination and the time spent on them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryStat {
+    pub count: u64,
+    pub total_ms: u64,
+}
+
+
+/// Queries recorded by cube and drilldown levels. Cloning is cheap; all clones (one per
+/// server worker) share the same statistics.
+#[derive(Clone, Default)]
+pub struct QueryStats {
+    stats: Arc<Mutex<HashMap<(String, Vec<LevelName>), QueryStat>>>,
+}
+
+impl QueryStats {
+    pub fn new() -> Self {
+        QueryStats::default()
+    }
+
+    /// Records a query on `cube` drilled down on `levels`, which took `elapsed`. Levels are
+    /// sorted, so the order of the drilldowns doesn't matter. Queries without drilldowns
+    /// aren't recorded.
+    pub fn record(&self, cube: &str, levels: &[LevelName], elapsed: Duration) {
+        if levels.is_empty() {
+            return;
+        }
+
+        let mut levels = levels.to_vec();
+        levels.sort_by_key(|level| level.to_string());
+        levels.dedup();
+
+        let mut stats = self.stats.lock().unwrap();
+
+        let key = (cube.to_owned(), levels);
+        if !stats.contains_key(&key) && stats.len() >= MAX_COMBINATIONS {
+            return;
+        }
+
+        let stat = stats.entry(key).or_default();
+        stat.count += 1;
+        stat.total_ms += elapsed.as_millis() as u64;
+    }
+
+    /// Level combinations queried at least `min_count` times, by the total time spent on
+    /// them (most first), up to `limit`.
+    pub fn top(&self, min_count: u64, limit: usize) -> Vec<(String, Vec<LevelName>, QueryStat)> {
+        let stats = self.stats.lock().unwrap();
+
+        let mut top: Vec<_> = stats.iter()
+            .filter(|(_, stat)| stat.count >= min_count)
+            .map(|((cube, levels), stat)| (cube.clone(), levels.clone(), stat.clone()))
+            .collect();
+
+        top.sort_by(|a, b| b.2.total_ms.cmp(&a.2.total_ms).then(b.2.count.cmp(&a.2.count)));
+        top.truncate(limit);
+
+        top
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query_stats() {
+        let stats = QueryStats::new();
+        let year = LevelName::new("Year", "Year", "Year");
+        let category = LevelName::new("Category", "Category", "Category");
+
+        stats.record("Sales", &[year.clone(), category.clone()], Duration::from_millis(100));
+        stats.record("Sales", &[category.clone(), year.clone()], Duration::from_millis(300));
+        stats.record("Sales", &[year.clone()], Duration::from_millis(50));
+        stats.record("Sales", &[], Duration::from_millis(1000));
+
+        let top = stats.top(1, 10);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].1, vec![category, year.clone()]);
+        assert_eq!(top[0].2, QueryStat { count: 2, total_ms: 400 });
+        assert_eq!(top[1].1, vec![year]);
+
+        assert_eq!(stats.top(2, 10).len(), 1);
+        assert_eq!(stats.top(1, 1).len(), 1);
+    }
+}
@@ -0,0 +1,246 @@
+//! Rate limiting middleware.
+//!
+//! Requests are counted per client over a fixed one minute window. A client is identified by
+//! its API key when it's a configured one, else by the subject of its JWT token when the token
+//! validates, and by its remote IP otherwise; a client can't get a new window by sending
+//! made up keys or tokens. The remote IP is the peer address of the connection; the
+//! `X-Forwarded-For` header is only read when the peer is a configured trusted proxy, since
+//! any client can send one. Once a client is over the limit, requests are rejected with
+//! `429 Too Many Requests` until the window resets.
+//!
+//! Every response carries the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+//! headers, so that well-behaved clients can pace themselves.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Middleware, Response, Started};
+
+use crate::api_keys::API_KEY_HEADER;
+use crate::app::AppState;
+use crate::auth::{extract_token, token_subject};
+
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+// The map of clients is only swept once it grows past this size, so that
+// the common case doesn't pay for it.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+
+/// Result of counting one request against a client's window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the current window resets.
+    pub reset: u64,
+    pub allowed: bool,
+}
+
+
+/// Shared request counters. Cloning is cheap; all clones (one per server
+/// worker) count against the same map.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: u32,
+    trusted_proxies: Vec<IpAddr>,
+    clients: Arc<Mutex<HashMap<String, (Instant, u32)>>>,
+}
+
+impl RateLimiter {
+    /// `limit` is the number of requests allowed per client per minute.
+    pub fn new(limit: u32) -> Self {
+        RateLimiter {
+            limit,
+            trusted_proxies: Vec::new(),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Proxies whose `X-Forwarded-For` header is trusted to name the client.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Counts a request for `key` at time `now`.
+    pub fn check(&self, key: &str, now: Instant) -> RateLimitStatus {
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() > SWEEP_THRESHOLD {
+            clients.retain(|_, (window_start, _)| now.duration_since(*window_start) < RATE_LIMIT_WINDOW);
+        }
+
+        let entry = clients.entry(key.to_owned()).or_insert((now, 0));
+
+        if now.duration_since(entry.0) >= RATE_LIMIT_WINDOW {
+            *entry = (now, 0);
+        }
+
+        let allowed = entry.1 < self.limit;
+        if allowed {
+            entry.1 += 1;
+        }
+
+        let reset = (RATE_LIMIT_WINDOW - now.duration_since(entry.0)).as_secs();
+
+        RateLimitStatus {
+            limit: self.limit,
+            remaining: self.limit - entry.1,
+            reset,
+            allowed,
+        }
+    }
+}
+
+
+/// Identifies the client making the request; a configured API key, else the subject of a
+/// valid JWT token, else the remote IP.
+pub fn client_key(req: &HttpRequest<AppState>) -> String {
+    let api_key = req.headers().get(API_KEY_HEADER)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.trim());
+    if let (Some(api_keys), Some(api_key)) = (&req.state().api_keys, api_key) {
+        if api_keys.contains(api_key) {
+            return format!("key:{}", api_key);
+        }
+    }
+
+    if let Some(subject) = token_subject(&req.state().env_vars.jwt_secret, &extract_token(req)) {
+        return format!("token:{}", subject);
+    }
+
+    let peer = match req.peer_addr() {
+        Some(addr) => addr.ip(),
+        None => return "ip:unknown".to_owned(),
+    };
+
+    let trusted_proxies = req.state().rate_limiter.as_ref()
+        .map(|rate_limiter| rate_limiter.trusted_proxies.as_slice())
+        .unwrap_or(&[]);
+    let forwarded_for = req.headers().get("x-forwarded-for")
+        .and_then(|val| val.to_str().ok());
+
+    format!("ip:{}", remote_ip(peer, forwarded_for, trusted_proxies))
+}
+
+
+/// The client's IP. Behind trusted proxies, it's the last address in `X-Forwarded-For` which
+/// isn't one of them, since each proxy appends the address it got the request from; the
+/// addresses before it were sent by the client and can't be trusted.
+fn remote_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let forwarded_for = match forwarded_for {
+        Some(val) => val,
+        None => return peer,
+    };
+
+    let mut client = peer;
+    for addr in forwarded_for.rsplit(',') {
+        match addr.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            },
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+
+fn set_rate_limit_headers(resp: &mut HttpResponse, status: &RateLimitStatus) {
+    let headers = resp.headers_mut();
+    headers.insert(HeaderName::from_static("ratelimit-limit"), HeaderValue::from(status.limit));
+    headers.insert(HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(status.remaining));
+    headers.insert(HeaderName::from_static("ratelimit-reset"), HeaderValue::from(status.reset));
+}
+
+
+impl Middleware<AppState> for RateLimiter {
+    fn start(&self, req: &HttpRequest<AppState>) -> ActixResult<Started> {
+        let status = self.check(&client_key(req), Instant::now());
+
+        if !status.allowed {
+            let mut resp = HttpResponse::TooManyRequests()
+                .header("Retry-After", status.reset.to_string())
+                .json("Rate limit exceeded, please try again later".to_string());
+            set_rate_limit_headers(&mut resp, &status);
+
+            return Ok(Started::Response(resp));
+        }
+
+        req.extensions_mut().insert(status);
+
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<AppState>, mut resp: HttpResponse) -> ActixResult<Response> {
+        if let Some(status) = req.extensions().get::<RateLimitStatus>() {
+            set_rate_limit_headers(&mut resp, status);
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_window() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+
+        let first = limiter.check("a", now);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+
+        assert!(limiter.check("a", now).allowed);
+
+        let third = limiter.check("a", now);
+        assert!(!third.allowed);
+        assert_eq!(third.remaining, 0);
+
+        // other clients have their own window
+        assert!(limiter.check("b", now).allowed);
+
+        // window resets after a minute
+        let later = limiter.check("a", now + RATE_LIMIT_WINDOW);
+        assert!(later.allowed);
+        assert_eq!(later.remaining, 1);
+    }
+
+    #[test]
+    fn test_remote_ip() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let proxies = vec![peer, "10.0.0.2".parse().unwrap()];
+        let client: IpAddr = "1.2.3.4".parse().unwrap();
+
+        // forwarded headers from untrusted peers are ignored
+        assert_eq!(remote_ip(client, Some("5.6.7.8"), &proxies), client);
+        assert_eq!(remote_ip(client, Some("5.6.7.8"), &[]), client);
+
+        // behind trusted proxies, only the addresses they appended count
+        assert_eq!(remote_ip(peer, None, &proxies), peer);
+        assert_eq!(remote_ip(peer, Some("1.2.3.4"), &proxies), client);
+        assert_eq!(remote_ip(peer, Some("5.6.7.8, 1.2.3.4, 10.0.0.2"), &proxies), client);
+        assert_eq!(remote_ip(peer, Some("not-an-ip, 1.2.3.4"), &proxies), client);
+    }
+}
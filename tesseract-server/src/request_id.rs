@@ -0,0 +1,189 @@
+//! Per-request IDs, for correlating log lines.
+//!
+//! The `RequestId` middleware gives each request an ID (the client's `X-Request-Id`, if it
+//! sent a reasonable one, or a new UUID), and returns it in the `X-Request-Id` response
+//! header. While the request's handler runs, the ID is the worker thread's current request
+//! ID, which the JSON log format (see `logging`) adds to every log line. It's reset by a
+//! guard once the response is ready, or the request is dropped without one.
+//!
+//! Handlers run their backend queries as futures on the same thread as other requests, so
+//! those futures are wrapped with `with_request_id` to log with the right ID.
+
+use std::cell::RefCell;
+
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Middleware, Response, Started};
+use futures::{Future, Poll};
+use uuid::Uuid;
+
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longer client IDs are replaced, so they don't bloat the logs.
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+thread_local! {
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+
+/// ID of the request currently being handled on this thread, if any.
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Sets the current request ID, returning the previous one.
+fn set_current_request_id(request_id: Option<String>) -> Option<String> {
+    CURRENT_REQUEST_ID.with(|id| id.replace(request_id))
+}
+
+/// Sets the current request ID until dropped, even by a panic, then sets `restore`.
+struct RequestIdGuard {
+    restore: Option<String>,
+}
+
+impl RequestIdGuard {
+    /// For nested scopes, like polling a request's future: the previous ID is restored.
+    fn set(request_id: Option<String>) -> Self {
+        RequestIdGuard { restore: set_current_request_id(request_id) }
+    }
+
+    /// For a request, from its start to its response. There's no current request outside of
+    /// requests, so the ID is then reset: the previous one may be that of a request whose
+    /// handler is still waiting on its query, which would log with its own ID anyway.
+    fn set_for_request(request_id: String) -> Self {
+        set_current_request_id(Some(request_id));
+        RequestIdGuard { restore: None }
+    }
+}
+
+impl Drop for RequestIdGuard {
+    fn drop(&mut self) {
+        set_current_request_id(self.restore.take());
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct RequestIdValue(String);
+
+/// ID of a request, as set by the `RequestId` middleware.
+pub fn request_id<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.extensions().get::<RequestIdValue>().map(|id| id.0.clone())
+}
+
+/// Keeps the client's ID if it's printable ascii and not too long.
+fn valid_client_id(client_id: &str) -> bool {
+    !client_id.is_empty()
+        && client_id.len() <= MAX_CLIENT_ID_LEN
+        && client_id.chars().all(|c| c.is_ascii_graphic())
+}
+
+
+/// Middleware assigning request IDs.
+pub struct RequestId;
+
+impl<S> Middleware<S> for RequestId {
+    fn start(&self, req: &HttpRequest<S>) -> ActixResult<Started> {
+        let request_id = req.headers().get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|client_id| valid_client_id(client_id))
+            .map(|client_id| client_id.to_owned())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let guard = RequestIdGuard::set_for_request(request_id.clone());
+        req.extensions_mut().insert(guard);
+        req.extensions_mut().insert(RequestIdValue(request_id));
+
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<S>, mut resp: HttpResponse) -> ActixResult<Response> {
+        // resets the current request ID
+        req.extensions_mut().remove::<RequestIdGuard>();
+
+        if let Some(request_id) = request_id(req) {
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                resp.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+
+/// A future that is polled with its request's ID as the current request ID.
+pub struct WithRequestId<F> {
+    request_id: Option<String>,
+    inner: F,
+}
+
+impl<F: Future> Future for WithRequestId<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let _guard = RequestIdGuard::set(self.request_id.clone());
+        self.inner.poll()
+    }
+}
+
+/// Wraps a future run for `req`, so that it logs with the request's ID.
+pub fn with_request_id<S, F: Future>(req: &HttpRequest<S>, fut: F) -> WithRequestId<F> {
+    WithRequestId {
+        request_id: request_id(req),
+        inner: fut,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future;
+
+    #[test]
+    fn test_valid_client_id() {
+        assert!(valid_client_id("3f2c9a1e-0b7d-4c55-9d1a-6e0f2b8c4d17"));
+        assert!(!valid_client_id(""));
+        assert!(!valid_client_id("has space"));
+        assert!(!valid_client_id(&"a".repeat(MAX_CLIENT_ID_LEN + 1)));
+    }
+
+    #[test]
+    fn test_with_request_id() {
+        let fut = WithRequestId {
+            request_id: Some("abc".to_owned()),
+            inner: future::lazy(|| future::ok::<_, ()>(current_request_id())),
+        };
+
+        assert_eq!(fut.wait(), Ok(Some("abc".to_owned())));
+        assert_eq!(current_request_id(), None);
+
+        // the previous ID is restored, even if the future panics
+        {
+            let _outer = RequestIdGuard::set(Some("outer".to_owned()));
+
+            let res = std::panic::catch_unwind(|| {
+                WithRequestId {
+                    request_id: Some("abc".to_owned()),
+                    inner: future::lazy(|| -> Result<(), ()> { panic!("query failed") }),
+                }.wait()
+            });
+            assert!(res.is_err());
+            assert_eq!(current_request_id(), Some("outer".to_owned()));
+        }
+        assert_eq!(current_request_id(), None);
+
+        // a request's ID is reset, not restored, once it's done
+        let _outer = RequestIdGuard::set(Some("outer".to_owned()));
+        drop(RequestIdGuard::set_for_request("abc".to_owned()));
+        assert_eq!(current_request_id(), None);
+    }
+}
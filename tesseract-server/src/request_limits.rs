@@ -0,0 +1,175 @@
+//! Limits on the size of requests.
+//!
+//! Long cut lists make for long query strings, which would otherwise fail in whichever
+//! proxy or server limit they hit first, without saying why. The `RequestLimits` middleware
+//! checks the query string and the declared body size first, and returns a `414` or `413`
+//! with the limit, and for aggregate queries, a pointer to the structured query endpoint,
+//! which takes the query as a JSON body instead.
+//!
+//! The limits are global: they apply to the requests of every route alike.
+
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use actix_web::dev::JsonConfig;
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::middleware::{Middleware, Started};
+use serde_derive::Serialize;
+
+
+/// Body size allowed when none is configured, the default of actix's json extractor.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 262_144;
+
+/// Query string length allowed when none is configured. actix drops requests whose head is
+/// over 128 KiB before any middleware runs, so this leaves room for the path and headers,
+/// and longer query strings get the `414` instead.
+pub const DEFAULT_MAX_QUERY_LENGTH: usize = 65_536;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestLimits {
+    /// Length of the query string, in bytes.
+    pub max_query_length: Option<usize>,
+    /// Size of request bodies, in bytes.
+    pub max_body_size: Option<usize>,
+}
+
+/// Body of a `414` or `413` response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LimitExceeded {
+    pub error: String,
+    pub limit: usize,
+    pub size: usize,
+    /// Another way to send the query, if there is one.
+    pub suggestion: Option<String>,
+}
+
+impl RequestLimits {
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE)
+    }
+
+    pub fn max_query_length(&self) -> usize {
+        self.max_query_length.unwrap_or(DEFAULT_MAX_QUERY_LENGTH)
+    }
+
+    /// Checks the query string of a request to `path`.
+    pub fn check_query(&self, path: &str, query_length: usize) -> Option<LimitExceeded> {
+        let limit = self.max_query_length();
+        if query_length <= limit {
+            return None;
+        }
+
+        Some(LimitExceeded {
+            error: format!("Query string of {} bytes is over the limit of {} bytes", query_length, limit),
+            limit,
+            size: query_length,
+            suggestion: query_body_suggestion(path),
+        })
+    }
+
+    /// Checks the size of a body, from its `Content-Length`.
+    pub fn check_body(&self, body_size: usize) -> Option<LimitExceeded> {
+        let limit = self.max_body_size();
+        if body_size <= limit {
+            return None;
+        }
+
+        Some(body_too_large(limit, Some(body_size)))
+    }
+
+    /// Sets the body limit of a json extractor, with an error handler returning a `413`
+    /// when it's over, e.g. for a chunked body without a `Content-Length`.
+    pub fn json_config<S: 'static>(&self, cfg: &mut JsonConfig<S>) {
+        let limit = self.max_body_size();
+
+        cfg.limit(limit)
+            .error_handler(move |err, _req| {
+                let resp = match err {
+                    JsonPayloadError::Overflow => HttpResponse::PayloadTooLarge().json(body_too_large(limit, None)),
+                    ref err => HttpResponse::BadRequest().json(err.to_string()),
+                };
+                InternalError::from_response(err, resp).into()
+            });
+    }
+}
+
+fn body_too_large(limit: usize, body_size: Option<usize>) -> LimitExceeded {
+    LimitExceeded {
+        error: format!("Request body is over the limit of {} bytes", limit),
+        limit,
+        size: body_size.unwrap_or(limit + 1),
+        suggestion: None,
+    }
+}
+
+/// Aggregate queries can be sent to the structured query endpoint instead.
+fn query_body_suggestion(path: &str) -> Option<String> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match &segments[..] {
+        ["cubes", cube, aggregate] if aggregate.starts_with("aggregate") => {
+            Some(format!("Send the query as a JSON body to POST /cubes/{}/query", cube))
+        },
+        ["data"] | ["data.csv"] | ["data.jsonrecords"] | ["data.jsonarrays"] => {
+            Some("Send the query as a JSON body to POST /cubes/<cube>/query".to_owned())
+        },
+        _ => None,
+    }
+}
+
+
+impl<S> Middleware<S> for RequestLimits {
+    fn start(&self, req: &HttpRequest<S>) -> ActixResult<Started> {
+        if let Some(exceeded) = self.check_query(req.path(), req.query_string().len()) {
+            return Ok(Started::Response(HttpResponse::UriTooLong().json(exceeded)));
+        }
+
+        let content_length = req.headers().get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        if let Some(exceeded) = content_length.and_then(|len| self.check_body(len)) {
+            return Ok(Started::Response(HttpResponse::PayloadTooLarge().json(exceeded)));
+        }
+
+        Ok(Started::Done)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_query() {
+        let limits = RequestLimits { max_query_length: Some(10), max_body_size: None };
+
+        assert_eq!(limits.check_query("/cubes/sales/aggregate.csv", 10), None);
+
+        let exceeded = limits.check_query("/cubes/sales/aggregate.csv", 11).unwrap();
+        assert_eq!(exceeded.limit, 10);
+        assert_eq!(exceeded.size, 11);
+        assert_eq!(exceeded.suggestion, Some("Send the query as a JSON body to POST /cubes/sales/query".to_owned()));
+
+        assert!(limits.check_query("/data.jsonrecords", 11).unwrap().suggestion.is_some());
+        assert_eq!(limits.check_query("/cubes/sales/members", 11).unwrap().suggestion, None);
+
+        assert_eq!(RequestLimits::default().check_query("/data", DEFAULT_MAX_QUERY_LENGTH), None);
+        assert!(RequestLimits::default().check_query("/data", DEFAULT_MAX_QUERY_LENGTH + 1).is_some());
+    }
+
+    #[test]
+    fn test_check_body() {
+        let limits = RequestLimits { max_query_length: None, max_body_size: Some(100) };
+        assert_eq!(limits.check_body(100), None);
+        assert_eq!(limits.check_body(101).unwrap().limit, 100);
+
+        assert_eq!(RequestLimits::default().check_body(DEFAULT_MAX_BODY_SIZE), None);
+        assert!(RequestLimits::default().check_body(DEFAULT_MAX_BODY_SIZE + 1).is_some());
+    }
+}
@@ -0,0 +1,169 @@
+//! Retries for transient backend errors.
+//!
+//! `RetryBackend` wraps a `Backend`, and retries `exec_sql` with exponential backoff when the
+//! error looks transient (connection reset or refused, pool timeout, etc.), e.g. while the
+//! database restarts. Other errors, like SQL syntax errors, fail immediately.
+//!
+//! Streaming queries are not retried, since part of the response may already have been sent.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use failure::{Error, format_err};
+use futures::future::{self, Future, Loop};
+use futures::Stream;
+use log::warn;
+use tokio_timer::Delay;
+
+use tesseract_core::{Backend, Capabilities, ColumnInfo, DataFrame, QueryIr, TableInfo};
+use tesseract_core::dialect::SqlDialect;
+
+
+// Lowercase fragments of error messages that are worth retrying. Only timeouts of the
+// connection or the network are; a query that timed out in the database, like ClickHouse's
+// `Timeout exceeded: elapsed ...` past its `max_execution_time`, would only time out again.
+const TRANSIENT_ERRORS: &[&str] = &[
+    "connection reset",
+    "connection refused",
+    "connection closed",
+    "connection aborted",
+    "broken pipe",
+    "connection timed out",
+    "connect timeout",
+    "operation timed out",
+    "timed out waiting for connection",
+    "timeout while getting connection",
+    "while reading from socket",
+    "while writing to socket",
+    "unexpected eof",
+];
+
+// Backoff doesn't grow past this.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled on each further retry.
+    pub backoff: Duration,
+}
+
+impl RetryConfig {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.backoff.checked_mul(factor)
+            .map(|backoff| backoff.min(MAX_BACKOFF))
+            .unwrap_or(MAX_BACKOFF)
+    }
+}
+
+
+#[derive(Clone)]
+pub struct RetryBackend {
+    inner: Box<dyn Backend + Send + Sync>,
+    config: RetryConfig,
+}
+
+impl RetryBackend {
+    pub fn new(inner: Box<dyn Backend + Send + Sync>, config: RetryConfig) -> Self {
+        RetryBackend { inner, config }
+    }
+}
+
+
+/// Whether an error is worth retrying.
+pub fn is_transient(err: &Error) -> bool {
+    let msg = err.to_string().to_lowercase();
+    TRANSIENT_ERRORS.iter().any(|fragment| msg.contains(fragment))
+}
+
+
+impl Backend for RetryBackend {
+    fn exec_sql(&self, sql: String) -> Box<dyn Future<Item=DataFrame, Error=Error>> {
+        let inner = self.inner.clone();
+        let config = self.config.clone();
+
+        let fut = future::loop_fn(0, move |attempt| {
+            let config = config.clone();
+
+            inner.exec_sql(sql.clone())
+                .then(move |res| -> Box<dyn Future<Item=Loop<DataFrame, u32>, Error=Error>> {
+                    match res {
+                        Ok(df) => Box::new(future::ok(Loop::Break(df))),
+                        Err(err) => {
+                            if attempt >= config.max_retries || !is_transient(&err) {
+                                return Box::new(future::err(err));
+                            }
+
+                            let backoff = config.backoff(attempt);
+                            warn!(
+                                "Transient backend error, retry {}/{} in {}ms: {}",
+                                attempt + 1, config.max_retries, backoff.as_millis(), err,
+                            );
+
+                            Box::new(
+                                Delay::new(Instant::now() + backoff)
+                                    .map_err(|err| format_err!("Retry timer error: {}", err))
+                                    .map(move |_| Loop::Continue(attempt + 1))
+                            )
+                        },
+                    }
+                })
+        });
+
+        Box::new(fut)
+    }
+
+    fn exec_sql_stream(&self, sql: String) -> Box<dyn Stream<Item=Result<DataFrame, Error>, Error=Error>> {
+        self.inner.exec_sql_stream(sql)
+    }
+
+    fn box_clone(&self) -> Box<dyn Backend + Send + Sync> {
+        Box::new((*self).clone())
+    }
+
+    fn dialect(&self) -> Arc<dyn SqlDialect> {
+        self.inner.dialect()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn generate_sql(&self, query_ir: QueryIr) -> String {
+        self.inner.generate_sql(query_ir)
+    }
+
+    fn inspect_tables(&self) -> Box<dyn Future<Item=Vec<TableInfo>, Error=Error>> {
+        self.inner.inspect_tables()
+    }
+
+    fn inspect_columns(&self, table: &str) -> Box<dyn Future<Item=Vec<ColumnInfo>, Error=Error>> {
+        self.inner.inspect_columns(table)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff() {
+        let config = RetryConfig { max_retries: 5, backoff: Duration::from_millis(100) };
+        assert_eq!(config.backoff(0), Duration::from_millis(100));
+        assert_eq!(config.backoff(3), Duration::from_millis(800));
+        assert_eq!(config.backoff(40), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&format_err!("Connection reset by peer (os error 104)")));
+        assert!(is_transient(&format_err!("Timeout while getting connection from pool")));
+        assert!(is_transient(&format_err!("Connection timed out (os error 110)")));
+        assert!(is_transient(&format_err!("Code: 209, Timeout exceeded while reading from socket (10.0.0.1:9000)")));
+        assert!(!is_transient(&format_err!("Code: 62, Syntax error: failed at position 8")));
+        assert!(!is_transient(&format_err!("Code: 159, Timeout exceeded: elapsed 30.001 seconds, maximum: 30")));
+    }
+}
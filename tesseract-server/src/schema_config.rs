@@ -1,4 +1,5 @@
 use failure::{Error, format_err};
+use std::path::Path;
 
 use tesseract_core::Schema;
 
@@ -8,10 +9,13 @@ pub fn read_schema(schema_path: &str) -> Result<Schema, Error> {
     let schema_str = std::fs::read_to_string(&schema_path)
         .map_err(|_| format_err!("Schema file not found at {}", schema_path))?;
 
+    // inline tables' csv paths are relative to the schema file
+    let schema_dir = Path::new(schema_path).parent().unwrap_or_else(|| Path::new(""));
+
     let schema = if schema_path.ends_with("xml") {
-        Schema::from_xml(&schema_str)?
+        Schema::from_xml_at(&schema_str, schema_dir)?
     } else if schema_path.ends_with("json") {
-        Schema::from_json(&schema_str)?
+        Schema::from_json_at(&schema_str, schema_dir)?
     } else {
         return Err(format_err!("Schema format not supported"))
     };
@@ -0,0 +1,143 @@
+//! Graceful shutdown.
+//!
+//! On SIGTERM (or SIGINT/SIGQUIT), the server stops accepting new connections, and waits
+//! for in-flight requests and export jobs to finish, up to a timeout, before stopping.
+//! Backend pools are dropped once the server has stopped. A second signal stops the server
+//! right away.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Result as ActixResult,
+};
+use actix_web::middleware::{Finished, Middleware, Started};
+use actix_web::server::{PauseServer, StopServer};
+use log::*;
+
+use crate::export::ExportJobs;
+
+
+// How often to check whether in-flight work is done.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(200);
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Number of requests being handled, across all server workers.
+pub fn in_flight_requests() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+
+/// Middleware counting in-flight requests, so that shutdown can wait for them.
+pub struct InFlightRequests;
+
+impl<S> Middleware<S> for InFlightRequests {
+    fn start(&self, _req: &HttpRequest<S>) -> ActixResult<Started> {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        Ok(Started::Done)
+    }
+
+    fn finish(&self, _req: &HttpRequest<S>, _resp: &HttpResponse) -> Finished {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        Finished::Done
+    }
+}
+
+
+/// Handles termination signals for the server.
+pub struct GracefulShutdown {
+    pause: Recipient<PauseServer>,
+    stop: Recipient<StopServer>,
+    export_jobs: Option<ExportJobs>,
+    timeout: Duration,
+    stopping: bool,
+}
+
+impl GracefulShutdown {
+    pub fn new(
+        pause: Recipient<PauseServer>,
+        stop: Recipient<StopServer>,
+        export_jobs: Option<ExportJobs>,
+        timeout: Duration,
+    ) -> Self
+    {
+        GracefulShutdown {
+            pause,
+            stop,
+            export_jobs,
+            timeout,
+            stopping: false,
+        }
+    }
+
+    fn active_export_jobs(&self) -> usize {
+        self.export_jobs.as_ref()
+            .map(|export_jobs| export_jobs.active_count())
+            .unwrap_or(0)
+    }
+
+    fn stop_now(&self) {
+        let _ = self.stop.do_send(StopServer { graceful: false });
+        System::current().stop();
+    }
+}
+
+impl Actor for GracefulShutdown {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Signal> for GracefulShutdown {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) {
+        match msg.0 {
+            SignalType::Int | SignalType::Term | SignalType::Quit => (),
+            _ => return,
+        }
+
+        if self.stopping {
+            warn!("Shutting down now, without waiting for in-flight requests");
+            self.stop_now();
+            return;
+        }
+        self.stopping = true;
+
+        info!(
+            "Shutting down, waiting up to {}s for {} requests and {} export jobs",
+            self.timeout.as_secs(),
+            in_flight_requests(),
+            self.active_export_jobs(),
+        );
+
+        let _ = self.pause.do_send(PauseServer);
+
+        let deadline = Instant::now() + self.timeout;
+
+        ctx.run_interval(DRAIN_INTERVAL, move |act, _ctx| {
+            let requests = in_flight_requests();
+            let export_jobs = act.active_export_jobs();
+
+            if requests == 0 && export_jobs == 0 {
+                info!("In-flight work done, stopping");
+                act.stop_now();
+            } else if Instant::now() >= deadline {
+                warn!(
+                    "Shutdown timeout, stopping with {} requests and {} export jobs in flight",
+                    requests, export_jobs,
+                );
+                act.stop_now();
+            }
+        });
+    }
+}
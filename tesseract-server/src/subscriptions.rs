@@ -0,0 +1,94 @@
+//! Registry of WebSocket sessions with subscribed queries.
+//!
+//! Sessions (see `handlers::ws`) register on connect. When data may have changed, on a
+//! `/flush` or a background cache refresh, the registry tells every session to re-run its
+//! subscribed queries and push the new results. Sessions start re-running them at
+//! different times, spread over a few seconds, so that a flush doesn't send every
+//! subscribed query to the database at once.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use actix::{Message, Recipient};
+
+
+/// Time over which the sessions' re-runs are spread.
+const REFRESH_SPREAD: Duration = Duration::from_secs(5);
+
+
+/// Asks a session to re-run its subscribed queries on `cube`, or all of them if `None`,
+/// after `delay`.
+#[derive(Debug, Clone)]
+pub struct Refresh {
+    pub cube: Option<String>,
+    pub delay: Duration,
+}
+
+impl Message for Refresh {
+    type Result = ();
+}
+
+
+/// Connected sessions. Cloning is cheap; all clones (one per server worker, plus the
+/// cache refresh thread) share the same sessions.
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    sessions: Arc<Mutex<HashMap<usize, Recipient<Refresh>>>>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions::default()
+    }
+
+    /// Registers a session, returning the id to remove it with.
+    pub fn add(&self, session: Recipient<Refresh>) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.sessions.lock().unwrap().insert(id, session);
+        id
+    }
+
+    pub fn remove(&self, id: usize) {
+        self.sessions.lock().unwrap().remove(&id);
+    }
+
+    /// Tells every session to re-run its queries on `cube` (all queries if `None`).
+    pub fn refresh(&self, cube: Option<&str>) {
+        let sessions = self.sessions.lock().unwrap();
+        let count = sessions.len();
+
+        for (idx, session) in sessions.values().enumerate() {
+            let refresh = Refresh {
+                cube: cube.map(|c| c.to_owned()),
+                delay: refresh_delay(idx, count),
+            };
+            // a session that's gone will be removed when it stops
+            let _ = session.do_send(refresh);
+        }
+    }
+}
+
+/// Delay of the `idx`th of `count` sessions, spread evenly over `REFRESH_SPREAD`.
+fn refresh_delay(idx: usize, count: usize) -> Duration {
+    if count == 0 {
+        return Duration::from_secs(0);
+    }
+    REFRESH_SPREAD * idx as u32 / count as u32
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_refresh_delay() {
+        assert_eq!(refresh_delay(0, 1), Duration::from_secs(0));
+        assert_eq!(refresh_delay(0, 4), Duration::from_secs(0));
+        assert_eq!(refresh_delay(2, 4), Duration::from_millis(2500));
+        assert!(refresh_delay(999, 1000) < REFRESH_SPREAD);
+    }
+}
@@ -0,0 +1,222 @@
+//! Benchmarks for sql generation: building the `QueryIr` from a schema and query, and
+//! generating sql from it with the standard and ClickHouse dialects.
+//!
+//! Run with `cargo bench -p integration-tests`. Setting `TESSERACT_BENCH_DATABASE_URL` to a
+//! ClickHouse url also benchmarks executing the queries, against the webshop tables used by
+//! the end-to-end test.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use futures::Future;
+use std::env;
+use tesseract_clickhouse::{Clickhouse, ClickhouseSql};
+use tesseract_core::{Backend, Query, Schema};
+use tesseract_core::dialect::{SqlDialect, StandardSql};
+
+static SCHEMA_STR: &str = r##"
+<Schema name="Webshop">
+    <SharedDimension name="Geography" type="geo">
+        <Hierarchy name="Geography">
+            <Table name="tesseract_webshop_geographies" />
+            <Level name="Continent" key_column="continent_id" name_column="continent_name"
+                    key_type="text">
+                <Property name="Continent PT" column="continent_name_pt" caption_set="pt" />
+                <Property name="Continent ES" column="continent_name_es" caption_set="es" />
+            </Level>
+            <Level name="Country" key_column="country_id" name_column="country_name"
+                    key_type="nontext">
+                <Property name="Country PT" column="country_name_pt" caption_set="pt" />
+                <Property name="Country ES" column="country_name_es" caption_set="es" />
+            </Level>
+        </Hierarchy>
+    </SharedDimension>
+    <Cube name="Sales">
+        <Table name="tesseract_webshop_sales" />
+        <DimensionUsage foreign_key="country_id" name="Geography" source="Geography" />
+        <Dimension name="Year" foreign_key="year">
+            <Hierarchy name="Year">
+                <Level name="Year" key_column="year" />
+            </Hierarchy>
+        </Dimension>
+
+        <Dimension name="Month" foreign_key="month_id">
+            <Hierarchy name="Month">
+                <Table name="tesseract_webshop_time" />
+
+                <Level name="Month" key_column="month_id" name_column="month_name">
+                    <Property name="Month PT" column="month_name_pt" caption_set="pt" />
+                </Level>
+            </Hierarchy>
+        </Dimension>
+
+        <Dimension name="Category" foreign_key="category_id">
+            <Hierarchy name="Category">
+                <InlineTable alias="tesseract_webshop_categories">
+                    <ColumnDef name="category_name" key_type="text" />
+                    <ColumnDef name="category_name_pt" key_type="text" caption_set="pt" />
+                    <ColumnDef name="category_name_es" key_type="text" caption_set="es" />
+                    <ColumnDef name="category_idx" key_type="nontext" key_column_type="Int32" />
+                    <Row>
+                        <Value column="category_name">Books</Value>
+                        <Value column="category_name_pt">Livros</Value>
+                        <Value column="category_name_es">Libros</Value>
+                        <Value column="category_idx">1</Value>
+                    </Row>
+                    <Row>
+                        <Value column="category_name">Sports</Value>
+                        <Value column="category_name_pt">Esportes</Value>
+                        <Value column="category_name_es">Deportes</Value>
+                        <Value column="category_idx">2</Value>
+                    </Row>
+                    <Row>
+                        <Value column="category_name">Various</Value>
+                        <Value column="category_name_pt">Vários</Value>
+                        <Value column="category_name_es">Varios</Value>
+                        <Value column="category_idx">3</Value>
+                    </Row>
+                    <Row>
+                        <Value column="category_name">Videos</Value>
+                        <Value column="category_name_pt">Vídeos</Value>
+                        <Value column="category_name_es">Videos</Value>
+                        <Value column="category_idx">4</Value>
+                    </Row>
+                </InlineTable>
+
+                <!-- <Level name="Category" key_column="category_id" name_column="category_name" key_type="nontext" /> -->
+                <Level name="Category" key_column="category_idx" name_column="category_name" key_type="nontext" />
+            </Hierarchy>
+        </Dimension>
+        <Measure name="Price Total" column="price_total" aggregator="sum" />
+        <Measure name="Quantity" column="quantity" aggregator="sum" />
+    </Cube>
+</Schema>
+"##;
+
+const CUBE: &str = "Sales";
+
+/// Named queries covering the main sql generation paths.
+fn fixtures() -> Vec<(&'static str, Query)> {
+    let mut simple = Query::new();
+    simple.drilldowns = vec!["Year.Year.Year".parse().unwrap()];
+    simple.measures = vec!["Quantity".parse().unwrap()];
+
+    let mut cuts = Query::new();
+    cuts.drilldowns = vec![
+        "Year.Year.Year".parse().unwrap(),
+        "Geography.Geography.Country".parse().unwrap(),
+    ];
+    cuts.cuts = vec![
+        "Year.Year.Year.2017,2018".parse().unwrap(),
+        "~Category.Category.Category.1".parse().unwrap(),
+    ];
+    cuts.measures = vec!["Quantity".parse().unwrap(), "Price Total".parse().unwrap()];
+    cuts.properties = vec!["Geography.Geography.Country.Country PT".parse().unwrap()];
+
+    let mut wide = Query::new();
+    wide.drilldowns = vec![
+        "Year.Year.Year".parse().unwrap(),
+        "Month.Month.Month".parse().unwrap(),
+        "Geography.Geography.Country".parse().unwrap(),
+        "Category.Category.Category".parse().unwrap(),
+    ];
+    wide.measures = vec!["Quantity".parse().unwrap(), "Price Total".parse().unwrap()];
+    wide.parents = true;
+    wide.sort = Some("Quantity.desc".parse().unwrap());
+    wide.limit = Some("100".parse().unwrap());
+
+    let mut top = Query::new();
+    top.drilldowns = vec![
+        "Year.Year.Year".parse().unwrap(),
+        "Geography.Geography.Country".parse().unwrap(),
+    ];
+    top.measures = vec!["Quantity".parse().unwrap()];
+    top.top = Some("3,Year.Year.Year,Quantity,desc".parse().unwrap());
+    top.filters = vec!["Quantity.gt.10".parse().unwrap()];
+
+    let mut growth = Query::new();
+    growth.drilldowns = vec![
+        "Year.Year.Year".parse().unwrap(),
+        "Geography.Geography.Country".parse().unwrap(),
+    ];
+    growth.measures = vec!["Quantity".parse().unwrap()];
+    growth.growth = Some("Year.Year.Year,Quantity".parse().unwrap());
+
+    let mut rca = Query::new();
+    rca.drilldowns = vec!["Year.Year.Year".parse().unwrap()];
+    rca.measures = vec!["Quantity".parse().unwrap()];
+    rca.rca = Some("Geography.Geography.Country,Category.Category.Category,Quantity".parse().unwrap());
+
+    vec![
+        ("simple", simple),
+        ("cuts", cuts),
+        ("wide", wide),
+        ("top", top),
+        ("growth", growth),
+        ("rca", rca),
+    ]
+}
+
+fn schema() -> Schema {
+    let mut schema = Schema::from_xml(SCHEMA_STR).expect("failed to parse schema");
+    schema.validate().expect("failed to validate schema");
+    schema
+}
+
+fn bench_query_ir(c: &mut Criterion) {
+    let schema = schema();
+    let mut group = c.benchmark_group("query_ir");
+
+    for (name, query) in fixtures() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &query, |b, query| {
+            b.iter(|| schema.sql_query(CUBE, query, None).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_sql(c: &mut Criterion) {
+    let schema = schema();
+    let dialects: Vec<(&str, Box<dyn SqlDialect>)> = vec![
+        ("standard", Box::new(StandardSql)),
+        ("clickhouse", Box::new(ClickhouseSql)),
+    ];
+    let mut group = c.benchmark_group("sql");
+
+    for (name, query) in fixtures() {
+        let (query_ir, _) = schema.sql_query(CUBE, &query, None).unwrap();
+
+        for (dialect_name, dialect) in &dialects {
+            group.bench_with_input(BenchmarkId::new(*dialect_name, name), &query_ir, |b, query_ir| {
+                b.iter_batched(
+                    || query_ir.clone(),
+                    |query_ir| dialect.generate_sql(query_ir),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_end_to_end(c: &mut Criterion) {
+    let db_url = match env::var("TESSERACT_BENCH_DATABASE_URL") {
+        Ok(db_url) => db_url,
+        Err(_) => return,
+    };
+    let backend = Clickhouse::from_url(&db_url).expect("failed to connect to clickhouse");
+    let schema = schema();
+    let mut group = c.benchmark_group("end_to_end");
+    group.sample_size(10);
+
+    for (name, query) in fixtures() {
+        let (query_ir, _) = schema.sql_query(CUBE, &query, None).unwrap();
+        let sql = backend.generate_sql(query_ir);
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), &sql, |b, sql| {
+            b.iter(|| backend.exec_sql(sql.clone()).wait().unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_query_ir, bench_sql, bench_end_to_end);
+criterion_main!(benches);
@@ -1,2 +0,0 @@
-use tesseract_clickhouse;
-use tesseract_core;
@@ -1,11 +1,14 @@
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use actix_web::server;
 use failure::{Error, format_err};
 use log::*;
 use std::env;
-use tesseract_olap::app::{EnvVars, SchemaSource, create_app};
+use tesseract_olap::app::{EnvVars, QueryLimits, SchemaSource, SchemaVersion, create_app};
+use tesseract_olap::subscriptions::Subscriptions;
 use tesseract_olap::logic_layer;
 use tesseract_olap::{schema_config, db_config};
+use tesseract_olap::config_file::Config;
 use std::path::Path;
 
 use actix_web::*;
@@ -141,7 +144,7 @@ mod tests {
     fn test_end_to_end() {
         let db_url_full = env::var("TESSERACT_DATABASE_URL").unwrap_or_else(|_| "clickhouse://localhost:9000".into());
 
-        let (db, db_url, db_type) = db_config::get_db(&db_url_full).unwrap();
+        let (db, db_url, db_type) = db_config::get_db(&Config::default(), &db_url_full).unwrap();
         let schema_source = SchemaSource::LocalSchema { filepath: "blah".to_string() };
 
         let env_vars = EnvVars {
@@ -150,6 +153,9 @@ mod tests {
             schema_source,
             jwt_secret: None,
             flush_secret: None,
+            query_limits: QueryLimits::default(),
+            logic_layer_config_path: None,
+            dataframe_growth: false,
         };
 
         let mut schema = Schema::from_xml(&schema_str).unwrap();
@@ -167,7 +173,7 @@ mod tests {
             let mut sys = actix::System::new("tesseract");
 
             let cache = logic_layer::populate_cache(
-                schema.clone(), &logic_layer_config, db.clone(), &mut sys
+                schema.clone(), &logic_layer_config, db.clone(), &HashMap::new(), &mut sys
             ).map_err(|err| format_err!("Cache population error: {}", err)).unwrap();
             let logic_layer_config = match logic_layer_config {
                 Some(ll_config) => Some(Arc::new(RwLock::new(ll_config))),
@@ -175,19 +181,27 @@ mod tests {
             };
             let cache_arc = Arc::new(RwLock::new(cache));
             let schema_arc = Arc::new(RwLock::new(schema.clone()));
+            let schema_version_arc = Arc::new(RwLock::new(SchemaVersion::new(&schema)));
     
             server::new(
                 move|| create_app(
                     false,
                     db.clone(),
+                    HashMap::new(),
                     None,
                     db_type.clone(),
                     env_vars.clone(),
                     schema_arc.clone(),
+                    schema_version_arc.clone(),
                     cache_arc.clone(),
                     logic_layer_config.clone(),
                     false,
                     has_unique_levels_properties.clone(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    Subscriptions::new(),
                 )
             )
             .bind("127.0.0.1:7777")
@@ -209,9 +223,31 @@ mod tests {
                 .map_err(|_| ())
                 .and_then(|response| {
                     assert_eq!(response.status(), 200);
+
+                    // query metadata
+                    let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_owned());
+                    assert_eq!(header("X-Tesseract-Cube"), Some("Sales".to_owned()));
+                    assert_eq!(header("X-Tesseract-Row-Count"), Some("1".to_owned()));
+                    assert_eq!(header("X-Tesseract-Cache"), Some("miss".to_owned()));
+                    assert!(header("X-Tesseract-Query-Ms").unwrap().parse::<u64>().is_ok());
+
                     let res = response.body().wait().expect("Failed to parse test API response body");
                     let expected = "{\"data\":[{\"Year\":2017,\"Quantity\":266.0}],\n\"source\": [\n{\"name\":\"Sales\",\"measures\":[\"Price Total\",\"Quantity\"],\"annotations\":null}\n]}";
                     assert_eq!(res, expected);
+
+                    // rate of two categories, with a cut and a limit
+                    client::get("http://127.0.0.1:7777/data?cube=Sales&drilldowns=Year&measures=Quantity&Year=2017,2018&rate=Unique%20Category.1,4&limit=1")
+                        .header("User-Agent", "Actix-web")
+                        .finish().unwrap()
+                        .send()
+                        .map_err(|_| ())
+                })
+                .and_then(|response| {
+                    assert_eq!(response.status(), 200);
+                    let res = response.body().wait().expect("Failed to parse test API response body");
+                    let res = std::str::from_utf8(&res).unwrap();
+                    assert!(res.starts_with("{\"data\":[{\"Year\":2017,\"Quantity\":266.0,\"Rate\":"));
+                    assert!(!res.contains("2018"));
                     actix::System::current().stop();
                     Ok(())
                 })
@@ -1,6 +1,6 @@
 #[cfg(test)]
-mod clickhouse_bench;
-#[cfg(test)]
 mod clickhouse_end_to_end;
 #[cfg(test)]
 mod query_ir;
+#[cfg(test)]
+mod rate;
@@ -0,0 +1,95 @@
+//! Sql generated for rate queries by each backend's dialect, from the schema and query down,
+//! including the interactions of rate with cuts and limits.
+//!
+//! Postgres uses the standard dialect.
+
+use tesseract_clickhouse::ClickhouseSql;
+use tesseract_core::{Query, Schema};
+use tesseract_core::dialect::{SqlDialect, StandardSql};
+use tesseract_mysql::MySqlSql;
+
+static SCHEMA_STR: &str = r##"
+<Schema name="Webshop">
+    <Cube name="Sales">
+        <Table name="tesseract_webshop_sales" />
+
+        <Dimension name="Year" foreign_key="year">
+            <Hierarchy name="Year">
+                <Level name="Year" key_column="year" />
+            </Hierarchy>
+        </Dimension>
+
+        <Dimension name="Category" foreign_key="category_id">
+            <Hierarchy name="Category">
+                <Table name="tesseract_webshop_categories" />
+                <Level name="Category" key_column="category_code" key_type="text" />
+            </Hierarchy>
+        </Dimension>
+
+        <Measure name="Quantity" column="quantity" aggregator="sum" />
+        <Measure name="Price Total" column="price_total" aggregator="sum" />
+    </Cube>
+</Schema>
+"##;
+
+/// Quantity by year, with the rate of `rate`.
+fn rate_query(rate: &str) -> Query {
+    let mut query = Query::new();
+    query.drilldowns = vec!["Year.Year.Year".parse().unwrap()];
+    query.measures = vec!["Quantity".parse().unwrap()];
+    query.rate = Some(rate.parse().unwrap());
+    query
+}
+
+fn generate_sql(dialect: &dyn SqlDialect, query: &Query) -> String {
+    let schema = Schema::from_xml(SCHEMA_STR).unwrap();
+    let (query_ir, headers) = schema.sql_query("Sales", query, None).unwrap();
+    assert_eq!(headers, vec!["Year".to_owned(), "Quantity".to_owned(), "Rate".to_owned()]);
+
+    dialect.generate_sql(query_ir)
+}
+
+#[test]
+fn test_rate_with_cut_and_limit() {
+    let mut query = rate_query("Category.Category.Category.books,videos");
+    query.cuts = vec!["Year.Year.Year.2018,2019".parse().unwrap()];
+    query.limit = Some("1".parse().unwrap());
+
+    assert_eq!(
+        generate_sql(&ClickhouseSql, &query),
+        "select * from (select year_Year, final_m0, sum(final_m0_rate) / avg(final_m0) from (select year_Year, category_code_Category_group, final_m0_agg as final_m0, final_m0_rate from (select year_Year, sum(final_m0) as final_m0_agg, groupArray(final_m0) as final_m0_rate, groupArray(category_code_Category) as category_code_Category_group from (SELECT year_Year, category_code_Category, sum(m0) as final_m0 FROM (SELECT year_Year, category_id, category_code_Category, m0 FROM (select category_code as category_code_Category, category_code as category_id from tesseract_webshop_categories) ALIAS0 ALL INNER JOIN (SELECT year as year_Year, category_id, sum(quantity) as m0 FROM tesseract_webshop_sales WHERE year in (2018, 2019) GROUP BY year_Year, category_id) ALIAS1 USING category_id) GROUP BY year_Year, category_code_Category) group by year_Year) array join category_code_Category_group as category_code_Category_group, final_m0_rate as final_m0_rate) where category_code_Category_group in ('books', 'videos') group by year_Year, final_m0)  order by year_Year limit 1"
+    );
+
+    let standard = "select tesseract_webshop_sales.year, sum(quantity), 1.0 * sum(case when tesseract_webshop_categories.category_code in ('books', 'videos') then quantity end) / nullif(sum(quantity), 0) from tesseract_webshop_sales inner join tesseract_webshop_categories on tesseract_webshop_categories.category_code = tesseract_webshop_sales.category_id where tesseract_webshop_sales.year in (2018, 2019) group by tesseract_webshop_sales.year order by tesseract_webshop_sales.year limit 1;";
+    assert_eq!(generate_sql(&StandardSql, &query), standard);
+    assert_eq!(generate_sql(&MySqlSql, &query), standard);
+}
+
+/// The cut is on the rate's own level, and the rate's members are escaped by each dialect.
+#[test]
+fn test_rate_with_excluded_members() {
+    let mut query = rate_query("Category.Category.Category.o'reilly");
+    query.cuts = vec!["~Category.Category.Category.books".parse().unwrap()];
+
+    assert_eq!(
+        generate_sql(&ClickhouseSql, &query),
+        "select * from (select year_Year, final_m0, sum(final_m0_rate) / avg(final_m0) from (select year_Year, category_code_Category_group, final_m0_agg as final_m0, final_m0_rate from (select year_Year, sum(final_m0) as final_m0_agg, groupArray(final_m0) as final_m0_rate, groupArray(category_code_Category) as category_code_Category_group from (SELECT year_Year, category_code_Category, sum(m0) as final_m0 FROM (SELECT year_Year, category_id, category_code_Category, m0 FROM (select category_code as category_code_Category, category_code as category_id from tesseract_webshop_categories) ALIAS0 ALL INNER JOIN (SELECT year as year_Year, category_id, sum(quantity) as m0 FROM tesseract_webshop_sales WHERE category_id IN (SELECT category_code FROM tesseract_webshop_categories WHERE category_code not in ('books')) GROUP BY year_Year, category_id) ALIAS1 USING category_id) GROUP BY year_Year, category_code_Category) group by year_Year) array join category_code_Category_group as category_code_Category_group, final_m0_rate as final_m0_rate) where category_code_Category_group in ('o\\'reilly') group by year_Year, final_m0)  order by year_Year "
+    );
+
+    let standard = "select tesseract_webshop_sales.year, sum(quantity), 1.0 * sum(case when tesseract_webshop_categories.category_code in ('o''reilly') then quantity end) / nullif(sum(quantity), 0) from tesseract_webshop_sales inner join tesseract_webshop_categories on tesseract_webshop_categories.category_code = tesseract_webshop_sales.category_id where tesseract_webshop_categories.category_code not in ('books') group by tesseract_webshop_sales.year;";
+    assert_eq!(generate_sql(&StandardSql, &query), standard);
+    assert_eq!(generate_sql(&MySqlSql, &query), standard);
+}
+
+#[test]
+fn test_rate_errors() {
+    let schema = Schema::from_xml(SCHEMA_STR).unwrap();
+
+    // only a single measure
+    let mut query = rate_query("Category.Category.Category.books");
+    query.measures.push("Price Total".parse().unwrap());
+    assert!(schema.sql_query("Sales", &query, None).is_err());
+
+    let query = rate_query("Category.Category.Missing.books");
+    assert!(schema.sql_query("Sales", &query, None).is_err());
+}